@@ -1,6 +1,11 @@
 #![allow(unwrap_check)]
 
-use std::{fs, io::Write, sync::Arc, time::SystemTime};
+use std::{
+    fs,
+    io::{BufRead, Write},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use anyhow::{anyhow, Result};
 use clap::Parser;
@@ -11,7 +16,7 @@ use tracing::level_filters::LevelFilter;
 
 use telio_model::{config::Server, event::Event as DevEvent, features::Features};
 
-use tcli::cli;
+use tcli::{cli, json};
 
 #[derive(Parser)]
 struct Args {
@@ -20,6 +25,12 @@ struct Args {
     features: Option<String>,
     #[clap(long)]
     less_spam: bool,
+    /// Read commands from stdin and print every response as a line of JSON, for scripting
+    #[clap(long)]
+    json: bool,
+    /// Print events as JSON lines as soon as they happen, instead of on 'events'
+    #[clap(long, requires = "json")]
+    watch: bool,
 }
 
 fn main() -> Result<()> {
@@ -46,6 +57,10 @@ fn main() -> Result<()> {
     let derp_server = Arc::new(Mutex::<Option<Server>>::new(None));
 
     let mut cli = cli::Cli::new(features, token, derp_server)?;
+    if args.json {
+        return run_json(cli, args.watch);
+    }
+
     let mut stdout = std::io::stdout();
 
     let less_spam = args.less_spam;
@@ -94,10 +109,42 @@ fn main() -> Result<()> {
             use cli::Resp::*;
             match resp {
                 Info(i) => println!("- {}", i),
+                Data { name, value } => println!("- {}: {}", name, value),
                 Event { ts, event } => match *event {
                     DevEvent::Node { body: b } => print_event(ts, "node", &b)?,
                     DevEvent::Relay { body: b } => print_event(ts, "relay", &b)?,
                     DevEvent::Error { body: b } => print_event(ts, "error", &b)?,
+                    DevEvent::PeerRekeyed { body: b } => print_event(ts, "peer_rekeyed", &b)?,
+                    DevEvent::PeerUnreachable { body: b } => {
+                        print_event(ts, "peer_unreachable", &b)?
+                    }
+                    DevEvent::MtuChanged { body: b } => print_event(ts, "mtu_changed", &b)?,
+                    DevEvent::NodeTransition { body: b } => print_event(ts, "node_transition", &b)?,
+                    DevEvent::InboundConnection { body: b } => {
+                        print_event(ts, "inbound_connection", &b)?
+                    }
+                    DevEvent::MaintenanceStateChanged { body: b } => {
+                        print_event(ts, "maintenance_state_changed", &b)?
+                    }
+                    DevEvent::ConfigRollback { body: b } => print_event(ts, "config_rollback", &b)?,
+                    DevEvent::AdapterRecovery { body: b } => {
+                        print_event(ts, "adapter_recovery", &b)?
+                    }
+                    DevEvent::MeshRemediation { body: b } => {
+                        print_event(ts, "mesh_remediation", &b)?
+                    }
+                    DevEvent::HaRoleChanged { body: b } => print_event(ts, "ha_role_changed", &b)?,
+                    DevEvent::AnalyticsConsentChanged { body: b } => {
+                        print_event(ts, "analytics_consent_changed", &b)?
+                    }
+                    DevEvent::RouteConflict { body: b } => print_event(ts, "route_conflict", &b)?,
+                    DevEvent::PeerHandshake { body: b } => print_event(ts, "peer_handshake", &b)?,
+                    DevEvent::DnsRecordsInvalidated { body: b } => {
+                        print_event(ts, "dns_records_invalidated", &b)?
+                    }
+                    DevEvent::AuditRecord { body: b } => print_event(ts, "audit_record", &b)?,
+                    DevEvent::PingProbe { body: b } => print_event(ts, "ping_probe", &b)?,
+                    DevEvent::PeerStatsReport { body: b } => print_event(ts, "peer_stats", &b)?,
                 },
                 Error(e) => {
                     println!("error: {e:#?}")
@@ -124,6 +171,40 @@ fn main() -> Result<()> {
     }
 }
 
+/// Non-interactive mode, the responses of every command are followed by a `done` line
+fn run_json(mut cli: cli::Cli, watch: bool) -> Result<()> {
+    if watch {
+        let events = cli.take_events();
+        std::thread::spawn(move || {
+            for event in events {
+                if json::write_resp(&mut std::io::stdout().lock(), &event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    for cmd in std::io::stdin().lock().lines() {
+        let cmd = cmd?;
+        if cmd.trim().is_empty() {
+            continue;
+        }
+
+        let resps = cli.exec(&cmd);
+        let mut stdout = std::io::stdout().lock();
+        for resp in &resps {
+            json::write_resp(&mut stdout, resp)?;
+        }
+        let ok = !resps.iter().any(|resp| matches!(resp, cli::Resp::Error(_)));
+        json::write_done(&mut stdout, &cmd, ok)?;
+
+        if resps.iter().any(|resp| matches!(resp, cli::Resp::Quit)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
 fn print_event(
     ts: SystemTime,
     ty: impl std::fmt::Display,
@@ -112,6 +112,11 @@ pub struct Cli {
 #[derive(Debug)]
 pub enum Resp {
     Info(String),
+    /// Result of a command, kept structured for the scripting mode
+    Data {
+        name: &'static str,
+        value: serde_json::Value,
+    },
     Event {
         ts: SystemTime,
         event: Box<DevEvent>,
@@ -126,6 +131,9 @@ impl Display for Resp {
             Resp::Info(msg) => {
                 write!(f, "INFO: {}", msg)
             }
+            Resp::Data { name, value } => {
+                write!(f, "DATA: {}: {}", name, value)
+            }
             Resp::Error(e) => {
                 write!(f, "ERROR: {:?}", e)
             }
@@ -174,6 +182,9 @@ enum Cmd {
     Pmtu {
         host: String,
     },
+    #[cfg(feature = "chaos")]
+    #[clap(subcommand)]
+    Chaos(ChaosCmd),
     Quit,
 }
 
@@ -221,15 +232,37 @@ enum DevCmd {
     Dis {
         public_key: PublicKey,
     },
+    #[clap(about = "Stage a VPN server for a quick switch")]
+    Prewarm {
+        /// Public key of the VPN server
+        public_key: PublicKey,
+        /// IP:PORT of the VPN server
+        endpoint: SocketAddr,
+        /// Handshake with the server ahead of the switch
+        #[clap(long = "handshake")]
+        handshake: bool,
+    },
+    #[clap(about = "Remove the staged VPN server")]
+    CancelPrewarm,
     #[clap(about = "Disconnect from all exit nodes")]
     Disall,
     #[clap(about = "Restarts telio wg adapter")]
     NotifyNetChange,
+    #[clap(about = "Suspend probing, analytics and keepalives")]
+    Suspend,
+    #[clap(about = "Resume the background activity")]
+    Resume,
     Stop,
     #[clap(about = "Trigger analytics event")]
     Analytics,
     #[clap(about = "Trigger qos collection")]
     Qos,
+    #[clap(about = "Grant the analytics consent, or revoke it purging the collected data")]
+    Consent {
+        /// Revoke the consent
+        #[clap(long)]
+        revoke: bool,
+    },
 }
 
 #[derive(Parser)]
@@ -278,6 +311,13 @@ enum MeshCmd {
     FileConfig {
         filename: std::path::PathBuf,
     },
+    /// Assess the expected connectivity to a node before adding it
+    Preview {
+        /// Public key of the node
+        public_key: PublicKey,
+        /// IP:PORT endpoints the node is expected to be reachable at
+        endpoint_hints: Vec<SocketAddr>,
+    },
     Off,
 }
 
@@ -287,6 +327,36 @@ enum DnsCmd {
     On { forward_servers: Vec<String> },
     /// Turn off DNS module
     Off,
+    /// Print the .nord zone, or one of its reverse zones, as zone file text
+    Export {
+        #[clap(default_value = "nord")]
+        zone: String,
+    },
+}
+
+#[cfg(feature = "chaos")]
+#[derive(Parser)]
+#[clap(about = "Inject faults for resilience tests")]
+enum ChaosCmd {
+    /// Drop the packets of a peer, delay or reorder its relayed packets
+    Packets {
+        public_key: PublicKey,
+        /// Probability of dropping a packet, from 0 to 1
+        #[clap(long, default_value = "0")]
+        drop: f64,
+        /// Delay added to every relayed packet, in milliseconds
+        #[clap(long, default_value = "0")]
+        delay: u64,
+        /// Upper bound of the random delay added on top, in milliseconds
+        #[clap(long, default_value = "0")]
+        jitter: u64,
+    },
+    /// Kill the relay connection, it reconnects as after a network failure
+    KillRelay,
+    /// Fail the queries to the upstream DNS servers, none to stop
+    FailDns { upstreams: Vec<IpAddr> },
+    /// Stop injecting faults
+    Clear,
 }
 
 #[derive(Parser)]
@@ -319,6 +389,14 @@ macro_rules! cli_res {
             }
         }
     };
+    [ $vec:expr; $((d $name:literal, $value:expr)),+ ] => {
+        {
+            $(
+                let value = cli_try!($vec; serde_json::to_value($value));
+                $vec.push(Resp::Data { name: $name, value });
+            )+
+        }
+    };
     [ $vec:expr; q ] => {
         {
             $vec.push(Resp::Quit);
@@ -406,6 +484,11 @@ impl Cli {
         })
     }
 
+    /// Take the events, so they can be consumed as they come instead of by the 'events' command
+    pub fn take_events(&mut self) -> Receiver<Resp> {
+        std::mem::replace(&mut self.resp, mpsc::channel().1)
+    }
+
     /// Function for only handling the help message without extra overhead. Used by the TCLID API.
     pub fn print_help(args: Vec<String>) -> anyhow::Result<String> {
         match Cmd::try_parse_from(args) {
@@ -445,6 +528,8 @@ impl Cli {
             Cmd::Quit => cli_res!(res; q),
             #[cfg(any(target_os = "linux", target_os = "android"))]
             Cmd::Pmtu { host } => cli_res!(res; (j self.exec_pmtu(&host))),
+            #[cfg(feature = "chaos")]
+            Cmd::Chaos(cmd) => cli_res!(res; (j self.exec_chaos(cmd))),
         }
         res
     }
@@ -528,6 +613,28 @@ impl Cli {
                 cli_res!(res; (i "stopping peer {}", public_key));
                 cli_try!(self.telio.disconnect_exit_node(&public_key));
             }
+            Prewarm {
+                public_key,
+                endpoint,
+                handshake,
+            } => {
+                if !self.telio.is_running() {
+                    cli_res!(res; (e Error::NotStarted));
+                }
+
+                cli_res!(res; (i "prewarming node {}", public_key));
+                cli_try!(self
+                    .telio
+                    .prewarm_exit_node(public_key, endpoint, handshake));
+            }
+            CancelPrewarm => {
+                if !self.telio.is_running() {
+                    cli_res!(res; (e Error::NotStarted));
+                }
+
+                cli_res!(res; (i "cancelling prewarm"));
+                cli_try!(self.telio.cancel_exit_node_prewarm());
+            }
             Disall => {
                 if !self.telio.is_running() {
                     cli_res!(res; (e Error::NotStarted));
@@ -542,7 +649,23 @@ impl Cli {
                 }
 
                 cli_res!(res; (i "notify net change"));
-                cli_try!(self.telio.notify_network_change());
+                cli_try!(self.telio.notify_network_change(Default::default()));
+            }
+            Suspend => {
+                if !self.telio.is_running() {
+                    cli_res!(res; (e Error::NotStarted));
+                }
+
+                cli_res!(res; (i "suspending"));
+                cli_try!(self.telio.suspend());
+            }
+            Resume => {
+                if !self.telio.is_running() {
+                    cli_res!(res; (e Error::NotStarted));
+                }
+
+                cli_res!(res; (i "resuming"));
+                cli_try!(self.telio.resume());
             }
             Stop => {
                 self.telio.stop();
@@ -556,6 +679,10 @@ impl Cli {
                 cli_res!(res; (i "Trigger qos collection."));
                 cli_try!(self.telio.trigger_qos_collection());
             }
+            Consent { revoke } => {
+                cli_res!(res; (i "Set analytics consent to {}.", !revoke));
+                cli_try!(self.telio.set_analytics_consent(!revoke));
+            }
         }
         res
     }
@@ -612,8 +739,9 @@ impl Cli {
 
                 let nord = cli_try!(res; self.nord.as_ref().ok_or(Error::NeedsLogin));
                 let meshmap_str = cli_try!(res; nord.get_meshmap(&conf.id));
-                cli_res!(res; (i "got config:\n{}", &meshmap_str));
-                self.meshmap = cli_try!(res; serde_json::from_str(&meshmap_str));
+                let meshmap: serde_json::Value = cli_try!(res; serde_json::from_str(&meshmap_str));
+                cli_res!(res; (d "config", &meshmap));
+                self.meshmap = cli_try!(res; serde_json::from_value(meshmap));
                 let adapter_type = cli_try!(res; AdapterType::from_str(&adapter));
 
                 let private_key = conf.sk;
@@ -673,13 +801,15 @@ impl Cli {
                 };
                 self.conf = Some(conf);
             }
-            Ping {} => cli_res!(res; (i "receive ping - {:?}", self.telio.receive_ping())),
+            Ping {} => {
+                let ping = cli_try!(res; self.telio.receive_ping());
+                cli_res!(res; (d "ping", ping));
+            }
             Config { mesh_config } => {
                 if mesh_config.is_empty() {
                     let meshmap =
                         cli_try!(self.meshmap.as_ref().cloned().ok_or(Error::EmptyConfig));
-                    let meshmap = cli_try!(serde_json::to_string_pretty(&meshmap));
-                    cli_res!(res; (i "Current meshnet config:\n{}", meshmap));
+                    cli_res!(res; (d "config", &meshmap));
                 } else {
                     let meshmap: MeshMap = cli_try!(serde_json::from_str(&mesh_config));
                     cli_try!(self.telio.set_config(&Some(meshmap)));
@@ -690,6 +820,15 @@ impl Cli {
                 let meshmap: MeshMap = cli_try!(serde_json::from_str(&file_contents));
                 cli_try!(self.telio.set_config(&Some(meshmap)));
             }
+            Preview {
+                public_key,
+                endpoint_hints,
+            } => {
+                let preview = cli_try!(res; self
+                    .telio
+                    .preview_peer_connectivity(public_key, endpoint_hints));
+                cli_res!(res; (d "preview", &preview));
+            }
             Off => {
                 cli_try!(res; self.telio.set_config(&None));
             }
@@ -718,6 +857,52 @@ impl Cli {
             DnsCmd::Off => {
                 cli_try!(res; self.telio.disable_magic_dns());
             }
+            DnsCmd::Export { zone } => {
+                let text = cli_try!(res; self.telio.export_dns_zone(&zone));
+                cli_res!(res; (d "zone", text));
+            }
+        }
+
+        res
+    }
+
+    #[cfg(feature = "chaos")]
+    fn exec_chaos(&mut self, cmd: ChaosCmd) -> Vec<Resp> {
+        use std::time::Duration;
+        use telio_utils::chaos::{self, PacketFault};
+
+        let mut res = Vec::new();
+
+        match cmd {
+            ChaosCmd::Packets {
+                public_key,
+                drop,
+                delay,
+                jitter,
+            } => {
+                let fault = PacketFault {
+                    drop,
+                    delay: Duration::from_millis(delay),
+                    jitter: Duration::from_millis(jitter),
+                };
+                cli_res!(res; (i "injecting {:?} into packets of {}", fault, public_key));
+                chaos::set_packet_fault(
+                    public_key.0,
+                    (fault != PacketFault::default()).then_some(fault),
+                );
+            }
+            ChaosCmd::KillRelay => {
+                cli_res!(res; (i "killing relay connection"));
+                chaos::kill_relay();
+            }
+            ChaosCmd::FailDns { upstreams } => {
+                cli_res!(res; (i "failing upstream dns servers: {:?}", upstreams));
+                chaos::set_failing_dns_upstreams(upstreams.into_iter().collect());
+            }
+            ChaosCmd::Clear => {
+                cli_res!(res; (i "clearing injected faults"));
+                chaos::clear();
+            }
         }
 
         res
@@ -735,8 +920,10 @@ impl Cli {
                 stun_port,
             )) {
                 Ok(data) => {
-                    cli_res!(res; (i "Public Address: {:?}", data.public_ip));
-                    cli_res!(res; (i "Nat Type: {:?}", data.nat_type));
+                    cli_res!(res;
+                        (d "public_address", data.public_ip),
+                        (d "nat_type", format!("{:?}", data.nat_type))
+                    );
                 }
 
                 Err(error) => {
@@ -760,7 +947,9 @@ impl Cli {
         let host = host.ip();
 
         let mtu = cli_try!(self.telio.probe_pmtu(host));
-        vec![Resp::Info(format!("PMTU -> {host}: {mtu}"))]
+        let mut res = Vec::new();
+        cli_res!(res; (d "pmtu", serde_json::json!({ "host": host, "mtu": mtu })));
+        res
     }
 
     fn start_telio(
@@ -877,12 +1066,10 @@ impl Cli {
             let derp_status = (*self.derp_server.lock()).clone();
             match cmd {
                 Simple => {
-                    let telio_nodes = cli_try!(res; serde_json::to_string(&telio_nodes));
-                    let derp_status = cli_try!(res; serde_json::to_string(&derp_status));
+                    cli_res!(res; (i "telio running."));
                     cli_res!(res;
-                        (i "telio running."),
-                        (i "telio nodes: {}", telio_nodes),
-                        (i "derp status: {}", derp_status)
+                        (d "telio_nodes", &telio_nodes),
+                        (d "derp_status", &derp_status)
                     );
                 }
                 Pretty => {
@@ -0,0 +1,123 @@
+//! Machine readable output for scripting
+//!
+//! Every response is written as a single line of JSON tagged with its `type`, and every command
+//! is terminated by a `done` line, so the output can be consumed without parsing human oriented
+//! text. Command results, like the status or the meshnet config, come as `data` lines carrying
+//! the value as is.
+
+use std::{io::Write, time::SystemTime};
+
+use serde::Serialize;
+use serde_json::Value;
+use telio_model::event::Event as DevEvent;
+
+use crate::cli::Resp;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'a> {
+    Info { message: &'a str },
+    Data { name: &'a str, value: &'a Value },
+    Event { ts: String, event: &'a DevEvent },
+    Error { message: String },
+    Done { command: &'a str, ok: bool },
+}
+
+/// Write the response as a line of JSON, `Quit` has no output
+pub fn write_resp(out: &mut impl Write, resp: &Resp) -> anyhow::Result<()> {
+    let line = match resp {
+        Resp::Info(message) => Line::Info { message },
+        Resp::Data { name, value } => Line::Data { name, value },
+        Resp::Event { ts, event } => Line::Event {
+            ts: rfc3339(*ts)?,
+            event,
+        },
+        Resp::Error(e) => Line::Error {
+            message: e.to_string(),
+        },
+        Resp::Quit => return Ok(()),
+    };
+    write_line(out, &line)
+}
+
+/// Write the line terminating the responses of the command
+pub fn write_done(out: &mut impl Write, command: &str, ok: bool) -> anyhow::Result<()> {
+    write_line(out, &Line::Done { command, ok })
+}
+
+fn write_line(out: &mut impl Write, line: &Line) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn rfc3339(ts: SystemTime) -> anyhow::Result<String> {
+    Ok(time::OffsetDateTime::from(ts).format(&time::format_description::well_known::Rfc3339)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Error;
+    use serde_json::json;
+    use telio_model::event::MtuChanged;
+
+    fn lines(resps: &[Resp]) -> Vec<Value> {
+        let mut out = Vec::new();
+        for resp in resps {
+            write_resp(&mut out, resp).unwrap();
+        }
+        write_done(&mut out, "dev start", true).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn every_response_is_a_line_of_json() {
+        let resps = [
+            Resp::Info("started telio".to_owned()),
+            Resp::Data {
+                name: "pmtu",
+                value: json!({"host": "10.0.0.1", "mtu": 1420}),
+            },
+            Resp::Event {
+                ts: SystemTime::UNIX_EPOCH,
+                event: Box::new(DevEvent::MtuChanged {
+                    body: MtuChanged {
+                        mtu: 1280,
+                        mss_ipv4: 1240,
+                        mss_ipv6: 1220,
+                    },
+                }),
+            },
+            Resp::Error(Box::new(Error::NotStarted)),
+            Resp::Quit,
+        ];
+
+        assert_eq!(
+            lines(&resps),
+            vec![
+                json!({"type": "info", "message": "started telio"}),
+                json!({
+                    "type": "data",
+                    "name": "pmtu",
+                    "value": {"host": "10.0.0.1", "mtu": 1420},
+                }),
+                json!({
+                    "type": "event",
+                    "ts": "1970-01-01T00:00:00Z",
+                    "event": {
+                        "type": "mtu_changed",
+                        "body": {"mtu": 1280, "mss_ipv4": 1240, "mss_ipv6": 1220},
+                    },
+                }),
+                json!({"type": "error", "message": "device must be started."}),
+                json!({"type": "done", "command": "dev start", "ok": true}),
+            ]
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub mod cli;
 mod derp;
+pub mod json;
 mod nord;
@@ -260,6 +260,7 @@ mod tests {
                 reconnect_after_expiry: Percentage(100),
                 certificate_file_path: Some(PathBuf::from("some/certificate/path/")),
             },
+            rpc_socket_path: None,
         };
         let initial_config = r#"
         {
@@ -341,6 +342,7 @@ mod tests {
                 "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_owned(),
             http_certificate_file_path: Some(PathBuf::from("/http/certificate/path/")),
             mqtt: MqttConfig::default(),
+            rpc_socket_path: None,
         };
         let initial_config = r#"
         {
@@ -21,6 +21,8 @@ use interprocess::{
 pub struct DaemonSocket {
     /// The inner socket over which the actual communication is happening.
     socket: LocalSocketListener,
+    /// Path of the socket file, removed when the socket is dropped.
+    path: PathBuf,
 }
 
 impl DaemonSocket {
@@ -61,7 +63,10 @@ impl DaemonSocket {
             .reclaim_name(true)
             .create_tokio()?;
 
-        Ok(Self { socket })
+        Ok(Self {
+            socket,
+            path: ipc_socket_path.to_owned(),
+        })
     }
 
     /// Opens a connection on the IPC socket for listening to commands (requests) from the API and
@@ -73,7 +78,7 @@ impl DaemonSocket {
     ///
     /// A struct for receiving commands from the new IPC connection stream and responding to them.
     pub async fn accept(&self) -> Result<DaemonConnection> {
-        let stream = self.socket.accept().await?;
+        let stream = BufReader::new(self.socket.accept().await?);
 
         Ok(DaemonConnection { stream })
     }
@@ -116,19 +121,15 @@ impl DaemonSocket {
 impl Drop for DaemonSocket {
     fn drop(&mut self) {
         // Normally this should be cleaned up, but in some cases it is not
-        // The ipc path here should be valid, as the daemon socket is created
-        // based on whether the path is valid or not.
-        if let Ok(socket_path) = DaemonSocket::get_ipc_socket_path() {
-            let _ = fs::remove_file(socket_path);
-        }
+        let _ = fs::remove_file(&self.path);
     }
 }
 
 /// Struct for tracking a single IPC connection and keeping the stream
 /// Mostly needed for keeping the stream while the server is generating a reply.
 pub struct DaemonConnection {
-    // The inner stream to be abstracted away.
-    stream: LocalSocketStream,
+    // The inner stream to be abstracted away, buffered so several commands can be read from it.
+    stream: BufReader<LocalSocketStream>,
 }
 
 impl DaemonConnection {
@@ -137,11 +138,35 @@ impl DaemonConnection {
     ///
     /// # Returns
     ///
-    /// String containing the received command.
+    /// String containing the received command, empty if the other side closed the connection.
     pub async fn read_command(&mut self) -> Result<String> {
         let mut command_buffer = String::new();
-        let mut reader = BufReader::new(&mut self.stream);
-        reader.read_line(&mut command_buffer).await?;
+        self.stream.read_line(&mut command_buffer).await?;
+        // Removing the trailing newline used as a message ending according to platform.
+        command_buffer.pop();
+
+        Ok(command_buffer)
+    }
+
+    /// Same as [Self::read_command], but fails without reading the rest of the command once it
+    /// gets longer than `max_len` bytes, so a client can't make the daemon buffer it whole.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_len` - Longest command accepted, without the trailing newline.
+    pub async fn read_command_bounded(&mut self, max_len: usize) -> Result<String> {
+        let mut command_buffer = String::new();
+        let limit = u64::try_from(max_len).unwrap_or(u64::MAX).saturating_add(1);
+        (&mut self.stream)
+            .take(limit)
+            .read_line(&mut command_buffer)
+            .await?;
+        if !command_buffer.ends_with('\n') && command_buffer.len() > max_len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Command is longer than {max_len} bytes"),
+            ));
+        }
         // Removing the trailing newline used as a message ending according to platform.
         command_buffer.pop();
 
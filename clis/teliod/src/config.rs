@@ -1,14 +1,18 @@
-use std::{num::NonZeroU64, path::PathBuf, str::FromStr};
+use std::{
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use smart_default::SmartDefault;
 use std::fs;
-use tracing::{debug, info, level_filters::LevelFilter, warn};
+use tracing::{debug, error, info, level_filters::LevelFilter, warn};
 use uuid::Uuid;
 
 use telio::crypto::SecretKey;
 
-use crate::configure_interface::InterfaceConfigurationProvider;
+use crate::{configure_interface::InterfaceConfigurationProvider, TeliodError};
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug, SmartDefault)]
 #[repr(transparent)]
@@ -90,7 +94,7 @@ impl DeviceIdentity {
     }
 }
 
-#[derive(PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
 pub struct TeliodDaemonConfig {
     #[serde(
         deserialize_with = "deserialize_log_level",
@@ -111,9 +115,30 @@ pub struct TeliodDaemonConfig {
 
     #[serde(default)]
     pub mqtt: MqttConfig,
+
+    /// Path of the JSON-RPC control socket, not opened if unset
+    #[serde(default)]
+    pub rpc_socket_path: Option<PathBuf>,
 }
 
 impl TeliodDaemonConfig {
+    /// Read the config file, the authentication token may be overridden with `NORD_TOKEN`
+    pub fn from_file(path: &Path) -> Result<Self, TeliodError> {
+        let file = fs::File::open(path)?;
+        let mut config: TeliodDaemonConfig = serde_json::from_reader(file)?;
+
+        if let Ok(token) = std::env::var("NORD_TOKEN") {
+            debug!("Overriding token from env");
+            if token.len() == 64 && token.chars().all(|c| c.is_ascii_hexdigit()) {
+                config.authentication_token = token;
+            } else {
+                error!("Token from env not valid")
+            }
+        }
+
+        Ok(config)
+    }
+
     #[allow(dead_code)]
     pub fn update(&mut self, update: TeliodDaemonConfigPartial) {
         if let Some(log_level) = update.log_level {
@@ -134,6 +159,9 @@ impl TeliodDaemonConfig {
         if let Some(mqtt) = update.mqtt {
             self.mqtt = mqtt;
         }
+        if let Some(rpc_socket_path) = update.rpc_socket_path {
+            self.rpc_socket_path = rpc_socket_path;
+        }
     }
 }
 
@@ -200,7 +228,7 @@ where
     }
 }
 
-#[derive(Default, PartialEq, Eq, Deserialize, Serialize, Debug)]
+#[derive(Default, PartialEq, Eq, Clone, Deserialize, Serialize, Debug)]
 pub struct InterfaceConfig {
     pub name: String,
     pub config_provider: InterfaceConfigurationProvider,
@@ -218,6 +246,7 @@ pub struct TeliodDaemonConfigPartial {
     authentication_token: Option<String>,
     http_certificate_file_path: Option<Option<PathBuf>>,
     mqtt: Option<MqttConfig>,
+    rpc_socket_path: Option<Option<PathBuf>>,
 }
 
 fn deserialize_partial_log_level<'de, D: Deserializer<'de>>(
@@ -286,6 +315,7 @@ mod tests {
                 reconnect_after_expiry: Percentage(90),
                 certificate_file_path: None,
             },
+            rpc_socket_path: None,
         };
 
         {
@@ -1,8 +1,8 @@
-use futures::stream::StreamExt;
+use futures::{future::pending, stream::StreamExt};
 use nix::libc::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
 use nix::sys::signal::Signal;
 use signal_hook_tokio::Signals;
-use std::{fs, net::IpAddr, sync::Arc};
+use std::{fs, net::IpAddr, path::PathBuf, sync::Arc};
 use telio::{
     crypto::SecretKey,
     device::{Device, DeviceConfig, Error as DeviceError},
@@ -11,17 +11,23 @@ use telio::{
     telio_utils::select,
     telio_wg::AdapterType,
 };
-use tokio::{sync::mpsc, sync::oneshot, time::Duration};
-use tracing::{debug, error, info, trace};
+use tokio::{
+    sync::mpsc,
+    sync::oneshot,
+    time::{interval, timeout, Duration},
+};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::core_api::{get_meshmap as get_meshmap_from_server, init_with_api};
 use crate::ClientCmd;
 use crate::{
     command_listener::CommandListener,
-    comms::DaemonSocket,
+    comms::{DaemonConnection, DaemonSocket},
     config::DeviceIdentity,
     config::{InterfaceConfig, TeliodDaemonConfig},
     nc::NotificationCenter,
+    rpc::{self, DeviceCall},
+    systemd::Notifier,
     TelioStatusReport, TeliodError,
 };
 
@@ -33,15 +39,25 @@ pub enum TelioTaskCmd {
     GetStatus(oneshot::Sender<TelioStatusReport>),
     // Configure the system interface
     SetIp(IpAddr),
+    // Call the device API on behalf of an RPC client
+    Device(DeviceCall, oneshot::Sender<Result<(), String>>),
+    // Restart meshnet on the interface of the reloaded config
+    ReloadInterface(InterfaceConfig),
     // Break the receive loop to quit the daemon and exit gracefully
     Quit,
 }
 
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 const EMPTY_TOKEN: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
 
 #[derive(Debug, Default)]
 pub struct TelioTaskStates {
     interface_ip_address: Option<IpAddr>,
+    // Meshmap set over RPC, the ones of the API are ignored from then on
+    rpc_meshmap: Option<Option<MeshMap>>,
 }
 
 // From async context Telio needs to be run in separate task
@@ -50,7 +66,7 @@ fn telio_task(
     auth_token: Arc<String>,
     mut rx_channel: mpsc::Receiver<TelioTaskCmd>,
     tx_channel: mpsc::Sender<TelioTaskCmd>,
-    interface_config: &InterfaceConfig,
+    mut interface_config: InterfaceConfig,
 ) -> Result<(), TeliodError> {
     debug!("Initializing telio device");
 
@@ -68,7 +84,7 @@ fn telio_task(
 
     // Keep track of current meshnet states
     let mut state = TelioTaskStates::default();
-    let sys_config = interface_config.config_provider.create();
+    let mut sys_config = interface_config.config_provider.create();
 
     // TODO: This is temporary to be removed later on when we have proper integration
     // tests with core API. This is to not look for tokens in a test environment
@@ -80,11 +96,18 @@ fn telio_task(
             node_identity.private_key.clone(),
             &interface_config.name,
         )?;
-        task_retrieve_meshmap(node_identity, auth_token, tx_channel.clone());
+        task_retrieve_meshmap(
+            node_identity.clone(),
+            auth_token.clone(),
+            tx_channel.clone(),
+        );
 
         while let Some(cmd) = rx_channel.blocking_recv() {
             info!("Got command {:?}", cmd);
             match cmd {
+                TelioTaskCmd::UpdateMeshmap(_) if state.rpc_meshmap.is_some() => {
+                    debug!("Meshmap was set over RPC, ignoring the one from the API");
+                }
                 TelioTaskCmd::UpdateMeshmap(map) => {
                     let some_new_ip_address = map
                         .ip_addresses
@@ -124,6 +147,46 @@ fn telio_task(
                         _ = sys_config.set_ip(&interface_config.name, &new_ip_address);
                     }
                 }
+                TelioTaskCmd::Device(call, response_tx_channel) => {
+                    let meshmap = match &call {
+                        DeviceCall::SetMeshnet(meshmap) => Some(meshmap.clone()),
+                        _ => None,
+                    };
+                    let result = call.execute(&telio).map_err(|e| e.to_string());
+                    if result.is_ok() && meshmap.is_some() {
+                        state.rpc_meshmap = meshmap;
+                    }
+                    if response_tx_channel.send(result).is_err() {
+                        error!("Telio task failed sending device call result")
+                    }
+                }
+                TelioTaskCmd::ReloadInterface(new_interface_config) => {
+                    info!("Restarting meshnet on {:?}", new_interface_config);
+                    telio.stop();
+                    interface_config = new_interface_config;
+                    sys_config = interface_config.config_provider.create();
+                    state.interface_ip_address = None;
+                    if let Err(e) = start_telio(
+                        &mut telio,
+                        node_identity.private_key.clone(),
+                        &interface_config.name,
+                    ) {
+                        error!("Unable to restart meshnet due to {e}");
+                        continue;
+                    }
+                    match &state.rpc_meshmap {
+                        Some(meshmap) => {
+                            if let Err(e) = telio.set_config(meshmap) {
+                                error!("Unable to set meshmap due to {e}");
+                            }
+                        }
+                        None => task_retrieve_meshmap(
+                            node_identity.clone(),
+                            auth_token.clone(),
+                            tx_channel.clone(),
+                        ),
+                    }
+                }
                 TelioTaskCmd::Quit => {
                     telio.stop();
                     break;
@@ -179,18 +242,71 @@ fn start_telio(
     Ok(())
 }
 
-pub async fn daemon_event_loop(config: TeliodDaemonConfig) -> Result<(), TeliodError> {
+async fn accept_rpc_client(socket: Option<&DaemonSocket>) -> std::io::Result<DaemonConnection> {
+    match socket {
+        Some(socket) => socket.accept().await,
+        None => pending().await,
+    }
+}
+
+/// Report the daemon ready once the telio task is healthy, and ping the watchdog while it stays so
+fn spawn_health_check(
+    notifier: Arc<Notifier>,
+    tx: mpsc::Sender<TelioTaskCmd>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut health_check = interval(
+            notifier
+                .watchdog_interval()
+                .map_or(HEALTH_CHECK_INTERVAL, |i| i.min(HEALTH_CHECK_INTERVAL)),
+        );
+        let mut is_ready = false;
+        loop {
+            health_check.tick().await;
+            if telio_is_running(&tx).await {
+                if !is_ready {
+                    notifier.ready("Meshnet running");
+                    is_ready = true;
+                }
+                notifier.watchdog();
+            } else {
+                warn!("Telio is not running, skipping the watchdog ping");
+            }
+        }
+    })
+}
+
+/// Whether the telio task answers in time, reporting telio as running
+async fn telio_is_running(tx: &mpsc::Sender<TelioTaskCmd>) -> bool {
+    let (response_tx, response_rx) = oneshot::channel();
+    let report = timeout(HEALTH_CHECK_TIMEOUT, async {
+        #[allow(mpsc_blocking_send)]
+        tx.send(TelioTaskCmd::GetStatus(response_tx)).await.ok()?;
+        response_rx.await.ok()
+    })
+    .await;
+    matches!(report, Ok(Some(report)) if report.telio_is_running)
+}
+
+pub async fn daemon_event_loop(
+    config: TeliodDaemonConfig,
+    config_path: PathBuf,
+) -> Result<(), TeliodError> {
     let (non_blocking_writer, _tracing_worker_guard) =
         tracing_appender::non_blocking(fs::File::create(&config.log_file_path)?);
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_max_level(config.log_level)
         .with_writer(non_blocking_writer)
         .with_ansi(false)
         .with_line_number(true)
         .with_level(true)
-        .init();
+        .with_filter_reloading();
+    let log_level = subscriber.reload_handle();
+    subscriber.init();
 
     debug!("started with config: {config:?}");
+    let mut active_config = config.clone();
+    let notifier = Arc::new(Notifier::from_env());
 
     let socket = DaemonSocket::new(&DaemonSocket::get_ipc_socket_path()?)?;
 
@@ -198,6 +314,10 @@ pub async fn daemon_event_loop(config: TeliodDaemonConfig) -> Result<(), TeliodE
     // telio task
     let (tx, rx) = mpsc::channel(10);
     let mut cmd_listener = CommandListener::new(socket, tx.clone());
+    let mut rpc_socket = match &config.rpc_socket_path {
+        Some(path) => Some(DaemonSocket::new(path)?),
+        None => None,
+    };
 
     // TODO: This if condition and ::default call is temporary to be removed later
     // on when we have proper integration tests with core API.
@@ -219,21 +339,29 @@ pub async fn daemon_event_loop(config: TeliodDaemonConfig) -> Result<(), TeliodE
     let identity_clone = identity_ptr.clone();
 
     let mut telio_task_handle = tokio::task::spawn_blocking(move || {
-        telio_task(identity_clone, token_clone, rx, tx_clone, &config.interface)
+        telio_task(identity_clone, token_clone, rx, tx_clone, config.interface)
     });
 
     let tx_clone = tx.clone();
+    let (identity_clone, token_clone) = (identity_ptr.clone(), token_ptr.clone());
     nc.add_callback(Arc::new(move |_am| {
-        task_retrieve_meshmap(identity_ptr.clone(), token_ptr.clone(), tx_clone.clone());
+        task_retrieve_meshmap(
+            identity_clone.clone(),
+            token_clone.clone(),
+            tx_clone.clone(),
+        );
     }))
     .await;
 
     let mut signals = Signals::new([SIGHUP, SIGTERM, SIGINT, SIGQUIT])?;
 
+    // Readiness and the watchdog pings follow the health of the telio task, not of this loop
+    let health_check = spawn_health_check(notifier.clone(), tx.clone());
+
     info!("Entering event loop");
     eprintln!("Daemon started");
 
-    loop {
+    let result = loop {
         select! {
             // Check if telio_task completes and exit if it fails
             join_result = &mut telio_task_handle => {
@@ -265,14 +393,74 @@ pub async fn daemon_event_loop(config: TeliodDaemonConfig) -> Result<(), TeliodE
                     }
                 }
             },
-            // Handle interrupt signals for clean shutdown
+            // Serve each RPC client on its own, as it may keep the connection open
+            result = accept_rpc_client(rpc_socket.as_ref()) => {
+                match result {
+                    Ok(connection) => {
+                        tokio::spawn(rpc::serve(connection, tx.clone()));
+                    }
+                    Err(err) => {
+                        error!("Failed to accept RPC client: {}", err);
+                    }
+                }
+            },
+            // Handle interrupt signals for config reload and clean shutdown
             signal = signals.next() => {
                 match signal {
-                    Some(s @ SIGHUP | s @ SIGTERM | s @ SIGINT | s @ SIGQUIT) => {
+                    Some(SIGHUP) => {
+                        info!("Received SIGHUP, reloading config from {config_path:?}");
+                        notifier.reloading();
+                        match TeliodDaemonConfig::from_file(&config_path) {
+                            Ok(new_config) => {
+                                if new_config.log_level != active_config.log_level {
+                                    if let Err(e) = log_level.reload(new_config.log_level) {
+                                        error!("Unable to change the log level due to {e}");
+                                    }
+                                }
+                                if new_config.interface != active_config.interface {
+                                    #[allow(mpsc_blocking_send)]
+                                    if let Err(e) = tx.send(TelioTaskCmd::ReloadInterface(new_config.interface.clone())).await {
+                                        error!("Unable to reload the interface due to {e}");
+                                    }
+                                }
+                                if new_config.rpc_socket_path != active_config.rpc_socket_path {
+                                    // Clients already connected are served until they disconnect
+                                    rpc_socket = new_config.rpc_socket_path.as_deref().and_then(|path| {
+                                        DaemonSocket::new(path)
+                                            .map_err(|e| error!("Unable to open the RPC socket due to {e}"))
+                                            .ok()
+                                    });
+                                }
+                                let applied = TeliodDaemonConfig {
+                                    log_level: active_config.log_level,
+                                    interface: active_config.interface.clone(),
+                                    rpc_socket_path: active_config.rpc_socket_path.clone(),
+                                    ..new_config.clone()
+                                };
+                                if applied != active_config {
+                                    warn!("Changes of the log file, token, certificate and mqtt are applied once teliod restarts");
+                                }
+                                active_config = new_config;
+                            }
+                            Err(e) => {
+                                error!("Unable to reload config due to {e}, keeping the current one");
+                            }
+                        }
+                        // The meshmap may have changed while nobody was listening
+                        task_retrieve_meshmap(identity_ptr.clone(), token_ptr.clone(), tx.clone());
+                        notifier.ready("Config reloaded");
+                    }
+                    Some(s @ SIGTERM | s @ SIGINT | s @ SIGQUIT) => {
                         info!("Received signal {:?}, exiting", Signal::try_from(s));
+                        health_check.abort();
+                        notifier.stopping();
                         if let Err(e) = tx.send_timeout(TelioTaskCmd::Quit, Duration::from_secs(2)).await {
                             error!("Unable to send QUIT due to {e}");
                         };
+                        // Let the device stop, so the interface is gone before the daemon exits
+                        if timeout(SHUTDOWN_TIMEOUT, &mut telio_task_handle).await.is_err() {
+                            warn!("Telio task did not stop in {SHUTDOWN_TIMEOUT:?}");
+                        }
                         break Ok(());
                     }
                     Some(s) => {
@@ -284,5 +472,7 @@ pub async fn daemon_event_loop(config: TeliodDaemonConfig) -> Result<(), TeliodE
                 }
             }
         }
-    }
+    };
+    health_check.abort();
+    result
 }
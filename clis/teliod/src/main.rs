@@ -3,14 +3,13 @@
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 use serde_json::error::Error as SerdeJsonError;
-use std::{fs::File, net::IpAddr};
+use std::{net::IpAddr, path::PathBuf};
 use telio::{device::Error as DeviceError, telio_model::mesh::Node};
 use thiserror::Error as ThisError;
 use tokio::{
     task::JoinError,
     time::{timeout, Duration},
 };
-use tracing::{debug, error};
 
 #[cfg(feature = "cgi")]
 mod cgi;
@@ -21,6 +20,8 @@ mod configure_interface;
 mod core_api;
 mod daemon;
 mod nc;
+mod rpc;
+mod systemd;
 
 use crate::{
     command_listener::CommandResponse,
@@ -105,20 +106,10 @@ async fn main() -> Result<(), TeliodError> {
             if DaemonSocket::get_ipc_socket_path()?.exists() {
                 Err(TeliodError::DaemonIsRunning)
             } else {
-                let file = File::open(&config_path)?;
+                let config_path = PathBuf::from(config_path);
+                let config = TeliodDaemonConfig::from_file(&config_path)?;
 
-                let mut config: TeliodDaemonConfig = serde_json::from_reader(file)?;
-
-                if let Ok(token) = std::env::var("NORD_TOKEN") {
-                    debug!("Overriding token from env");
-                    if token.len() == 64 && token.chars().all(|c| c.is_ascii_hexdigit()) {
-                        config.authentication_token = token;
-                    } else {
-                        error!("Token from env not valid")
-                    }
-                }
-
-                Box::pin(daemon::daemon_event_loop(config)).await
+                Box::pin(daemon::daemon_event_loop(config, config_path)).await
             }
         }
         Cmd::Client(cmd) => {
@@ -0,0 +1,379 @@
+//! JSON-RPC 2.0 control interface of the daemon.
+//!
+//! Opened on its own local socket when `rpc_socket_path` is configured, so the daemon can be
+//! driven by CLIs and GUIs in any language without linking libtelio. Every request and response
+//! is a single line of JSON, and a client may send any number of requests over one connection.
+//! A meshmap set by `set_meshnet` replaces the one of the API until the daemon restarts.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::net::IpAddr;
+use telio::{
+    crypto::PublicKey,
+    device::{Device, Error as DeviceError},
+    telio_model::{config::Config as MeshMap, mesh::ExitNode, network::NetworkInfo},
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error};
+
+use crate::{comms::DaemonConnection, daemon::TelioTaskCmd};
+
+const JSONRPC_VERSION: &str = "2.0";
+/// Longest request line, enough for the meshmap of a large meshnet
+const MAX_REQUEST_LEN: usize = 4 * 1024 * 1024;
+
+// Error codes defined by the JSON-RPC 2.0 specification
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// The device rejected the call, from the range left to the applications by the specification
+const DEVICE_ERROR: i64 = -32000;
+
+/// Device API calls made by the telio task on behalf of the RPC clients
+#[derive(Debug)]
+pub enum DeviceCall {
+    SetMeshnet(Option<MeshMap>),
+    ConnectExitNode(ExitNode),
+    DisconnectExitNode(PublicKey),
+    DisconnectExitNodes,
+    NotifyNetworkChange(NetworkInfo),
+    EnableMagicDns(Vec<IpAddr>),
+    DisableMagicDns,
+}
+
+impl DeviceCall {
+    pub fn execute(self, telio: &Device) -> Result<(), DeviceError> {
+        match self {
+            DeviceCall::SetMeshnet(meshmap) => telio.set_config(&meshmap),
+            DeviceCall::ConnectExitNode(node) => telio.connect_exit_node(&node),
+            DeviceCall::DisconnectExitNode(public_key) => telio.disconnect_exit_node(&public_key),
+            DeviceCall::DisconnectExitNodes => telio.disconnect_exit_nodes(),
+            DeviceCall::NotifyNetworkChange(info) => telio.notify_network_change(info),
+            DeviceCall::EnableMagicDns(upstream_servers) => {
+                telio.enable_magic_dns(&upstream_servers)
+            }
+            DeviceCall::DisableMagicDns => telio.disable_magic_dns(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// Notifications have no id and get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct MeshnetParams {
+    meshmap: Option<MeshMap>,
+}
+
+#[derive(Deserialize)]
+struct ExitNodeParams {
+    public_key: PublicKey,
+}
+
+#[derive(Deserialize)]
+struct MagicDnsParams {
+    upstream_servers: Vec<IpAddr>,
+}
+
+enum Method {
+    GetStatus,
+    Device(DeviceCall),
+}
+
+impl Method {
+    fn parse(method: &str, params: Value) -> Result<Self, RpcError> {
+        let call = match method {
+            "get_status" => return Ok(Method::GetStatus),
+            "set_meshnet" => DeviceCall::SetMeshnet(params_of::<MeshnetParams>(params)?.meshmap),
+            "connect_exit_node" => DeviceCall::ConnectExitNode(params_of(params)?),
+            "disconnect_exit_node" => {
+                DeviceCall::DisconnectExitNode(params_of::<ExitNodeParams>(params)?.public_key)
+            }
+            "disconnect_exit_nodes" => DeviceCall::DisconnectExitNodes,
+            "notify_network_change" => DeviceCall::NotifyNetworkChange(match params {
+                Value::Null => NetworkInfo::default(),
+                params => params_of(params)?,
+            }),
+            "enable_magic_dns" => {
+                DeviceCall::EnableMagicDns(params_of::<MagicDnsParams>(params)?.upstream_servers)
+            }
+            "disable_magic_dns" => DeviceCall::DisableMagicDns,
+            _ => {
+                return Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("Unknown method {method}"),
+                ))
+            }
+        };
+        Ok(Method::Device(call))
+    }
+}
+
+fn params_of<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn internal_error(e: impl ToString) -> RpcError {
+    RpcError::new(INTERNAL_ERROR, e)
+}
+
+async fn call(
+    method: Method,
+    telio_task_tx: &mpsc::Sender<TelioTaskCmd>,
+) -> Result<Value, RpcError> {
+    match method {
+        Method::GetStatus => {
+            let (response_tx, response_rx) = oneshot::channel();
+            #[allow(mpsc_blocking_send)]
+            telio_task_tx
+                .send(TelioTaskCmd::GetStatus(response_tx))
+                .await
+                .map_err(internal_error)?;
+            let report = response_rx.await.map_err(internal_error)?;
+            serde_json::to_value(report).map_err(internal_error)
+        }
+        Method::Device(device_call) => {
+            let (response_tx, response_rx) = oneshot::channel();
+            #[allow(mpsc_blocking_send)]
+            telio_task_tx
+                .send(TelioTaskCmd::Device(device_call, response_tx))
+                .await
+                .map_err(internal_error)?;
+            response_rx
+                .await
+                .map_err(internal_error)?
+                .map_err(|e| RpcError::new(DEVICE_ERROR, e))?;
+            Ok(Value::Null)
+        }
+    }
+}
+
+/// Handle a single request line, returning the response unless the request is a notification
+pub async fn handle_request(
+    request: &str,
+    telio_task_tx: &mpsc::Sender<TelioTaskCmd>,
+) -> Option<RpcResponse> {
+    let request = match serde_json::from_str::<Value>(request) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(RpcResponse::new(
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, e)),
+            ))
+        }
+    };
+    let request = match serde_json::from_value::<RpcRequest>(request) {
+        Ok(request) if request.jsonrpc == JSONRPC_VERSION => request,
+        _ => {
+            return Some(RpcResponse::new(
+                Value::Null,
+                Err(RpcError::new(INVALID_REQUEST, "Not a JSON-RPC 2.0 request")),
+            ))
+        }
+    };
+
+    debug!("RPC request {}", request.method);
+    let result = match Method::parse(&request.method, request.params) {
+        Ok(method) => call(method, telio_task_tx).await,
+        Err(e) => Err(e),
+    };
+    request.id.map(|id| RpcResponse::new(id, result))
+}
+
+/// Answer the requests of a client until it closes the connection
+pub async fn serve(mut connection: DaemonConnection, telio_task_tx: mpsc::Sender<TelioTaskCmd>) {
+    loop {
+        let request = match connection.read_command_bounded(MAX_REQUEST_LEN).await {
+            Ok(request) if request.is_empty() => break,
+            Ok(request) => request,
+            Err(e) => {
+                error!("Failed reading RPC request: {e}");
+                break;
+            }
+        };
+        let Some(response) = handle_request(&request, &telio_task_tx).await else {
+            continue;
+        };
+        let response = match serde_json::to_string(&response) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed serializing RPC response: {e}");
+                break;
+            }
+        };
+        if let Err(e) = connection.respond(format!("{response}\n")).await {
+            error!("Failed sending RPC response: {e}");
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{comms::DaemonSocket, TelioStatusReport};
+    use serde_json::json;
+    use std::path::Path;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+        task,
+    };
+
+    // Fake telio task, answering every command with `device_result`
+    fn make_telio_task(device_result: Result<(), String>) -> mpsc::Sender<TelioTaskCmd> {
+        let (tx, mut rx) = mpsc::channel(1);
+        task::spawn(async move {
+            while let Some(cmd) = rx.recv().await {
+                match cmd {
+                    TelioTaskCmd::GetStatus(response_tx) => {
+                        let _ = response_tx.send(TelioStatusReport::default());
+                    }
+                    TelioTaskCmd::Device(_, response_tx) => {
+                        let _ = response_tx.send(device_result.clone());
+                    }
+                    _ => (),
+                }
+            }
+        });
+        tx
+    }
+
+    fn make_call(method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params})
+    }
+
+    async fn request(tx: &mpsc::Sender<TelioTaskCmd>, request: Value) -> Option<RpcResponse> {
+        handle_request(&request.to_string(), tx).await
+    }
+
+    #[tokio::test]
+    async fn methods_are_answered() {
+        let tx = make_telio_task(Ok(()));
+
+        let response = request(
+            &tx,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "get_status"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.id, json!(1));
+        assert_eq!(
+            response.result,
+            Some(serde_json::to_value(TelioStatusReport::default()).unwrap())
+        );
+
+        let response = request(
+            &tx,
+            json!({
+                "jsonrpc": "2.0",
+                "id": "dns",
+                "method": "enable_magic_dns",
+                "params": {"upstream_servers": ["1.1.1.1"]},
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.result, Some(Value::Null));
+        assert_eq!(response.error, None);
+
+        // Notifications get no response
+        assert_eq!(
+            request(
+                &tx,
+                json!({"jsonrpc": "2.0", "method": "disconnect_exit_nodes"})
+            )
+            .await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_are_reported_with_their_codes() {
+        let tx = make_telio_task(Err("Device not started".to_owned()));
+        let code = |response: Option<RpcResponse>| response.unwrap().error.unwrap().code;
+
+        assert_eq!(code(handle_request("{\"jsonrpc\"", &tx).await), PARSE_ERROR);
+        let mut old_version = make_call("get_status", Value::Null);
+        old_version["jsonrpc"] = json!("1.0");
+        assert_eq!(code(request(&tx, old_version).await), INVALID_REQUEST);
+        assert_eq!(
+            code(request(&tx, make_call("reboot", Value::Null)).await),
+            METHOD_NOT_FOUND
+        );
+        let bad_key = make_call("disconnect_exit_node", json!({"public_key": 42}));
+        assert_eq!(code(request(&tx, bad_key).await), INVALID_PARAMS);
+        assert_eq!(
+            code(request(&tx, make_call("disable_magic_dns", Value::Null)).await),
+            DEVICE_ERROR
+        );
+    }
+    #[tokio::test]
+    async fn overlong_request_closes_the_connection() {
+        let path = format!("test_rpc_socket_{}", rand::random::<u16>());
+        let socket = DaemonSocket::new(Path::new(&path)).unwrap();
+        let tx = make_telio_task(Ok(()));
+
+        let client = async {
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            // The daemon stops reading past the limit, so the rest of the write may fail
+            let _ = stream.write_all(&vec![b' '; MAX_REQUEST_LEN + 1]).await;
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response).await;
+            response
+        };
+        let server = async { serve(socket.accept().await.unwrap(), tx.clone()).await };
+        let (response, ()) = tokio::join!(client, server);
+        assert_eq!(response, "");
+    }
+}
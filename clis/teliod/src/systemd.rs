@@ -0,0 +1,160 @@
+//! Integration with the systemd service manager, doing nothing when not started by it.
+//!
+//! The service state is reported over the datagram socket passed in `NOTIFY_SOCKET`, as described
+//! in `sd_notify(3)`, so teliod can run as a `Type=notify-reload` service with `WatchdogSec=` set.
+
+use std::{env, os::unix::net::UnixDatagram, path::PathBuf, time::Duration};
+
+use nix::time::{clock_gettime, ClockId};
+use tracing::{debug, warn};
+
+/// Reports the state of the daemon to systemd
+pub struct Notifier {
+    socket: Option<(UnixDatagram, PathBuf)>,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Notifier for the socket and watchdog timeout given by systemd in the environment
+    pub fn from_env() -> Self {
+        let watchdog_usec = env::var("WATCHDOG_USEC").ok();
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        Self::new(
+            env::var_os("NOTIFY_SOCKET").map(PathBuf::from),
+            watchdog_usec.as_deref(),
+            watchdog_pid.as_deref(),
+        )
+    }
+
+    fn new(
+        socket_path: Option<PathBuf>,
+        watchdog_usec: Option<&str>,
+        watchdog_pid: Option<&str>,
+    ) -> Self {
+        let socket = socket_path.and_then(|path| match UnixDatagram::unbound() {
+            Ok(socket) => Some((socket, path)),
+            Err(e) => {
+                warn!("Failed to create the systemd notification socket: {e}");
+                None
+            }
+        });
+        // The watchdog may be meant for another process of the service
+        let for_us = watchdog_pid.map_or(true, |pid| pid == std::process::id().to_string());
+        let watchdog = watchdog_usec
+            .and_then(|usec| usec.parse().ok())
+            .filter(|usec| *usec > 0 && for_us)
+            .map(Duration::from_micros);
+
+        Self { socket, watchdog }
+    }
+
+    /// Interval of the watchdog pings, half of the timeout so a late one is not fatal
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    /// Startup or reload finished
+    pub fn ready(&self, status: &str) {
+        self.notify(&format!("READY=1\nSTATUS={status}"));
+    }
+
+    /// Configuration is being reloaded, `ready` is expected once it is done
+    pub fn reloading(&self) {
+        let monotonic_usec = clock_gettime(ClockId::CLOCK_MONOTONIC)
+            .map(|now| Duration::from(now).as_micros())
+            .unwrap_or_default();
+        self.notify(&format!("RELOADING=1\nMONOTONIC_USEC={monotonic_usec}"));
+    }
+
+    /// Shutdown started
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// The daemon is healthy, systemd restarts it if these stop for the watchdog timeout
+    pub fn watchdog(&self) {
+        if self.watchdog.is_some() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+
+    fn notify(&self, state: &str) {
+        let Some((socket, path)) = &self.socket else {
+            return;
+        };
+        debug!("Notifying systemd: {state:?}");
+        if let Err(e) = send_to(socket, path, state.as_bytes()) {
+            warn!("Failed to notify systemd: {e}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_to(socket: &UnixDatagram, path: &std::path::Path, state: &[u8]) -> std::io::Result<()> {
+    use std::os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt, unix::net::SocketAddr};
+
+    // Sockets in the abstract namespace are given with a leading '@'
+    match path.as_os_str().as_bytes().strip_prefix(b"@") {
+        Some(name) => socket.send_to_addr(state, &SocketAddr::from_abstract_name(name)?),
+        None => socket.send_to(state, path),
+    }
+    .map(drop)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_to(socket: &UnixDatagram, path: &std::path::Path, state: &[u8]) -> std::io::Result<()> {
+    socket.send_to(state, path).map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn make_systemd_socket() -> (UnixDatagram, PathBuf) {
+        let path = env::temp_dir().join(format!(
+            "teliod_notify_{}.sock",
+            rand::thread_rng().gen::<u32>()
+        ));
+        (UnixDatagram::bind(&path).unwrap(), path)
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0u8; 256];
+        let size = socket.recv(&mut buffer).unwrap();
+        String::from_utf8(buffer[..size].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn state_is_reported_to_systemd() {
+        let (systemd, path) = make_systemd_socket();
+        let notifier = Notifier::new(Some(path.clone()), Some("10000000"), None);
+
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(5)));
+        notifier.ready("Meshnet running");
+        assert_eq!(receive(&systemd), "READY=1\nSTATUS=Meshnet running");
+        notifier.watchdog();
+        assert_eq!(receive(&systemd), "WATCHDOG=1");
+        notifier.reloading();
+        assert!(receive(&systemd).starts_with("RELOADING=1\nMONOTONIC_USEC="));
+        notifier.stopping();
+        assert_eq!(receive(&systemd), "STOPPING=1");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn watchdog_of_other_process_is_ignored() {
+        let notifier = Notifier::new(None, Some("10000000"), Some("1"));
+        assert_eq!(notifier.watchdog_interval(), None);
+
+        let own_pid = std::process::id().to_string();
+        let notifier = Notifier::new(None, Some("10000000"), Some(&own_pid));
+        assert!(notifier.watchdog_interval().is_some());
+
+        let notifier = Notifier::new(None, Some("0"), None);
+        assert_eq!(notifier.watchdog_interval(), None);
+        // Not started by systemd, nothing to do
+        notifier.ready("Meshnet running");
+    }
+}
@@ -0,0 +1,34 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use telio_bench::config::{meshnet_config, ConfigTarget};
+use tokio::runtime::Runtime;
+
+const PEER_COUNTS: [usize; 5] = [1, 8, 32, 64, 256];
+
+pub fn config_apply_benchmarks(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to start runtime");
+
+    let mut group = c.benchmark_group("meshnet config apply");
+    for peers in PEER_COUNTS {
+        // Alternate between two different configs, so every apply has to replace all peers
+        let configs = [
+            serde_json::to_string(&meshnet_config(peers, 0)).expect("Failed to serialize config"),
+            serde_json::to_string(&meshnet_config(peers, 1)).expect("Failed to serialize config"),
+        ];
+        group.bench_with_input(
+            BenchmarkId::from_parameter(peers),
+            &configs,
+            |b, configs| {
+                let target = rt.block_on(async { ConfigTarget::start() });
+                let mut which = 0;
+                b.iter(|| {
+                    rt.block_on(target.apply(&configs[which]));
+                    which = (which + 1) % configs.len();
+                });
+                rt.block_on(target.stop());
+            },
+        );
+    }
+}
+
+criterion_group!(benches, config_apply_benchmarks);
+criterion_main!(benches);
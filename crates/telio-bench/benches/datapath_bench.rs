@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use telio_bench::{pipeline::Pipeline, tunnel::TunnelPair};
+use telio_firewall::firewall::tests::make_udp;
+use tokio::runtime::Runtime;
+
+const PEER_COUNTS: [usize; 4] = [1, 4, 16, 64];
+const PACKET_COUNT: usize = 10_000;
+/// Sizes of the IP packets, the largest one is the default WG MTU
+const PACKET_SIZES: [usize; 3] = [64, 512, 1420];
+const IPV4_HEADER: usize = 20;
+
+/// UDP packet padded to `size`, NepTUN trims decrypted packets to the length in the IP header
+fn udp_packet(size: usize) -> Vec<u8> {
+    let mut packet = make_udp("100.64.0.2:1111", "100.64.0.1:2222");
+    packet.resize(size, 0);
+    packet[2..4].copy_from_slice(&(size as u16).to_be_bytes());
+    packet[IPV4_HEADER + 4..IPV4_HEADER + 6]
+        .copy_from_slice(&((size - IPV4_HEADER) as u16).to_be_bytes());
+    packet
+}
+
+pub fn proxy_firewall_benchmarks(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to start runtime");
+    let packet = make_udp("127.0.0.2:1111", "127.0.0.1:2222");
+
+    let mut group = c.benchmark_group("proxy and firewall - packets");
+    group.throughput(Throughput::Elements(PACKET_COUNT as u64));
+    for peers in PEER_COUNTS {
+        group.bench_with_input(BenchmarkId::from_parameter(peers), &peers, |b, peers| {
+            let mut pipeline = rt.block_on(Pipeline::start(*peers));
+            b.iter(|| {
+                assert_eq!(
+                    rt.block_on(pipeline.run(&packet, PACKET_COUNT)),
+                    PACKET_COUNT
+                )
+            });
+            rt.block_on(pipeline.stop());
+        });
+    }
+}
+
+pub fn wireguard_benchmarks(c: &mut Criterion) {
+    {
+        let mut group = c.benchmark_group("wireguard encrypt");
+        for size in PACKET_SIZES {
+            let packet = udp_packet(size);
+            group.throughput(Throughput::Bytes(packet.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &packet, |b, packet| {
+                let mut pair = TunnelPair::new();
+                b.iter(|| pair.encrypt(packet));
+            });
+        }
+    }
+
+    {
+        let mut group = c.benchmark_group("wireguard encrypt and decrypt");
+        for size in PACKET_SIZES {
+            let packet = udp_packet(size);
+            group.throughput(Throughput::Bytes(packet.len() as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(size), &packet, |b, packet| {
+                let mut pair = TunnelPair::new();
+                b.iter(|| assert_eq!(pair.roundtrip(packet), packet.len()));
+            });
+        }
+    }
+}
+
+criterion_group!(benches, proxy_firewall_benchmarks, wireguard_benchmarks);
+criterion_main!(benches);
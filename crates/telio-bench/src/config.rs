@@ -0,0 +1,155 @@
+//! Applying meshnet configs to the components which are usable without an adapter
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use telio_crypto::{keygen::KeyGenerator, PublicKey};
+use telio_firewall::firewall::{Firewall, Permissions, StatefullFirewall, FILE_SEND_PORT};
+use telio_model::{
+    config::{Config, Peer, PeerBase},
+    features::FeatureFirewall,
+};
+use telio_proto::DataMsg;
+use telio_proxy::{Config as ProxyConfig, Io, UdpProxy};
+use telio_task::io::Chan;
+use telio_utils::Hidden;
+
+use crate::KEY_SEED;
+
+/// Meshnet config with `peers` peers, as received from the API
+///
+/// Configs of different generations share this node, but none of the peer keys.
+pub fn meshnet_config(peers: usize, generation: u64) -> Config {
+    let ip = |i: usize| IpAddr::V4(Ipv4Addr::new(100, 64, (i / 256) as u8, (i % 256) as u8));
+    let (_, this_key) = KeyGenerator::seeded(KEY_SEED).keypair();
+    let peers = (1..=peers)
+        .zip(KeyGenerator::seeded(KEY_SEED.wrapping_add(generation + 1)).public_keys(peers))
+        .map(|(i, public_key)| Peer {
+            base: PeerBase {
+                identifier: format!("{i:032x}"),
+                public_key,
+                hostname: Hidden(format!("peer-{i}.nord")),
+                ip_addresses: Some(vec![ip(i)]),
+                ..Default::default()
+            },
+            allow_incoming_connections: i % 2 == 0,
+            allow_peer_send_files: i % 3 == 0,
+            ..Default::default()
+        })
+        .collect();
+
+    Config {
+        this: PeerBase {
+            identifier: format!("{:032x}", 0),
+            public_key: this_key,
+            hostname: Hidden("this.nord".to_owned()),
+            ip_addresses: Some(vec![ip(0)]),
+            ..Default::default()
+        },
+        peers: Some(peers),
+        ..Default::default()
+    }
+}
+
+/// Proxy and firewall, reconfigured on each meshnet config.
+///
+/// WireGuard itself needs an adapter, so its part of the config is left out.
+pub struct ConfigTarget {
+    proxy: UdpProxy,
+    firewall: StatefullFirewall,
+    _relay: Chan<(PublicKey, DataMsg)>,
+}
+
+impl ConfigTarget {
+    /// Start the proxy with an empty config
+    pub fn start() -> Self {
+        let (proxy_io, relay) = Chan::pipe();
+        Self {
+            proxy: UdpProxy::start(Io { relay: proxy_io }),
+            firewall: StatefullFirewall::new(true, FeatureFirewall::default()),
+            _relay: relay,
+        }
+    }
+
+    /// Parse the config and apply it just like the device does for these components
+    pub async fn apply(&self, json: &str) {
+        let config: Config = serde_json::from_str(json).expect("Invalid meshnet config");
+        let peers = config.peers.unwrap_or_default();
+
+        self.firewall
+            .set_ip_addresses(config.this.ip_addresses.clone().unwrap_or_default());
+        self.firewall.clear_peer_whitelists();
+        self.firewall.clear_port_whitelist();
+        for peer in &peers {
+            if peer.allow_incoming_connections {
+                self.firewall
+                    .add_to_peer_whitelist(peer.public_key, Permissions::IncomingConnections);
+            }
+            if peer.allow_peer_local_network_access {
+                self.firewall
+                    .add_to_peer_whitelist(peer.public_key, Permissions::LocalAreaConnections);
+            }
+            if peer.allow_peer_traffic_routing {
+                self.firewall
+                    .add_to_peer_whitelist(peer.public_key, Permissions::RoutingConnections);
+            }
+            if peer.allow_peer_send_files {
+                self.firewall
+                    .add_to_port_whitelist(peer.public_key, FILE_SEND_PORT);
+            }
+        }
+
+        self.proxy
+            .configure(ProxyConfig {
+                wg_port: None,
+                peers: peers.iter().map(|p| p.public_key).collect(),
+                idle_timeout: None,
+            })
+            .await
+            .expect("Failed to configure proxy");
+    }
+
+    /// Stop the proxy
+    pub async fn stop(self) {
+        self.proxy.stop().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_proxy::Proxy;
+
+    #[tokio::test]
+    async fn config_is_applied() {
+        let config = meshnet_config(10, 0);
+        let target = ConfigTarget::start();
+        target.apply(&serde_json::to_string(&config).unwrap()).await;
+
+        assert_eq!(target.proxy.get_endpoint_map().await.unwrap().len(), 10);
+        assert_eq!(
+            target
+                .firewall
+                .get_peer_whitelist(Permissions::IncomingConnections)
+                .len(),
+            5
+        );
+        assert_eq!(target.firewall.get_port_whitelist().len(), 3);
+        target.stop().await;
+    }
+
+    #[test]
+    fn generations_differ_only_in_peers() {
+        let (a, b) = (meshnet_config(4, 0), meshnet_config(4, 1));
+        assert_eq!(a.this.public_key, b.this.public_key);
+
+        let keys = |c: &Config| -> Vec<PublicKey> {
+            c.peers
+                .iter()
+                .flatten()
+                .map(|p| p.base.public_key)
+                .collect()
+        };
+        assert!(keys(&a).iter().all(|k| !keys(&b).contains(k)));
+        assert_eq!(keys(&a), keys(&meshnet_config(4, 0)));
+    }
+}
@@ -0,0 +1,15 @@
+#![deny(missing_docs)]
+
+//! Loopback harnesses for the benchmarks of the datapath and config handling.
+//!
+//! Time to direct connection is measured with real NATs by the nat-lab `test_time_to_direct`.
+//!
+//! Everything runs in a single process over the loopback, so the numbers are comparable
+//! between runs on the same machine, but not between machines.
+
+pub mod config;
+pub mod pipeline;
+pub mod tunnel;
+
+/// Seed of the generated keys, so every run benchmarks the same configs
+pub const KEY_SEED: u64 = 0x7e110;
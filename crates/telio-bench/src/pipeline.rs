@@ -0,0 +1,118 @@
+//! Relayed datapath: WG socket -> `UdpProxy` -> relay channel -> firewall
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use telio_crypto::{keygen::KeyGenerator, PublicKey};
+use telio_firewall::firewall::{Firewall, Permissions, StatefullFirewall};
+use telio_model::features::FeatureFirewall;
+use telio_proto::DataMsg;
+use telio_proxy::{Config, Io, Proxy, UdpProxy};
+use telio_task::io::Chan;
+use tokio::net::UdpSocket;
+
+use crate::KEY_SEED;
+
+/// Address of this node as seen by the firewall
+pub const LOCAL_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+const PACKETS_IN_FLIGHT_PER_PEER: usize = 16;
+
+/// Proxy with `peers` configured peers, whose relayed packets are inspected by the firewall
+pub struct Pipeline {
+    wg: UdpSocket,
+    proxy: UdpProxy,
+    relay: Chan<(PublicKey, DataMsg)>,
+    endpoints: Vec<SocketAddr>,
+    firewall: StatefullFirewall,
+}
+
+impl Pipeline {
+    /// Start the proxy and whitelist all of the peers in the firewall, needs at least one peer
+    pub async fn start(peers: usize) -> Self {
+        assert!(peers > 0, "Pipeline needs at least one peer");
+        let wg = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("Failed to bind WG socket");
+        let (proxy_io, relay) = Chan::pipe();
+        let proxy = UdpProxy::start(Io { relay: proxy_io });
+
+        let firewall = StatefullFirewall::new(true, FeatureFirewall::default());
+        firewall.set_ip_addresses(vec![LOCAL_IP]);
+        let keys = KeyGenerator::seeded(KEY_SEED).public_keys(peers);
+        for key in &keys {
+            firewall.add_to_peer_whitelist(*key, Permissions::IncomingConnections);
+        }
+
+        proxy
+            .configure(Config {
+                wg_port: Some(wg.local_addr().expect("No WG address").port()),
+                peers: keys.iter().copied().collect(),
+                idle_timeout: None,
+            })
+            .await
+            .expect("Failed to configure proxy");
+        let endpoints = proxy
+            .get_endpoint_map()
+            .await
+            .expect("Failed to get proxy endpoints");
+        let endpoints = keys.iter().map(|key| endpoints[key][0]).collect();
+
+        Self {
+            wg,
+            proxy,
+            relay,
+            endpoints,
+            firewall,
+        }
+    }
+
+    /// Send `count` packets spread over all of the peers and wait for them to reach the relay.
+    ///
+    /// Returns how many of them were accepted by the firewall.
+    pub async fn run(&mut self, packet: &[u8], count: usize) -> usize {
+        // Keep the amount of packets in flight within the proxy socket buffers
+        let batch = self.endpoints.len() * PACKETS_IN_FLIGHT_PER_PEER;
+        let mut accepted = 0;
+        let mut sent = 0;
+        while sent < count {
+            let size = batch.min(count - sent);
+            for i in sent..sent + size {
+                let endpoint = self.endpoints[i % self.endpoints.len()];
+                self.wg
+                    .send_to(packet, endpoint)
+                    .await
+                    .expect("Failed to send to proxy");
+            }
+            for _ in 0..size {
+                let (key, msg) = self.relay.rx.recv().await.expect("Proxy stopped");
+                if self
+                    .firewall
+                    .process_inbound_packet(&key.0, msg.get_payload())
+                {
+                    accepted += 1;
+                }
+            }
+            sent += size;
+        }
+        accepted
+    }
+
+    /// Stop the proxy
+    pub async fn stop(self) {
+        self.proxy.stop().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_firewall::firewall::tests::make_udp;
+
+    #[tokio::test]
+    async fn packets_pass_through_the_pipeline() {
+        let mut pipeline = Pipeline::start(4).await;
+        let packet = make_udp("127.0.0.2:1111", "127.0.0.1:2222");
+        assert_eq!(pipeline.run(&packet, 100).await, 100);
+        pipeline.stop().await;
+    }
+}
@@ -0,0 +1,111 @@
+//! Pair of NepTUN tunnels with a completed handshake, without any sockets in between
+
+use neptun::noise::{Tunn, TunnResult};
+use telio_crypto::SecretKey;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Large enough for any packet together with the WireGuard overhead
+const BUF_SIZE: usize = 65536;
+
+/// Two ends of a WireGuard tunnel
+pub struct TunnelPair {
+    sender: Tunn,
+    receiver: Tunn,
+    encrypted: Box<[u8; BUF_SIZE]>,
+    decrypted: Box<[u8; BUF_SIZE]>,
+}
+
+impl TunnelPair {
+    /// Create both ends and run the handshake between them
+    pub fn new() -> Self {
+        let (sender_key, receiver_key) = (SecretKey::gen(), SecretKey::gen());
+        let tunn = |own: &SecretKey, peer: &SecretKey, index| {
+            Tunn::new(
+                StaticSecret::from(own.clone().into_bytes()),
+                PublicKey::from(peer.public().0),
+                None,
+                None,
+                index,
+                None,
+            )
+            .expect("Failed to create tunnel")
+        };
+
+        let mut pair = Self {
+            sender: tunn(&sender_key, &receiver_key, 0),
+            receiver: tunn(&receiver_key, &sender_key, 1),
+            encrypted: Box::new([0; BUF_SIZE]),
+            decrypted: Box::new([0; BUF_SIZE]),
+        };
+        pair.handshake();
+        pair
+    }
+
+    fn handshake(&mut self) {
+        let mut msg = match self
+            .sender
+            .format_handshake_initiation(&mut self.encrypted[..], false)
+        {
+            TunnResult::WriteToNetwork(init) => init.to_vec(),
+            other => panic!("Unexpected handshake initiation result {other:?}"),
+        };
+
+        // Bounce the messages until both ends are done: initiation, response, keepalive
+        let mut to_receiver = true;
+        loop {
+            let tunn = if to_receiver {
+                &mut self.receiver
+            } else {
+                &mut self.sender
+            };
+            match tunn.decapsulate(None, &msg, &mut self.decrypted[..]) {
+                TunnResult::WriteToNetwork(reply) => msg = reply.to_vec(),
+                TunnResult::Done => break,
+                other => panic!("Unexpected handshake result {other:?}"),
+            }
+            to_receiver = !to_receiver;
+        }
+    }
+
+    /// Encrypt the IP packet on the sending end, returns the size of the WireGuard packet
+    pub fn encrypt(&mut self, packet: &[u8]) -> usize {
+        match self.sender.encapsulate(packet, &mut self.encrypted[..]) {
+            TunnResult::WriteToNetwork(encrypted) => encrypted.len(),
+            other => panic!("Unexpected encapsulation result {other:?}"),
+        }
+    }
+
+    /// Encrypt the IP packet and decrypt it on the receiving end, returns the decrypted size
+    pub fn roundtrip(&mut self, packet: &[u8]) -> usize {
+        let size = self.encrypt(packet);
+        match self
+            .receiver
+            .decapsulate(None, &self.encrypted[..size], &mut self.decrypted[..])
+        {
+            TunnResult::WriteToTunnelV4(decrypted, _)
+            | TunnResult::WriteToTunnelV6(decrypted, _) => decrypted.len(),
+            other => panic!("Unexpected decapsulation result {other:?}"),
+        }
+    }
+}
+
+impl Default for TunnelPair {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_firewall::firewall::tests::make_udp;
+
+    #[test]
+    fn packets_survive_the_roundtrip() {
+        let mut pair = TunnelPair::new();
+        let packet = make_udp("100.64.0.1:1111", "100.64.0.2:2222");
+        for _ in 0..10 {
+            assert_eq!(pair.roundtrip(&packet), packet.len());
+        }
+    }
+}
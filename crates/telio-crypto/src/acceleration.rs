@@ -0,0 +1,183 @@
+//! Report of the CPU features accelerating the ciphers, and of the implementation sealing the
+//! tunnel packets.
+//!
+//! Nothing is dispatched here. When NepTUN runs the tunnel, its packets are sealed by `ring`,
+//! which picks the fastest ChaCha20-Poly1305 for the CPU at runtime on its own, and the report
+//! mirrors that selection. The kernel WireGuard, wireguard-go and WireGuard-NT seal the packets
+//! with their own ciphers, which cannot be told from here, so only the CPU features are reported
+//! for them.
+
+use std::sync::OnceLock;
+
+/// Instruction set extensions of the CPU which speed up the ciphers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuFeatures {
+    /// x86 AES instructions
+    pub aes_ni: bool,
+    /// x86 SSE2 vector instructions
+    pub sse2: bool,
+    /// x86 SSSE3 vector instructions
+    pub ssse3: bool,
+    /// x86 AVX2 vector instructions
+    pub avx2: bool,
+    /// ARM NEON vector instructions
+    pub neon: bool,
+}
+
+impl CpuFeatures {
+    /// Features of the CPU the process runs on
+    pub fn detect() -> Self {
+        detect_cpu_features()
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detect_cpu_features() -> CpuFeatures {
+    CpuFeatures {
+        aes_ni: std::is_x86_feature_detected!("aes"),
+        sse2: std::is_x86_feature_detected!("sse2"),
+        ssse3: std::is_x86_feature_detected!("ssse3"),
+        avx2: std::is_x86_feature_detected!("avx2"),
+        neon: false,
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect_cpu_features() -> CpuFeatures {
+    CpuFeatures {
+        neon: std::arch::is_aarch64_feature_detected!("neon"),
+        ..Default::default()
+    }
+}
+
+// Runtime detection is not stable on 32-bit ARM, only the build target is known
+#[cfg(target_arch = "arm")]
+fn detect_cpu_features() -> CpuFeatures {
+    CpuFeatures {
+        neon: cfg!(target_feature = "neon"),
+        ..Default::default()
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "arm"
+)))]
+fn detect_cpu_features() -> CpuFeatures {
+    CpuFeatures::default()
+}
+
+/// Implementation of a cipher
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CryptoImplementation {
+    /// Plain Rust, without vector instructions
+    #[default]
+    Portable,
+    /// x86 SSSE3 vector instructions
+    Ssse3,
+    /// x86 AVX2 vector instructions
+    Avx2,
+    /// ARM NEON vector instructions
+    Neon,
+}
+
+/// Report of the crypto acceleration of the device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CryptoAccelerationReport {
+    /// Features of the CPU
+    pub cpu_features: CpuFeatures,
+    /// ChaCha20-Poly1305 sealing the tunnel packets, known only while NepTUN runs the tunnel
+    pub tunnel_aead: Option<CryptoImplementation>,
+}
+
+impl CryptoAccelerationReport {
+    /// Report for the given CPU features, `neptun` tells whether NepTUN runs the tunnel
+    pub fn for_features(cpu_features: CpuFeatures, neptun: bool) -> Self {
+        let x86 = cfg!(any(target_arch = "x86", target_arch = "x86_64"));
+        let x86_64 = cfg!(target_arch = "x86_64");
+        let arm = cfg!(any(target_arch = "aarch64", target_arch = "arm"));
+
+        // ring has an AVX2 path only on 64-bit x86
+        let tunnel_aead = neptun.then_some(match cpu_features {
+            CpuFeatures { avx2: true, .. } if x86_64 => CryptoImplementation::Avx2,
+            CpuFeatures { ssse3: true, .. } if x86 => CryptoImplementation::Ssse3,
+            CpuFeatures { neon: true, .. } if arm => CryptoImplementation::Neon,
+            _ => CryptoImplementation::Portable,
+        });
+
+        Self {
+            cpu_features,
+            tunnel_aead,
+        }
+    }
+}
+
+/// Report of the crypto acceleration on this CPU, detected once, `neptun` tells whether NepTUN
+/// runs the tunnel
+pub fn crypto_acceleration_report(neptun: bool) -> CryptoAccelerationReport {
+    static CPU_FEATURES: OnceLock<CpuFeatures> = OnceLock::new();
+    CryptoAccelerationReport::for_features(*CPU_FEATURES.get_or_init(CpuFeatures::detect), neptun)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn portable_without_cpu_features() {
+        let acceleration = CryptoAccelerationReport::for_features(CpuFeatures::default(), true);
+        assert_eq!(
+            acceleration.tunnel_aead,
+            Some(CryptoImplementation::Portable)
+        );
+    }
+
+    #[test]
+    fn tunnel_aead_is_unknown_without_neptun() {
+        let cpu_features = CpuFeatures {
+            avx2: true,
+            ssse3: true,
+            neon: true,
+            ..Default::default()
+        };
+        let acceleration = CryptoAccelerationReport::for_features(cpu_features, false);
+        assert_eq!(acceleration.cpu_features, cpu_features);
+        assert_eq!(acceleration.tunnel_aead, None);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn fastest_x86_implementation_is_picked() {
+        let acceleration = CryptoAccelerationReport::for_features(
+            CpuFeatures {
+                aes_ni: true,
+                sse2: true,
+                ssse3: true,
+                avx2: true,
+                neon: false,
+            },
+            true,
+        );
+        assert_eq!(acceleration.tunnel_aead, Some(CryptoImplementation::Avx2));
+
+        let acceleration = CryptoAccelerationReport::for_features(
+            CpuFeatures {
+                sse2: true,
+                ssse3: true,
+                ..Default::default()
+            },
+            true,
+        );
+        assert_eq!(acceleration.tunnel_aead, Some(CryptoImplementation::Ssse3));
+    }
+
+    #[test]
+    fn detection_is_consistent() {
+        assert_eq!(
+            crypto_acceleration_report(true),
+            CryptoAccelerationReport::for_features(CpuFeatures::detect(), true)
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Generation of X25519 keys from an injectable source of randomness.
+//!
+//! Production code uses the OS CSPRNG, while tests, fuzzing and simulations can plug in a seeded
+//! RNG to get the same keys on every run.
+//!
+//! ```
+//! # use telio_crypto::keygen::KeyGenerator;
+//! let mut a = KeyGenerator::seeded(7);
+//! let mut b = KeyGenerator::seeded(7);
+//! assert_eq!(a.keypairs(4), b.keypairs(4));
+//! ```
+
+use rand::{rngs::OsRng, rngs::StdRng, CryptoRng, RngCore, SeedableRng};
+
+use crate::{PresharedKey, PublicKey, SecretKey, KEY_SIZE};
+
+/// Source of randomness for the generated keys
+///
+/// Implemented for every cryptographically secure RNG, including boxed ones.
+pub trait KeyRng: RngCore + CryptoRng + Send {}
+
+impl<T: RngCore + CryptoRng + Send> KeyRng for T {}
+
+/// Generator of secret, public and preshared keys
+pub struct KeyGenerator<R: KeyRng = OsRng> {
+    rng: R,
+}
+
+impl KeyGenerator {
+    /// Create a generator using the OS CSPRNG
+    pub fn new() -> Self {
+        Self { rng: OsRng }
+    }
+}
+
+impl Default for KeyGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyGenerator<StdRng> {
+    /// Create a deterministic generator, producing the same keys for the same seed.
+    /// Only meant for tests, fuzzing and simulations.
+    pub fn seeded(seed: u64) -> Self {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+}
+
+impl<R: KeyRng> KeyGenerator<R> {
+    /// Create a generator using the given RNG
+    pub fn with_rng(rng: R) -> Self {
+        Self { rng }
+    }
+
+    /// Generate a new secret key
+    pub fn secret_key(&mut self) -> SecretKey {
+        SecretKey::gen_with(&mut self.rng)
+    }
+
+    /// Generate a new secret key along with its public key
+    pub fn keypair(&mut self) -> (SecretKey, PublicKey) {
+        let secret_key = self.secret_key();
+        let public_key = secret_key.public();
+        (secret_key, public_key)
+    }
+
+    /// Generate a new preshared key
+    pub fn preshared_key(&mut self) -> PresharedKey {
+        let mut bytes = [0u8; KEY_SIZE];
+        self.rng.fill_bytes(&mut bytes);
+        PresharedKey::new(bytes)
+    }
+
+    /// Generate `count` new secret keys
+    pub fn secret_keys(&mut self, count: usize) -> Vec<SecretKey> {
+        (0..count).map(|_| self.secret_key()).collect()
+    }
+
+    /// Generate `count` new secret keys along with their public keys
+    pub fn keypairs(&mut self, count: usize) -> Vec<(SecretKey, PublicKey)> {
+        (0..count).map(|_| self.keypair()).collect()
+    }
+
+    /// Generate `count` public keys, dropping their secret keys
+    pub fn public_keys(&mut self, count: usize) -> Vec<PublicKey> {
+        (0..count).map(|_| self.secret_key().public()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn seeded_generators_are_deterministic() {
+        let mut a = KeyGenerator::seeded(42);
+        let mut b = KeyGenerator::seeded(42);
+        assert_eq!(a.secret_keys(8), b.secret_keys(8));
+        assert_eq!(a.preshared_key(), b.preshared_key());
+
+        let mut c = KeyGenerator::seeded(43);
+        assert_ne!(KeyGenerator::seeded(42).secret_key(), c.secret_key());
+    }
+
+    #[test]
+    fn batches_are_unique_and_consistent() {
+        let keypairs = KeyGenerator::new().keypairs(16);
+        let unique: HashSet<_> = keypairs.iter().map(|(_, pk)| *pk).collect();
+        assert_eq!(unique.len(), 16);
+        for (sk, pk) in keypairs {
+            assert_eq!(sk.public(), pk);
+            assert_eq!(sk, SecretKey::new(sk.clone().into_bytes()));
+        }
+    }
+
+    #[test]
+    fn boxed_rng_can_be_injected() {
+        let rng: Box<dyn KeyRng> = Box::new(StdRng::seed_from_u64(1));
+        let mut boxed = KeyGenerator::with_rng(rng);
+        assert_eq!(boxed.public_keys(2), KeyGenerator::seeded(1).public_keys(2));
+    }
+}
@@ -20,8 +20,10 @@
 //! # }
 //! ```
 
+pub mod acceleration;
 pub mod chachabox;
 pub mod encryption;
+pub mod keygen;
 
 use std::{cmp::Ordering, convert::TryInto, fmt};
 
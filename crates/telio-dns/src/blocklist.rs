@@ -0,0 +1,290 @@
+//! Blocking of the queries for unwanted domains, e.g. the known malware or phishing ones, so
+//! threat protection can be done locally.
+//!
+//! The list holds one domain per line, lines starting with `#` are comments:
+//! ```text
+//! # Exact domain, its subdomains are not blocked
+//! ads.example.com
+//! # Every subdomain of tracker.example, but not tracker.example itself
+//! *.tracker.example
+//! ```
+//! Lines in the hosts file format, e.g. `0.0.0.0 ads.example.com tracker.example.com`, are
+//! accepted too, so the common lists can be used as they are. Every name after the address is
+//! blocked, except for the local names standard hosts files start with, e.g. `localhost`. Text
+//! after a `#` is a comment on any line. The list is loaded zlib compressed, as lists with
+//! hundreds of thousands of domains are common.
+
+use hickory_server::{
+    authority::MessageRequest,
+    proto::{
+        op::{Message, MessageType, ResponseCode},
+        rr::{rdata, RData, Record, RecordType},
+        serialize::binary::BinEncodable,
+    },
+};
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Largest list accepted once decompressed
+const MAX_BLOCKLIST_SIZE: usize = 64 * 1024 * 1024;
+/// TTL of the sinkhole answers, short so unblocked domains recover quickly on a list update
+const SINKHOLE_TTL: u32 = 60;
+/// Local names of the standard hosts files, never blocked
+const LOCAL_HOST_NAMES: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+];
+
+/// Size of the loaded list and the queries blocked by it
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct DnsBlocklistStats {
+    /// Exact domains on the list
+    pub exact_entries: u64,
+    /// Wildcard domains on the list
+    pub wildcard_entries: u64,
+    /// Queries answered as blocked since the list was loaded
+    pub blocked_queries: u64,
+}
+
+/// Domains whose queries are answered locally instead of being resolved
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    exact: HashSet<String>,
+    wildcards: HashSet<String>,
+    sinkhole_ips: Vec<IpAddr>,
+    blocked: AtomicU64,
+}
+
+impl Blocklist {
+    /// Parse the list from plain text.
+    ///
+    /// Blocked names get NXDOMAIN if `sinkhole_ips` is empty, otherwise they resolve to them.
+    pub fn parse(list: &str, sinkhole_ips: Vec<IpAddr>) -> Self {
+        let mut blocklist = Self {
+            sinkhole_ips,
+            ..Default::default()
+        };
+        for line in list.lines() {
+            let line = line.split_once('#').map_or(line, |(line, _)| line);
+            let mut names = line.split_whitespace().peekable();
+            // Hosts file format, the names follow the address
+            if names
+                .peek()
+                .is_some_and(|name| name.parse::<IpAddr>().is_ok())
+            {
+                names.next();
+            }
+            for domain in names {
+                blocklist.insert(domain);
+            }
+        }
+        blocklist
+    }
+
+    fn insert(&mut self, domain: &str) {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        if LOCAL_HOST_NAMES.contains(&domain.as_str()) {
+            return;
+        }
+        match domain.strip_prefix("*.") {
+            Some(parent) if !parent.is_empty() => {
+                self.wildcards.insert(parent.to_owned());
+            }
+            Some(_) => {}
+            None if !domain.is_empty() => {
+                self.exact.insert(domain);
+            }
+            None => {}
+        }
+    }
+
+    /// Parse the list from zlib compressed text, see [`Blocklist::parse`]
+    pub fn from_compressed(list: &[u8], sinkhole_ips: Vec<IpAddr>) -> Result<Self, String> {
+        let list = decompress_to_vec_zlib_with_limit(list, MAX_BLOCKLIST_SIZE)
+            .map_err(|e| format!("Failed to decompress the blocklist: {:?}", e.status))?;
+        let list = String::from_utf8(list)
+            .map_err(|_| String::from("Blocklist is not valid UTF-8 text"))?;
+        Ok(Self::parse(&list, sinkhole_ips))
+    }
+
+    /// Whether the queries for `name` are blocked, the name is expected in lowercase
+    pub fn matches(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.');
+        if self.exact.contains(name) {
+            return true;
+        }
+        name.match_indices('.').any(|(dot, _)| {
+            name.get(dot + 1..)
+                .is_some_and(|parent| self.wildcards.contains(parent))
+        })
+    }
+
+    /// Size of the list and the queries it blocked
+    pub fn stats(&self) -> DnsBlocklistStats {
+        DnsBlocklistStats {
+            exact_entries: self.exact.len() as u64,
+            wildcard_entries: self.wildcards.len() as u64,
+            blocked_queries: self.blocked.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Encoded response to the request, `None` if its name is not blocked
+    pub(crate) fn response(&self, request: &MessageRequest) -> Option<Vec<u8>> {
+        let query = request.queries().first()?;
+        if !self.matches(&query.name().to_string()) {
+            return None;
+        }
+        self.blocked.fetch_add(1, Ordering::Relaxed);
+
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .set_op_code(request.op_code())
+            .set_recursion_desired(request.recursion_desired())
+            .set_recursion_available(true)
+            .add_query(query.original().clone());
+        if self.sinkhole_ips.is_empty() {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+        // Other record types get an empty answer, the name exists but has no such records
+        for ip in &self.sinkhole_ips {
+            let data = match (ip, query.query_type()) {
+                (IpAddr::V4(ip), RecordType::A) => RData::A(rdata::A(*ip)),
+                (IpAddr::V6(ip), RecordType::AAAA) => RData::AAAA(rdata::AAAA(*ip)),
+                _ => continue,
+            };
+            response.add_answer(Record::from_rdata(
+                query.original().name().clone(),
+                SINKHOLE_TTL,
+                data,
+            ));
+        }
+        response.to_bytes().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::{op::Query, rr::Name, serialize::binary::BinDecodable};
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+    use std::{net::Ipv4Addr, str::FromStr};
+
+    const LIST: &str = "
+        # Threats
+        ads.example.com
+        0.0.0.0 Phishing.Example.\t
+        *.tracker.example
+        *.
+    ";
+
+    fn query(blocklist: &Blocklist, name: &str, query_type: RecordType) -> Option<Message> {
+        let mut question = Message::new();
+        question
+            .set_id(7)
+            .add_query(Query::query(Name::from_str(name).unwrap(), query_type));
+        let request = MessageRequest::from_bytes(&question.to_bytes().unwrap()).unwrap();
+        blocklist
+            .response(&request)
+            .map(|response| Message::from_bytes(&response).unwrap())
+    }
+
+    #[test]
+    fn exact_and_wildcard_domains_are_matched() {
+        let blocklist = Blocklist::parse(LIST, Vec::new());
+
+        assert!(blocklist.matches("ads.example.com"));
+        assert!(blocklist.matches("phishing.example."));
+        assert!(blocklist.matches("a.tracker.example"));
+        assert!(blocklist.matches("a.b.tracker.example"));
+        assert!(!blocklist.matches("sub.ads.example.com"));
+        assert!(!blocklist.matches("example.com"));
+        assert!(!blocklist.matches("tracker.example"));
+        assert!(!blocklist.matches("nottracker.example"));
+        assert_eq!(
+            blocklist.stats(),
+            DnsBlocklistStats {
+                exact_entries: 2,
+                wildcard_entries: 1,
+                blocked_queries: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn hosts_file_lines_block_every_name_but_the_local_ones() {
+        let hosts = "
+            127.0.0.1 localhost
+            255.255.255.255 broadcasthost
+            ::1 localhost ip6-localhost ip6-loopback
+            0.0.0.0 ads.example.com tracker.example.com # both of them
+            ads.example.net  # inline comment
+        ";
+        let blocklist = Blocklist::parse(hosts, Vec::new());
+
+        assert!(blocklist.matches("ads.example.com"));
+        assert!(blocklist.matches("tracker.example.com"));
+        assert!(blocklist.matches("ads.example.net"));
+        assert!(!blocklist.matches("localhost"));
+        assert!(!blocklist.matches("broadcasthost"));
+        assert!(!blocklist.matches("comment"));
+        assert_eq!(blocklist.stats().exact_entries, 3);
+    }
+
+    #[test]
+    fn compressed_list_is_loaded() {
+        let compressed = compress_to_vec_zlib(LIST.as_bytes(), 6);
+        let blocklist = Blocklist::from_compressed(&compressed, Vec::new()).unwrap();
+        assert!(blocklist.matches("x.tracker.example"));
+
+        assert!(Blocklist::from_compressed(LIST.as_bytes(), Vec::new()).is_err());
+        let not_text = compress_to_vec_zlib(&[0xff, 0xfe], 6);
+        assert!(Blocklist::from_compressed(&not_text, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn blocked_queries_get_nxdomain_and_are_counted() {
+        let blocklist = Blocklist::parse(LIST, Vec::new());
+
+        assert_eq!(query(&blocklist, "example.com.", RecordType::A), None);
+        let response = query(&blocklist, "ADS.example.com.", RecordType::A).unwrap();
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+        assert_eq!(blocklist.stats().blocked_queries, 1);
+    }
+
+    #[test]
+    fn blocked_queries_resolve_to_sinkhole() {
+        let sinkhole = Ipv4Addr::new(10, 0, 0, 53);
+        let blocklist = Blocklist::parse(LIST, vec![sinkhole.into()]);
+
+        let response = query(&blocklist, "a.tracker.example.", RecordType::A).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(rdata::A(sinkhole)))
+        );
+
+        let response = query(&blocklist, "a.tracker.example.", RecordType::AAAA).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        assert_eq!(blocklist.stats().blocked_queries, 2);
+    }
+}
@@ -0,0 +1,282 @@
+//! Discovery of the relay and STUN servers through DNS.
+//!
+//! The servers of a deployment are published as SRV records `_telio-relay._tcp.<domain>`,
+//! pointing to the host and the relay port of each server. The host itself carries a TXT
+//! record with the public key and the STUN port of the server, `"pk=<base64 key>" "stun=<port>"`.
+//! The list is refreshed periodically and every change is published to the subscriber.
+//!
+//! The answers are not authenticated, so only the servers with a pinned public key are
+//! accepted: a spoofed answer can at most point to an address where the relay handshake
+//! with the pinned key fails. The lookups go through protected sockets, as they are done
+//! before the relay servers, and so the tunnel, are known.
+
+use std::{net::Ipv4Addr, str::FromStr, sync::Arc, time::Duration};
+
+use hickory_server::{
+    proto::rr::Name,
+    resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        name_server::GenericConnector,
+    },
+};
+use telio_crypto::PublicKey;
+use telio_model::{config::Server, features::FeatureServerBootstrap};
+use telio_sockets::SocketPool;
+use telio_utils::{
+    exponential_backoff::{Backoff, ExponentialBackoff, ExponentialBackoffBounds},
+    telio_log_debug, telio_log_info, telio_log_warn,
+};
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
+
+use crate::protected::{ProtectedAsyncResolver, ProtectedRuntimeProvider};
+
+/// Label of the SRV records listing the servers
+const SRV_LABEL: &str = "_telio-relay._tcp";
+/// STUN port of the servers which do not publish one
+const DEFAULT_STUN_PORT: u16 = 3478;
+/// First retry after a failed lookup, later ones back off up to the refresh interval
+const INITIAL_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Periodically discovers the relay and STUN servers of a domain
+pub struct ServerBootstrap {
+    servers: watch::Receiver<Vec<Server>>,
+    task_handle: JoinHandle<()>,
+}
+
+impl ServerBootstrap {
+    /// Start discovering the servers in the background, with the lookup sockets created
+    /// through `socket_pool`
+    pub fn start(
+        config: &FeatureServerBootstrap,
+        socket_pool: Arc<SocketPool>,
+    ) -> Result<Self, String> {
+        let srv_name = Name::from_str(&format!("{SRV_LABEL}.{}", config.domain))
+            .map_err(|e| format!("Invalid bootstrap domain {}: {e}", config.domain))?;
+        if config.resolvers.is_empty() {
+            return Err("No resolvers to bootstrap the servers with".to_owned());
+        }
+        if config.pinned_keys.is_empty() {
+            return Err("No pinned keys of the servers to bootstrap".to_owned());
+        }
+        let refresh_interval = Duration::from_secs(config.refresh_interval_s.max(1));
+        let pinned_keys = config.pinned_keys.clone();
+
+        let resolver = ProtectedAsyncResolver::new(
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                NameServerConfigGroup::from_ips_clear(&config.resolvers, 53, true),
+            ),
+            ResolverOpts::default(),
+            GenericConnector::new(ProtectedRuntimeProvider::new(socket_pool)),
+        );
+
+        let (tx, servers) = watch::channel(Vec::new());
+        let task_handle = tokio::spawn(async move {
+            #[allow(clippy::expect_used)]
+            let mut retry_backoff = ExponentialBackoff::new(ExponentialBackoffBounds {
+                initial: INITIAL_RETRY_INTERVAL.min(refresh_interval),
+                maximal: Some(refresh_interval),
+            })
+            .expect("Retry backoff bounds are valid");
+
+            loop {
+                let next_lookup = match discover(&resolver, &srv_name, &pinned_keys).await {
+                    Ok(discovered) if !discovered.is_empty() => {
+                        retry_backoff.reset();
+                        tx.send_if_modified(|servers| {
+                            if *servers == discovered {
+                                return false;
+                            }
+                            telio_log_info!("Bootstrapped {} servers", discovered.len());
+                            *servers = discovered;
+                            true
+                        });
+                        refresh_interval
+                    }
+                    Ok(_) => {
+                        telio_log_warn!("No servers found at {}", srv_name);
+                        next_retry(&mut retry_backoff)
+                    }
+                    Err(err) => {
+                        telio_log_warn!("Failed to bootstrap servers: {}", err);
+                        next_retry(&mut retry_backoff)
+                    }
+                };
+                sleep(next_lookup).await;
+            }
+        });
+
+        Ok(Self {
+            servers,
+            task_handle,
+        })
+    }
+
+    /// Wait for the next change of the server list
+    ///
+    /// Returns `None` once the discovery has stopped.
+    pub async fn changed(&mut self) -> Option<Vec<Server>> {
+        self.servers.changed().await.ok()?;
+        Some(self.servers.borrow_and_update().clone())
+    }
+
+    /// Servers discovered so far
+    pub fn servers(&self) -> Vec<Server> {
+        self.servers.borrow().clone()
+    }
+}
+
+impl Drop for ServerBootstrap {
+    fn drop(&mut self) {
+        self.task_handle.abort();
+    }
+}
+
+fn next_retry(backoff: &mut ExponentialBackoff) -> Duration {
+    let retry = backoff.get_backoff();
+    backoff.next_backoff();
+    retry
+}
+
+async fn discover(
+    resolver: &ProtectedAsyncResolver,
+    srv_name: &Name,
+    pinned_keys: &[PublicKey],
+) -> Result<Vec<Server>, String> {
+    let records = resolver
+        .srv_lookup(srv_name.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut servers = Vec::new();
+    for srv in records.iter() {
+        let target = srv.target();
+        let ipv4 = match resolver.ipv4_lookup(target.clone()).await {
+            Ok(lookup) => lookup.iter().next().map(|a| a.0),
+            Err(e) => {
+                telio_log_debug!("No address of {}: {}", target, e);
+                None
+            }
+        };
+        let txt = match resolver.txt_lookup(target.clone()).await {
+            Ok(lookup) => lookup
+                .iter()
+                .flat_map(|txt| txt.txt_data().iter())
+                .map(|data| String::from_utf8_lossy(data).into_owned())
+                .collect(),
+            Err(e) => {
+                telio_log_debug!("No TXT record of {}: {}", target, e);
+                Vec::new()
+            }
+        };
+
+        match server_from_records(&target.to_utf8(), srv.port(), srv.priority(), ipv4, &txt) {
+            Some(server) if pinned_keys.contains(&server.public_key) => servers.push(server),
+            Some(server) => telio_log_warn!(
+                "Ignoring server {} with a key which is not pinned: {:?}",
+                target,
+                server.public_key
+            ),
+            None => telio_log_warn!("Ignoring incomplete records of server {}", target),
+        }
+    }
+
+    Ok(servers)
+}
+
+/// Build a server out of its SRV, A and TXT records
+fn server_from_records(
+    target: &str,
+    relay_port: u16,
+    priority: u16,
+    ipv4: Option<Ipv4Addr>,
+    txt: &[String],
+) -> Option<Server> {
+    let mut public_key = None;
+    let mut stun_port = DEFAULT_STUN_PORT;
+    for entry in txt {
+        match entry.split_once('=') {
+            Some(("pk", key)) => public_key = PublicKey::from_str(key.trim()).ok(),
+            Some(("stun", port)) => stun_port = port.trim().parse().ok()?,
+            _ => (),
+        }
+    }
+
+    let hostname = target.trim_end_matches('.').to_owned();
+    Some(Server {
+        name: hostname.split('.').next().unwrap_or_default().to_owned(),
+        hostname,
+        ipv4: ipv4?,
+        relay_port,
+        stun_port,
+        public_key: public_key?,
+        weight: priority.into(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+
+    fn txt(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn server_is_built_from_records() {
+        let public_key = SecretKey::gen().public();
+        let server = server_from_records(
+            "de1.relays.example.com.",
+            8765,
+            2,
+            Some(Ipv4Addr::new(192, 0, 2, 1)),
+            &txt(&[&format!("pk={public_key}"), "stun=3479", "comment=ignored"]),
+        )
+        .unwrap();
+
+        assert_eq!(server.hostname, "de1.relays.example.com");
+        assert_eq!(server.name, "de1");
+        assert_eq!(server.ipv4, Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(server.relay_port, 8765);
+        assert_eq!(server.stun_port, 3479);
+        assert_eq!(server.public_key, public_key);
+        assert_eq!(server.weight, 2);
+    }
+
+    #[test]
+    fn stun_port_defaults() {
+        let public_key = SecretKey::gen().public();
+        let server = server_from_records(
+            "relay.example.com.",
+            443,
+            1,
+            Some(Ipv4Addr::new(192, 0, 2, 1)),
+            &txt(&[&format!("pk={public_key}")]),
+        )
+        .unwrap();
+        assert_eq!(server.stun_port, DEFAULT_STUN_PORT);
+    }
+
+    #[test]
+    fn incomplete_records_are_rejected() {
+        let public_key = SecretKey::gen().public();
+        let ipv4 = Some(Ipv4Addr::new(192, 0, 2, 1));
+        let pk = format!("pk={public_key}");
+
+        let build = |ipv4, entries: &[&str]| {
+            server_from_records("relay.example.com.", 443, 1, ipv4, &txt(entries))
+        };
+
+        // No public key
+        assert!(build(ipv4, &["stun=3478"]).is_none());
+        // Invalid public key
+        assert!(build(ipv4, &["pk=abc"]).is_none());
+        // Invalid STUN port
+        assert!(build(ipv4, &[&pk, "stun=x"]).is_none());
+        // No address
+        assert!(build(None, &[&pk]).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{bind_tun, LocalNameServer, NameServer, Records};
+use crate::{bind_tun, Blocklist, DnsFailure, LocalNameServer, NameServer, Records};
 use async_trait::async_trait;
 use ipnet::IpNet;
 use neptun::noise::Tunn;
@@ -10,7 +10,9 @@ use tokio::net::UdpSocket;
 use tokio::sync::{Mutex, RwLock};
 use x25519_dalek::{PublicKey as PublicKeyDalek, StaticSecret};
 
-use telio_model::features::{FeatureExitDns, TtlValue};
+use telio_model::features::{FeatureDns, TtlValue};
+use telio_sockets::SocketPool;
+use tokio::sync::mpsc::Sender;
 
 //debug tools
 use telio_utils::{telio_log_debug, telio_log_error};
@@ -44,6 +46,12 @@ pub trait DnsResolver {
     fn get_default_dns_servers(&self) -> Vec<IpAddr>;
     /// Change DNS peer's public key
     async fn set_peer_public_key(&self, key: PublicKey);
+    /// Get the number of bytes allocated for the records of the local zones
+    async fn memory_usage(&self) -> usize;
+    /// Export the records of a local zone as zone file text, `None` if the zone is not known
+    async fn export_zone(&self, zone: &str) -> Option<String>;
+    /// Configure the domains whose queries are answered as blocked, `None` blocks nothing
+    async fn set_blocklist(&self, blocklist: Option<Arc<Blocklist>>);
 }
 
 /// Dns resolver server that can be run in process.
@@ -61,12 +69,17 @@ pub struct LocalDnsResolver {
 
 impl LocalDnsResolver {
     /// Creates new instance of `LocalDnsResolver`.
+    ///
+    /// Failures of the upstream resolvers are published to `failures`, if given. Forward rules
+    /// bypassing the tunnel reach their resolvers over sockets of `socket_pool`.
     pub async fn new(
         public_key: &PublicKey,
         port: u16,
         forward_ips: &[IpAddr],
         tun: Option<i32>,
-        exit_dns: Option<FeatureExitDns>,
+        features: &FeatureDns,
+        failures: Option<Sender<DnsFailure>>,
+        socket_pool: Option<Arc<SocketPool>>,
     ) -> Result<Self, String> {
         let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
             .await
@@ -96,10 +109,19 @@ impl LocalDnsResolver {
         // Telio public key
         let telio_public_key: PublicKeyDalek = PublicKeyDalek::from(public_key.0);
 
-        let nameserver = LocalNameServer::new(forward_ips).await?;
+        let nameserver = LocalNameServer::new(forward_ips, failures).await?;
+        nameserver.set_soa(features.soa.clone()).await;
+        nameserver.set_socket_pool(socket_pool).await;
+        for rule in &features.forward_rules {
+            nameserver
+                .forward_domain(&rule.domain, &rule.resolvers, rule.bypass_tunnel)
+                .await?;
+        }
 
-        let auto_switch_ips =
-            exit_dns.map_or(false, |feature| feature.auto_switch_dns_ips.unwrap_or(true));
+        let auto_switch_ips = features
+            .exit_dns
+            .as_ref()
+            .map_or(false, |feature| feature.auto_switch_dns_ips.unwrap_or(true));
 
         Ok(LocalDnsResolver {
             socket: Arc::new(socket),
@@ -207,6 +229,18 @@ impl DnsResolver for LocalDnsResolver {
 
         *self.peer.lock().await = peer;
     }
+
+    async fn memory_usage(&self) -> usize {
+        self.nameserver.read().await.memory_usage()
+    }
+
+    async fn export_zone(&self, zone: &str) -> Option<String> {
+        self.nameserver.read().await.export_zone(zone)
+    }
+
+    async fn set_blocklist(&self, blocklist: Option<Arc<Blocklist>>) {
+        self.nameserver.set_blocklist(blocklist).await;
+    }
 }
 
 #[cfg(test)]
@@ -216,9 +250,17 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_default_dns_allowed_ips() {
-        let resolver = LocalDnsResolver::new(&SecretKey::gen().public(), 42, &[], None, None)
-            .await
-            .unwrap();
+        let resolver = LocalDnsResolver::new(
+            &SecretKey::gen().public(),
+            42,
+            &[],
+            None,
+            &FeatureDns::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
         assert_eq!(
             vec![
                 "100.64.0.2/32".parse::<IpNet>().unwrap(),
@@ -232,9 +274,17 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_exit_connected_dns_allowed_ips() {
-        let resolver = LocalDnsResolver::new(&SecretKey::gen().public(), 42, &[], None, None)
-            .await
-            .unwrap();
+        let resolver = LocalDnsResolver::new(
+            &SecretKey::gen().public(),
+            42,
+            &[],
+            None,
+            &FeatureDns::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
         assert_eq!(
             vec![
                 "100.64.0.2/32".parse::<IpNet>().unwrap(),
@@ -246,9 +296,17 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_default_dns_servers() {
-        let resolver = LocalDnsResolver::new(&SecretKey::gen().public(), 42, &[], None, None)
-            .await
-            .unwrap();
+        let resolver = LocalDnsResolver::new(
+            &SecretKey::gen().public(),
+            42,
+            &[],
+            None,
+            &FeatureDns::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
         assert_eq!(
             vec![
                 "100.64.0.3".parse::<IpAddr>().unwrap(),
@@ -4,8 +4,9 @@
 use std::{
     future::Future,
     io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
+    sync::Arc,
 };
 
 use async_trait::async_trait;
@@ -14,13 +15,14 @@ use hickory_server::{
         Authority, LookupError, LookupObject, LookupOptions, MessageRequest, UpdateResult, ZoneType,
     },
     proto::{
+        error::ProtoErrorKind,
         op::ResponseCode,
         rr::{LowerName, Name, Record, RecordType},
         udp::{DnsUdpSocket, UdpSocket as ProtoUdpSocket},
     },
     resolver::{
-        config::ResolverConfig,
-        error::ResolveErrorKind,
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        error::{ResolveError, ResolveErrorKind},
         lookup::Lookup as ResolverLookup,
         name_server::{GenericConnector, RuntimeProvider, TokioRuntimeProvider},
         AsyncResolver,
@@ -28,10 +30,16 @@ use hickory_server::{
     server::RequestInfo,
     store::forwarder::ForwardConfig,
 };
-use telio_utils::{telio_log_debug, telio_log_info, telio_log_trace, telio_log_warn};
-use tokio::net::UdpSocket;
-
-use crate::bind_tun;
+use parking_lot::Mutex;
+use telio_sockets::SocketPool;
+use telio_utils::{chaos, telio_log_debug, telio_log_info, telio_log_trace, telio_log_warn};
+use tokio::{net::UdpSocket, sync::mpsc::Sender, time::Instant};
+
+use crate::{
+    bind_tun,
+    protected::{ProtectedAsyncResolver, ProtectedRuntimeProvider},
+    upstream::{QueryOutcome, UpstreamsHealth},
+};
 
 #[derive(Default, Clone)]
 pub struct TelioRuntimeProvider(TokioRuntimeProvider);
@@ -68,6 +76,24 @@ impl RuntimeProvider for TelioRuntimeProvider {
 
 pub type TelioAsyncResolver = AsyncResolver<GenericConnector<TelioRuntimeProvider>>;
 
+/// Resolver of a single upstream
+#[derive(Clone)]
+enum ForwardResolver {
+    /// Sockets bound to the tunnel, reaching the upstream through the exit node if connected
+    Tunnel(TelioAsyncResolver),
+    /// Sockets made external by the protector, reaching the upstream outside the tunnel
+    Protected(ProtectedAsyncResolver),
+}
+
+impl ForwardResolver {
+    async fn lookup(&self, name: Name, rtype: RecordType) -> Result<ResolverLookup, ResolveError> {
+        match self {
+            Self::Tunnel(resolver) => resolver.lookup(name, rtype).await,
+            Self::Protected(resolver) => resolver.lookup(name, rtype).await,
+        }
+    }
+}
+
 pub struct TelioUdpSocket(UdpSocket);
 
 #[async_trait]
@@ -127,20 +153,55 @@ impl ProtoUdpSocket for TelioUdpSocket {
     }
 }
 
+/// Reason why an upstream resolver failed to answer a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsFailureKind {
+    /// Upstream answered with SERVFAIL
+    ServFail,
+    /// Upstream refused the query
+    Refused,
+    /// No answer arrived in time
+    Timeout,
+    /// Upstream could not be reached at all
+    Unreachable,
+    /// Answer could not be parsed
+    Malformed,
+    /// Any other resolver error
+    Other,
+}
+
+/// Failed query of an upstream resolver, published for the analytics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsFailure {
+    /// Upstream resolver which failed
+    pub upstream: IpAddr,
+    /// How it failed
+    pub kind: DnsFailureKind,
+}
+
 /// An authority that will forward resolutions to upstream resolvers.
 ///
-/// This uses the trust-dns-resolver for resolving requests.
+/// This uses the trust-dns-resolver for resolving requests. Every upstream has its own
+/// resolver, so that queries can be routed to the healthiest one instead of relying on
+/// the fixed order of the configured name servers.
 pub struct ForwardAuthority {
     origin: LowerName,
-    resolver: TelioAsyncResolver,
+    resolvers: Vec<ForwardResolver>,
+    health: Arc<Mutex<UpstreamsHealth>>,
+    failures: Option<Sender<DnsFailure>>,
 }
 
 impl ForwardAuthority {
     /// Read the Authority for the origin from the specified configuration
+    ///
+    /// With `external` the sockets of the upstreams are created through the socket pool, which
+    /// keeps them out of the tunnel even while connected to an exit node.
     pub async fn try_from_config(
         origin: Name,
         _zone_type: ZoneType,
         config: ForwardConfig,
+        external: Option<Arc<SocketPool>>,
+        failures: Option<Sender<DnsFailure>>,
     ) -> Result<Self, String> {
         telio_log_info!("loading forwarder config: {}", origin);
 
@@ -166,18 +227,154 @@ impl ForwardAuthority {
             options.preserve_intermediates = true;
         }
 
-        let config = ResolverConfig::from_parts(None, vec![], name_servers);
+        // Group name servers by upstream, each upstream may have both UDP and TCP configs
+        let mut upstreams: Vec<(IpAddr, NameServerConfigGroup)> = Vec::new();
+        for name_server in name_servers.iter() {
+            let ip = name_server.socket_addr.ip();
+            match upstreams.iter_mut().find(|(upstream, _)| *upstream == ip) {
+                Some((_, group)) => group.push(name_server.clone()),
+                None => {
+                    let mut group = NameServerConfigGroup::new();
+                    group.push(name_server.clone());
+                    upstreams.push((ip, group));
+                }
+            }
+        }
 
-        let resolver = TelioAsyncResolver::new(config, options, GenericConnector::default());
+        let health = UpstreamsHealth::new(upstreams.iter().map(|(ip, _)| *ip));
+        let resolvers = upstreams
+            .into_iter()
+            .map(|(_, group)| Self::build_resolver(group, options.clone(), external.clone()))
+            .collect();
 
         telio_log_info!("forward resolver configured: {}: ", origin);
 
         // TODO: this might be infallible?
         Ok(Self {
             origin: origin.into(),
-            resolver,
+            resolvers,
+            health: Arc::new(Mutex::new(health)),
+            failures,
         })
     }
+
+    fn build_resolver(
+        name_servers: NameServerConfigGroup,
+        options: ResolverOpts,
+        external: Option<Arc<SocketPool>>,
+    ) -> ForwardResolver {
+        let config = ResolverConfig::from_parts(None, vec![], name_servers);
+        match external {
+            Some(socket_pool) => ForwardResolver::Protected(ProtectedAsyncResolver::new(
+                config,
+                options,
+                GenericConnector::new(ProtectedRuntimeProvider::new(socket_pool)),
+            )),
+            None => ForwardResolver::Tunnel(TelioAsyncResolver::new(
+                config,
+                options,
+                GenericConnector::default(),
+            )),
+        }
+    }
+
+    /// Send the query to the upstreams in order of their health, falling over to the next
+    /// one on timeouts and SERVFAILs
+    async fn resolve(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+    ) -> Option<Result<ResolverLookup, ResolveError>> {
+        let plan = self.health.lock().plan(Instant::now());
+
+        for idx in plan.probes {
+            self.probe(idx);
+        }
+
+        let mut resolve = None;
+        for idx in plan.candidates {
+            let Some(resolver) = self.resolvers.get(idx) else {
+                continue;
+            };
+            let failing = self
+                .health
+                .lock()
+                .ip(idx)
+                .is_some_and(|ip| chaos::is_dns_upstream_failing(&ip));
+            let result = if failing {
+                Err(ResolveErrorKind::Timeout.into())
+            } else {
+                resolver.lookup(name.clone().into(), rtype).await
+            };
+            let outcome = QueryOutcome::from_result(&result);
+            self.health.lock().record(idx, outcome, Instant::now());
+            self.report_failure(idx, &result);
+            resolve = Some(result);
+            if outcome == QueryOutcome::Answered {
+                break;
+            }
+            telio_log_debug!(
+                "Upstream #{} failed with {:?}, trying the next one",
+                idx,
+                outcome
+            );
+        }
+        resolve
+    }
+
+    /// Publish the failed query for the analytics, probes are not reported
+    fn report_failure<T>(&self, idx: usize, result: &Result<T, ResolveError>) {
+        let Some(failures) = &self.failures else {
+            return;
+        };
+        let upstream = self.health.lock().ip(idx);
+        if let (Some(upstream), Some(kind)) = (upstream, dns_failure_kind(result)) {
+            // Queries must not wait for the analytics, failures are dropped when they lag behind
+            let _ = failures.try_send(DnsFailure { upstream, kind });
+        }
+    }
+
+    /// Check in the background whether an unhealthy upstream has recovered
+    fn probe(&self, idx: usize) {
+        let Some(resolver) = self.resolvers.get(idx).cloned() else {
+            return;
+        };
+        let health = self.health.clone();
+        tokio::spawn(async move {
+            let result = resolver.lookup(Name::root(), RecordType::NS).await;
+            let outcome = QueryOutcome::from_result(&result);
+            let mut health = health.lock();
+            telio_log_debug!(
+                "Probe of upstream DNS {:?} finished with {:?}",
+                health.ip(idx),
+                outcome
+            );
+            health.record(idx, outcome, Instant::now());
+        });
+    }
+}
+
+/// Classify a failed lookup for the analytics, negative answers like NXDOMAIN are not failures
+fn dns_failure_kind<T>(result: &Result<T, ResolveError>) -> Option<DnsFailureKind> {
+    let Err(e) = result else {
+        return None;
+    };
+    let kind = match e.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => match *response_code {
+            ResponseCode::ServFail => DnsFailureKind::ServFail,
+            ResponseCode::Refused => DnsFailureKind::Refused,
+            _ => return None,
+        },
+        ResolveErrorKind::Timeout => DnsFailureKind::Timeout,
+        ResolveErrorKind::NoConnections | ResolveErrorKind::Io(_) => DnsFailureKind::Unreachable,
+        ResolveErrorKind::Proto(e) => match e.kind() {
+            ProtoErrorKind::Timeout => DnsFailureKind::Timeout,
+            ProtoErrorKind::Io(_) | ProtoErrorKind::NoConnections => DnsFailureKind::Unreachable,
+            _ => DnsFailureKind::Malformed,
+        },
+        _ => DnsFailureKind::Other,
+    };
+    Some(kind)
 }
 
 #[async_trait::async_trait]
@@ -218,7 +415,14 @@ impl Authority for ForwardAuthority {
         debug_assert!(self.origin.zone_of(name));
 
         telio_log_debug!("forwarding lookup: {} {}", name, rtype);
-        let resolve = self.resolver.lookup(name.clone(), rtype).await;
+        let resolve = match self.resolve(name, rtype).await {
+            Some(resolve) => resolve,
+            None => {
+                telio_log_warn!("No upstream DNS servers configured for {}", self.origin);
+                // Nothing can answer the query, so the client must not wait for it
+                return Err(LookupError::from(ResponseCode::ServFail));
+            }
+        };
 
         // Log DNS failures
         match resolve {
@@ -309,3 +513,106 @@ impl LookupObject for ForwardLookup {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_server::proto::{error::ProtoError, op::Query};
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use telio_sockets::protector::make_external_protector;
+
+    fn kind(error: ResolveError) -> Option<DnsFailureKind> {
+        dns_failure_kind::<()>(&Err(error))
+    }
+
+    fn no_records(response_code: ResponseCode) -> ResolveError {
+        ResolveErrorKind::NoRecordsFound {
+            query: Box::new(Query::query(Name::root(), RecordType::A)),
+            soa: None,
+            negative_ttl: None,
+            response_code,
+            trusted: true,
+        }
+        .into()
+    }
+
+    #[test]
+    fn failures_are_classified_by_error_kind() {
+        assert_eq!(dns_failure_kind(&Ok(())), None);
+        assert_eq!(kind(no_records(ResponseCode::NXDomain)), None);
+        assert_eq!(
+            kind(no_records(ResponseCode::ServFail)),
+            Some(DnsFailureKind::ServFail)
+        );
+        assert_eq!(
+            kind(no_records(ResponseCode::Refused)),
+            Some(DnsFailureKind::Refused)
+        );
+        assert_eq!(
+            kind(ResolveErrorKind::Timeout.into()),
+            Some(DnsFailureKind::Timeout)
+        );
+        assert_eq!(
+            kind(ProtoError::from(ProtoErrorKind::Timeout).into()),
+            Some(DnsFailureKind::Timeout)
+        );
+        assert_eq!(
+            kind(ResolveErrorKind::NoConnections.into()),
+            Some(DnsFailureKind::Unreachable)
+        );
+        assert_eq!(
+            kind(io::Error::from(io::ErrorKind::ConnectionRefused).into()),
+            Some(DnsFailureKind::Unreachable)
+        );
+        assert_eq!(
+            kind(ProtoError::from(ProtoErrorKind::Message("bad label")).into()),
+            Some(DnsFailureKind::Malformed)
+        );
+        assert_eq!(
+            kind(ResolveErrorKind::Message("no resolvers").into()),
+            Some(DnsFailureKind::Other)
+        );
+    }
+
+    #[tokio::test]
+    async fn upstreams_bypassing_tunnel_are_queried_over_protected_sockets() {
+        let protected = Arc::new(AtomicUsize::new(0));
+        let socket_pool = Arc::new(SocketPool::new(make_external_protector(Arc::new({
+            let protected = protected.clone();
+            move |_| {
+                protected.fetch_add(1, Ordering::Relaxed);
+            }
+        }))));
+
+        // Upstream which never answers
+        let upstream = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let mut options = ResolverOpts::default();
+        options.timeout = Duration::from_millis(100);
+        options.attempts = 0;
+        let config = ForwardConfig {
+            options: Some(options),
+            name_servers: NameServerConfigGroup::from_ips_clear(
+                &[Ipv4Addr::LOCALHOST.into()],
+                upstream.local_addr().unwrap().port(),
+                true,
+            ),
+        };
+        let authority = ForwardAuthority::try_from_config(
+            Name::root(),
+            ZoneType::Forward,
+            config,
+            Some(socket_pool),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let name = LowerName::from(Name::from_ascii("example.com.").unwrap());
+        let result = authority.resolve(&name, RecordType::A).await;
+        assert!(matches!(result, Some(Err(_))));
+        assert!(protected.load(Ordering::Relaxed) > 0);
+    }
+}
@@ -3,19 +3,25 @@
 
 //! Easily create and run in process dns resolver.
 
+mod blocklist;
 mod dns;
 mod nameserver;
+mod protected;
 mod resolver;
+mod upstream;
 mod zone;
 
 pub mod bind_tun;
+pub mod bootstrap;
 
 pub(crate) mod forward;
 
 pub use crate::dns::{DnsResolver, LocalDnsResolver};
+pub use blocklist::{Blocklist, DnsBlocklistStats};
+pub use forward::{DnsFailure, DnsFailureKind};
 pub use nameserver::{LocalNameServer, NameServer};
 pub use resolver::Resolver;
-pub use zone::Records;
+pub use zone::{Records, ZoneVersions, REVERSE_ZONE_IPV4, REVERSE_ZONE_IPV6};
 
 #[cfg(feature = "mockall")]
 pub use crate::dns::MockDnsResolver;
@@ -1,6 +1,8 @@
 use crate::{
+    blocklist::Blocklist,
+    forward::DnsFailure,
     resolver::Resolver,
-    zone::{AuthoritativeZone, ClonableZones, ForwardZone, Records},
+    zone::{soa_or_default, AuthoritativeZone, ClonableZones, ForwardZone, Records, ZoneVersions},
 };
 use async_trait::async_trait;
 use hickory_server::{
@@ -22,8 +24,9 @@ use std::{
     str::FromStr,
     sync::Arc,
 };
-use telio_model::features::TtlValue;
-use tokio::sync::{RwLock, RwLockMappedWriteGuard, RwLockWriteGuard, Semaphore};
+use telio_model::features::{FeatureDnsSoa, TtlValue};
+use telio_sockets::SocketPool;
+use tokio::sync::{mpsc::Sender, RwLock, RwLockMappedWriteGuard, RwLockWriteGuard, Semaphore};
 use tokio::task::JoinHandle;
 use tokio::{net::UdpSocket, sync::Mutex};
 
@@ -31,10 +34,20 @@ use telio_utils::{telio_log_debug, telio_log_error, telio_log_trace, telio_log_w
 
 const IPV4_HEADER: usize = 20; // bytes
 const IPV6_HEADER: usize = 40; // bytes
-const MAX_PACKET: usize = 2048;
+/// Largest DNS response served over UDP, as advertised by the most common EDNS0 clients
+const MAX_DNS_PAYLOAD: u16 = 4096;
+/// Leaves room for the IP, UDP and WireGuard headers around the largest DNS response
+const MAX_PACKET: usize = MAX_DNS_PAYLOAD as usize + 256;
+/// Size of a DNS message over UDP without EDNS0, see RFC 1035
+const MIN_DNS_PAYLOAD: u16 = 512;
 const UDP_HEADER: usize = 8;
 const TCP_MIN_HEADER: usize = 20;
+/// Largest TCP segments of the responses, the default MSS as the SYN-ACK carries no options
+const TCP_MSS_IPV4: usize = 536;
+const TCP_MSS_IPV6: usize = 1220;
 const MAX_CONCURRENT_QUERIES: usize = 256;
+/// Zone of the meshnet nodes, always answered locally
+const LOCAL_ZONE: &str = "nord";
 
 /// NameServer is a server that stores the DNS records.
 #[async_trait]
@@ -48,13 +61,34 @@ pub trait NameServer {
     async fn stop(&self);
     /// Configure list of forward DNS servers for zone '.'.
     async fn forward(&self, to: &[IpAddr]) -> Result<(), String>;
+    /// Configure list of DNS servers the queries for `domain` and its subdomains are forwarded to.
+    ///
+    /// Takes priority over zone '.', but can not override the local `nord` zone. With
+    /// `bypass_tunnel` the DNS servers are reached over sockets made external by the socket pool,
+    /// so not through the exit node.
+    async fn forward_domain(
+        &self,
+        domain: &str,
+        to: &[IpAddr],
+        bypass_tunnel: bool,
+    ) -> Result<(), String>;
     /// Insert or update zone records used by the server.
+    ///
+    /// Serial of the zone is bumped whenever the records or the TTL change.
     async fn upsert(
         &self,
         zone: &str,
         records: &Records,
         ttl_value: TtlValue,
     ) -> Result<(), String>;
+    /// Configure SOA of the zones upserted from now on, `None` derives it from their TTL.
+    async fn set_soa(&self, soa: Option<FeatureDnsSoa>);
+    /// Configure the domains whose queries are answered as blocked, `None` blocks nothing.
+    ///
+    /// Names in the local zones are never blocked.
+    async fn set_blocklist(&self, blocklist: Option<Arc<Blocklist>>);
+    /// Configure the socket pool creating the sockets of the forward rules bypassing the tunnel.
+    async fn set_socket_pool(&self, socket_pool: Option<Arc<SocketPool>>);
 }
 
 /// Local name server.
@@ -62,15 +96,57 @@ pub trait NameServer {
 pub struct LocalNameServer {
     zones: Arc<ClonableZones>,
     task_handle: Option<JoinHandle<()>>,
+    soa: Option<FeatureDnsSoa>,
+    versions: ZoneVersions,
+    /// Failures of the forward zones are published here
+    failures: Option<Sender<DnsFailure>>,
+    blocklist: Option<Arc<Blocklist>>,
+    /// Creates the sockets of the forward rules bypassing the tunnel
+    socket_pool: Option<Arc<SocketPool>>,
+}
+
+impl LocalNameServer {
+    /// Number of bytes allocated for the records of the local zones
+    pub fn memory_usage(&self) -> usize {
+        self.versions.memory_usage()
+    }
+
+    /// Contents of a local zone, or of one of the reverse zones of their addresses, as zone file
+    /// text, `None` if the zone was never upserted
+    pub fn export_zone(&self, zone: &str) -> Option<String> {
+        self.versions.export(zone, self.soa.as_ref())
+    }
+
+    /// Response to the request if it is blocked, the local zones are always resolved
+    fn blocked_response(&self, request: &MessageRequest) -> Option<Vec<u8>> {
+        let blocklist = self.blocklist.as_ref()?;
+        let name = request.queries().first()?.name();
+        let is_local = LowerName::from_str(LOCAL_ZONE).is_ok_and(|local| local.zone_of(name))
+            || self.versions.zones().any(|zone| zone.zone_of(name));
+        if is_local {
+            return None;
+        }
+        blocklist.response(request)
+    }
+
+    fn soa(&self, ttl_value: TtlValue) -> FeatureDnsSoa {
+        soa_or_default(self.soa.as_ref(), ttl_value)
+    }
 }
 
 impl LocalNameServer {
     /// Create a new `LocalNameServer` with forwarding dns servers from `forward_ips`
     /// configured for zone `.`.
-    pub async fn new(forward_ips: &[IpAddr]) -> Result<Arc<RwLock<Self>>, String> {
+    ///
+    /// Failed queries of the forward zones are published to `failures`.
+    pub async fn new(
+        forward_ips: &[IpAddr],
+        failures: Option<Sender<DnsFailure>>,
+    ) -> Result<Arc<RwLock<Self>>, String> {
         let ns = Arc::new(RwLock::new(LocalNameServer {
             zones: Arc::new(ClonableZones::new()),
-            task_handle: None,
+            failures,
+            ..Default::default()
         }));
         ns.forward(forward_ips).await?;
         Ok(ns)
@@ -161,45 +237,42 @@ impl LocalNameServer {
                         telio_log_debug!("Write2Tun");
 
                         let nameserver = nameserver.clone();
-                        let length = match LocalNameServer::process_packet(
-                            nameserver,
-                            packet,
-                            &mut receiving_buffer,
-                        )
-                        .await
-                        {
-                            Ok(length) => length,
-                            Err(e) => {
-                                telio_log_error!(
-                                    "[DNS] {}. Offending request packet: {:?}",
-                                    e,
-                                    packet
-                                );
-                                return;
-                            }
-                        };
+                        let responses =
+                            match LocalNameServer::process_packet(nameserver, packet).await {
+                                Ok(responses) => responses,
+                                Err(e) => {
+                                    telio_log_error!(
+                                        "[DNS] {}. Offending request packet: {:?}",
+                                        e,
+                                        packet
+                                    );
+                                    return;
+                                }
+                            };
                         telio_log_debug!("Pkt processed");
 
-                        let tunn_res = peer.lock().await.encapsulate(
-                            receiving_buffer.get(..length).unwrap_or(&[]),
-                            &mut sending_buffer,
-                        );
-                        match tunn_res {
-                            TunnResult::WriteToNetwork(dns) => {
-                                if let Err(e) = socket.send_to(dns, dst_address).await {
+                        for response in responses {
+                            let tunn_res = peer
+                                .lock()
+                                .await
+                                .encapsulate(&response, &mut sending_buffer);
+                            match tunn_res {
+                                TunnResult::WriteToNetwork(dns) => {
+                                    if let Err(e) = socket.send_to(dns, dst_address).await {
+                                        telio_log_warn!(
+                                            "[DNS] Failed to send DNS query response  {:?}",
+                                            e
+                                        )
+                                    };
+                                }
+                                TunnResult::Err(e) => {
                                     telio_log_warn!(
-                                        "[DNS] Failed to send DNS query response  {:?}",
+                                        "[DNS] Failed to encapsulate DNS query response  {:?}",
                                         e
                                     )
-                                };
+                                }
+                                _ => {}
                             }
-                            TunnResult::Err(e) => {
-                                telio_log_warn!(
-                                    "[DNS] Failed to encapsulate DNS query response  {:?}",
-                                    e
-                                )
-                            }
-                            _ => {}
                         }
                     }
                     _ => {}
@@ -213,19 +286,36 @@ impl LocalNameServer {
         request_info: &mut RequestInfo,
     ) -> Result<Vec<u8>, String> {
         telio_log_debug!("Resolving dns");
-        let resolver = Resolver::new();
         let zones = nameserver.zones().await;
 
-        let dns_request = match &mut request_info.payload {
+        let (dns_request, protocol) = match &mut request_info.payload {
             PayloadRequestInfo::Udp {
                 ref mut dns_request,
                 ..
-            } => dns_request
-                .take()
-                .ok_or_else(|| String::from("Inexistent DNS request"))?,
-            _ => return Ok(Vec::new()),
+            } => (dns_request.take(), Protocol::Udp),
+            PayloadRequestInfo::Tcp {
+                ref mut dns_request,
+                ..
+            } => match dns_request.take() {
+                Some(dns_request) => (Some(dns_request), Protocol::Tcp),
+                None => return Ok(Vec::new()),
+            },
+        };
+        let dns_request = dns_request.ok_or_else(|| String::from("Inexistent DNS request"))?;
+        if let Some(response) = nameserver.read().await.blocked_response(&dns_request) {
+            telio_log_debug!("Blocked DNS request: {:?}", &dns_request);
+            return Ok(response);
+        }
+        // Clients not using EDNS0 get at most 512 bytes over UDP, with the TC flag set on
+        // truncation they retry over TCP, where the response is limited only by its length prefix
+        let max_size = match protocol {
+            Protocol::Udp => dns_request.edns().map_or(MIN_DNS_PAYLOAD, |edns| {
+                edns.max_payload().clamp(MIN_DNS_PAYLOAD, MAX_DNS_PAYLOAD)
+            }),
+            _ => u16::MAX,
         };
-        let dns_request = Request::new(dns_request, request_info.dns_source(), Protocol::Udp);
+        let resolver = Resolver::with_max_size(max_size);
+        let dns_request = Request::new(dns_request, request_info.dns_source(), protocol);
         telio_log_debug!("DNS request: {:?}", &dns_request);
 
         zones
@@ -241,8 +331,7 @@ impl LocalNameServer {
     async fn process_packet(
         nameserver: Arc<RwLock<LocalNameServer>>,
         request_packet: &[u8],
-        response_packet: &mut [u8],
-    ) -> Result<usize, String> {
+    ) -> Result<Vec<Vec<u8>>, String> {
         let mut request_info = request_packet
             .first()
             .ok_or_else(|| String::from("Empty request packet"))
@@ -255,7 +344,7 @@ impl LocalNameServer {
         let dns_response =
             LocalNameServer::resolve_dns_request(nameserver, &mut request_info).await?;
 
-        request_info.build_response_packet(&dns_response, response_packet)
+        request_info.build_response_packets(&dns_response)
     }
 
     fn process_ip_packet<'a, P: IpPacket<'a>>(
@@ -279,11 +368,7 @@ impl LocalNameServer {
             IpNextHeaderProtocols::Tcp => {
                 let tcp_request = TcpPacket::new(ip.payload())
                     .ok_or_else(|| String::from("Failed to build TcpPacket from request packet"))?;
-                PayloadRequestInfo::Tcp {
-                    source_port: tcp_request.get_source(),
-                    destination_port: tcp_request.get_destination(),
-                    next_sequence: tcp_request.get_sequence() + 1,
-                }
+                LocalNameServer::process_tcp_packet(&tcp_request)?
             }
             _ => {
                 return Err(String::from("Invalid protocol for DNS request"));
@@ -319,6 +404,65 @@ impl LocalNameServer {
             dns_request: Some(dns_request),
         })
     }
+
+    /// Answer the segment without keeping any connection state
+    ///
+    /// The whole query has to arrive in a single segment. The response is sent right after it,
+    /// along with the FIN, so the sequence number of the server is always the acknowledgement of
+    /// the last segment of the client.
+    fn process_tcp_packet(tcp_request: &TcpPacket) -> Result<PayloadRequestInfo, String> {
+        let flags = tcp_request.get_flags();
+        let sequence = tcp_request.get_sequence();
+        let acknowledgement = tcp_request.get_acknowledgement();
+        let payload = tcp_request.payload();
+
+        let mut dns_request = None;
+        let reply = if tcp_request.get_destination() != 53 {
+            TcpReply::Reset {
+                sequence: sequence.wrapping_add(1),
+            }
+        } else if flags & TcpFlags::RST != 0 {
+            TcpReply::Ignore
+        } else if flags & TcpFlags::SYN != 0 {
+            TcpReply::Accept {
+                // Any initial sequence number does, it is echoed back by the client
+                sequence: !sequence,
+                acknowledgement: sequence.wrapping_add(1),
+            }
+        } else if !payload.is_empty() {
+            // Queries are prefixed with their length, see RFC 1035 section 4.2.2
+            match payload {
+                [high, low, query @ ..]
+                    if query.len() == usize::from(u16::from_be_bytes([*high, *low])) =>
+                {
+                    dns_request = Some(MessageRequest::from_bytes(query).map_err(|_| {
+                        String::from("Failed to build MessageRequest from request packet")
+                    })?);
+                    TcpReply::Answer {
+                        sequence: acknowledgement,
+                        acknowledgement: sequence.wrapping_add(payload.len() as u32),
+                    }
+                }
+                _ => TcpReply::Reset {
+                    sequence: acknowledgement,
+                },
+            }
+        } else if flags & TcpFlags::FIN != 0 {
+            TcpReply::Close {
+                sequence: acknowledgement,
+                acknowledgement: sequence.wrapping_add(1),
+            }
+        } else {
+            TcpReply::Ignore
+        };
+
+        Ok(PayloadRequestInfo::Tcp {
+            source_port: tcp_request.get_source(),
+            destination_port: tcp_request.get_destination(),
+            reply,
+            dns_request,
+        })
+    }
 }
 
 enum IpRequestInfo {
@@ -370,15 +514,39 @@ enum PayloadRequestInfo {
     Tcp {
         source_port: u16,
         destination_port: u16,
-        next_sequence: u32,
+        reply: TcpReply,
+        dns_request: Option<MessageRequest>,
     },
 }
 
+/// Segments sent back in reply to a TCP segment of the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TcpReply {
+    /// SYN-ACK accepting the connection
+    Accept { sequence: u32, acknowledgement: u32 },
+    /// Response to the query, closing the connection
+    Answer { sequence: u32, acknowledgement: u32 },
+    /// ACK of the FIN of the client
+    Close { sequence: u32, acknowledgement: u32 },
+    /// RST of unexpected segments
+    Reset { sequence: u32 },
+    /// Nothing to reply, e.g. to bare ACKs
+    Ignore,
+}
+
 struct RequestInfo {
     ip: IpRequestInfo,
     payload: PayloadRequestInfo,
 }
 
+/// Header fields of a TCP segment of the response
+#[derive(Clone, Copy)]
+struct TcpSegment {
+    sequence: u32,
+    acknowledgement: u32,
+    flags: u8,
+}
+
 impl RequestInfo {
     fn dns_source(&self) -> SocketAddr {
         let source_port = match self.payload {
@@ -388,19 +556,90 @@ impl RequestInfo {
         SocketAddr::new(self.ip.source_ip(), source_port)
     }
 
+    fn build_response_packets(&self, dns_response: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let reply = match self.payload {
+            PayloadRequestInfo::Udp { .. } => {
+                return Ok(vec![self.build_response_packet(dns_response, None)?]);
+            }
+            PayloadRequestInfo::Tcp { reply, .. } => reply,
+        };
+
+        let segment = |sequence, acknowledgement, flags| TcpSegment {
+            sequence,
+            acknowledgement,
+            flags,
+        };
+        match reply {
+            TcpReply::Accept {
+                sequence,
+                acknowledgement,
+            } => Ok(vec![self.build_response_packet(
+                &[],
+                Some(segment(
+                    sequence,
+                    acknowledgement,
+                    TcpFlags::SYN | TcpFlags::ACK,
+                )),
+            )?]),
+            TcpReply::Answer {
+                mut sequence,
+                acknowledgement,
+            } => {
+                let mut stream = (dns_response.len() as u16).to_be_bytes().to_vec();
+                stream.extend_from_slice(dns_response);
+                let mss = match self.ip {
+                    IpRequestInfo::V4 { .. } => TCP_MSS_IPV4,
+                    IpRequestInfo::V6 { .. } => TCP_MSS_IPV6,
+                };
+
+                let chunks = stream.chunks(mss).count();
+                let mut packets = Vec::with_capacity(chunks);
+                for (i, data) in stream.chunks(mss).enumerate() {
+                    let mut flags = TcpFlags::ACK;
+                    if i + 1 == chunks {
+                        flags |= TcpFlags::PSH | TcpFlags::FIN;
+                    }
+                    packets.push(self.build_response_packet(
+                        data,
+                        Some(segment(sequence, acknowledgement, flags)),
+                    )?);
+                    sequence = sequence.wrapping_add(data.len() as u32);
+                }
+                Ok(packets)
+            }
+            TcpReply::Close {
+                sequence,
+                acknowledgement,
+            } => Ok(vec![self.build_response_packet(
+                &[],
+                Some(segment(sequence, acknowledgement, TcpFlags::ACK)),
+            )?]),
+            TcpReply::Reset { sequence } => {
+                Ok(vec![self.build_response_packet(
+                    &[],
+                    Some(segment(sequence, 0, TcpFlags::RST)),
+                )?])
+            }
+            TcpReply::Ignore => Ok(Vec::new()),
+        }
+    }
+
     fn build_response_packet(
         &self,
-        dns_response: &[u8],
-        response_packet: &mut [u8],
-    ) -> Result<usize, String> {
+        data: &[u8],
+        segment: Option<TcpSegment>,
+    ) -> Result<Vec<u8>, String> {
         let payload_header = match self.payload {
             PayloadRequestInfo::Udp { .. } => UDP_HEADER,
             PayloadRequestInfo::Tcp { .. } => TCP_MIN_HEADER,
         };
+        let mut response_packet = vec![0u8; IPV6_HEADER + payload_header + data.len()];
         let ip_header_length =
-            self.build_ip_header(payload_header + dns_response.len(), response_packet)?;
+            self.build_ip_header(payload_header + data.len(), &mut response_packet)?;
 
-        self.build_payload(ip_header_length, dns_response, response_packet)
+        let length = self.build_payload(ip_header_length, data, segment, &mut response_packet)?;
+        response_packet.truncate(length);
+        Ok(response_packet)
     }
 
     fn build_ip_header(
@@ -465,7 +704,6 @@ impl RequestInfo {
                         ip_response.set_next_header(IpNextHeaderProtocols::Tcp)
                     }
                 }
-                ip_response.set_next_header(IpNextHeaderProtocols::Udp);
                 ip_response.set_hop_limit(255);
                 ip_response.set_source(*destination_ip);
                 ip_response.set_destination(*source_ip);
@@ -480,6 +718,7 @@ impl RequestInfo {
         &self,
         ihl: usize,
         dns_response: &[u8],
+        segment: Option<TcpSegment>,
         response_packet: &mut [u8],
     ) -> Result<usize, String> {
         match self.payload {
@@ -511,9 +750,11 @@ impl RequestInfo {
             PayloadRequestInfo::Tcp {
                 source_port,
                 destination_port,
-                next_sequence,
+                ..
             } => {
-                let length = ihl + TCP_MIN_HEADER;
+                let segment =
+                    segment.ok_or_else(|| String::from("Missing TCP segment of the response"))?;
+                let length = ihl + TCP_MIN_HEADER + dns_response.len();
                 let buffer_slice = response_packet
                     .get_mut(ihl..length)
                     .ok_or_else(|| String::from("Out of bounds on response packet buffer"))?;
@@ -521,10 +762,14 @@ impl RequestInfo {
                     String::from("Failed to build MutableTcpPacket for response packet")
                 })?;
 
-                tcp_response.set_source(source_port);
-                tcp_response.set_destination(destination_port);
-                tcp_response.set_sequence(next_sequence);
-                tcp_response.set_flags(TcpFlags::RST);
+                tcp_response.set_source(destination_port);
+                tcp_response.set_destination(source_port);
+                tcp_response.set_sequence(segment.sequence);
+                tcp_response.set_acknowledgement(segment.acknowledgement);
+                tcp_response.set_data_offset((TCP_MIN_HEADER / 4) as u8);
+                tcp_response.set_flags(segment.flags);
+                tcp_response.set_window(u16::MAX);
+                tcp_response.set_payload(dns_response);
                 tcp_response.set_checksum(0);
                 let checksum = match self.ip {
                     IpRequestInfo::V4 {
@@ -547,7 +792,7 @@ impl RequestInfo {
                 };
                 tcp_response.set_checksum(checksum);
 
-                telio_log_debug!("UDP response: {:?}", &tcp_response);
+                telio_log_debug!("TCP response: {:?}", &tcp_response);
                 Ok(length)
             }
         }
@@ -656,7 +901,12 @@ impl NameServer for Arc<RwLock<LocalNameServer>> {
         records: &Records,
         ttl_value: TtlValue,
     ) -> Result<(), String> {
-        let azone = Arc::new(AuthoritativeZone::new(zone, records, ttl_value).await?);
+        let (soa, serial) = {
+            let mut this = self.write().await;
+            let serial = this.versions.update(zone, records, ttl_value)?;
+            (this.soa(ttl_value), serial)
+        };
+        let azone = Arc::new(AuthoritativeZone::new(zone, records, ttl_value, &soa, serial).await?);
 
         self.zones_mut()
             .await
@@ -664,10 +914,59 @@ impl NameServer for Arc<RwLock<LocalNameServer>> {
         Ok(())
     }
 
+    async fn set_soa(&self, soa: Option<FeatureDnsSoa>) {
+        self.write().await.soa = soa;
+    }
+
+    async fn set_blocklist(&self, blocklist: Option<Arc<Blocklist>>) {
+        self.write().await.blocklist = blocklist;
+    }
+
+    async fn set_socket_pool(&self, socket_pool: Option<Arc<SocketPool>>) {
+        self.write().await.socket_pool = socket_pool;
+    }
+
     async fn forward(&self, to: &[IpAddr]) -> Result<(), String> {
+        let failures = self.read().await.failures.clone();
         self.zones_mut().await.upsert(
             LowerName::from_str(".")?,
-            Box::new(Arc::new(ForwardZone::new(".", to).await?)),
+            Box::new(Arc::new(ForwardZone::new(".", to, None, failures).await?)),
+        );
+        Ok(())
+    }
+
+    async fn forward_domain(
+        &self,
+        domain: &str,
+        to: &[IpAddr],
+        bypass_tunnel: bool,
+    ) -> Result<(), String> {
+        let name = LowerName::from_str(domain)?;
+        if name.is_root() || LowerName::from_str(LOCAL_ZONE)?.zone_of(&name) {
+            return Err(format!("Queries for {domain} can not be forwarded"));
+        }
+        if to.is_empty() {
+            return Err(format!("No resolvers to forward {domain} to"));
+        }
+
+        let (failures, socket_pool) = {
+            let ns = self.read().await;
+            (ns.failures.clone(), ns.socket_pool.clone())
+        };
+        let external = match (bypass_tunnel, socket_pool) {
+            (false, _) => None,
+            (true, Some(socket_pool)) => Some(socket_pool),
+            (true, None) => {
+                return Err(format!(
+                    "Queries for {domain} can not bypass the tunnel without a socket pool"
+                ))
+            }
+        };
+        self.zones_mut().await.upsert(
+            name,
+            Box::new(Arc::new(
+                ForwardZone::new(domain, to, external, failures).await?,
+            )),
         );
         Ok(())
     }
@@ -699,19 +998,25 @@ mod tests {
         authority::MessageRequest,
         proto::{
             op::{Message, Query},
-            rr::Name,
+            rr::{Name, RData, RecordType},
             serialize::binary::{BinDecodable, BinDecoder, BinEncodable},
         },
         server::Request,
     };
-    use std::{net::Ipv4Addr, str::FromStr};
+    use std::{mem, net::Ipv4Addr, str::FromStr};
+    use telio_sockets::protector::make_external_protector;
 
     use super::*;
 
     fn dns_request(host: String) -> Request {
+        dns_request_of_type(host, RecordType::A)
+    }
+
+    fn dns_request_of_type(host: String, query_type: RecordType) -> Request {
         let mut question = Message::new();
         let mut query = Query::new();
         query.set_name(Name::from_str(&host).unwrap());
+        query.set_query_type(query_type);
         question.add_query(query);
         let message_request = MessageRequest::from_bytes(&question.to_bytes().unwrap()).unwrap();
 
@@ -730,7 +1035,7 @@ mod tests {
             entry_name.clone(),
             vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
         );
-        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
             .await
             .unwrap();
         nameserver
@@ -757,6 +1062,187 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn memory_usage_follows_records() {
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+        assert_eq!(nameserver.read().await.memory_usage(), 0);
+
+        let mut records = Records::new();
+        records.insert(
+            "pashka.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
+        );
+        nameserver
+            .upsert("nord", &records, TtlValue(60))
+            .await
+            .unwrap();
+        let single = nameserver.read().await.memory_usage();
+        assert!(single > 0);
+
+        records.insert(
+            "pashka.nord.".to_owned(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69)),
+                IpAddr::V4(Ipv4Addr::new(100, 69, 69, 70)),
+            ],
+        );
+        nameserver
+            .upsert("nord", &records, TtlValue(60))
+            .await
+            .unwrap();
+        assert_eq!(
+            nameserver.read().await.memory_usage(),
+            single + mem::size_of::<IpAddr>()
+        );
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_truncated_to_max_size() {
+        let entry_name = String::from("many.nord.");
+        let mut records = Records::new();
+        records.insert(
+            entry_name.clone(),
+            (0..100)
+                .map(|i| IpAddr::V4(Ipv4Addr::new(100, 64, 0, i)))
+                .collect(),
+        );
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+        nameserver
+            .upsert("nord", &records, TtlValue(60))
+            .await
+            .unwrap();
+
+        for (max_size, truncated) in [(MIN_DNS_PAYLOAD, true), (MAX_DNS_PAYLOAD, false)] {
+            let resolver = Resolver::with_max_size(max_size);
+            nameserver
+                .zones()
+                .await
+                .lookup(&dns_request(entry_name.clone()), resolver.clone())
+                .await
+                .unwrap();
+            let buf = resolver.0.lock().await;
+            assert!(buf.len() <= max_size as usize);
+            let message = Message::from_vec(&buf).unwrap();
+            assert_eq!(message.truncated(), truncated);
+            if !truncated {
+                assert_eq!(message.answers().len(), 100);
+            }
+        }
+    }
+
+    fn tcp_packet(flags: u8, sequence: u32, acknowledgement: u32, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; IPV4_HEADER + TCP_MIN_HEADER + payload.len()];
+        {
+            let mut tcp = MutableTcpPacket::new(&mut packet[IPV4_HEADER..]).unwrap();
+            tcp.set_source(40000);
+            tcp.set_destination(53);
+            tcp.set_sequence(sequence);
+            tcp.set_acknowledgement(acknowledgement);
+            tcp.set_data_offset((TCP_MIN_HEADER / 4) as u8);
+            tcp.set_flags(flags);
+            tcp.set_window(u16::MAX);
+            tcp.set_payload(payload);
+        }
+        let total_length = packet.len() as u16;
+        let mut ip = MutableIpv4Packet::new(&mut packet).unwrap();
+        ip.set_version(4);
+        ip.set_header_length((IPV4_HEADER / 4) as u8);
+        ip.set_total_length(total_length);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+        ip.set_source(Ipv4Addr::new(100, 64, 0, 2));
+        ip.set_destination(Ipv4Addr::new(100, 64, 0, 3));
+        ip.set_checksum(checksum(&ip.to_immutable()));
+        packet
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_served_over_tcp() {
+        let entry_name = String::from("many.nord.");
+        let mut records = Records::new();
+        records.insert(
+            entry_name.clone(),
+            (0..100)
+                .map(|i| IpAddr::V4(Ipv4Addr::new(100, 64, 0, i)))
+                .collect(),
+        );
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+            .await
+            .unwrap();
+        nameserver
+            .upsert("nord", &records, TtlValue(60))
+            .await
+            .unwrap();
+
+        let syn_ack = LocalNameServer::process_packet(
+            nameserver.clone(),
+            &tcp_packet(TcpFlags::SYN, 1000, 0, &[]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(syn_ack.len(), 1);
+        let syn_ack = TcpPacket::owned(syn_ack[0][IPV4_HEADER..].to_vec()).unwrap();
+        assert_eq!(syn_ack.get_flags(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(syn_ack.get_acknowledgement(), 1001);
+        assert_eq!(syn_ack.get_source(), 53);
+        let server_sequence = syn_ack.get_sequence().wrapping_add(1);
+
+        // Bare ACK completing the handshake is not answered
+        let ack = tcp_packet(TcpFlags::ACK, 1001, server_sequence, &[]);
+        assert!(LocalNameServer::process_packet(nameserver.clone(), &ack)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut question = Message::new();
+        question.add_query(Query::query(
+            Name::from_str(&entry_name).unwrap(),
+            RecordType::A,
+        ));
+        let query = question.to_bytes().unwrap();
+        let mut stream = (query.len() as u16).to_be_bytes().to_vec();
+        stream.extend_from_slice(&query);
+        let segments = LocalNameServer::process_packet(
+            nameserver.clone(),
+            &tcp_packet(
+                TcpFlags::ACK | TcpFlags::PSH,
+                1001,
+                server_sequence,
+                &stream,
+            ),
+        )
+        .await
+        .unwrap();
+        assert!(segments.len() > 1);
+
+        let mut response = Vec::new();
+        let mut sequence = server_sequence;
+        for (i, segment) in segments.iter().enumerate() {
+            let ip = Ipv4Packet::new(segment).unwrap();
+            assert!(ip.check_valid());
+            let tcp = TcpPacket::new(ip.payload()).unwrap();
+            assert!(tcp.payload().len() <= TCP_MSS_IPV4);
+            assert_eq!(tcp.get_sequence(), sequence);
+            assert_eq!(tcp.get_acknowledgement(), 1001 + stream.len() as u32);
+            assert_eq!(
+                tcp.get_flags() & TcpFlags::FIN != 0,
+                i + 1 == segments.len()
+            );
+            sequence = sequence.wrapping_add(tcp.payload().len() as u32);
+            response.extend_from_slice(tcp.payload());
+        }
+
+        let length = u16::from_be_bytes([response[0], response[1]]) as usize;
+        assert_eq!(length, response.len() - 2);
+        let message = Message::from_vec(&response[2..]).unwrap();
+        assert!(!message.truncated());
+        assert_eq!(message.answers().len(), 100);
+    }
+
     #[tokio::test]
     async fn zones_are_lazily_copied_on_write_access() {
         let name1 = "test.nord.".to_owned();
@@ -765,7 +1251,7 @@ mod tests {
             name1.clone(),
             vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
         );
-        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
             .await
             .unwrap();
         let raw_read_ptr1 = Arc::as_ptr(&nameserver.zones().await);
@@ -804,7 +1290,7 @@ mod tests {
             name1.clone(),
             vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
         );
-        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
             .await
             .unwrap();
         nameserver
@@ -816,4 +1302,181 @@ mod tests {
         assert!(zones.contains(&LowerName::from_str(".").unwrap()));
         assert!(zones.contains(&LowerName::from_str("nord").unwrap()));
     }
+
+    async fn lookup_soa(nameserver: &Arc<RwLock<LocalNameServer>>) -> (Name, u32, u32) {
+        let resolver = Resolver::new();
+        nameserver
+            .zones()
+            .await
+            .lookup(
+                &dns_request_of_type("nord.".to_owned(), RecordType::SOA),
+                resolver.clone(),
+            )
+            .await
+            .unwrap();
+        let buf = resolver.0.lock().await;
+        let answers = Message::read(&mut BinDecoder::new(&buf))
+            .unwrap()
+            .take_answers();
+        match answers.first().and_then(|answer| answer.data()) {
+            Some(RData::SOA(soa)) => (soa.mname().clone(), soa.serial(), soa.minimum()),
+            other => panic!("Unexpected SOA lookup result {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn soa_serial_is_bumped_on_changes() {
+        let mut records = Records::new();
+        records.insert(
+            "test.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
+        );
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+
+        nameserver
+            .upsert("nord", &records, TtlValue(30))
+            .await
+            .unwrap();
+        let (mname, serial, minimum) = lookup_soa(&nameserver).await;
+        assert_eq!(mname, Name::from_str("mesh.nordsec.com.").unwrap());
+        assert_eq!(minimum, 30);
+
+        nameserver
+            .upsert("nord", &records, TtlValue(30))
+            .await
+            .unwrap();
+        assert_eq!(lookup_soa(&nameserver).await.1, serial);
+
+        nameserver
+            .set_soa(Some(FeatureDnsSoa {
+                primary_ns: "ns.example.com.".parse().unwrap(),
+                negative_ttl_s: 5,
+                ..Default::default()
+            }))
+            .await;
+        records.insert(
+            "other.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 70))],
+        );
+        nameserver
+            .upsert("nord", &records, TtlValue(30))
+            .await
+            .unwrap();
+        let (mname, next_serial, minimum) = lookup_soa(&nameserver).await;
+        assert_eq!(mname, Name::from_str("ns.example.com.").unwrap());
+        assert!(next_serial > serial);
+        assert_eq!(minimum, 5);
+    }
+
+    #[tokio::test]
+    async fn zone_is_exported_as_zone_file() {
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+        assert_eq!(nameserver.read().await.export_zone("nord"), None);
+
+        let mut records = Records::new();
+        records.insert(
+            "test.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
+        );
+        nameserver
+            .upsert("nord", &records, TtlValue(30))
+            .await
+            .unwrap();
+
+        let (_, serial, _) = lookup_soa(&nameserver).await;
+        let text = nameserver.read().await.export_zone("nord").unwrap();
+        assert!(text.contains(&format!(
+            "nord. 30 IN SOA mesh.nordsec.com. support.nordsec.com. {serial} "
+        )));
+        assert!(text.contains("test.nord. 30 IN A 100.69.69.69\n"));
+        assert!(!text.contains("PTR"));
+
+        let text = nameserver.read().await.export_zone("in-addr.arpa").unwrap();
+        assert!(text.contains("$ORIGIN in-addr.arpa.\n"));
+        assert!(text.contains("69.69.69.100.in-addr.arpa. 30 IN PTR test.nord.\n"));
+    }
+
+    #[tokio::test]
+    async fn domains_are_forwarded_to_own_resolvers() {
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+        let corp = [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 53))];
+
+        nameserver
+            .forward_domain("corp.example.com", &corp, false)
+            .await
+            .unwrap();
+        // Sockets outside the tunnel can only be made by the socket pool
+        assert!(nameserver
+            .forward_domain("lan.example.com", &corp, true)
+            .await
+            .is_err());
+        nameserver
+            .set_socket_pool(Some(Arc::new(SocketPool::new(make_external_protector(
+                Arc::new(|_| {}),
+            )))))
+            .await;
+        nameserver
+            .forward_domain("lan.example.com", &corp, true)
+            .await
+            .unwrap();
+        let zones = nameserver.zones().await;
+        assert!(zones.contains(&LowerName::from_str(".").unwrap()));
+        assert!(zones.contains(&LowerName::from_str("corp.example.com").unwrap()));
+        assert!(zones.contains(&LowerName::from_str("lan.example.com").unwrap()));
+
+        // Switching the forward servers, e.g. to exit DNS, keeps the rules
+        nameserver
+            .forward(&[IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))])
+            .await
+            .unwrap();
+        let zones = nameserver.zones().await;
+        assert!(zones.contains(&LowerName::from_str("corp.example.com").unwrap()));
+        assert!(zones.contains(&LowerName::from_str("lan.example.com").unwrap()));
+
+        for domain in [".", "nord", "host.nord."] {
+            assert!(nameserver
+                .forward_domain(domain, &corp, false)
+                .await
+                .is_err());
+        }
+        assert!(nameserver
+            .forward_domain("example.org", &[], false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn local_zones_are_never_blocked() {
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))], None)
+            .await
+            .unwrap();
+        let blocklist = Arc::new(Blocklist::parse(
+            "*.nord\nads.example.com\n*.corp.example\n",
+            Vec::new(),
+        ));
+        nameserver.set_blocklist(Some(blocklist.clone())).await;
+        nameserver
+            .upsert("corp.example", &Records::new(), TtlValue(60))
+            .await
+            .unwrap();
+
+        let blocked = |host: &str| {
+            let request = dns_request(host.to_owned());
+            nameserver.try_read().unwrap().blocked_response(&request)
+        };
+        assert!(blocked("ads.example.com.").is_some());
+        assert!(blocked("host.nord.").is_none());
+        assert!(blocked("host.corp.example.").is_none());
+        assert!(blocked("example.com.").is_none());
+        assert_eq!(blocklist.stats().blocked_queries, 1);
+
+        nameserver.set_blocklist(None).await;
+        assert!(blocked("ads.example.com.").is_none());
+    }
 }
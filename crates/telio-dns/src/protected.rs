@@ -0,0 +1,174 @@
+//! Resolver runtime whose sockets are created through the [SocketPool].
+//!
+//! The sockets are made external by the protector, so lookups done while the tunnel is
+//! being set up do not get routed into it.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+use hickory_server::{
+    proto::{iocompat::AsyncIoTokioAsStd, tcp::DnsTcpStream, udp::DnsUdpSocket},
+    resolver::{
+        name_server::{GenericConnector, RuntimeProvider, TokioRuntimeProvider},
+        AsyncResolver,
+    },
+};
+use telio_sockets::{External, SocketPool};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Resolver with protected sockets
+pub(crate) type ProtectedAsyncResolver = AsyncResolver<GenericConnector<ProtectedRuntimeProvider>>;
+
+#[derive(Clone)]
+pub(crate) struct ProtectedRuntimeProvider {
+    inner: TokioRuntimeProvider,
+    socket_pool: Arc<SocketPool>,
+}
+
+impl ProtectedRuntimeProvider {
+    pub(crate) fn new(socket_pool: Arc<SocketPool>) -> Self {
+        Self {
+            inner: TokioRuntimeProvider::default(),
+            socket_pool,
+        }
+    }
+}
+
+impl RuntimeProvider for ProtectedRuntimeProvider {
+    type Handle = <TokioRuntimeProvider as RuntimeProvider>::Handle;
+    type Timer = <TokioRuntimeProvider as RuntimeProvider>::Timer;
+    type Udp = ProtectedUdpSocket;
+    type Tcp = ProtectedTcpStream;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.inner.create_handle()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
+        let socket_pool = self.socket_pool.clone();
+        Box::pin(async move {
+            let socket = match server_addr {
+                SocketAddr::V4(_) => socket_pool.new_external_tcp_v4(None)?,
+                SocketAddr::V6(_) => socket_pool.new_external_tcp_v6(None)?,
+            };
+            let stream = socket.connect(server_addr).await?;
+            Ok(ProtectedTcpStream(AsyncIoTokioAsStd(stream)))
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        local_addr: SocketAddr,
+        _server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Udp>>>> {
+        let socket_pool = self.socket_pool.clone();
+        Box::pin(async move {
+            let socket = socket_pool.new_external_udp(local_addr, None).await?;
+            Ok(ProtectedUdpSocket(socket))
+        })
+    }
+}
+
+pub(crate) struct ProtectedUdpSocket(External<UdpSocket>);
+
+#[async_trait]
+impl DnsUdpSocket for ProtectedUdpSocket {
+    type Time = <UdpSocket as DnsUdpSocket>::Time;
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        DnsUdpSocket::poll_recv_from(&*self.0, cx, buf)
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        DnsUdpSocket::poll_send_to(&*self.0, cx, buf, target)
+    }
+}
+
+pub(crate) struct ProtectedTcpStream(AsyncIoTokioAsStd<External<TcpStream>>);
+
+impl DnsTcpStream for ProtectedTcpStream {
+    type Time = <TokioRuntimeProvider as RuntimeProvider>::Timer;
+}
+
+impl AsyncRead for ProtectedTcpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProtectedTcpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use telio_sockets::protector::make_external_protector;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn resolver_sockets_are_made_external() {
+        let protected = Arc::new(AtomicUsize::new(0));
+        let socket_pool = Arc::new(SocketPool::new(make_external_protector(Arc::new({
+            let protected = protected.clone();
+            move |_| {
+                protected.fetch_add(1, Ordering::Relaxed);
+            }
+        }))));
+        let provider = ProtectedRuntimeProvider::new(socket_pool);
+
+        let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let _udp = provider
+            .bind_udp((Ipv4Addr::UNSPECIFIED, 0).into(), server_addr)
+            .await
+            .unwrap();
+        assert_eq!(protected.load(Ordering::Relaxed), 1);
+
+        let _tcp = provider.connect_tcp(server_addr).await.unwrap();
+        assert_eq!(protected.load(Ordering::Relaxed), 2);
+    }
+}
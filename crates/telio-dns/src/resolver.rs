@@ -9,12 +9,19 @@ use tokio::sync::Mutex;
 
 #[derive(Clone)]
 /// Resolver converts DNS responses to &[u8].
-pub struct Resolver(pub(crate) Arc<Mutex<Vec<u8>>>);
+///
+/// Responses not fitting into the maximum size are truncated, with the TC flag set.
+pub struct Resolver(pub(crate) Arc<Mutex<Vec<u8>>>, u16);
 
 impl Resolver {
     /// Create new `Resolver`.
     pub fn new() -> Self {
-        Resolver(Arc::new(Mutex::new(Vec::new())))
+        Self::with_max_size(u16::MAX)
+    }
+
+    /// Create new `Resolver` producing responses of at most `max_size` bytes.
+    pub fn with_max_size(max_size: u16) -> Self {
+        Resolver(Arc::new(Mutex::new(Vec::new())), max_size)
     }
 }
 
@@ -36,6 +43,7 @@ impl ResponseHandler for Resolver {
         // TODO: fix a bug in https://docs.rs/trust-dns-proto/0.20.3/src/trust_dns_proto/serialize/binary/encoder.rs.html#61
         // so that its possible to use BinEncoder::with_offset
         let mut encoder = BinEncoder::new(&mut buf);
+        encoder.set_max_size(self.1);
         response
             .destructive_emit(&mut encoder)
             .map_err(Into::<IOError>::into)
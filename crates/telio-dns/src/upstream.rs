@@ -0,0 +1,368 @@
+//! Health tracking of upstream (forward) DNS resolvers.
+//!
+//! Each configured upstream keeps a record of its recent query outcomes. Queries are routed
+//! to the healthiest upstreams first, while the unhealthy ones are periodically probed in
+//! the background until they recover.
+
+use std::{collections::VecDeque, net::IpAddr, time::Duration};
+
+use hickory_server::{
+    proto::op::ResponseCode,
+    resolver::error::{ResolveError, ResolveErrorKind},
+};
+use telio_utils::{
+    exponential_backoff::{Backoff, ExponentialBackoff, ExponentialBackoffBounds},
+    telio_log_info, telio_log_warn,
+};
+use tokio::time::Instant;
+
+/// Number of consecutive failures after which an upstream is considered unhealthy
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+/// Number of most recent query outcomes used to calculate the SERVFAIL rate
+const OUTCOME_WINDOW: usize = 20;
+/// Minimal number of outcomes needed before the SERVFAIL rate is taken into account
+const MIN_SERVFAIL_SAMPLES: usize = 5;
+/// SERVFAIL rate above which an upstream is considered unhealthy
+const MAX_SERVFAIL_RATE: f64 = 0.5;
+/// Probing interval of an unhealthy upstream right after it was marked unhealthy
+const INITIAL_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// Maximal probing interval of an unhealthy upstream
+const MAX_PROBE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Outcome of a single query sent to an upstream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QueryOutcome {
+    /// Upstream answered the query (including negative answers like NXDOMAIN)
+    Answered,
+    /// Upstream answered with SERVFAIL
+    ServFail,
+    /// Upstream did not answer in time or could not be reached at all
+    Timeout,
+}
+
+impl QueryOutcome {
+    /// Classify the result of a lookup sent to a single upstream
+    pub(crate) fn from_result<T>(result: &Result<T, ResolveError>) -> Self {
+        match result {
+            Ok(_) => QueryOutcome::Answered,
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. }
+                    if *response_code == ResponseCode::ServFail =>
+                {
+                    QueryOutcome::ServFail
+                }
+                ResolveErrorKind::NoRecordsFound { .. } => QueryOutcome::Answered,
+                _ => QueryOutcome::Timeout,
+            },
+        }
+    }
+}
+
+/// Health state of a single upstream
+#[derive(Debug)]
+pub(crate) struct UpstreamHealth {
+    ip: IpAddr,
+    consecutive_failures: u32,
+    outcomes: VecDeque<QueryOutcome>,
+    /// When the unhealthy upstream should be probed next, `None` for healthy upstreams
+    next_probe: Option<Instant>,
+    probe_backoff: ExponentialBackoff,
+}
+
+impl UpstreamHealth {
+    pub(crate) fn new(ip: IpAddr) -> Self {
+        #[allow(clippy::expect_used)]
+        let probe_backoff = ExponentialBackoff::new(ExponentialBackoffBounds {
+            initial: INITIAL_PROBE_INTERVAL,
+            maximal: Some(MAX_PROBE_INTERVAL),
+        })
+        .expect("Probe backoff bounds are valid");
+
+        Self {
+            ip,
+            consecutive_failures: 0,
+            outcomes: VecDeque::with_capacity(OUTCOME_WINDOW),
+            next_probe: None,
+            probe_backoff,
+        }
+    }
+
+    /// Returns address of the upstream
+    pub(crate) fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Returns true if the upstream is considered usable for regular queries
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.next_probe.is_none()
+    }
+
+    /// Ratio of SERVFAIL answers among the recent outcomes
+    pub(crate) fn servfail_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        let servfails = self
+            .outcomes
+            .iter()
+            .filter(|o| **o == QueryOutcome::ServFail)
+            .count();
+        servfails as f64 / self.outcomes.len() as f64
+    }
+
+    /// Update the health state with the outcome of a query
+    pub(crate) fn record(&mut self, outcome: QueryOutcome, now: Instant) {
+        if self.outcomes.len() == OUTCOME_WINDOW {
+            self.outcomes.pop_front();
+        }
+        self.outcomes.push_back(outcome);
+
+        if outcome == QueryOutcome::Answered {
+            self.consecutive_failures = 0;
+            if !self.is_healthy() {
+                // A successful probe brings the upstream back right away, even though
+                // its window still holds the failures which got it marked unhealthy
+                telio_log_info!("Upstream DNS {} recovered", self.ip);
+                self.outcomes.retain(|o| *o == QueryOutcome::Answered);
+                self.next_probe = None;
+                self.probe_backoff.reset();
+            }
+            return;
+        }
+
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+
+        if !self.is_healthy() {
+            // Failed probe, wait longer before the next one
+            self.probe_backoff.next_backoff();
+            self.next_probe = Some(now + self.probe_backoff.get_backoff());
+            return;
+        }
+
+        let failing = self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES
+            || (self.outcomes.len() >= MIN_SERVFAIL_SAMPLES
+                && self.servfail_rate() > MAX_SERVFAIL_RATE);
+        if failing {
+            telio_log_warn!(
+                "Upstream DNS {} marked as unhealthy, consecutive failures: {}, servfail rate: {:.2}",
+                self.ip,
+                self.consecutive_failures,
+                self.servfail_rate()
+            );
+            self.probe_backoff.reset();
+            self.next_probe = Some(now + self.probe_backoff.get_backoff());
+        }
+    }
+
+    /// Returns true if the unhealthy upstream should be probed now. Calling this
+    /// function reserves the probe, so it will not be reported as due again until
+    /// the next probing interval elapses.
+    fn take_probe(&mut self, now: Instant) -> bool {
+        match self.next_probe {
+            Some(next_probe) if next_probe <= now => {
+                self.next_probe = Some(now + self.probe_backoff.get_backoff());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Routing decision for a single query
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct QueryPlan {
+    /// Indices of upstreams to which the query should be sent, in order
+    pub(crate) candidates: Vec<usize>,
+    /// Indices of unhealthy upstreams which should be probed in the background
+    pub(crate) probes: Vec<usize>,
+}
+
+/// Health state of all of the upstreams of a forward zone
+#[derive(Debug, Default)]
+pub(crate) struct UpstreamsHealth {
+    upstreams: Vec<UpstreamHealth>,
+}
+
+impl UpstreamsHealth {
+    pub(crate) fn new(ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        Self {
+            upstreams: ips.into_iter().map(UpstreamHealth::new).collect(),
+        }
+    }
+
+    /// Returns address of the upstream at `idx`
+    pub(crate) fn ip(&self, idx: usize) -> Option<IpAddr> {
+        self.upstreams.get(idx).map(UpstreamHealth::ip)
+    }
+
+    /// Update the health state of the upstream at `idx`
+    pub(crate) fn record(&mut self, idx: usize, outcome: QueryOutcome, now: Instant) {
+        if let Some(upstream) = self.upstreams.get_mut(idx) {
+            upstream.record(outcome, now);
+        }
+    }
+
+    /// Decide which upstreams should be used for the next query.
+    ///
+    /// Healthy upstreams are ordered by their SERVFAIL rate, keeping the configured
+    /// order for equal rates. If there are no healthy upstreams left, the unhealthy
+    /// ones are used as the last resort, so queries are never dropped locally.
+    pub(crate) fn plan(&mut self, now: Instant) -> QueryPlan {
+        let mut healthy: Vec<usize> = self
+            .upstreams
+            .iter()
+            .enumerate()
+            .filter(|(_, u)| u.is_healthy())
+            .map(|(idx, _)| idx)
+            .collect();
+        healthy.sort_by(|a, b| {
+            let rate = |idx: &usize| self.upstreams.get(*idx).map_or(0.0, |u| u.servfail_rate());
+            rate(a).total_cmp(&rate(b))
+        });
+
+        let probes = self
+            .upstreams
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, u)| u.take_probe(now).then_some(idx))
+            .collect();
+
+        let candidates = if healthy.is_empty() {
+            let mut unhealthy: Vec<usize> = (0..self.upstreams.len()).collect();
+            unhealthy.sort_by_key(|idx| self.upstreams.get(*idx).and_then(|u| u.next_probe));
+            unhealthy
+        } else {
+            healthy
+        };
+
+        QueryPlan { candidates, probes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn upstreams(count: u8) -> UpstreamsHealth {
+        UpstreamsHealth::new((1..=count).map(|i| IpAddr::V4(Ipv4Addr::new(10, 0, 0, i))))
+    }
+
+    #[test]
+    fn healthy_upstreams_keep_configured_order() {
+        let mut health = upstreams(3);
+        let plan = health.plan(Instant::now());
+        assert_eq!(plan.candidates, vec![0, 1, 2]);
+        assert!(plan.probes.is_empty());
+    }
+
+    #[test]
+    fn consecutive_timeouts_move_upstream_out_of_rotation() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health.record(0, QueryOutcome::Timeout, now);
+        }
+
+        let plan = health.plan(now);
+        assert_eq!(plan.candidates, vec![1]);
+        assert!(plan.probes.is_empty());
+    }
+
+    #[test]
+    fn single_failure_does_not_affect_health() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        health.record(0, QueryOutcome::Timeout, now);
+        health.record(0, QueryOutcome::Answered, now);
+        health.record(0, QueryOutcome::Timeout, now);
+
+        assert_eq!(health.plan(now).candidates, vec![0, 1]);
+    }
+
+    #[test]
+    fn servfail_rate_lowers_priority_and_health() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        health.record(0, QueryOutcome::ServFail, now);
+        health.record(0, QueryOutcome::Answered, now);
+        assert_eq!(health.plan(now).candidates, vec![1, 0]);
+
+        // Never three failures in a row, but most of the recent answers are SERVFAIL
+        health.record(0, QueryOutcome::ServFail, now);
+        health.record(0, QueryOutcome::ServFail, now);
+        health.record(0, QueryOutcome::Answered, now);
+        assert_eq!(health.plan(now).candidates, vec![1, 0]);
+        health.record(0, QueryOutcome::ServFail, now);
+        assert_eq!(health.plan(now).candidates, vec![1]);
+    }
+
+    #[test]
+    fn unhealthy_upstream_is_probed_with_backoff() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health.record(0, QueryOutcome::Timeout, now);
+        }
+
+        assert!(health.plan(now).probes.is_empty());
+
+        let first_probe = now + INITIAL_PROBE_INTERVAL;
+        assert_eq!(health.plan(first_probe).probes, vec![0]);
+        // Probe is already in flight
+        assert!(health.plan(first_probe).probes.is_empty());
+
+        health.record(0, QueryOutcome::Timeout, first_probe);
+        assert!(health
+            .plan(first_probe + INITIAL_PROBE_INTERVAL)
+            .probes
+            .is_empty());
+        assert_eq!(
+            health.plan(first_probe + INITIAL_PROBE_INTERVAL * 2).probes,
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn successful_probe_restores_upstream() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health.record(0, QueryOutcome::ServFail, now);
+        }
+        assert_eq!(health.plan(now).candidates, vec![1]);
+
+        health.record(0, QueryOutcome::Answered, now + INITIAL_PROBE_INTERVAL);
+        assert_eq!(
+            health.plan(now + INITIAL_PROBE_INTERVAL).candidates,
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn all_unhealthy_upstreams_are_still_used() {
+        let mut health = upstreams(2);
+        let now = Instant::now();
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health.record(1, QueryOutcome::Timeout, now);
+        }
+        let later = now + Duration::from_secs(1);
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            health.record(0, QueryOutcome::Timeout, later);
+        }
+
+        assert_eq!(health.plan(later).candidates, vec![1, 0]);
+    }
+
+    #[test]
+    fn outcome_classification() {
+        assert_eq!(
+            QueryOutcome::from_result::<()>(&Ok(())),
+            QueryOutcome::Answered
+        );
+        assert_eq!(
+            QueryOutcome::from_result::<()>(&Err(ResolveErrorKind::Timeout.into())),
+            QueryOutcome::Timeout
+        );
+    }
+}
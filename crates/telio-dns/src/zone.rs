@@ -12,13 +12,17 @@ use hickory_server::{
 use std::{
     collections::{HashMap, HashSet},
     convert::TryInto,
+    mem,
     net::IpAddr,
     str::FromStr,
+    sync::Arc,
 };
-use telio_model::features::TtlValue;
+use telio_model::features::{FeatureDnsSoa, TtlValue};
+use telio_sockets::SocketPool;
 use telio_utils::telio_log_warn;
+use tokio::sync::mpsc::Sender;
 
-use crate::forward::ForwardAuthority;
+use crate::forward::{DnsFailure, ForwardAuthority};
 
 /// Zone is a portion of the DNS namespace that is managed by a specific
 /// organization or administrator.
@@ -41,6 +45,8 @@ impl AuthoritativeZone {
         name: &str,
         records: &Records,
         ttl_value: TtlValue,
+        soa: &FeatureDnsSoa,
+        serial: u32,
     ) -> Result<Self, String> {
         // TODO: rewrite code so that this assert is not needed.
         for domain in records.keys() {
@@ -50,14 +56,6 @@ impl AuthoritativeZone {
         }
         let zone_name = Name::from_str(name)?;
         let zone = InMemoryAuthority::empty(zone_name.clone(), ZoneType::Primary, false);
-        let ttl_value_signed: i32 = match ttl_value.0.try_into() {
-            Ok(ttl_value) => ttl_value,
-            Err(_) => {
-                let default = TtlValue::default().0 as i32;
-                telio_log_warn!("TTL value could not be converted from u32 to i32 without data loss, so using default value: {default}");
-                default
-            }
-        };
         zone.upsert(
             Record::new()
                 .set_name(zone_name)
@@ -65,13 +63,13 @@ impl AuthoritativeZone {
                 .set_rr_type(RecordType::SOA)
                 .set_dns_class(DNSClass::IN)
                 .set_data(Some(RData::SOA(SOA::new(
-                    Name::parse("mesh.nordsec.com.", None)?,
-                    Name::parse("support.nordsec.com.", None)?,
-                    2015082403,
-                    7200,
-                    ttl_value_signed,
-                    1209600,
-                    ttl_value.0,
+                    Name::parse(&soa.primary_ns, None)?,
+                    Name::parse(&soa.responsible, None)?,
+                    serial,
+                    signed_interval("refresh", soa.refresh_s, 7200),
+                    signed_interval("retry", soa.retry_s, TtlValue::default().0 as i32),
+                    signed_interval("expire", soa.expire_s, 1209600),
+                    soa.negative_ttl_s,
                 ))))
                 .clone(),
             0,
@@ -166,6 +164,243 @@ impl Authority for AuthoritativeZone {
     }
 }
 
+/// SOA intervals are signed, fall back to the default when the configured one does not fit
+fn signed_interval(name: &str, value: u32, default: i32) -> i32 {
+    value.try_into().unwrap_or_else(|_| {
+        telio_log_warn!(
+            "SOA {name} interval {value} does not fit into i32, so using default value: {default}"
+        );
+        default
+    })
+}
+
+/// Reverse zone of the PTR records of the IPv4 addresses
+pub const REVERSE_ZONE_IPV4: &str = "in-addr.arpa";
+/// Reverse zone of the PTR records of the IPv6 addresses
+pub const REVERSE_ZONE_IPV6: &str = "ip6.arpa";
+
+/// Contents of a local zone along with its serial
+struct ZoneVersion {
+    records: Records,
+    ttl_value: TtlValue,
+    serial: u32,
+}
+
+/// Contents of the local zones, kept to be exported as zone files (RFC 1035)
+///
+/// The PTR records of the local zones are kept as the separate `in-addr.arpa` and `ip6.arpa`
+/// zones, as they are out of zone for the local ones. Every zone gets its own serial, which
+/// starts at 1 and is increased by one whenever the contents of the zone change.
+#[derive(Default)]
+pub struct ZoneVersions {
+    zones: HashMap<LowerName, ZoneVersion>,
+    reverse: HashMap<LowerName, ZoneVersion>,
+}
+
+impl ZoneVersions {
+    /// Update the contents of the local zone, returns its serial
+    pub fn update(
+        &mut self,
+        zone: &str,
+        records: &Records,
+        ttl_value: TtlValue,
+    ) -> Result<u32, String> {
+        let serial = next_serial(
+            &mut self.zones,
+            LowerName::from_str(zone)?,
+            records,
+            ttl_value,
+        );
+
+        for (reverse, is_family) in [
+            (REVERSE_ZONE_IPV4, IpAddr::is_ipv4 as fn(&IpAddr) -> bool),
+            (REVERSE_ZONE_IPV6, IpAddr::is_ipv6),
+        ] {
+            let mut records = Records::new();
+            for (name, ips) in self.zones.values().flat_map(|version| &version.records) {
+                let ips: Vec<IpAddr> = ips.iter().copied().filter(|ip| is_family(ip)).collect();
+                // Wildcard names do not get PTR records
+                if !name.starts_with("*.") && !ips.is_empty() {
+                    records.insert(name.clone(), ips);
+                }
+            }
+            next_serial(
+                &mut self.reverse,
+                LowerName::from_str(reverse)?,
+                &records,
+                ttl_value,
+            );
+        }
+        Ok(serial)
+    }
+
+    /// Local zones, the reverse ones are not included
+    pub fn zones(&self) -> impl Iterator<Item = &LowerName> {
+        self.zones.keys()
+    }
+
+    /// Number of bytes allocated for the records of the zones
+    pub fn memory_usage(&self) -> usize {
+        self.zones
+            .iter()
+            .chain(self.reverse.iter())
+            .map(|(zone, version)| {
+                let records: usize = version
+                    .records
+                    .iter()
+                    .map(|(name, ips)| name.capacity() + ips.capacity() * mem::size_of::<IpAddr>())
+                    .sum();
+                let table = version.records.capacity() * mem::size_of::<(String, Vec<IpAddr>)>();
+                zone.len() + table + records
+            })
+            .sum()
+    }
+
+    /// Zone file text of the local or reverse zone, `None` if the zone is not known
+    pub fn export(&self, zone: &str, soa: Option<&FeatureDnsSoa>) -> Option<String> {
+        let name = LowerName::from_str(zone).ok()?;
+        if let Some(version) = self.zones.get(&name) {
+            let soa = soa_or_default(soa, version.ttl_value);
+            return Some(zone_file(
+                zone,
+                &version.records,
+                version.ttl_value,
+                &soa,
+                version.serial,
+            ));
+        }
+        let version = self.reverse.get(&name)?;
+        let soa = soa_or_default(soa, version.ttl_value);
+        Some(reverse_zone_file(
+            zone,
+            &version.records,
+            version.ttl_value,
+            &soa,
+            version.serial,
+        ))
+    }
+}
+
+/// Serial for the new contents of the zone, the old one if nothing has changed
+fn next_serial(
+    versions: &mut HashMap<LowerName, ZoneVersion>,
+    zone: LowerName,
+    records: &Records,
+    ttl_value: TtlValue,
+) -> u32 {
+    let previous = versions.get(&zone);
+    if let Some(version) = previous {
+        if version.records == *records && version.ttl_value == ttl_value {
+            return version.serial;
+        }
+    }
+
+    // Serials wrap around as defined by RFC 1982, zero is left out as it is often special cased
+    let serial = previous.map_or(1, |version| version.serial.wrapping_add(1).max(1));
+    versions.insert(
+        zone,
+        ZoneVersion {
+            records: records.clone(),
+            ttl_value,
+            serial,
+        },
+    );
+    serial
+}
+
+/// SOA of the local zones, unless configured the default one with the TTL of the records
+pub(crate) fn soa_or_default(soa: Option<&FeatureDnsSoa>, ttl_value: TtlValue) -> FeatureDnsSoa {
+    soa.cloned().unwrap_or_else(|| FeatureDnsSoa {
+        retry_s: ttl_value.0,
+        negative_ttl_s: ttl_value.0,
+        ..Default::default()
+    })
+}
+
+/// Render the A and AAAA records of a local zone as zone file text (RFC 1035)
+fn zone_file(
+    name: &str,
+    records: &Records,
+    ttl_value: TtlValue,
+    soa: &FeatureDnsSoa,
+    serial: u32,
+) -> String {
+    let ttl = ttl_value.0;
+    let mut text = zone_file_header(name, ttl_value, soa, serial);
+    for (name, ips) in sorted(records) {
+        for ip in ips.iter() {
+            let ty = if ip.is_ipv4() { "A" } else { "AAAA" };
+            text += &format!("{} {ttl} IN {ty} {ip}\n", fqdn(name));
+        }
+    }
+    text
+}
+
+/// Render the PTR records of the addresses as reverse zone file text (RFC 1035)
+fn reverse_zone_file(
+    name: &str,
+    records: &Records,
+    ttl_value: TtlValue,
+    soa: &FeatureDnsSoa,
+    serial: u32,
+) -> String {
+    let ttl = ttl_value.0;
+    let mut text = zone_file_header(name, ttl_value, soa, serial);
+    for (name, ips) in sorted(records) {
+        for ip in ips.iter() {
+            text += &format!("{} {ttl} IN PTR {}\n", reverse_name(ip), fqdn(name));
+        }
+    }
+    text
+}
+
+fn zone_file_header(name: &str, ttl_value: TtlValue, soa: &FeatureDnsSoa, serial: u32) -> String {
+    let ttl = ttl_value.0;
+    let origin = fqdn(name);
+    format!(
+        "$ORIGIN {origin}\n$TTL {ttl}\n{origin} {ttl} IN SOA {} {} {serial} {} {} {} {}\n",
+        fqdn(&soa.primary_ns),
+        fqdn(&soa.responsible),
+        soa.refresh_s,
+        soa.retry_s,
+        soa.expire_s,
+        soa.negative_ttl_s,
+    )
+}
+
+fn sorted(records: &Records) -> Vec<(&String, &Vec<IpAddr>)> {
+    let mut names: Vec<_> = records.iter().collect();
+    names.sort_by(|(a, _), (b, _)| a.cmp(b));
+    names
+}
+
+fn fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_owned()
+    } else {
+        format!("{name}.")
+    }
+}
+
+/// Name of the PTR record of the address
+fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa.")
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: String = ip
+                .octets()
+                .iter()
+                .rev()
+                .map(|byte| format!("{:x}.{:x}.", byte & 0xf, byte >> 4))
+                .collect();
+            format!("{nibbles}ip6.arpa.")
+        }
+    }
+}
+
 /// ForwardZone allows the DNS Server to resolve queries where the client
 /// sends a name to the DNS Server to request the IP address of the requested
 /// host.
@@ -174,7 +409,12 @@ pub(crate) struct ForwardZone {
 }
 
 impl ForwardZone {
-    pub(crate) async fn new(name: &str, ips: &[IpAddr]) -> Result<Self, String> {
+    pub(crate) async fn new(
+        name: &str,
+        ips: &[IpAddr],
+        external: Option<Arc<SocketPool>>,
+        failures: Option<Sender<DnsFailure>>,
+    ) -> Result<Self, String> {
         let mut options = ResolverOpts::default();
         // Some tools and browsers do not accept responses without intermediates preserved
         options.preserve_intermediates = true;
@@ -183,6 +423,11 @@ impl ForwardZone {
 
         options.num_concurrent_reqs = 1;
 
+        // Ask upstreams for responses larger than 512 bytes, needed by DNSSEC and large TXT
+        // records. Responses still truncated are retried over TCP, which the name servers
+        // below are configured for as well.
+        options.edns0 = true;
+
         // We set the number of retries to 0. The retry should be handled by the OS retry mechanism
         options.attempts = 0;
 
@@ -193,6 +438,8 @@ impl ForwardZone {
                 options: Some(options),
                 name_servers: NameServerConfigGroup::from_ips_clear(ips, 53, true),
             },
+            external,
+            failures,
         )
         .await?;
         Ok(ForwardZone { zone })
@@ -359,7 +606,7 @@ mod tests {
         records.insert(String::from("beta.nord"), vec![IpAddr::V4(beta_ipv4)]);
         records.insert(String::from("gamma.nord"), vec![IpAddr::V6(gamma_ipv6)]);
 
-        let zone = AuthoritativeZone::new("nord", &records, TtlValue(60))
+        let zone = AuthoritativeZone::new("nord", &records, TtlValue(60), &Default::default(), 1)
             .await
             .unwrap();
 
@@ -367,4 +614,88 @@ mod tests {
         validate_record(&zone, "beta.nord", Some(beta_ipv4), None).await;
         validate_record(&zone, "gamma.nord", None, Some(gamma_ipv6)).await;
     }
+
+    #[test]
+    fn test_zone_file() {
+        let mut versions = ZoneVersions::default();
+        let mut records = HashMap::new();
+        records.insert(
+            String::from("alpha.nord"),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1)),
+                IpAddr::V6("fd74:656c:696f::1".parse().unwrap()),
+            ],
+        );
+        records.insert(
+            String::from("*.alpha.nord"),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))],
+        );
+
+        assert_eq!(versions.update("nord", &records, TtlValue(60)), Ok(1));
+
+        assert_eq!(
+            versions.export("nord", None).unwrap(),
+            concat!(
+                "$ORIGIN nord.\n",
+                "$TTL 60\n",
+                "nord. 60 IN SOA mesh.nordsec.com. support.nordsec.com. 1 7200 60 1209600 60\n",
+                "*.alpha.nord. 60 IN A 100.64.0.1\n",
+                "alpha.nord. 60 IN A 100.64.0.1\n",
+                "alpha.nord. 60 IN AAAA fd74:656c:696f::1\n",
+            )
+        );
+        // The PTR records are out of zone for nord, so they get zones of their own
+        assert_eq!(
+            versions.export(REVERSE_ZONE_IPV4, None).unwrap(),
+            concat!(
+                "$ORIGIN in-addr.arpa.\n",
+                "$TTL 60\n",
+                "in-addr.arpa. 60 IN SOA mesh.nordsec.com. support.nordsec.com. 1 7200 60 1209600 60\n",
+                "1.0.64.100.in-addr.arpa. 60 IN PTR alpha.nord.\n",
+            )
+        );
+        assert_eq!(
+            versions.export(REVERSE_ZONE_IPV6, None).unwrap(),
+            concat!(
+                "$ORIGIN ip6.arpa.\n",
+                "$TTL 60\n",
+                "ip6.arpa. 60 IN SOA mesh.nordsec.com. support.nordsec.com. 1 7200 60 1209600 60\n",
+                "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.f.6.9.6.c.6.5.6.4.7.d.f.ip6.arpa. 60 IN PTR alpha.nord.\n",
+            )
+        );
+        assert_eq!(versions.export("other", None), None);
+    }
+
+    #[test]
+    fn zone_serials_follow_changes() {
+        let mut versions = ZoneVersions::default();
+        let mut records = HashMap::new();
+        records.insert(
+            String::from("alpha.nord"),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))],
+        );
+        assert_eq!(versions.update("nord", &records, TtlValue(60)), Ok(1));
+        assert_eq!(versions.update("nord", &records, TtlValue(60)), Ok(1));
+
+        records.insert(
+            String::from("beta.nord"),
+            vec![IpAddr::V6("fd74:656c:696f::2".parse().unwrap())],
+        );
+        assert_eq!(versions.update("nord", &records, TtlValue(60)), Ok(2));
+        assert_eq!(versions.update("nord", &records, TtlValue(30)), Ok(3));
+
+        // Reverse zones change only with the addresses of their family
+        let soa = |zone| {
+            let text = versions.export(zone, None).unwrap();
+            text.lines()
+                .nth(2)
+                .unwrap()
+                .split(' ')
+                .nth(6)
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(soa(REVERSE_ZONE_IPV4), "2");
+        assert_eq!(soa(REVERSE_ZONE_IPV6), "3");
+    }
 }
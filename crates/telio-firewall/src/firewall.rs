@@ -8,10 +8,10 @@ use pnet_packet::{
     icmp::{
         destination_unreachable::IcmpCodes, IcmpPacket, IcmpType, IcmpTypes, MutableIcmpPacket,
     },
-    icmpv6::{Icmpv6Packet, Icmpv6Type, Icmpv6Types},
+    icmpv6::{Icmpv6Type, Icmpv6Types},
     ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
     ipv4::{Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
-    ipv6::Ipv6Packet,
+    ipv6::{FragmentPacket, Ipv6Packet, MutableIpv6Packet},
     tcp::{MutableTcpPacket, TcpFlags, TcpPacket},
     udp::UdpPacket,
     Packet,
@@ -21,11 +21,17 @@ use std::{
     fmt::{Debug, Formatter},
     io,
     net::{IpAddr as StdIpAddr, Ipv4Addr as StdIpv4Addr, Ipv6Addr as StdIpv6Addr},
-    sync::{Mutex, RwLock, RwLockReadGuard},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock, RwLockReadGuard,
+    },
     time::Duration,
 };
 
-use telio_model::features::FeatureFirewall;
+use telio_model::{
+    event::ConnectionProtocol,
+    features::{ExitSwitchPolicy, FeatureFirewall, FeatureFirewallRateLimit, FirewallPolicy},
+};
 use telio_network_monitors::monitor::LOCAL_ADDRS_CACHE;
 use telio_utils::{
     lru_cache::{Entry, LruCache},
@@ -33,16 +39,27 @@ use telio_utils::{
 };
 use tracing::error;
 
+#[cfg(test)]
+use sn_fake_clock::FakeClock as Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
 use telio_crypto::PublicKey;
-use telio_utils::{telio_log_debug, telio_log_trace, telio_log_warn};
+use telio_utils::{telio_log_debug, telio_log_hot, telio_log_info, telio_log_warn};
+
+use crate::packet::{self, PacketHeaders, Transport};
 
 /// HashSet type used internally by firewall and returned by get_peer_whitelist
 pub type HashSet<V> = rustc_hash::FxHashSet<V>;
 /// HashMap type used internally by firewall and returned by get_port_whitelist
 pub type HashMap<K, V> = rustc_hash::FxHashMap<K, V>;
 
+/// Called with the peer, protocol and local port of every new inbound connection accepted
+pub type InboundConnectionCallback = Box<dyn Fn(PublicKey, ConnectionProtocol, u16) + Send + Sync>;
+
 const LRU_CAPACITY: usize = 4096; // Max entries to keep (sepatately for TCP, UDP, and others)
 const LRU_TIMEOUT: u64 = 120_000; // 2min (https://datatracker.ietf.org/doc/html/rfc4787#section-4.3)
+const FRAGMENT_TIMEOUT: u64 = 30_000; // 30s, same as the reassembly timeout of Linux
 
 const TCP_FIRST_PKT_MASK: u8 = TcpFlags::SYN | TcpFlags::ACK;
 
@@ -65,34 +82,17 @@ pub const FILE_SEND_PORT: u16 = 49111;
 
 trait Icmp: Sized {
     const BLOCKED_TYPES: [u8; 4];
-    fn new(payload: &[u8]) -> Option<Self>;
-    fn get_type(&self) -> u8;
+    const ECHO_REQUEST: u8;
 }
 
 impl Icmp for IcmpType {
-    fn new(payload: &[u8]) -> Option<Self> {
-        // We only need the type
-        IcmpPacket::new(payload).map(|p| p.get_icmp_type())
-    }
-
-    fn get_type(&self) -> u8 {
-        self.0
-    }
-
     const BLOCKED_TYPES: [u8; 4] = ICMP_BLOCKED_TYPES;
+    const ECHO_REQUEST: u8 = IcmpTypes::EchoRequest.0;
 }
 
 impl Icmp for Icmpv6Type {
-    fn new(payload: &[u8]) -> Option<Self> {
-        // We only need the type
-        Icmpv6Packet::new(payload).map(|p| p.get_icmpv6_type())
-    }
-
-    fn get_type(&self) -> u8 {
-        self.0
-    }
-
     const BLOCKED_TYPES: [u8; 4] = ICMPV6_BLOCKED_TYPES;
+    const ECHO_REQUEST: u8 = Icmpv6Types::EchoRequest.0;
 }
 
 trait IpPacket<'a>: Sized + Debug + Packet {
@@ -104,6 +104,69 @@ trait IpPacket<'a>: Sized + Debug + Packet {
     fn get_destination(&self) -> Self::Addr;
     fn get_source(&self) -> Self::Addr;
     fn try_from(buffer: &'a [u8]) -> Option<Self>;
+    fn get_fragment(&self) -> Option<Fragment>;
+    /// Copy of the first fragment with the fragmentation removed from the headers, `None` if
+    /// the fragment can be processed as is
+    fn unfragmented(&self) -> Option<Vec<u8>>;
+}
+
+/// Fragmentation details of an IP packet
+#[derive(Debug)]
+struct Fragment {
+    /// Identification shared by all the fragments of the original packet
+    id: u32,
+    /// Protocol of the original packet
+    proto: u8,
+    /// Offset of the fragment in the original payload, in 8 byte units
+    offset: u16,
+    /// Bytes of the original payload carried by the fragment
+    len: usize,
+    /// Whether more fragments follow
+    more: bool,
+}
+
+impl Fragment {
+    /// The first fragment is the only one carrying the transport header
+    fn is_first(&self) -> bool {
+        self.offset == 0
+    }
+
+    /// Fragments which could overwrite the transport header checked with the first fragment
+    /// and first fragments too short to carry the whole header are dropped (RFC 1858)
+    fn is_malicious(&self) -> bool {
+        let header = match IpNextHeaderProtocol(self.proto) {
+            IpNextHeaderProtocols::Tcp => TcpPacket::minimum_packet_size(),
+            IpNextHeaderProtocols::Udp => UdpPacket::minimum_packet_size(),
+            IpNextHeaderProtocols::Icmp | IpNextHeaderProtocols::Icmpv6 => 8,
+            _ => 0,
+        };
+        if self.is_first() {
+            self.more && self.len < header
+        } else {
+            usize::from(self.offset) * 8 < header
+        }
+    }
+}
+
+/// Offset of the IPv6 Fragment header in the payload, behind the extension headers which
+/// may precede it
+fn ipv6_fragment_header(ip: &Ipv6Packet) -> Option<usize> {
+    let payload = ip.payload();
+    let mut next = ip.get_next_header();
+    let mut offset = 0;
+    loop {
+        match next {
+            IpNextHeaderProtocols::Ipv6Frag => return Some(offset),
+            IpNextHeaderProtocols::Hopopt
+            | IpNextHeaderProtocols::Ipv6Route
+            | IpNextHeaderProtocols::Ipv6Opts => {
+                let (header, len) = (payload.get(offset)?, payload.get(offset + 1)?);
+                next = IpNextHeaderProtocol(*header);
+                offset += (usize::from(*len) + 1) * 8;
+            }
+            _ => return None,
+        }
+    }
 }
 
 impl<'a> IpPacket<'a> for Ipv4Packet<'a> {
@@ -135,6 +198,26 @@ impl<'a> IpPacket<'a> for Ipv4Packet<'a> {
     fn try_from(buffer: &'a [u8]) -> Option<Self> {
         Self::new(buffer)
     }
+
+    fn get_fragment(&self) -> Option<Fragment> {
+        let more = self.get_flags() & Ipv4Flags::MoreFragments != 0;
+        let offset = self.get_fragment_offset();
+        if !more && offset == 0 {
+            return None;
+        }
+        Some(Fragment {
+            id: u32::from(self.get_identification()),
+            proto: self.get_next_level_protocol().0,
+            offset,
+            len: self.payload().len(),
+            more,
+        })
+    }
+
+    fn unfragmented(&self) -> Option<Vec<u8>> {
+        // The transport header directly follows the IPv4 header of the first fragment
+        None
+    }
 }
 
 impl<'a> IpPacket<'a> for Ipv6Packet<'a> {
@@ -166,7 +249,40 @@ impl<'a> IpPacket<'a> for Ipv6Packet<'a> {
     fn try_from(buffer: &'a [u8]) -> Option<Self> {
         Self::new(buffer)
     }
+
+    fn get_fragment(&self) -> Option<Fragment> {
+        let start = ipv6_fragment_header(self)?;
+        let header = FragmentPacket::new(self.payload().get(start..)?)?;
+        let offset_with_flags = header.get_fragment_offset_with_flags();
+        Some(Fragment {
+            id: header.get_id(),
+            proto: header.get_next_header().0,
+            offset: offset_with_flags >> 3,
+            len: header.payload().len(),
+            more: offset_with_flags & 0b1 != 0,
+        })
+    }
+
+    fn unfragmented(&self) -> Option<Vec<u8>> {
+        // Drop the fragment header along with the extension headers before it,
+        // so the transport header follows the IPv6 header
+        let start = ipv6_fragment_header(self)?;
+        let header = FragmentPacket::new(self.payload().get(start..)?)?;
+        let upper = self
+            .payload()
+            .get(start + FragmentPacket::minimum_packet_size()..)?;
+        let mut buffer = self
+            .packet()
+            .get(..Ipv6Packet::minimum_packet_size())?
+            .to_vec();
+        buffer.extend_from_slice(upper);
+        let mut ip = MutableIpv6Packet::new(&mut buffer)?;
+        ip.set_next_header(header.get_next_header());
+        ip.set_payload_length(upper.len().try_into().ok()?);
+        Some(buffer)
+    }
 }
+
 /// Firewall trait.
 #[cfg_attr(any(test, feature = "mockall"), mockall::automock)]
 pub trait Firewall {
@@ -200,6 +316,10 @@ pub trait Firewall {
     /// Removes vpn peer
     fn remove_vpn_peer(&self);
 
+    /// Finishes the switch to the vpn peer once it is connected, until then its traffic is
+    /// handled according to the exit switch policy
+    fn complete_exit_switch(&self, vpn_peer: PublicKey);
+
     /// For new connections it opens a pinhole for incoming connection
     /// If connection is already cached, it resets its timer and extends its lifetime
     /// Only returns false for invalid or not ipv4 packets
@@ -223,6 +343,40 @@ pub trait Firewall {
 
     /// Saves local node Ip address into firewall object
     fn set_ip_addresses(&self, ip_addrs: Vec<StdIpAddr>);
+
+    /// Forgets all tracked connections with the peer, e.g. when it is replaced by a peer
+    /// with a different public key
+    fn remove_peer_connections(&self, peer: &PublicKey);
+
+    /// Returns the counters of packets dropped by the rate limits, per peer
+    fn get_rate_limit_stats(&self) -> HashMap<PublicKey, RateLimitStats>;
+
+    /// Returns the number of bytes allocated for the connection tracking
+    fn get_conntrack_memory_usage(&self) -> usize;
+
+    /// Returns the counters of packets which would have been dropped in monitor-only mode
+    fn get_monitor_stats(&self) -> MonitorStats;
+
+    /// Returns the number of packets dropped, inbound and outbound, per peer
+    fn get_dropped_packets(&self) -> HashMap<PublicKey, u64>;
+}
+
+/// Counters of inbound packets of a single peer dropped due to rate limiting
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    /// Dropped TCP connection attempts
+    pub dropped_connections: u64,
+    /// Dropped ICMP echo requests
+    pub dropped_echo_requests: u64,
+}
+
+/// Counters of packets which the rules would drop, but which were forwarded in monitor-only mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MonitorStats {
+    /// Inbound packets which would have been dropped
+    pub would_drop_inbound: u64,
+    /// Outbound packets which would have been dropped
+    pub would_drop_outbound: u64,
 }
 
 /// Possible permissions of the peer
@@ -271,6 +425,9 @@ struct Whitelist {
 
     /// Public key of vpn peer
     vpn_peer: Option<PublicKey>,
+
+    /// Start of the switch to `vpn_peer` from another vpn peer, until it is connected
+    exit_switch: Option<Instant>,
 }
 
 impl Whitelist {
@@ -301,6 +458,41 @@ pub struct StatefullFirewall {
     ip_addresses: RwLock<Vec<StdIpAddr>>,
     /// Custom IPv4 range to check against
     exclude_ip_range: Option<Ipv4Net>,
+    /// Per peer rate limits of new inbound connections and ICMP echo requests
+    rate_limiter: Option<RateLimiter>,
+    /// Action for inbound connections from peers which are not whitelisted
+    inbound_policy: FirewallPolicy,
+    /// Action for outbound connections to peers which are not whitelisted
+    outbound_policy: FirewallPolicy,
+    /// Verdicts of the first fragments, applied to the rest of the fragments of the same packet
+    fragments: Mutex<LruCache<FragmentKey, bool>>,
+    /// Whether to drop all fragmented packets
+    drop_fragments: bool,
+    /// Reports new inbound connections to the app
+    on_inbound_connection: Option<InboundConnectionCallback>,
+    /// Whether to forward the packets the rules would drop, only counting them
+    monitor_only: bool,
+    /// Inbound packets which would have been dropped in monitor-only mode
+    would_drop_inbound: AtomicU64,
+    /// Outbound packets which would have been dropped in monitor-only mode
+    would_drop_outbound: AtomicU64,
+    /// Packets dropped per peer, the write lock is taken only for the first drop of a peer
+    dropped: RwLock<HashMap<PublicKey, AtomicU64>>,
+    /// Handling of the vpn peer traffic while switching to it from another vpn peer
+    exit_switch_policy: ExitSwitchPolicy,
+    /// How long the vpn peer traffic is allowed with the grace exit switch policy
+    exit_switch_grace: Duration,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+struct FragmentKey {
+    // This public key refers to source peer for inbound fragments and
+    // destination peer for outbound fragments
+    pubkey: PublicKey,
+    src: IpAddr,
+    dst: IpAddr,
+    proto: u8,
+    id: u32,
 }
 
 #[derive(Debug)]
@@ -437,6 +629,121 @@ macro_rules! unwrap_lock_or_return {
     };
 }
 
+/// Inbound packets which are rate limited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RateLimited {
+    Connection,
+    EchoRequest,
+}
+
+impl RateLimited {
+    fn classify<'a, P: IpPacket<'a>>(headers: &PacketHeaders) -> Option<Self> {
+        match headers.transport {
+            Transport::Tcp { flags, .. } if flags & TCP_FIRST_PKT_MASK == TcpFlags::SYN => {
+                Some(Self::Connection)
+            }
+            Transport::Icmp { icmp_type, .. } if icmp_type == P::Icmp::ECHO_REQUEST => {
+                Some(Self::EchoRequest)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Rate limit following the generic cell rate algorithm
+#[derive(Debug)]
+struct RateLimit {
+    /// Time between packets at the sustained rate
+    interval: Duration,
+    /// How far ahead of the sustained rate a burst may get
+    tolerance: Duration,
+}
+
+impl RateLimit {
+    fn new(per_s: u32, burst: u32) -> Self {
+        let interval = Duration::from_secs(1) / per_s.max(1);
+        Self {
+            interval,
+            tolerance: interval * burst.max(1).saturating_sub(1),
+        }
+    }
+
+    /// Check the packet against the theoretical arrival time of the next one, updating it if
+    /// the packet is allowed
+    fn allow(&self, arrival: &mut Option<Instant>, now: Instant) -> bool {
+        let arrival_time = match *arrival {
+            Some(t) if t > now => t,
+            _ => now,
+        };
+        if arrival_time > now + self.tolerance {
+            return false;
+        }
+        *arrival = Some(arrival_time + self.interval);
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct PeerRateLimits {
+    connection: Option<Instant>,
+    echo_request: Option<Instant>,
+}
+
+/// Per peer rate limits of inbound packets which are costly to handle
+struct RateLimiter {
+    connection: RateLimit,
+    echo_request: RateLimit,
+    peers: Mutex<LruCache<PublicKey, PeerRateLimits>>,
+    stats: Mutex<HashMap<PublicKey, RateLimitStats>>,
+}
+
+impl RateLimiter {
+    fn new(feature: FeatureFirewallRateLimit, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            connection: RateLimit::new(
+                feature.new_connections_per_s,
+                feature.new_connections_burst,
+            ),
+            echo_request: RateLimit::new(feature.icmp_echo_per_s, feature.icmp_echo_burst),
+            peers: Mutex::new(LruCache::new(ttl, capacity)),
+            stats: Mutex::new(HashMap::default()),
+        }
+    }
+
+    fn allow(&self, peer: PublicKey, kind: RateLimited) -> bool {
+        let now = Instant::now();
+        let allowed = {
+            let mut peers = unwrap_lock_or_return!(self.peers.lock(), false);
+            if peers.get_mut(&peer).is_none() {
+                peers.insert(peer, PeerRateLimits::default());
+            }
+            let limits = unwrap_option_or_return!(peers.get_mut(&peer), false);
+            match kind {
+                RateLimited::Connection => self.connection.allow(&mut limits.connection, now),
+                RateLimited::EchoRequest => self.echo_request.allow(&mut limits.echo_request, now),
+            }
+        };
+
+        if !allowed {
+            let mut stats = unwrap_lock_or_return!(self.stats.lock(), false);
+            let stats = stats.entry(peer).or_default();
+            if *stats == RateLimitStats::default() {
+                telio_log_warn!("Peer {peer:?} exceeded the firewall rate limits");
+            }
+            match kind {
+                RateLimited::Connection => stats.dropped_connections += 1,
+                RateLimited::EchoRequest => stats.dropped_echo_requests += 1,
+            }
+        }
+        allowed
+    }
+
+    fn remove_peer(&self, peer: &PublicKey) {
+        unwrap_lock_or_return!(self.peers.lock()).remove(peer);
+        unwrap_lock_or_return!(self.stats.lock()).remove(peer);
+    }
+}
+
 impl StatefullFirewall {
     /// Constructs firewall with default timeout (2 mins) and capacity (4096 entries).
     pub fn new(use_ipv6: bool, feature: FeatureFirewall) -> Self {
@@ -460,12 +767,152 @@ impl StatefullFirewall {
             icmp: Mutex::new(LruCache::new(ttl, capacity)),
             whitelist: RwLock::new(Whitelist::default()),
             allow_ipv6: use_ipv6,
-            record_whitelisted: feature.boringtun_reset_conns || feature.neptun_reset_conns,
+            // Connections of whitelisted peers have to be tracked to tell the new ones apart
+            record_whitelisted: feature.boringtun_reset_conns
+                || feature.neptun_reset_conns
+                || feature.connection_notifications,
             ip_addresses: RwLock::new(Vec::<StdIpAddr>::new()),
             exclude_ip_range: feature.exclude_private_ip_range,
+            rate_limiter: feature
+                .rate_limit
+                .map(|rate_limit| RateLimiter::new(rate_limit, ttl, capacity)),
+            inbound_policy: feature.inbound_policy,
+            outbound_policy: feature.outbound_policy,
+            fragments: Mutex::new(LruCache::new(
+                Duration::from_millis(FRAGMENT_TIMEOUT),
+                capacity,
+            )),
+            drop_fragments: feature.drop_fragments,
+            on_inbound_connection: None,
+            monitor_only: feature.monitor_only,
+            would_drop_inbound: AtomicU64::new(0),
+            would_drop_outbound: AtomicU64::new(0),
+            dropped: RwLock::new(HashMap::default()),
+            exit_switch_policy: feature.exit_switch_policy,
+            exit_switch_grace: Duration::from_secs(feature.exit_switch_grace_s.into()),
         }
     }
 
+    /// Sets the callback reporting new inbound connections, which are only tracked when
+    /// `connection_notifications` is enabled
+    pub fn with_inbound_connection_callback(mut self, callback: InboundConnectionCallback) -> Self {
+        self.on_inbound_connection = Some(callback);
+        self
+    }
+
+    fn notify_inbound_connection(&self, peer: PublicKey, protocol: ConnectionProtocol, port: u16) {
+        if let Some(callback) = &self.on_inbound_connection {
+            telio_log_debug!(
+                "New inbound {:?} connection from {:?} to port {}",
+                protocol,
+                peer,
+                port
+            );
+            callback(peer, protocol, port);
+        }
+    }
+
+    /// Whether the traffic of `peer` is dropped as the exit node switch to it is not finished
+    fn is_blocked_by_exit_switch(&self, whitelist: &Whitelist, peer: &PublicKey) -> bool {
+        let Some(started) = whitelist.exit_switch else {
+            return false;
+        };
+        if whitelist.vpn_peer.as_ref() != Some(peer) {
+            return false;
+        }
+        match self.exit_switch_policy {
+            ExitSwitchPolicy::Block => true,
+            ExitSwitchPolicy::Grace => started.elapsed() >= self.exit_switch_grace,
+            ExitSwitchPolicy::Allow => false,
+        }
+    }
+
+    /// In monitor-only mode packets which would be dropped are counted and forwarded anyway
+    fn enforce(&self, public_key: &[u8; 32], verdict: bool, inbound: bool) -> bool {
+        if verdict {
+            return true;
+        }
+        if !self.monitor_only {
+            self.count_dropped(PublicKey(*public_key));
+            return false;
+        }
+
+        let (counter, other) = if inbound {
+            (&self.would_drop_inbound, &self.would_drop_outbound)
+        } else {
+            (&self.would_drop_outbound, &self.would_drop_inbound)
+        };
+        if counter.fetch_add(1, Ordering::Relaxed) == 0 && other.load(Ordering::Relaxed) == 0 {
+            telio_log_info!("Firewall in monitor-only mode would drop its first packet");
+        }
+        true
+    }
+
+    fn count_dropped(&self, peer: PublicKey) {
+        if let Some(count) = unwrap_lock_or_return!(self.dropped.read()).get(&peer) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        unwrap_lock_or_return!(self.dropped.write())
+            .entry(peer)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Virtual reassembly of fragmented packets. Only the first fragment carries the transport
+    /// header, so it is processed as if it was not fragmented and its verdict is applied to the
+    /// rest of the fragments. Fragments arriving before the first one are let through, as the
+    /// original packet cannot be reassembled without the first fragment, which is checked.
+    fn process_ip_fragments<'a, P: IpPacket<'a>>(
+        &self,
+        public_key: &[u8; 32],
+        buffer: &'a [u8],
+        process: impl FnOnce(&[u8]) -> bool,
+    ) -> bool {
+        let ip = unwrap_option_or_return!(P::try_from(buffer), false);
+        let Some(fragment) = ip.get_fragment() else {
+            return process(buffer);
+        };
+
+        if self.drop_fragments {
+            telio_log_hot!("Dropping fragmented IP packet: {:?}", ip);
+            return false;
+        }
+
+        let key = FragmentKey {
+            pubkey: PublicKey(*public_key),
+            src: ip.get_source().into(),
+            dst: ip.get_destination().into(),
+            proto: fragment.proto,
+            id: fragment.id,
+        };
+
+        if fragment.is_malicious() {
+            telio_log_hot!("Dropping overlapping or tiny IP fragment: {:?}", ip);
+            if fragment.is_first() {
+                unwrap_lock_or_return!(self.fragments.lock(), false).insert(key, false);
+            }
+            return false;
+        }
+
+        if !fragment.is_first() {
+            let verdict = unwrap_lock_or_return!(self.fragments.lock(), false)
+                .get(&key)
+                .copied();
+            telio_log_hot!("Fragment {:?} follows verdict {:?}", key, verdict);
+            return verdict.unwrap_or(true);
+        }
+
+        let verdict = match ip.unfragmented() {
+            Some(unfragmented) => process(&unfragmented),
+            None => process(buffer),
+        };
+        if fragment.more {
+            unwrap_lock_or_return!(self.fragments.lock(), verdict).insert(key, verdict);
+        }
+        verdict
+    }
+
     fn process_outbound_ip_packet<'a, P: IpPacket<'a>>(
         &self,
         public_key: &[u8; 32],
@@ -478,10 +925,18 @@ impl StatefullFirewall {
         // whitelist read-lock scope
         let whitelist = unwrap_lock_or_return!(self.whitelist.read(), false);
 
+        if self.is_blocked_by_exit_switch(&whitelist, &peer) {
+            telio_log_hot!(
+                "Outbound IP packet is for switched exit node, dropping: {:?}",
+                ip
+            );
+            return false;
+        }
+
         // If peer is whitelisted - allow immediately
         #[allow(index_access_check)]
         if whitelist.peer_whitelists[Permissions::IncomingConnections].contains(&peer) {
-            telio_log_trace!(
+            telio_log_hot!(
                 "Outbound IP packet is for whitelisted peer, forwarding: {:?}",
                 ip
             );
@@ -503,27 +958,47 @@ impl StatefullFirewall {
         }
 
         if !ip.check_valid() {
-            telio_log_trace!("Outbound IP packet is not valid, dropping: {:?}", ip);
+            telio_log_hot!("Outbound IP packet is not valid, dropping: {:?}", ip);
             return false;
         }
+        let headers = match packet::parse(ip.packet()) {
+            Ok(headers) => headers,
+            Err(error) => {
+                telio_log_hot!("Outbound IP packet is malformed, dropping: {}", error);
+                return false;
+            }
+        };
 
-        match proto {
-            IpNextHeaderProtocols::Udp => {
+        // With default-deny only replies to connections initiated by the peer may leave,
+        // unless it is the VPN server or a peer we route through
+        #[allow(index_access_check)]
+        if self.outbound_policy == FirewallPolicy::Deny
+            && whitelist.vpn_peer != Some(peer)
+            && !whitelist.peer_whitelists[Permissions::RoutingConnections].contains(&peer)
+            && !self.is_reply_to_remote(peer, &ip, &headers)
+        {
+            telio_log_hot!(
+                "Outbound policy denies packet, dropping: {:?} {:?}",
+                ip,
+                peer
+            );
+            return false;
+        }
+
+        match headers.transport {
+            Transport::Udp { .. } => {
                 self.handle_outbound_udp(peer, &ip);
             }
-            IpNextHeaderProtocols::Tcp => {
+            Transport::Tcp { .. } => {
                 self.handle_outbound_tcp(peer, &ip);
             }
-            IpNextHeaderProtocols::Icmp => {
-                self.handle_outbound_icmp(peer, &ip);
-            }
-            IpNextHeaderProtocols::Icmpv6 => {
+            Transport::Icmp { .. } => {
                 self.handle_outbound_icmp(peer, &ip);
             }
-            _ => (),
+            Transport::Fragment | Transport::Other => (),
         };
 
-        telio_log_trace!("Accepting packet {:?} {:?}", ip, peer);
+        telio_log_hot!("Accepting packet {:?} {:?}", ip, peer);
         true
     }
 
@@ -538,10 +1013,18 @@ impl StatefullFirewall {
 
         let whitelist = unwrap_lock_or_return!(self.whitelist.read(), false);
 
+        if self.is_blocked_by_exit_switch(&whitelist, &peer) {
+            telio_log_hot!(
+                "Inbound IP packet is from switched exit node, dropping: {:?}",
+                ip
+            );
+            return false;
+        }
+
         // Fasttrack, if peer is whitelisted - skip any conntrack and allow immediately
         if let Some(vpn_peer) = whitelist.vpn_peer {
             if vpn_peer == peer {
-                telio_log_trace!("Inbound IP packet is for vpn peer, forwarding: {:?}", ip);
+                telio_log_hot!("Inbound IP packet is for vpn peer, forwarding: {:?}", ip);
                 // We still need to track the state of TCP and UDP connections
                 if self.record_whitelisted {
                     let check_connection_policy = |_pubkey, _port| PacketAction::HandleLocally;
@@ -562,38 +1045,48 @@ impl StatefullFirewall {
         }
 
         if !ip.check_valid() {
-            telio_log_trace!("Inbound IP packet is not valid, dropping: {:?}", ip);
+            telio_log_hot!("Inbound IP packet is not valid, dropping: {:?}", ip);
             return false;
         }
+        let headers = match packet::parse(ip.packet()) {
+            Ok(headers) => headers,
+            Err(error) => {
+                telio_log_hot!("Inbound IP packet is malformed, dropping: {}", error);
+                return false;
+            }
+        };
 
         let check_connection_policy = |pubkey, port| self.decide_connection_handling(pubkey, port);
 
         // Check packet policy first
-        match self.decide_packet_handling(
-            peer,
-            From::<IpAddr>::from(ip.get_destination().into()),
-            whitelist,
-        ) {
+        match self.decide_packet_handling(peer, headers.destination, whitelist) {
             PacketAction::Drop => {
-                telio_log_trace!("Dropping packet {:?} {:?}", ip.get_source().into(), peer);
+                telio_log_hot!("Dropping packet {:?} {:?}", headers.source, peer);
                 return false;
             }
             PacketAction::HandleLocally => (),
             PacketAction::PassThrough => {
-                telio_log_trace!("Accepting packet {:?} {:?}", ip.get_source().into(), peer);
+                telio_log_hot!("Accepting packet {:?} {:?}", headers.source, peer);
                 return true;
             }
         }
 
-        match proto {
-            IpNextHeaderProtocols::Udp => {
-                self.handle_inbound_udp(check_connection_policy, &peer, &ip)
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if let Some(kind) = RateLimited::classify::<P>(&headers) {
+                if !rate_limiter.allow(peer, kind) {
+                    telio_log_hot!("Dropping rate limited packet {:?} {:?}", ip, peer);
+                    return false;
+                }
             }
-            IpNextHeaderProtocols::Tcp => {
-                self.handle_inbound_tcp(check_connection_policy, &peer, &ip)
+        }
+
+        match headers.transport {
+            Transport::Udp { .. } => self.handle_inbound_udp(check_connection_policy, &peer, &ip),
+            Transport::Tcp { .. } => self.handle_inbound_tcp(check_connection_policy, &peer, &ip),
+            Transport::Icmp { .. } if headers.protocol == IpNextHeaderProtocols::Icmp.0 => {
+                self.handle_inbound_icmp(peer, &ip)
             }
-            IpNextHeaderProtocols::Icmp => self.handle_inbound_icmp(peer, &ip),
-            IpNextHeaderProtocols::Icmpv6 if self.allow_ipv6 => self.handle_inbound_icmp(peer, &ip),
+            Transport::Icmp { .. } if self.allow_ipv6 => self.handle_inbound_icmp(peer, &ip),
             _ => false,
         }
     }
@@ -622,7 +1115,7 @@ impl StatefullFirewall {
                     is_remote_initiated: false,
                     last_out_pkg_chunk: Some(last_chunk),
                 };
-                telio_log_trace!("Inserting new UDP conntrack entry {:?}", e.key());
+                telio_log_hot!("Inserting new UDP conntrack entry {:?}", e.key());
                 e.insert(conninfo);
             }
             Entry::Occupied(mut o) => {
@@ -648,7 +1141,7 @@ impl StatefullFirewall {
         let mut tcp_cache = unwrap_lock_or_return!(self.tcp.lock());
 
         if flags & TCP_FIRST_PKT_MASK == TcpFlags::SYN {
-            telio_log_trace!("Inserting TCP conntrack entry {:?}", key.link);
+            telio_log_hot!("Inserting TCP conntrack entry {:?}", key.link);
             tcp_cache.insert(
                 key,
                 TcpConnectionInfo {
@@ -659,10 +1152,10 @@ impl StatefullFirewall {
                 },
             );
         } else if flags & TcpFlags::RST == TcpFlags::RST {
-            telio_log_trace!("Removing TCP conntrack entry {:?}", key);
+            telio_log_hot!("Removing TCP conntrack entry {:?}", key);
             tcp_cache.remove(&key);
         } else if flags & TcpFlags::FIN == TcpFlags::FIN {
-            telio_log_trace!("Connection {:?} closing", key);
+            telio_log_hot!("Connection {:?} closing", key);
             if let Entry::Occupied(mut e) = tcp_cache.entry(key, false) {
                 let TcpConnectionInfo { tx_alive, .. } = e.get_mut();
                 *tx_alive = false;
@@ -675,7 +1168,7 @@ impl StatefullFirewall {
         if let Ok(key) = Self::build_icmp_key(peer, ip, false) {
             // If key already exists, dont change the value, just update timer with entry
             if let Entry::Vacant(e) = icmp_cache.entry(key, true) {
-                telio_log_trace!("Inserting new ICMP conntrack entry {:?}", e.key());
+                telio_log_hot!("Inserting new ICMP conntrack entry {:?}", e.key());
                 e.insert(());
             }
         }
@@ -701,7 +1194,7 @@ impl StatefullFirewall {
                 let connection_info = occ.get();
                 let key = occ.key();
 
-                telio_log_trace!(
+                telio_log_hot!(
                     "Matched UDP conntrack entry {:?} {:?}",
                     key,
                     connection_info
@@ -710,7 +1203,7 @@ impl StatefullFirewall {
                 if connection_info.is_remote_initiated {
                     match decide_connection_handling(*peer, local_port) {
                         PacketAction::Drop => {
-                            telio_log_trace!("Removing UDP conntrack entry {:?}", key);
+                            telio_log_hot!("Removing UDP conntrack entry {:?}", key);
                             occ.remove();
                             return false;
                         }
@@ -723,25 +1216,26 @@ impl StatefullFirewall {
 
                 match decide_connection_handling(*peer, local_port) {
                     PacketAction::PassThrough => {
-                        telio_log_trace!("Accepting UDP packet {:?} {:?}", key, peer);
+                        telio_log_hot!("Accepting UDP packet {:?} {:?}", key, peer);
                         return true;
                     }
                     PacketAction::Drop => {
-                        telio_log_trace!("Dropping UDP packet {:?} {:?}", key, peer);
+                        telio_log_hot!("Dropping UDP packet {:?} {:?}", key, peer);
                         return false;
                     }
                     PacketAction::HandleLocally => (),
                 }
 
-                telio_log_trace!("Updating UDP conntrack entry {:?} {:?}", key, peer,);
+                telio_log_hot!("Updating UDP conntrack entry {:?} {:?}", key, peer,);
                 vacc.insert(UdpConnectionInfo {
                     is_remote_initiated: true,
                     last_out_pkg_chunk: None,
                 });
+                self.notify_inbound_connection(*peer, ConnectionProtocol::Udp, local_port);
             }
         }
 
-        telio_log_trace!("Accepting UDP packet {:?} {:?}", ip, peer);
+        telio_log_hot!("Accepting UDP packet {:?} {:?}", ip, peer);
         true
     }
 
@@ -757,19 +1251,19 @@ impl StatefullFirewall {
             pubkey: *pubkey,
         };
         let local_port = key.link.local_port;
-        telio_log_trace!("Processing TCP packet with {:?}", key);
+        telio_log_hot!("Processing TCP packet with {:?}", key);
 
         let mut cache = unwrap_lock_or_return!(self.tcp.lock(), false);
         // Dont update last access time in tcp, as it is handled by tcp flags
         match cache.entry(key, false) {
             Entry::Occupied(mut occ) => {
                 let connection_info = occ.get_mut();
-                telio_log_trace!("Matched conntrack entry {:?} {:?}", pubkey, connection_info);
+                telio_log_hot!("Matched conntrack entry {:?} {:?}", pubkey, connection_info);
 
                 if connection_info.conn_remote_initiated {
                     match is_conn_policy_compliant(*pubkey, local_port) {
                         PacketAction::Drop => {
-                            telio_log_trace!("Removing TCP conntrack entry {:?}", pubkey);
+                            telio_log_hot!("Removing TCP conntrack entry {:?}", pubkey);
                             occ.remove();
                             return false;
                         }
@@ -781,25 +1275,21 @@ impl StatefullFirewall {
                     let flags = pkt.get_flags();
                     let mut is_conn_reset = false;
                     if flags & TcpFlags::RST == TcpFlags::RST {
-                        telio_log_trace!(
+                        telio_log_hot!(
                             "Removing TCP conntrack entry with {:?} for {:?}",
                             pubkey,
                             local_port
                         );
                         is_conn_reset = true;
                     } else if (flags & TcpFlags::FIN) == TcpFlags::FIN {
-                        telio_log_trace!(
-                            "Connection with {:?} for {:?} closing",
-                            pubkey,
-                            local_port
-                        );
+                        telio_log_hot!("Connection with {:?} for {:?} closing", pubkey, local_port);
                         connection_info.rx_alive = false;
                     } else if !connection_info.tx_alive
                         && !connection_info.rx_alive
                         && !connection_info.conn_remote_initiated
                     {
                         if (flags & TcpFlags::ACK) == TcpFlags::ACK {
-                            telio_log_trace!(
+                            telio_log_hot!(
                                 "Removing TCP conntrack entry with {:?} for {:?}",
                                 pubkey,
                                 local_port
@@ -820,7 +1310,7 @@ impl StatefullFirewall {
                     if is_conn_reset {
                         occ.remove();
                     }
-                    telio_log_trace!("Accepting TCP packet {:?} {:?}", ip, pubkey);
+                    telio_log_hot!("Accepting TCP packet {:?} {:?}", ip, pubkey);
                     return true;
                 }
             }
@@ -828,11 +1318,11 @@ impl StatefullFirewall {
                 let key = vacc.key();
                 match is_conn_policy_compliant(*pubkey, local_port) {
                     PacketAction::PassThrough => {
-                        telio_log_trace!("Accepting UDP packet {:?} {:?}", ip, pubkey);
+                        telio_log_hot!("Accepting UDP packet {:?} {:?}", ip, pubkey);
                         return true;
                     }
                     PacketAction::Drop => {
-                        telio_log_trace!("Dropping UDP packet {:?} {:?}", key, pubkey);
+                        telio_log_hot!("Dropping UDP packet {:?} {:?}", key, pubkey);
                         return false;
                     }
                     PacketAction::HandleLocally => (),
@@ -847,19 +1337,24 @@ impl StatefullFirewall {
                             conn_remote_initiated: true,
                             next_seq: Some(pkt.get_sequence() + 1),
                         };
-                        telio_log_trace!(
+                        telio_log_hot!(
                             "Updating TCP conntrack entry {:?} {:?} {:?}",
                             key,
                             pubkey,
                             conn_info
                         );
                         vacc.insert(conn_info);
+                        self.notify_inbound_connection(
+                            *pubkey,
+                            ConnectionProtocol::Tcp,
+                            local_port,
+                        );
                     }
                 }
             }
         }
 
-        telio_log_trace!("Accepting TCP packet {:?} {:?}", ip, pubkey);
+        telio_log_hot!("Accepting TCP packet {:?} {:?}", ip, pubkey);
         true
     }
 
@@ -868,11 +1363,13 @@ impl StatefullFirewall {
         let whitelist = unwrap_lock_or_return!(self.whitelist.read(), false);
 
         #[allow(index_access_check)]
-        if whitelist.peer_whitelists[Permissions::IncomingConnections].contains(&peer) {
-            telio_log_trace!("Accepting ICMP packet {:?} {:?}", ip, peer);
+        if whitelist.peer_whitelists[Permissions::IncomingConnections].contains(&peer)
+            || self.inbound_policy == FirewallPolicy::Allow
+        {
+            telio_log_hot!("Accepting ICMP packet {:?} {:?}", ip, peer);
             return true;
         } else if P::Icmp::BLOCKED_TYPES.contains(&icmp_packet.get_icmp_type().0) {
-            telio_log_trace!("Dropping ICMP packet {:?} {:?}", ip, peer);
+            telio_log_hot!("Dropping ICMP packet {:?} {:?}", ip, peer);
             return false;
         }
 
@@ -881,18 +1378,18 @@ impl StatefullFirewall {
                 let mut icmp_cache = unwrap_lock_or_return!(self.icmp.lock(), false);
                 let is_in_cache = icmp_cache.get(&key).is_some();
                 if is_in_cache {
-                    telio_log_trace!("Matched ICMP conntrack entry {:?}", key,);
-                    telio_log_trace!("Removing ICMP conntrack entry {:?}", key);
+                    telio_log_hot!("Matched ICMP conntrack entry {:?}", key,);
+                    telio_log_hot!("Removing ICMP conntrack entry {:?}", key);
                     icmp_cache.remove(&key);
-                    telio_log_trace!("Accepting ICMP packet {:?} {:?}", ip, peer);
+                    telio_log_hot!("Accepting ICMP packet {:?} {:?}", ip, peer);
                 }
                 is_in_cache
             }
             Err(icmp_error_key) => {
-                telio_log_trace!("Encountered ICMP error packet, checking nested packet");
+                telio_log_hot!("Encountered ICMP error packet, checking nested packet");
                 let should_accept = self.handle_icmp_error(peer, icmp_error_key);
                 if should_accept {
-                    telio_log_trace!("Accepting ICMP packet {:?} {:?}", ip, peer);
+                    telio_log_hot!("Accepting ICMP packet {:?} {:?}", ip, peer);
                 }
                 should_accept
             }
@@ -908,14 +1405,14 @@ impl StatefullFirewall {
             IpNextHeaderProtocols::Udp => match UdpPacket::new(ip.payload()) {
                 Some(packet) => (packet.get_source(), packet.get_destination(), None),
                 _ => {
-                    telio_log_trace!("Could not create UDP packet from IP packet {:?}", ip);
+                    telio_log_hot!("Could not create UDP packet from IP packet {:?}", ip);
                     return None;
                 }
             },
             IpNextHeaderProtocols::Tcp => match TcpPacket::new(ip.payload()) {
                 Some(packet) => (packet.get_source(), packet.get_destination(), Some(packet)),
                 _ => {
-                    telio_log_trace!("Could not create TCP packet from IP packet {:?}", ip);
+                    telio_log_hot!("Could not create TCP packet from IP packet {:?}", ip);
                     return None;
                 }
             },
@@ -949,7 +1446,7 @@ impl StatefullFirewall {
         let icmp_packet = match IcmpPacket::new(ip.payload()) {
             Some(packet) => packet,
             _ => {
-                telio_log_trace!("Could not create ICMP packet from IP packet {:?}", ip);
+                telio_log_hot!("Could not create ICMP packet from IP packet {:?}", ip);
                 return Err(IcmpErrorKey::None);
             }
         };
@@ -1027,7 +1524,7 @@ impl StatefullFirewall {
         let inner_packet = match icmp_packet.payload().get(4..) {
             Some(bytes) => bytes,
             None => {
-                telio_log_trace!("ICMP error body does not contain a nested packet");
+                telio_log_hot!("ICMP error body does not contain a nested packet");
                 return Err(IcmpErrorKey::None);
             }
         };
@@ -1035,7 +1532,7 @@ impl StatefullFirewall {
             let packet = match Ipv4Packet::try_from(inner_packet) {
                 Some(packet) => packet,
                 _ => {
-                    telio_log_trace!("ICMP error contains invalid IPv4 packet");
+                    telio_log_hot!("ICMP error contains invalid IPv4 packet");
                     return Err(IcmpErrorKey::None);
                 }
             };
@@ -1055,7 +1552,7 @@ impl StatefullFirewall {
             let packet = match Ipv6Packet::try_from(inner_packet) {
                 Some(packet) => packet,
                 _ => {
-                    telio_log_trace!("ICMP error contains invalid IPv6 packet");
+                    telio_log_hot!("ICMP error contains invalid IPv6 packet");
                     return Err(IcmpErrorKey::None);
                 }
             };
@@ -1081,11 +1578,11 @@ impl StatefullFirewall {
                 let mut icmp_cache = unwrap_lock_or_return!(self.icmp.lock(), false);
                 let is_in_cache = icmp_cache.get(&icmp_key).is_some();
                 if is_in_cache {
-                    telio_log_trace!(
+                    telio_log_hot!(
                         "Nested packet in ICMP error packet matched ICMP conntrack entry {:?}",
                         icmp_key,
                     );
-                    telio_log_trace!("Removing ICMP conntrack entry {:?}", icmp_key);
+                    telio_log_hot!("Removing ICMP conntrack entry {:?}", icmp_key);
                     icmp_cache.remove(&icmp_key);
                 }
                 is_in_cache
@@ -1097,11 +1594,11 @@ impl StatefullFirewall {
 
                 let is_in_cache = tcp_cache.get(&tcp_key).is_some();
                 if is_in_cache {
-                    telio_log_trace!(
+                    telio_log_hot!(
                         "Nested packet in ICMP error packet matched TCP conntrack entry {:?}",
                         tcp_key,
                     );
-                    telio_log_trace!("Removing TCP conntrack entry {:?}", tcp_key);
+                    telio_log_hot!("Removing TCP conntrack entry {:?}", tcp_key);
                     tcp_cache.remove(&tcp_key);
                 }
                 is_in_cache
@@ -1112,11 +1609,11 @@ impl StatefullFirewall {
                 let mut udp_cache = unwrap_lock_or_return!(self.udp.lock(), false);
                 let is_in_cache = udp_cache.get(&udp_key).is_some();
                 if is_in_cache {
-                    telio_log_trace!(
+                    telio_log_hot!(
                         "Nested packet in ICMP error packet matched UDP conntrack entry {:?}",
                         udp_key,
                     );
-                    telio_log_trace!("Removing UDP conntrack entry {:?}", udp_key);
+                    telio_log_hot!("Removing UDP conntrack entry {:?}", udp_key);
                     udp_cache.remove(&udp_key);
                 }
                 is_in_cache
@@ -1361,7 +1858,7 @@ impl StatefullFirewall {
             if whitelist.peer_whitelists[Permissions::LocalAreaConnections].contains(&pubkey) {
                 return PacketAction::PassThrough;
             } else {
-                telio_log_trace!("Local connection policy failed");
+                telio_log_hot!("Local connection policy failed");
                 return PacketAction::Drop;
             }
         }
@@ -1372,7 +1869,7 @@ impl StatefullFirewall {
 
         #[allow(index_access_check)]
         if whitelist.peer_whitelists[Permissions::RoutingConnections].contains(&pubkey) {
-            telio_log_trace!("Forwarding due to Routing policy");
+            telio_log_hot!("Forwarding due to Routing policy");
             return PacketAction::PassThrough;
         }
 
@@ -1390,12 +1887,41 @@ impl StatefullFirewall {
             }
         }
 
-        if whitelist.is_port_whitelisted(&pubkey, local_port) {
+        // Connections allowed by the policy are always tracked, so replies to them pass
+        // the outbound policy
+        if whitelist.is_port_whitelisted(&pubkey, local_port)
+            || self.inbound_policy == FirewallPolicy::Allow
+        {
             return PacketAction::HandleLocally;
         }
 
         PacketAction::Drop
     }
+
+    fn is_reply_to_remote<'a, P: IpPacket<'a>>(
+        &self,
+        pubkey: PublicKey,
+        ip: &P,
+        headers: &PacketHeaders,
+    ) -> bool {
+        match headers.transport {
+            Transport::Udp { .. } => {
+                let (link, _) = unwrap_option_or_return!(Self::build_conn_info(ip, false), false);
+                unwrap_lock_or_return!(self.udp.lock(), false)
+                    .peek(&Connection { link, pubkey })
+                    .map_or(false, |info| info.is_remote_initiated)
+            }
+            Transport::Tcp { .. } => {
+                let (link, _) = unwrap_option_or_return!(Self::build_conn_info(ip, false), false);
+                unwrap_lock_or_return!(self.tcp.lock(), false)
+                    .peek(&Connection { link, pubkey })
+                    .map_or(false, |info| info.conn_remote_initiated)
+            }
+            // Replies and errors are fine, requests would open a new exchange
+            Transport::Icmp { icmp_type, .. } => !P::Icmp::BLOCKED_TYPES.contains(&icmp_type),
+            _ => false,
+        }
+    }
 }
 
 impl Firewall for StatefullFirewall {
@@ -1453,24 +1979,45 @@ impl Firewall for StatefullFirewall {
     }
 
     fn add_vpn_peer(&self, vpn_peer: PublicKey) {
-        unwrap_lock_or_return!(self.whitelist.write()).vpn_peer = Some(vpn_peer);
+        let mut whitelist = unwrap_lock_or_return!(self.whitelist.write());
+        match whitelist.vpn_peer.replace(vpn_peer) {
+            Some(old_vpn_peer) if old_vpn_peer != vpn_peer => {
+                telio_log_debug!("Switching exit node from {old_vpn_peer:?} to {vpn_peer:?}");
+                whitelist.exit_switch = Some(Instant::now());
+            }
+            _ => (),
+        }
     }
 
     fn remove_vpn_peer(&self) {
-        unwrap_lock_or_return!(self.whitelist.write()).vpn_peer = None;
+        let mut whitelist = unwrap_lock_or_return!(self.whitelist.write());
+        whitelist.vpn_peer = None;
+        whitelist.exit_switch = None;
+    }
+
+    fn complete_exit_switch(&self, vpn_peer: PublicKey) {
+        let mut whitelist = unwrap_lock_or_return!(self.whitelist.write());
+        if whitelist.vpn_peer == Some(vpn_peer) && whitelist.exit_switch.take().is_some() {
+            telio_log_debug!("Switched exit node to {vpn_peer:?}");
+        }
     }
 
     fn process_outbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
-        match unwrap_option_or_return!(buffer.first(), false) >> 4 {
-            4 => self.process_outbound_ip_packet::<Ipv4Packet>(public_key, buffer),
+        let verdict = match unwrap_option_or_return!(buffer.first(), false) >> 4 {
+            4 => self.process_ip_fragments::<Ipv4Packet>(public_key, buffer, |buffer| {
+                self.process_outbound_ip_packet::<Ipv4Packet>(public_key, buffer)
+            }),
             6 if self.allow_ipv6 => {
-                self.process_outbound_ip_packet::<Ipv6Packet>(public_key, buffer)
+                self.process_ip_fragments::<Ipv6Packet>(public_key, buffer, |buffer| {
+                    self.process_outbound_ip_packet::<Ipv6Packet>(public_key, buffer)
+                })
             }
             version => {
                 telio_log_warn!("Unexpected IP version {version} for outbound packet");
                 false
             }
-        }
+        };
+        self.enforce(public_key, verdict, false)
     }
 
     /// Checks if incoming packet should be accepted.
@@ -1478,16 +2025,21 @@ impl Firewall for StatefullFirewall {
     /// Adds new connection to cache only if ip is whitelisted
     /// Allows all icmp packets except for request types
     fn process_inbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
-        match unwrap_option_or_return!(buffer.first(), false) >> 4 {
-            4 => self.process_inbound_ip_packet::<Ipv4Packet>(public_key, buffer),
+        let verdict = match unwrap_option_or_return!(buffer.first(), false) >> 4 {
+            4 => self.process_ip_fragments::<Ipv4Packet>(public_key, buffer, |buffer| {
+                self.process_inbound_ip_packet::<Ipv4Packet>(public_key, buffer)
+            }),
             6 if self.allow_ipv6 => {
-                self.process_inbound_ip_packet::<Ipv6Packet>(public_key, buffer)
+                self.process_ip_fragments::<Ipv6Packet>(public_key, buffer, |buffer| {
+                    self.process_inbound_ip_packet::<Ipv6Packet>(public_key, buffer)
+                })
             }
             version => {
                 telio_log_warn!("Unexpected IP version {version} for inbound packet");
                 false
             }
-        }
+        };
+        self.enforce(public_key, verdict, true)
     }
 
     fn reset_connections(
@@ -1511,6 +2063,46 @@ impl Firewall for StatefullFirewall {
             node_ip_address.push(ip);
         }
     }
+
+    fn remove_peer_connections(&self, peer: &PublicKey) {
+        telio_log_debug!("Removing tracked connections of {peer:?}");
+        unwrap_lock_or_return!(self.tcp.lock()).retain(|conn, _| conn.pubkey != *peer);
+        unwrap_lock_or_return!(self.udp.lock()).retain(|conn, _| conn.pubkey != *peer);
+        unwrap_lock_or_return!(self.icmp.lock()).retain(|conn, _| conn.pubkey != *peer);
+        unwrap_lock_or_return!(self.fragments.lock()).retain(|key, _| key.pubkey != *peer);
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.remove_peer(peer);
+        }
+        unwrap_lock_or_return!(self.dropped.write()).remove(peer);
+    }
+
+    fn get_rate_limit_stats(&self) -> HashMap<PublicKey, RateLimitStats> {
+        self.rate_limiter
+            .as_ref()
+            .and_then(|rate_limiter| rate_limiter.stats.lock().ok().map(|stats| stats.clone()))
+            .unwrap_or_default()
+    }
+
+    fn get_conntrack_memory_usage(&self) -> usize {
+        unwrap_lock_or_return!(self.tcp.lock(), 0).allocated_size()
+            + unwrap_lock_or_return!(self.udp.lock(), 0).allocated_size()
+            + unwrap_lock_or_return!(self.icmp.lock(), 0).allocated_size()
+            + unwrap_lock_or_return!(self.fragments.lock(), 0).allocated_size()
+    }
+
+    fn get_monitor_stats(&self) -> MonitorStats {
+        MonitorStats {
+            would_drop_inbound: self.would_drop_inbound.load(Ordering::Relaxed),
+            would_drop_outbound: self.would_drop_outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get_dropped_packets(&self) -> HashMap<PublicKey, u64> {
+        unwrap_lock_or_return!(self.dropped.read(), Default::default())
+            .iter()
+            .map(|(peer, count)| (*peer, count.load(Ordering::Relaxed)))
+            .collect()
+    }
 }
 
 /// The default initialization of Firewall object
@@ -1522,6 +2114,14 @@ impl Default for StatefullFirewall {
                 boringtun_reset_conns: false,
                 neptun_reset_conns: false,
                 exclude_private_ip_range: None,
+                rate_limit: None,
+                inbound_policy: FirewallPolicy::Deny,
+                outbound_policy: FirewallPolicy::Allow,
+                drop_fragments: false,
+                connection_notifications: false,
+                monitor_only: false,
+                exit_switch_policy: ExitSwitchPolicy::Allow,
+                exit_switch_grace_s: 5,
             },
         )
     }
@@ -1539,7 +2139,7 @@ pub mod tests {
         icmpv6::{Icmpv6Type, MutableIcmpv6Packet},
         ip::IpNextHeaderProtocol,
         ipv4::MutableIpv4Packet,
-        ipv6::MutableIpv6Packet,
+        ipv6::{MutableFragmentPacket, MutableIpv6Packet},
         tcp::MutableTcpPacket,
         udp::MutableUdpPacket,
         MutablePacket,
@@ -1548,6 +2148,7 @@ pub mod tests {
         convert::TryInto,
         net::{Ipv4Addr, SocketAddr as StdSocketAddr, SocketAddrV6},
         net::{Ipv6Addr, SocketAddrV4},
+        sync::Arc,
         time::Duration,
     };
     use telio_crypto::SecretKey;
@@ -1556,6 +2157,7 @@ pub mod tests {
     type MakeTcp = &'static dyn Fn(&str, &str, u8) -> Vec<u8>;
     type MakeIcmp = &'static dyn Fn(&str, &str, GenericIcmpType) -> Vec<u8>;
     type MakeIcmpWithBody = &'static dyn Fn(&str, &str, GenericIcmpType, &[u8]) -> Vec<u8>;
+    type MakeFragments = &'static dyn Fn(&[u8], u32) -> (Vec<u8>, Vec<u8>);
 
     fn advance_time(time: Duration) {
         #[cfg(not(feature = "test_utils"))]
@@ -1578,6 +2180,7 @@ pub mod tests {
     const TCP_HEADER_MIN: usize = 20; // TCP header minimal length in bytes
     const UDP_HEADER: usize = 8; // UDP header length in bytes
     const ICMP_HEADER: usize = 8; // ICMP header length in bytes
+    const FRAGMENT_HEADER: usize = 8; // IPv6 fragment header length in bytes
 
     fn set_ipv4(
         ip: &mut MutableIpv4Packet,
@@ -1697,6 +2300,7 @@ pub mod tests {
             MutableTcpPacket::new(&mut raw[IPV4_HEADER_MIN..]).expect("TCP: Bad TCP buffer");
         tcp.set_source(source.port());
         tcp.set_destination(destination.port());
+        tcp.set_data_offset((TCP_HEADER_MIN / 4) as u8);
         tcp.set_checksum(0);
         tcp.payload_mut().copy_from_slice(msg.as_bytes());
         tcp.set_flags(flags);
@@ -1725,6 +2329,7 @@ pub mod tests {
             MutableTcpPacket::new(&mut raw[IPV6_HEADER_MIN..]).expect("TCP: Bad TCP buffer");
         tcp.set_source(source.port());
         tcp.set_destination(destination.port());
+        tcp.set_data_offset((TCP_HEADER_MIN / 4) as u8);
         tcp.set_checksum(0);
         tcp.payload_mut().copy_from_slice(msg.as_bytes());
         tcp.set_flags(flags);
@@ -1732,6 +2337,58 @@ pub mod tests {
         raw
     }
 
+    /// Split the packet into the first fragment, holding 8 bytes of its payload, and the rest
+    fn make_fragments4(packet: &[u8], id: u32) -> (Vec<u8>, Vec<u8>) {
+        let split = IPV4_HEADER_MIN + 8;
+
+        let mut first = packet[..split].to_vec();
+        let mut ip = MutableIpv4Packet::new(&mut first).expect("Fragment: Bad IP buffer");
+        ip.set_total_length(split as u16);
+        ip.set_flags(Ipv4Flags::MoreFragments);
+        ip.set_identification(id as u16);
+
+        let mut rest = packet[..IPV4_HEADER_MIN].to_vec();
+        rest.extend_from_slice(&packet[split..]);
+        let rest_len = rest.len();
+        let mut ip = MutableIpv4Packet::new(&mut rest).expect("Fragment: Bad IP buffer");
+        ip.set_total_length(rest_len as u16);
+        ip.set_flags(0);
+        ip.set_fragment_offset(1);
+        ip.set_identification(id as u16);
+
+        (first, rest)
+    }
+
+    /// Split the packet into the first fragment, holding 8 bytes of its payload, and the rest
+    fn make_fragments6(packet: &[u8], id: u32) -> (Vec<u8>, Vec<u8>) {
+        let split = IPV6_HEADER_MIN + 8;
+        let next_header = Ipv6Packet::new(packet)
+            .expect("Fragment: Bad IP buffer")
+            .get_next_header();
+
+        let make_fragment = |payload: &[u8], offset_with_flags: u16| {
+            let mut raw = vec![0u8; IPV6_HEADER_MIN + FRAGMENT_HEADER + payload.len()];
+            raw[..IPV6_HEADER_MIN].copy_from_slice(&packet[..IPV6_HEADER_MIN]);
+            raw[IPV6_HEADER_MIN + FRAGMENT_HEADER..].copy_from_slice(payload);
+
+            let mut ip = MutableIpv6Packet::new(&mut raw).expect("Fragment: Bad IP buffer");
+            ip.set_next_header(IpNextHeaderProtocols::Ipv6Frag);
+            ip.set_payload_length((FRAGMENT_HEADER + payload.len()) as u16);
+
+            let mut fragment = MutableFragmentPacket::new(&mut raw[IPV6_HEADER_MIN..])
+                .expect("Fragment: Bad fragment buffer");
+            fragment.set_next_header(next_header);
+            fragment.set_fragment_offset_with_flags(offset_with_flags);
+            fragment.set_id(id);
+            raw
+        };
+
+        (
+            make_fragment(&packet[IPV6_HEADER_MIN..split], 0b1),
+            make_fragment(&packet[split..], 8),
+        )
+    }
+
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     enum GenericIcmpType {
         V4(IcmpType),
@@ -1863,6 +2520,440 @@ pub mod tests {
         make_icmp6_with_body(src, dst, icmp_type, &[])
     }
 
+    #[test]
+    fn firewall_rate_limits_connections_and_echo_requests() {
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                rate_limit: Some(FeatureFirewallRateLimit {
+                    new_connections_per_s: 1,
+                    new_connections_burst: 2,
+                    icmp_echo_per_s: 1,
+                    icmp_echo_burst: 1,
+                }),
+                ..Default::default()
+            },
+        );
+        fw.set_ip_addresses(vec![StdIpAddr::V4(StdIpv4Addr::new(127, 0, 0, 1))]);
+        let (peer, other) = (make_random_peer(), make_random_peer());
+        fw.add_to_peer_whitelist(peer, Permissions::IncomingConnections);
+        fw.add_to_peer_whitelist(other, Permissions::IncomingConnections);
+
+        let syn = |src: &str| make_tcp(src, "127.0.0.1:22", TcpFlags::SYN);
+        let echo = make_icmp4("8.8.8.8", "127.0.0.1", IcmpTypes::EchoRequest.into());
+
+        assert!(fw.process_inbound_packet(&peer.0, &syn("8.8.8.8:1111")));
+        assert!(fw.process_inbound_packet(&peer.0, &syn("8.8.8.8:2222")));
+        assert!(!fw.process_inbound_packet(&peer.0, &syn("8.8.8.8:3333")));
+        // Packets of established connections are not limited
+        assert!(fw.process_inbound_packet(
+            &peer.0,
+            &make_tcp("8.8.8.8:1111", "127.0.0.1:22", TcpFlags::ACK)
+        ));
+        // Every peer has its own limit
+        assert!(fw.process_inbound_packet(&other.0, &syn("8.8.8.8:3333")));
+
+        assert!(fw.process_inbound_packet(&peer.0, &echo));
+        assert!(!fw.process_inbound_packet(&peer.0, &echo));
+
+        advance_time(Duration::from_secs(1));
+        assert!(fw.process_inbound_packet(&peer.0, &syn("8.8.8.8:3333")));
+        assert!(fw.process_inbound_packet(&peer.0, &echo));
+
+        let stats = fw.get_rate_limit_stats();
+        assert_eq!(
+            stats.get(&peer),
+            Some(&RateLimitStats {
+                dropped_connections: 1,
+                dropped_echo_requests: 1,
+            })
+        );
+        assert_eq!(stats.get(&other), None);
+
+        fw.remove_peer_connections(&peer);
+        assert!(fw.get_rate_limit_stats().is_empty());
+    }
+
+    #[test]
+    fn firewall_notifies_new_inbound_connections() {
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                connection_notifications: true,
+                ..Default::default()
+            },
+        )
+        .with_inbound_connection_callback({
+            let notified = notified.clone();
+            Box::new(move |peer, protocol, port| {
+                notified.lock().unwrap().push((peer, protocol, port))
+            })
+        });
+        fw.set_ip_addresses(vec![StdIpAddr::V4(StdIpv4Addr::new(127, 0, 0, 1))]);
+        let (peer, stranger) = (make_random_peer(), make_random_peer());
+        fw.add_to_peer_whitelist(peer, Permissions::IncomingConnections);
+
+        // Replies to our own connections are not reported
+        assert!(fw.process_outbound_packet(&peer.0, &make_udp("127.0.0.1:1111", "8.8.8.8:53")));
+        assert!(fw.process_inbound_packet(&peer.0, &make_udp("8.8.8.8:53", "127.0.0.1:1111")));
+
+        assert!(fw.process_inbound_packet(
+            &peer.0,
+            &make_tcp("8.8.8.8:2222", "127.0.0.1:22", TcpFlags::SYN)
+        ));
+        assert!(fw.process_inbound_packet(
+            &peer.0,
+            &make_tcp("8.8.8.8:2222", "127.0.0.1:22", TcpFlags::ACK)
+        ));
+        assert!(fw.process_inbound_packet(&peer.0, &make_udp("8.8.8.8:3333", "127.0.0.1:5353")));
+        assert!(fw.process_inbound_packet(&peer.0, &make_udp("8.8.8.8:3333", "127.0.0.1:5353")));
+        // Dropped connections are not reported
+        assert!(!fw.process_inbound_packet(
+            &stranger.0,
+            &make_tcp("8.8.8.8:4444", "127.0.0.1:22", TcpFlags::SYN)
+        ));
+
+        assert_eq!(
+            *notified.lock().unwrap(),
+            vec![
+                (peer, ConnectionProtocol::Tcp, 22),
+                (peer, ConnectionProtocol::Udp, 5353)
+            ]
+        );
+    }
+
+    #[test]
+    fn firewall_default_policies() {
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                inbound_policy: FirewallPolicy::Allow,
+                outbound_policy: FirewallPolicy::Deny,
+                ..Default::default()
+            },
+        );
+        fw.set_ip_addresses(vec![StdIpAddr::V4(StdIpv4Addr::new(127, 0, 0, 1))]);
+        let (peer, trusted) = (make_random_peer(), make_random_peer());
+        fw.add_to_peer_whitelist(trusted, Permissions::IncomingConnections);
+
+        // Anyone can connect to us
+        assert!(fw.process_inbound_packet(
+            &peer.0,
+            &make_tcp("8.8.8.8:1111", "127.0.0.1:22", TcpFlags::SYN)
+        ));
+        assert!(fw.process_inbound_packet(&peer.0, &make_udp("8.8.8.8:2222", "127.0.0.1:53")));
+        assert!(fw.process_inbound_packet(
+            &peer.0,
+            &make_icmp4("8.8.8.8", "127.0.0.1", IcmpTypes::EchoRequest.into())
+        ));
+
+        // But we can only reply
+        assert!(fw.process_outbound_packet(
+            &peer.0,
+            &make_tcp(
+                "127.0.0.1:22",
+                "8.8.8.8:1111",
+                TcpFlags::SYN | TcpFlags::ACK
+            )
+        ));
+        assert!(fw.process_outbound_packet(&peer.0, &make_udp("127.0.0.1:53", "8.8.8.8:2222")));
+        assert!(fw.process_outbound_packet(
+            &peer.0,
+            &make_icmp4("127.0.0.1", "8.8.8.8", IcmpTypes::EchoReply.into())
+        ));
+        assert!(!fw.process_outbound_packet(
+            &peer.0,
+            &make_tcp("127.0.0.1:3333", "8.8.8.8:80", TcpFlags::SYN)
+        ));
+        assert!(!fw.process_outbound_packet(&peer.0, &make_udp("127.0.0.1:4444", "8.8.8.8:53")));
+        assert!(!fw.process_outbound_packet(
+            &peer.0,
+            &make_icmp4("127.0.0.1", "8.8.8.8", IcmpTypes::EchoRequest.into())
+        ));
+
+        // Whitelisted peers are not restricted
+        assert!(fw.process_outbound_packet(
+            &trusted.0,
+            &make_tcp("127.0.0.1:3333", "8.8.8.8:80", TcpFlags::SYN)
+        ));
+    }
+
+    #[test]
+    fn firewall_fragments_follow_first_fragment() {
+        let test_inputs: [(MakeUdp, MakeFragments, &str, &str, &str); 2] = [
+            (
+                &make_udp,
+                &make_fragments4,
+                "127.0.0.1:1111",
+                "8.8.8.8:8888",
+                "8.8.8.8:9999",
+            ),
+            (
+                &make_udp6,
+                &make_fragments6,
+                "[::1]:1111",
+                "[2001:4860:4860::8888]:8888",
+                "[2001:4860:4860::8888]:9999",
+            ),
+        ];
+
+        for (make_udp, make_fragments, us, them, stranger) in test_inputs {
+            let fw = StatefullFirewall::new(true, FeatureFirewall::default());
+            fw.set_ip_addresses(vec![
+                StdIpAddr::V4(StdIpv4Addr::LOCALHOST),
+                StdIpAddr::V6(StdIpv6Addr::LOCALHOST),
+            ]);
+            let peer = make_peer();
+
+            // Outbound fragments are tracked by the first one
+            let (first, rest) = make_fragments(&make_udp(us, them), 1);
+            assert!(fw.process_outbound_packet(&peer, &first));
+            assert!(fw.process_outbound_packet(&peer, &rest));
+            assert_eq!(fw.udp.lock().unwrap().len(), 1);
+
+            // Fragments of the reply pass along with the first one
+            let (first, rest) = make_fragments(&make_udp(them, us), 2);
+            assert!(fw.process_inbound_packet(&peer, &first));
+            assert!(fw.process_inbound_packet(&peer, &rest));
+
+            // Fragments of an unsolicited packet are dropped with the first one
+            let (first, rest) = make_fragments(&make_udp(stranger, us), 3);
+            assert!(!fw.process_inbound_packet(&peer, &first));
+            assert!(!fw.process_inbound_packet(&peer, &rest));
+
+            // Fragments arriving before the first one pass, the first one still decides
+            let (first, rest) = make_fragments(&make_udp(them, us), 4);
+            assert!(fw.process_inbound_packet(&peer, &rest));
+            assert!(fw.process_inbound_packet(&peer, &first));
+            let (first, rest) = make_fragments(&make_udp(stranger, us), 5);
+            assert!(fw.process_inbound_packet(&peer, &rest));
+            assert!(!fw.process_inbound_packet(&peer, &first));
+        }
+    }
+
+    #[test]
+    fn firewall_drops_tiny_and_overlapping_fragments() {
+        let test_inputs: [(MakeTcp, MakeFragments, &str, &str); 2] = [
+            (
+                &make_tcp,
+                &make_fragments4,
+                "127.0.0.1:1111",
+                "8.8.8.8:8888",
+            ),
+            (
+                &make_tcp6,
+                &make_fragments6,
+                "[::1]:1111",
+                "[2001:4860:4860::8888]:8888",
+            ),
+        ];
+
+        for (make_tcp, make_fragments, us, them) in test_inputs {
+            let fw = StatefullFirewall::new(true, FeatureFirewall::default());
+            fw.set_ip_addresses(vec![
+                StdIpAddr::V4(StdIpv4Addr::LOCALHOST),
+                StdIpAddr::V6(StdIpv6Addr::LOCALHOST),
+            ]);
+            let peer = make_peer();
+            assert!(fw.process_outbound_packet(&peer, &make_tcp(us, them, TcpFlags::SYN)));
+
+            // The first fragment does not hold the whole TCP header and the rest would overwrite it
+            let (first, rest) =
+                make_fragments(&make_tcp(them, us, TcpFlags::SYN | TcpFlags::ACK), 1);
+            assert!(!fw.process_inbound_packet(&peer, &first));
+            assert!(!fw.process_inbound_packet(&peer, &rest));
+        }
+    }
+
+    #[test]
+    fn firewall_finds_ipv6_fragment_header_behind_extension_headers() {
+        let fw = StatefullFirewall::new(true, FeatureFirewall::default());
+        fw.set_ip_addresses(vec![StdIpAddr::V6(StdIpv6Addr::LOCALHOST)]);
+        let peer = make_peer();
+        let (us, them) = ("[::1]:1111", "[2001:4860:4860::8888]:8888");
+
+        // Prepend a Hop-by-Hop Options header to the Fragment header
+        let with_hop_by_hop = |fragment: Vec<u8>| {
+            let mut raw = fragment[..IPV6_HEADER_MIN].to_vec();
+            raw.extend_from_slice(&[IpNextHeaderProtocols::Ipv6Frag.0, 0, 1, 4, 0, 0, 0, 0]);
+            raw.extend_from_slice(&fragment[IPV6_HEADER_MIN..]);
+            let mut ip = MutableIpv6Packet::new(&mut raw).expect("Fragment: Bad IP buffer");
+            ip.set_next_header(IpNextHeaderProtocols::Hopopt);
+            ip.set_payload_length(ip.get_payload_length() + 8);
+            raw
+        };
+
+        let (first, rest) = make_fragments6(&make_udp6(us, them), 1);
+        assert!(fw.process_outbound_packet(&peer, &with_hop_by_hop(first)));
+        assert!(fw.process_outbound_packet(&peer, &with_hop_by_hop(rest)));
+        assert_eq!(fw.udp.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn firewall_drops_fragments_if_configured() {
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                drop_fragments: true,
+                ..Default::default()
+            },
+        );
+        let peer = make_peer();
+        let (us, them) = ("127.0.0.1:1111", "8.8.8.8:8888");
+
+        assert!(fw.process_outbound_packet(&peer, &make_udp(us, them)));
+        let (first, rest) = make_fragments4(&make_udp(them, us), 1);
+        assert!(!fw.process_inbound_packet(&peer, &first));
+        assert!(!fw.process_inbound_packet(&peer, &rest));
+        assert!(fw.process_inbound_packet(&peer, &make_udp(them, us)));
+        assert_eq!(fw.get_dropped_packets().get(&PublicKey(peer)), Some(&2));
+
+        fw.remove_peer_connections(&PublicKey(peer));
+        assert!(fw.get_dropped_packets().is_empty());
+    }
+
+    #[test]
+    fn firewall_monitor_only_counts_would_be_drops() {
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                outbound_policy: FirewallPolicy::Deny,
+                monitor_only: true,
+                ..Default::default()
+            },
+        );
+        fw.set_ip_addresses(vec![StdIpAddr::V4(StdIpv4Addr::new(127, 0, 0, 1))]);
+        let peer = make_peer();
+        let (us, them) = ("127.0.0.1:1111", "8.8.8.8:8888");
+
+        assert!(fw.process_inbound_packet(&peer, &make_udp(them, us)));
+        assert!(fw.process_inbound_packet(&peer, &make_udp(them, us)));
+        assert!(fw.process_outbound_packet(&peer, &make_udp(us, them)));
+        assert_eq!(
+            fw.get_monitor_stats(),
+            MonitorStats {
+                would_drop_inbound: 2,
+                would_drop_outbound: 1,
+            }
+        );
+
+        // Allowed packets are not counted
+        fw.add_to_peer_whitelist(PublicKey(peer), Permissions::IncomingConnections);
+        assert!(fw.process_inbound_packet(&peer, &make_udp(them, us)));
+        assert!(fw.process_outbound_packet(&peer, &make_udp(us, them)));
+        assert_eq!(fw.get_monitor_stats().would_drop_inbound, 2);
+        assert_eq!(fw.get_monitor_stats().would_drop_outbound, 1);
+        assert!(fw.get_dropped_packets().is_empty());
+    }
+
+    #[test]
+    fn firewall_exit_switch_block_drops_until_connected() {
+        let fw = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                exit_switch_policy: ExitSwitchPolicy::Block,
+                ..Default::default()
+            },
+        );
+        let (old_exit, new_exit) = (make_random_peer(), make_random_peer());
+        let (us, them) = ("127.0.0.1:1111", "8.8.8.8:8888");
+
+        // Connecting the first exit node is not a switch
+        fw.add_vpn_peer(old_exit);
+        assert!(fw.process_outbound_packet(&old_exit.0, &make_udp(us, them)));
+
+        fw.add_vpn_peer(new_exit);
+        assert!(!fw.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+        assert!(!fw.process_inbound_packet(&new_exit.0, &make_udp(them, us)));
+        // Reapplying the same exit node keeps the switch going
+        fw.add_vpn_peer(new_exit);
+        fw.complete_exit_switch(old_exit);
+        assert!(!fw.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+
+        fw.complete_exit_switch(new_exit);
+        assert!(fw.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+        assert!(fw.process_inbound_packet(&new_exit.0, &make_udp(them, us)));
+        assert_eq!(fw.get_dropped_packets().get(&new_exit), Some(&3));
+    }
+
+    #[test]
+    fn firewall_exit_switch_grace_and_allow() {
+        let grace = StatefullFirewall::new(
+            true,
+            FeatureFirewall {
+                exit_switch_policy: ExitSwitchPolicy::Grace,
+                exit_switch_grace_s: 5,
+                ..Default::default()
+            },
+        );
+        let allow = StatefullFirewall::new(true, FeatureFirewall::default());
+        let (old_exit, new_exit) = (make_random_peer(), make_random_peer());
+        let (us, them) = ("127.0.0.1:1111", "8.8.8.8:8888");
+
+        for fw in [&grace, &allow] {
+            fw.add_vpn_peer(old_exit);
+            fw.add_vpn_peer(new_exit);
+            assert!(fw.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+        }
+
+        advance_time(Duration::from_secs(5));
+        assert!(!grace.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+        assert!(allow.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+
+        // Disconnecting the exit node cancels the switch
+        grace.remove_vpn_peer();
+        grace.add_vpn_peer(new_exit);
+        assert!(grace.process_outbound_packet(&new_exit.0, &make_udp(us, them)));
+    }
+
+    #[test]
+    fn firewall_remove_peer_connections() {
+        let fw = StatefullFirewall::new(true, FeatureFirewall::default());
+        let old_peer = make_peer();
+        let other_peer = make_random_peer();
+        let (src, dst) = ("127.0.0.1:1111", "8.8.8.8:8888");
+
+        for peer in [&old_peer, &other_peer.0] {
+            assert!(fw.process_outbound_packet(peer, &make_tcp(src, dst, TcpFlags::SYN)));
+            assert!(fw.process_outbound_packet(peer, &make_udp(src, dst)));
+        }
+        assert_eq!(fw.tcp.lock().unwrap().len(), 2);
+        assert_eq!(fw.udp.lock().unwrap().len(), 2);
+
+        fw.remove_peer_connections(&PublicKey(old_peer));
+
+        assert_eq!(fw.tcp.lock().unwrap().len(), 1);
+        assert_eq!(fw.udp.lock().unwrap().len(), 1);
+        assert!(!fw.process_inbound_packet(&old_peer, &make_udp(dst, src)));
+        assert!(fw.process_inbound_packet(&other_peer.0, &make_udp(dst, src)));
+    }
+
+    #[test]
+    fn firewall_conntrack_memory_usage() {
+        let fw = StatefullFirewall::new(true, FeatureFirewall::default());
+        let peer = make_peer();
+        assert_eq!(fw.get_conntrack_memory_usage(), 0);
+
+        assert!(fw.process_outbound_packet(
+            &peer,
+            &make_tcp("127.0.0.1:1111", "8.8.8.8:8888", TcpFlags::SYN)
+        ));
+        let single = fw.get_conntrack_memory_usage();
+        assert!(single > 0);
+
+        assert!(fw.process_outbound_packet(
+            &peer,
+            &make_tcp("127.0.0.1:2222", "8.8.8.8:8888", TcpFlags::SYN)
+        ));
+        let double = fw.get_conntrack_memory_usage();
+        assert!(double > single);
+
+        // The table keeps its capacity, only the entries are freed
+        fw.remove_peer_connections(&PublicKey(peer));
+        assert!(fw.get_conntrack_memory_usage() < single);
+    }
+
     #[test]
     fn firewall_ipv4_packet_validation() {
         let mut raw = make_icmp4("127.0.0.1", "8.8.8.8", IcmpTypes::EchoRequest.into());
@@ -3229,6 +4320,8 @@ pub mod tests {
                 ),
                 boringtun_reset_conns: false,
                 neptun_reset_conns: false,
+                rate_limit: None,
+                ..Default::default()
             },
         );
         fw.set_ip_addresses(vec![
@@ -4,3 +4,4 @@
 //! initiated connections, and deny inbound packet
 //! from an unrecognized source
 pub mod firewall;
+pub mod packet;
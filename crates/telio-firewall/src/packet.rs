@@ -0,0 +1,385 @@
+//! Parsing of the IP, TCP, UDP and ICMP headers of the packets passing the firewall.
+//!
+//! The packets are decrypted data of the peers, so none of the header fields can be trusted.
+//! Every length is checked against the buffer before anything past it is read, and a packet
+//! failing any of the checks is rejected with the reason, instead of being partially parsed.
+//! The parser is a pure function of the buffer, so it can be fuzzed on its own.
+
+use std::net::IpAddr;
+
+use pnet_packet::{
+    icmp::IcmpPacket,
+    ip::IpNextHeaderProtocols,
+    ipv4::Ipv4Packet,
+    ipv6::{FragmentPacket, Ipv6Packet},
+    tcp::TcpPacket,
+    udp::UdpPacket,
+    Packet,
+};
+
+const IPV4_HEADER_MIN: usize = 20;
+const IPV6_HEADER: usize = 40;
+const TCP_HEADER_MIN: usize = 20;
+const UDP_HEADER: usize = 8;
+/// Type, code, checksum and the four bytes specific to the message type
+const ICMP_HEADER: usize = 8;
+
+/// Reason for a packet to be rejected as malformed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PacketError {
+    /// The buffer holds no bytes at all
+    #[error("Empty packet")]
+    Empty,
+    /// Neither IPv4 nor IPv6
+    #[error("Unknown IP version {0}")]
+    UnknownVersion(u8),
+    /// The buffer ends within the IP header
+    #[error("IP header truncated")]
+    TruncatedIpHeader,
+    /// IPv4 header length below the 5 words of the fixed header
+    #[error("IPv4 header length of {0} words is invalid")]
+    InvalidHeaderLength(u8),
+    /// Length declared in the IP header does not fit the buffer
+    #[error("IP length of {declared} bytes does not match the {actual} bytes of the packet")]
+    LengthMismatch {
+        /// Length of the packet according to its header
+        declared: usize,
+        /// Length of the buffer
+        actual: usize,
+    },
+    /// The buffer ends within the header of the transport protocol
+    #[error("Header of protocol {0} truncated")]
+    TruncatedTransportHeader(u8),
+    /// TCP header length below the fixed header or beyond the packet
+    #[error("TCP data offset of {0} words is invalid")]
+    InvalidTcpDataOffset(u8),
+    /// UDP length shorter than the UDP header itself
+    #[error("UDP length of {0} bytes is invalid")]
+    InvalidUdpLength(u16),
+}
+
+/// Transport header of a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// TCP segment
+    Tcp {
+        /// Source port
+        source_port: u16,
+        /// Destination port
+        destination_port: u16,
+        /// Control bits, e.g. SYN or ACK
+        flags: u8,
+    },
+    /// UDP datagram
+    Udp {
+        /// Source port
+        source_port: u16,
+        /// Destination port
+        destination_port: u16,
+    },
+    /// ICMP or ICMPv6 message
+    Icmp {
+        /// Message type
+        icmp_type: u8,
+        /// Message code
+        code: u8,
+    },
+    /// Fragment other than the first, carrying no transport header
+    Fragment,
+    /// Protocol whose header is not parsed
+    Other,
+}
+
+/// Headers of a well formed packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeaders {
+    /// Source address
+    pub source: IpAddr,
+    /// Destination address
+    pub destination: IpAddr,
+    /// Transport protocol, of the original packet for fragments
+    pub protocol: u8,
+    /// Transport header
+    pub transport: Transport,
+}
+
+/// Parse the headers of an IP packet, rejecting it if any of them is malformed
+pub fn parse(buffer: &[u8]) -> Result<PacketHeaders, PacketError> {
+    match buffer.first().ok_or(PacketError::Empty)? >> 4 {
+        4 => parse_ipv4(buffer),
+        6 => parse_ipv6(buffer),
+        version => Err(PacketError::UnknownVersion(version)),
+    }
+}
+
+fn parse_ipv4(buffer: &[u8]) -> Result<PacketHeaders, PacketError> {
+    let ip = Ipv4Packet::new(buffer).ok_or(PacketError::TruncatedIpHeader)?;
+    let ihl = ip.get_header_length();
+    let header_length = usize::from(ihl) * 4;
+    if header_length < IPV4_HEADER_MIN {
+        return Err(PacketError::InvalidHeaderLength(ihl));
+    }
+    if header_length > buffer.len() {
+        return Err(PacketError::TruncatedIpHeader);
+    }
+    let total_length = usize::from(ip.get_total_length());
+    if total_length < header_length || total_length > buffer.len() {
+        return Err(PacketError::LengthMismatch {
+            declared: total_length,
+            actual: buffer.len(),
+        });
+    }
+
+    let protocol = ip.get_next_level_protocol().0;
+    let transport = match buffer.get(header_length..total_length) {
+        Some(_) if ip.get_fragment_offset() != 0 => Transport::Fragment,
+        Some(payload) => parse_transport(protocol, payload)?,
+        None => return Err(PacketError::TruncatedIpHeader),
+    };
+
+    Ok(PacketHeaders {
+        source: ip.get_source().into(),
+        destination: ip.get_destination().into(),
+        protocol,
+        transport,
+    })
+}
+
+fn parse_ipv6(buffer: &[u8]) -> Result<PacketHeaders, PacketError> {
+    let ip = Ipv6Packet::new(buffer).ok_or(PacketError::TruncatedIpHeader)?;
+    let declared = IPV6_HEADER + usize::from(ip.get_payload_length());
+    if declared != buffer.len() {
+        return Err(PacketError::LengthMismatch {
+            declared,
+            actual: buffer.len(),
+        });
+    }
+
+    let mut protocol = ip.get_next_header().0;
+    let mut transport = None;
+    if protocol == IpNextHeaderProtocols::Ipv6Frag.0 {
+        let fragment = FragmentPacket::new(ip.payload())
+            .ok_or(PacketError::TruncatedTransportHeader(protocol))?;
+        protocol = fragment.get_next_header().0;
+        transport = if fragment.get_fragment_offset_with_flags() >> 3 != 0 {
+            Some(Transport::Fragment)
+        } else {
+            Some(parse_transport(protocol, fragment.payload())?)
+        };
+    }
+    let transport = match transport {
+        Some(transport) => transport,
+        None => parse_transport(protocol, ip.payload())?,
+    };
+
+    Ok(PacketHeaders {
+        source: ip.get_source().into(),
+        destination: ip.get_destination().into(),
+        protocol,
+        transport,
+    })
+}
+
+/// Transport header at the start of the payload of the first or the only fragment.
+///
+/// UDP length is not checked against the payload, as the first fragment holds only a part of
+/// the datagram.
+fn parse_transport(protocol: u8, payload: &[u8]) -> Result<Transport, PacketError> {
+    let truncated = PacketError::TruncatedTransportHeader(protocol);
+
+    match protocol {
+        p if p == IpNextHeaderProtocols::Tcp.0 => {
+            let tcp = TcpPacket::new(payload).ok_or(truncated)?;
+            let data_offset = tcp.get_data_offset();
+            let header_length = usize::from(data_offset) * 4;
+            if header_length < TCP_HEADER_MIN || header_length > payload.len() {
+                return Err(PacketError::InvalidTcpDataOffset(data_offset));
+            }
+            Ok(Transport::Tcp {
+                source_port: tcp.get_source(),
+                destination_port: tcp.get_destination(),
+                flags: tcp.get_flags(),
+            })
+        }
+        p if p == IpNextHeaderProtocols::Udp.0 => {
+            let udp = UdpPacket::new(payload).ok_or(truncated)?;
+            let length = udp.get_length();
+            if usize::from(length) < UDP_HEADER {
+                return Err(PacketError::InvalidUdpLength(length));
+            }
+            Ok(Transport::Udp {
+                source_port: udp.get_source(),
+                destination_port: udp.get_destination(),
+            })
+        }
+        p if p == IpNextHeaderProtocols::Icmp.0 || p == IpNextHeaderProtocols::Icmpv6.0 => {
+            // ICMPv6 shares the layout of the ICMP header
+            let icmp = IcmpPacket::new(payload)
+                .filter(|_| payload.len() >= ICMP_HEADER)
+                .ok_or(truncated)?;
+            Ok(Transport::Icmp {
+                icmp_type: icmp.get_icmp_type().0,
+                code: icmp.get_icmp_code().0,
+            })
+        }
+        _ => Ok(Transport::Other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    // Test vectors, captured from the tunnel and then corrupted field by field
+    const TCP_SYN_V4: [u8; 40] = [
+        0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 100, 64, 0, 2, 100,
+        64, 0, 1, 0xd4, 0x31, 0x00, 0x16, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0,
+        0,
+    ];
+    const UDP_V6: [u8; 52] = [
+        0x60, 0, 0, 0, 0x00, 0x0c, 0x11, 0x40, 0xfd, 0x74, 0x65, 0x6c, 0x69, 0x6f, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 2, 0xfd, 0x74, 0x65, 0x6c, 0x69, 0x6f, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x13,
+        0x88, 0x00, 0x35, 0x00, 0x0c, 0, 0, b'p', b'i', b'n', b'g',
+    ];
+    const ICMP_ECHO_V4: [u8; 28] = [
+        0x45, 0x00, 0x00, 0x1c, 0x00, 0x02, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, 100, 64, 0, 2, 100,
+        64, 0, 1, 0x08, 0x00, 0xf7, 0xfe, 0x00, 0x01, 0x00, 0x00,
+    ];
+
+    fn corrupted<const N: usize>(mut packet: [u8; N], at: usize, value: u8) -> [u8; N] {
+        packet[at] = value;
+        packet
+    }
+
+    #[test]
+    fn well_formed_packets_are_parsed() {
+        assert_eq!(
+            parse(&TCP_SYN_V4),
+            Ok(PacketHeaders {
+                source: Ipv4Addr::new(100, 64, 0, 2).into(),
+                destination: Ipv4Addr::new(100, 64, 0, 1).into(),
+                protocol: 6,
+                transport: Transport::Tcp {
+                    source_port: 54321,
+                    destination_port: 22,
+                    flags: 0x02,
+                },
+            })
+        );
+        assert_eq!(
+            parse(&UDP_V6).map(|headers| headers.transport),
+            Ok(Transport::Udp {
+                source_port: 5000,
+                destination_port: 53,
+            })
+        );
+        assert_eq!(
+            parse(&ICMP_ECHO_V4).map(|headers| headers.transport),
+            Ok(Transport::Icmp {
+                icmp_type: 8,
+                code: 0,
+            })
+        );
+        // Trailing bytes past the IPv4 total length are ignored
+        let mut padded = TCP_SYN_V4.to_vec();
+        padded.extend_from_slice(&[0; 6]);
+        assert!(parse(&padded).is_ok());
+    }
+
+    #[test]
+    fn malformed_ip_headers_are_rejected() {
+        assert_eq!(parse(&[]), Err(PacketError::Empty));
+        assert_eq!(parse(&[0x25]), Err(PacketError::UnknownVersion(2)));
+        assert_eq!(
+            parse(&TCP_SYN_V4[..19]),
+            Err(PacketError::TruncatedIpHeader)
+        );
+        assert_eq!(parse(&UDP_V6[..39]), Err(PacketError::TruncatedIpHeader));
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 0, 0x44)),
+            Err(PacketError::InvalidHeaderLength(4))
+        );
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 0, 0x4f)),
+            Err(PacketError::TruncatedIpHeader)
+        );
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 3, 0x29)),
+            Err(PacketError::LengthMismatch {
+                declared: 41,
+                actual: 40,
+            })
+        );
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 3, 0x10)),
+            Err(PacketError::LengthMismatch {
+                declared: 16,
+                actual: 40,
+            })
+        );
+        assert_eq!(
+            parse(&corrupted(UDP_V6, 5, 0x0b)),
+            Err(PacketError::LengthMismatch {
+                declared: 51,
+                actual: 52,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_transport_headers_are_rejected() {
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 32, 0x40)),
+            Err(PacketError::InvalidTcpDataOffset(4))
+        );
+        assert_eq!(
+            parse(&corrupted(TCP_SYN_V4, 32, 0xf0)),
+            Err(PacketError::InvalidTcpDataOffset(15))
+        );
+        assert_eq!(
+            parse(&corrupted(UDP_V6, 45, 0x07)),
+            Err(PacketError::InvalidUdpLength(7))
+        );
+
+        let mut short_icmp = ICMP_ECHO_V4[..24].to_vec();
+        short_icmp[3] = 24;
+        assert_eq!(
+            parse(&short_icmp),
+            Err(PacketError::TruncatedTransportHeader(1))
+        );
+        // Tiny first fragment, splitting the TCP header
+        let mut tiny = TCP_SYN_V4[..28].to_vec();
+        tiny[3] = 28;
+        tiny[6] = 0x20;
+        assert_eq!(parse(&tiny), Err(PacketError::TruncatedTransportHeader(6)));
+    }
+
+    #[test]
+    fn fragments_carry_no_transport_header() {
+        let mut rest = TCP_SYN_V4[..24].to_vec();
+        rest[3] = 24;
+        rest[7] = 1;
+        assert_eq!(
+            parse(&rest).map(|headers| (headers.protocol, headers.transport)),
+            Ok((6, Transport::Fragment))
+        );
+
+        let mut rest = UDP_V6[..40].to_vec();
+        rest[5] = 12;
+        rest[6] = IpNextHeaderProtocols::Ipv6Frag.0;
+        rest.extend_from_slice(&[0x11, 0, 0x00, 0x08, 0, 0, 0, 1, 1, 2, 3, 4]);
+        assert_eq!(
+            parse(&rest).map(|headers| (headers.protocol, headers.transport)),
+            Ok((0x11, Transport::Fragment))
+        );
+        rest[5] = 4;
+        rest.truncate(44);
+        assert_eq!(
+            parse(&rest),
+            Err(PacketError::TruncatedTransportHeader(
+                IpNextHeaderProtocols::Ipv6Frag.0
+            ))
+        );
+    }
+}
@@ -1,12 +1,119 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{fmt::Write, path::Path};
+
+const SCHEMA_PATH: &str = "schema/libtelioapp.json";
+/// Variable pointing at the schema the moose tracker was generated from, defaults to the event
+/// schema for the file tracker
+const TRACKER_SCHEMA_ENV: &str = "LANA_TRACKER_SCHEMA";
+
 #[allow(unwrap_check)]
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", SCHEMA_PATH);
+    println!("cargo:rerun-if-env-changed={}", TRACKER_SCHEMA_ENV);
     let target_os = std::env::var("CARGO_CFG_TARGET_OS")?;
     if target_os == "windows" {
         if let Some(path) = std::option_env!("OUT_DIR") {
             println!("cargo:rustc-link-search={}", path);
         }
     }
+
+    let schema = read_schema(SCHEMA_PATH)?;
+    let tracker_version = match std::env::var(TRACKER_SCHEMA_ENV) {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={}", path);
+            schema_version(&read_schema(&path)?)
+                .context(path)?
+                .to_owned()
+        }
+        Err(_) => schema_version(&schema).context(SCHEMA_PATH)?.to_owned(),
+    };
+
+    let mut out = generate_schema(&schema).context(SCHEMA_PATH)?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Version of the libtelioapp schema the tracker was generated from"
+    )?;
+    writeln!(
+        out,
+        "pub const TRACKER_SCHEMA_VERSION: &str = {:?};",
+        tracker_version
+    )?;
+
+    let out_dir = std::env::var("OUT_DIR")?;
+    std::fs::write(Path::new(&out_dir).join("schema.rs"), out)?;
     Ok(())
 }
+
+fn read_schema(path: &str) -> Result<Value> {
+    let schema = std::fs::read_to_string(path).with_context(|| format!("reading {}", path))?;
+    serde_json::from_str(&schema).with_context(|| format!("parsing {}", path))
+}
+
+fn schema_version(schema: &Value) -> Result<&str> {
+    schema["version"]
+        .as_str()
+        .context("schema version is missing")
+}
+
+/// Turn the event schema into a static table for the validators in `schema.rs`
+fn generate_schema(schema: &Value) -> Result<String> {
+    let version = schema_version(schema)?;
+    let mut out = String::new();
+    writeln!(
+        out,
+        "/// Version of the event schema the validators were generated from"
+    )?;
+    writeln!(out, "pub const SCHEMA_VERSION: &str = {:?};", version)?;
+    writeln!(out)?;
+    writeln!(out, "/// Schemas of all validated events")?;
+    writeln!(out, "pub const EVENTS: &[EventSchema] = &[")?;
+
+    for event in schema["events"]
+        .as_array()
+        .context("schema events are missing")?
+    {
+        let name = event["name"].as_str().context("event name is missing")?;
+        writeln!(out, "    EventSchema {{")?;
+        writeln!(out, "        name: {:?},", name)?;
+        writeln!(out, "        fields: &[")?;
+        for field in event["fields"]
+            .as_array()
+            .with_context(|| format!("fields of {} are missing", name))?
+        {
+            let field_name = field["name"]
+                .as_str()
+                .with_context(|| format!("field name in {} is missing", name))?;
+            let kind = match field["type"].as_str() {
+                Some("string") => "FieldKind::String".to_owned(),
+                Some("int") => "FieldKind::Int".to_owned(),
+                Some("bool") => "FieldKind::Bool".to_owned(),
+                Some("json") => "FieldKind::Json".to_owned(),
+                Some("enum") => {
+                    let values = field["values"]
+                        .as_array()
+                        .and_then(|v| v.iter().map(Value::as_str).collect::<Option<Vec<_>>>())
+                        .with_context(|| {
+                            format!("values of {}.{} are missing", name, field_name)
+                        })?;
+                    format!("FieldKind::Enum(&{:?})", values)
+                }
+                other => bail!("unknown type {:?} of {}.{}", other, name, field_name),
+            };
+            writeln!(
+                out,
+                "            FieldSchema {{ name: {:?}, kind: {}, optional: {} }},",
+                field_name,
+                kind,
+                field["optional"].as_bool().unwrap_or(false)
+            )?;
+        }
+        writeln!(out, "        ],")?;
+        writeln!(out, "    }},")?;
+    }
+    writeln!(out, "];")?;
+
+    Ok(out)
+}
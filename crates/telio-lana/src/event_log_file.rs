@@ -7,6 +7,9 @@ pub use telio_utils::telio_log_warn;
 
 const LOGFILE_PATH: &str = "events-moose.log";
 
+/// Files the events are kept in locally, besides the event database of the tracker
+pub const LOCAL_EVENT_FILES: &[&str] = &[LOGFILE_PATH];
+
 /// Logs a function call and its arguments to file.
 ///
 /// Parameters:
@@ -361,6 +364,11 @@ pub mod moose {
         derpConnectionDuration: i32,
         nat_monitoring: String,
         derp_monitoring: String,
+        dns_failures: Option<String>,
+        endpoint_families: Option<String>,
+        rtt_relay: Option<String>,
+        rtt_direct: Option<String>,
+        relay_paths: Option<String>,
         debug_json: Option<String>,
     ) -> std::result::Result<Result, Error> {
         let heartbeatIntervalString = heartbeatInterval.to_string();
@@ -377,6 +385,11 @@ pub mod moose {
             derpConnectionDurationStr.as_str(),
             nat_monitoring.as_str(),
             derp_monitoring.as_str(),
+            dns_failures.as_deref().unwrap_or_default(),
+            endpoint_families.as_deref().unwrap_or_default(),
+            rtt_relay.as_deref().unwrap_or_default(),
+            rtt_direct.as_deref().unwrap_or_default(),
+            relay_paths.as_deref().unwrap_or_default(),
         ];
         if let Some(dbg_json) = debug_json.as_ref() {
             args.push(dbg_json.as_str());
@@ -403,6 +416,11 @@ pub mod moose {
         derpConnectionDuration: i32,
         nat_monitoring: String,
         derp_monitoring: String,
+        dns_failures: Option<String>,
+        endpoint_families: Option<String>,
+        rtt_relay: Option<String>,
+        rtt_direct: Option<String>,
+        relay_paths: Option<String>,
         debug_json: Option<String>,
     ) -> std::result::Result<Result, Error> {
         let heartbeatIntervalString = heartbeatInterval.to_string();
@@ -419,6 +437,11 @@ pub mod moose {
             derpConnectionDurationStr.as_str(),
             nat_monitoring.as_str(),
             derp_monitoring.as_str(),
+            dns_failures.as_deref().unwrap_or_default(),
+            endpoint_families.as_deref().unwrap_or_default(),
+            rtt_relay.as_deref().unwrap_or_default(),
+            rtt_direct.as_deref().unwrap_or_default(),
+            relay_paths.as_deref().unwrap_or_default(),
         ];
         if let Some(dbg_json) = debug_json.as_ref() {
             args.push(dbg_json.as_str());
@@ -2,3 +2,6 @@
 //!
 pub use mooselibtelioapp as moose;
 pub use mooselibtelioapp::Error;
+
+/// Files the events are kept in locally, besides the event database of the tracker
+pub const LOCAL_EVENT_FILES: &[&str] = &[];
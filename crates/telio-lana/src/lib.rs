@@ -13,6 +13,8 @@ pub mod event_log;
 /// Module containing moose callbacks
 pub mod moose_callbacks;
 
+pub mod schema;
+
 pub use event_log::*;
 
 /// App name used to initialize moose with
@@ -21,18 +23,25 @@ pub const LANA_APP_NAME: &str = "libtelio";
 /// Timeout for moose to send init error, if any, through callback
 pub const LANA_MOOSE_MAX_INIT_TIME: u8 = 10;
 
+/// Files kept by the tracker next to its event database
+const EVENT_DB_JOURNAL_SUFFIXES: [&str; 3] = ["-journal", "-wal", "-shm"];
+
 static MOOSE_INITIALIZED: AtomicBool = AtomicBool::new(false);
 const DEFAULT_ORDERING: Ordering = Ordering::SeqCst;
 
 /// Wrapper to call a moose function with optional arguments,
 /// returns the result of the call while also logging any error.
 ///
+/// The arguments are validated against the event schema first, an event not
+/// matching it is reported locally and not sent at all.
+///
 /// # Parameters:
 /// * func - Name of the moose function to call.
 /// * arg - Contains the arguments passed to the called function, if any.
 /// # Returns:
 /// * result - Moose function call result (successful or not).
 /// * Err() - moose::Error::NotInitiatedError if lana was not initialized.
+/// * Err() - moose::Error::EventLogError if the arguments do not match the schema.
 #[macro_export]
 macro_rules! lana {
     (
@@ -40,7 +49,7 @@ macro_rules! lana {
         $(,$arg:expr)*
     ) => {{
             if is_lana_initialized() {
-                let result = moose::$func($($arg),*);
+                let result = $crate::__lana_call!($func [] $($arg),*);
                 if let Some(error) = result.as_ref().err() {
                     telio_log_warn!(
                         "[Moose] Error: {} on call to `{}`",
@@ -55,6 +64,49 @@ macro_rules! lana {
     }};
 }
 
+/// Binds the arguments of a [`lana!`] call one at a time, so they can be
+/// validated before being moved into the moose function.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lana_call {
+    ($func:ident [$($bound:ident)*]) => {{
+        let valid = $crate::schema::validate(
+            stringify!($func),
+            &[$($crate::schema::SchemaValue::schema_value(&$bound)),*],
+        );
+        match valid {
+            Ok(()) => moose::$func($($bound),*),
+            Err(error) => {
+                $crate::report_schema_mismatch(stringify!($func), &error);
+                Err(moose::Error::EventLogError)
+            }
+        }
+    }};
+    ($func:ident [$($bound:ident)*] $arg:expr $(,$rest:expr)*) => {{
+        let arg = $arg;
+        $crate::__lana_call!($func [$($bound)* arg] $($rest),*)
+    }};
+}
+
+/// Report an event not matching the schema with a local log and an error event,
+/// instead of leaving it to be silently dropped server side.
+#[doc(hidden)]
+pub fn report_schema_mismatch(event: &str, error: &dyn std::fmt::Display) {
+    telio_log_error!(
+        "[Moose] `{}` does not match the event schema: {}",
+        event,
+        error
+    );
+    if let Err(e) = moose::send_developer_logging_log(
+        0,
+        moose::LibtelioappLogLevel::Error,
+        format!("Schema mismatch in `{event}`: {error}"),
+        None,
+    ) {
+        telio_log_warn!("[Moose] Failed to report schema mismatch: {}", e);
+    }
+}
+
 /// Initialize lana with the given configuration
 ///
 /// # Parameters:
@@ -74,6 +126,7 @@ pub fn init_lana(
             }
             ok => {
                 MOOSE_INITIALIZED.store(true, DEFAULT_ORDERING);
+                check_schema_version();
                 ok
             }
         }
@@ -102,6 +155,51 @@ pub fn deinit_lana() -> Result<moose::Result, moose::Error> {
     }
 }
 
+/// Deinitialize lana and remove the events it has not sent yet
+///
+/// Removes the event database along with the journal files of the tracker, so nothing collected
+/// so far is sent once lana is initialized again.
+///
+/// # Parameters:
+/// * event_path - path of the DB file where events are stored.
+pub fn purge_lana(event_path: &str) -> std::io::Result<()> {
+    let _ = deinit_lana();
+
+    let journals = EVENT_DB_JOURNAL_SUFFIXES
+        .iter()
+        .map(|suffix| format!("{event_path}{suffix}"));
+    for path in std::iter::once(event_path.to_owned())
+        .chain(journals)
+        .chain(LOCAL_EVENT_FILES.iter().map(|path| path.to_string()))
+    {
+        match std::fs::remove_file(&path) {
+            Ok(()) => telio_log_debug!("[Moose] Removed {}", path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                telio_log_warn!("[Moose] Failed to remove {}: {}", path, e);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Events are validated against the generated schema, which has to stay compatible
+/// with the schema of the tracker itself
+fn check_schema_version() {
+    let major = |version: &'static str| version.split('.').next();
+    if major(schema::SCHEMA_VERSION) != major(schema::TRACKER_SCHEMA_VERSION) {
+        report_schema_mismatch(
+            "moose_init",
+            &format!(
+                "tracker schema {} is incompatible with event schema {}",
+                schema::TRACKER_SCHEMA_VERSION,
+                schema::SCHEMA_VERSION
+            ),
+        );
+    }
+}
+
 /// Has lana been initialized, generally should not be called manually,
 /// is used to verify that the feature was enabled when using the lana! macro.
 ///
@@ -169,6 +267,7 @@ mod test {
         event_log::moose,
         init_lana, is_lana_initialized,
         moose::{ErrorCallback, InitCallback},
+        purge_lana, telio_log_warn,
     };
 
     pub static STUB: Mutex<Option<MooseStub>> = Mutex::new(None);
@@ -219,12 +318,16 @@ mod test {
         ) -> std::result::Result<moose::Result, moose::Error> {
             let should_call_init_cb = self.should_call_init_cb.clone();
             let init_cb_result = self.init_cb_result.clone();
-            self.init_cb_thread = Some(std::thread::spawn(move || {
-                if let Some(barrier) = should_call_init_cb {
-                    barrier.wait();
-                    init_cb.after_init(init_cb_result);
-                }
-            }));
+            let init_cb_thread = std::thread::Builder::new()
+                .name("telio-lana-init".to_owned())
+                .spawn(move || {
+                    if let Some(barrier) = should_call_init_cb {
+                        barrier.wait();
+                        init_cb.after_init(init_cb_result);
+                    }
+                })
+                .expect("Failed to spawn the init callback thread");
+            self.init_cb_thread = Some(init_cb_thread);
             if self.return_success {
                 Ok(moose::Result::Success)
             } else {
@@ -369,6 +472,54 @@ mod test {
         teardown();
     }
 
+    #[test]
+    #[serial]
+    fn test_purge_lana_removes_event_files() {
+        let event_path = std::env::temp_dir().join(format!("lana-purge-{}.db", std::process::id()));
+        let event_path = event_path.to_string_lossy().into_owned();
+        let files = [event_path.clone(), format!("{event_path}-wal")];
+        for file in &files {
+            std::fs::write(file, b"events").unwrap();
+        }
+
+        assert!(init_lana(event_path.clone(), "tests".to_string(), false).is_ok());
+        assert!(purge_lana(&event_path).is_ok());
+
+        assert!(!is_lana_initialized());
+        for file in &files {
+            assert!(!std::path::Path::new(file).exists());
+        }
+        // Nothing is left to remove the second time
+        assert!(purge_lana(&event_path).is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_lana_rejects_events_not_matching_schema() {
+        let result = init_lana("/event.db".to_string(), "tests".to_string(), false);
+        assert!(result.is_ok());
+
+        let result = lana!(
+            send_developer_logging_log,
+            1400,
+            moose::LibtelioappLogLevel::Info,
+            "PMTU".to_owned(),
+            Some("not json".to_owned())
+        );
+        assert!(matches!(result, Err(moose::Error::EventLogError)));
+
+        let result = lana!(
+            send_developer_logging_log,
+            1400,
+            moose::LibtelioappLogLevel::Info,
+            "PMTU".to_owned(),
+            None
+        );
+        assert!(result.is_ok());
+
+        teardown();
+    }
+
     #[test]
     #[serial]
     fn test_deinit_lana_uninitialized() {
@@ -0,0 +1,287 @@
+//! Local validation of events against the moose schema.
+//!
+//! The schema tables are generated at build time from `schema/libtelioapp.json`,
+//! so events which moose would reject are caught before they leave the client.
+
+use std::{borrow::Cow, fmt};
+
+use crate::moose::LibtelioappLogLevel;
+
+include!(concat!(env!("OUT_DIR"), "/schema.rs"));
+
+/// Schema of a single event or context setter
+#[derive(Debug)]
+pub struct EventSchema {
+    /// Name of the moose function
+    pub name: &'static str,
+    /// Arguments of the moose function, in order
+    pub fields: &'static [FieldSchema],
+}
+
+/// Schema of a single event field
+#[derive(Debug)]
+pub struct FieldSchema {
+    /// Name of the field in the schema
+    pub name: &'static str,
+    /// Expected value of the field
+    pub kind: FieldKind,
+    /// Whether the field may be left out
+    pub optional: bool,
+}
+
+/// Kind of values a field accepts
+#[derive(Debug)]
+pub enum FieldKind {
+    /// Any string
+    String,
+    /// 32 bit integer
+    Int,
+    /// Boolean
+    Bool,
+    /// String holding a JSON object
+    Json,
+    /// One of the listed values
+    Enum(&'static [&'static str]),
+}
+
+/// Value of an event argument, as seen by the validator
+#[derive(Debug)]
+pub enum Value<'a> {
+    /// Left out optional argument
+    Missing,
+    /// String argument
+    Text(Cow<'a, str>),
+    /// Integer argument
+    Int(i32),
+    /// Boolean argument
+    Bool(bool),
+}
+
+/// Conversion of moose function arguments into validated values
+pub trait SchemaValue {
+    /// Get the value to validate
+    fn schema_value(&self) -> Value<'_>;
+}
+
+impl SchemaValue for String {
+    fn schema_value(&self) -> Value<'_> {
+        Value::Text(Cow::Borrowed(self))
+    }
+}
+
+impl SchemaValue for i32 {
+    fn schema_value(&self) -> Value<'_> {
+        Value::Int(*self)
+    }
+}
+
+impl SchemaValue for bool {
+    fn schema_value(&self) -> Value<'_> {
+        Value::Bool(*self)
+    }
+}
+
+impl SchemaValue for LibtelioappLogLevel {
+    fn schema_value(&self) -> Value<'_> {
+        Value::Text(Cow::Owned(format!("{:?}", self).to_lowercase()))
+    }
+}
+
+impl<T: SchemaValue> SchemaValue for Option<T> {
+    fn schema_value(&self) -> Value<'_> {
+        self.as_ref()
+            .map_or(Value::Missing, SchemaValue::schema_value)
+    }
+}
+
+/// Mismatch between an event and the schema
+#[derive(Debug, PartialEq, Eq)]
+pub enum SchemaError {
+    /// Event was called with a different number of arguments
+    ArgumentCount {
+        /// Number of fields in the schema
+        expected: usize,
+        /// Number of arguments passed
+        actual: usize,
+    },
+    /// Argument does not fit the field
+    InvalidField {
+        /// Name of the field
+        field: &'static str,
+        /// What was wrong with the value
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaError::ArgumentCount { expected, actual } => {
+                write!(
+                    f,
+                    "expected {expected} arguments by schema {SCHEMA_VERSION}, got {actual}"
+                )
+            }
+            SchemaError::InvalidField { field, reason } => {
+                write!(f, "field `{field}` {reason} by schema {SCHEMA_VERSION}")
+            }
+        }
+    }
+}
+
+/// Validate arguments of the moose function `event`.
+///
+/// Functions which are not part of the schema, like `flush_changes`, are always valid.
+pub fn validate(event: &str, args: &[Value]) -> Result<(), SchemaError> {
+    let schema = match EVENTS.iter().find(|e| e.name == event) {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+
+    if schema.fields.len() != args.len() {
+        return Err(SchemaError::ArgumentCount {
+            expected: schema.fields.len(),
+            actual: args.len(),
+        });
+    }
+
+    for (field, arg) in schema.fields.iter().zip(args) {
+        let invalid = |reason| SchemaError::InvalidField {
+            field: field.name,
+            reason,
+        };
+        match (&field.kind, arg) {
+            (_, Value::Missing) if field.optional => (),
+            (_, Value::Missing) => return Err(invalid("is required")),
+            (FieldKind::String, Value::Text(_)) => (),
+            (FieldKind::Int, Value::Int(_)) => (),
+            (FieldKind::Bool, Value::Bool(_)) => (),
+            (FieldKind::Json, Value::Text(text)) => {
+                if !matches!(
+                    serde_json::from_str::<serde_json::Value>(text),
+                    Ok(serde_json::Value::Object(_))
+                ) {
+                    return Err(invalid("is not a JSON object"));
+                }
+            }
+            (FieldKind::Enum(values), Value::Text(text)) => {
+                let text: &str = text;
+                if !values.contains(&text) {
+                    return Err(invalid("has a value not allowed"));
+                }
+            }
+            _ => return Err(invalid("has a wrong type")),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat_args<'a>(debug_json: &'a Option<String>) -> Vec<Value<'a>> {
+        let mut args: Vec<Value> = (0..7).map(|_| Value::Text(Cow::Borrowed(""))).collect();
+        args.push(Value::Int(3600));
+        args.push(Value::Int(0));
+        args.push(Value::Text(Cow::Borrowed("")));
+        args.push(Value::Text(Cow::Borrowed("")));
+        args.push(Value::Missing);
+        args.push(Value::Missing);
+        args.push(Value::Missing);
+        args.push(Value::Missing);
+        args.push(Value::Missing);
+        args.push(debug_json.schema_value());
+        args
+    }
+
+    #[test]
+    fn valid_heartbeat_passes() {
+        for debug_json in [None, Some(r#"{"extra":"10:20"}"#.to_owned())] {
+            assert_eq!(
+                validate(
+                    "send_serviceQuality_node_heartbeat",
+                    &heartbeat_args(&debug_json)
+                ),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_debug_json_is_caught() {
+        let debug_json = Some("extra=10".to_owned());
+        assert_eq!(
+            validate(
+                "send_serviceQuality_node_heartbeat",
+                &heartbeat_args(&debug_json)
+            ),
+            Err(SchemaError::InvalidField {
+                field: "debug_json",
+                reason: "is not a JSON object"
+            })
+        );
+    }
+
+    #[test]
+    fn argument_count_mismatch_is_caught() {
+        let debug_json = None;
+        let mut args = heartbeat_args(&debug_json);
+        args.pop();
+        assert_eq!(
+            validate("send_serviceQuality_node_disconnect", &args),
+            Err(SchemaError::ArgumentCount {
+                expected: 17,
+                actual: 16
+            })
+        );
+    }
+
+    #[test]
+    fn enum_and_type_mismatches_are_caught() {
+        let message = "PMTU".to_owned();
+        assert_eq!(
+            validate(
+                "send_developer_logging_log",
+                &[
+                    Value::Int(1400),
+                    LibtelioappLogLevel::Info.schema_value(),
+                    message.schema_value(),
+                    Value::Missing,
+                ]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validate(
+                "send_developer_logging_log",
+                &[
+                    Value::Int(1400),
+                    Value::Text(Cow::Borrowed("verbose")),
+                    message.schema_value(),
+                    Value::Missing,
+                ]
+            ),
+            Err(SchemaError::InvalidField {
+                field: "logLevel",
+                reason: "has a value not allowed"
+            })
+        );
+        assert_eq!(
+            validate(
+                "set_context_application_libtelioapp_config_currentState_meshnetEnabled",
+                &[message.schema_value()]
+            ),
+            Err(SchemaError::InvalidField {
+                field: "meshnetEnabled",
+                reason: "has a wrong type"
+            })
+        );
+    }
+
+    #[test]
+    fn events_outside_of_schema_pass() {
+        assert_eq!(validate("flush_changes", &[]), Ok(()));
+    }
+}
@@ -15,6 +15,9 @@ use std::{
 
 use telio_crypto::PublicKey;
 
+mod builder;
+pub use builder::{ConfigBuildError, ConfigBuilder, PeerBuilder};
+
 const MAX_CONFIG_LENGTH: usize = 16 * 1024 * 1024;
 
 /// Characterstics descriping a peer
@@ -30,6 +33,82 @@ pub struct PeerBase {
     pub ip_addresses: Option<Vec<IpAddr>>,
     /// Nickname for the peer
     pub nickname: Option<Hidden<String>>,
+    #[serde(default)]
+    /// Device metadata, missing for peers which predate it
+    pub metadata: Option<PeerMetadata>,
+}
+
+impl PeerBase {
+    /// Check if the peer advertises the capability.
+    ///
+    /// Peers without metadata are running an older version and are assumed to
+    /// support none of the optional capabilities.
+    pub fn supports(&self, capability: PeerCapability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+
+    /// Capabilities advertised by the peer, none for the peers without metadata
+    pub fn capabilities(&self) -> &[PeerCapability] {
+        self.metadata
+            .as_ref()
+            .map_or(&[], |m| m.capabilities.as_slice())
+    }
+}
+
+/// Capabilities which can be used with a peer advertising `remote`, given the ones supported locally.
+///
+/// The advertised capabilities may come from the peer config or from the control messages of the
+/// peer itself.
+pub fn negotiate_capabilities(
+    local: &[PeerCapability],
+    remote: &[PeerCapability],
+) -> Vec<PeerCapability> {
+    local
+        .iter()
+        .copied()
+        .filter(|c| *c != PeerCapability::Unknown && remote.contains(c))
+        .unique()
+        .collect()
+}
+
+/// Information about the device running a peer
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerMetadata {
+    #[serde(default)]
+    /// Operating system of the device
+    pub os: Option<String>,
+    #[serde(default)]
+    /// Version of the application running on the device
+    pub app_version: Option<String>,
+    #[serde(default)]
+    /// Optional telio capabilities supported by the device
+    pub capabilities: Vec<PeerCapability>,
+    #[serde(default)]
+    /// Hardware address the device is woken up by over its LAN, e.g. "aa:bb:cc:dd:ee:ff"
+    pub mac_address: Option<String>,
+}
+
+/// Optional telio capability, which may be missing on older peers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerCapability {
+    /// Meshnet traffic over IPv6
+    Ipv6,
+    /// Multicast forwarding
+    Multicast,
+    /// Post-quantum key exchange
+    PostQuantum,
+    /// Sends Wake-on-LAN magic packets to its LAN on behalf of the other nodes
+    WakeOnLan,
+    /// Concurrent upgrades are resolved by the winning session of the upgrade decision
+    UpgradeRaceResolution,
+    /// Coordinated hole punching with punch requests
+    PunchRequest,
+    /// Large control messages relayed through Derp may be compressed
+    Compression,
+    /// Capability introduced by a newer version, never negotiated
+    #[serde(other)]
+    Unknown,
 }
 
 /// Description of a peer
@@ -57,6 +136,37 @@ pub struct Peer {
     pub peer_allows_multicast: bool,
 }
 
+/// Permissions a meshnet namespace grants at most to its peers, on top of their own flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, SmartDefault)]
+pub struct MeshnetNamespacePolicy {
+    #[default(true)]
+    /// Peers of the namespace may connect to us
+    pub allow_incoming_connections: bool,
+    #[default(true)]
+    /// Peers of the namespace may route traffic through us
+    pub allow_peer_traffic_routing: bool,
+    #[default(true)]
+    /// Peers of the namespace may access our local network
+    pub allow_peer_local_network_access: bool,
+    #[default(true)]
+    /// Peers of the namespace may send us files
+    pub allow_peer_send_files: bool,
+    #[default(true)]
+    /// Multicast messages from peers of the namespace are accepted
+    pub allow_multicast: bool,
+}
+
+impl MeshnetNamespacePolicy {
+    /// Restrict the permissions of the peer to the ones allowed by the policy
+    pub fn restrict(&self, peer: &mut Peer) {
+        peer.allow_incoming_connections &= self.allow_incoming_connections;
+        peer.allow_peer_traffic_routing &= self.allow_peer_traffic_routing;
+        peer.allow_peer_local_network_access &= self.allow_peer_local_network_access;
+        peer.allow_peer_send_files &= self.allow_peer_send_files;
+        peer.allow_multicast &= self.allow_multicast;
+    }
+}
+
 /// Representation of DNS configuration
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DnsConfig {
@@ -75,6 +185,8 @@ pub enum RelayState {
     Connecting,
     /// Connected to the Derp server
     Connected,
+    /// Connected to the Derp server, but the connection keeps dropping
+    Degraded,
 }
 
 /// Representation of a server, which might be used
@@ -447,6 +559,7 @@ mod tests {
                 hostname: telio_utils::Hidden("everest-alice.nord".to_owned()),
                 ip_addresses: Some(vec!["198.51.100.42".parse().unwrap()]),
                 nickname: Some(telio_utils::Hidden("bunnyg".to_owned())),
+                metadata: None,
             },
             peers: Some(vec![
                 Peer {
@@ -458,6 +571,7 @@ mod tests {
                         hostname: telio_utils::Hidden("everest-bob.nord".to_owned()),
                         ip_addresses: Some(vec!["198.51.100.43".parse().unwrap()]),
                         nickname: Some(telio_utils::Hidden("".to_owned())),
+                        metadata: None,
                     },
                     is_local: true,
                     allow_incoming_connections: true,
@@ -476,6 +590,7 @@ mod tests {
                         hostname: telio_utils::Hidden("everest-alice.nord".to_owned()),
                         ip_addresses: Some(vec!["198.51.100.43".parse().unwrap()]),
                         nickname: None,
+                        metadata: None,
                     },
                     is_local: false,
                     allow_incoming_connections: false,
@@ -511,4 +626,67 @@ mod tests {
         assert_eq!(peer_deserialization_failure_count, 3);
         assert_eq!(full_config, expected_config);
     }
+
+    #[test]
+    fn peer_metadata_tolerates_unknown_capabilities() {
+        let json = r#"
+            {
+              "identifier": "98e00fa1-2c83-4e85-bf01-45c1d4eefea6",
+              "public_key": "LRrbraNJXOrVdnpXy6gA/XcpmxymE0oMZlzP5Pqi20I=",
+              "hostname": "everest-bob.nord",
+              "metadata": {
+                "os": "linux",
+                "app_version": "4.2.0",
+                "capabilities": ["ipv6", "teleportation", "post_quantum", "wake_on_lan"],
+                "mac_address": "aa:bb:cc:dd:ee:ff"
+              }
+            }
+        "#;
+
+        let peer: PeerBase = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            peer.metadata,
+            Some(PeerMetadata {
+                os: Some("linux".to_owned()),
+                app_version: Some("4.2.0".to_owned()),
+                capabilities: vec![
+                    PeerCapability::Ipv6,
+                    PeerCapability::Unknown,
+                    PeerCapability::PostQuantum,
+                    PeerCapability::WakeOnLan
+                ],
+                mac_address: Some("aa:bb:cc:dd:ee:ff".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn capabilities_are_negotiated() {
+        let local = [
+            PeerCapability::Ipv6,
+            PeerCapability::Multicast,
+            PeerCapability::Unknown,
+        ];
+
+        let legacy = PeerBase::default();
+        assert!(!legacy.supports(PeerCapability::Ipv6));
+        assert!(negotiate_capabilities(&local, legacy.capabilities()).is_empty());
+
+        let peer = PeerBase {
+            metadata: Some(PeerMetadata {
+                capabilities: vec![
+                    PeerCapability::Multicast,
+                    PeerCapability::PostQuantum,
+                    PeerCapability::Unknown,
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(peer.supports(PeerCapability::PostQuantum));
+        assert_eq!(
+            negotiate_capabilities(&local, peer.capabilities()),
+            vec![PeerCapability::Multicast]
+        );
+    }
 }
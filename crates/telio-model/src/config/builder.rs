@@ -0,0 +1,325 @@
+//! Builders for assembling meshnet configs programmatically
+//!
+//! Configs coming from the backend are deserialized from JSON as they are. Configs built here
+//! are validated instead, so mistakes in tests or in embedded controllers surface early, not as
+//! peers which silently fail to connect.
+
+use std::{collections::HashSet, net::IpAddr};
+
+use telio_crypto::PublicKey;
+use telio_utils::Hidden;
+use thiserror::Error;
+
+use super::{Config, DnsConfig, Peer, PeerBase, PeerMetadata, Server};
+use crate::validation::validate_nickname;
+
+/// Reasons for a built config to be rejected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConfigBuildError {
+    /// Node has an empty identifier
+    #[error("Node {0:?} has an empty identifier")]
+    EmptyIdentifier(PublicKey),
+    /// Node has an empty hostname
+    #[error("Node {0:?} has an empty hostname")]
+    EmptyHostname(PublicKey),
+    /// Node has a nickname which does not pass the validation
+    #[error("Node {0:?} has an invalid nickname")]
+    InvalidNickname(PublicKey),
+    /// Public key is used by more than one node
+    #[error("Public key {0:?} is used by more than one node")]
+    DuplicatePublicKey(PublicKey),
+    /// Ip address is assigned to more than one node
+    #[error("Ip address {0} is assigned to more than one node")]
+    DuplicateIpAddress(IpAddr),
+}
+
+/// Builder of a [Config], validated on [ConfigBuilder::build]
+#[derive(Clone, Debug)]
+pub struct ConfigBuilder {
+    this: PeerBase,
+    peers: Option<Vec<Peer>>,
+    derp_servers: Option<Vec<Server>>,
+    dns: Option<DnsConfig>,
+}
+
+impl ConfigBuilder {
+    /// Start a config of the local node
+    pub fn new(identifier: impl Into<String>, public_key: PublicKey, hostname: &str) -> Self {
+        Self {
+            this: new_peer_base(identifier.into(), public_key, hostname),
+            peers: None,
+            derp_servers: None,
+            dns: None,
+        }
+    }
+
+    /// Set the meshnet ip addresses of the local node
+    pub fn ip_addresses(mut self, ip_addresses: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.this.ip_addresses = Some(ip_addresses.into_iter().collect());
+        self
+    }
+
+    /// Set the nickname of the local node
+    pub fn nickname(mut self, nickname: &str) -> Self {
+        self.this.nickname = Some(Hidden(nickname.to_owned()));
+        self
+    }
+
+    /// Set the device metadata of the local node
+    pub fn metadata(mut self, metadata: PeerMetadata) -> Self {
+        self.this.metadata = Some(metadata);
+        self
+    }
+
+    /// Add a peer, see [PeerBuilder]
+    pub fn peer(mut self, peer: Peer) -> Self {
+        self.peers.get_or_insert_with(Vec::new).push(peer);
+        self
+    }
+
+    /// Add a relay server
+    pub fn derp_server(mut self, server: Server) -> Self {
+        self.derp_servers.get_or_insert_with(Vec::new).push(server);
+        self
+    }
+
+    /// Set the DNS servers of the meshnet
+    pub fn dns_servers(mut self, dns_servers: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.dns = Some(DnsConfig {
+            dns_servers: Some(dns_servers.into_iter().collect()),
+        });
+        self
+    }
+
+    /// Validate and build the config
+    pub fn build(self) -> Result<Config, ConfigBuildError> {
+        let config = Config {
+            this: self.this,
+            peers: self.peers,
+            derp_servers: self.derp_servers,
+            dns: self.dns,
+        };
+
+        let nodes = std::iter::once(&config.this)
+            .chain(config.peers.iter().flatten().map(|peer| &peer.base));
+        let mut public_keys = HashSet::new();
+        let mut ip_addresses = HashSet::new();
+        for node in nodes {
+            validate_peer_base(node)?;
+            if !public_keys.insert(node.public_key) {
+                return Err(ConfigBuildError::DuplicatePublicKey(node.public_key));
+            }
+            for ip in node.ip_addresses.iter().flatten() {
+                if !ip_addresses.insert(*ip) {
+                    return Err(ConfigBuildError::DuplicateIpAddress(*ip));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+/// Builder of a [Peer], with all the permissions denied unless allowed explicitly
+#[derive(Clone, Debug)]
+pub struct PeerBuilder {
+    peer: Peer,
+}
+
+impl PeerBuilder {
+    /// Start a description of the peer
+    pub fn new(identifier: impl Into<String>, public_key: PublicKey, hostname: &str) -> Self {
+        Self {
+            peer: Peer {
+                base: new_peer_base(identifier.into(), public_key, hostname),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Set the meshnet ip addresses of the peer
+    pub fn ip_addresses(mut self, ip_addresses: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.peer.base.ip_addresses = Some(ip_addresses.into_iter().collect());
+        self
+    }
+
+    /// Set the nickname of the peer
+    pub fn nickname(mut self, nickname: &str) -> Self {
+        self.peer.base.nickname = Some(Hidden(nickname.to_owned()));
+        self
+    }
+
+    /// Set the device metadata of the peer
+    pub fn metadata(mut self, metadata: PeerMetadata) -> Self {
+        self.peer.base.metadata = Some(metadata);
+        self
+    }
+
+    /// Mark the peer as belonging to the same account as the local node
+    pub fn is_local(mut self, is_local: bool) -> Self {
+        self.peer.is_local = is_local;
+        self
+    }
+
+    /// Allow the peer to connect to the local node
+    pub fn allow_incoming_connections(mut self, allow: bool) -> Self {
+        self.peer.allow_incoming_connections = allow;
+        self
+    }
+
+    /// Allow the peer to route its traffic through the local node
+    pub fn allow_peer_traffic_routing(mut self, allow: bool) -> Self {
+        self.peer.allow_peer_traffic_routing = allow;
+        self
+    }
+
+    /// Allow the peer to access the local network of the local node
+    pub fn allow_peer_local_network_access(mut self, allow: bool) -> Self {
+        self.peer.allow_peer_local_network_access = allow;
+        self
+    }
+
+    /// Allow the peer to send files to the local node
+    pub fn allow_peer_send_files(mut self, allow: bool) -> Self {
+        self.peer.allow_peer_send_files = allow;
+        self
+    }
+
+    /// Accept multicast messages from the peer
+    pub fn allow_multicast(mut self, allow: bool) -> Self {
+        self.peer.allow_multicast = allow;
+        self
+    }
+
+    /// Mark the peer as accepting multicast messages from the local node
+    pub fn peer_allows_multicast(mut self, allow: bool) -> Self {
+        self.peer.peer_allows_multicast = allow;
+        self
+    }
+
+    /// Validate and build the peer
+    pub fn build(self) -> Result<Peer, ConfigBuildError> {
+        validate_peer_base(&self.peer.base)?;
+        Ok(self.peer)
+    }
+}
+
+fn new_peer_base(identifier: String, public_key: PublicKey, hostname: &str) -> PeerBase {
+    PeerBase {
+        identifier,
+        public_key,
+        hostname: Hidden(hostname.to_owned()),
+        ..Default::default()
+    }
+}
+
+fn validate_peer_base(base: &PeerBase) -> Result<(), ConfigBuildError> {
+    if base.identifier.is_empty() {
+        return Err(ConfigBuildError::EmptyIdentifier(base.public_key));
+    }
+    if base.hostname.is_empty() {
+        return Err(ConfigBuildError::EmptyHostname(base.public_key));
+    }
+    if let Some(nickname) = &base.nickname {
+        if !validate_nickname(nickname) {
+            return Err(ConfigBuildError::InvalidNickname(base.public_key));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::Ipv4Addr;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey([i; 32])
+    }
+
+    fn ip(i: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(100, 64, 0, i))
+    }
+
+    #[test]
+    fn built_config_matches_the_deserialized_one() {
+        let peer = PeerBuilder::new("bob", pk(2), "bob.nord")
+            .ip_addresses([ip(2)])
+            .nickname("bobby")
+            .allow_incoming_connections(true)
+            .allow_peer_send_files(true)
+            .build()
+            .unwrap();
+        let config = ConfigBuilder::new("alice", pk(1), "alice.nord")
+            .ip_addresses([ip(1)])
+            .peer(peer)
+            .dns_servers([ip(10)])
+            .build()
+            .unwrap();
+
+        let json = format!(
+            r#"{{
+                "identifier": "alice",
+                "public_key": "{}",
+                "hostname": "alice.nord",
+                "ip_addresses": ["100.64.0.1"],
+                "peers": [{{
+                    "identifier": "bob",
+                    "public_key": "{}",
+                    "hostname": "bob.nord",
+                    "ip_addresses": ["100.64.0.2"],
+                    "nickname": "bobby",
+                    "is_local": false,
+                    "allow_incoming_connections": true,
+                    "allow_peer_traffic_routing": false,
+                    "allow_peer_local_network_access": false,
+                    "allow_peer_send_files": true
+                }}],
+                "dns": {{"dns_servers": ["100.64.0.10"]}}
+            }}"#,
+            pk(1),
+            pk(2)
+        );
+        assert_eq!(Config::new_from_str(&json).unwrap(), (config, 0));
+    }
+
+    #[test]
+    fn invalid_nodes_are_rejected() {
+        assert_eq!(
+            PeerBuilder::new("", pk(2), "bob.nord").build(),
+            Err(ConfigBuildError::EmptyIdentifier(pk(2)))
+        );
+        assert_eq!(
+            PeerBuilder::new("bob", pk(2), "").build(),
+            Err(ConfigBuildError::EmptyHostname(pk(2)))
+        );
+        assert_eq!(
+            ConfigBuilder::new("alice", pk(1), "alice.nord")
+                .nickname("alice--")
+                .build(),
+            Err(ConfigBuildError::InvalidNickname(pk(1)))
+        );
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        let bob = |public_key, ip_address| {
+            PeerBuilder::new("bob", public_key, "bob.nord")
+                .ip_addresses([ip_address])
+                .build()
+                .unwrap()
+        };
+        let alice = ConfigBuilder::new("alice", pk(1), "alice.nord").ip_addresses([ip(1)]);
+
+        assert_eq!(
+            alice.clone().peer(bob(pk(1), ip(2))).build(),
+            Err(ConfigBuildError::DuplicatePublicKey(pk(1)))
+        );
+        assert_eq!(
+            alice.clone().peer(bob(pk(2), ip(1))).build(),
+            Err(ConfigBuildError::DuplicateIpAddress(ip(1)))
+        );
+        assert!(alice.peer(bob(pk(2), ip(2))).build().is_ok());
+    }
+}
@@ -1,10 +1,14 @@
 //! Event reporting module
 
 use super::mesh::Node;
+use ipnet::IpNet;
 use modifier::Modifier;
 use serde::Serialize;
+use std::net::IpAddr;
+use telio_crypto::PublicKey;
 
 use crate::config::Server as Relay;
+use crate::features::HaRole;
 
 pub use modifier::Set;
 
@@ -61,6 +65,304 @@ pub struct Error {
     pub msg: EventMsg,
 }
 
+/// Peer rekey event. Used to inform that a node has been replaced by one with a different public
+/// key but the same meshnet addresses, and all state of the old key has been dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerRekeyed {
+    /// Identifier of the node after the change
+    pub identifier: String,
+    /// Public key the node was using before
+    pub old_public_key: PublicKey,
+    /// Public key the node is using from now on
+    pub new_public_key: PublicKey,
+    /// Meshnet addresses shared by both keys
+    pub ip_addresses: Vec<IpAddr>,
+}
+
+/// Best-effort guess of why a peer could not be reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnreachableCause {
+    /// Device has no usable network interfaces
+    NoNetwork,
+    /// Connection to the relay server is down
+    RelayDown,
+    /// Relay reports the peer as offline
+    PeerOffline,
+    /// Handshakes are sent, but nothing comes back from the peer's endpoint
+    EndpointUnreachable,
+    /// Peer is online but does not answer handshakes, keys may be out of sync
+    PossibleKeyMismatch,
+    /// None of the known causes apply
+    Unknown,
+}
+
+/// Peer unreachable event. Used to inform that a node did not finish a handshake within the
+/// configured time, along with the most likely cause.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerUnreachable {
+    /// Identifier of the node
+    pub identifier: String,
+    /// Public key of the node
+    pub public_key: PublicKey,
+    /// Is the node a VPN server
+    pub is_vpn: bool,
+    /// Most likely cause
+    pub cause: UnreachableCause,
+    /// For how long the node has been connecting, in seconds
+    pub connecting_for_s: u64,
+}
+
+/// MTU changed event. Used to inform that the tunnel interface uses a new MTU, along with the
+/// largest TCP segments fitting into it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MtuChanged {
+    /// New MTU of the tunnel interface
+    pub mtu: u16,
+    /// MSS of IPv4 TCP connections over the tunnel
+    pub mss_ipv4: u16,
+    /// MSS of IPv6 TCP connections over the tunnel
+    pub mss_ipv6: u16,
+}
+
+/// State of the background activity of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceState {
+    /// Probing, analytics and keepalives are stopped, the adapter stays configured
+    Suspended,
+    /// Background activity is running again
+    Resumed,
+}
+
+/// Maintenance state changed event. Used to inform that the device was suspended or resumed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MaintenanceStateChanged {
+    /// New state of the device
+    pub state: MaintenanceState,
+}
+
+/// Stage of the automatic recovery of the adapter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdapterRecoveryState {
+    /// Adapter failed fatally and is being recreated
+    Recovering,
+    /// Adapter was recreated and the last config applied again. The addresses and routes of the
+    /// interface are only restored on Linux, elsewhere the app has to apply them again.
+    Recovered,
+    /// Adapter could not be recreated in time
+    Failed,
+}
+
+/// Adapter recovery event. Used to inform that the adapter failed fatally and is being recreated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AdapterRecovery {
+    /// Stage of the recovery
+    pub state: AdapterRecoveryState,
+}
+
+/// Step of the remediation of meshnet stuck without connected peers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemediationStep {
+    /// Relay connection is being reconnected
+    RelayReconnect,
+    /// Endpoints of the peers are being rediscovered
+    EndpointRediscovery,
+    /// Peers are being removed from and added back to the adapter, forcing new handshakes
+    AdapterRehandshake,
+    /// A peer has connected again
+    Recovered,
+    /// All of the steps were taken and none of the peers has connected
+    Exhausted,
+}
+
+/// Mesh remediation event. Used to inform about the steps taken by the watchdog when meshnet is
+/// stuck without any connected peers while the network is up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MeshRemediation {
+    /// Step which was taken
+    pub step: RemediationStep,
+    /// Time meshnet has been without connected peers, in seconds
+    pub stuck_for_s: u64,
+}
+
+/// High availability role change event. Used to inform that this instance took over as the
+/// primary of the pair, or stepped down to the standby after hearing a newer primary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct HaRoleChanged {
+    /// Role of this instance from now on
+    pub role: HaRole,
+    /// Term of the primary, increased by every takeover
+    pub term: u64,
+}
+
+/// Route conflict event. Used to warn that the allowed IPs of a peer overlap a network the host
+/// is attached to, so either the peer or the local network may be unreachable.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RouteConflict {
+    /// Public key of the peer
+    pub public_key: PublicKey,
+    /// Allowed IPs of the peer overlapping the local network
+    pub allowed_ips: IpNet,
+    /// Local network of the host
+    pub local_network: IpNet,
+    /// Name of the interface attached to the local network
+    pub interface_name: String,
+    /// Whether the local network was cut out of the allowed IPs of the peer
+    pub excluded: bool,
+}
+
+/// Outcome of the handshakes with a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HandshakeResult {
+    /// Handshake completed and the session with the peer is established
+    Completed,
+    /// No handshake completed within the failure timeout
+    Failed,
+}
+
+/// Peer handshake event. Used to inform when a handshake with a peer completes, or when the peer
+/// has been failing to complete one, so apps can show the connecting and connected states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerHandshake {
+    /// Public key of the peer
+    pub public_key: PublicKey,
+    /// Outcome of the handshakes
+    pub result: HandshakeResult,
+    /// Time since the peer started waiting for a session, in milliseconds. Zero when the session
+    /// was already established once the peer was first polled.
+    pub elapsed_ms: u64,
+}
+
+/// Analytics consent change event. Used to acknowledge that the collection of analytics was
+/// started or stopped, and whether the data collected so far was removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AnalyticsConsentChanged {
+    /// Whether analytics are collected from now on
+    pub consent: bool,
+    /// Whether all of the analytics not sent yet were removed, including the stored events
+    pub purged: bool,
+}
+
+/// Config rollback event. Used to inform that applying a new meshnet config failed midway and
+/// the previous one was restored.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ConfigRollback {
+    /// Why the new config could not be applied
+    pub cause: String,
+}
+
+/// Transport protocol of a connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionProtocol {
+    /// TCP connection, opened by a SYN
+    Tcp,
+    /// UDP flow, opened by its first packet
+    Udp,
+}
+
+/// Inbound connection event. Used to inform that a meshnet peer opened a new connection to this
+/// device, which was allowed through the firewall.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InboundConnection {
+    /// Identifier of the node
+    pub identifier: String,
+    /// Public key of the node
+    pub public_key: PublicKey,
+    /// Hostname of the node
+    pub hostname: String,
+    /// Transport protocol of the connection
+    pub protocol: ConnectionProtocol,
+    /// Local port the connection is destined to
+    pub port: u16,
+}
+
+/// DNS records invalidated event. Used to inform that meshnet names stopped resolving or
+/// resolve to other addresses, so apps can flush them from the DNS cache of the OS instead of
+/// waiting for their TTL to expire.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DnsRecordsInvalidated {
+    /// Names removed from the `.nord` zone or whose addresses changed
+    pub names: Vec<String>,
+}
+
+/// Change of the identities with access to the meshnet of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Key of the peer was installed on the interface
+    PeerInstalled,
+    /// Key of the peer was removed from the interface, as the peer lost its access
+    PeerRemoved,
+    /// Meshnet config was set, after running without one
+    MeshnetEnabled,
+    /// Meshnet config was cleared
+    MeshnetDisabled,
+    /// Meshnet config was replaced by a different one
+    MeshnetConfigChanged,
+    /// Exit node was connected, replacing the previous one if any
+    ExitNodeConnected,
+    /// Exit node was disconnected
+    ExitNodeDisconnected,
+}
+
+/// Audit record event. Used to keep a trail of the peer keys with access to the device and of
+/// the config they came from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    /// Time of the change, in milliseconds since the UNIX epoch
+    pub timestamp_ms: u64,
+    /// What changed
+    pub action: AuditAction,
+    /// Peer installed or removed, or the exit node connected or disconnected
+    pub public_key: Option<PublicKey>,
+}
+
+/// Ping probe event. Used to report the outcome of every echo request sent by `Device::ping` to a
+/// meshnet peer through the tunnel.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PingProbe {
+    /// Public key of the pinged peer
+    pub public_key: PublicKey,
+    /// Meshnet IP address of the peer the echo request was sent to
+    pub address: IpAddr,
+    /// Sequence number of the probe, starting from 0
+    pub sequence: u32,
+    /// Round trip time in microseconds, `None` if no reply came in time
+    pub rtt_us: Option<u64>,
+}
+
+/// Traffic counters of a peer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerStats {
+    /// Public key of the peer
+    pub public_key: PublicKey,
+    /// Bytes received from the peer
+    pub rx_bytes: u64,
+    /// Bytes sent to the peer
+    pub tx_bytes: u64,
+}
+
+/// Peer stats event. Used to push the traffic counters of the peers periodically.
+///
+/// A full report lists all of the peers with their totals. The reports in between list only the
+/// peers whose counters changed, with the bytes since the previous report, and the peers removed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerStatsReport {
+    /// Sequence number of the report, starting from 0, a gap means a report was missed
+    pub sequence: u64,
+    /// Whether the counters are totals rather than changes since the previous report
+    pub full: bool,
+    /// Peers along with their counters
+    pub peers: Vec<PeerStats>,
+    /// Peers removed since the previous report
+    pub removed: Vec<PublicKey>,
+}
+
 /// Used for the constructing `Event` object.
 /// Adding another `Event` type, that type should implement this trait,
 /// for the ability to be constructed, but not used outside of this module.
@@ -87,6 +389,102 @@ impl MakeEvent for Node {
     }
 }
 
+impl MakeEvent for PeerRekeyed {
+    fn make() -> EventBuilder {
+        EventBuilder::PeerRekeyed { body: None }
+    }
+}
+
+impl MakeEvent for PeerUnreachable {
+    fn make() -> EventBuilder {
+        EventBuilder::PeerUnreachable { body: None }
+    }
+}
+
+impl MakeEvent for MtuChanged {
+    fn make() -> EventBuilder {
+        EventBuilder::MtuChanged { body: None }
+    }
+}
+
+impl MakeEvent for InboundConnection {
+    fn make() -> EventBuilder {
+        EventBuilder::InboundConnection { body: None }
+    }
+}
+
+impl MakeEvent for MaintenanceStateChanged {
+    fn make() -> EventBuilder {
+        EventBuilder::MaintenanceStateChanged { body: None }
+    }
+}
+
+impl MakeEvent for ConfigRollback {
+    fn make() -> EventBuilder {
+        EventBuilder::ConfigRollback { body: None }
+    }
+}
+
+impl MakeEvent for AdapterRecovery {
+    fn make() -> EventBuilder {
+        EventBuilder::AdapterRecovery { body: None }
+    }
+}
+
+impl MakeEvent for MeshRemediation {
+    fn make() -> EventBuilder {
+        EventBuilder::MeshRemediation { body: None }
+    }
+}
+
+impl MakeEvent for HaRoleChanged {
+    fn make() -> EventBuilder {
+        EventBuilder::HaRoleChanged { body: None }
+    }
+}
+
+impl MakeEvent for RouteConflict {
+    fn make() -> EventBuilder {
+        EventBuilder::RouteConflict { body: None }
+    }
+}
+
+impl MakeEvent for PeerHandshake {
+    fn make() -> EventBuilder {
+        EventBuilder::PeerHandshake { body: None }
+    }
+}
+
+impl MakeEvent for AnalyticsConsentChanged {
+    fn make() -> EventBuilder {
+        EventBuilder::AnalyticsConsentChanged { body: None }
+    }
+}
+
+impl MakeEvent for DnsRecordsInvalidated {
+    fn make() -> EventBuilder {
+        EventBuilder::DnsRecordsInvalidated { body: None }
+    }
+}
+
+impl MakeEvent for AuditRecord {
+    fn make() -> EventBuilder {
+        EventBuilder::AuditRecord { body: None }
+    }
+}
+
+impl MakeEvent for PingProbe {
+    fn make() -> EventBuilder {
+        EventBuilder::PingProbe { body: None }
+    }
+}
+
+impl MakeEvent for PeerStatsReport {
+    fn make() -> EventBuilder {
+        EventBuilder::PeerStatsReport { body: None }
+    }
+}
+
 /// Main object of `Event`. See `Event::new()` for init options.
 #[derive(Clone, Debug, Serialize)]
 #[serde(tag = "type")]
@@ -108,6 +506,108 @@ pub enum Event {
         /// Error type event
         body: Error,
     },
+    /// Used to report that a node has changed its public key
+    #[serde(rename = "peer_rekeyed")]
+    PeerRekeyed {
+        /// Peer rekey type event
+        body: PeerRekeyed,
+    },
+    /// Used to report that a node could not be connected to in time
+    #[serde(rename = "peer_unreachable")]
+    PeerUnreachable {
+        /// Peer unreachable type event
+        body: PeerUnreachable,
+    },
+    /// Used to report that the MTU of the tunnel has changed
+    #[serde(rename = "mtu_changed")]
+    MtuChanged {
+        /// MTU changed type event
+        body: MtuChanged,
+    },
+    /// Used to report every state change of a Node before debouncing, for diagnostics
+    #[serde(rename = "node_transition")]
+    NodeTransition {
+        /// Node type event
+        body: Node,
+    },
+    /// Used to report that a meshnet peer opened a new connection to this device
+    #[serde(rename = "inbound_connection")]
+    InboundConnection {
+        /// Inbound connection type event
+        body: InboundConnection,
+    },
+    /// Used to report that the device was suspended or resumed
+    #[serde(rename = "maintenance_state_changed")]
+    MaintenanceStateChanged {
+        /// Maintenance state changed type event
+        body: MaintenanceStateChanged,
+    },
+    /// Used to report that a new meshnet config was rolled back
+    #[serde(rename = "config_rollback")]
+    ConfigRollback {
+        /// Config rollback type event
+        body: ConfigRollback,
+    },
+    /// Used to report the progress of the automatic adapter recovery
+    #[serde(rename = "adapter_recovery")]
+    AdapterRecovery {
+        /// Adapter recovery type event
+        body: AdapterRecovery,
+    },
+    /// Used to report the steps taken to remediate meshnet without connected peers
+    #[serde(rename = "mesh_remediation")]
+    MeshRemediation {
+        /// Mesh remediation type event
+        body: MeshRemediation,
+    },
+    /// Used to report the role changes of a high availability pair
+    #[serde(rename = "ha_role_changed")]
+    HaRoleChanged {
+        /// High availability role change type event
+        body: HaRoleChanged,
+    },
+    /// Used to warn about the allowed IPs overlapping the local networks
+    #[serde(rename = "route_conflict")]
+    RouteConflict {
+        /// Route conflict type event
+        body: RouteConflict,
+    },
+    /// Used to acknowledge the analytics consent changes
+    #[serde(rename = "analytics_consent_changed")]
+    AnalyticsConsentChanged {
+        /// Analytics consent change type event
+        body: AnalyticsConsentChanged,
+    },
+    /// Used to report the completed and failed handshakes with the peers
+    #[serde(rename = "peer_handshake")]
+    PeerHandshake {
+        /// Peer handshake type event
+        body: PeerHandshake,
+    },
+    /// Used to report the meshnet names to flush from the DNS cache of the OS
+    #[serde(rename = "dns_records_invalidated")]
+    DnsRecordsInvalidated {
+        /// DNS records invalidated type event
+        body: DnsRecordsInvalidated,
+    },
+    /// Used to report the peer keys installed on or removed from the device
+    #[serde(rename = "audit_record")]
+    AuditRecord {
+        /// Audit record type event
+        body: AuditRecord,
+    },
+    /// Used to report the replies to the echo requests sent to a meshnet peer
+    #[serde(rename = "ping_probe")]
+    PingProbe {
+        /// Ping probe type event
+        body: PingProbe,
+    },
+    /// Used to push the traffic counters of the peers
+    #[serde(rename = "peer_stats")]
+    PeerStatsReport {
+        /// Peer stats type event
+        body: PeerStatsReport,
+    },
 }
 
 impl Event {
@@ -144,9 +644,63 @@ impl Event {
 
 #[allow(missing_docs)]
 pub enum EventBuilder {
-    Relay { body: Option<Relay> },
-    Node { body: Option<Node> },
-    Error { body: Option<Error> },
+    Relay {
+        body: Option<Relay>,
+    },
+    Node {
+        body: Option<Node>,
+    },
+    Error {
+        body: Option<Error>,
+    },
+    PeerRekeyed {
+        body: Option<PeerRekeyed>,
+    },
+    PeerUnreachable {
+        body: Option<PeerUnreachable>,
+    },
+    MtuChanged {
+        body: Option<MtuChanged>,
+    },
+    InboundConnection {
+        body: Option<InboundConnection>,
+    },
+    MaintenanceStateChanged {
+        body: Option<MaintenanceStateChanged>,
+    },
+    ConfigRollback {
+        body: Option<ConfigRollback>,
+    },
+    AdapterRecovery {
+        body: Option<AdapterRecovery>,
+    },
+    MeshRemediation {
+        body: Option<MeshRemediation>,
+    },
+    HaRoleChanged {
+        body: Option<HaRoleChanged>,
+    },
+    AnalyticsConsentChanged {
+        body: Option<AnalyticsConsentChanged>,
+    },
+    RouteConflict {
+        body: Option<RouteConflict>,
+    },
+    PeerHandshake {
+        body: Option<PeerHandshake>,
+    },
+    DnsRecordsInvalidated {
+        body: Option<DnsRecordsInvalidated>,
+    },
+    AuditRecord {
+        body: Option<AuditRecord>,
+    },
+    PingProbe {
+        body: Option<PingProbe>,
+    },
+    PeerStatsReport {
+        body: Option<PeerStatsReport>,
+    },
 }
 
 impl EventBuilder {
@@ -157,6 +711,40 @@ impl EventBuilder {
             EventBuilder::Relay { body: Some(body) } => Some(Event::Relay { body }),
             EventBuilder::Node { body: Some(body) } => Some(Event::Node { body }),
             EventBuilder::Error { body: Some(body) } => Some(Event::Error { body }),
+            EventBuilder::PeerRekeyed { body: Some(body) } => Some(Event::PeerRekeyed { body }),
+            EventBuilder::PeerUnreachable { body: Some(body) } => {
+                Some(Event::PeerUnreachable { body })
+            }
+            EventBuilder::MtuChanged { body: Some(body) } => Some(Event::MtuChanged { body }),
+            EventBuilder::InboundConnection { body: Some(body) } => {
+                Some(Event::InboundConnection { body })
+            }
+            EventBuilder::MaintenanceStateChanged { body: Some(body) } => {
+                Some(Event::MaintenanceStateChanged { body })
+            }
+            EventBuilder::ConfigRollback { body: Some(body) } => {
+                Some(Event::ConfigRollback { body })
+            }
+            EventBuilder::AdapterRecovery { body: Some(body) } => {
+                Some(Event::AdapterRecovery { body })
+            }
+            EventBuilder::MeshRemediation { body: Some(body) } => {
+                Some(Event::MeshRemediation { body })
+            }
+            EventBuilder::HaRoleChanged { body: Some(body) } => Some(Event::HaRoleChanged { body }),
+            EventBuilder::AnalyticsConsentChanged { body: Some(body) } => {
+                Some(Event::AnalyticsConsentChanged { body })
+            }
+            EventBuilder::RouteConflict { body: Some(body) } => Some(Event::RouteConflict { body }),
+            EventBuilder::PeerHandshake { body: Some(body) } => Some(Event::PeerHandshake { body }),
+            EventBuilder::DnsRecordsInvalidated { body: Some(body) } => {
+                Some(Event::DnsRecordsInvalidated { body })
+            }
+            EventBuilder::AuditRecord { body: Some(body) } => Some(Event::AuditRecord { body }),
+            EventBuilder::PingProbe { body: Some(body) } => Some(Event::PingProbe { body }),
+            EventBuilder::PeerStatsReport { body: Some(body) } => {
+                Some(Event::PeerStatsReport { body })
+            }
             _ => None,
         }
     }
@@ -178,6 +766,134 @@ impl Modifier<EventBuilder> for Node {
     }
 }
 
+impl Modifier<EventBuilder> for PeerRekeyed {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::PeerRekeyed { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for PeerUnreachable {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::PeerUnreachable { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for MtuChanged {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::MtuChanged { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for InboundConnection {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::InboundConnection { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for MaintenanceStateChanged {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::MaintenanceStateChanged { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for ConfigRollback {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::ConfigRollback { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for AdapterRecovery {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::AdapterRecovery { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for MeshRemediation {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::MeshRemediation { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for HaRoleChanged {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::HaRoleChanged { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for RouteConflict {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::RouteConflict { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for AnalyticsConsentChanged {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::AnalyticsConsentChanged { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for PeerHandshake {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::PeerHandshake { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for DnsRecordsInvalidated {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::DnsRecordsInvalidated { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for AuditRecord {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::AuditRecord { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for PingProbe {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::PingProbe { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<EventBuilder> for PeerStatsReport {
+    fn modify(self, res: &mut EventBuilder) {
+        if let EventBuilder::PeerStatsReport { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
 impl Modifier<EventBuilder> for ErrorLevel {
     fn modify(self, res: &mut EventBuilder) {
         if let EventBuilder::Error { body } = res {
@@ -256,6 +972,9 @@ mod tests {
             path: crate::features::PathType::Relay,
             allow_multicast: false,
             peer_allows_multicast: false,
+            local_candidate: Some(CandidateType::Stun),
+            remote_candidate: Some(CandidateType::PeerReflexive),
+            local_endpoint: Some(SocketAddr::new("10.0.0.1".parse().unwrap(), 1234)),
         };
 
         let server = Server {
@@ -307,7 +1026,10 @@ mod tests {
             r#""allow_peer_send_files":false,"#,
             r#""path":"relay","#,
             r#""allow_multicast":false,"#,
-            r#""peer_allows_multicast":false"#,
+            r#""peer_allows_multicast":false,"#,
+            r#""local_candidate":"stun","#,
+            r#""remote_candidate":"peer_reflexive","#,
+            r#""local_endpoint":"10.0.0.1:1234""#,
             r#"}}"#
         ));
 
@@ -320,10 +1042,314 @@ mod tests {
 
         let conn_event = Event::builder::<Relay>().set(server).build().unwrap();
 
-        let node_event = Event::builder::<Node>().set(node).build().unwrap();
+        let node_event = Event::builder::<Node>().set(node.clone()).build().unwrap();
+
+        let rekeyed_json = String::from(concat!(
+            r#"{"type":"peer_rekeyed","#,
+            r#""body":"#,
+            r#"{"identifier":"f2b18d10-82ed-49a3-8b50-3356685ec5fa","#,
+            r#""old_public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""new_public_key":"AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=","#,
+            r#""ip_addresses":["100.64.0.1"]"#,
+            r#"}}"#
+        ));
+
+        let rekeyed_event = Event::builder::<PeerRekeyed>()
+            .set(PeerRekeyed {
+                identifier: "f2b18d10-82ed-49a3-8b50-3356685ec5fa".to_owned(),
+                old_public_key: PublicKey([1_u8; KEY_SIZE]),
+                new_public_key: PublicKey([2_u8; KEY_SIZE]),
+                ip_addresses: vec!["100.64.0.1".parse().unwrap()],
+            })
+            .build()
+            .unwrap();
 
         assert_eq!(err_json, err_event.to_json().unwrap());
         assert_eq!(conn_json, conn_event.to_json().unwrap());
         assert_eq!(node_json, node_event.to_json().unwrap());
+        assert_eq!(rekeyed_json, rekeyed_event.to_json().unwrap());
+
+        let unreachable_json = String::from(concat!(
+            r#"{"type":"peer_unreachable","#,
+            r#""body":"#,
+            r#"{"identifier":"f2b18d10-82ed-49a3-8b50-3356685ec5fa","#,
+            r#""public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""is_vpn":false,"#,
+            r#""cause":"possible_key_mismatch","#,
+            r#""connecting_for_s":30"#,
+            r#"}}"#
+        ));
+
+        let unreachable_event = Event::builder::<PeerUnreachable>()
+            .set(PeerUnreachable {
+                identifier: "f2b18d10-82ed-49a3-8b50-3356685ec5fa".to_owned(),
+                public_key: PublicKey([1_u8; KEY_SIZE]),
+                is_vpn: false,
+                cause: UnreachableCause::PossibleKeyMismatch,
+                connecting_for_s: 30,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(unreachable_json, unreachable_event.to_json().unwrap());
+
+        let transition_json =
+            node_json.replacen(r#""type":"node""#, r#""type":"node_transition""#, 1);
+        let transition_event = Event::NodeTransition { body: node };
+
+        assert_eq!(transition_json, transition_event.to_json().unwrap());
+
+        let mtu_json = String::from(concat!(
+            r#"{"type":"mtu_changed","#,
+            r#""body":{"mtu":1280,"mss_ipv4":1240,"mss_ipv6":1220}}"#
+        ));
+
+        let mtu_event = Event::builder::<MtuChanged>()
+            .set(MtuChanged {
+                mtu: 1280,
+                mss_ipv4: 1240,
+                mss_ipv6: 1220,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(mtu_json, mtu_event.to_json().unwrap());
+
+        let inbound_json = String::from(concat!(
+            r#"{"type":"inbound_connection","#,
+            r#""body":"#,
+            r#"{"identifier":"f2b18d10-82ed-49a3-8b50-3356685ec5fa","#,
+            r#""public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""hostname":"alpha.nord","#,
+            r#""protocol":"tcp","#,
+            r#""port":22"#,
+            r#"}}"#
+        ));
+
+        let inbound_event = Event::builder::<InboundConnection>()
+            .set(InboundConnection {
+                identifier: "f2b18d10-82ed-49a3-8b50-3356685ec5fa".to_owned(),
+                public_key: PublicKey([1_u8; KEY_SIZE]),
+                hostname: "alpha.nord".to_owned(),
+                protocol: ConnectionProtocol::Tcp,
+                port: 22,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(inbound_json, inbound_event.to_json().unwrap());
+
+        let maintenance_json = String::from(concat!(
+            r#"{"type":"maintenance_state_changed","#,
+            r#""body":{"state":"suspended"}}"#
+        ));
+
+        let maintenance_event = Event::builder::<MaintenanceStateChanged>()
+            .set(MaintenanceStateChanged {
+                state: MaintenanceState::Suspended,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(maintenance_json, maintenance_event.to_json().unwrap());
+
+        let rollback_json = String::from(concat!(
+            r#"{"type":"config_rollback","#,
+            r#""body":{"cause":"Bad private key"}}"#
+        ));
+
+        let rollback_event = Event::builder::<ConfigRollback>()
+            .set(ConfigRollback {
+                cause: "Bad private key".to_owned(),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(rollback_json, rollback_event.to_json().unwrap());
+
+        let recovery_json = String::from(concat!(
+            r#"{"type":"adapter_recovery","#,
+            r#""body":{"state":"recovering"}}"#
+        ));
+
+        let recovery_event = Event::builder::<AdapterRecovery>()
+            .set(AdapterRecovery {
+                state: AdapterRecoveryState::Recovering,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(recovery_json, recovery_event.to_json().unwrap());
+
+        let remediation_json = String::from(concat!(
+            r#"{"type":"mesh_remediation","#,
+            r#""body":{"step":"relay_reconnect","stuck_for_s":300}}"#
+        ));
+
+        let remediation_event = Event::builder::<MeshRemediation>()
+            .set(MeshRemediation {
+                step: RemediationStep::RelayReconnect,
+                stuck_for_s: 300,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(remediation_json, remediation_event.to_json().unwrap());
+
+        let ha_json =
+            String::from(r#"{"type":"ha_role_changed","body":{"role":"primary","term":2}}"#);
+
+        let ha_event = Event::builder::<HaRoleChanged>()
+            .set(HaRoleChanged {
+                role: HaRole::Primary,
+                term: 2,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(ha_json, ha_event.to_json().unwrap());
+
+        let consent_json = String::from(
+            r#"{"type":"analytics_consent_changed","body":{"consent":false,"purged":true}}"#,
+        );
+
+        let consent_event = Event::builder::<AnalyticsConsentChanged>()
+            .set(AnalyticsConsentChanged {
+                consent: false,
+                purged: true,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(consent_json, consent_event.to_json().unwrap());
+
+        let conflict_json = String::from(concat!(
+            r#"{"type":"route_conflict","#,
+            r#""body":"#,
+            r#"{"public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""allowed_ips":"100.64.0.2/32","#,
+            r#""local_network":"100.64.0.0/16","#,
+            r#""interface_name":"wlan0","#,
+            r#""excluded":false"#,
+            r#"}}"#
+        ));
+
+        let conflict_event = Event::builder::<RouteConflict>()
+            .set(RouteConflict {
+                public_key: PublicKey([1_u8; KEY_SIZE]),
+                allowed_ips: "100.64.0.2/32".parse().unwrap(),
+                local_network: "100.64.0.0/16".parse().unwrap(),
+                interface_name: "wlan0".to_owned(),
+                excluded: false,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(conflict_json, conflict_event.to_json().unwrap());
+
+        let handshake_json = String::from(concat!(
+            r#"{"type":"peer_handshake","#,
+            r#""body":"#,
+            r#"{"public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""result":"completed","#,
+            r#""elapsed_ms":1500"#,
+            r#"}}"#
+        ));
+
+        let handshake_event = Event::builder::<PeerHandshake>()
+            .set(PeerHandshake {
+                public_key: PublicKey([1_u8; KEY_SIZE]),
+                result: HandshakeResult::Completed,
+                elapsed_ms: 1500,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(handshake_json, handshake_event.to_json().unwrap());
+
+        let invalidated_json = String::from(concat!(
+            r#"{"type":"dns_records_invalidated","#,
+            r#""body":"#,
+            r#"{"names":["alice.nord","bob.nord"]"#,
+            r#"}}"#
+        ));
+
+        let invalidated_event = Event::builder::<DnsRecordsInvalidated>()
+            .set(DnsRecordsInvalidated {
+                names: vec!["alice.nord".to_owned(), "bob.nord".to_owned()],
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(invalidated_json, invalidated_event.to_json().unwrap());
+
+        let audit_json = String::from(concat!(
+            r#"{"type":"audit_record","#,
+            r#""body":"#,
+            r#"{"timestamp_ms":1700000000000,"#,
+            r#""action":"peer_installed","#,
+            r#""public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=""#,
+            r#"}}"#
+        ));
+
+        let audit_event = Event::builder::<AuditRecord>()
+            .set(AuditRecord {
+                timestamp_ms: 1700000000000,
+                action: AuditAction::PeerInstalled,
+                public_key: Some(PublicKey([1_u8; KEY_SIZE])),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(audit_json, audit_event.to_json().unwrap());
+
+        let ping_json = String::from(concat!(
+            r#"{"type":"ping_probe","#,
+            r#""body":"#,
+            r#"{"public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""address":"100.64.0.2","#,
+            r#""sequence":3,"#,
+            r#""rtt_us":null"#,
+            r#"}}"#
+        ));
+
+        let ping_event = Event::builder::<PingProbe>()
+            .set(PingProbe {
+                public_key: PublicKey([1_u8; KEY_SIZE]),
+                address: IpAddr::from([100, 64, 0, 2]),
+                sequence: 3,
+                rtt_us: None,
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(ping_json, ping_event.to_json().unwrap());
+
+        let stats_json = String::from(concat!(
+            r#"{"type":"peer_stats","#,
+            r#""body":"#,
+            r#"{"sequence":7,"#,
+            r#""full":false,"#,
+            r#""peers":[{"public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""rx_bytes":1024,"tx_bytes":0}],"#,
+            r#""removed":["AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI="]"#,
+            r#"}}"#
+        ));
+
+        let stats_event = Event::builder::<PeerStatsReport>()
+            .set(PeerStatsReport {
+                sequence: 7,
+                full: false,
+                peers: vec![PeerStats {
+                    public_key: PublicKey([1_u8; KEY_SIZE]),
+                    rx_bytes: 1024,
+                    tx_bytes: 0,
+                }],
+                removed: vec![PublicKey([2_u8; KEY_SIZE])],
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(stats_json, stats_event.to_json().unwrap());
     }
 }
@@ -1,21 +1,34 @@
 //! Object descriptions of various
 //! telio configurable features via API
 
-use std::{collections::HashSet, fmt};
+use std::{
+    collections::HashSet,
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
 
 use ipnet::Ipv4Net;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize};
 use smart_default::SmartDefault;
 use strum_macros::EnumCount;
+use telio_crypto::PublicKey;
 use telio_utils::telio_log_warn;
 
+mod domain;
+mod duration;
+
+pub use domain::{DomainName, InvalidDomainName};
+
 /// Type alias for UniFFI
 pub type EndpointProviders = HashSet<EndpointProvider>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
-#[serde(default)]
+#[serde(default, remote = "Self")]
 /// Encompasses all of the possible features that can be enabled
+///
+/// Deprecated fields are still accepted, and mapped to their successors when deserialized.
+/// See [Features::warnings] for the ones in use.
 pub struct Features {
     /// Additional wireguard configuration
     #[serde(deserialize_with = "FeatureWireguard::default_on_null")]
@@ -47,6 +60,7 @@ pub struct Features {
     /// Flag to turn on connection reset upon VPN server change for NepTUN adapter
     pub firewall: FeatureFirewall,
     /// If and for how long to flush events when stopping telio. Setting to Some(0) means waiting until all events have been flushed, regardless of how long it takes
+    #[serde(deserialize_with = "duration::opt_secs")]
     pub flush_events_on_stop_timeout_seconds: Option<u64>,
     /// Post quantum VPN tunnel configuration
     pub post_quantum_vpn: FeaturePostQuantumVPN,
@@ -61,8 +75,146 @@ pub struct Features {
     pub multicast: bool,
     /// Batching feature configuration, disabled by default, used for batching keep-alives
     pub batching: Option<FeatureBatching>,
+    /// SOCKS5 gateway into the meshnet, only available when running without a tunnel interface
+    pub socks5: Option<FeatureSocks5>,
+    /// AmneziaWG-compatible obfuscation of WireGuard traffic, only supported by NepTUN builds
+    /// with UAPI extensions
+    pub obfuscation: Option<FeatureObfuscation>,
+    /// Report peers which fail to connect in time, disabled by default
+    pub peer_unreachable: Option<FeaturePeerUnreachable>,
+    /// Open meshnet proxy sockets only for peers with traffic, disabled by default
+    pub lazy_proxy: Option<FeatureLazyProxy>,
+    /// Hide the IP addresses of meshnet nodes' endpoints in node events, keeping only the ports
+    pub redact_node_endpoints: bool,
+    /// Relay VPN server traffic over DERP when its UDP endpoint is unreachable, disabled by default
+    pub vpn_relay_fallback: Option<FeatureVpnRelayFallback>,
+    /// Hold back brief connection drops from node events, disabled by default
+    pub node_event_debounce: Option<FeatureNodeEventDebounce>,
+    /// Skip persistent keepalives for peers exchanging data anyway, disabled by default
+    pub passive_keepalive: Option<FeaturePassiveKeepalive>,
+    /// Tune VPN keepalives on cellular networks, disabled by default
+    pub cellular: Option<FeatureCellular>,
+    /// Limit the number of meshnet peers, disabled by default
+    pub peer_limit: Option<FeaturePeerLimit>,
+    /// Mark the outer packets with a DSCP value for QoS prioritization, disabled by default
+    pub dscp: Option<FeatureDscp>,
+    /// Remediate meshnet stuck without any connected peers, disabled by default
+    pub mesh_watchdog: Option<FeatureMeshWatchdog>,
+    /// Standby instance taking over a failed primary, disabled by default
+    pub high_availability: Option<FeatureHighAvailability>,
+    /// Detect allowed IPs colliding with the local networks of the host, disabled by default
+    pub route_conflicts: Option<FeatureRouteConflicts>,
+    /// Keep a trail of the peer keys installed on and removed from the device, disabled by default
+    pub audit_log: Option<FeatureAuditLog>,
+    /// Scheduling priority of the threads of libtelio, left to the OS by default
+    pub threads: Option<FeatureThreads>,
+    /// Hold back meshnet configs set while the device is unsettled, disabled by default
+    pub config_queue: Option<FeatureConfigQueue>,
+    /// Time budget of the blocking device calls, calls may block indefinitely if not set
+    pub api_timeout: Option<FeatureApiTimeout>,
+    /// Send Wake-on-LAN magic packets to the LAN on behalf of the meshnet nodes, disabled by default
+    pub wake_on_lan: Option<FeatureWakeOnLan>,
+    /// Push the traffic counters of the peers with `PeerStatsReport` events, disabled by default
+    pub peer_stats: Option<FeaturePeerStats>,
+    /// Quiesce the meshnet peers without traffic until it is sent to them, disabled by default
+    pub on_demand: Option<FeatureOnDemand>,
+}
+
+impl<'de> Deserialize<'de> for Features {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut features = Features::deserialize(deserializer)?;
+        features.map_deprecated();
+        Ok(features)
+    }
+}
+
+impl Features {
+    /// Deprecated fields in use, each already mapped to its successor
+    pub fn warnings(&self) -> Vec<FeatureDeprecation> {
+        DEPRECATED_FIELDS
+            .iter()
+            .filter(|field| (field.is_used)(self))
+            .map(|field| field.deprecation.clone())
+            .collect()
+    }
+
+    /// Map the deprecated fields in use to their successors, unless the successors are already
+    /// configured. Done on deserialization, but has to be repeated for features built otherwise.
+    pub fn map_deprecated(&mut self) {
+        for field in DEPRECATED_FIELDS {
+            if (field.is_used)(self) {
+                (field.map)(self);
+            }
+        }
+    }
+
+    /// Features as they take effect, with the deprecated fields mapped
+    pub fn effective(&self) -> Features {
+        let mut features = self.clone();
+        features.map_deprecated();
+        features
+    }
+}
+
+/// Use of a deprecated feature field
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FeatureDeprecation {
+    /// Path of the deprecated field
+    pub field: &'static str,
+    /// Path of the field replacing it
+    pub successor: &'static str,
+    /// Version since which the field is deprecated
+    pub since: &'static str,
 }
 
+impl fmt::Display for FeatureDeprecation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Feature `{}` is deprecated since {}, use `{}` instead",
+            self.field, self.since, self.successor
+        )
+    }
+}
+
+struct DeprecatedField {
+    deprecation: FeatureDeprecation,
+    is_used: fn(&Features) -> bool,
+    map: fn(&mut Features),
+}
+
+const DEPRECATED_FIELDS: &[DeprecatedField] = &[
+    DeprecatedField {
+        deprecation: FeatureDeprecation {
+            field: "paths",
+            successor: "direct",
+            since: "4.0.0",
+        },
+        is_used: |features| features.paths.is_some(),
+        map: |features| {
+            let wants_direct = features
+                .paths
+                .as_ref()
+                .is_some_and(|paths| paths.paths().contains(&PathType::Direct));
+            if wants_direct && features.direct.is_none() {
+                features.direct = Some(Default::default());
+            }
+        },
+    },
+    DeprecatedField {
+        deprecation: FeatureDeprecation {
+            field: "firewall.boringtun_reset_conns",
+            successor: "firewall.neptun_reset_conns",
+            since: "5.3.0",
+        },
+        is_used: |features| features.firewall.boringtun_reset_conns,
+        map: |features| features.firewall.neptun_reset_conns = true,
+    },
+];
+
 /// Configure keepalive batching
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -70,14 +222,17 @@ pub struct FeatureBatching {
     /// Direct connection threshold when batching (in seconds) [default 0s]
     /// Reused for Proxy, STUN, VPN peers as well
     #[default(0)]
+    #[serde(deserialize_with = "duration::secs")]
     pub direct_connection_threshold: u32,
 
     /// Trigger effective duration [default 10s]
     #[default(10)]
+    #[serde(deserialize_with = "duration::secs")]
     pub trigger_effective_duration: u32,
 
     /// Trigger cooldown duration [default 60s]
     #[default(60)]
+    #[serde(deserialize_with = "duration::secs")]
     pub trigger_cooldown_duration: u32,
 }
 
@@ -90,6 +245,71 @@ pub struct FeatureWireguard {
     /// Configurable wireguard polling period
     #[serde(default)]
     pub polling: FeaturePolling,
+    /// Handshake load protection of the userspace WireGuard implementation
+    #[serde(default)]
+    pub handshake_load: Option<FeatureHandshakeLoad>,
+    /// Local UAPI socket for inspecting the adapter with standard WireGuard tools, unix only
+    #[serde(default)]
+    pub uapi_socket: Option<FeatureUapiSocket>,
+    /// Recreate the adapter when it fails fatally, instead of leaving it to the app
+    #[serde(default)]
+    pub recovery: Option<FeatureAdapterRecovery>,
+    /// Report the completed and failed handshakes of the peers as events
+    #[serde(default)]
+    pub handshake_events: Option<FeatureHandshakeEvents>,
+}
+
+/// Protection of the data path against handshake floods, only supported by NepTUN builds with
+/// UAPI extensions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureHandshakeLoad {
+    /// Handshake initiations per second above which the device is considered under load.
+    /// Under load, handshakes are only answered with a cookie reply until the initiator
+    /// proves its address, so no expensive crypto is spent on spoofed floods [default 100]
+    #[default = 100]
+    pub under_load_threshold: u32,
+}
+
+/// Local UAPI socket of the userspace WireGuard implementation, only supported by NepTUN
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureUapiSocket {
+    /// Path of the socket [default /var/run/wireguard/<interface name>.sock]
+    pub path: Option<String>,
+    /// Permissions of the socket file [default 0o600]
+    #[default(0o600)]
+    pub mode: u32,
+    /// Only allow reading the configuration, rejecting `set` commands [default true]
+    #[default(true)]
+    pub read_only: bool,
+}
+
+/// Automatic recreation of the adapter after fatal failures
+///
+/// A removed driver or a tunnel device gone bad makes every UAPI request fail. After a number of
+/// consecutive failures the adapter is recreated and the last known config is applied again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureAdapterRecovery {
+    /// Time to keep retrying the recreation before giving up (in seconds) [default 30s]
+    #[default(30)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub timeout_s: u32,
+}
+
+/// Handshake events of the peers, for telling apart connecting and connected peers without
+/// polling their stats
+///
+/// A handshake is reported as completed as soon as the adapter shows it. A peer with an endpoint
+/// but without a valid session is reported as failed once per timeout, until a handshake completes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureHandshakeEvents {
+    /// Time without a completed handshake after which a failure is reported (in seconds) [default 10s]
+    #[default(10)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub failure_timeout_s: u32,
 }
 
 impl FeatureWireguard {
@@ -108,18 +328,22 @@ impl FeatureWireguard {
 pub struct FeaturePersistentKeepalive {
     /// Persistent keepalive period given for VPN peers (in seconds) [default 15s]
     #[default(Some(25))]
+    #[serde(deserialize_with = "duration::opt_secs")]
     pub vpn: Option<u32>,
 
     /// Persistent keepalive period for direct peers (in seconds) [default 5s]
     #[default(5)]
+    #[serde(deserialize_with = "duration::secs")]
     pub direct: u32,
 
     /// Persistent keepalive period for proxying peers (in seconds) [default 25s]
     #[default(Some(25))]
+    #[serde(deserialize_with = "duration::opt_secs")]
     pub proxying: Option<u32>,
 
     /// Persistent keepalive period for stun peers (in seconds) [default 25s]
     #[default(Some(25))]
+    #[serde(deserialize_with = "duration::opt_secs")]
     pub stun: Option<u32>,
 }
 
@@ -129,9 +353,11 @@ pub struct FeaturePersistentKeepalive {
 pub struct FeaturePolling {
     /// Wireguard state polling period (in milliseconds) [default 1000ms]
     #[default(1000)]
+    #[serde(deserialize_with = "duration::millis")]
     pub wireguard_polling_period: u32,
     /// Wireguard state polling period after state change (in milliseconds) [default 50ms]
     #[default(50)]
+    #[serde(deserialize_with = "duration::millis")]
     pub wireguard_polling_period_after_state_change: u32,
 }
 
@@ -141,9 +367,11 @@ pub struct FeaturePolling {
 pub struct FeatureNurse {
     /// Heartbeat interval in seconds. Default value is 3600.
     #[default(60 * 60)]
+    #[serde(deserialize_with = "duration::secs")]
     pub heartbeat_interval: u64,
     /// Initial heartbeat interval in seconds. Default value is 300.
     #[default(5 * 60)]
+    #[serde(deserialize_with = "duration::secs")]
     pub initial_heartbeat_interval: u64,
     /// QoS configuration for Nurse. Enabled by default.
     #[default(Some(Default::default()))]
@@ -158,7 +386,10 @@ pub struct FeatureNurse {
     pub enable_nat_traversal_conn_data: bool,
     /// How long a session can be before it is forcibly reported, in seconds. Default value is 24h.
     #[default(60 * 60 * 24)]
+    #[serde(deserialize_with = "duration::secs")]
     pub state_duration_cap: u64,
+    /// Enable/disable counting DNS failures of the upstream resolvers. Disabled by default.
+    pub enable_dns_failure_data: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
@@ -167,6 +398,7 @@ pub struct FeatureNurse {
 pub struct FeatureQoS {
     /// How often to collect rtt data in seconds. Default value is 300.
     #[default(5 * 60)]
+    #[serde(deserialize_with = "duration::secs")]
     pub rtt_interval: u64,
     /// Number of tries for each node. Default value is 3.
     #[default(3)]
@@ -254,6 +486,7 @@ pub struct FeatureDirect {
     pub providers: Option<EndpointProviders>,
     /// Polling interval for endpoints [default 10s]
     #[default = 25]
+    #[serde(deserialize_with = "duration::secs")]
     pub endpoint_interval_secs: u64,
     /// Configuration options for skipping unresponsive peers
     #[default(Some(Default::default()))]
@@ -264,6 +497,10 @@ pub struct FeatureDirect {
     /// Configurable features for UPNP endpoint provider
     #[default(Some(Default::default()))]
     pub upnp_features: Option<FeatureUpnp>,
+    /// Request coordinated hole punching from peers when pinging alone keeps failing
+    pub coordinated_punch: Option<FeatureCoordinatedPunch>,
+    /// Publish STUN endpoints of both IPv4 and IPv6 and use the faster one [default false]
+    pub dual_stack: bool,
 }
 
 fn deserialize_providers<'de, D>(de: D) -> Result<Option<EndpointProviders>, D::Error>
@@ -320,9 +557,24 @@ pub enum EndpointProvider {
 pub struct FeatureSkipUnresponsivePeers {
     /// Time after which peers is considered unresponsive if it didn't receive any packets
     #[default = 180]
+    #[serde(deserialize_with = "duration::secs")]
     pub no_rx_threshold_secs: u64,
 }
 
+/// Ask the peer over relay to punch at the same time, for nodes behind NATs which map each
+/// destination to a different port
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureCoordinatedPunch {
+    /// Number of failed pinging rounds with a peer before punching is requested [default 2]
+    #[default = 2]
+    pub failed_rounds_threshold: u32,
+    /// Time between the request and the punch, should exceed the relay round trip [default 1000ms]
+    #[default = 1000]
+    #[serde(deserialize_with = "duration::millis")]
+    pub delay_ms: u32,
+}
+
 /// Control which battery optimizations are turned on
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
 #[serde(default)]
@@ -339,8 +591,10 @@ pub struct FeatureEndpointProvidersOptimization {
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 pub struct FeatureDerp {
     /// Tcp keepalive set on derp server's side [default 15s]
+    #[serde(default, deserialize_with = "duration::opt_secs")]
     pub tcp_keepalive: Option<u32>,
     /// Derp will send empty messages after this many seconds of not sending/receiving any data [default 60s]
+    #[serde(default, deserialize_with = "duration::opt_secs")]
     pub derp_keepalive: Option<u32>,
     /// Poll Keepalive: Application level keepalives meant to replace the TCP keepalives
     /// They will use derp_keepalive as interval
@@ -348,9 +602,97 @@ pub struct FeatureDerp {
     pub poll_keepalive: Option<bool>,
     /// Enable polling of remote peer states to reduce derp traffic
     pub enable_polling: Option<bool>,
+    /// Mark peers offline and online as soon as the server signals their presence, skipping
+    /// direct connection attempts to offline peers [default false]
+    #[serde(default)]
+    pub enable_peer_presence: bool,
     /// Use Mozilla's root certificates instead of OS ones [default false]
     #[serde(default)]
     pub use_built_in_root_certificates: bool,
+    /// Discover relay and STUN servers through DNS, used when the meshnet config has none
+    #[serde(default)]
+    pub server_bootstrap: Option<FeatureServerBootstrap>,
+    /// Close the relay connection while it is idle, to save battery
+    #[serde(default)]
+    pub idle_disconnect: Option<FeatureRelayIdleDisconnect>,
+    /// Compress large control messages relayed to the peers supporting it, e.g. the endpoint
+    /// lists of the nodes with many interfaces [default false]
+    #[serde(default)]
+    pub enable_compression: bool,
+    /// Measure the delay variation of the relayed path with timestamped probes
+    #[serde(default)]
+    pub path_probes: Option<FeatureRelayPathProbes>,
+}
+
+/// Probing of the delay of the relayed path.
+///
+/// Timestamped probes are relayed by the server back to this client. A distant server has a high
+/// but steady delay, while the delay through an overloaded one keeps varying. Once the
+/// variation, the jitter, exceeds the threshold, the relay connection is reported as degraded
+/// and fails over to another server, trying the overloaded one last for a while. The delays are
+/// also included in the analytics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureRelayPathProbes {
+    /// Interval between the probes (in seconds) [default 5s]
+    #[default(5)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub interval_s: u32,
+    /// Jitter above which the server is considered overloaded (in milliseconds) [default 50ms]
+    #[default(50)]
+    #[serde(deserialize_with = "duration::millis")]
+    pub overloaded_jitter_ms: u32,
+}
+
+/// Closing of the relay connection while nothing is relayed.
+///
+/// The connection is considered idle when nothing was relayed through it, neither WireGuard
+/// packets nor control messages, in either direction, for the whole timeout. This is the case when all the peers are either connected
+/// directly or offline, as relayed peers keep exchanging keepalives. The relay is connected
+/// again on a config change, on any outgoing packet to a relayed peer, on a network change or
+/// wakeup, or when the app asks for it, e.g. on a push notification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureRelayIdleDisconnect {
+    /// Time without relayed traffic after which the connection is closed (in seconds) [default 600s]
+    #[default(600)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub idle_timeout_s: u32,
+}
+
+/// Discovery of the relay and STUN servers through DNS records of a domain.
+///
+/// Every server is published as an SRV record `_telio-relay._tcp.<domain>`, which points to
+/// the host of the server and its relay port. The host in turn has a TXT record with the public
+/// key and the STUN port of the server, `"pk=<base64 key>" "stun=<port>"`.
+///
+/// DNS answers are not authenticated, so only the servers with one of the pinned keys are used.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct FeatureServerBootstrap {
+    /// Domain holding the records of the servers
+    pub domain: DomainName,
+    /// Public keys of the servers which may be bootstrapped, others are ignored
+    pub pinned_keys: Vec<PublicKey>,
+    /// Resolvers used for the lookups [default 1.1.1.1, 8.8.8.8]
+    #[serde(default = "FeatureServerBootstrap::default_resolvers")]
+    pub resolvers: Vec<IpAddr>,
+    /// How often the server list is refreshed, in seconds [default 3600]
+    #[serde(default = "FeatureServerBootstrap::default_refresh_interval_s")]
+    #[serde(deserialize_with = "duration::secs")]
+    pub refresh_interval_s: u64,
+}
+
+impl FeatureServerBootstrap {
+    fn default_resolvers() -> Vec<IpAddr> {
+        vec![
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+        ]
+    }
+
+    fn default_refresh_interval_s() -> u64 {
+        60 * 60
+    }
 }
 
 /// Whether to validate keys
@@ -365,7 +707,8 @@ impl Default for FeatureValidateKeys {
 }
 
 /// Feature config for firewall
-#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
 pub struct FeatureFirewall {
     /// Turns on connection resets upon VPN server change
     #[serde(default)]
@@ -376,6 +719,70 @@ pub struct FeatureFirewall {
     /// Customizable private IP range to treat certain private IP ranges
     /// as public IPs for testing purposes.
     pub exclude_private_ip_range: Option<Ipv4Net>,
+    /// Per peer rate limiting of new inbound connections and ICMP echo requests
+    pub rate_limit: Option<FeatureFirewallRateLimit>,
+    /// Action for inbound meshnet connections from peers which are not whitelisted [default deny]
+    pub inbound_policy: FirewallPolicy,
+    /// Action for outbound meshnet connections to peers which are not whitelisted [default allow]
+    #[default(FirewallPolicy::Allow)]
+    pub outbound_policy: FirewallPolicy,
+    /// Drop fragmented packets instead of matching them by their first fragment [default false]
+    pub drop_fragments: bool,
+    /// Emit `InboundConnection` events for new inbound connections from meshnet peers
+    /// [default false]
+    pub connection_notifications: bool,
+    /// Only count the packets which would be dropped, forwarding all of them [default false]
+    pub monitor_only: bool,
+    /// Traffic of the new exit node while switching from another one [default allow]
+    #[default(ExitSwitchPolicy::Allow)]
+    pub exit_switch_policy: ExitSwitchPolicy,
+    /// How long the traffic is allowed with the `grace` exit switch policy [default 5s]
+    #[default(5)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub exit_switch_grace_s: u32,
+}
+
+/// Default action of the firewall, applied to traffic not covered by the whitelists
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirewallPolicy {
+    /// Drop traffic unless the peer is whitelisted
+    #[default]
+    Deny,
+    /// Accept traffic from or to any peer
+    Allow,
+}
+
+/// Handling of the exit node traffic between switching to a new exit node and its connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitSwitchPolicy {
+    /// Drop the traffic until the new exit node is connected
+    Block,
+    /// Allow the traffic for `exit_switch_grace_s`, then drop it until the new exit node is
+    /// connected
+    Grace,
+    /// Allow the traffic during the whole switch
+    #[default]
+    Allow,
+}
+
+/// Per peer rate limits of the firewall, protecting against floods from misbehaving peers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureFirewallRateLimit {
+    /// New inbound TCP connections accepted from a single peer per second
+    #[default = 20]
+    pub new_connections_per_s: u32,
+    /// New inbound TCP connections accepted from a single peer at once
+    #[default = 40]
+    pub new_connections_burst: u32,
+    /// ICMP echo requests accepted from a single peer per second
+    #[default = 5]
+    pub icmp_echo_per_s: u32,
+    /// ICMP echo requests accepted from a single peer at once
+    #[default = 10]
+    pub icmp_echo_burst: u32,
 }
 
 /// Turns on post quantum VPN tunnel
@@ -384,10 +791,12 @@ pub struct FeatureFirewall {
 pub struct FeaturePostQuantumVPN {
     /// Initial handshake retry interval in seconds
     #[default = 8]
+    #[serde(deserialize_with = "duration::secs")]
     pub handshake_retry_interval_s: u32,
 
     /// Rekey interval in seconds
     #[default = 90]
+    #[serde(deserialize_with = "duration::secs")]
     pub rekey_interval_s: u32,
 }
 
@@ -397,6 +806,7 @@ pub struct FeaturePostQuantumVPN {
 pub struct FeatureLinkDetection {
     /// Configurable rtt in seconds
     #[default = 15]
+    #[serde(deserialize_with = "duration::secs")]
     pub rtt_seconds: u64,
 
     /// Check the link state before reporting it as down
@@ -412,17 +822,86 @@ pub struct FeatureLinkDetection {
 #[derive(Default, Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct FeatureDns {
     /// TTL for SOA record and for A and AAAA records.
+    ///
+    /// Removed peers keep resolving from the OS cache for up to this long, unless their names
+    /// are flushed on the `DnsRecordsInvalidated` event.
     #[serde(default)]
     pub ttl_value: TtlValue,
     /// Configure options for exit dns
     #[serde(default)]
     pub exit_dns: Option<FeatureExitDns>,
+    /// Domains resolved by dedicated resolvers instead of the forward servers
+    #[serde(default)]
+    pub forward_rules: Vec<FeatureDnsForwardRule>,
+    /// SOA of the `.nord` zone, derived from `ttl_value` if not set
+    #[serde(default)]
+    pub soa: Option<FeatureDnsSoa>,
+    /// Answer the queries for the domains on a blocklist locally, the list is loaded at runtime
+    #[serde(default)]
+    pub blocklist: Option<FeatureDnsBlocklist>,
+}
+
+/// SOA record of the authoritative `.nord` zone.
+///
+/// The serial is not configurable, it is bumped on every change of the meshnet config.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureDnsSoa {
+    /// Primary name server of the zone
+    #[default(DomainName::from_static("mesh.nordsec.com."))]
+    pub primary_ns: DomainName,
+    /// Mailbox of the person responsible for the zone, with `@` replaced by `.`
+    #[default(DomainName::from_static("support.nordsec.com."))]
+    pub responsible: DomainName,
+    /// How often secondary servers should refresh the zone
+    #[default = 7200]
+    #[serde(deserialize_with = "duration::secs")]
+    pub refresh_s: u32,
+    /// How long secondary servers should wait before retrying a failed refresh
+    #[default = 60]
+    #[serde(deserialize_with = "duration::secs")]
+    pub retry_s: u32,
+    /// When secondary servers should stop answering for the zone they failed to refresh
+    #[default = 1209600]
+    #[serde(deserialize_with = "duration::secs")]
+    pub expire_s: u32,
+    /// How long resolvers may cache the nonexistence of a name, at most the TTL of the SOA itself
+    #[default = 60]
+    #[serde(deserialize_with = "duration::secs")]
+    pub negative_ttl_s: u32,
+}
+
+/// Forward queries for a domain, e.g. a corporate search domain, to its own resolvers.
+///
+/// The rules are evaluated before the forward servers: the most specific rule wins, `.nord` is
+/// always answered locally and anything not matching any of the rules is sent to the forward
+/// servers, i.e. exit DNS. The rules are kept when the forward servers are switched.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct FeatureDnsForwardRule {
+    /// Domain along with all of its subdomains
+    pub domain: DomainName,
+    /// Resolvers answering queries for the domain
+    pub resolvers: Vec<IpAddr>,
+    /// Reach the resolvers over sockets kept out of the tunnel instead of through the exit node,
+    /// e.g. for split DNS of an enterprise network while connected to an exit node
+    #[serde(default)]
+    pub bypass_tunnel: bool,
+}
+
+/// Blocking of the domains on a list loaded at runtime, e.g. for threat protection.
+///
+/// Names in the `.nord` zone are never blocked.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureDnsBlocklist {
+    /// Addresses the blocked names resolve to, NXDOMAIN is answered if empty
+    pub sinkhole_ips: Vec<IpAddr>,
 }
 
 /// Newtype for TTL value to ensure that the default function returns the actual default value and not 0.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
 #[serde(transparent)]
-pub struct TtlValue(pub u32);
+pub struct TtlValue(#[serde(deserialize_with = "duration::secs")] pub u32);
 
 impl Default for TtlValue {
     fn default() -> Self {
@@ -444,15 +923,400 @@ pub struct FeatureExitDns {
 pub struct FeaturePmtuDiscovery {
     /// A timeout for wait for the ICMP response packet
     #[default = 5]
+    #[serde(deserialize_with = "duration::secs")]
     pub response_wait_timeout_s: u32,
 }
 
+/// Configure the SOCKS5 gateway into the meshnet
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureSocks5 {
+    /// Port of the gateway on the loopback interface [default 1080]
+    #[default(1080)]
+    pub port: u16,
+}
+
+/// AmneziaWG-compatible obfuscation parameters, which must match on both ends of the tunnel
+///
+/// Defaults only send junk packets before handshakes, which plain WireGuard peers ignore.
+/// Handshake padding and magic headers make the traffic unrecognizable as WireGuard, but
+/// are understood only by peers configured with the same values.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureObfuscation {
+    /// Number of junk packets sent before each handshake initiation (Jc) [default 4]
+    #[default(4)]
+    pub junk_packet_count: u8,
+    /// Minimal size of a junk packet (Jmin) [default 40]
+    #[default(40)]
+    pub junk_packet_min_size: u16,
+    /// Maximal size of a junk packet (Jmax) [default 70]
+    #[default(70)]
+    pub junk_packet_max_size: u16,
+    /// Random bytes prepended to handshake initiations (S1) [default 0]
+    pub init_packet_junk_size: u16,
+    /// Random bytes prepended to handshake responses (S2) [default 0]
+    pub response_packet_junk_size: u16,
+    /// Message type of handshake initiations (H1) [default 1]
+    #[default(1)]
+    pub init_packet_magic_header: u32,
+    /// Message type of handshake responses (H2) [default 2]
+    #[default(2)]
+    pub response_packet_magic_header: u32,
+    /// Message type of cookie replies (H3) [default 3]
+    #[default(3)]
+    pub under_load_packet_magic_header: u32,
+    /// Message type of transport data (H4) [default 4]
+    #[default(4)]
+    pub transport_packet_magic_header: u32,
+}
+
+/// Configure reporting of peers which fail to connect
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeaturePeerUnreachable {
+    /// Time a peer may stay connecting before it is reported unreachable (in seconds) [default 30s]
+    #[default(30)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub connection_timeout_s: u32,
+}
+
+/// Configure lazy opening of proxy sockets
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureLazyProxy {
+    /// Time after which the socket of a peer without traffic in either direction is closed (in seconds) [default 300s]
+    #[default(300)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub idle_timeout_s: u64,
+}
+
+/// Configure relaying of VPN server traffic over DERP
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureVpnRelayFallback {
+    /// Time without a response on the direct endpoint before falling back to the relay (in seconds) [default 15s]
+    #[default(15)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub handshake_timeout_s: u64,
+    /// Time spent on the relay before the direct endpoint is retried (in seconds) [default 300s]
+    #[default(300)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub direct_retry_interval_s: u64,
+}
+
+/// Configure VPN keepalives on cellular networks
+///
+/// Some carriers drop idle UDP mappings early, others drain the battery on every keepalive,
+/// so the keepalive periods are taken from the profile of the carrier the device is on.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureCellular {
+    /// Detect cellular networks from the names of the local interfaces, when the app does not
+    /// hint the network type in `notify_network_change` [default true]
+    #[default(true)]
+    pub detect: bool,
+    /// Profiles, of which the first one matching the carrier is used [default none]
+    pub profiles: Vec<FeatureCellularProfile>,
+}
+
+impl FeatureCellular {
+    /// Find the profile of the carrier, hinted by the app
+    pub fn profile(&self, carrier: Option<&str>) -> Option<&FeatureCellularProfile> {
+        self.profiles
+            .iter()
+            .find(|profile| match (&profile.carrier, carrier) {
+                (None, _) => true,
+                (Some(expected), Some(carrier)) => expected.eq_ignore_ascii_case(carrier),
+                (Some(_), None) => false,
+            })
+    }
+}
+
+/// Keepalive tuning for a cellular carrier
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureCellularProfile {
+    /// Name of the carrier, compared case insensitively, `None` matches any carrier
+    pub carrier: Option<String>,
+    /// Persistent keepalive period of VPN peers (in seconds), the configured one if `None`
+    #[serde(deserialize_with = "duration::opt_secs")]
+    pub vpn_keepalive_s: Option<u32>,
+    /// Time without a response from the VPN server before falling back to the relay
+    /// (in seconds), the fallback's own if `None`
+    #[serde(deserialize_with = "duration::opt_secs")]
+    pub handshake_timeout_s: Option<u64>,
+}
+
+/// Configure the limit on the number of meshnet peers
+///
+/// Meant for devices with little memory, e.g. routers, where the adapter cannot hold all of
+/// the peers of a large meshnet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeaturePeerLimit {
+    /// Maximum number of meshnet peers [default 1024]
+    #[default(1024)]
+    pub max_peers: u32,
+    /// Handling of the meshnet configs with more peers than the limit [default prioritize]
+    pub strategy: PeerLimitStrategy,
+}
+
+/// Configure DSCP marking of the outer packets
+///
+/// Enterprise networks prioritize traffic by the DSCP field of the IP header. The packets of the
+/// NepTUN adapter and the relay connections are marked, the inner packets are left as they are.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureDscp {
+    /// DSCP value of the outer packets, from 0 to 63 [default 46, expedited forwarding]
+    #[default(46)]
+    pub value: u8,
+}
+
+/// Configure the watchdog of meshnet connectivity
+///
+/// Meshnet is stuck when it has peers configured, the network is up, yet none of the peers is
+/// connected. Once stuck for long enough, the watchdog reconnects the relay, rediscovers the
+/// endpoints and re-handshakes with the peers, one step at a time until a peer connects.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureMeshWatchdog {
+    /// Time meshnet may stay without connected peers before remediation starts [default 300s]
+    #[default(300)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub stuck_timeout_s: u32,
+    /// Time given to each remediation step before the next one is taken [default 60s]
+    #[default(60)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub step_interval_s: u32,
+}
+
+/// Role of an instance of a high availability pair
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HaRole {
+    /// Serves the traffic and mirrors its state to the standby
+    #[default]
+    Primary,
+    /// Mirrors the state of the primary and takes over once it stops sending heartbeats
+    Standby,
+}
+
+/// Configure a high availability pair of gateways
+///
+/// Both instances run with the same private key, only the primary is configured by the app. The
+/// standby keeps its adapter up without any peers and mirrors the meshnet config and the exit
+/// node of the primary from its heartbeats. Once the heartbeats stop, the standby applies the
+/// mirrored state and becomes the primary, while a former primary hearing the new one steps down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct FeatureHighAvailability {
+    /// Role of this instance when started
+    pub role: HaRole,
+    /// Address the heartbeats of the other instance are received on
+    pub listen_address: SocketAddr,
+    /// Address of the other instance
+    pub peer_address: SocketAddr,
+    /// Interval of the heartbeats (in milliseconds) [default 500ms]
+    #[serde(
+        default = "FeatureHighAvailability::default_heartbeat_interval_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub heartbeat_interval_ms: u64,
+    /// Time without heartbeats after which the standby takes over (in milliseconds) [default 3000ms]
+    #[serde(
+        default = "FeatureHighAvailability::default_takeover_timeout_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub takeover_timeout_ms: u64,
+}
+
+impl FeatureHighAvailability {
+    fn default_heartbeat_interval_ms() -> u64 {
+        500
+    }
+
+    fn default_takeover_timeout_ms() -> u64 {
+        3000
+    }
+}
+
+/// Configure the detection of allowed IPs colliding with the local networks of the host
+///
+/// Conflicts are reported with an event once a meshnet config or an exit node is applied. Default
+/// routes are never reported, the local networks stay reachable through their more specific
+/// routes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureRouteConflicts {
+    /// Cut the conflicting ranges out of the allowed IPs, keeping the local networks reachable
+    /// at the cost of the peers inside them [default false]
+    pub exclude: bool,
+}
+
+/// Configure the audit log of the peer keys with access to the device
+///
+/// Every installation and removal of a peer key, and every change of the meshnet config or the
+/// exit node they come from, is recorded with its time. The log is kept in memory, dropping the
+/// oldest records once full, and can be persisted by the app from the `AuditRecord` events.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureAuditLog {
+    /// Records kept in memory [default 1024]
+    #[default(1024)]
+    pub capacity: u32,
+    /// Emit an `AuditRecord` event for every record [default false]
+    pub events: bool,
+}
+
+/// Configure the on-demand activation of the meshnet peers
+///
+/// Peers not sent anything for the idle timeout stay on the adapter, but without keepalives and
+/// without upgrading to direct connections. Traffic sent to their meshnet IPs activates them
+/// again right away.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureOnDemand {
+    /// Time without traffic sent to a peer before it is quiesced [default 300s]
+    #[default(300)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub idle_timeout_s: u32,
+}
+
+/// Configure the stream of the traffic counters of the peers
+///
+/// Between the full reports only the peers whose counters changed are reported, with the bytes
+/// since the previous report, to keep the events small for meshes of hundreds of peers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeaturePeerStats {
+    /// Time between the reports [default 10s]
+    #[default(10)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub interval_s: u32,
+    /// Every how many reports all of the peers are reported with their totals [default 6]
+    #[default(6)]
+    pub full_report_every: u32,
+}
+
+/// Configure the relaying of the Wake-on-LAN requests
+///
+/// Requests are relayed only for the nodes allowed to access the local network, and only to
+/// wake up the nodes of the meshnet advertising their hardware address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureWakeOnLan {
+    /// UDP port the magic packets are broadcast to [default 9]
+    #[default(9)]
+    pub port: u16,
+}
+
+/// Configure the time budget of the blocking device calls
+///
+/// The budget covers starting and stopping the device as well. A call which times out before
+/// the device got to it is withdrawn, one which already started may still take effect later.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureApiTimeout {
+    /// Time a call may take before it fails with a timeout (in milliseconds) [default 30000ms]
+    #[default(30000)]
+    #[serde(deserialize_with = "duration::millis")]
+    pub timeout_ms: u64,
+    /// Once a call timed out, fail the following calls right away unless the runtime answers a
+    /// quick health probe [default false]
+    pub fail_fast: bool,
+}
+
+/// Configure the queueing of the meshnet configs
+///
+/// Configs set right after a network change, or while the relay reconnects, are held back and
+/// coalesced, so that only the latest one is applied once the device settles instead of racing
+/// the reconnection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureConfigQueue {
+    /// Time the device is considered unsettled after a network change (in milliseconds) [default 2000ms]
+    #[default(2000)]
+    #[serde(deserialize_with = "duration::millis")]
+    pub settle_ms: u64,
+    /// Longest time a config is held back, it is applied even if the device did not settle (in milliseconds) [default 10000ms]
+    #[default(10000)]
+    #[serde(deserialize_with = "duration::millis")]
+    pub max_delay_ms: u64,
+}
+
+/// Configure the scheduling of the threads of libtelio
+///
+/// The async runtime workers carry the relayed and proxied traffic along with the control of
+/// the device, the NepTUN threads encrypt and decrypt the tunnel traffic, so elevating them
+/// lowers the latency while the host is under load.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct FeatureThreads {
+    /// Priority of the async runtime workers [default normal]
+    pub worker_priority: ThreadPriority,
+    /// Priority of the datapath threads of NepTUN [default normal]
+    pub datapath_priority: ThreadPriority,
+}
+
+/// Scheduling priority of a thread, mapped to the nice value on Linux, the QoS class on Apple
+/// platforms and the thread priority on Windows
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThreadPriority {
+    /// Left as set by the OS
+    #[default]
+    Normal,
+    /// Scheduled ahead of the normal threads
+    AboveNormal,
+    /// Scheduled ahead of everything but the realtime threads, may need elevated privileges
+    High,
+}
+
+/// Handling of the meshnet configs with more peers than supported
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerLimitStrategy {
+    /// Reject the whole config
+    Reject,
+    /// Keep the peers online, rotating the remaining slots among the rest
+    #[default]
+    Prioritize,
+}
+
+/// Configure passive keepalives
+///
+/// Data flowing in both directions keeps the NAT mappings open, so persistent keepalives are
+/// held back until the link has been idle for so long that the next keepalive period would
+/// end past the NAT timeout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeaturePassiveKeepalive {
+    /// Idle time after which the NATs on the path may drop the mapping (in seconds) [default 30s]
+    #[default(30)]
+    #[serde(deserialize_with = "duration::secs")]
+    pub nat_timeout_s: u32,
+}
+
+/// Configure debouncing of node events
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
+#[serde(default)]
+pub struct FeatureNodeEventDebounce {
+    /// Time a connected node has to stay connecting before it is reported (in milliseconds) [default 3000ms]
+    #[default(3000)]
+    #[serde(deserialize_with = "duration::millis")]
+    pub hold_ms: u64,
+    /// Also publish every raw state change as a node transition event [default false]
+    pub node_transition_events: bool,
+}
+
 /// Configurable features for UPNP endpoint provider
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, SmartDefault)]
 #[serde(default)]
 pub struct FeatureUpnp {
     /// The upnp lease_duration parameter, in seconds. A value of 0 is infinite. Default: 3600
     #[default = 3600]
+    #[serde(deserialize_with = "duration::secs")]
     pub lease_duration_s: u32,
 }
 
@@ -515,6 +1379,20 @@ mod tests {
                 "polling": {
                     "wireguard_polling_period": 1000,
                     "wireguard_polling_period_after_state_change": 50
+                },
+                "handshake_load": {
+                    "under_load_threshold": 42
+                },
+                "uapi_socket": {
+                    "path": "/run/telio/wg.sock",
+                    "mode": 432,
+                    "read_only": false
+                },
+                "recovery": {
+                    "timeout_s": 60
+                },
+                "handshake_events": {
+                    "failure_timeout_s": 15
                 }
             },
             "nurse": {
@@ -530,7 +1408,8 @@ mod tests {
                 "enable_nat_type_collection": true,
                 "enable_relay_conn_data": false,
                 "enable_nat_traversal_conn_data": false,
-                "state_duration_cap": 10
+                "state_duration_cap": 10,
+                "enable_dns_failure_data": true
             },
             "lana": {
                 "event_path": "some/test/path.db",
@@ -552,7 +1431,12 @@ mod tests {
                 },
                 "upnp_features": {
                     "lease_duration_s": 60
-                }
+                },
+                "coordinated_punch": {
+                    "failed_rounds_threshold": 3,
+                    "delay_ms": 800
+                },
+                "dual_stack": true
             },
             "is_test_env": true,
             "hide_user_data": false,
@@ -561,7 +1445,22 @@ mod tests {
                 "derp_keepalive": 14,
                 "poll_keepalive": true,
                 "enable_polling": true,
-                "use_built_in_root_certificates": true
+                "enable_peer_presence": true,
+                "use_built_in_root_certificates": true,
+                "server_bootstrap": {
+                    "domain": "relays.example.com",
+                    "pinned_keys": ["qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqo="],
+                    "resolvers": ["10.0.0.53"],
+                    "refresh_interval_s": 600
+                },
+                "idle_disconnect": {
+                    "idle_timeout_s": 300
+                },
+                "enable_compression": true,
+                "path_probes": {
+                    "interval_s": 10,
+                    "overloaded_jitter_ms": 80
+                }
             },
             "validate_keys": false,
             "ipv6": true,
@@ -569,7 +1468,20 @@ mod tests {
             "firewall": {
                 "neptun_reset_conns": true,
                 "boringtun_reset_conns": true,
-                "exclude_private_ip_range": null
+                "exclude_private_ip_range": null,
+                "rate_limit": {
+                    "new_connections_per_s": 1,
+                    "new_connections_burst": 2,
+                    "icmp_echo_per_s": 3,
+                    "icmp_echo_burst": 4
+                },
+                "inbound_policy": "allow",
+                "outbound_policy": "deny",
+                "drop_fragments": true,
+                "connection_notifications": true,
+                "monitor_only": true,
+                "exit_switch_policy": "grace",
+                "exit_switch_grace_s": 10
             },
             "flush_events_on_stop_timeout_seconds": 15,
             "post_quantum_vpn": {
@@ -585,6 +1497,24 @@ mod tests {
                 "ttl_value": 19,
                 "exit_dns": {
                     "auto_switch_dns_ips": true
+                },
+                "forward_rules": [
+                    {
+                        "domain": "corp.example.com",
+                        "resolvers": ["10.0.0.53"],
+                        "bypass_tunnel": true
+                    }
+                ],
+                "soa": {
+                    "primary_ns": "ns.example.com.",
+                    "responsible": "admin.example.com.",
+                    "refresh_s": 100,
+                    "retry_s": 101,
+                    "expire_s": 102,
+                    "negative_ttl_s": 103
+                },
+                "blocklist": {
+                    "sinkhole_ips": ["0.0.0.0", "::"]
                 }
             },
             "pmtu_discovery": {
@@ -595,6 +1525,92 @@ mod tests {
                 "direct_connection_threshold": 60,
                 "trigger_effective_duration": 10,
                 "trigger_cooldown_duration": 60
+            },
+            "socks5": {
+                "port": 1081
+            },
+            "obfuscation": {
+                "junk_packet_count": 5,
+                "junk_packet_min_size": 50,
+                "junk_packet_max_size": 1000,
+                "init_packet_junk_size": 15,
+                "response_packet_junk_size": 18,
+                "init_packet_magic_header": 1020325451,
+                "response_packet_magic_header": 3288052141,
+                "under_load_packet_magic_header": 1766607858,
+                "transport_packet_magic_header": 2528465083
+            },
+            "peer_unreachable": {
+                "connection_timeout_s": 45
+            },
+            "lazy_proxy": {
+                "idle_timeout_s": 600
+            },
+            "redact_node_endpoints": true,
+            "vpn_relay_fallback": {
+                "handshake_timeout_s": 20,
+                "direct_retry_interval_s": 120
+            },
+            "node_event_debounce": {
+                "hold_ms": 5000,
+                "node_transition_events": true
+            },
+            "passive_keepalive": {
+                "nat_timeout_s": 20
+            },
+            "cellular": {
+                "detect": false,
+                "profiles": [
+                    {"carrier": "Telco", "vpn_keepalive_s": 20, "handshake_timeout_s": 30},
+                    {"vpn_keepalive_s": 120}
+                ]
+            },
+            "peer_limit": {
+                "max_peers": 256,
+                "strategy": "reject"
+            },
+            "dscp": {
+                "value": 34
+            },
+            "mesh_watchdog": {
+                "stuck_timeout_s": 600,
+                "step_interval_s": 30
+            },
+            "high_availability": {
+                "role": "standby",
+                "listen_address": "10.0.0.2:7000",
+                "peer_address": "10.0.0.1:7000",
+                "heartbeat_interval_ms": 200,
+                "takeover_timeout_ms": "1s"
+            },
+            "route_conflicts": {
+                "exclude": true
+            },
+            "audit_log": {
+                "capacity": 16,
+                "events": true
+            },
+            "threads": {
+                "worker_priority": "above-normal",
+                "datapath_priority": "high"
+            },
+            "config_queue": {
+                "settle_ms": 500,
+                "max_delay_ms": "5s"
+            },
+            "api_timeout": {
+                "timeout_ms": "10s",
+                "fail_fast": true
+            },
+            "wake_on_lan": {
+                "port": 7
+            },
+            "peer_stats": {
+                "interval_s": "30s",
+                "full_report_every": 4
+            },
+            "on_demand": {
+                "idle_timeout_s": "10m"
             }
         }
         "#,
@@ -609,7 +1625,19 @@ mod tests {
                         polling: FeaturePolling {
                             wireguard_polling_period: 1000,
                             wireguard_polling_period_after_state_change: 50
-                        }
+                        },
+                        handshake_load: Some(FeatureHandshakeLoad {
+                            under_load_threshold: 42,
+                        }),
+                        uapi_socket: Some(FeatureUapiSocket {
+                            path: Some("/run/telio/wg.sock".to_owned()),
+                            mode: 0o660,
+                            read_only: false,
+                        }),
+                        recovery: Some(FeatureAdapterRecovery { timeout_s: 60 }),
+                        handshake_events: Some(FeatureHandshakeEvents {
+                            failure_timeout_s: 15,
+                        }),
                     },
                     nurse: Some(FeatureNurse {
                         heartbeat_interval: 5,
@@ -624,6 +1652,7 @@ mod tests {
                         enable_relay_conn_data: false,
                         enable_nat_traversal_conn_data: false,
                         state_duration_cap: 10,
+                        enable_dns_failure_data: true,
                     }),
                     lana: Some(FeatureLana {
                         event_path: "some/test/path.db".to_owned(),
@@ -648,6 +1677,11 @@ mod tests {
                         upnp_features: Some(FeatureUpnp {
                             lease_duration_s: 60
                         }),
+                        coordinated_punch: Some(FeatureCoordinatedPunch {
+                            failed_rounds_threshold: 3,
+                            delay_ms: 800,
+                        }),
+                        dual_stack: true,
                     }),
                     is_test_env: Some(true),
                     hide_user_data: false,
@@ -656,7 +1690,22 @@ mod tests {
                         derp_keepalive: Some(14),
                         poll_keepalive: Some(true),
                         enable_polling: Some(true),
+                        enable_peer_presence: true,
                         use_built_in_root_certificates: true,
+                        server_bootstrap: Some(FeatureServerBootstrap {
+                            domain: "relays.example.com".parse().unwrap(),
+                            pinned_keys: vec![PublicKey([0xaa; 32])],
+                            resolvers: vec![IpAddr::from([10, 0, 0, 53])],
+                            refresh_interval_s: 600,
+                        }),
+                        idle_disconnect: Some(FeatureRelayIdleDisconnect {
+                            idle_timeout_s: 300,
+                        }),
+                        enable_compression: true,
+                        path_probes: Some(FeatureRelayPathProbes {
+                            interval_s: 10,
+                            overloaded_jitter_ms: 80,
+                        }),
                     }),
                     validate_keys: FeatureValidateKeys(false),
                     ipv6: true,
@@ -665,6 +1714,19 @@ mod tests {
                         neptun_reset_conns: true,
                         boringtun_reset_conns: true,
                         exclude_private_ip_range: None,
+                        rate_limit: Some(FeatureFirewallRateLimit {
+                            new_connections_per_s: 1,
+                            new_connections_burst: 2,
+                            icmp_echo_per_s: 3,
+                            icmp_echo_burst: 4,
+                        }),
+                        inbound_policy: FirewallPolicy::Allow,
+                        outbound_policy: FirewallPolicy::Deny,
+                        drop_fragments: true,
+                        connection_notifications: true,
+                        monitor_only: true,
+                        exit_switch_policy: ExitSwitchPolicy::Grace,
+                        exit_switch_grace_s: 10,
                     },
                     flush_events_on_stop_timeout_seconds: Some(15),
                     post_quantum_vpn: FeaturePostQuantumVPN {
@@ -681,6 +1743,22 @@ mod tests {
                         exit_dns: Some(FeatureExitDns {
                             auto_switch_dns_ips: Some(true),
                         }),
+                        forward_rules: vec![FeatureDnsForwardRule {
+                            domain: "corp.example.com".parse().unwrap(),
+                            resolvers: vec![IpAddr::from([10, 0, 0, 53])],
+                            bypass_tunnel: true,
+                        }],
+                        soa: Some(FeatureDnsSoa {
+                            primary_ns: "ns.example.com.".parse().unwrap(),
+                            responsible: "admin.example.com.".parse().unwrap(),
+                            refresh_s: 100,
+                            retry_s: 101,
+                            expire_s: 102,
+                            negative_ttl_s: 103,
+                        }),
+                        blocklist: Some(FeatureDnsBlocklist {
+                            sinkhole_ips: vec![IpAddr::from([0, 0, 0, 0]), "::".parse().unwrap()],
+                        }),
                     },
                     pmtu_discovery: Some(FeaturePmtuDiscovery {
                         response_wait_timeout_s: 20,
@@ -691,6 +1769,90 @@ mod tests {
                         trigger_effective_duration: 10,
                         trigger_cooldown_duration: 60,
                     }),
+                    socks5: Some(FeatureSocks5 { port: 1081 }),
+                    obfuscation: Some(FeatureObfuscation {
+                        junk_packet_count: 5,
+                        junk_packet_min_size: 50,
+                        junk_packet_max_size: 1000,
+                        init_packet_junk_size: 15,
+                        response_packet_junk_size: 18,
+                        init_packet_magic_header: 1020325451,
+                        response_packet_magic_header: 3288052141,
+                        under_load_packet_magic_header: 1766607858,
+                        transport_packet_magic_header: 2528465083,
+                    }),
+                    peer_unreachable: Some(FeaturePeerUnreachable {
+                        connection_timeout_s: 45,
+                    }),
+                    lazy_proxy: Some(FeatureLazyProxy {
+                        idle_timeout_s: 600,
+                    }),
+                    redact_node_endpoints: true,
+                    vpn_relay_fallback: Some(FeatureVpnRelayFallback {
+                        handshake_timeout_s: 20,
+                        direct_retry_interval_s: 120,
+                    }),
+                    node_event_debounce: Some(FeatureNodeEventDebounce {
+                        hold_ms: 5000,
+                        node_transition_events: true,
+                    }),
+                    passive_keepalive: Some(FeaturePassiveKeepalive { nat_timeout_s: 20 }),
+                    cellular: Some(FeatureCellular {
+                        detect: false,
+                        profiles: vec![
+                            FeatureCellularProfile {
+                                carrier: Some("Telco".to_owned()),
+                                vpn_keepalive_s: Some(20),
+                                handshake_timeout_s: Some(30),
+                            },
+                            FeatureCellularProfile {
+                                carrier: None,
+                                vpn_keepalive_s: Some(120),
+                                handshake_timeout_s: None,
+                            },
+                        ],
+                    }),
+                    peer_limit: Some(FeaturePeerLimit {
+                        max_peers: 256,
+                        strategy: PeerLimitStrategy::Reject,
+                    }),
+                    dscp: Some(FeatureDscp { value: 34 }),
+                    mesh_watchdog: Some(FeatureMeshWatchdog {
+                        stuck_timeout_s: 600,
+                        step_interval_s: 30,
+                    }),
+                    high_availability: Some(FeatureHighAvailability {
+                        role: HaRole::Standby,
+                        listen_address: SocketAddr::from(([10, 0, 0, 2], 7000)),
+                        peer_address: SocketAddr::from(([10, 0, 0, 1], 7000)),
+                        heartbeat_interval_ms: 200,
+                        takeover_timeout_ms: 1000,
+                    }),
+                    route_conflicts: Some(FeatureRouteConflicts { exclude: true }),
+                    audit_log: Some(FeatureAuditLog {
+                        capacity: 16,
+                        events: true,
+                    }),
+                    threads: Some(FeatureThreads {
+                        worker_priority: ThreadPriority::AboveNormal,
+                        datapath_priority: ThreadPriority::High,
+                    }),
+                    config_queue: Some(FeatureConfigQueue {
+                        settle_ms: 500,
+                        max_delay_ms: 5000,
+                    }),
+                    api_timeout: Some(FeatureApiTimeout {
+                        timeout_ms: 10000,
+                        fail_fast: true,
+                    }),
+                    wake_on_lan: Some(FeatureWakeOnLan { port: 7 }),
+                    peer_stats: Some(FeaturePeerStats {
+                        interval_s: 30,
+                        full_report_every: 4,
+                    }),
+                    on_demand: Some(FeatureOnDemand {
+                        idle_timeout_s: 600,
+                    }),
                 }
             );
         }
@@ -758,6 +1920,15 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_empty_direct_coordinated_punch() {
+            assert_json!(
+                r#"{"direct": {"coordinated_punch": {}}}"#,
+                FeatureCoordinatedPunch::default(),
+                direct.unwrap().coordinated_punch.unwrap()
+            );
+        }
+
         #[test]
         fn test_empty_direct_endpoint_providers_optimization() {
             assert_json!(
@@ -781,11 +1952,111 @@ mod tests {
             assert_json!(r#"{"derp": {}}"#, FeatureDerp::default(), derp.unwrap());
         }
 
+        #[test]
+        fn test_empty_derp_server_bootstrap() {
+            assert_json!(
+                r#"{"derp": {"server_bootstrap": {"domain": "relays.example.com", "pinned_keys": []}}}"#,
+                Some(FeatureServerBootstrap {
+                    domain: "relays.example.com".parse().unwrap(),
+                    pinned_keys: vec![],
+                    resolvers: vec![IpAddr::from([1, 1, 1, 1]), IpAddr::from([8, 8, 8, 8])],
+                    refresh_interval_s: 3600,
+                }),
+                derp.unwrap().server_bootstrap
+            );
+        }
+
+        #[test]
+        fn test_empty_derp_idle_disconnect() {
+            assert_json!(
+                r#"{"derp": {"idle_disconnect": {}}}"#,
+                Some(FeatureRelayIdleDisconnect {
+                    idle_timeout_s: 600
+                }),
+                derp.unwrap().idle_disconnect
+            );
+        }
+
+        #[test]
+        fn test_empty_derp_path_probes() {
+            assert_json!(
+                r#"{"derp": {"path_probes": {}}}"#,
+                Some(FeatureRelayPathProbes {
+                    interval_s: 5,
+                    overloaded_jitter_ms: 50,
+                }),
+                derp.unwrap().path_probes
+            );
+        }
+
         #[test]
         fn test_empty_firewall() {
             assert_json!(r#"{"firewall": {}}"#, FeatureFirewall::default(), firewall);
         }
 
+        #[test]
+        fn test_firewall_default_policies() {
+            let firewall = FeatureFirewall::default();
+            assert_eq!(firewall.inbound_policy, FirewallPolicy::Deny);
+            assert_eq!(firewall.outbound_policy, FirewallPolicy::Allow);
+            assert_eq!(firewall.exit_switch_policy, ExitSwitchPolicy::Allow);
+            assert_eq!(firewall.exit_switch_grace_s, 5);
+        }
+
+        #[test]
+        fn test_empty_handshake_load() {
+            assert_json!(
+                r#"{"wireguard": {"handshake_load": {}}}"#,
+                Some(FeatureHandshakeLoad::default()),
+                wireguard.handshake_load
+            );
+        }
+
+        #[test]
+        fn test_empty_uapi_socket() {
+            assert_json!(
+                r#"{"wireguard": {"uapi_socket": {}}}"#,
+                Some(FeatureUapiSocket {
+                    path: None,
+                    mode: 0o600,
+                    read_only: true,
+                }),
+                wireguard.uapi_socket
+            );
+        }
+
+        #[test]
+        fn test_empty_adapter_recovery() {
+            assert_json!(
+                r#"{"wireguard": {"recovery": {}}}"#,
+                Some(FeatureAdapterRecovery { timeout_s: 30 }),
+                wireguard.recovery
+            );
+        }
+
+        #[test]
+        fn test_empty_handshake_events() {
+            assert_json!(
+                r#"{"wireguard": {"handshake_events": {}}}"#,
+                Some(FeatureHandshakeEvents {
+                    failure_timeout_s: 10,
+                }),
+                wireguard.handshake_events
+            );
+        }
+
+        #[test]
+        fn test_empty_firewall_rate_limit() {
+            assert_json!(
+                r#"{"firewall": {"rate_limit": {}}}"#,
+                FeatureFirewall {
+                    rate_limit: Some(FeatureFirewallRateLimit::default()),
+                    ..Default::default()
+                },
+                firewall
+            );
+        }
+
         #[test]
         fn test_empty_post_quantum_vpn() {
             assert_json!(
@@ -818,6 +2089,24 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_empty_dns_soa() {
+            assert_json!(
+                r#"{"dns": {"soa": {}}}"#,
+                FeatureDnsSoa::default(),
+                dns.soa.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_dns_blocklist() {
+            assert_json!(
+                r#"{"dns": {"blocklist": {}}}"#,
+                FeatureDnsBlocklist::default(),
+                dns.blocklist.unwrap()
+            );
+        }
+
         #[test]
         fn test_empty_pmtu_discovery() {
             assert_json!(
@@ -827,6 +2116,229 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_empty_socks5() {
+            assert_json!(
+                r#"{"socks5": {}}"#,
+                FeatureSocks5::default(),
+                socks5.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_obfuscation() {
+            assert_json!(
+                r#"{"obfuscation": {}}"#,
+                FeatureObfuscation::default(),
+                obfuscation.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_peer_unreachable() {
+            assert_json!(
+                r#"{"peer_unreachable": {}}"#,
+                FeaturePeerUnreachable::default(),
+                peer_unreachable.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_lazy_proxy() {
+            assert_json!(
+                r#"{"lazy_proxy": {}}"#,
+                FeatureLazyProxy::default(),
+                lazy_proxy.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_vpn_relay_fallback() {
+            assert_json!(
+                r#"{"vpn_relay_fallback": {}}"#,
+                FeatureVpnRelayFallback::default(),
+                vpn_relay_fallback.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_node_event_debounce() {
+            assert_json!(
+                r#"{"node_event_debounce": {}}"#,
+                FeatureNodeEventDebounce::default(),
+                node_event_debounce.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_passive_keepalive() {
+            assert_json!(
+                r#"{"passive_keepalive": {}}"#,
+                FeaturePassiveKeepalive::default(),
+                passive_keepalive.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_cellular() {
+            assert_json!(
+                r#"{"cellular": {}}"#,
+                FeatureCellular::default(),
+                cellular.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_peer_limit() {
+            assert_json!(
+                r#"{"peer_limit": {}}"#,
+                FeaturePeerLimit::default(),
+                peer_limit.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_dscp() {
+            assert_json!(r#"{"dscp": {}}"#, FeatureDscp { value: 46 }, dscp.unwrap());
+        }
+
+        #[test]
+        fn test_empty_mesh_watchdog() {
+            assert_json!(
+                r#"{"mesh_watchdog": {}}"#,
+                FeatureMeshWatchdog {
+                    stuck_timeout_s: 300,
+                    step_interval_s: 60,
+                },
+                mesh_watchdog.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_minimal_high_availability() {
+            assert_json!(
+                r#"{"high_availability": {"role": "primary", "listen_address": "127.0.0.1:7000", "peer_address": "127.0.0.1:7001"}}"#,
+                FeatureHighAvailability {
+                    role: HaRole::Primary,
+                    listen_address: SocketAddr::from(([127, 0, 0, 1], 7000)),
+                    peer_address: SocketAddr::from(([127, 0, 0, 1], 7001)),
+                    heartbeat_interval_ms: 500,
+                    takeover_timeout_ms: 3000,
+                },
+                high_availability.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_route_conflicts() {
+            assert_json!(
+                r#"{"route_conflicts": {}}"#,
+                FeatureRouteConflicts { exclude: false },
+                route_conflicts.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_audit_log() {
+            assert_json!(
+                r#"{"audit_log": {}}"#,
+                FeatureAuditLog {
+                    capacity: 1024,
+                    events: false,
+                },
+                audit_log.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_threads() {
+            assert_json!(
+                r#"{"threads": {}}"#,
+                FeatureThreads {
+                    worker_priority: ThreadPriority::Normal,
+                    datapath_priority: ThreadPriority::Normal,
+                },
+                threads.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_config_queue() {
+            assert_json!(
+                r#"{"config_queue": {}}"#,
+                FeatureConfigQueue {
+                    settle_ms: 2000,
+                    max_delay_ms: 10000,
+                },
+                config_queue.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_api_timeout() {
+            assert_json!(
+                r#"{"api_timeout": {}}"#,
+                FeatureApiTimeout {
+                    timeout_ms: 30000,
+                    fail_fast: false,
+                },
+                api_timeout.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_wake_on_lan() {
+            assert_json!(
+                r#"{"wake_on_lan": {}}"#,
+                FeatureWakeOnLan { port: 9 },
+                wake_on_lan.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_peer_stats() {
+            assert_json!(
+                r#"{"peer_stats": {}}"#,
+                FeaturePeerStats {
+                    interval_s: 10,
+                    full_report_every: 6,
+                },
+                peer_stats.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_empty_on_demand() {
+            assert_json!(
+                r#"{"on_demand": {}}"#,
+                FeatureOnDemand {
+                    idle_timeout_s: 300,
+                },
+                on_demand.unwrap()
+            );
+        }
+
+        #[test]
+        fn test_cellular_profile_matches_carrier() {
+            let profile = |carrier: Option<&str>, vpn_keepalive_s| FeatureCellularProfile {
+                carrier: carrier.map(str::to_owned),
+                vpn_keepalive_s: Some(vpn_keepalive_s),
+                handshake_timeout_s: None,
+            };
+            let cellular = FeatureCellular {
+                detect: true,
+                profiles: vec![profile(Some("Telco"), 20), profile(None, 120)],
+            };
+
+            assert_eq!(
+                cellular.profile(Some("TELCO")),
+                Some(&profile(Some("Telco"), 20))
+            );
+            assert_eq!(cellular.profile(Some("Other")), Some(&profile(None, 120)));
+            assert_eq!(cellular.profile(None), Some(&profile(None, 120)));
+            assert_eq!(FeatureCellular::default().profile(None), None);
+        }
+
         #[test]
         fn test_json_direct_accepts_arbitrary_providers() {
             assert_json!(
@@ -843,6 +2355,47 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_json_accepts_human_friendly_durations() {
+            assert_json!(
+                FeatureWireguard,
+                r#"{
+                    "persistent_keepalive": {"vpn": "25s", "direct": 5, "stun": null},
+                    "polling": {"wireguard_polling_period": "2s"}
+                }"#,
+                FeatureWireguard {
+                    persistent_keepalive: FeaturePersistentKeepalive {
+                        vpn: Some(25),
+                        direct: 5,
+                        proxying: Some(25),
+                        stun: None,
+                    },
+                    polling: FeaturePolling {
+                        wireguard_polling_period: 2000,
+                        wireguard_polling_period_after_state_change: 50,
+                    },
+                    ..Default::default()
+                }
+            );
+            assert_json!(
+                r#"{"nurse": {"heartbeat_interval": "1h", "qos": {"rtt_interval": "5m"}}}"#,
+                (60 * 60, 5 * 60),
+                nurse
+                    .map(|nurse| (nurse.heartbeat_interval, nurse.qos.unwrap().rtt_interval))
+                    .unwrap()
+            );
+            assert_json!(
+                r#"{"firewall": {"exit_switch_grace_s": "10s"}}"#,
+                10,
+                firewall.exit_switch_grace_s
+            );
+            assert_json!(
+                r#"{"derp": {"server_bootstrap": {"domain": "relays.example.com", "pinned_keys": [], "refresh_interval_s": "2h"}}}"#,
+                2 * 60 * 60,
+                derp.unwrap().server_bootstrap.unwrap().refresh_interval_s
+            );
+        }
+
         #[test]
         fn test_json_allow_null_for_wireguard() {
             assert_json!(
@@ -852,6 +2405,53 @@ mod tests {
             );
         }
 
+        #[test]
+        fn test_deprecated_paths_are_mapped_to_direct() {
+            let features: Features =
+                serde_json::from_str(r#"{"paths": {"priority": ["direct"]}}"#).unwrap();
+            assert_eq!(features.direct, Some(FeatureDirect::default()));
+            assert_eq!(
+                features.warnings(),
+                vec![FeatureDeprecation {
+                    field: "paths",
+                    successor: "direct",
+                    since: "4.0.0",
+                }]
+            );
+
+            let features: Features =
+                serde_json::from_str(r#"{"paths": {"priority": ["direct"], "force": "relay"}}"#)
+                    .unwrap();
+            assert_eq!(features.direct, None);
+
+            let features: Features = serde_json::from_str(
+                r#"{"paths": {"priority": ["direct"]}, "direct": {"endpoint_interval_secs": 5}}"#,
+            )
+            .unwrap();
+            assert_eq!(features.direct.unwrap().endpoint_interval_secs, 5);
+        }
+
+        #[test]
+        fn test_deprecated_boringtun_reset_conns_is_mapped() {
+            let features: Features =
+                serde_json::from_str(r#"{"firewall": {"boringtun_reset_conns": true}}"#).unwrap();
+            assert!(features.firewall.neptun_reset_conns);
+            assert_eq!(features.warnings().len(), 1);
+            assert!(Features::default().warnings().is_empty());
+        }
+
+        #[test]
+        fn test_effective_features() {
+            let features = Features {
+                paths: Some(FeaturePaths {
+                    priority: vec![PathType::Direct],
+                    force: None,
+                }),
+                ..Default::default()
+            };
+            assert_eq!(features.effective().direct, Some(FeatureDirect::default()));
+        }
+
         #[test]
         fn test_hide_user_data() {
             assert_json!(r#"{}"#, true, hide_user_data);
@@ -0,0 +1,172 @@
+//! Domain names of the feature config
+//!
+//! Names are validated when the config is deserialized, so a malformed one is reported along
+//! with its position in the config instead of failing once the feature is started.
+
+use std::{convert::TryFrom, fmt, ops::Deref, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Longest domain name in its textual form, without the trailing dot
+const MAX_NAME_LEN: usize = 253;
+/// Longest label of a domain name
+const MAX_LABEL_LEN: usize = 63;
+
+/// Reasons a domain name is rejected
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum InvalidDomainName {
+    /// The name has no labels at all
+    #[error("domain name is empty")]
+    Empty,
+    /// The name is longer than 253 characters
+    #[error("domain name `{0}` is too long")]
+    TooLong(String),
+    /// One of the labels is empty or longer than 63 characters
+    #[error("domain name `{0}` has a label of invalid length")]
+    BadLabelLength(String),
+    /// One of the labels has characters other than letters, digits, `-` and `_`, or starts or
+    /// ends with a hyphen
+    #[error("domain name `{0}` has an invalid label")]
+    BadLabel(String),
+}
+
+/// Domain name, either relative or fully qualified with the trailing dot
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DomainName(String);
+
+impl DomainName {
+    /// Name known to be valid, e.g. a default of the config
+    pub(crate) fn from_static(name: &'static str) -> Self {
+        Self(name.to_owned())
+    }
+
+    /// Whether the name ends with the root label, i.e. a dot
+    pub fn is_fqdn(&self) -> bool {
+        self.0.ends_with('.')
+    }
+}
+
+impl TryFrom<String> for DomainName {
+    type Error = InvalidDomainName;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        let relative = name.strip_suffix('.').unwrap_or(&name);
+        if relative.is_empty() {
+            return Err(InvalidDomainName::Empty);
+        }
+        if relative.len() > MAX_NAME_LEN {
+            return Err(InvalidDomainName::TooLong(name));
+        }
+        for label in relative.split('.') {
+            if label.is_empty() || label.len() > MAX_LABEL_LEN {
+                return Err(InvalidDomainName::BadLabelLength(name));
+            }
+            if label.starts_with('-')
+                || label.ends_with('-')
+                || !label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(InvalidDomainName::BadLabel(name));
+            }
+        }
+        Ok(Self(name))
+    }
+}
+
+impl FromStr for DomainName {
+    type Err = InvalidDomainName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::try_from(name.to_owned())
+    }
+}
+
+impl From<DomainName> for String {
+    fn from(name: DomainName) -> Self {
+        name.0
+    }
+}
+
+impl Deref for DomainName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<str> for DomainName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names_are_accepted() {
+        for name in [
+            "nord",
+            "relays.example.com",
+            "ns.example.com.",
+            "_telio-relay._tcp.example.com",
+            "xn--bcher-kva.example",
+        ] {
+            assert_eq!(name.parse::<DomainName>().as_deref(), Ok(name));
+        }
+        assert!("ns.example.com.".parse::<DomainName>().unwrap().is_fqdn());
+        assert!(!"example.com".parse::<DomainName>().unwrap().is_fqdn());
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        assert_eq!("".parse::<DomainName>(), Err(InvalidDomainName::Empty));
+        assert_eq!(".".parse::<DomainName>(), Err(InvalidDomainName::Empty));
+        assert_eq!(
+            "a..b".parse::<DomainName>(),
+            Err(InvalidDomainName::BadLabelLength("a..b".to_owned()))
+        );
+        let long_label = format!("{}.com", "a".repeat(64));
+        assert_eq!(
+            long_label.parse::<DomainName>(),
+            Err(InvalidDomainName::BadLabelLength(long_label))
+        );
+        let long_name = vec!["a".repeat(63); 4].join(".");
+        assert_eq!(
+            long_name.parse::<DomainName>(),
+            Err(InvalidDomainName::TooLong(long_name))
+        );
+        for name in [
+            "-a.com",
+            "a-.com",
+            "exa mple.com",
+            "1.2.3.4:53",
+            "https://a.com",
+        ] {
+            assert_eq!(
+                name.parse::<DomainName>(),
+                Err(InvalidDomainName::BadLabel(name.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_name_is_reported_when_deserialized() {
+        let err = serde_json::from_str::<DomainName>(r#""bad name.com""#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "domain name `bad name.com` has an invalid label at line 1 column 14"
+        );
+    }
+}
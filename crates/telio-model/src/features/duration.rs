@@ -0,0 +1,194 @@
+//! Durations of the feature config
+//!
+//! Interval fields accept both plain integers, in the unit of the field, and human friendly
+//! durations like `"25s"` or `"5m"`. The latter are parsed by [humantime] into a [Duration] and
+//! normalized to the unit of the field, so `"1m"` is `60` in a field in seconds and `60000` in
+//! one in milliseconds.
+//!
+//! Only the input is affected. The fields keep their integer types and the units named by their
+//! `_s` and `_ms` suffixes, which are also the types exposed through the bindings.
+
+use std::{convert::TryFrom, fmt, time::Duration};
+
+use serde::{
+    de::{Error, Unexpected, Visitor},
+    Deserializer,
+};
+
+/// Deserialize a duration in seconds
+pub(super) fn secs<'de, D, T>(de: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    in_unit(de, Unit::SECS)
+}
+
+/// Deserialize a duration in milliseconds
+pub(super) fn millis<'de, D, T>(de: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    in_unit(de, Unit::MILLIS)
+}
+
+/// Deserialize an optional duration in seconds, `null` being `None`
+pub(super) fn opt_secs<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    match de.deserialize_option(OptionalUnit(Unit::SECS))? {
+        Some(value) => narrow(value, Unit::SECS).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn in_unit<'de, D, T>(de: D, unit: Unit) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    narrow(de.deserialize_any(unit)?, unit)
+}
+
+fn narrow<T: TryFrom<u64>, E: Error>(value: u64, unit: Unit) -> Result<T, E> {
+    T::try_from(value).map_err(|_| E::invalid_value(Unexpected::Unsigned(value), &unit))
+}
+
+#[derive(Clone, Copy)]
+struct Unit {
+    length: Duration,
+    name: &'static str,
+}
+
+impl Unit {
+    const SECS: Unit = Unit {
+        length: Duration::from_secs(1),
+        name: "seconds",
+    };
+    const MILLIS: Unit = Unit {
+        length: Duration::from_millis(1),
+        name: "milliseconds",
+    };
+}
+
+impl<'de> Visitor<'de> for Unit {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} or a duration like \"25s\"", self.name)
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<u64, E> {
+        u64::try_from(v).map_err(|_| E::invalid_value(Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<u64, E> {
+        let duration = humantime::parse_duration(v)
+            .map_err(|e| E::custom(format!("invalid duration {v:?}: {e}")))?;
+        let length = self.length.as_nanos();
+        if duration.as_nanos() % length != 0 {
+            return Err(E::custom(format!(
+                "duration {v:?} is not a whole number of {}",
+                self.name
+            )));
+        }
+        u64::try_from(duration.as_nanos() / length)
+            .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+struct OptionalUnit(Unit);
+
+impl<'de> Visitor<'de> for OptionalUnit {
+    type Value = Option<u64>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    fn visit_none<E: Error>(self) -> Result<Option<u64>, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Option<u64>, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, de: D) -> Result<Option<u64>, D::Error> {
+        de.deserialize_any(self.0).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Intervals {
+        #[serde(deserialize_with = "secs")]
+        secs: u32,
+        #[serde(deserialize_with = "millis")]
+        millis: u64,
+        #[serde(default, deserialize_with = "opt_secs")]
+        opt_secs: Option<u32>,
+    }
+
+    fn parse(json: &str) -> Result<Intervals, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn integers_are_in_the_unit_of_the_field() {
+        assert_eq!(
+            parse(r#"{"secs": 25, "millis": 1500, "opt_secs": 5}"#).unwrap(),
+            Intervals {
+                secs: 25,
+                millis: 1500,
+                opt_secs: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn durations_are_normalized_to_the_unit_of_the_field() {
+        assert_eq!(
+            parse(r#"{"secs": "5m", "millis": "1s 500ms", "opt_secs": "1h"}"#).unwrap(),
+            Intervals {
+                secs: 300,
+                millis: 1500,
+                opt_secs: Some(3600),
+            }
+        );
+    }
+
+    #[test]
+    fn missing_or_null_optional_duration_is_none() {
+        let expected = Intervals {
+            secs: 1,
+            millis: 1,
+            opt_secs: None,
+        };
+        assert_eq!(parse(r#"{"secs": 1, "millis": 1}"#).unwrap(), expected);
+        assert_eq!(
+            parse(r#"{"secs": 1, "millis": 1, "opt_secs": null}"#).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        assert!(parse(r#"{"secs": "25", "millis": 1}"#).is_err());
+        assert!(parse(r#"{"secs": "1500ms", "millis": 1}"#).is_err());
+        assert!(parse(r#"{"secs": -1, "millis": 1}"#).is_err());
+        assert!(parse(r#"{"secs": 4294967296, "millis": 1}"#).is_err());
+        assert!(parse(r#"{"secs": 1.5, "millis": 1}"#).is_err());
+    }
+}
@@ -4,7 +4,9 @@ pub mod config;
 pub mod constants;
 pub mod event;
 pub mod features;
+pub mod memory;
 pub mod mesh;
+pub mod network;
 pub mod validation;
 
 pub use std::collections::HashMap;
@@ -0,0 +1,83 @@
+//! Accounting of the memory held by the subsystems of a device
+
+use std::collections::BTreeMap;
+
+/// Subsystem whose memory usage is accounted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemoryComponent {
+    /// Packet and socket buffers of the UDP proxy
+    ProxyBuffers,
+    /// Connection tracking entries of the firewall
+    FirewallConntrack,
+    /// Records held by the local DNS server
+    DnsCache,
+    /// Packets queued by the relay connection
+    RelayQueues,
+}
+
+/// Memory usage of a single subsystem
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ComponentMemoryUsage {
+    /// The accounted subsystem
+    pub component: MemoryComponent,
+    /// Bytes held at the last sample
+    pub current_bytes: u64,
+    /// Most bytes held at any sample since the device was started
+    pub peak_bytes: u64,
+}
+
+/// Current and peak memory usage of the subsystems, fed with periodic samples.
+///
+/// Usage is taken from the capacities of the buffers and tables of the subsystems and from the
+/// socket buffer sizes reported by the kernel. Allocator overhead is not included.
+#[derive(Debug, Default)]
+pub struct MemoryAccounting {
+    usage: BTreeMap<MemoryComponent, ComponentMemoryUsage>,
+}
+
+impl MemoryAccounting {
+    /// Record the bytes currently held by the component
+    pub fn record(&mut self, component: MemoryComponent, bytes: u64) {
+        let usage = self.usage.entry(component).or_insert(ComponentMemoryUsage {
+            component,
+            current_bytes: 0,
+            peak_bytes: 0,
+        });
+        usage.current_bytes = bytes;
+        usage.peak_bytes = usage.peak_bytes.max(bytes);
+    }
+
+    /// Usage of every component sampled so far
+    pub fn usage(&self) -> Vec<ComponentMemoryUsage> {
+        self.usage.values().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peak_outlives_current_usage() {
+        let mut accounting = MemoryAccounting::default();
+        accounting.record(MemoryComponent::RelayQueues, 4096);
+        accounting.record(MemoryComponent::FirewallConntrack, 100);
+        accounting.record(MemoryComponent::RelayQueues, 1024);
+
+        assert_eq!(
+            accounting.usage(),
+            vec![
+                ComponentMemoryUsage {
+                    component: MemoryComponent::FirewallConntrack,
+                    current_bytes: 100,
+                    peak_bytes: 100,
+                },
+                ComponentMemoryUsage {
+                    component: MemoryComponent::RelayQueues,
+                    current_bytes: 1024,
+                    peak_bytes: 4096,
+                },
+            ]
+        );
+    }
+}
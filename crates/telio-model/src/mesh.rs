@@ -2,7 +2,7 @@
 
 use super::EndpointMap as RelayEndpointMap;
 
-use crate::features::PathType;
+use crate::features::{EndpointProvider, PathType};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, net::IpAddr};
 use telio_crypto::PublicKey;
@@ -52,11 +52,57 @@ pub struct Node {
     pub allow_multicast: bool,
     /// Flag to control whether the Node allows multicast messages from us
     pub peer_allows_multicast: bool,
+    /// Type of our candidate used by the direct connection
+    pub local_candidate: Option<CandidateType>,
+    /// Type of the node's candidate used by the direct connection
+    pub remote_candidate: Option<CandidateType>,
+    /// Our endpoint used by the direct connection, as advertised to the node
+    pub local_endpoint: Option<SocketAddr>,
+}
+
+/// Expected connection to a prospective peer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectivityOutlook {
+    /// A direct connection is likely to be formed
+    Direct,
+    /// Traffic will most likely be relayed, with lower throughput and higher latency
+    RelayOnly,
+    /// Neither relay nor direct connection is expected to work
+    Unreachable,
+}
+
+/// Path a node is allowed to take, set per node at runtime
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PathPreference {
+    /// Upgrade to a direct connection whenever possible, as allowed by the features
+    #[default]
+    DirectPreferred,
+    /// Always relay the traffic, never attempting a direct connection
+    RelayOnly,
+}
+
+/// Dry-run assessment of the connectivity to a node which is not yet in the meshnet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectivityPreview {
+    /// The relay server, through which every node is reachable, is connected
+    pub relay_reachable: bool,
+    /// Direct connections are enabled on this device
+    pub direct_enabled: bool,
+    /// One of the node's endpoint hints is on the same network as this device
+    pub same_network: bool,
+    /// This device has a publicly reachable endpoint, discovered through STUN or UPnP
+    pub local_public_endpoint: bool,
+    /// One of the node's endpoint hints is publicly routable
+    pub remote_public_endpoint: bool,
+    /// Expected connection, derived from the above
+    pub outlook: ConnectivityOutlook,
 }
 
 /// Description of the Exit Node
 /// It is the gateway node to the internet
-#[derive(Debug, Default, Clone, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct ExitNode {
     /// An identifier for an exit node
     /// Makes it possible to distinguish different exit nodes in the presence of key reuse
@@ -82,6 +128,131 @@ pub enum NodeState {
     Connected,
 }
 
+/// Type of the endpoint candidate which formed a direct connection
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateType {
+    /// Address of a local interface
+    Local,
+    /// Address discovered through STUN
+    Stun,
+    /// Address mapped on the gateway through UPnP
+    Upnp,
+    /// Address the node's packets actually arrive from, differing from the candidate
+    /// agreed on, e.g. after a NAT rebinding
+    PeerReflexive,
+}
+
+impl From<EndpointProvider> for CandidateType {
+    fn from(provider: EndpointProvider) -> Self {
+        match provider {
+            EndpointProvider::Local => Self::Local,
+            EndpointProvider::Stun => Self::Stun,
+            EndpointProvider::Upnp => Self::Upnp,
+        }
+    }
+}
+
+/// Stage of the connectivity check of a local candidate against a peer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateCheckState {
+    /// Waiting to ask the peer for its candidates, e.g. after a failed check
+    Idle,
+    /// The peer was asked for its candidates and did not answer yet
+    Gathering,
+    /// The candidates of the peer are being pinged
+    Pinging,
+    /// A candidate of the peer answered the pings and was handed over to WireGuard
+    Validated,
+}
+
+/// Local endpoint candidate and the outcome of its latest check against a peer
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct LocalCandidate {
+    /// Endpoint advertised to the peer
+    pub endpoint: SocketAddr,
+    /// How the endpoint was discovered
+    pub candidate_type: CandidateType,
+    /// Stage of the check against the peer
+    pub state: CandidateCheckState,
+    /// Pinging rounds which timed out since the last validated candidate
+    pub failed_rounds: u32,
+    /// Round trip of the pong which validated the candidate, in microseconds
+    pub rtt_us: Option<u64>,
+}
+
+/// Endpoint candidate of a peer, as received in its answer to our call me maybe request
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct RemoteCandidate {
+    /// Endpoint advertised by the peer
+    pub endpoint: SocketAddr,
+    /// How the peer discovered the endpoint, known only once it answered our pings
+    pub candidate_type: Option<CandidateType>,
+    /// One of our local candidates validated the connection to it
+    pub validated: bool,
+}
+
+/// Endpoint candidates currently known for a peer, with the outcomes of their checks
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerCandidates {
+    /// Our candidates, one per endpoint provider and address
+    pub local: Vec<LocalCandidate>,
+    /// Candidates of the peer
+    pub remote: Vec<RemoteCandidate>,
+}
+
+/// Address family of an endpoint
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// IPv4 endpoint
+    IPv4,
+    /// IPv6 endpoint
+    IPv6,
+}
+
+impl From<IpAddr> for AddressFamily {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(_) => Self::IPv4,
+            IpAddr::V6(_) => Self::IPv6,
+        }
+    }
+}
+
+/// Outcomes of the connectivity checks done over one address family
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FamilyCheckStats {
+    /// Pinging rounds started
+    pub attempts: u32,
+    /// Pinging rounds which validated an endpoint
+    pub successes: u32,
+}
+
+/// Events of the connection with a single peer, counted since it was added
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerCounters {
+    /// Public key of the peer
+    pub public_key: PublicKey,
+    /// WireGuard handshakes completed, including the periodic rekeying
+    pub handshakes_completed: u64,
+    /// Upgrades to a direct connection requested by either side
+    pub upgrades_attempted: u64,
+    /// Upgrades to a direct connection agreed on by both sides
+    pub upgrades_succeeded: u64,
+    /// Direct connections which fell back to the relay
+    pub downgrades: u64,
+    /// Keepalives sent to keep the direct connection open
+    pub keepalives_sent: u64,
+    /// Packets from or to the peer dropped by the firewall
+    pub firewall_drops: u64,
+    /// Inbound TCP connection attempts of the peer dropped by the firewall rate limit
+    pub rate_limited_connections: u64,
+    /// Inbound ICMP echo requests of the peer dropped by the firewall rate limit
+    pub rate_limited_echo_requests: u64,
+}
+
 /// This is a hint state computed based on the last_rx_timestamp
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -0,0 +1,59 @@
+//! State of the network, as known by the app
+
+use serde::Deserialize;
+
+/// Network state info passed by the app to `notify_network_change`, encoded as JSON
+///
+/// All of the fields are optional, and an empty string stands for no info at all.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct NetworkInfo {
+    /// The device is on a cellular network, detected by libtelio if `None`
+    pub cellular: Option<bool>,
+    /// Name of the cellular carrier
+    pub carrier: Option<String>,
+    /// Handle of the new default network on Android, external sockets are rebound to it
+    pub network_handle: Option<u64>,
+}
+
+impl NetworkInfo {
+    /// Parse the info passed by the app
+    pub fn new_from_str(info: &str) -> Result<Self, serde_json::Error> {
+        if info.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_info_is_parsed() {
+        assert_eq!(
+            NetworkInfo::new_from_str("").unwrap(),
+            NetworkInfo::default()
+        );
+        assert_eq!(
+            NetworkInfo::new_from_str("{}").unwrap(),
+            NetworkInfo::default()
+        );
+        assert_eq!(
+            NetworkInfo::new_from_str(r#"{"cellular": true, "carrier": "Telco"}"#).unwrap(),
+            NetworkInfo {
+                cellular: Some(true),
+                carrier: Some("Telco".to_owned()),
+                network_handle: None,
+            }
+        );
+        assert_eq!(
+            NetworkInfo::new_from_str(r#"{"network_handle": 432}"#)
+                .unwrap()
+                .network_handle,
+            Some(432)
+        );
+        assert!(NetworkInfo::new_from_str("cellular").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! ICMP echo through the tunnel, so the meshnet nodes can be pinged without any privileges
+
+use std::{
+    future::poll_fn,
+    net::IpAddr,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use smoltcp::{
+    iface::SocketHandle,
+    socket::icmp,
+    wire::{Icmpv4Message, Icmpv4Packet, Icmpv6Message, Icmpv6Packet},
+};
+
+use crate::stack::{Error, Shared};
+
+/// Header of the echo messages: type, code, checksum, identifier and sequence number
+const ECHO_HEADER_LEN: usize = 8;
+/// Payload of the echo requests, the same as the default of ping(8)
+const ECHO_PAYLOAD_LEN: usize = 56;
+/// Echo messages buffered by the socket in each direction
+const ECHO_BUFFER_LEN: usize = 4;
+
+/// Socket exchanging the echo messages of a single identifier
+pub(crate) struct EchoSocket {
+    handle: SocketHandle,
+    shared: Shared,
+}
+
+impl EchoSocket {
+    pub(crate) fn bind(shared: Shared, ident: u16) -> Result<Self, Error> {
+        let buffer = || {
+            icmp::PacketBuffer::new(
+                vec![icmp::PacketMetadata::EMPTY; ECHO_BUFFER_LEN],
+                vec![0; ECHO_BUFFER_LEN * (ECHO_HEADER_LEN + ECHO_PAYLOAD_LEN)],
+            )
+        };
+        let mut socket = icmp::Socket::new(buffer(), buffer());
+        socket
+            .bind(icmp::Endpoint::Ident(ident))
+            .map_err(|e| Error::Echo(e.to_string()))?;
+        let handle = shared.lock().sockets.add(socket);
+        Ok(Self { handle, shared })
+    }
+
+    /// Send an echo request and wait for its reply, returns the round trip time, or `None` if
+    /// no reply came in time
+    pub(crate) async fn ping(
+        &self,
+        remote: IpAddr,
+        ident: u16,
+        seq_no: u16,
+        timeout: Duration,
+    ) -> Result<Option<Duration>, Error> {
+        let started = Instant::now();
+        self.shared
+            .lock()
+            .sockets
+            .get_mut::<icmp::Socket>(self.handle)
+            .send_slice(&echo_request(remote, ident, seq_no), remote.into())
+            .map_err(|e| Error::Echo(e.to_string()))?;
+        self.shared.wake();
+
+        let reply = poll_fn(|cx| self.poll_reply(cx, remote, seq_no));
+        Ok(tokio::time::timeout(timeout, reply)
+            .await
+            .ok()
+            .map(|()| started.elapsed()))
+    }
+
+    fn poll_reply(&self, cx: &mut Context<'_>, remote: IpAddr, seq_no: u16) -> Poll<()> {
+        let mut inner = self.shared.lock();
+        let socket = inner.sockets.get_mut::<icmp::Socket>(self.handle);
+
+        let mut buf = [0; ECHO_HEADER_LEN + ECHO_PAYLOAD_LEN];
+        while let Ok((len, from)) = socket.recv_slice(&mut buf) {
+            // Replies of the earlier requests which came too late are skipped
+            if IpAddr::from(from) == remote && is_echo_reply(&buf[..len], remote, seq_no) {
+                return Poll::Ready(());
+            }
+        }
+
+        socket.register_recv_waker(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for EchoSocket {
+    fn drop(&mut self) {
+        self.shared.lock().sockets.remove(self.handle);
+    }
+}
+
+/// The stack parses the request and emits it again, filling in the checksum
+fn echo_request(remote: IpAddr, ident: u16, seq_no: u16) -> Vec<u8> {
+    let mut buf = vec![0; ECHO_HEADER_LEN + ECHO_PAYLOAD_LEN];
+    match remote {
+        IpAddr::V4(_) => {
+            let mut packet = Icmpv4Packet::new_unchecked(&mut buf[..]);
+            packet.set_msg_type(Icmpv4Message::EchoRequest);
+            packet.set_msg_code(0);
+            packet.set_echo_ident(ident);
+            packet.set_echo_seq_no(seq_no);
+        }
+        IpAddr::V6(_) => {
+            let mut packet = Icmpv6Packet::new_unchecked(&mut buf[..]);
+            packet.set_msg_type(Icmpv6Message::EchoRequest);
+            packet.set_msg_code(0);
+            packet.set_echo_ident(ident);
+            packet.set_echo_seq_no(seq_no);
+        }
+    }
+    buf
+}
+
+fn is_echo_reply(buf: &[u8], remote: IpAddr, seq_no: u16) -> bool {
+    match remote {
+        IpAddr::V4(_) => Icmpv4Packet::new_checked(buf).is_ok_and(|packet| {
+            packet.msg_type() == Icmpv4Message::EchoReply && packet.echo_seq_no() == seq_no
+        }),
+        IpAddr::V6(_) => Icmpv6Packet::new_checked(buf).is_ok_and(|packet| {
+            packet.msg_type() == Icmpv6Message::EchoReply && packet.echo_seq_no() == seq_no
+        }),
+    }
+}
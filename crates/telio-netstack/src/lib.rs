@@ -0,0 +1,23 @@
+#![deny(missing_docs)]
+//! Userspace TCP/IP stack for running telio without a tunnel interface
+//!
+//! Instead of handing decrypted packets to the operating system, the WireGuard adapter is
+//! given one end of a [VirtualTun]. The other end is driven by an in-process network stack,
+//! which lets the application open connections to meshnet nodes without any privileges:
+//! 1. [VirtualTun] carries raw IP packets between the adapter and the stack.
+//! 2. [Netstack] runs the TCP/IP state machines on top of those packets.
+//! 3. [TcpStream] and [TcpListener] expose connections as regular async streams.
+//! 4. [Socks5Gateway] lets unmodified applications use the stack through a local proxy.
+//! 5. [Netstack::ping] sends echo requests to the meshnet nodes.
+
+mod icmp;
+mod queue;
+mod socks5;
+mod stack;
+mod tcp;
+mod virtual_tun;
+
+pub use socks5::{Hosts, Socks5Gateway};
+pub use stack::{Config, Error, Netstack};
+pub use tcp::{TcpListener, TcpStream};
+pub use virtual_tun::VirtualTun;
@@ -0,0 +1,131 @@
+//! In-memory packet queues exposed to smoltcp as a network device
+
+use std::collections::VecDeque;
+
+use smoltcp::{
+    phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken},
+    time::Instant,
+};
+use telio_utils::telio_log_trace;
+
+/// Maximum number of packets waiting to be processed by the stack
+const MAX_RX_QUEUE_LEN: usize = 256;
+
+/// Network device which buffers packets instead of putting them on a wire
+///
+/// Received packets are pushed by the tunnel reader and consumed during interface poll,
+/// packets produced by the interface are collected until they are flushed to the tunnel.
+pub(crate) struct PacketQueue {
+    rx: VecDeque<Vec<u8>>,
+    tx: VecDeque<Vec<u8>>,
+    mtu: usize,
+}
+
+impl PacketQueue {
+    pub(crate) fn new(mtu: usize) -> Self {
+        Self {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            mtu,
+        }
+    }
+
+    /// Queue a packet coming from the tunnel, dropping it if the stack is lagging behind
+    pub(crate) fn push_rx(&mut self, packet: Vec<u8>) {
+        if self.rx.len() >= MAX_RX_QUEUE_LEN {
+            telio_log_trace!("Netstack rx queue is full, dropping packet");
+            return;
+        }
+        self.rx.push_back(packet);
+    }
+
+    /// Take all packets which are ready to be written to the tunnel
+    pub(crate) fn take_tx(&mut self) -> VecDeque<Vec<u8>> {
+        std::mem::take(&mut self.tx)
+    }
+}
+
+pub(crate) struct QueueRxToken(Vec<u8>);
+
+impl RxToken for QueueRxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+pub(crate) struct QueueTxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl<'a> TxToken for QueueTxToken<'a> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = vec![0; len];
+        let result = f(&mut packet);
+        self.0.push_back(packet);
+        result
+    }
+}
+
+impl Device for PacketQueue {
+    type RxToken<'a>
+        = QueueRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = QueueTxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.rx.pop_front()?;
+        Some((QueueRxToken(packet), QueueTxToken(&mut self.tx)))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(QueueTxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ip;
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rx_queue_is_bounded() {
+        let mut queue = PacketQueue::new(1420);
+        for i in 0..MAX_RX_QUEUE_LEN + 10 {
+            queue.push_rx(vec![i as u8]);
+        }
+        assert_eq!(queue.rx.len(), MAX_RX_QUEUE_LEN);
+        assert_eq!(queue.rx.front(), Some(&vec![0]));
+    }
+
+    #[test]
+    fn transmitted_packets_are_collected() {
+        let mut queue = PacketQueue::new(1420);
+        queue.push_rx(vec![1, 2, 3]);
+
+        let (rx, tx) = queue.receive(Instant::now()).unwrap();
+        assert_eq!(rx.consume(|packet| packet.to_vec()), vec![1, 2, 3]);
+        tx.consume(2, |packet| packet.copy_from_slice(&[4, 5]));
+        queue
+            .transmit(Instant::now())
+            .unwrap()
+            .consume(1, |packet| packet[0] = 6);
+
+        assert!(queue.receive(Instant::now()).is_none());
+        assert_eq!(queue.take_tx(), [vec![4, 5], vec![6]]);
+        assert!(queue.take_tx().is_empty());
+    }
+}
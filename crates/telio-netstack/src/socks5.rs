@@ -0,0 +1,342 @@
+//! SOCKS5 proxy routing application connections into the meshnet
+//!
+//! Implements the CONNECT command of [RFC 1928] without authentication, which is enough for
+//! CLI tools and browsers to reach meshnet nodes by IP address or by hostname.
+//!
+//! [RFC 1928]: https://www.rfc-editor.org/rfc/rfc1928
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use tokio::{
+    io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+
+use telio_task::{Runtime, RuntimeExt, Task, WaitResponse};
+use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
+
+use crate::stack::{Error, Netstack};
+
+const SOCKS_VERSION: u8 = 0x05;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Meshnet host names and their addresses
+pub type Hosts = HashMap<String, Vec<IpAddr>>;
+
+/// Reply codes sent back to the client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum Reply {
+    Succeeded = 0x00,
+    GeneralFailure = 0x01,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    TtlExpired = 0x06,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+/// Destination requested by the client
+#[derive(Debug, PartialEq, Eq)]
+enum Target {
+    Addr(SocketAddr),
+    Host(String, u16),
+}
+
+/// SOCKS5 gateway into the meshnet
+pub struct Socks5Gateway {
+    task: Task<State>,
+    hosts: Arc<RwLock<Hosts>>,
+    local_addr: SocketAddr,
+}
+
+struct State {
+    netstack: Arc<Netstack>,
+    listener: TcpListener,
+    hosts: Arc<RwLock<Hosts>>,
+    connections: JoinSet<()>,
+}
+
+impl Socks5Gateway {
+    /// Start accepting SOCKS5 clients on the given address, normally a loopback one
+    pub async fn start(netstack: Arc<Netstack>, addr: SocketAddr) -> Result<Self, Error> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        telio_log_info!("SOCKS5 gateway listening on {}", local_addr);
+
+        let hosts = Arc::new(RwLock::new(Hosts::new()));
+        Ok(Self {
+            task: Task::start(State {
+                netstack,
+                listener,
+                hosts: hosts.clone(),
+                connections: JoinSet::new(),
+            }),
+            hosts,
+            local_addr,
+        })
+    }
+
+    /// Address the gateway is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Replace host names which can be used as destinations
+    pub fn set_hosts(&self, hosts: Hosts) {
+        *self.hosts.write() = hosts
+            .into_iter()
+            .map(|(name, ips)| (name.to_lowercase(), ips))
+            .collect();
+    }
+
+    /// Stop the gateway, closing all proxied connections
+    pub async fn stop(self) {
+        let _ = self.task.stop().await.resume_unwind();
+    }
+}
+
+#[async_trait]
+impl Runtime for State {
+    const NAME: &'static str = "Socks5Gateway";
+
+    type Err = ();
+
+    async fn wait(&mut self) -> WaitResponse<'_, Self::Err> {
+        tokio::select! {
+            res = self.listener.accept() => match res {
+                Ok((client, peer)) => {
+                    telio_log_debug!("SOCKS5 client connected from {}", peer);
+                    let netstack = self.netstack.clone();
+                    let hosts = self.hosts.clone();
+                    self.connections.spawn(async move {
+                        if let Err(e) = serve(client, &netstack, &hosts).await {
+                            telio_log_debug!("SOCKS5 client {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => telio_log_warn!("Failed to accept SOCKS5 client: {}", e),
+            },
+            Some(_) = self.connections.join_next() => (),
+        }
+
+        Self::next()
+    }
+}
+
+async fn serve(
+    mut client: TcpStream,
+    netstack: &Netstack,
+    hosts: &RwLock<Hosts>,
+) -> io::Result<()> {
+    let addrs = match handshake(&mut client).await? {
+        Target::Addr(addr) => vec![addr],
+        Target::Host(name, port) => hosts
+            .read()
+            .get(name.trim_end_matches('.').to_lowercase().as_str())
+            .map(|ips| ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())
+            .unwrap_or_default(),
+    };
+
+    let mut reply = Reply::HostUnreachable;
+    for addr in addrs {
+        match netstack.connect_tcp(addr).await {
+            Ok(mut remote) => {
+                send_reply(&mut client, Reply::Succeeded).await?;
+                copy_bidirectional(&mut client, &mut remote).await?;
+                return Ok(());
+            }
+            Err(e) => {
+                telio_log_debug!("SOCKS5 connection to {} failed: {}", addr, e);
+                reply = match e {
+                    Error::ConnectionRefused(_) => Reply::ConnectionRefused,
+                    Error::ConnectTimeout(_) => Reply::TtlExpired,
+                    Error::Unaddressable(_) => Reply::HostUnreachable,
+                    _ => Reply::GeneralFailure,
+                };
+            }
+        }
+    }
+
+    send_reply(&mut client, reply).await?;
+    Err(io::Error::new(
+        io::ErrorKind::NotConnected,
+        format!("destination unreachable: {:?}", reply),
+    ))
+}
+
+/// Negotiate the method and read the request, replying to the client on protocol errors
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> io::Result<Target> {
+    let [version, nmethods] = read_array(stream).await?;
+    check_version(version)?;
+    let mut methods = vec![0; nmethods as usize];
+    stream.read_exact(&mut methods).await?;
+
+    if !methods.contains(&METHOD_NO_AUTH) {
+        stream
+            .write_all(&[SOCKS_VERSION, METHOD_NOT_ACCEPTABLE])
+            .await?;
+        return Err(invalid_data("no acceptable authentication method"));
+    }
+    stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).await?;
+
+    let [version, command, _reserved, atyp] = read_array(stream).await?;
+    check_version(version)?;
+
+    let target = match atyp {
+        ATYP_IPV4 => {
+            let ip = Ipv4Addr::from(read_array::<_, 4>(stream).await?);
+            Target::Addr(SocketAddr::new(ip.into(), read_port(stream).await?))
+        }
+        ATYP_IPV6 => {
+            let ip = Ipv6Addr::from(read_array::<_, 16>(stream).await?);
+            Target::Addr(SocketAddr::new(ip.into(), read_port(stream).await?))
+        }
+        ATYP_DOMAIN => {
+            let [len] = read_array(stream).await?;
+            let mut name = vec![0; len as usize];
+            stream.read_exact(&mut name).await?;
+            let name = String::from_utf8(name).map_err(|_| invalid_data("invalid domain name"))?;
+            Target::Host(name, read_port(stream).await?)
+        }
+        _ => {
+            send_reply(stream, Reply::AddressTypeNotSupported).await?;
+            return Err(invalid_data("unsupported address type"));
+        }
+    };
+
+    if command != CMD_CONNECT {
+        send_reply(stream, Reply::CommandNotSupported).await?;
+        return Err(invalid_data("unsupported command"));
+    }
+
+    Ok(target)
+}
+
+async fn send_reply<S: AsyncWrite + Unpin>(stream: &mut S, reply: Reply) -> io::Result<()> {
+    // Bound address is meaningless for the tunnel, so an unspecified one is reported
+    stream
+        .write_all(&[
+            SOCKS_VERSION,
+            reply as u8,
+            0x00,
+            ATYP_IPV4,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ])
+        .await
+}
+
+async fn read_array<S: AsyncRead + Unpin, const N: usize>(stream: &mut S) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn read_port<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<u16> {
+    Ok(u16::from_be_bytes(read_array(stream).await?))
+}
+
+fn check_version(version: u8) -> io::Result<()> {
+    if version != SOCKS_VERSION {
+        return Err(invalid_data("unsupported SOCKS version"));
+    }
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+
+    async fn run_handshake(request: &[u8]) -> (io::Result<Target>, Vec<u8>) {
+        let (mut client, mut server) = duplex(1024);
+        client.write_all(request).await.unwrap();
+        let target = handshake(&mut server).await;
+        drop(server);
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+        (target, response)
+    }
+
+    #[tokio::test]
+    async fn connect_to_ipv4() {
+        let (target, response) =
+            run_handshake(&[5, 1, 0, 5, 1, 0, 1, 100, 64, 0, 2, 0x1f, 0x90]).await;
+        assert_eq!(
+            target.unwrap(),
+            Target::Addr("100.64.0.2:8080".parse().unwrap())
+        );
+        assert_eq!(response, [5, 0]);
+    }
+
+    #[tokio::test]
+    async fn connect_to_ipv6() {
+        let mut request = vec![5, 1, 0, 5, 1, 0, 4];
+        request.extend_from_slice(&"fd74:656c:696f::2".parse::<Ipv6Addr>().unwrap().octets());
+        request.extend_from_slice(&[0, 80]);
+
+        let (target, _) = run_handshake(&request).await;
+        assert_eq!(
+            target.unwrap(),
+            Target::Addr("[fd74:656c:696f::2]:80".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_to_hostname() {
+        let mut request = vec![5, 2, 2, 0, 5, 1, 0, 3, 10];
+        request.extend_from_slice(b"alice.nord");
+        request.extend_from_slice(&[0, 22]);
+
+        let (target, response) = run_handshake(&request).await;
+        assert_eq!(target.unwrap(), Target::Host("alice.nord".to_owned(), 22));
+        assert_eq!(response, [5, 0]);
+    }
+
+    #[tokio::test]
+    async fn authentication_is_not_supported() {
+        let (target, response) = run_handshake(&[5, 1, 2]).await;
+        assert!(target.is_err());
+        assert_eq!(response, [5, METHOD_NOT_ACCEPTABLE]);
+    }
+
+    #[tokio::test]
+    async fn only_connect_command_is_supported() {
+        let (target, response) = run_handshake(&[5, 1, 0, 5, 2, 0, 1, 100, 64, 0, 2, 0, 80]).await;
+        assert!(target.is_err());
+        assert_eq!(response, [5, 0, 5, 7, 0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn unknown_version_is_rejected() {
+        let (target, response) = run_handshake(&[4, 1, 0]).await;
+        assert!(target.is_err());
+        assert!(response.is_empty());
+    }
+}
@@ -0,0 +1,379 @@
+//! Network stack driven by the packets of a virtual tunnel interface
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use parking_lot::{Mutex, MutexGuard};
+use smart_default::SmartDefault;
+use smoltcp::{
+    iface::{Config as IfaceConfig, Interface, SocketHandle, SocketSet},
+    socket::tcp,
+    time::Instant as SmolInstant,
+    wire::{HardwareAddress, IpCidr},
+};
+use tokio::{net::UnixDatagram, sync::Notify};
+
+use telio_task::{Runtime, RuntimeExt, Task, WaitResponse};
+use telio_utils::{telio_log_debug, telio_log_warn};
+
+use crate::{
+    icmp::EchoSocket,
+    queue::PacketQueue,
+    tcp::{TcpListener, TcpStream},
+    virtual_tun::VirtualTun,
+};
+
+/// First port of the IANA dynamic range, used for outgoing connections
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// How long to sleep when no socket has any pending timers
+const IDLE_POLL_DELAY: Duration = Duration::from_secs(1);
+
+/// Netstack errors
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Virtual tunnel interface failure
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Stack has no address which could be used to reach the remote
+    #[error("No local address to reach {0}")]
+    Unaddressable(SocketAddr),
+    /// Connection attempt could not be started
+    #[error("Failed to connect: {0}")]
+    Connect(String),
+    /// Listening socket could not be opened
+    #[error("Failed to listen: {0}")]
+    Listen(String),
+    /// Remote did not respond in time
+    #[error("Connection to {0} timed out")]
+    ConnectTimeout(SocketAddr),
+    /// Remote rejected the connection
+    #[error("Connection to {0} refused")]
+    ConnectionRefused(SocketAddr),
+    /// Echo request could not be sent
+    #[error("Failed to send echo request: {0}")]
+    Echo(String),
+}
+
+/// Netstack configuration
+#[derive(Clone, Debug, SmartDefault)]
+pub struct Config {
+    /// MTU of the virtual interface, should match the one of the adapter
+    #[default(1420)]
+    pub mtu: usize,
+    /// Time to wait for a TCP connection to be established
+    #[default(Duration::from_secs(10))]
+    pub connect_timeout: Duration,
+    /// Time to wait for the reply to an echo request
+    #[default(Duration::from_secs(5))]
+    pub echo_timeout: Duration,
+}
+
+/// Userspace TCP/IP stack
+pub struct Netstack {
+    task: Task<State>,
+    shared: Shared,
+    config: Config,
+}
+
+/// Stack state shared between the driving task and the sockets
+pub(crate) struct Inner {
+    pub(crate) iface: Interface,
+    pub(crate) device: PacketQueue,
+    pub(crate) sockets: SocketSet<'static>,
+    /// Sockets dropped by the application which still need to finish closing
+    pub(crate) closing: Vec<SocketHandle>,
+    next_ephemeral_port: u16,
+}
+
+#[derive(Clone)]
+pub(crate) struct Shared {
+    inner: Arc<Mutex<Inner>>,
+    notify: Arc<Notify>,
+}
+
+struct State {
+    shared: Shared,
+    tun: UnixDatagram,
+    buffer: Vec<u8>,
+}
+
+impl Netstack {
+    /// Start processing packets of the virtual tunnel interface
+    pub fn start(tun: VirtualTun, config: Config) -> Result<Self, Error> {
+        let tun = tun.into_async()?;
+        let mut device = PacketQueue::new(config.mtu);
+        let iface = Interface::new(
+            IfaceConfig::new(HardwareAddress::Ip),
+            &mut device,
+            SmolInstant::now(),
+        );
+
+        let shared = Shared {
+            inner: Arc::new(Mutex::new(Inner {
+                iface,
+                device,
+                sockets: SocketSet::new(Vec::new()),
+                closing: Vec::new(),
+                next_ephemeral_port: EPHEMERAL_PORT_START,
+            })),
+            notify: Arc::new(Notify::new()),
+        };
+
+        Ok(Self {
+            task: Task::start(State {
+                shared: shared.clone(),
+                tun,
+                buffer: vec![0; config.mtu],
+            }),
+            shared,
+            config,
+        })
+    }
+
+    /// Set addresses of the stack, normally the meshnet addresses of this node
+    pub fn set_addresses(&self, addresses: &[IpAddr]) {
+        telio_log_debug!("Setting netstack addresses: {:?}", addresses);
+        let mut inner = self.shared.lock();
+        let iface = &mut inner.iface;
+
+        iface.update_ip_addrs(|addrs| {
+            addrs.clear();
+            for addr in addresses {
+                let prefix = if addr.is_ipv4() { 32 } else { 128 };
+                if addrs.push(IpCidr::new((*addr).into(), prefix)).is_err() {
+                    telio_log_warn!("Too many netstack addresses, skipping {}", addr);
+                }
+            }
+        });
+
+        // Tunnel is point to point, so everything is routed through it
+        iface.routes_mut().update(|routes| routes.clear());
+        for addr in addresses {
+            let res = match addr {
+                IpAddr::V4(addr) => iface.routes_mut().add_default_ipv4_route((*addr).into()),
+                IpAddr::V6(addr) => iface.routes_mut().add_default_ipv6_route((*addr).into()),
+            };
+            if res.is_err() {
+                telio_log_warn!("Failed to add netstack route via {}", addr);
+            }
+        }
+        drop(inner);
+        self.shared.wake();
+    }
+
+    /// Open a TCP connection through the tunnel
+    pub async fn connect_tcp(&self, remote: SocketAddr) -> Result<TcpStream, Error> {
+        TcpStream::connect(self.shared.clone(), remote, self.config.connect_timeout).await
+    }
+
+    /// Accept TCP connections coming through the tunnel on the given port
+    pub fn listen_tcp(&self, port: u16) -> Result<TcpListener, Error> {
+        TcpListener::bind(self.shared.clone(), port)
+    }
+
+    /// Send an echo request through the tunnel, returns the round trip time, or `None` if no
+    /// reply came in time
+    pub async fn ping(
+        &self,
+        remote: IpAddr,
+        ident: u16,
+        seq_no: u16,
+    ) -> Result<Option<Duration>, Error> {
+        EchoSocket::bind(self.shared.clone(), ident)?
+            .ping(remote, ident, seq_no, self.config.echo_timeout)
+            .await
+    }
+
+    /// Stop the stack, open connections will stall
+    pub async fn stop(self) {
+        let _ = self.task.stop().await.resume_unwind();
+    }
+}
+
+impl Inner {
+    pub(crate) fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_ephemeral_port;
+        self.next_ephemeral_port = port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+        port
+    }
+
+    fn poll(&mut self) {
+        let Inner {
+            iface,
+            device,
+            sockets,
+            closing,
+            ..
+        } = self;
+        iface.poll(SmolInstant::now(), device, sockets);
+
+        closing.retain(|handle| {
+            let closed = matches!(
+                sockets.get::<tcp::Socket>(*handle).state(),
+                tcp::State::Closed | tcp::State::TimeWait
+            );
+            if closed {
+                sockets.remove(*handle);
+            }
+            !closed
+        });
+    }
+
+    fn poll_delay(&mut self) -> Duration {
+        self.iface
+            .poll_delay(SmolInstant::now(), &self.sockets)
+            .map_or(IDLE_POLL_DELAY, |delay| {
+                Duration::from_micros(delay.total_micros())
+            })
+    }
+}
+
+impl Shared {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock()
+    }
+
+    /// Make the driving task poll the interface, e.g. after socket buffers were changed
+    pub(crate) fn wake(&self) {
+        self.notify.notify_one();
+    }
+}
+
+#[async_trait]
+impl Runtime for State {
+    const NAME: &'static str = "Netstack";
+
+    type Err = ();
+
+    async fn wait(&mut self) -> WaitResponse<'_, Self::Err> {
+        let delay = self.shared.lock().poll_delay();
+
+        tokio::select! {
+            res = self.tun.recv(&mut self.buffer) => match res {
+                Ok(len) => {
+                    if let Some(packet) = self.buffer.get(..len) {
+                        self.shared.lock().device.push_rx(packet.to_vec());
+                    }
+                }
+                Err(e) => {
+                    telio_log_warn!("Failed to read from virtual tun: {}", e);
+                    return Self::error(());
+                }
+            },
+            _ = self.shared.notify.notified() => (),
+            _ = tokio::time::sleep(delay) => (),
+        }
+
+        let packets = {
+            let mut inner = self.shared.lock();
+            inner.poll();
+            inner.device.take_tx()
+        };
+        for packet in packets {
+            if let Err(e) = self.tun.send(&packet).await {
+                telio_log_warn!("Failed to write to virtual tun: {}", e);
+            }
+        }
+
+        Self::next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, os::unix::net::UnixDatagram as StdUnixDatagram};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    const ALICE: IpAddr = IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1));
+    const BOB: IpAddr = IpAddr::V4(Ipv4Addr::new(100, 64, 0, 2));
+
+    fn connected_stacks() -> (Netstack, Netstack) {
+        let (alice, bob) = StdUnixDatagram::pair().unwrap();
+        let alice =
+            Netstack::start(VirtualTun::from_socket(alice).unwrap(), Config::default()).unwrap();
+        let bob =
+            Netstack::start(VirtualTun::from_socket(bob).unwrap(), Config::default()).unwrap();
+        alice.set_addresses(&[ALICE]);
+        bob.set_addresses(&[BOB]);
+        (alice, bob)
+    }
+
+    #[test]
+    fn ephemeral_ports_wrap_around() {
+        let mut device = PacketQueue::new(1420);
+        let mut inner = Inner {
+            iface: Interface::new(
+                IfaceConfig::new(HardwareAddress::Ip),
+                &mut device,
+                SmolInstant::now(),
+            ),
+            device,
+            sockets: SocketSet::new(Vec::new()),
+            closing: Vec::new(),
+            next_ephemeral_port: u16::MAX,
+        };
+        assert_eq!(inner.ephemeral_port(), u16::MAX);
+        assert_eq!(inner.ephemeral_port(), EPHEMERAL_PORT_START);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tcp_roundtrip_between_stacks() {
+        let (alice, bob) = connected_stacks();
+        let mut listener = bob.listen_tcp(8080).unwrap();
+
+        let (client, server) = tokio::join!(
+            alice.connect_tcp(SocketAddr::new(BOB, 8080)),
+            listener.accept()
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        assert_eq!(server.peer_addr().ip(), ALICE);
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        server.write_all(b"pong").await.unwrap();
+        server.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"pong");
+
+        alice.stop().await;
+        bob.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn echo_is_answered_by_remote_stack() {
+        let (alice, bob) = connected_stacks();
+
+        assert!(alice.ping(BOB, 7, 0).await.unwrap().is_some());
+        assert!(alice.ping(BOB, 7, 1).await.unwrap().is_some());
+
+        alice.stop().await;
+        bob.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connect_without_address_fails() {
+        let (alice, _) = StdUnixDatagram::pair().unwrap();
+        let alice =
+            Netstack::start(VirtualTun::from_socket(alice).unwrap(), Config::default()).unwrap();
+
+        let remote = SocketAddr::new(BOB, 80);
+        assert!(matches!(
+            alice.connect_tcp(remote).await,
+            Err(Error::Unaddressable(addr)) if addr == remote
+        ));
+        alice.stop().await;
+    }
+}
@@ -0,0 +1,252 @@
+//! TCP sockets of the userspace stack exposed as async streams
+
+use std::{
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use smoltcp::{iface::SocketHandle, socket::tcp, time::Duration as SmolDuration, wire::IpEndpoint};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use telio_utils::telio_log_warn;
+
+use crate::stack::{Error, Shared};
+
+/// Size of both receive and send buffers of every socket
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Connection is reset if sent data is not acknowledged within this time
+const TCP_ACK_TIMEOUT: SmolDuration = SmolDuration::from_secs(60);
+
+/// TCP connection going through the tunnel
+pub struct TcpStream {
+    handle: SocketHandle,
+    shared: Shared,
+    peer: SocketAddr,
+}
+
+/// Socket accepting TCP connections coming through the tunnel
+pub struct TcpListener {
+    handle: SocketHandle,
+    shared: Shared,
+    port: u16,
+}
+
+fn new_socket() -> tcp::Socket<'static> {
+    let mut socket = tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    );
+    socket.set_timeout(Some(TCP_ACK_TIMEOUT));
+    socket
+}
+
+fn to_socket_addr(endpoint: IpEndpoint) -> SocketAddr {
+    SocketAddr::new(endpoint.addr.into(), endpoint.port)
+}
+
+fn to_io_error(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+/// Close the socket and leave it to the stack to finish the shutdown and release it
+fn release(shared: &Shared, handle: SocketHandle) {
+    let mut inner = shared.lock();
+    inner.sockets.get_mut::<tcp::Socket>(handle).close();
+    inner.closing.push(handle);
+    drop(inner);
+    shared.wake();
+}
+
+impl TcpStream {
+    pub(crate) async fn connect(
+        shared: Shared,
+        remote: SocketAddr,
+        connect_timeout: Duration,
+    ) -> Result<Self, Error> {
+        let handle = {
+            let mut inner = shared.lock();
+            let port = inner.ephemeral_port();
+            let inner = &mut *inner;
+
+            let mut socket = new_socket();
+            socket
+                .connect(inner.iface.context(), remote, port)
+                .map_err(|e| match e {
+                    tcp::ConnectError::Unaddressable => Error::Unaddressable(remote),
+                    e => Error::Connect(e.to_string()),
+                })?;
+            inner.sockets.add(socket)
+        };
+        shared.wake();
+
+        // Socket is released on drop if the connection does not succeed
+        let stream = Self {
+            handle,
+            shared,
+            peer: remote,
+        };
+        tokio::time::timeout(connect_timeout, poll_fn(|cx| stream.poll_established(cx)))
+            .await
+            .map_err(|_| Error::ConnectTimeout(remote))??;
+        Ok(stream)
+    }
+
+    /// Address of the remote end of the connection
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    fn poll_established(&self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let mut inner = self.shared.lock();
+        let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+        match socket.state() {
+            tcp::State::SynSent | tcp::State::SynReceived => {
+                socket.register_send_waker(cx.waker());
+                Poll::Pending
+            }
+            _ if socket.is_active() => Poll::Ready(Ok(())),
+            _ => Poll::Ready(Err(Error::ConnectionRefused(self.peer))),
+        }
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut inner = self.shared.lock();
+        let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+        if socket.can_recv() {
+            let read = socket
+                .recv_slice(buf.initialize_unfilled())
+                .map_err(to_io_error)?;
+            buf.advance(read);
+            drop(inner);
+            // Receive window has grown, let the stack announce it
+            self.shared.wake();
+            return Poll::Ready(Ok(()));
+        }
+
+        if !socket.may_recv() {
+            // Remote has closed its side, which is signalled by an empty read
+            return Poll::Ready(Ok(()));
+        }
+
+        socket.register_recv_waker(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = self.shared.lock();
+        let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+        if !socket.may_send() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        if socket.can_send() {
+            let written = socket.send_slice(buf).map_err(to_io_error)?;
+            drop(inner);
+            self.shared.wake();
+            return Poll::Ready(Ok(written));
+        }
+
+        socket.register_send_waker(cx.waker());
+        Poll::Pending
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Data is handed to the stack as soon as it is written
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.shared
+            .lock()
+            .sockets
+            .get_mut::<tcp::Socket>(self.handle)
+            .close();
+        self.shared.wake();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        release(&self.shared, self.handle);
+    }
+}
+
+impl TcpListener {
+    pub(crate) fn bind(shared: Shared, port: u16) -> Result<Self, Error> {
+        let mut socket = new_socket();
+        socket
+            .listen(port)
+            .map_err(|e| Error::Listen(e.to_string()))?;
+        let handle = shared.lock().sockets.add(socket);
+        Ok(Self {
+            handle,
+            shared,
+            port,
+        })
+    }
+
+    /// Wait for an incoming connection
+    pub async fn accept(&mut self) -> Result<TcpStream, Error> {
+        let peer = poll_fn(|cx| self.poll_accept(cx)).await;
+
+        // Connected socket is handed over to the stream, and a fresh one takes its place
+        let mut socket = new_socket();
+        socket
+            .listen(self.port)
+            .map_err(|e| Error::Listen(e.to_string()))?;
+        let handle = std::mem::replace(&mut self.handle, self.shared.lock().sockets.add(socket));
+
+        Ok(TcpStream {
+            handle,
+            shared: self.shared.clone(),
+            peer,
+        })
+    }
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<SocketAddr> {
+        let mut inner = self.shared.lock();
+        let socket = inner.sockets.get_mut::<tcp::Socket>(self.handle);
+
+        if socket.is_active() && socket.state() != tcp::State::SynReceived {
+            if let Some(remote) = socket.remote_endpoint() {
+                return Poll::Ready(to_socket_addr(remote));
+            }
+        }
+
+        if !socket.is_open() {
+            // Handshake was aborted by the remote, start over
+            if let Err(e) = socket.listen(self.port) {
+                telio_log_warn!("Failed to relisten on {}: {}", self.port, e);
+            }
+        }
+
+        socket.register_recv_waker(cx.waker());
+        Poll::Pending
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        release(&self.shared, self.handle);
+    }
+}
@@ -0,0 +1,37 @@
+//! Tunnel interface replacement backed by a datagram socket pair
+
+use std::{
+    io,
+    os::unix::{io::OwnedFd, net::UnixDatagram as StdUnixDatagram},
+};
+
+use tokio::net::UnixDatagram;
+
+/// Stack side of a virtual tunnel interface
+///
+/// The adapter gets the other end of the socket pair in place of a tunnel file descriptor.
+/// Every datagram carries exactly one IP packet, same as reads and writes of a tun device.
+#[derive(Debug)]
+pub struct VirtualTun {
+    socket: StdUnixDatagram,
+}
+
+impl VirtualTun {
+    /// Create a new virtual tunnel interface
+    ///
+    /// Returns the file descriptor to be used by the adapter instead of a tun device together
+    /// with the stack side of the tunnel.
+    pub fn new() -> io::Result<(OwnedFd, Self)> {
+        let (adapter, stack) = StdUnixDatagram::pair()?;
+        Ok((adapter.into(), Self::from_socket(stack)?))
+    }
+
+    pub(crate) fn from_socket(socket: StdUnixDatagram) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    pub(crate) fn into_async(self) -> io::Result<UnixDatagram> {
+        UnixDatagram::from_std(self.socket)
+    }
+}
@@ -5,12 +5,20 @@ use crate::{local_interfaces::gather_local_interfaces, local_interfaces::GetIfAd
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use std::io;
-use telio_utils::{telio_log_debug, telio_log_warn};
-use tokio::{sync::broadcast::Sender, task::JoinHandle};
+use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
+use tokio::{
+    sync::{broadcast::Sender, watch},
+    task::JoinHandle,
+};
 /// Sender to notify if there is a change in OS interface order
 pub static PATH_CHANGE_BROADCAST: Lazy<Sender<()>> = Lazy::new(|| Sender::new(2));
 /// Vector containing all local interfaces
 pub static LOCAL_ADDRS_CACHE: Mutex<Vec<if_addrs::Interface>> = Mutex::new(Vec::new());
+/// Availability of the network, updated together with [LOCAL_ADDRS_CACHE]. The network is
+/// considered available while there is at least one usable local interface.
+pub static NETWORK_AVAILABILITY: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::Sender::new(true));
+/// Name prefixes of the interfaces of cellular modems on Android, iOS and Linux
+const CELLULAR_INTERFACE_PREFIXES: [&str; 5] = ["rmnet", "v4-rmnet", "ccmni", "pdp_ip", "wwan"];
 #[cfg(all(
     not(test),
     any(target_os = "macos", target_os = "ios", target_os = "tvos")
@@ -33,7 +41,16 @@ fn save_local_interfaces<G: GetIfAddrs>(get_if_addr: &G) {
     match gather_local_interfaces(get_if_addr) {
         Ok(v) => {
             telio_log_debug!("Updating local addr cache");
+            let available = !v.is_empty();
             *(LOCAL_ADDRS_CACHE.lock()) = v;
+            NETWORK_AVAILABILITY.send_if_modified(|was_available| {
+                if *was_available == available {
+                    return false;
+                }
+                telio_log_info!("Network available: {available}");
+                *was_available = available;
+                true
+            });
         }
         Err(e) => telio_log_warn!("Unable to get local interfaces {e}"),
     }
@@ -79,6 +96,65 @@ impl NetworkMonitor {
     }
 }
 
+/// Check if the network is available, see [NETWORK_AVAILABILITY]
+pub fn is_network_available() -> bool {
+    *NETWORK_AVAILABILITY.borrow()
+}
+
+/// Check if the device is on a cellular network, judging by the names of the local interfaces
+/// in [LOCAL_ADDRS_CACHE], leaving the `tunnel` interface out. The interface of the default
+/// route decides when it is known, otherwise any cellular interface is taken as the one in use.
+pub fn is_on_cellular(tunnel: Option<&str>) -> bool {
+    let interfaces = LOCAL_ADDRS_CACHE.lock();
+    let names: Vec<&str> = interfaces
+        .iter()
+        .map(|interface| interface.name.as_str())
+        .filter(|name| Some(*name) != tunnel)
+        .collect();
+    is_cellular(&names, default_route_interface().as_deref())
+}
+
+fn is_cellular(interfaces: &[&str], default_route: Option<&str>) -> bool {
+    let is_modem = |name: &str| {
+        CELLULAR_INTERFACE_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    };
+    match default_route {
+        Some(name) if interfaces.contains(&name) => is_modem(name),
+        _ => interfaces.iter().any(|name| is_modem(name)),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn default_route_interface() -> Option<String> {
+    parse_default_route(&std::fs::read_to_string("/proc/net/route").ok()?)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn default_route_interface() -> Option<String> {
+    None
+}
+
+/// Interface of the IPv4 default route with the lowest metric in the `/proc/net/route` format
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_default_route(routes: &str) -> Option<String> {
+    routes
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            match columns.as_slice() {
+                [name, "00000000", _, _, _, _, metric, "00000000", ..] => {
+                    Some((metric.parse::<u32>().ok()?, *name))
+                }
+                _ => None,
+            }
+        })
+        .min()
+        .map(|(_, name)| name.to_owned())
+}
+
 impl Drop for NetworkMonitor {
     fn drop(&mut self) {
         if let Some(handle) = &self.if_cache_updater_handle {
@@ -108,22 +184,26 @@ mod tests {
 
     static CALL_COUNT: AtomicU8 = AtomicU8::new(0);
 
+    fn interface(name: &str) -> if_addrs::Interface {
+        if_addrs::Interface {
+            name: name.to_owned(),
+            addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                ip: Ipv4Addr::new(10, 0, 0, 1),
+                netmask: Ipv4Addr::new(255, 255, 255, 0),
+                broadcast: None,
+            }),
+            index: None,
+            #[cfg(windows)]
+            adapter_name: "{78f73923-a518-4936-ba87-2a30427b1f63}".to_string(),
+        }
+    }
+
     async fn setup_network_monitor() -> NetworkMonitor {
         CALL_COUNT.store(0, Ordering::SeqCst);
         let mut get_if_addrs_mock = MockGetIfAddrs::new();
         get_if_addrs_mock.expect_get().returning(|| {
             CALL_COUNT.fetch_add(1, Ordering::SeqCst);
-            Ok(vec![if_addrs::Interface {
-                name: "correct".to_owned(),
-                addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
-                    ip: Ipv4Addr::new(10, 0, 0, 1),
-                    netmask: Ipv4Addr::new(255, 255, 255, 0),
-                    broadcast: None,
-                }),
-                index: None,
-                #[cfg(windows)]
-                adapter_name: "{78f73923-a518-4936-ba87-2a30427b1f63}".to_string(),
-            }])
+            Ok(vec![interface("correct")])
         });
         NetworkMonitor::new(get_if_addrs_mock).await.unwrap()
     }
@@ -182,4 +262,51 @@ mod tests {
             panic!("Network monitor not started with {}", e);
         }
     }
+    #[tokio::test]
+    #[serial]
+    async fn test_network_availability_follows_interfaces() {
+        let _network_monitor = setup_network_monitor().await;
+        assert!(is_network_available());
+        let mut availability = NETWORK_AVAILABILITY.subscribe();
+
+        let mut offline_mock = MockGetIfAddrs::new();
+        offline_mock.expect_get().returning(|| Ok(vec![]));
+        save_local_interfaces(&offline_mock);
+        assert!(availability.has_changed().unwrap());
+        assert!(!*availability.borrow_and_update());
+
+        // Same state again is not a change
+        save_local_interfaces(&offline_mock);
+        assert!(!availability.has_changed().unwrap());
+
+        let _network_monitor = setup_network_monitor().await;
+        assert!(*availability.borrow_and_update());
+    }
+
+    #[test]
+    fn test_cellular_is_detected_from_interface_names() {
+        assert!(!is_cellular(&[], None));
+        assert!(is_cellular(&["rmnet_data0"], None));
+        assert!(is_cellular(&["pdp_ip0", "utun3"], None));
+        assert!(is_cellular(&["rmnet_data0", "wlan0"], None));
+        assert!(!is_cellular(&["en0"], None));
+
+        // The default route decides, unless it goes through an interface left out
+        assert!(is_cellular(&["rmnet_data0", "wlan0"], Some("rmnet_data0")));
+        assert!(!is_cellular(&["rmnet_data0", "wlan0"], Some("wlan0")));
+        assert!(is_cellular(&["rmnet_data0", "wlan0"], Some("nlx")));
+    }
+
+    #[test]
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn test_default_route_is_parsed() {
+        let routes = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+wlan0\t0000A8C0\t00000000\t0001\t0\t0\t600\t00FFFFFF\t0\t0\t0
+wlan0\t00000000\t0100A8C0\t0003\t0\t0\t600\t00000000\t0\t0\t0
+rmnet_data0\t00000000\t01000A0A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+";
+        assert_eq!(parse_default_route(routes).as_deref(), Some("rmnet_data0"));
+        assert_eq!(parse_default_route("Iface\tDestination\n"), None);
+    }
 }
@@ -1,4 +1,10 @@
-use std::{collections::hash_map::Entry, mem, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::{hash_map::Entry, BTreeMap},
+    mem,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::{sync::Mutex, time::Instant};
 
@@ -8,7 +14,8 @@ use crate::config::AggregatorConfig;
 use telio_crypto::{smaller_key_in_meshnet_canonical_order, PublicKey};
 use telio_model::{
     config::{DerpAnalyticsEvent, RelayConnectionChangeReason, RelayState},
-    features::EndpointProvider,
+    features::{EndpointProvider, PathType},
+    mesh::{AddressFamily, FamilyCheckStats},
     HashMap,
 };
 use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
@@ -20,6 +27,43 @@ use telio_wg::{
 #[cfg(feature = "mockall")]
 use mockall::automock;
 
+/// Upper bound of distinct (upstream, failure kind) counters kept between heartbeats
+const MAX_DNS_FAILURE_COUNTERS: usize = 64;
+
+/// Kind of a failed DNS resolution on an upstream (forward) resolver
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsFailureKind {
+    /// Upstream answered with SERVFAIL
+    ServFail,
+    /// Upstream answered with REFUSED
+    Refused,
+    /// Upstream did not answer in time
+    Timeout,
+    /// Upstream could not be reached
+    Unreachable,
+    /// Upstream answer could not be parsed
+    Malformed,
+    /// Any other resolver error
+    Other,
+}
+
+/// DNS failure counters collected since the last heartbeat
+pub(crate) type DnsFailureCounters = BTreeMap<(IpAddr, DnsFailureKind), u32>;
+
+/// Connectivity check outcomes per address family collected since the last heartbeat
+pub(crate) type FamilyCheckCounters = BTreeMap<AddressFamily, FamilyCheckStats>;
+
+/// Delay of the relayed path through a server, as last measured by the probes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct RelayPathDelay {
+    pub rtt: Duration,
+    pub jitter: Duration,
+}
+
+/// Delays of the relayed paths per server collected since the last heartbeat
+pub(crate) type RelayPathDelays = BTreeMap<SocketAddr, RelayPathDelay>;
+
 // Possible Relay connection states - because all of the first 8 bit combinations
 // are reserved for relay states, they need to have the 9th bit on
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
@@ -31,7 +75,7 @@ pub(crate) enum RelayConnectionState {
 
 impl From<RelayState> for RelayConnectionState {
     fn from(value: RelayState) -> Self {
-        if let RelayState::Connected = value {
+        if let RelayState::Connected | RelayState::Degraded = value {
             RelayConnectionState::Connected
         } else {
             RelayConnectionState::Connecting
@@ -175,7 +219,14 @@ struct AggregatorData {
     current_peer_events: HashMap<PublicKey, (AnalyticsEvent, PeerEndpointTypes)>,
     peer_segments: Vec<PeerConnDataSegment>,
     relay_segments: Vec<RelayConnDataSegment>,
+    dns_failures: DnsFailureCounters,
+    family_checks: FamilyCheckCounters,
+    relay_paths: RelayPathDelays,
+    // Path the peers are currently reached through, kept regardless of the enabled events
+    peer_paths: HashMap<PublicKey, PathType>,
     local_key: PublicKey,
+    // Cleared when the analytics consent is revoked, nothing is recorded until it is given again
+    enabled: bool,
 }
 
 /// A container to store the connectivity data for the Meshnet peers used by Nurse
@@ -211,7 +262,12 @@ impl ConnectivityDataAggregator {
                 current_peer_events: HashMap::new(),
                 peer_segments: Vec::new(),
                 relay_segments: Vec::new(),
+                dns_failures: BTreeMap::new(),
+                family_checks: BTreeMap::new(),
+                relay_paths: BTreeMap::new(),
+                peer_paths: HashMap::new(),
                 local_key,
+                enabled: true,
             }),
             config,
             wg_interface,
@@ -281,6 +337,9 @@ impl ConnectivityDataAggregator {
         }
 
         let mut data_guard = self.data.lock().await;
+        if !data_guard.enabled {
+            return;
+        }
 
         if event.is_from_virtual_peer() {
             telio_log_debug!(
@@ -345,6 +404,9 @@ impl ConnectivityDataAggregator {
         }
 
         let mut data_guard = self.data.lock().await;
+        if !data_guard.enabled {
+            return;
+        }
 
         let new_segment = data_guard
             .current_relay_event
@@ -462,6 +524,130 @@ impl ConnectivityDataAggregator {
         }
     }
 
+    /// Count a failed DNS resolution on an upstream resolver
+    ///
+    /// Failures are only aggregated here and sent out with the next heartbeat, so a
+    /// misbehaving upstream does not produce an analytics event per query.
+    ///
+    /// # Arguments
+    ///
+    /// * `upstream` - Address of the upstream resolver.
+    /// * `kind` - Kind of the failure.
+    pub async fn report_dns_failure(&self, upstream: IpAddr, kind: DnsFailureKind) {
+        if !self.config.dns_failure_events {
+            return;
+        }
+
+        let mut data_guard = self.data.lock().await;
+        if !data_guard.enabled {
+            return;
+        }
+        let counters = &mut data_guard.dns_failures;
+        if let Some(count) = counters.get_mut(&(upstream, kind)) {
+            *count = count.saturating_add(1);
+        } else if counters.len() < MAX_DNS_FAILURE_COUNTERS {
+            counters.insert((upstream, kind), 1);
+        } else {
+            telio_log_debug!("Too many DNS failure counters, dropping {upstream}: {kind:?}");
+        }
+    }
+
+    /// Collects DNS failure counters and resets them
+    pub(crate) async fn collect_dns_failures(&self) -> DnsFailureCounters {
+        mem::take(&mut self.data.lock().await.dns_failures)
+    }
+
+    /// Add up the connectivity checks done over each address family
+    ///
+    /// # Arguments
+    ///
+    /// * `stats` - Pinging rounds started and succeeded per family since the last report.
+    pub async fn report_family_checks(&self, stats: BTreeMap<AddressFamily, FamilyCheckStats>) {
+        if !self.config.nat_traversal_events || stats.is_empty() {
+            return;
+        }
+
+        let mut data_guard = self.data.lock().await;
+        if !data_guard.enabled {
+            return;
+        }
+        for (family, reported) in stats {
+            let counters = data_guard.family_checks.entry(family).or_default();
+            counters.attempts = counters.attempts.saturating_add(reported.attempts);
+            counters.successes = counters.successes.saturating_add(reported.successes);
+        }
+    }
+
+    /// Collects the connectivity check counters per address family and resets them
+    pub(crate) async fn collect_family_checks(&self) -> FamilyCheckCounters {
+        mem::take(&mut self.data.lock().await.family_checks)
+    }
+
+    /// Record the delay of the relayed path through a server, replacing the previous one
+    ///
+    /// # Arguments
+    ///
+    /// * `server_address` - Address of the relay server.
+    /// * `rtt` - Smoothed round trip time through the server.
+    /// * `jitter` - Smoothed variation of the round trip time.
+    pub async fn report_relay_path(
+        &self,
+        server_address: SocketAddr,
+        rtt: Duration,
+        jitter: Duration,
+    ) {
+        if !self.config.relay_events {
+            return;
+        }
+
+        let mut data_guard = self.data.lock().await;
+        if !data_guard.enabled {
+            return;
+        }
+        data_guard
+            .relay_paths
+            .insert(server_address, RelayPathDelay { rtt, jitter });
+    }
+
+    /// Collects the delays of the relayed paths and resets them
+    pub(crate) async fn collect_relay_paths(&self) -> RelayPathDelays {
+        mem::take(&mut self.data.lock().await.relay_paths)
+    }
+
+    /// Record the path a Meshnet peer is currently reached through
+    ///
+    /// # Arguments
+    ///
+    /// * `public_key` - Public key of the peer.
+    /// * `path` - Path in use, None once the peer is removed.
+    pub async fn report_peer_path(&self, public_key: PublicKey, path: Option<PathType>) {
+        let mut data_guard = self.data.lock().await;
+        match path {
+            Some(path) => data_guard.peer_paths.insert(public_key, path),
+            None => data_guard.peer_paths.remove(&public_key),
+        };
+    }
+
+    /// Path a Meshnet peer is currently reached through, if it is known
+    pub async fn peer_path(&self, public_key: PublicKey) -> Option<PathType> {
+        self.data.lock().await.peer_paths.get(&public_key).copied()
+    }
+
+    /// Start or stop recording, stopping drops everything recorded so far
+    pub async fn set_enabled(&self, enabled: bool) {
+        let mut data_guard = self.data.lock().await;
+        data_guard.enabled = enabled;
+        if !enabled {
+            data_guard.current_relay_event = None;
+            data_guard.current_peer_events.clear();
+            data_guard.peer_segments.clear();
+            data_guard.relay_segments.clear();
+            data_guard.dns_failures.clear();
+            data_guard.family_checks.clear();
+            data_guard.relay_paths.clear();
+        }
+    }
+
     /// Set our own current public key
     pub async fn set_local_key(&self, public_key: PublicKey) {
         self.data.lock().await.local_key = public_key;
@@ -1389,6 +1575,168 @@ mod tests {
         );
     }
 
+    fn create_dns_aggregator(dns_failure_events: bool) -> ConnectivityDataAggregator {
+        ConnectivityDataAggregator::new(
+            AggregatorConfig {
+                dns_failure_events,
+                ..Default::default()
+            },
+            Arc::new(MockWireGuard::new()),
+            SecretKey::gen().public(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_dns_failures() {
+        let aggregator = create_dns_aggregator(true);
+        let first = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+
+        for _ in 0..3 {
+            aggregator
+                .report_dns_failure(first, DnsFailureKind::Timeout)
+                .await;
+        }
+        aggregator
+            .report_dns_failure(first, DnsFailureKind::ServFail)
+            .await;
+        aggregator
+            .report_dns_failure(second, DnsFailureKind::Refused)
+            .await;
+
+        let failures = aggregator.collect_dns_failures().await;
+        assert_eq!(
+            failures.into_iter().collect::<Vec<_>>(),
+            vec![
+                ((first, DnsFailureKind::ServFail), 1),
+                ((first, DnsFailureKind::Timeout), 3),
+                ((second, DnsFailureKind::Refused), 1),
+            ]
+        );
+
+        // Counters are reset after collection
+        assert!(aggregator.collect_dns_failures().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_dns_failures_bounded() {
+        let aggregator = create_dns_aggregator(true);
+        for i in 0..(MAX_DNS_FAILURE_COUNTERS + 10) {
+            let upstream = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i as u8));
+            aggregator
+                .report_dns_failure(upstream, DnsFailureKind::Timeout)
+                .await;
+        }
+        assert_eq!(
+            aggregator.collect_dns_failures().await.len(),
+            MAX_DNS_FAILURE_COUNTERS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_dns_failures_disabled() {
+        let aggregator = create_dns_aggregator(false);
+        aggregator
+            .report_dns_failure(
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                DnsFailureKind::Timeout,
+            )
+            .await;
+        assert!(aggregator.collect_dns_failures().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_disabled_at_runtime_purges_data() {
+        let aggregator = create_dns_aggregator(true);
+        let upstream = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        aggregator
+            .report_dns_failure(upstream, DnsFailureKind::Timeout)
+            .await;
+
+        aggregator.set_enabled(false).await;
+        aggregator
+            .report_dns_failure(upstream, DnsFailureKind::Refused)
+            .await;
+        assert!(aggregator.collect_dns_failures().await.is_empty());
+
+        aggregator.set_enabled(true).await;
+        aggregator
+            .report_dns_failure(upstream, DnsFailureKind::Refused)
+            .await;
+        assert_eq!(
+            aggregator
+                .collect_dns_failures()
+                .await
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![((upstream, DnsFailureKind::Refused), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_family_checks() {
+        let aggregator = ConnectivityDataAggregator::new(
+            AggregatorConfig {
+                nat_traversal_events: true,
+                ..Default::default()
+            },
+            Arc::new(MockWireGuard::new()),
+            SecretKey::gen().public(),
+        );
+        let stats = |attempts, successes| FamilyCheckStats {
+            attempts,
+            successes,
+        };
+
+        aggregator
+            .report_family_checks(BTreeMap::from([(AddressFamily::IPv4, stats(2, 1))]))
+            .await;
+        aggregator
+            .report_family_checks(BTreeMap::from([
+                (AddressFamily::IPv4, stats(1, 1)),
+                (AddressFamily::IPv6, stats(3, 0)),
+            ]))
+            .await;
+
+        assert_eq!(
+            aggregator.collect_family_checks().await,
+            BTreeMap::from([
+                (AddressFamily::IPv4, stats(3, 2)),
+                (AddressFamily::IPv6, stats(3, 0)),
+            ])
+        );
+        assert!(aggregator.collect_family_checks().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_relay_paths_keep_latest_delay() {
+        let aggregator = ConnectivityDataAggregator::new(
+            AggregatorConfig {
+                relay_events: true,
+                ..Default::default()
+            },
+            Arc::new(MockWireGuard::new()),
+            SecretKey::gen().public(),
+        );
+        let server = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 8765);
+        let ms = Duration::from_millis;
+
+        aggregator.report_relay_path(server, ms(80), ms(40)).await;
+        aggregator.report_relay_path(server, ms(60), ms(5)).await;
+
+        assert_eq!(
+            aggregator.collect_relay_paths().await,
+            BTreeMap::from([(
+                server,
+                RelayPathDelay {
+                    rtt: ms(60),
+                    jitter: ms(5),
+                }
+            )])
+        );
+        assert!(aggregator.collect_relay_paths().await.is_empty());
+    }
+
     #[tokio::test]
     async fn test_relay_conn_data_serialization() {
         let mut writer = WriterBuilder::new()
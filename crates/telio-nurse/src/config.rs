@@ -99,6 +99,9 @@ pub struct AggregatorConfig {
     /// Collect nat traversal events
     pub nat_traversal_events: bool,
 
+    /// Collect DNS failure counters
+    pub dns_failure_events: bool,
+
     /// Maximum session length before forced collection
     #[default(Duration::from_secs(60 * 60 * 24))]
     pub state_duration_cap: Duration,
@@ -110,6 +113,7 @@ impl AggregatorConfig {
         Self {
             relay_events: features.enable_relay_conn_data,
             nat_traversal_events: features.enable_nat_traversal_conn_data,
+            dns_failure_events: features.enable_dns_failure_data,
             state_duration_cap: Duration::from_secs(features.state_duration_cap),
         }
     }
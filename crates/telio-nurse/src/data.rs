@@ -30,6 +30,12 @@ pub struct HeartbeatInfo {
     pub nat_traversal_conn_info: String,
     /// Derp connection info
     pub derp_conn_info: String,
+    /// String with comma-separated list of `upstream_hash:failure_kind:count` of the DNS upstreams
+    pub dns_failure_info: String,
+    /// String with comma-separated list of `family:attempts:successes` of the connectivity checks
+    pub endpoint_family_info: String,
+    /// String with comma-separated list of `server_hash:rtt_ms:jitter_ms` of the relayed paths
+    pub relay_path_info: String,
 }
 
 /// Analytics data
@@ -247,6 +247,9 @@ pub struct Analytics {
 
     /// Connectivity data aggregator
     aggregator: Arc<ConnectivityDataAggregator>,
+
+    /// Periodic collections are held back while the device is suspended
+    pub suspended: bool,
 }
 
 #[async_trait]
@@ -310,7 +313,7 @@ impl Runtime for Analytics {
                 ),
 
             // Time to send a data request to other nodes, only update in the `Monitoring` state
-            _ = self.task_interval.tick(), if self.state == RuntimeState::Monitoring =>
+            _ = self.task_interval.tick(), if self.state == RuntimeState::Monitoring && !self.suspended =>
                 Self::guard(
                     async move {
                         self.handle_collection().await;
@@ -407,6 +410,7 @@ impl Analytics {
             ip_stack: None,
             nat_type: NatType::Unknown,
             aggregator,
+            suspended: false,
         }
     }
 
@@ -964,12 +968,59 @@ impl Analytics {
             relay_str.push_str(String::from_utf8(writer.into_inner()?)?.as_str());
         }
 
-        // Pop last ',' from 'peers_str' and 'relay_str
+        let mut dns_str = String::new();
+        for ((upstream, kind), count) in self.aggregator.collect_dns_failures().await {
+            let mut writer = WriterBuilder::new()
+                .delimiter(b':')
+                .terminator(csv::Terminator::Any(b','))
+                .from_writer(vec![]);
+
+            writer.serialize((
+                format!("{:x}", md5::compute(upstream.to_string().as_bytes())),
+                kind,
+                count,
+            ))?;
+            dns_str.push_str(String::from_utf8(writer.into_inner()?)?.as_str());
+        }
+
+        let mut family_str = String::new();
+        for (family, stats) in self.aggregator.collect_family_checks().await {
+            let mut writer = WriterBuilder::new()
+                .delimiter(b':')
+                .terminator(csv::Terminator::Any(b','))
+                .from_writer(vec![]);
+
+            writer.serialize((family, stats.attempts, stats.successes))?;
+            family_str.push_str(String::from_utf8(writer.into_inner()?)?.as_str());
+        }
+
+        let mut relay_path_str = String::new();
+        for (server_address, delay) in self.aggregator.collect_relay_paths().await {
+            let mut writer = WriterBuilder::new()
+                .delimiter(b':')
+                .terminator(csv::Terminator::Any(b','))
+                .from_writer(vec![]);
+
+            writer.serialize((
+                format!("{:x}", md5::compute(server_address.to_string().as_bytes())),
+                delay.rtt.as_millis(),
+                delay.jitter.as_millis(),
+            ))?;
+            relay_path_str.push_str(String::from_utf8(writer.into_inner()?)?.as_str());
+        }
+
+        // Pop last ',' from 'peers_str', 'relay_str', 'dns_str', 'family_str' and 'relay_path_str'
         let _ = peers_str.pop();
         let _ = relay_str.pop();
+        let _ = dns_str.pop();
+        let _ = family_str.pop();
+        let _ = relay_path_str.pop();
 
         hb_info.nat_traversal_conn_info = peers_str.clone();
         hb_info.derp_conn_info = relay_str.clone();
+        hb_info.dns_failure_info = dns_str;
+        hb_info.endpoint_family_info = family_str;
+        hb_info.relay_path_info = relay_path_str;
 
         Ok(())
     }
@@ -1087,10 +1138,14 @@ mod tests {
     use tokio::time::{self, timeout};
 
     use crate::aggregator::{
-        EndpointType, PeerConnDataSegment, PeerConnectionData, PeerEndpointTypes,
-        RelayConnDataSegment, RelayConnectionData, RelayConnectionState,
+        DnsFailureCounters, DnsFailureKind, EndpointType, FamilyCheckCounters, PeerConnDataSegment,
+        PeerConnectionData, PeerEndpointTypes, RelayConnDataSegment, RelayConnectionData,
+        RelayConnectionState, RelayPathDelay, RelayPathDelays,
+    };
+    use telio_model::{
+        config::RelayConnectionChangeReason,
+        mesh::{AddressFamily, FamilyCheckStats},
     };
-    use telio_model::config::RelayConnectionChangeReason;
 
     use super::*;
 
@@ -1169,6 +1224,15 @@ mod tests {
                     },
                 }],
             });
+        fake_aggregator
+            .expect_collect_dns_failures()
+            .returning(DnsFailureCounters::new);
+        fake_aggregator
+            .expect_collect_family_checks()
+            .returning(FamilyCheckCounters::new);
+        fake_aggregator
+            .expect_collect_relay_paths()
+            .returning(RelayPathDelays::new);
 
         let aggregator = maybe_aggregator
             .unwrap_or_else(|| Arc::new(fake_aggregator))
@@ -1416,6 +1480,29 @@ mod tests {
         assert_eq!("vpn:7600617f9f9db5691a8c2768bd9d8110:2", external_links);
     }
 
+    #[tokio::test]
+    async fn test_no_analytics_report_while_suspended() {
+        let State {
+            mut analytics_channel,
+            mut analytics,
+            ..
+        } = setup(Some(Duration::from_secs(5)), None);
+
+        analytics
+            .task_interval
+            .reset_at(Instant::now() - Duration::from_secs(25));
+        analytics.config.collect_answer_timeout = Duration::from_millis(200);
+        analytics.suspended = true;
+
+        let rt = Task::start(analytics);
+
+        assert!(timeout(Duration::from_secs(1), analytics_channel.recv())
+            .await
+            .is_err());
+
+        rt.stop().await;
+    }
+
     #[tokio::test]
     // After a pause libtelio should send only one analytic even
     // if it missed more than one analytic interval.
@@ -1463,6 +1550,67 @@ mod tests {
             .times(1)
             .return_const(());
 
+        aggregator
+            .expect_collect_dns_failures()
+            .times(1)
+            .returning(|| {
+                let upstream = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+                DnsFailureCounters::from([
+                    ((upstream, DnsFailureKind::ServFail), 2),
+                    ((upstream, DnsFailureKind::Timeout), 5),
+                ])
+            });
+
+        aggregator
+            .expect_collect_dns_failures()
+            .times(1)
+            .returning(DnsFailureCounters::new);
+
+        aggregator
+            .expect_collect_family_checks()
+            .times(1)
+            .returning(|| {
+                FamilyCheckCounters::from([
+                    (
+                        AddressFamily::IPv4,
+                        FamilyCheckStats {
+                            attempts: 4,
+                            successes: 3,
+                        },
+                    ),
+                    (
+                        AddressFamily::IPv6,
+                        FamilyCheckStats {
+                            attempts: 2,
+                            successes: 0,
+                        },
+                    ),
+                ])
+            });
+
+        aggregator
+            .expect_collect_family_checks()
+            .times(1)
+            .returning(FamilyCheckCounters::new);
+
+        aggregator
+            .expect_collect_relay_paths()
+            .times(1)
+            .returning(|| {
+                RelayPathDelays::from([(
+                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 8765),
+                    RelayPathDelay {
+                        rtt: Duration::from_millis(80),
+                        jitter: Duration::from_millis(12),
+                    },
+                )])
+            });
+
+        aggregator
+            .expect_collect_relay_paths()
+            .times(1)
+            .returning(RelayPathDelays::new);
+
         aggregator
             .expect_collect_unacknowledged_segments()
             .times(1)
@@ -1599,6 +1747,16 @@ mod tests {
             3a71ac5dcb0e8e44b96645dbc335dae3:60:257:101",
             heartbeat_info.derp_conn_info
         );
+        assert_eq!(
+            "e086aa137fa19f67d27b39d0eca18610:serv_fail:2,\
+            e086aa137fa19f67d27b39d0eca18610:timeout:5",
+            heartbeat_info.dns_failure_info
+        );
+        assert_eq!("ipv4:4:3,ipv6:2:0", heartbeat_info.endpoint_family_info);
+        assert_eq!(
+            format!("{:x}:80:12", md5::compute("1.1.1.1:8765".as_bytes())),
+            heartbeat_info.relay_path_info
+        );
 
         time::pause();
         time::advance(Duration::from_secs(10)).await;
@@ -1621,5 +1779,8 @@ mod tests {
             "a49ea8474525d845da07e6ef09d0f222:120:256:102",
             heartbeat_info_disconnect.derp_conn_info
         );
+        assert!(heartbeat_info_disconnect.dns_failure_info.is_empty());
+        assert!(heartbeat_info_disconnect.endpoint_family_info.is_empty());
+        assert!(heartbeat_info_disconnect.relay_path_info.is_empty());
     }
 }
@@ -84,6 +84,15 @@ impl Nurse {
         .await;
     }
 
+    /// Hold back or restart the periodic heartbeats and QoS pings
+    pub async fn set_suspended(&self, suspended: bool) {
+        let _ = task_exec!(&self.task, async move |state| {
+            state.set_suspended(suspended).await;
+            Ok(())
+        })
+        .await;
+    }
+
     /// Send disconnect data
     pub async fn send_disconnect_data(&self) {
         let _ = task_exec!(&self.task, async move |state| {
@@ -98,6 +107,17 @@ impl Nurse {
         self.send_disconnect_data().await;
         let _ = self.task.stop().await.resume_unwind();
     }
+
+    /// Stop nurse without sending the disconnect data, dropping whatever was collected so far
+    pub async fn discard(self) {
+        let _ = self.task.stop().await.resume_unwind();
+    }
+
+    /// Stop nurse through a shared handle, without sending the disconnect data and without
+    /// waiting for it to stop
+    pub fn cancel(&self) {
+        self.task.cancellation_token().cancel();
+    }
 }
 
 /// Nurse struct, combines meshnet health data from different sources
@@ -163,7 +183,7 @@ impl State {
             meshnet_id,
             config.heartbeat_config,
             heartbeat_io,
-            aggregator,
+            aggregator.clone(),
         );
 
         // Qos component
@@ -181,6 +201,7 @@ impl State {
                     config_update_channel: config_update_channel.subscribe(),
                 },
                 ipv6_enabled,
+                aggregator,
             )))
         } else {
             None
@@ -225,6 +246,27 @@ impl State {
         .await;
     }
 
+    /// Hold back or restart the periodic heartbeats and QoS pings, e.g. while the device sleeps
+    ///
+    /// # Arguments
+    ///
+    /// * `suspended` - Whether the periodic collections should be held back.
+    pub async fn set_suspended(&self, suspended: bool) {
+        let _ = task_exec!(&self.heartbeat, async move |state| {
+            state.suspended = suspended;
+            Ok(())
+        })
+        .await;
+        if let Some(qos) = self.qos.as_ref() {
+            let _ = task_exec!(qos, async move |state| {
+                state.suspended = suspended;
+                Ok(())
+            })
+            .await;
+        }
+        telio_log_debug!("Nurse suspended: {suspended}");
+    }
+
     async fn handle_service_quality_event(&self, info: &HeartbeatInfo, disconnect: bool) {
         let internal_sorted_public_keys = info.internal_sorted_public_keys.clone();
         let external_sorted_public_keys = info.external_sorted_public_keys.clone();
@@ -261,6 +303,16 @@ impl State {
 
         let qos_data = QoSData::merge(internal_qos_data, external_qos_data);
 
+        let dns_failures =
+            (!info.dns_failure_info.is_empty()).then(|| info.dns_failure_info.clone());
+        let endpoint_families =
+            (!info.endpoint_family_info.is_empty()).then(|| info.endpoint_family_info.clone());
+        let rtt_relay = (!qos_data.rtt_relay.is_empty()).then(|| qos_data.rtt_relay.clone());
+        let rtt_direct = (!qos_data.rtt_direct.is_empty()).then(|| qos_data.rtt_direct.clone());
+        let relay_paths = (!info.relay_path_info.is_empty()).then(|| info.relay_path_info.clone());
+        // Everything has a dedicated field now, nothing goes into the debug one
+        let debug_json: Option<String> = None;
+
         let r = if disconnect {
             lana!(
                 send_serviceQuality_node_disconnect,
@@ -275,7 +327,12 @@ impl State {
                 0, // TODO Derp Connection Duration
                 info.nat_traversal_conn_info.clone(),
                 info.derp_conn_info.clone(),
-                None
+                dns_failures,
+                endpoint_families,
+                rtt_relay,
+                rtt_direct,
+                relay_paths,
+                debug_json
             )
         } else {
             lana!(
@@ -291,7 +348,12 @@ impl State {
                 0, // TODO Derp Connection Duration
                 info.nat_traversal_conn_info.clone(),
                 info.derp_conn_info.clone(),
-                None
+                dns_failures,
+                endpoint_families,
+                rtt_relay,
+                rtt_direct,
+                relay_paths,
+                debug_json
             )
         };
 
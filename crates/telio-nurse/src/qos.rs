@@ -9,7 +9,7 @@ use tokio::sync::mpsc;
 use tokio::time::{Duration, Instant, Interval};
 
 use telio_crypto::PublicKey;
-use telio_model::features::RttType;
+use telio_model::features::{PathType, RttType};
 use telio_task::{io::mc_chan, Runtime, RuntimeExt, WaitResponse};
 use telio_wg::uapi::{AnalyticsEvent, PeerState};
 
@@ -17,7 +17,9 @@ use telio_utils::{
     interval, telio_log_debug, telio_log_trace, DualPingResults, DualTarget, IpStack, Pinger,
 };
 
-use crate::{config::QoSConfig, data::MeshConfigUpdateEvent};
+use crate::{
+    aggregator::ConnectivityDataAggregator, config::QoSConfig, data::MeshConfigUpdateEvent,
+};
 
 /// Information about a node in the meshnet.
 #[derive(Clone)]
@@ -36,6 +38,10 @@ pub struct NodeInfo {
     pub rtt6_histogram: Histogram,
     pub rtt6_loss_histogram: Histogram,
 
+    // RTT split by the path in use at measurement time
+    pub rtt_relay_histogram: Histogram,
+    pub rtt_direct_histogram: Histogram,
+
     // Throughput
     pub last_tx_bytes: u64,
     pub last_rx_bytes: u64,
@@ -68,6 +74,14 @@ impl NodeInfo {
         };
     }
 
+    fn update_path_rtt_info(&mut self, path: Option<PathType>, rtt: u64) {
+        let _ = match path {
+            Some(PathType::Relay) => self.rtt_relay_histogram.increment(rtt),
+            Some(PathType::Direct) => self.rtt_direct_histogram.increment(rtt),
+            None => return,
+        };
+    }
+
     fn update_connection_duration(&mut self, event: &AnalyticsEvent, pause: bool) {
         if self.peer_state == PeerState::Connected && !pause {
             // Connected time
@@ -89,6 +103,8 @@ impl From<AnalyticsEvent> for NodeInfo {
             rtt_loss_histogram: Histogram::new(),
             rtt6_histogram: Histogram::new(),
             rtt6_loss_histogram: Histogram::new(),
+            rtt_relay_histogram: Histogram::new(),
+            rtt_direct_histogram: Histogram::new(),
             last_tx_bytes: 0,
             last_rx_bytes: 0,
             tx_histogram: Histogram::new(),
@@ -104,6 +120,8 @@ pub struct OutputData {
     pub rtt_loss: String,
     pub rtt6: String,
     pub rtt6_loss: String,
+    pub rtt_relay: String,
+    pub rtt_direct: String,
     pub tx: String,
     pub rx: String,
     pub connection_duration: String,
@@ -118,6 +136,8 @@ impl OutputData {
             rtt_loss: Analytics::null_data(buckets),
             rtt6: Analytics::null_data(buckets),
             rtt6_loss: Analytics::null_data(buckets),
+            rtt_relay: Analytics::null_data(buckets),
+            rtt_direct: Analytics::null_data(buckets),
             tx: Analytics::empty_data(buckets),
             rx: Analytics::empty_data(buckets),
             connection_duration: String::from("0"),
@@ -131,6 +151,8 @@ impl OutputData {
             rtt_loss: OutputData::merge_strings(a.rtt_loss, b.rtt_loss, ','),
             rtt6: OutputData::merge_strings(a.rtt6, b.rtt6, ','),
             rtt6_loss: OutputData::merge_strings(a.rtt6_loss, b.rtt6_loss, ','),
+            rtt_relay: OutputData::merge_strings(a.rtt_relay, b.rtt_relay, ','),
+            rtt_direct: OutputData::merge_strings(a.rtt_direct, b.rtt_direct, ','),
             tx: OutputData::merge_strings(a.tx, b.tx, ','),
             rx: OutputData::merge_strings(a.rx, b.rx, ','),
             connection_duration: OutputData::merge_strings(
@@ -178,10 +200,14 @@ pub struct Analytics {
     io: Io,
     nodes: HashMap<PublicKey, NodeInfo>,
     ping_backend: Arc<Option<Pinger>>,
-    ping_channel_rx: mpsc::Receiver<(PublicKey, DualPingResults)>,
-    ping_channel_tx: mpsc::WeakSender<(PublicKey, DualPingResults)>,
+    ping_channel_rx: mpsc::Receiver<(PublicKey, Option<PathType>, DualPingResults)>,
+    ping_channel_tx: mpsc::WeakSender<(PublicKey, Option<PathType>, DualPingResults)>,
     buckets: u32,
     ip_stack: Option<IpStack>,
+    /// Source of the path each peer is reached through
+    aggregator: Arc<ConnectivityDataAggregator>,
+    /// Periodic pings are held back while the device is suspended
+    pub suspended: bool,
     #[cfg(test)]
     ping_cnt: u32,
 }
@@ -217,7 +243,7 @@ impl Runtime for Analytics {
                 Self::next()
             },
 
-            _ = self.rtt_interval.tick(), if self.ping_channel_tx.upgrade().is_none() => {
+            _ = self.rtt_interval.tick(), if self.ping_channel_tx.upgrade().is_none() && !self.suspended => {
                 telio_log_debug!("Starting periodic ping");
                 self.perform_ping();
                 Self::next()
@@ -240,11 +266,17 @@ impl Analytics {
     ///
     /// * `config` - Config for QoS component.
     /// * `io` - Channel(s) for communicating with WireGuard.
+    /// * `aggregator` - Tells which path the peers are reached through.
     ///
     /// # Returns
     ///
     /// A new `Analytics` instance with the given configuration but with no nodes.
-    pub fn new(config: QoSConfig, io: Io, ipv6_enabled: bool) -> Self {
+    pub fn new(
+        config: QoSConfig,
+        io: Io,
+        ipv6_enabled: bool,
+        aggregator: Arc<ConnectivityDataAggregator>,
+    ) -> Self {
         let (ping_channel_tx, ping_channel_rx) = mpsc::channel(1);
 
         let ping_backend = if config.rtt_types.contains(&RttType::Ping) {
@@ -263,6 +295,8 @@ impl Analytics {
             ping_channel_tx: ping_channel_tx.downgrade(),
             buckets: config.buckets,
             ip_stack: None,
+            aggregator,
+            suspended: false,
             // TODO: introduce mocked `ping_backend` for testing
             #[cfg(test)]
             ping_cnt: 0,
@@ -306,6 +340,16 @@ impl Analytics {
                     buckets,
                     &mut output.rtt6_loss,
                 );
+                OutputData::push_rtt_data(
+                    Some(&node.rtt_relay_histogram),
+                    buckets,
+                    &mut output.rtt_relay,
+                );
+                OutputData::push_rtt_data(
+                    Some(&node.rtt_direct_histogram),
+                    buckets,
+                    &mut output.rtt_direct,
+                );
 
                 // Throughput
                 output.tx.push_str(&Analytics::percentile_histogram(
@@ -329,6 +373,8 @@ impl Analytics {
                 OutputData::push_rtt_data(None, buckets, &mut output.rtt_loss);
                 OutputData::push_rtt_data(None, buckets, &mut output.rtt6);
                 OutputData::push_rtt_data(None, buckets, &mut output.rtt6_loss);
+                OutputData::push_rtt_data(None, buckets, &mut output.rtt_relay);
+                OutputData::push_rtt_data(None, buckets, &mut output.rtt_direct);
 
                 output.tx.push_str(&Analytics::empty_data(buckets));
                 output.tx.push(',');
@@ -345,6 +391,8 @@ impl Analytics {
         output.rtt_loss.pop();
         output.rtt6.pop();
         output.rtt6_loss.pop();
+        output.rtt_relay.pop();
+        output.rtt_direct.pop();
         output.tx.pop();
         output.rx.pop();
         output.connection_duration.pop();
@@ -360,6 +408,8 @@ impl Analytics {
             node.rtt_loss_histogram = Histogram::new();
             node.rtt6_histogram = Histogram::new();
             node.rtt6_loss_histogram = Histogram::new();
+            node.rtt_relay_histogram = Histogram::new();
+            node.rtt_direct_histogram = Histogram::new();
             node.tx_histogram = Histogram::new();
             node.rx_histogram = Histogram::new();
             node.connected_time = Duration::default();
@@ -438,11 +488,14 @@ impl Analytics {
 
             let (pk, ip_addresses) = (node.public_key, node.ip_addresses.clone());
             let pinger = Arc::clone(&self.ping_backend);
+            let aggregator = Arc::clone(&self.aggregator);
             let ping_channel_tx = ping_channel_tx.clone();
             let curr_ip_stack = self.ip_stack.clone();
 
             tokio::spawn(async move {
                 if let Some(pinger) = &*pinger {
+                    // Samples are attributed to the path in use when the ping was started
+                    let path = aggregator.peer_path(pk).await;
                     let mut dpr = DualPingResults::default();
                     let mut ip_addresses = ip_addresses.iter().peekable();
 
@@ -474,7 +527,7 @@ impl Analytics {
                         }
 
                         if let Some(next_ip) = ip_addresses.peek() {
-                            let _ = ping_channel_tx.send((pk, dpr.clone())).await;
+                            let _ = ping_channel_tx.send((pk, path, dpr.clone())).await;
                             telio_log_debug!(
                                 "Node was not reachable through {:?}, trying {:?}.",
                                 ip_address,
@@ -483,39 +536,44 @@ impl Analytics {
                         }
                     }
 
-                    let _ = ping_channel_tx.send((pk, dpr)).await;
+                    let _ = ping_channel_tx.send((pk, path, dpr)).await;
                 }
             });
         }
     }
 
-    fn process_node_ping_results(&mut self, dpr: (PublicKey, DualPingResults)) {
+    fn process_node_ping_results(&mut self, dpr: (PublicKey, Option<PathType>, DualPingResults)) {
         if let Some(pinger) = &*self.ping_backend {
-            self.nodes.entry(dpr.0).and_modify(|node| {
-                if let Some(results_v4) = dpr.1.v4 {
+            let (public_key, path, dpr) = dpr;
+            self.nodes.entry(public_key).and_modify(|node| {
+                if let Some(results_v4) = dpr.v4 {
                     if let Some(avg_rtt) = results_v4.avg_rtt {
                         let avg_v4 = avg_rtt.as_millis() as u64;
                         let _ = node.rtt_histogram.increment(avg_v4);
+                        node.update_path_rtt_info(path, avg_v4);
                         let _ = node.rtt_loss_histogram.increment(
                             (100 * results_v4.unsuccessful_pings / pinger.no_of_tries) as u64,
                         );
                     } else if results_v4.unsuccessful_pings > 0 {
                         let _ = node.rtt_histogram.increment(0u64);
+                        node.update_path_rtt_info(path, 0);
                         let _ = node.rtt_loss_histogram.increment(
                             (100 * results_v4.unsuccessful_pings / pinger.no_of_tries) as u64,
                         );
                     }
                 }
 
-                if let Some(results_v6) = dpr.1.v6 {
+                if let Some(results_v6) = dpr.v6 {
                     if let Some(avg_rtt) = results_v6.avg_rtt {
                         let avg_v6 = avg_rtt.as_millis() as u64;
                         let _ = node.rtt6_histogram.increment(avg_v6);
+                        node.update_path_rtt_info(path, avg_v6);
                         let _ = node.rtt6_loss_histogram.increment(
                             (100 * results_v6.unsuccessful_pings / pinger.no_of_tries) as u64,
                         );
                     } else if results_v6.unsuccessful_pings > 0 {
                         let _ = node.rtt6_histogram.increment(0u64);
+                        node.update_path_rtt_info(path, 0);
                         let _ = node.rtt6_loss_histogram.increment(
                             (100 * results_v6.unsuccessful_pings / pinger.no_of_tries) as u64,
                         );
@@ -607,6 +665,10 @@ mod tests {
     use tokio::time as t_time;
 
     use telio_crypto::{PublicKey, SecretKey};
+    use telio_utils::PingResults;
+    use telio_wg::MockWireGuard;
+
+    use crate::config::AggregatorConfig;
 
     const RTT: Duration = Duration::from_secs(1);
 
@@ -654,6 +716,10 @@ mod tests {
             rtt_loss: String::from("20:40:70:90:100,null:null:null:null:null,20:40:70:90:100"),
             rtt6: String::from("20:40:70:90:100,null:null:null:null:null,20:40:70:90:100"),
             rtt6_loss: String::from("20:40:70:90:100,null:null:null:null:null,20:40:70:90:100"),
+            rtt_relay: String::from("20:40:70:90:100,null:null:null:null:null,20:40:70:90:100"),
+            rtt_direct: String::from(
+                "null:null:null:null:null,null:null:null:null:null,null:null:null:null:null",
+            ),
             tx: String::from("20:40:70:90:100,0:0:0:0:0,20:40:70:90:100"),
             rx: String::from("20:40:70:90:100,0:0:0:0:0,20:40:70:90:100"),
             connection_duration: String::from("100;0;200"),
@@ -732,7 +798,7 @@ mod tests {
         for _ in 0..4 {
             analytics.perform_ping();
             let node_ping_res = analytics.ping_channel_rx.recv().await.unwrap();
-            analytics.process_node_ping_results((node_ping_res.0, node_ping_res.1));
+            analytics.process_node_ping_results(node_ping_res);
         }
 
         let output = analytics.get_data(&BTreeSet::<PublicKey>::from([event.public_key]));
@@ -743,6 +809,7 @@ mod tests {
             let mut od = OutputData::new(analytics.buckets);
             od.rtt = Analytics::empty_data(analytics.buckets);
             od.rtt_loss = Analytics::empty_data(analytics.buckets);
+            od.rtt_direct = Analytics::empty_data(analytics.buckets);
             od
         };
         assert_eq!(output, after_ping);
@@ -766,7 +833,7 @@ mod tests {
         // Perform ping with new data
         analytics.perform_ping();
         let node_ping_res = analytics.ping_channel_rx.recv().await.unwrap();
-        analytics.process_node_ping_results((node_ping_res.0, node_ping_res.1));
+        analytics.process_node_ping_results(node_ping_res.clone());
 
         let node = analytics.nodes.get(&node_ping_res.0).unwrap();
         assert_eq!(node.public_key, event.public_key);
@@ -784,6 +851,8 @@ mod tests {
             rtt_loss: "0:0:0:0:0".to_owned(),
             rtt6: "null:null:null:null:null".to_owned(),
             rtt6_loss: "null:null:null:null:null".to_owned(),
+            rtt_relay: "null:null:null:null:null".to_owned(),
+            rtt_direct: "0:0:0:0:0".to_owned(),
             tx: "5:8:8:8:8".to_owned(),
             rx: "10:12:12:12:12".to_owned(),
             connection_duration: String::from("7"),
@@ -792,6 +861,53 @@ mod tests {
         assert_eq!(output, expected_output);
     }
 
+    #[tokio::test]
+    #[cfg(not(target_os = "macos"))]
+    async fn test_rtt_attributed_to_path_at_measurement_time() {
+        let (mut analytics, _, _) = setup();
+        let event = generate_event();
+        analytics.handle_wg_event(&event).await;
+
+        let results = |rtt_ms| DualPingResults {
+            v4: Some(PingResults {
+                host: Some(Ipv4Addr::LOCALHOST.into()),
+                successful_pings: 1,
+                unsuccessful_pings: 0,
+                avg_rtt: Some(Duration::from_millis(rtt_ms)),
+            }),
+            v6: None,
+        };
+
+        // Ping started while relayed, but the peer got upgraded before the result came back
+        analytics.process_node_ping_results((
+            event.public_key,
+            Some(PathType::Relay),
+            results(100),
+        ));
+        analytics.process_node_ping_results((
+            event.public_key,
+            Some(PathType::Direct),
+            results(10),
+        ));
+
+        let output = analytics.get_data(&BTreeSet::<PublicKey>::from([event.public_key]));
+        assert_eq!(output.rtt, "10:10:100:100:100");
+        assert_eq!(output.rtt_relay, "100:100:100:100:100");
+        assert_eq!(output.rtt_direct, "10:10:10:10:10");
+
+        analytics.reset_cached_data();
+        let output = analytics.get_data(&BTreeSet::<PublicKey>::from([event.public_key]));
+        assert_eq!(output.rtt_relay, Analytics::null_data(analytics.buckets));
+        assert_eq!(output.rtt_direct, Analytics::null_data(analytics.buckets));
+
+        // The path of a peer that is not installed yet is unknown
+        analytics.process_node_ping_results((event.public_key, None, results(50)));
+        let output = analytics.get_data(&BTreeSet::<PublicKey>::from([event.public_key]));
+        assert_eq!(output.rtt, "50:50:50:50:50");
+        assert_eq!(output.rtt_relay, Analytics::null_data(analytics.buckets));
+        assert_eq!(output.rtt_direct, Analytics::null_data(analytics.buckets));
+    }
+
     #[tokio::test(start_paused = true)]
     #[cfg(not(target_os = "macos"))]
     async fn test_manual_and_rtt_interval() {
@@ -933,7 +1049,16 @@ mod tests {
         };
 
         (
-            Analytics::new(config, io, true),
+            Analytics::new(
+                config,
+                io,
+                true,
+                Arc::new(ConnectivityDataAggregator::new(
+                    AggregatorConfig::default(),
+                    Arc::new(MockWireGuard::new()),
+                    SecretKey::gen().public(),
+                )),
+            ),
             manual_trigger_channel.tx,
             wg_channel.tx,
         )
@@ -981,6 +1106,8 @@ mod tests {
             rtt_loss_histogram: histogram.clone(),
             rtt6_histogram: histogram.clone(),
             rtt6_loss_histogram: histogram.clone(),
+            rtt_relay_histogram: histogram.clone(),
+            rtt_direct_histogram: Histogram::new(),
             last_rx_bytes: 0,
             last_tx_bytes: 0,
             tx_histogram: histogram.clone(),
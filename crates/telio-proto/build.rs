@@ -27,6 +27,7 @@ fn main() -> Result<()> {
             "protos/pinger.proto",
             "protos/upgrade.proto",
             "protos/derppoll.proto",
+            "protos/wake.proto",
         ])
         .out_dir(out_dir)
         .run()
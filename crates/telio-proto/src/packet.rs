@@ -5,15 +5,19 @@ use crate::{Codec, CodecError, CodecResult};
 use telio_crypto::PublicKey;
 
 pub use relayed::{
+    compressed::COMPRESSION_THRESHOLD,
+    control::{ControlFeatures, ControlHeader, CONTROL_VERSION, LOCAL_CONTROL_CAPABILITIES},
     data::DataMsg,
     generation::Generation,
     natter::CallMeMaybeMsg,
     natter::CallMeMaybeMsgDeprecated,
+    natter::PunchRequestMsg,
     nurse::HeartbeatMessage,
     pinger::PingerMsg,
     pinger::Timestamp,
     pinger::{PartialPongerMsg, PlaintextPongerMsg},
     upgrade::{Decision, UpgradeDecisionMsg, UpgradeMsg},
+    wake::WakeOnLanMsg,
 };
 
 pub use control::derppoll::{DerpPollRequestMsg, DerpPollResponseMsg, PeersStatesMap};
@@ -82,6 +86,15 @@ pub enum PacketTypeRelayed {
     /// Message with a reply for the Upgrade message
     UpgradeDecision = 0x0a,
 
+    /// Request for a coordinated hole punch from a node behind a hard NAT
+    PunchRequest = 0x0b,
+
+    /// Compressed control packet, relayed through Derp
+    Compressed = 0x0c,
+
+    /// Request to send a Wake-on-LAN magic packet on behalf of the sender
+    WakeOnLan = 0x0d,
+
     /// Reserved for future, in case we use all byte values for types.
     Reserved = 0xfe,
 
@@ -114,6 +127,10 @@ pub enum PacketRelayed {
     Upgrade(UpgradeMsg),
     /// Upgrading connection result
     UpgradeDecision(UpgradeDecisionMsg),
+    /// Coordinated hole punching
+    PunchRequest(PunchRequestMsg),
+    /// Waking up a sleeping node
+    WakeOnLan(WakeOnLanMsg),
 }
 
 impl PacketRelayed {
@@ -154,14 +171,47 @@ impl PacketRelayed {
                 }
                 Upgrade => Self::Upgrade(UpgradeMsg::decode(bytes)?),
                 UpgradeDecision => Self::UpgradeDecision(UpgradeDecisionMsg::decode(bytes)?),
+                PunchRequest => Self::PunchRequest(PunchRequestMsg::decode(bytes)?),
+                WakeOnLan => Self::WakeOnLan(WakeOnLanMsg::decode(bytes)?),
+                // Compressed packets are only relayed through Derp, which decodes them
                 // At this point a package already should be decrypted if is not Data
-                Reserved | Invalid | Encrypted => return Err(CodecError::DecodeFailed),
+                Reserved | Invalid | Encrypted | Compressed => {
+                    return Err(CodecError::DecodeFailed)
+                }
             },
             None,
         ))
     }
 }
 
+impl PacketRelayed {
+    /// Version and features of the sender, for the control messages carrying them
+    pub fn control_header(&self) -> Option<ControlHeader> {
+        match self {
+            Self::CallMeMaybe(msg) => Some(msg.get_control()),
+            Self::PunchRequest(msg) => Some(msg.get_control()),
+            Self::Upgrade(msg) => Some(msg.control),
+            Self::UpgradeDecision(msg) => Some(msg.control),
+            Self::WakeOnLan(msg) => Some(msg.control),
+            Self::Data(_)
+            | Self::Heartbeat(_)
+            | Self::CallMeMaybeDeprecated(_)
+            | Self::Pinger(_)
+            | Self::Ponger(_) => None,
+        }
+    }
+
+    /// Encode the packet, compressing it if it is a large control packet.
+    ///
+    /// Must only be used for the peers which advertised [`PeerCapability::Compression`].
+    ///
+    /// [`PeerCapability::Compression`]: telio_model::config::PeerCapability::Compression
+    pub fn encode_compressed(self) -> CodecResult<Vec<u8>> {
+        let bytes = self.encode()?;
+        Ok(relayed::compressed::compress(&bytes).unwrap_or(bytes))
+    }
+}
+
 impl Codec<PacketTypeRelayed> for PacketRelayed {
     const TYPES: &'static [PacketTypeRelayed] = &[
         PacketTypeRelayed::Data,
@@ -173,6 +223,8 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
         PacketTypeRelayed::Upgrade,
         PacketTypeRelayed::Ponger,
         PacketTypeRelayed::UpgradeDecision,
+        PacketTypeRelayed::PunchRequest,
+        PacketTypeRelayed::WakeOnLan,
     ];
 
     fn decode(bytes: &[u8]) -> CodecResult<Self>
@@ -196,6 +248,9 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
             )),
             Upgrade => Ok(Self::Upgrade(UpgradeMsg::decode(bytes)?)),
             UpgradeDecision => Ok(Self::UpgradeDecision(UpgradeDecisionMsg::decode(bytes)?)),
+            PunchRequest => Ok(Self::PunchRequest(PunchRequestMsg::decode(bytes)?)),
+            WakeOnLan => Ok(Self::WakeOnLan(WakeOnLanMsg::decode(bytes)?)),
+            Compressed => Self::decode(&relayed::compressed::decompress(bytes)?),
             // At this point a package already should be decrypted if is not Data
             Reserved | Invalid | Encrypted => Err(CodecError::DecodeFailed),
         }
@@ -211,6 +266,8 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
             Self::CallMeMaybeDeprecated(msg) => msg.encode(),
             Self::Upgrade(msg) => msg.encode(),
             Self::UpgradeDecision(msg) => msg.encode(),
+            Self::PunchRequest(msg) => msg.encode(),
+            Self::WakeOnLan(msg) => msg.encode(),
         }
     }
 
@@ -225,6 +282,8 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
             Self::CallMeMaybeDeprecated(msg) => msg.packet_type(),
             Self::Upgrade(msg) => msg.packet_type(),
             Self::UpgradeDecision(msg) => msg.packet_type(),
+            Self::PunchRequest(msg) => msg.packet_type(),
+            Self::WakeOnLan(msg) => msg.packet_type(),
         }
     }
 }
@@ -374,6 +433,12 @@ impl From<UpgradeDecisionMsg> for PacketRelayed {
     }
 }
 
+impl From<PunchRequestMsg> for PacketRelayed {
+    fn from(other: PunchRequestMsg) -> Self {
+        Self::PunchRequest(other)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -384,6 +449,7 @@ mod test {
         let skip = [
             PacketTypeRelayed::Reserved,
             PacketTypeRelayed::Encrypted,
+            PacketTypeRelayed::Compressed,
             PacketTypeRelayed::Invalid,
         ];
         assert_eq!(
@@ -430,4 +496,23 @@ mod test {
         let expected = &[0, 3, 2, 1];
         assert_eq!(&packet.encode().unwrap(), expected);
     }
+
+    #[test]
+    fn compressed_packet_roundtrip() {
+        let addrs = (0..64u8).map(|i| std::net::SocketAddr::from(([10, 0, 0, i], 51820)));
+        let packet: PacketRelayed = CallMeMaybeMsg::new(true, addrs, 42).into();
+
+        let plain = packet.clone().encode().unwrap();
+        let compressed = packet.clone().encode_compressed().unwrap();
+        assert_eq!(
+            compressed.first(),
+            Some(&(PacketTypeRelayed::Compressed as u8))
+        );
+        assert!(compressed.len() < plain.len());
+        assert_eq!(PacketRelayed::decode(&compressed), Ok(packet));
+
+        // Too small to bother
+        let packet: PacketRelayed = DataMsg::new(&[3, 2, 1]).into();
+        assert_eq!(packet.encode_compressed().unwrap(), &[0, 3, 2, 1]);
+    }
 }
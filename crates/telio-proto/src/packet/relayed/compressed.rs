@@ -0,0 +1,119 @@
+//! Compression of the large control messages relayed through Derp, e.g. the endpoint lists of
+//! the nodes with many interfaces.
+//!
+//! A compressed packet wraps a whole encoded packet, type byte included, deflated:
+//! ```text
+//! [PacketTypeRelayed::Compressed][deflate([packet type][packet payload])]
+//! ```
+//! Only the peers which advertised [`PeerCapability::Compression`] may be sent compressed packets,
+//! and WireGuard data is never compressed, as it is encrypted anyway.
+//!
+//! [`PeerCapability::Compression`]: telio_model::config::PeerCapability::Compression
+
+use crate::{CodecError, CodecResult, PacketTypeRelayed, MAX_PACKET_SIZE};
+use miniz_oxide::{deflate::compress_to_vec, inflate::decompress_to_vec_with_limit};
+
+/// Packets shorter than this are sent as is, as there is little to gain
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Deflate level, the messages are small so the fastest one compresses about as well
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// Compress the encoded packet, if it is a control packet which gets shorter
+pub fn compress(packet: &[u8]) -> Option<Vec<u8>> {
+    let packet_type = PacketTypeRelayed::from(*packet.first()?);
+    if packet.len() < COMPRESSION_THRESHOLD || !is_compressible(packet_type) {
+        return None;
+    }
+
+    let mut compressed = Vec::with_capacity(packet.len());
+    compressed.push(PacketTypeRelayed::Compressed as u8);
+    compressed.extend(compress_to_vec(packet, COMPRESSION_LEVEL));
+    (compressed.len() < packet.len()).then_some(compressed)
+}
+
+/// Decompress the packet back to the encoded packet it wraps
+pub fn decompress(bytes: &[u8]) -> CodecResult<Vec<u8>> {
+    match bytes.split_first() {
+        Some((&packet_type, payload)) if packet_type == PacketTypeRelayed::Compressed as u8 => {
+            let packet = decompress_to_vec_with_limit(payload, MAX_PACKET_SIZE)
+                .map_err(|_| CodecError::DecodeFailed)?;
+            match packet.first() {
+                Some(&packet_type) if is_compressible(PacketTypeRelayed::from(packet_type)) => {
+                    Ok(packet)
+                }
+                Some(_) => Err(CodecError::InvalidType),
+                None => Err(CodecError::InvalidLength),
+            }
+        }
+        Some(_) => Err(CodecError::InvalidType),
+        None => Err(CodecError::InvalidLength),
+    }
+}
+
+fn is_compressible(packet_type: PacketTypeRelayed) -> bool {
+    use PacketTypeRelayed::*;
+
+    match packet_type {
+        Heartbeat
+        | CallMeMaybe
+        | CallMeMaybeDeprecated
+        | Upgrade
+        | UpgradeDecision
+        | PunchRequest
+        | WakeOnLan => true,
+        Data | GenData | Pinger | Ponger | Encrypted | Compressed | Reserved | Invalid => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_me_maybe(len: usize) -> Vec<u8> {
+        let mut packet = vec![PacketTypeRelayed::CallMeMaybe as u8];
+        packet.extend(b"10.0.0.1:51820,".iter().cycle().take(len - 1));
+        packet
+    }
+
+    #[test]
+    fn large_control_packet_is_compressed() {
+        let packet = call_me_maybe(1024);
+        let compressed = compress(&packet).unwrap();
+        assert_eq!(compressed[0], PacketTypeRelayed::Compressed as u8);
+        assert!(compressed.len() < packet.len() / 4);
+        assert_eq!(decompress(&compressed), Ok(packet));
+    }
+
+    #[test]
+    fn small_and_data_packets_are_not_compressed() {
+        assert_eq!(compress(&call_me_maybe(COMPRESSION_THRESHOLD - 1)), None);
+        assert_eq!(compress(&[]), None);
+
+        let mut data = call_me_maybe(1024);
+        data[0] = PacketTypeRelayed::Data as u8;
+        assert_eq!(compress(&data), None);
+    }
+
+    #[test]
+    fn malformed_compressed_packets_are_rejected() {
+        let compressed = |packet: &[u8]| {
+            let mut bytes = vec![PacketTypeRelayed::Compressed as u8];
+            bytes.extend(compress_to_vec(packet, COMPRESSION_LEVEL));
+            bytes
+        };
+
+        assert_eq!(
+            decompress(&[PacketTypeRelayed::Compressed as u8, 1, 2, 3]),
+            Err(CodecError::DecodeFailed)
+        );
+        // Nested compression and data are never sent
+        let nested = compressed(&compressed(&call_me_maybe(1024)));
+        assert_eq!(decompress(&nested), Err(CodecError::InvalidType));
+        let data = compressed(&[PacketTypeRelayed::Data as u8, 1, 2, 3]);
+        assert_eq!(decompress(&data), Err(CodecError::InvalidType));
+        // Bombs are cut at the packet size
+        let bomb = compressed(&call_me_maybe(MAX_PACKET_SIZE + 1));
+        assert_eq!(decompress(&bomb), Err(CodecError::DecodeFailed));
+    }
+}
@@ -0,0 +1,140 @@
+//! Versioning of the control messages exchanged by the nodes, e.g. the endpoint exchange and
+//! the upgrades.
+//!
+//! Every control message carries the version of the control protocol of its sender along with
+//! the optional features it supports. Newer versions only add fields and packet types, so their
+//! messages stay decodable by the older clients, which ignore what they do not know. A node
+//! uses a new feature only once the peer has advertised it.
+//!
+//! The features are the control protocol [`PeerCapability`]s, so they are negotiated the same way
+//! as the capabilities advertised in the peer config.
+
+use telio_model::config::{negotiate_capabilities, PeerCapability};
+
+/// Version of the control protocol implemented by this node
+pub const CONTROL_VERSION: u32 = 1;
+
+/// Capabilities of the control protocol, in the order of their feature bits
+const CONTROL_CAPABILITIES: [PeerCapability; 3] = [
+    PeerCapability::UpgradeRaceResolution,
+    PeerCapability::PunchRequest,
+    PeerCapability::Compression,
+];
+
+/// Capabilities of the control protocol supported by this node
+pub const LOCAL_CONTROL_CAPABILITIES: &[PeerCapability] = &CONTROL_CAPABILITIES;
+
+/// Optional features of the control protocol, as encoded in the control messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ControlFeatures(u64);
+
+impl ControlFeatures {
+    /// No optional features, as advertised by the clients predating the versioning
+    pub const NONE: Self = Self(0);
+
+    /// Features from their bits, the bits unknown to this node are kept
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Bits of the features
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Features of the control protocol capabilities, other capabilities have no bit
+    pub fn from_capabilities(capabilities: &[PeerCapability]) -> Self {
+        Self(
+            CONTROL_CAPABILITIES
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| capabilities.contains(c))
+                .fold(0, |bits, (bit, _)| bits | 1 << bit),
+        )
+    }
+
+    /// Capabilities of the features known to this node
+    pub fn capabilities(self) -> Vec<PeerCapability> {
+        CONTROL_CAPABILITIES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.0 & 1 << bit != 0)
+            .map(|(_, c)| *c)
+            .collect()
+    }
+}
+
+/// Version and features of the sender of a control message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControlHeader {
+    /// Version of the control protocol, 0 for the clients predating the versioning
+    pub version: u32,
+    /// Optional features supported by the sender
+    pub features: ControlFeatures,
+}
+
+impl ControlHeader {
+    /// Header of the control messages sent by this node
+    pub fn local() -> Self {
+        Self {
+            version: CONTROL_VERSION,
+            features: ControlFeatures::from_capabilities(LOCAL_CONTROL_CAPABILITIES),
+        }
+    }
+
+    pub(crate) const fn from_proto(version: u32, features: u64) -> Self {
+        Self {
+            version,
+            features: ControlFeatures::from_bits(features),
+        }
+    }
+
+    /// Whether the capability can be used with the sender, i.e. both sides support it
+    pub fn supports(&self, capability: PeerCapability) -> bool {
+        negotiate_capabilities(LOCAL_CONTROL_CAPABILITIES, &self.features.capabilities())
+            .contains(&capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_sender_supports_nothing() {
+        let header = ControlHeader::default();
+        assert_eq!(header.version, 0);
+        assert!(!header.supports(PeerCapability::UpgradeRaceResolution));
+        assert!(!header.supports(PeerCapability::PunchRequest));
+    }
+
+    #[test]
+    fn feature_bits_stay_stable() {
+        assert_eq!(ControlHeader::local().features.bits(), 0b111);
+        assert_eq!(
+            ControlFeatures::from_capabilities(&[
+                PeerCapability::PunchRequest,
+                PeerCapability::Ipv6
+            ])
+            .bits(),
+            0b10
+        );
+    }
+
+    #[test]
+    fn features_unknown_to_us_are_kept_but_not_used() {
+        let unknown = 1 << 63;
+        let header = ControlHeader::from_proto(
+            CONTROL_VERSION + 1,
+            ControlFeatures::from_capabilities(&[PeerCapability::PunchRequest]).bits() | unknown,
+        );
+        assert_eq!(header.features.bits() & unknown, unknown);
+        assert_eq!(
+            header.features.capabilities(),
+            [PeerCapability::PunchRequest]
+        );
+        assert!(header.supports(PeerCapability::PunchRequest));
+        assert!(!header.supports(PeerCapability::UpgradeRaceResolution));
+        assert!(!header.supports(PeerCapability::Unknown));
+    }
+}
@@ -1,7 +1,10 @@
 //! Implementation for Node <-> Node packets
+pub mod compressed;
+pub mod control;
 pub mod data;
 pub mod generation;
 pub mod natter;
 pub mod nurse;
 pub mod pinger;
 pub mod upgrade;
+pub mod wake;
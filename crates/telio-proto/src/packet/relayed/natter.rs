@@ -1,9 +1,11 @@
+use std::convert::TryInto;
 use std::iter::FromIterator;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use crate::{
-    messages::natter::*, Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed,
-    PacketTypeRelayed, PeerId, Session, MAX_PACKET_SIZE,
+    messages::natter::*, Codec, CodecError, CodecResult, ControlHeader, DowncastPacket,
+    PacketRelayed, PacketTypeRelayed, PeerId, Session, MAX_PACKET_SIZE,
 };
 use bytes::BufMut;
 use protobuf::{Message, RepeatedField};
@@ -43,6 +45,8 @@ impl CallMeMaybeMsg {
                 addrs.into_iter().map(|addr| addr.to_string()).collect(),
             ),
             session,
+            control_version: ControlHeader::local().version,
+            control_features: ControlHeader::local().features.bits(),
             ..Default::default()
         })
     }
@@ -66,6 +70,11 @@ impl CallMeMaybeMsg {
     pub fn get_session(&self) -> u64 {
         self.0.get_session()
     }
+
+    /// Get control protocol version and features of the sender
+    pub fn get_control(&self) -> ControlHeader {
+        ControlHeader::from_proto(self.0.control_version, self.0.control_features)
+    }
 }
 
 impl Codec<PacketTypeRelayed> for CallMeMaybeMsg {
@@ -135,6 +144,114 @@ impl std::fmt::Display for CallMeMaybeMsg {
     }
 }
 
+/// Request for a coordinated hole punch, sent by a node which cannot open a path on its own
+/// (e.g. behind a symmetric NAT). The receiver replies with a [`CallMeMaybeMsg`] response and
+/// both sides start pinging each other once the delay passes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PunchRequestMsg(PunchRequest);
+
+impl PunchRequestMsg {
+    /// Returns new msg [`PunchRequestMsg`].
+    pub fn new<T: Iterator<Item = SocketAddr>>(
+        addrs: T,
+        session: Session,
+        delay: Duration,
+    ) -> Self {
+        Self(PunchRequest {
+            my_addresses: RepeatedField::from_vec(
+                addrs.into_iter().map(|addr| addr.to_string()).collect(),
+            ),
+            session,
+            delay_ms: delay.as_millis().try_into().unwrap_or(u32::MAX),
+            control_version: ControlHeader::local().version,
+            control_features: ControlHeader::local().features.bits(),
+            ..Default::default()
+        })
+    }
+
+    /// Get list of endpoints
+    pub fn get_addrs(&self) -> Vec<SocketAddr> {
+        self.0.my_addresses.iter().flat_map(|s| s.parse()).collect()
+    }
+
+    /// Get unique session number
+    pub fn get_session(&self) -> u64 {
+        self.0.get_session()
+    }
+
+    /// Time from receiving the request until the punch should start
+    pub fn get_delay(&self) -> Duration {
+        Duration::from_millis(self.0.get_delay_ms() as u64)
+    }
+
+    /// Get control protocol version and features of the sender
+    pub fn get_control(&self) -> ControlHeader {
+        ControlHeader::from_proto(self.0.control_version, self.0.control_features)
+    }
+}
+
+impl Codec<PacketTypeRelayed> for PunchRequestMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::PunchRequest];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::PunchRequest => {
+                let request =
+                    PunchRequest::parse_from_bytes(bytes.get(1..).ok_or(CodecError::DecodeFailed)?);
+                Ok(Self(request.map_err(|_| CodecError::DecodeFailed)?))
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>>
+    where
+        Self: Sized,
+    {
+        let mut bytes = Vec::with_capacity(MAX_PACKET_SIZE);
+
+        bytes.put_u8(PacketTypeRelayed::PunchRequest as u8);
+        self.0
+            .write_to_vec(&mut bytes)
+            .map_err(|_| CodecError::Encode)?;
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::PunchRequest
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for PunchRequestMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::PunchRequest(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+impl std::fmt::Display for PunchRequestMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PunchRequest: heres-my-number: {:?}, session: {}, delay: {}ms",
+            self.0.my_addresses, self.0.session, self.0.delay_ms,
+        )
+    }
+}
+
 /// Packet encapsulating containing WG packets
 #[derive(Debug, PartialEq, Clone)]
 pub struct CallMeMaybeMsgDeprecated(CallMeMaybeDeprecated);
@@ -285,11 +402,43 @@ mod tests {
         );
         let bytes = &[
             6, 8, 1, 18, 14, 49, 57, 50, 46, 49, 54, 56, 46, 49, 46, 49, 58, 56, 48, 33, 1, 0, 0,
-            0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 112, 1, 120, 7,
         ];
+        assert_eq!(packet.get_control(), ControlHeader::local());
         assert_eq!(packet.encode().unwrap(), bytes)
     }
 
+    #[test]
+    fn punch_request_roundtrip() {
+        let packet = PunchRequestMsg::new(
+            vec!["192.168.1.1:80".parse().unwrap()].into_iter(),
+            1,
+            Duration::from_millis(500),
+        );
+        let bytes = &[
+            11, 10, 14, 49, 57, 50, 46, 49, 54, 56, 46, 49, 46, 49, 58, 56, 48, 17, 1, 0, 0, 0, 0,
+            0, 0, 0, 24, 244, 3, 112, 1, 120, 7,
+        ];
+        assert_eq!(packet.clone().encode().unwrap(), bytes);
+
+        let data = PunchRequestMsg::decode(bytes).expect("Failed to parse packet");
+        assert_eq!(data, packet);
+        assert_eq!(data.get_session(), 1);
+        assert_eq!(data.get_delay(), Duration::from_millis(500));
+        assert_eq!(data.get_control(), ControlHeader::local());
+        assert_eq!(
+            data.get_addrs(),
+            vec!["192.168.1.1:80".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn punch_request_fail_to_decode_packet_of_wrong_type() {
+        let bytes = &[PacketTypeRelayed::CallMeMaybe as u8, 10, 0];
+        let data = PunchRequestMsg::decode(bytes);
+        assert_eq!(data, Err(CodecError::DecodeFailed));
+    }
+
     #[test]
     fn deprecated_decode_packet() {
         let bytes = &[
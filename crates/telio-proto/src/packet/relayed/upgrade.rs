@@ -1,8 +1,8 @@
 use std::{convert::TryInto, net::SocketAddr};
 
 use crate::{
-    messages::upgrade::*, Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed,
-    PacketTypeRelayed, Session, MAX_PACKET_SIZE,
+    messages::upgrade::*, Codec, CodecError, CodecResult, ControlHeader, DowncastPacket,
+    PacketRelayed, PacketTypeRelayed, Session, MAX_PACKET_SIZE,
 };
 
 use bytes::BufMut;
@@ -20,6 +20,8 @@ pub struct UpgradeMsg {
     pub endpoint_type: EndpointProvider,
     /// Endpoint type of the receiver
     pub receiver_endpoint_type: EndpointProvider,
+    /// Control protocol version and features of the sender
+    pub control: ControlHeader,
 }
 
 impl Codec<PacketTypeRelayed> for UpgradeMsg {
@@ -56,6 +58,10 @@ impl Codec<PacketTypeRelayed> for UpgradeMsg {
                         .get_receiver_endpoint_type()
                         .try_into()
                         .map_err(|_| CodecError::DecodeFailed)?,
+                    control: ControlHeader::from_proto(
+                        proto_upgrade.get_control_version(),
+                        proto_upgrade.get_control_features(),
+                    ),
                 })
             }
             _ => Err(CodecError::DecodeFailed),
@@ -69,6 +75,8 @@ impl Codec<PacketTypeRelayed> for UpgradeMsg {
         msg.set_session(self.session);
         msg.set_endpoint_type(self.endpoint_type.into());
         msg.set_receiver_endpoint_type(self.receiver_endpoint_type.into());
+        msg.set_control_version(self.control.version);
+        msg.set_control_features(self.control.features.bits());
 
         bytes.put_u8(PacketTypeRelayed::Upgrade as u8);
         msg.write_to_vec(&mut bytes)
@@ -138,6 +146,10 @@ pub struct UpgradeDecisionMsg {
     pub decision: Decision,
     /// Session identifing request for which this result in an answer
     pub session: Session,
+    /// Session of the upgrade request kept instead, when rejected due to a concurrent upgrade
+    pub winning_session: Option<Session>,
+    /// Control protocol version and features of the sender
+    pub control: ControlHeader,
 }
 
 impl Codec<PacketTypeRelayed> for UpgradeDecisionMsg {
@@ -156,8 +168,20 @@ impl Codec<PacketTypeRelayed> for UpgradeDecisionMsg {
                     .map_err(|_| CodecError::DecodeFailed)?;
                 let decision: Decision = proto_upgrade_decision.get_decision().into();
                 let session: Session = proto_upgrade_decision.session;
+                let winning_session =
+                    Some(proto_upgrade_decision.winning_session).filter(|session| *session != 0);
 
-                Ok(Self { decision, session })
+                let control = ControlHeader::from_proto(
+                    proto_upgrade_decision.get_control_version(),
+                    proto_upgrade_decision.get_control_features(),
+                );
+
+                Ok(Self {
+                    decision,
+                    session,
+                    winning_session,
+                    control,
+                })
             }
             _ => Err(CodecError::DecodeFailed),
         }
@@ -171,6 +195,11 @@ impl Codec<PacketTypeRelayed> for UpgradeDecisionMsg {
         let mut msg = UpgradeDecision::new();
         msg.set_decision(self.decision.into());
         msg.set_session(self.session);
+        if let Some(winning_session) = self.winning_session {
+            msg.set_winning_session(winning_session);
+        }
+        msg.set_control_version(self.control.version);
+        msg.set_control_features(self.control.features.bits());
 
         bytes.put_u8(PacketTypeRelayed::UpgradeDecision as u8);
         msg.write_to_vec(&mut bytes)
@@ -211,6 +240,45 @@ mod tests {
         assert_eq!(upgrade_msg.session, 42);
         assert_eq!(upgrade_msg.endpoint_type, EndpointProvider::Upnp);
         assert_eq!(upgrade_msg.receiver_endpoint_type, EndpointProvider::Local);
+        // Sent by a client predating the versioning
+        assert_eq!(upgrade_msg.control, ControlHeader::default());
+    }
+
+    #[test]
+    fn encode_versioned_upgrade_packet() {
+        let upgrade_msg = UpgradeMsg {
+            endpoint: "127.0.0.1:1234".parse().unwrap(),
+            session: 42,
+            endpoint_type: EndpointProvider::Local,
+            receiver_endpoint_type: EndpointProvider::Stun,
+            control: ControlHeader::local(),
+        };
+        let expected_upgrade_bytes: &[u8] = &[
+            8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52, 17, 42, 0, 0, 0, 0,
+            0, 0, 0, 24, 1, 32, 2, 112, 1, 120, 7,
+        ];
+        let actual_upgrade_bytes = upgrade_msg.clone().encode().unwrap();
+        assert_eq!(expected_upgrade_bytes, actual_upgrade_bytes);
+        assert_eq!(
+            upgrade_msg,
+            UpgradeMsg::decode(&actual_upgrade_bytes).unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_upgrade_packet_from_newer_version() {
+        // Version 2 with an unknown feature bit and an unknown field 16
+        let upgrade_bytes = &[
+            8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52, 17, 42, 0, 0, 0, 0,
+            0, 0, 0, 24, 1, 32, 2, 112, 2, 120, 131, 1, 128, 1, 5,
+        ];
+        let upgrade_msg = UpgradeMsg::decode(upgrade_bytes).expect("Failed to parse upgrade msg");
+        assert_eq!(upgrade_msg.session, 42);
+        assert_eq!(upgrade_msg.control.version, 2);
+        assert_eq!(upgrade_msg.control.features.bits(), 131);
+        assert!(upgrade_msg
+            .control
+            .supports(telio_model::config::PeerCapability::UpgradeRaceResolution));
     }
 
     #[test]
@@ -234,6 +302,7 @@ mod tests {
             session: 42,
             endpoint_type: EndpointProvider::Local,
             receiver_endpoint_type: EndpointProvider::Stun,
+            control: ControlHeader::default(),
         };
         let expected_upgrade_bytes: &[u8] = &[
             8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52, 17, 42, 0, 0, 0, 0,
@@ -250,6 +319,7 @@ mod tests {
             UpgradeDecisionMsg::decode(upgrade_bytes).expect("Failed to parse upgrade msg");
         assert_eq!(upgrade_msg.decision, Decision::Accepted);
         assert_eq!(upgrade_msg.session, 42);
+        assert_eq!(upgrade_msg.winning_session, None);
     }
 
     #[test]
@@ -271,6 +341,8 @@ mod tests {
         let upgrade_msg = UpgradeDecisionMsg {
             decision: crate::Decision::RejectedDueToUnknownSession,
             session: 42,
+            winning_session: None,
+            control: ControlHeader::default(),
         };
         let expected_upgrade_bytes: &[u8] = &[10, 8, 1, 17, 42, 0, 0, 0, 0, 0, 0, 0];
 
@@ -282,4 +354,25 @@ mod tests {
             UpgradeDecisionMsg::decode(&actual_upgrade_bytes).unwrap()
         );
     }
+
+    #[test]
+    fn encode_concurrent_upgrade_decision_packet() {
+        let upgrade_msg = UpgradeDecisionMsg {
+            decision: crate::Decision::RejectedDueToConcurrentUpgrade,
+            session: 42,
+            winning_session: Some(7),
+            control: ControlHeader::default(),
+        };
+        let expected_upgrade_bytes: &[u8] = &[
+            10, 8, 2, 17, 42, 0, 0, 0, 0, 0, 0, 0, 25, 7, 0, 0, 0, 0, 0, 0, 0,
+        ];
+
+        let actual_upgrade_bytes = upgrade_msg.clone().encode().unwrap();
+        assert_eq!(expected_upgrade_bytes, actual_upgrade_bytes);
+
+        assert_eq!(
+            upgrade_msg,
+            UpgradeDecisionMsg::decode(&actual_upgrade_bytes).unwrap()
+        );
+    }
 }
@@ -0,0 +1,118 @@
+use std::convert::TryInto;
+
+use crate::{
+    messages::wake::WakeOnLan, Codec, CodecError, CodecResult, ControlHeader, DowncastPacket,
+    PacketRelayed, PacketTypeRelayed, MAX_PACKET_SIZE,
+};
+
+use bytes::BufMut;
+use protobuf::Message;
+use telio_crypto::PublicKey;
+
+/// Request to wake up a sleeping node, sent to a node on the same LAN as the target which sends
+/// the Wake-on-LAN magic packet on behalf of the sender
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WakeOnLanMsg {
+    /// Public key of the node to be woken up
+    pub target: PublicKey,
+    /// Control protocol version and features of the sender
+    pub control: ControlHeader,
+}
+
+impl WakeOnLanMsg {
+    /// Returns new msg [`WakeOnLanMsg`].
+    pub fn new(target: PublicKey) -> Self {
+        Self {
+            target,
+            control: ControlHeader::local(),
+        }
+    }
+}
+
+impl Codec<PacketTypeRelayed> for WakeOnLanMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::WakeOnLan];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::WakeOnLan => {
+                let proto_wake =
+                    WakeOnLan::parse_from_bytes(bytes.get(1..).ok_or(CodecError::DecodeFailed)?)
+                        .map_err(|_| CodecError::DecodeFailed)?;
+                let target: [u8; telio_crypto::KEY_SIZE] = proto_wake
+                    .get_target()
+                    .try_into()
+                    .map_err(|_| CodecError::DecodeFailed)?;
+
+                Ok(Self {
+                    target: PublicKey(target),
+                    control: ControlHeader::from_proto(
+                        proto_wake.get_control_version(),
+                        proto_wake.get_control_features(),
+                    ),
+                })
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(MAX_PACKET_SIZE);
+        let mut msg = WakeOnLan::new();
+        msg.set_target(self.target.0.to_vec());
+        msg.set_control_version(self.control.version);
+        msg.set_control_features(self.control.features.bits());
+
+        bytes.put_u8(PacketTypeRelayed::WakeOnLan as u8);
+        msg.write_to_vec(&mut bytes)
+            .map_err(|_| CodecError::Encode)?;
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::WakeOnLan
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for WakeOnLanMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::WakeOnLan(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wake_on_lan_roundtrip() {
+        let packet = WakeOnLanMsg::new(PublicKey([7; telio_crypto::KEY_SIZE]));
+        let bytes = packet.clone().encode().unwrap();
+        assert_eq!(bytes[0], PacketTypeRelayed::WakeOnLan as u8);
+
+        let data = WakeOnLanMsg::decode(&bytes).expect("Failed to parse packet");
+        assert_eq!(data, packet);
+        assert_eq!(data.control, ControlHeader::local());
+    }
+
+    #[test]
+    fn wake_on_lan_with_truncated_key_fails_to_decode() {
+        // Field 1 with a 2 byte long target
+        let bytes = &[PacketTypeRelayed::WakeOnLan as u8, 10, 2, 1, 2];
+        assert_eq!(WakeOnLanMsg::decode(bytes), Err(CodecError::DecodeFailed));
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use telio_crypto::PublicKey;
+use telio_proxy::Mac1Key;
+
+fuzz_target!(|data: &[u8]| {
+    let key = Mac1Key::new(&PublicKey([7u8; 32]));
+    let _ = key.matches(data);
+});
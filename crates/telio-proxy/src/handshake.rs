@@ -0,0 +1,98 @@
+//! Identification of the peer a WireGuard handshake initiation is meant for
+//!
+//! The first MAC of the initiation is keyed by the public key of the responder, so it can be
+//! verified against the keys of the candidates without any secrets.
+
+use blake2::{
+    digest::{consts::U16, Mac},
+    Blake2s256, Blake2sMac, Digest,
+};
+use telio_crypto::PublicKey;
+
+const LABEL_MAC1: &[u8] = b"mac1----";
+const HANDSHAKE_INIT_TYPE: [u8; 4] = [1, 0, 0, 0];
+const HANDSHAKE_INIT_SIZE: usize = 148;
+const MAC1_OFFSET: usize = 116;
+const MAC_SIZE: usize = 16;
+
+/// Key of the first MAC of handshake initiations sent to a peer
+#[derive(Clone, Debug)]
+pub struct Mac1Key([u8; 32]);
+
+impl Mac1Key {
+    /// Derive the key from the public key of the peer
+    pub fn new(public_key: &PublicKey) -> Self {
+        Self(
+            Blake2s256::new()
+                .chain_update(LABEL_MAC1)
+                .chain_update(public_key.0)
+                .finalize()
+                .into(),
+        )
+    }
+
+    /// Check if the packet is a handshake initiation meant for the peer of this key
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        if packet.len() != HANDSHAKE_INIT_SIZE || !packet.starts_with(&HANDSHAKE_INIT_TYPE) {
+            return false;
+        }
+        let Ok(mut mac) = <Blake2sMac<U16> as Mac>::new_from_slice(&self.0) else {
+            return false;
+        };
+        mac.update(&packet[..MAC1_OFFSET]);
+        mac.verify_slice(&packet[MAC1_OFFSET..MAC1_OFFSET + MAC_SIZE])
+            .is_ok()
+    }
+}
+
+/// Find out which of the peers the handshake initiation is meant for
+pub(crate) fn recipient<'a>(
+    packet: &[u8],
+    mut peers: impl Iterator<Item = (&'a PublicKey, &'a Mac1Key)>,
+) -> Option<PublicKey> {
+    peers
+        .find(|(_, key)| key.matches(packet))
+        .map(|(public_key, _)| *public_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use neptun::noise::{Tunn, TunnResult};
+    use std::collections::HashMap;
+    use telio_crypto::SecretKey;
+    use x25519_dalek::{PublicKey as PublicKeyDalek, StaticSecret};
+
+    #[test]
+    fn handshake_recipient_is_found() {
+        let peers: HashMap<_, _> = (0..8)
+            .map(|_| {
+                let public_key = SecretKey::gen().public();
+                (public_key, Mac1Key::new(&public_key))
+            })
+            .collect();
+        let responder = *peers.keys().nth(3).unwrap();
+
+        let mut tunn = Tunn::new(
+            StaticSecret::from(SecretKey::gen().into_bytes()),
+            PublicKeyDalek::from(responder.0),
+            None,
+            None,
+            0,
+            None,
+        )
+        .unwrap();
+        let mut buf = [0u8; 256];
+        let TunnResult::WriteToNetwork(init) = tunn.format_handshake_initiation(&mut buf, false)
+        else {
+            panic!("Handshake initiation expected");
+        };
+
+        assert_eq!(recipient(init, peers.iter()), Some(responder));
+        // Only initiations can be attributed, anything else is ignored
+        assert_eq!(recipient(&init[..MAC1_OFFSET], peers.iter()), None);
+        let mut transport = init.to_vec();
+        transport[0] = 4;
+        assert_eq!(recipient(&transport, peers.iter()), None);
+    }
+}
@@ -1,5 +1,7 @@
 #![deny(missing_docs)]
 //! Proxy component acts as a middle layer between telio-wg and telio-relay (DERP)
 //! It does this by mapping UDP sockets with public keys
+mod handshake;
 mod proxy;
+pub use handshake::Mac1Key;
 pub use proxy::*;
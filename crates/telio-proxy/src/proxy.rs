@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use futures::future::{pending, select_all, FutureExt};
+use socket2::SockRef;
 use std::{
     collections::{HashMap, HashSet},
     io::ErrorKind,
@@ -18,17 +19,26 @@ use telio_task::{
     },
     task_exec, Runtime, RuntimeExt, Task, WaitResponse,
 };
-use tokio::{net::UdpSocket, sync::mpsc::error::SendTimeoutError};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::error::SendTimeoutError,
+    time::{interval, Instant, Interval},
+};
 
+use crate::handshake::{self, Mac1Key};
 use telio_utils::{
+    chaos,
     exponential_backoff::{Backoff, ExponentialBackoff, ExponentialBackoffBounds},
     telio_log_debug, telio_log_error, telio_log_info, telio_log_warn, PinnedSleep,
 };
 
 type SocketMap = HashMap<PublicKey, Arc<UdpSocket>>;
 type SocketMuteMap = HashMap<PublicKey, (Arc<UdpSocket>, Option<PinnedSleep<PublicKey>>)>;
+/// Socket opened for a peer, `None` once the peer goes dormant
+type SocketUpdate = (PublicKey, Option<Arc<UdpSocket>>);
 
 const SOCK_BUF_SZ: usize = 212992;
+const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(10);
 
 #[derive(Debug, thiserror::Error)]
 /// Custom `UdpProxy` error
@@ -92,6 +102,10 @@ pub trait Proxy {
     async fn get_endpoint_map(&self) -> Result<EndpointMap, Error>;
     /// Mute peer for duration (or unmute if duration is None)
     async fn mute_peer(&self, pk: PublicKey, dur: Option<Duration>) -> Result<(), Error>;
+    /// Get peers without their own socket, whose endpoint only wakes them up
+    async fn get_dormant_peers(&self) -> Result<HashSet<PublicKey>, Error>;
+    /// Get the number of bytes of the packet buffer and of the socket buffers held by the kernel
+    async fn get_memory_usage(&self) -> Result<usize, Error>;
 }
 
 /// `UdpProxy` struct wrapping its state in Task runtime
@@ -122,13 +136,37 @@ pub struct Config {
     pub wg_port: Option<u16>,
     /// tracked WG peers
     pub peers: HashSet<PublicKey>,
+    /// Open peer sockets lazily and close them after being idle for this long.
+    ///
+    /// Until then peers are dormant and share a single wake-up endpoint, which opens the socket
+    /// of the peer once WG sends a handshake initiation to it. `None` opens all sockets upfront.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Peers waiting for the first traffic to get their own socket
+struct Dormant {
+    wake: Arc<UdpSocket>,
+    peers: HashMap<PublicKey, Mac1Key>,
 }
 
 struct StateIngress {
     sockets: SocketMap,
+    dormant: Option<Dormant>,
     output: Tx<(PublicKey, DataMsg)>,
     read_buf: Box<[u8; MAX_PACKET_SIZE]>,
-    updated_socket: Rx<(PublicKey, Arc<UdpSocket>)>,
+    updated_socket: Rx<SocketUpdate>,
+    activated_socket: Tx<(PublicKey, Arc<UdpSocket>)>,
+    /// Peers WG sends to through their socket, reported to egress to keep the socket open
+    active_peer: Tx<PublicKey>,
+    last_reported: HashMap<PublicKey, Instant>,
+    idle_timeout: Option<Duration>,
+}
+
+/// Result of ingress configuration, to be mirrored by egress
+struct IngressConfig {
+    sockets: SocketMap,
+    dormant: HashSet<PublicKey>,
+    wake_addr: Option<SocketAddr>,
 }
 
 struct StateEgress {
@@ -136,9 +174,16 @@ struct StateEgress {
     input: Rx<(PublicKey, DataMsg)>,
     wg_addr: Option<SocketAddr>,
     conn_state: Result<(), std::io::ErrorKind>,
-    update_socket: Tx<(PublicKey, Arc<UdpSocket>)>,
+    update_socket: Tx<SocketUpdate>,
+    activated_socket: Rx<(PublicKey, Arc<UdpSocket>)>,
+    active_peer: Rx<PublicKey>,
     peer_socket_backoffs: HashMap<PublicKey, (ExponentialBackoff, PinnedSleep<PublicKey>)>,
     replaced_sockets: EndpointMap,
+    dormant: HashSet<PublicKey>,
+    wake_addr: Option<SocketAddr>,
+    idle_timeout: Option<Duration>,
+    last_active: HashMap<PublicKey, Instant>,
+    idle_check: Interval,
 }
 
 impl UdpProxy {
@@ -148,13 +193,26 @@ impl UdpProxy {
             tx: update_socket,
             rx: updated_socket,
         } = Chan::new(16);
+        let Chan {
+            tx: activated_socket_tx,
+            rx: activated_socket_rx,
+        } = Chan::new(16);
+        let Chan {
+            tx: active_peer_tx,
+            rx: active_peer_rx,
+        } = Chan::new(64);
 
         UdpProxy {
             task_ingress: Task::start(StateIngress {
                 sockets: HashMap::new(),
+                dormant: None,
                 output: io.relay.tx,
                 read_buf: Box::new([0u8; MAX_PACKET_SIZE]),
                 updated_socket,
+                activated_socket: activated_socket_tx,
+                active_peer: active_peer_tx,
+                last_reported: HashMap::new(),
+                idle_timeout: None,
             }),
             task_egress: Task::start(StateEgress {
                 sockets: HashMap::new(),
@@ -162,8 +220,15 @@ impl UdpProxy {
                 wg_addr: None,
                 conn_state: Err(ErrorKind::NotConnected),
                 update_socket,
+                activated_socket: activated_socket_rx,
+                active_peer: active_peer_rx,
                 peer_socket_backoffs: HashMap::new(),
                 replaced_sockets: HashMap::new(),
+                dormant: HashSet::new(),
+                wake_addr: None,
+                idle_timeout: None,
+                last_active: HashMap::new(),
+                idle_check: interval(IDLE_CHECK_PERIOD),
             }),
         }
     }
@@ -171,15 +236,16 @@ impl UdpProxy {
     /// Update configuration for the background task
     pub async fn configure(&self, config: Config) -> Result<(), Error> {
         let port = config.wg_port;
+        let idle_timeout = config.idle_timeout;
 
-        let sockets = task_exec!(&self.task_ingress, async move |state| {
+        let ingress = task_exec!(&self.task_ingress, async move |state| {
             Ok(state.configure(config).await)
         })
         .await??;
 
         task_exec!(&self.task_egress, async move |state| {
             state.reset_socket_limit().await;
-            Ok(state.configure(sockets, port).await)
+            Ok(state.configure(ingress, port, idle_timeout).await)
         })
         .await?
     }
@@ -196,6 +262,20 @@ impl UdpProxy {
         let _ = self.task_ingress.stop().await.resume_unwind();
     }
 
+    /// Close the socket of the peer and drop everything kept for it, until it is configured again
+    pub async fn remove_peer(&self, pk: PublicKey) {
+        let _ = task_exec!(&self.task_ingress, async move |state| {
+            state.remove_peer(&pk);
+            Ok(())
+        })
+        .await;
+        let _ = task_exec!(&self.task_egress, async move |state| {
+            state.remove_peer(&pk);
+            Ok(())
+        })
+        .await;
+    }
+
     /// Notify proxy about network change
     pub async fn on_network_change(&self) {
         let _ = task_exec!(&self.task_egress, async move |state| {
@@ -224,10 +304,35 @@ impl Proxy for UdpProxy {
         })
         .await?
     }
+
+    async fn get_dormant_peers(&self) -> Result<HashSet<PublicKey>, Error> {
+        task_exec!(&self.task_egress, async move |state| Ok(Ok(state
+            .dormant
+            .clone())))
+        .await?
+    }
+
+    async fn get_memory_usage(&self) -> Result<usize, Error> {
+        task_exec!(&self.task_ingress, async move |state| Ok(Ok(
+            state.memory_usage()
+        )))
+        .await?
+    }
+}
+
+async fn new_peer_socket() -> Result<UdpSocket, std::io::Error> {
+    SocketPool::new_udp(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        Some(UdpParams(SocketBufSizes {
+            rx_buf_size: Some(SOCK_BUF_SZ),
+            tx_buf_size: Some(SOCK_BUF_SZ),
+        })),
+    )
+    .await
 }
 
 impl StateIngress {
-    async fn configure(&mut self, config: Config) -> Result<SocketMap, Error> {
+    async fn configure(&mut self, config: Config) -> Result<IngressConfig, Error> {
         telio_log_debug!("Configuring ingress proxy: {:?}", &config);
 
         let current: HashSet<_> = self.sockets.keys().copied().collect();
@@ -236,31 +341,150 @@ impl StateIngress {
         for peer in del.iter() {
             self.sockets.remove(peer);
         }
+        self.last_reported.retain(|pk, _| config.peers.contains(pk));
+        self.idle_timeout = config.idle_timeout;
 
         let add = &config.peers - &current;
-        for peer in add.into_iter() {
-            let socket = SocketPool::new_udp(
-                SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
-                Some(UdpParams(SocketBufSizes {
-                    rx_buf_size: Some(SOCK_BUF_SZ),
-                    tx_buf_size: Some(SOCK_BUF_SZ),
-                })),
-            )
-            .await?;
-
-            let socket = Arc::new(socket);
-            self.sockets.insert(peer, socket);
+        if config.idle_timeout.is_some() {
+            let dormant = match self.dormant.take() {
+                Some(dormant) => dormant,
+                None => Dormant {
+                    wake: Arc::new(new_peer_socket().await?),
+                    peers: HashMap::new(),
+                },
+            };
+            let dormant = self.dormant.insert(dormant);
+            dormant.peers.retain(|pk, _| add.contains(pk));
+            for peer in add {
+                dormant
+                    .peers
+                    .entry(peer)
+                    .or_insert_with(|| Mac1Key::new(&peer));
+            }
+        } else {
+            self.dormant = None;
+            for peer in add.into_iter() {
+                self.sockets
+                    .insert(peer, Arc::new(new_peer_socket().await?));
+            }
+        }
+
+        Ok(IngressConfig {
+            sockets: self.sockets.clone(),
+            dormant: self
+                .dormant
+                .iter()
+                .flat_map(|d| d.peers.keys().copied())
+                .collect(),
+            wake_addr: self
+                .dormant
+                .as_ref()
+                .map(|d| d.wake.local_addr())
+                .transpose()?,
+        })
+    }
+
+    /// Read buffer plus the rx and tx buffers the kernel reserved for each open socket
+    fn memory_usage(&self) -> usize {
+        let sockets = self
+            .sockets
+            .values()
+            .chain(self.dormant.as_ref().map(|d| &d.wake));
+        let socket_buffers: usize = sockets
+            .map(|socket| {
+                let socket = SockRef::from(socket.as_ref());
+                socket.recv_buffer_size().unwrap_or_default()
+                    + socket.send_buffer_size().unwrap_or_default()
+            })
+            .sum();
+        self.read_buf.len() + socket_buffers
+    }
+
+    /// Give the dormant peer its own socket once WG tries to reach it
+    async fn wake_up(&mut self, packet: &[u8]) -> Result<(), Error> {
+        let Some(dormant) = &mut self.dormant else {
+            return Ok(());
+        };
+        let Some(pk) = handshake::recipient(packet, dormant.peers.iter()) else {
+            return Ok(());
+        };
+        dormant.peers.remove(&pk);
+
+        telio_log_debug!("Waking up dormant peer {:?}", pk);
+        let socket = Arc::new(new_peer_socket().await?);
+        self.sockets.insert(pk, socket.clone());
+        if self.activated_socket.send((pk, socket)).await.is_err() {
+            telio_log_warn!("Failed to sync up activated proxy socket due to closed channel");
+        }
+        self.output.send((pk, DataMsg::new(packet))).await?;
+        Ok(())
+    }
+
+    fn remove_peer(&mut self, pk: &PublicKey) {
+        self.sockets.remove(pk);
+        self.last_reported.remove(pk);
+        if let Some(dormant) = &mut self.dormant {
+            dormant.peers.remove(pk);
+        }
+    }
+
+    /// Keep the socket of the peer open while WG is sending to it, even if nothing comes back
+    fn report_active(&mut self, pk: PublicKey) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        // Egress is told only a few times per idle timeout, which is all it needs
+        let period = IDLE_CHECK_PERIOD.min(idle_timeout / 2);
+        let now = Instant::now();
+        let recently = self
+            .last_reported
+            .get(&pk)
+            .is_some_and(|at| now.duration_since(*at) < period);
+        if !recently && self.active_peer.try_send(pk).is_ok() {
+            self.last_reported.insert(pk, now);
         }
+    }
 
-        Ok(self.sockets.clone())
+    fn update_socket(&mut self, pk: PublicKey, socket: Option<Arc<UdpSocket>>) {
+        match socket {
+            Some(socket) => {
+                telio_log_debug!("Updating socket mapping for {pk} to {socket:?}");
+                if let Some(dormant) = &mut self.dormant {
+                    dormant.peers.remove(&pk);
+                }
+                self.sockets.insert(pk, socket);
+            }
+            None => {
+                telio_log_debug!("Peer {pk} is idle, closing its socket");
+                self.last_reported.remove(&pk);
+                if let (Some(dormant), Some(_)) = (&mut self.dormant, self.sockets.remove(&pk)) {
+                    dormant.peers.insert(pk, Mac1Key::new(&pk));
+                }
+            }
+        }
     }
 }
 
 impl StateEgress {
-    async fn configure(&mut self, sockets: SocketMap, wg_port: Option<u16>) -> Result<(), Error> {
+    async fn configure(
+        &mut self,
+        ingress: IngressConfig,
+        wg_port: Option<u16>,
+        idle_timeout: Option<Duration>,
+    ) -> Result<(), Error> {
         telio_log_debug!("Configuring egress proxy with wg port {:?}", wg_port);
 
         self.wg_addr = wg_port.map(|p| (Ipv4Addr::LOCALHOST, p).into());
+        self.dormant = ingress.dormant;
+        self.wake_addr = ingress.wake_addr;
+        self.idle_timeout = idle_timeout;
+        let sockets = ingress.sockets;
+        self.last_active.retain(|pk, _| sockets.contains_key(pk));
+        if idle_timeout.is_some() {
+            for pk in sockets.keys() {
+                self.last_active.entry(*pk).or_insert_with(Instant::now);
+            }
+        }
 
         self.sockets.retain(|&pk, _| sockets.contains_key(&pk));
         for (pk, new_socket) in sockets {
@@ -275,6 +499,14 @@ impl StateEgress {
         Ok(())
     }
 
+    fn remove_peer(&mut self, pk: &PublicKey) {
+        self.sockets.remove(pk);
+        self.dormant.remove(pk);
+        self.last_active.remove(pk);
+        self.peer_socket_backoffs.remove(pk);
+        self.replaced_sockets.remove(pk);
+    }
+
     async fn reset_socket_limit(&mut self) {
         self.peer_socket_backoffs.clear();
         self.replaced_sockets.clear();
@@ -288,6 +520,11 @@ impl StateEgress {
             peer_endpoints.insert(0, sock.local_addr()?);
             map.insert(*key, peer_endpoints);
         }
+        if let Some(wake_addr) = self.wake_addr {
+            for key in self.dormant.iter() {
+                map.insert(*key, vec![wake_addr]);
+            }
+        }
 
         Ok(map)
     }
@@ -313,8 +550,28 @@ impl StateEgress {
     }
 
     async fn send_outbound_data(&mut self, pk: PublicKey, msg: DataMsg) {
+        if self.dormant.remove(&pk) {
+            telio_log_debug!("Waking up dormant peer {:?} on relayed traffic", pk);
+            match new_peer_socket().await {
+                Ok(socket) => self.activate(pk, Arc::new(socket), true).await,
+                Err(err) => telio_log_error!("Cannot open socket. {}", err),
+            }
+        }
+        if self.idle_timeout.is_some() {
+            self.last_active.insert(pk, Instant::now());
+        }
+
         match (self.sockets.get(&pk), self.wg_addr) {
             (Some((socket, None)), Some(wg_addr)) => {
+                let delay = chaos::packet_delay(&pk.0);
+                if !delay.is_zero() {
+                    let socket = socket.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = socket.send_to(msg.get_payload(), wg_addr).await;
+                    });
+                    return;
+                }
                 match socket.send_to(msg.get_payload(), wg_addr).await {
                     Ok(_) => {
                         if self.conn_state.is_err() {
@@ -376,16 +633,49 @@ impl StateEgress {
         }
     }
 
+    /// Start using the socket for a dormant peer, `sync` tells ingress about it
+    async fn activate(&mut self, pk: PublicKey, socket: Arc<UdpSocket>, sync: bool) {
+        if !self.dormant.remove(&pk) && !sync {
+            // Relayed traffic was first, ingress is already switching to the socket of egress
+            return;
+        }
+        self.last_active.insert(pk, Instant::now());
+        if sync {
+            if let Err(e) = self.update_socket.send((pk, Some(socket.clone()))).await {
+                telio_log_warn!("Failed to sync up proxy sockets due to closed channel: {e:?}");
+            }
+        }
+        self.sockets.insert(pk, (socket, None));
+    }
+
+    /// Put the peers which have not received anything for a while back to sleep
+    async fn close_idle_sockets(&mut self) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        let now = Instant::now();
+        let idle: Vec<_> = self
+            .last_active
+            .iter()
+            .filter(|(_, last_active)| now.duration_since(**last_active) >= idle_timeout)
+            .map(|(pk, _)| *pk)
+            .collect();
+
+        for pk in idle {
+            self.last_active.remove(&pk);
+            self.replaced_sockets.remove(&pk);
+            self.peer_socket_backoffs.remove(&pk);
+            if self.sockets.remove(&pk).is_some() {
+                self.dormant.insert(pk);
+                if let Err(e) = self.update_socket.send((pk, None)).await {
+                    telio_log_warn!("Failed to sync up proxy sockets due to closed channel: {e:?}");
+                }
+            }
+        }
+    }
+
     async fn replace_socket(&mut self, pk: PublicKey) {
-        let socket = match SocketPool::new_udp(
-            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
-            Some(UdpParams(SocketBufSizes {
-                rx_buf_size: Some(SOCK_BUF_SZ),
-                tx_buf_size: Some(SOCK_BUF_SZ),
-            })),
-        )
-        .await
-        {
+        let socket = match new_peer_socket().await {
             Ok(skt) => skt,
             Err(err) => {
                 telio_log_error!("Cannot open socket. {}", err);
@@ -395,7 +685,7 @@ impl StateEgress {
 
         let socket = Arc::new(socket);
 
-        if let Err(e) = self.update_socket.send((pk, socket.clone())).await {
+        if let Err(e) = self.update_socket.send((pk, Some(socket.clone()))).await {
             // May happen when they are being closed, but is not critical
             telio_log_warn!("Failed to sync up proxy sockets due to closed channel: {e:?}");
         }
@@ -428,10 +718,25 @@ impl Runtime for StateIngress {
     ///
     /// Use [RuntimeExt] to create valid responses in an easier manner.
     async fn wait(&mut self) -> WaitResponse<'_, Self::Err> {
-        if self.sockets.is_empty() {
+        if self.sockets.is_empty() && self.dormant.is_none() {
             return Self::sleep_forever().await;
         }
 
+        let wake = match &self.dormant {
+            Some(dormant) => {
+                let wake = dormant.wake.clone();
+                async move {
+                    if wake.readable().await.is_ok() {
+                        wake
+                    } else {
+                        pending().await
+                    }
+                }
+                .right_future()
+            }
+            None => pending().left_future(),
+        };
+
         let futures = self
             .sockets
             .clone()
@@ -460,7 +765,17 @@ impl Runtime for StateIngress {
                         } else {
                             return Self::error(());
                         });
-                        let _ = permit.send((pk, msg));
+                        self.report_active(pk);
+                        let delay = chaos::packet_delay(&pk.0);
+                        if delay.is_zero() {
+                            let _ = permit.send((pk, msg));
+                        } else {
+                            let output = self.output.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                let _ = output.send((pk, msg)).await;
+                            });
+                        }
                     }
                     Err(e) if e.kind() == ErrorKind::WouldBlock => {
                         // Blocking error is not an issue here
@@ -470,10 +785,22 @@ impl Runtime for StateIngress {
                     }
                 }
             }
+            wake = wake => {
+                match wake.try_recv(self.read_buf.as_mut_slice()) {
+                    Ok(n) => {
+                        let packet = self.read_buf.get(..n).unwrap_or_default().to_vec();
+                        if let Err(e) = self.wake_up(&packet).await {
+                            telio_log_warn!("StateIngress: failed to wake up peer: {e}");
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+                    Err(e) => {
+                        telio_log_warn!("StateIngress: received error {e:?} on wake socket");
+                    }
+                }
+            }
             Some((pk, sock)) = self.updated_socket.recv() => {
-                telio_log_debug!("Updating socket mapping for {pk} to {sock:?}");
-
-                let _ = self.sockets.insert(pk, sock);
+                self.update_socket(pk, sock);
             }
             else => {
                 telio_log_warn!("StateIngress: no events to wait on ...");
@@ -496,7 +823,7 @@ impl Runtime for StateEgress {
     ///
     /// Use [RuntimeExt] to create valid responses in an easier manner.
     async fn wait(&mut self) -> WaitResponse<'_, Self::Err> {
-        if self.sockets.is_empty() {
+        if self.sockets.is_empty() && self.dormant.is_empty() {
             return Self::sleep_forever().await;
         }
 
@@ -524,10 +851,27 @@ impl Runtime for StateEgress {
             select_all(sockets_to_update).right_future()
         };
 
+        let idle_check = if self.idle_timeout.is_some() {
+            self.idle_check.tick().right_future()
+        } else {
+            futures::future::pending().left_future()
+        };
+
         tokio::select! {
             Some((pk, msg)) = self.input.recv() => {
                 self.send_outbound_data(pk, msg).await;
             },
+            Some((pk, socket)) = self.activated_socket.recv() => {
+                self.activate(pk, socket, false).await;
+            },
+            Some(pk) = self.active_peer.recv() => {
+                if self.idle_timeout.is_some() && self.sockets.contains_key(&pk) {
+                    self.last_active.insert(pk, Instant::now());
+                }
+            },
+            _ = idle_check => {
+                self.close_idle_sockets().await;
+            },
             (pk, _, _) = mute => {
                 if let Some((_, muted)) = self.sockets.get_mut(&pk) {
                     telio_log_debug!("Unmuting peer: {:?}", pk);
@@ -564,6 +908,19 @@ mod tests {
         ts.stop().await;
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_memory_usage_grows_with_sockets() {
+        let mut ts = TestSystem::start().await;
+        let idle = ts.proxy.get_memory_usage().await.unwrap();
+        assert_eq!(idle, MAX_PACKET_SIZE);
+
+        ts.create_peers(2).await;
+        // The buffer sizes reserved for the sockets depend on the kernel
+        assert!(ts.proxy.get_memory_usage().await.unwrap() > idle);
+
+        ts.stop().await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_graceful_shutdown() {
         use telio_crypto::SecretKey;
@@ -583,6 +940,7 @@ mod tests {
                 .configure(Config {
                     wg_port: Some(wg.addr().port()),
                     peers: pks.iter().copied().collect(),
+                    idle_timeout: None,
                 })
                 .await
                 .expect("UdpProxy::configure() failed");
@@ -653,9 +1011,48 @@ mod tests {
         ts.stop().await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lazy_sockets() {
+        let mut ts = TestSystem::start().await;
+
+        ts.test_lazy_sockets().await;
+
+        ts.stop().await;
+    }
+
+    #[tokio::test]
+    async fn test_removed_peer_is_forgotten() {
+        let ts = TestSystem::start().await;
+        let pk = telio_crypto::SecretKey::gen().public();
+        ts.proxy
+            .configure(Config {
+                wg_port: Some(ts.wg.addr().port()),
+                peers: HashSet::from([pk]),
+                idle_timeout: None,
+            })
+            .await
+            .unwrap();
+        assert!(ts.proxy.get_endpoint_map().await.unwrap().contains_key(&pk));
+
+        ts.proxy.remove_peer(pk).await;
+        assert!(ts.proxy.get_endpoint_map().await.unwrap().is_empty());
+
+        ts.stop().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sending_keeps_lazy_socket_open() {
+        let mut ts = TestSystem::start().await;
+
+        ts.test_sending_keeps_lazy_socket_open().await;
+
+        ts.stop().await;
+    }
+
     /// Helper utils for tests
     mod helper {
         use futures::future::join_all;
+        use neptun::noise::{Tunn, TunnResult};
         use rand::seq::SliceRandom;
         use std::{panic, thread, time::Duration};
         use tokio::{
@@ -664,6 +1061,7 @@ mod tests {
         };
 
         use telio_crypto::SecretKey;
+        use x25519_dalek::{PublicKey as PublicKeyDalek, StaticSecret};
 
         use super::*;
 
@@ -869,6 +1267,127 @@ mod tests {
                 Ok(())
             }
 
+            pub async fn test_lazy_sockets(&mut self) {
+                let pks: Vec<_> = (0..2).map(|_| SecretKey::gen().public()).collect();
+                self.proxy
+                    .configure(Config {
+                        wg_port: Some(self.wg.addr().port()),
+                        peers: pks.iter().copied().collect(),
+                        idle_timeout: Some(Duration::from_millis(100)),
+                    })
+                    .await
+                    .expect("UdpProxy::configure() failed");
+
+                // All peers share the wake-up endpoint until the first traffic
+                let endpoints = self.proxy.get_endpoint_map().await.unwrap();
+                let wake = endpoints[&pks[0]].clone();
+                assert_eq!(endpoints[&pks[1]], wake);
+                assert_eq!(
+                    self.proxy.get_dormant_peers().await.unwrap(),
+                    pks.iter().copied().collect()
+                );
+
+                // Relayed traffic opens the socket
+                self.relay.send(pks[0], DataMsg::new(b"relayed")).await;
+                let mut buf = [0u8; 256];
+                let (size, from) =
+                    timeout(Duration::from_secs(1), self.wg.sock.recv_from(&mut buf))
+                        .await
+                        .expect("wg recv timeout")
+                        .expect("failed to recv from wg");
+                assert_eq!(&buf[..size], b"relayed");
+                assert_eq!(
+                    self.proxy.get_endpoint_map().await.unwrap()[&pks[0]],
+                    vec![from]
+                );
+
+                // Handshake initiation sent by WG to the wake-up endpoint opens the socket too
+                let mut tunn = Tunn::new(
+                    StaticSecret::from(SecretKey::gen().into_bytes()),
+                    PublicKeyDalek::from(pks[1].0),
+                    None,
+                    None,
+                    0,
+                    None,
+                )
+                .unwrap();
+                let init = match tunn.format_handshake_initiation(&mut buf, false) {
+                    TunnResult::WriteToNetwork(init) => init.to_vec(),
+                    _ => panic!("Handshake initiation expected"),
+                };
+                self.wg.sock.send_to(&init, wake[0]).await.unwrap();
+                self.relay
+                    .expect_recv(&[(pks[1], DataMsg::new(&init))])
+                    .await;
+                timeout(Duration::from_secs(1), async {
+                    while !self.proxy.get_dormant_peers().await.unwrap().is_empty() {
+                        time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .expect("Peer was not woken up");
+
+                self.wg.peers = self.proxy.get_endpoint_map().await.unwrap();
+                assert_ne!(self.wg.peers[&pks[1]], wake);
+                self.wg.send(pks[1], b"open").await;
+                self.relay
+                    .expect_recv(&[(pks[1], DataMsg::new(b"open"))])
+                    .await;
+
+                // Idle peers go back to sleep
+                time::sleep(Duration::from_millis(200)).await;
+                task_exec!(&self.proxy.task_egress, async move |state| {
+                    state.close_idle_sockets().await;
+                    Ok(())
+                })
+                .await
+                .unwrap();
+                let endpoints = self.proxy.get_endpoint_map().await.unwrap();
+                assert!(pks.iter().all(|pk| endpoints[pk] == wake));
+                assert_eq!(
+                    self.proxy.get_dormant_peers().await.unwrap(),
+                    pks.iter().copied().collect()
+                );
+
+                self.clear_peers();
+            }
+
+            pub async fn test_sending_keeps_lazy_socket_open(&mut self) {
+                let pk = SecretKey::gen().public();
+                self.proxy
+                    .configure(Config {
+                        wg_port: Some(self.wg.addr().port()),
+                        peers: HashSet::from([pk]),
+                        idle_timeout: Some(Duration::from_millis(300)),
+                    })
+                    .await
+                    .expect("UdpProxy::configure() failed");
+
+                self.relay.send(pk, DataMsg::new(b"relayed")).await;
+                let mut buf = [0u8; 256];
+                timeout(Duration::from_secs(1), self.wg.sock.recv_from(&mut buf))
+                    .await
+                    .expect("wg recv timeout")
+                    .expect("failed to recv from wg");
+                self.wg.peers = self.proxy.get_endpoint_map().await.unwrap();
+
+                // Nothing is relayed back, yet the socket stays open while WG sends through it
+                for _ in 0..12 {
+                    self.wg.send(pk, b"out").await;
+                    self.relay.expect_recv(&[(pk, DataMsg::new(b"out"))]).await;
+                    time::sleep(Duration::from_millis(50)).await;
+                }
+                task_exec!(&self.proxy.task_egress, async move |state| {
+                    state.close_idle_sockets().await;
+                    Ok(())
+                })
+                .await
+                .unwrap();
+                assert!(self.proxy.get_dormant_peers().await.unwrap().is_empty());
+
+                self.clear_peers();
+            }
+
             pub async fn send_to_wg_via_relay(
                 &mut self,
                 peers: usize,
@@ -901,6 +1420,7 @@ mod tests {
                     .configure(Config {
                         wg_port: Some(self.wg.addr().port()),
                         peers: pks.iter().copied().collect(),
+                        idle_timeout: None,
                     })
                     .await
                     .expect("UdpProxy::configure() failed");
@@ -8,17 +8,21 @@
 //! until first connection is made. For other configuration values, see `Config` description
 
 pub mod http;
+pub mod presence;
+pub mod probes;
 pub mod proto;
+pub mod queue;
 
 use async_trait::async_trait;
 use futures::{future::select_all, Future};
 use generic_array::typenum::Unsigned;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 use telio_crypto::{PublicKey, SecretKey};
-use telio_model::config::{DerpAnalyticsEvent, RelayConnectionChangeReason};
+use telio_model::config::{DerpAnalyticsEvent, PeerCapability, RelayConnectionChangeReason};
 use telio_model::{
     config::{RelayState, Server},
     features::FeatureDerp,
@@ -26,18 +30,21 @@ use telio_model::{
 #[mockall_double::double]
 use telio_nurse::aggregator::ConnectivityDataAggregator;
 use telio_proto::{
-    Codec, DerpPollRequestMsg, PacketControl, PacketRelayed, PacketTypeRelayed, PeersStatesMap,
-    Session,
+    Codec, ControlHeader, DerpPollRequestMsg, PacketControl, PacketRelayed, PacketTypeRelayed,
+    PeersStatesMap, Session,
 };
 use telio_sockets::SocketPool;
 use telio_task::io::{wait_for_tx, Chan};
 use telio_task::{io::mc_chan::Tx, task_exec, BoxAction, Runtime, Task};
 use telio_utils::{
-    telio_err_with_log, telio_log_debug, telio_log_error, telio_log_info, telio_log_trace,
+    chaos, telio_err_with_log, telio_log_debug, telio_log_error, telio_log_info, telio_log_trace,
     telio_log_warn,
 };
 use tokio::sync::mpsc::OwnedPermit;
-use tokio::{task::JoinHandle, time::sleep};
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, sleep_until, Instant},
+};
 
 use crypto_box::aead::{AeadCore, Error, Nonce, Payload};
 use telio_crypto::chachabox::ChaChaBox;
@@ -48,7 +55,9 @@ use smart_default::SmartDefault;
 
 use self::{http::connect_http_and_start, http::DerpConnection};
 
+pub use self::probes::{PathProbesConfig, PathStats};
 pub use self::proto::Error as DerpError;
+pub use self::queue::DroppedPackets;
 
 /// Helper container structure for specific server ordering
 #[derive(Clone, Debug, Default)]
@@ -81,6 +90,14 @@ impl SortedServers {
         self.servers.contains(server)
     }
 
+    /// Move the avoided servers to the end of the list, keeping them as the last resort
+    fn deprioritize(&mut self, avoided: impl Fn(&Server) -> bool) {
+        let (mut servers, last): (Vec<_>, Vec<_>) =
+            self.servers.drain(..).partition(|server| !avoided(server));
+        servers.extend(last);
+        self.servers = servers;
+    }
+
     fn reset_server_index(&mut self) {
         self.current_server_num = 0;
     }
@@ -116,14 +133,42 @@ struct State {
     derp_poll_session: Session,
     /// Cache the result of derp polling
     remote_peers_states: PeersStatesMap,
+    /// Time of the last derp polling result, older presence signals are outdated by it
+    remote_peers_states_at: Option<Instant>,
     /// Connectivity data aggregator
     aggregator: Option<Arc<ConnectivityDataAggregator>>,
 
     last_disconnection_reason: RelayConnectionChangeReason,
 
     connecting: Option<JoinHandle<(Server, DerpConnection)>>,
+
+    /// Times at which established connections dropped unexpectedly, within the flapping window
+    connection_drops: VecDeque<Instant>,
+    /// Servers found overloaded by the probes, tried last until the given time
+    overloaded_servers: HashMap<SocketAddr, Instant>,
+
+    /// Relayed packets dropped by the previous connections
+    dropped_packets: DroppedPackets,
+
+    /// Time of the last packet relayed in either direction
+    last_relayed_at: Instant,
+    /// Connection was closed for being idle, and is not reconnected until needed
+    idle: bool,
+    /// Packet which woke the idle connection up, sent once connected again
+    woken_by: Option<(PublicKey, PacketRelayed)>,
+    /// No poll keepalives are sent while the device is suspended
+    suspended: bool,
+    /// Control headers of the last control messages received from the peers
+    peer_controls: HashMap<PublicKey, ControlHeader>,
 }
 
+/// Window in which repeated connection drops mark the relay connection as degraded
+const FLAPPING_WINDOW: Duration = Duration::from_secs(300);
+/// Number of connection drops within the window which mark the relay connection as degraded
+const FLAPPING_DROP_COUNT: usize = 3;
+/// For how long an overloaded server is tried only after all the others
+const OVERLOADED_SERVER_BACKOFF: Duration = Duration::from_secs(300);
+
 /// Keepalive values that help keeping Derp connection in conntrack alive,
 /// so server can send traffic after being silent for a while
 /// *derp_keepalive* is also used as an interval for retrieving remote peer states.
@@ -183,11 +228,110 @@ pub struct Config {
     pub server_keepalives: DerpKeepaliveConfig,
     /// Enable mechanism for turning off keepalive to offline peers
     pub enable_polling: bool,
+    /// Update the remote peer states on the presence signals of the server
+    pub enable_peer_presence: bool,
     /// Use Mozilla's root certificates instead of OS ones [default false]
     pub use_built_in_root_certificates: bool,
+    /// Close the connection after this long without relayed packets
+    pub idle_timeout: Option<Duration>,
+    /// Compress large control messages for the peers supporting it
+    pub enable_compression: bool,
+    /// Measure the delay variation of the relayed path, telling a distant server from an
+    /// overloaded one
+    pub path_probes: Option<PathProbesConfig>,
 }
 
 impl State {
+    /// Connect again the next time around, if the connection was closed for being idle
+    fn wake(&mut self) {
+        if self.idle {
+            telio_log_info!("({}) Waking up the idle DERP connection", Self::NAME);
+            self.idle = false;
+        }
+        self.last_relayed_at = Instant::now();
+    }
+
+    /// Remember that the established connection dropped, as opposed to being closed on purpose
+    fn record_connection_drop(&mut self) {
+        if self.conn.is_some() {
+            self.connection_drops.push_back(Instant::now());
+        }
+    }
+
+    /// Check if the connection dropped too many times recently to be considered stable
+    fn is_flapping(&mut self) -> bool {
+        let now = Instant::now();
+        while let Some(drop) = self.connection_drops.front() {
+            if now.saturating_duration_since(*drop) < FLAPPING_WINDOW {
+                break;
+            }
+            self.connection_drops.pop_front();
+        }
+        self.connection_drops.len() >= FLAPPING_DROP_COUNT
+    }
+
+    /// Delay of the relayed path, if it is probed
+    fn path_stats(&self) -> Option<(PathStats, PathProbesConfig)> {
+        let probes = self.config.as_ref()?.path_probes?;
+        Some((self.conn.as_ref()?.probes.stats()?, probes))
+    }
+
+    /// Report the connection as degraded while its server is overloaded, and as healthy again
+    /// once it is not overloaded and stops flapping
+    fn refresh_degraded_state(&mut self) {
+        let overloaded = self
+            .path_stats()
+            .filter(|(stats, probes)| stats.is_overloaded(probes.overloaded_jitter));
+        let degraded = overloaded.is_some() || self.is_flapping();
+        let Some(server) = self.server.as_mut() else {
+            return;
+        };
+        match (server.conn_state, overloaded) {
+            (RelayState::Connected, Some((stats, _))) => {
+                telio_log_warn!("({}) DERP server is overloaded: {:?}", Self::NAME, stats);
+                server.conn_state = RelayState::Degraded;
+            }
+            (RelayState::Degraded, _) if !degraded => {
+                telio_log_info!("({}) DERP connection is stable again", Self::NAME);
+                server.conn_state = RelayState::Connected;
+            }
+            _ => return,
+        }
+        let _ = self.event.send(Box::new(server.clone()));
+    }
+
+    /// Hand the delay of the relayed path over to analytics
+    async fn report_path_stats(&self) {
+        if let (Some(aggregator), Some(server), Some((stats, _))) =
+            (&self.aggregator, &self.server, self.path_stats())
+        {
+            aggregator
+                .report_relay_path(server_address(server), stats.rtt, stats.jitter)
+                .await;
+        }
+    }
+
+    /// Avoid the server while it is overloaded, returns whether there is another server to
+    /// fail over to
+    fn avoid_overloaded_server(&mut self) -> bool {
+        let now = Instant::now();
+        self.overloaded_servers.retain(|_, until| *until > now);
+
+        let overloaded = self
+            .path_stats()
+            .is_some_and(|(stats, probes)| stats.is_overloaded(probes.overloaded_jitter));
+        let (Some(server), Some(config), true) = (&self.server, &self.config, overloaded) else {
+            return false;
+        };
+        self.overloaded_servers
+            .insert(server_address(server), now + OVERLOADED_SERVER_BACKOFF);
+        config.servers.servers.iter().any(|server| {
+            !self
+                .overloaded_servers
+                .contains_key(&server_address(server))
+        })
+    }
+
     async fn disconnect(&mut self) {
         // Stop attempts to connect
         if let Some(c) = self.connecting.take() {
@@ -197,6 +341,11 @@ impl State {
         // Stop current connection
         if let Some(c) = self.conn.take() {
             c.stop();
+            let dropped = c.dropped.get();
+            if dropped != DroppedPackets::default() {
+                telio_log_info!("({}) Relayed packets dropped: {:?}", Self::NAME, dropped);
+            }
+            self.dropped_packets += dropped;
         }
         // kill server
         if let Some(mut server) = self.server.take() {
@@ -208,6 +357,10 @@ impl State {
     }
 
     fn start_connecting(&self, mut config: Config) -> JoinHandle<(Server, DerpConnection)> {
+        config.servers.deprioritize(|server| {
+            self.overloaded_servers
+                .contains_key(&server_address(server))
+        });
         let event = self.event.clone();
         let socket_pool = self.socket_pool.clone();
 
@@ -313,9 +466,18 @@ impl DerpRelay {
                 socket_pool,
                 derp_poll_session: 0,
                 remote_peers_states: HashMap::new(),
+                remote_peers_states_at: None,
+                peer_controls: HashMap::new(),
                 connecting: None,
                 last_disconnection_reason: RelayConnectionChangeReason::ConfigurationChange,
                 aggregator,
+                connection_drops: VecDeque::new(),
+                overloaded_servers: HashMap::new(),
+                dropped_packets: DroppedPackets::default(),
+                last_relayed_at: Instant::now(),
+                idle: false,
+                woken_by: None,
+                suspended: false,
             }),
         }
     }
@@ -329,6 +491,10 @@ impl DerpRelay {
             }
 
             s.config = config;
+            s.wake();
+            if let Some(config) = s.config.as_ref() {
+                s.peer_controls.retain(|pk, _| config.meshnet_peers.contains(pk));
+            }
 
             // Prepare new config
             if let Some(config) = s.config.as_mut() {
@@ -382,22 +548,74 @@ impl DerpRelay {
 
     /// Get newest information about remote peer states
     pub async fn get_remote_peer_states(&self) -> PeersStatesMap {
-        task_exec!(&self.task, async move |s| Ok(s.remote_peers_states.clone()))
-            .await
-            .ok()
-            .unwrap_or_default()
+        task_exec!(&self.task, async move |s| {
+            let mut states = s.remote_peers_states.clone();
+            let enable_peer_presence = s.config.as_ref().is_some_and(|c| c.enable_peer_presence);
+            if let Some(conn) = s.conn.as_ref().filter(|_| enable_peer_presence) {
+                conn.presence
+                    .merge_into(&mut states, s.remote_peers_states_at);
+            }
+            Ok(states)
+        })
+        .await
+        .ok()
+        .unwrap_or_default()
+    }
+
+    /// Get number of relayed packets dropped because either the server or the peers could not
+    /// keep up, since the relay was started
+    pub async fn get_dropped_packets(&self) -> DroppedPackets {
+        task_exec!(&self.task, async move |s| {
+            let mut dropped = s.dropped_packets;
+            if let Some(conn) = &s.conn {
+                dropped += conn.dropped.get();
+            }
+            Ok(dropped)
+        })
+        .await
+        .ok()
+        .unwrap_or_default()
+    }
+
+    /// Get number of bytes of relayed packets currently queued by the connection
+    pub async fn get_queued_bytes(&self) -> usize {
+        task_exec!(&self.task, async move |s| {
+            Ok(s.conn.as_ref().map_or(0, |conn| conn.queued.get()))
+        })
+        .await
+        .ok()
+        .unwrap_or_default()
     }
 
     /// Try reconnect
     pub async fn reconnect(&self) {
         let _ = task_exec!(&self.task, async move |s| {
             telio_log_info!("Explicit relay reconnect requested");
+            s.wake();
             s.disconnect().await;
             Ok(())
         })
         .await;
     }
 
+    /// Stop sending the poll keepalives while the device is suspended
+    pub async fn set_suspended(&self, suspended: bool) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.suspended = suspended;
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Connect again if the connection was closed for being idle
+    pub async fn wake(&self) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.wake();
+            Ok(())
+        })
+        .await;
+    }
+
     /// Stop relay
     pub async fn stop(self) {
         let _ = self.task.stop().await;
@@ -541,6 +759,67 @@ impl DerpRelay {
 }
 
 impl State {
+    /// Wait for a reason to connect again while the connection is idle.
+    ///
+    /// Any packet to the relayed peers wakes the connection up, the packet itself is kept and
+    /// sent once connected again, so control messages are not lost.
+    async fn wait_while_idle<F>(&mut self, update: F) -> Result<(), ()>
+    where
+        F: Future<Output = BoxAction<Self, Result<(), ()>>> + Send,
+    {
+        let Some(chan) = self.channel.as_mut() else {
+            return (update.await)(self).await;
+        };
+
+        tokio::select! {
+            msg = chan.rx.recv() => match msg {
+                Some((pk, msg)) => {
+                    telio_log_debug!(
+                        "({}) Outgoing {:?} to relayed peer {:?}",
+                        Self::NAME,
+                        msg.packet_type(),
+                        pk
+                    );
+                    self.wake();
+                    self.woken_by = Some((pk, msg));
+                }
+                None => {
+                    telio_log_debug!("({}) Relay channel closed while idle", Self::NAME);
+                    self.channel = None;
+                }
+            },
+            update = update => return update(self).await,
+        }
+        Ok(())
+    }
+
+    /// Send the packet which woke the idle connection up over the new connection
+    async fn send_woken_by(&mut self) {
+        let (Some((pk, msg)), Some(conn), Some(config)) = (
+            self.woken_by.take(),
+            self.conn.as_ref(),
+            self.config.as_ref(),
+        ) else {
+            return;
+        };
+        let Ok(permit) = conn.comms_relayed.tx.clone().try_reserve_owned() else {
+            telio_log_debug!(
+                "({}) Dropping {:?} to {:?}",
+                Self::NAME,
+                msg.packet_type(),
+                pk
+            );
+            return;
+        };
+        let compress = config.enable_compression
+            && self
+                .peer_controls
+                .get(&pk)
+                .is_some_and(|control| control.supports(PeerCapability::Compression));
+        Self::handle_outcoming_payload_relayed(permit, pk, msg, config, &mut self.rng, compress)
+            .await;
+    }
+
     /// handle traffic for |LocalNode -> Derp -> RemoteNode|
     async fn handle_outcoming_payload_relayed(
         permit: OwnedPermit<(PublicKey, Vec<u8>)>,
@@ -548,6 +827,7 @@ impl State {
         msg: PacketRelayed,
         config: &Config,
         rng: &mut StdRng,
+        compress: bool,
     ) {
         // TODO add custom task's log format macro
         telio_log_trace!(
@@ -556,7 +836,12 @@ impl State {
             pk,
             msg.packet_type()
         );
-        match msg.encode() {
+        let encoded = if compress {
+            msg.encode_compressed()
+        } else {
+            msg.encode()
+        };
+        match encoded {
             Ok(buf) => match DerpRelay::encrypt_if_needed(config.secret_key.clone(), pk, rng, &buf)
             {
                 Ok(cipher_text) => {
@@ -595,6 +880,7 @@ impl State {
         pk: PublicKey,
         buf: Vec<u8>,
         config: &Config,
+        peer_controls: &mut HashMap<PublicKey, ControlHeader>,
     ) {
         if config.meshnet_peers.contains(&pk) {
             match DerpRelay::decrypt_if_needed(config.secret_key.clone(), pk, &buf) {
@@ -607,6 +893,10 @@ impl State {
                             buf.len(),
                             msg.packet_type()
                         );
+                        // Also replaces the header of a peer downgraded to an older version
+                        if let Some(control) = msg.control_header() {
+                            peer_controls.insert(pk, control);
+                        }
                         permit.send((pk, msg));
                     }
                     Err(e) => {
@@ -671,6 +961,11 @@ impl State {
 }
 
 #[async_trait]
+/// Address the server is identified by in analytics and when it is avoided
+fn server_address(server: &Server) -> SocketAddr {
+    SocketAddr::new(server.ipv4.into(), server.relay_port)
+}
+
 impl Runtime for State {
     const NAME: &'static str = "DerpRelay";
 
@@ -708,6 +1003,8 @@ impl Runtime for State {
                 let derp_direct_read = c.comms_direct.rx.recv();
                 let conn_join = select_all([&mut c.join_sender, &mut c.join_receiver]);
                 let poll_timer_tick = c.poll_timer.tick();
+                let idle_timer =
+                    sleep_until(self.last_relayed_at + config.idle_timeout.unwrap_or_default());
 
                 tokio::select! {
                     // Connection returned, reconnect
@@ -719,12 +1016,19 @@ impl Runtime for State {
                             Ok(Err(err)) => err.into(),
                             _ => RelayConnectionChangeReason::ClientError,
                         };
+                        self.record_connection_drop();
                         self.disconnect().await;
                     },
                     // Received payload from upper relay, forward it to DERP stream
                     res = wait_for_tx(&c.comms_relayed.tx, upper_read) => match res {
                         Some((permit, Some((pk, msg)))) => {
-                            Self::handle_outcoming_payload_relayed(permit, pk, msg, config, &mut self.rng).await;
+                            self.last_relayed_at = Instant::now();
+                            let compress = config.enable_compression
+                                && self
+                                    .peer_controls
+                                    .get(&pk)
+                                    .is_some_and(|control| control.supports(PeerCapability::Compression));
+                            Self::handle_outcoming_payload_relayed(permit, pk, msg, config, &mut self.rng, compress).await;
                         },
                         Some((_, None)) => {
                             telio_log_debug!("Disconnecting from DERP server due to closed rx channel");
@@ -736,11 +1040,12 @@ impl Runtime for State {
                         None => {
                             telio_log_info!("Disconnecting from DERP server");
                             self.last_disconnection_reason = RelayConnectionChangeReason::ClientError;
+                            self.record_connection_drop();
                             self.disconnect().await;
                         }
                     },
                     // On tick send derp poll request to derp stream
-                    Some((permit, _)) = wait_for_tx(&c.comms_direct.tx, poll_timer_tick) => {
+                    Some((permit, _)) = wait_for_tx(&c.comms_direct.tx, poll_timer_tick), if !self.suspended => {
                         if config.enable_polling || config.server_keepalives.poll_keepalive {
                             self.derp_poll_session = self.derp_poll_session.wrapping_add(1);
                             telio_log_debug!("Sending DerpPollRequest with session {}", self.derp_poll_session);
@@ -748,16 +1053,43 @@ impl Runtime for State {
                                 self.derp_poll_session, &config.meshnet_peers
                             ))).await;
                         }
+                        self.refresh_degraded_state();
+                        self.report_path_stats().await;
+                        if self.avoid_overloaded_server() {
+                            telio_log_warn!("({}) Failing over from the overloaded DERP server", Self::NAME);
+                            self.last_disconnection_reason =
+                                RelayConnectionChangeReason::IoError(ErrorKind::TimedOut);
+                            self.disconnect().await;
+                        }
                     }
                     // Received payload from DERP stream, forward it to upper relay
                     Some((permit, Some((pk, buf)))) = wait_for_tx(chan_tx, derp_relayed_read) => {
-                        Self::handle_incoming_payload_relayed(permit, pk, buf, config).await;
+                        self.last_relayed_at = Instant::now();
+                        Self::handle_incoming_payload_relayed(permit, pk, buf, config, &mut self.peer_controls).await;
                     },
                     Some((_, Some(buf))) = wait_for_tx(chan_tx, derp_direct_read) => {
                         self.remote_peers_states = Self::handle_incoming_payload_direct(self.derp_poll_session, buf).await.unwrap_or_default();
+                        self.remote_peers_states_at = Some(Instant::now());
                         telio_log_debug!("Remote peers statuses: {:?}", self.remote_peers_states);
                     }
 
+                    // Nothing relayed for a while, close the connection until it is needed
+                    _ = idle_timer, if config.idle_timeout.is_some() => {
+                        telio_log_info!("Disconnecting from idle DERP server");
+                        self.last_disconnection_reason = RelayConnectionChangeReason::ConfigurationChange;
+                        self.idle = true;
+                        self.disconnect().await;
+                    }
+
+                    // Fault injected by a resilience test
+                    _ = chaos::relay_killed() => {
+                        telio_log_info!("Disconnecting from DERP server, killed by fault injection");
+                        self.last_disconnection_reason =
+                            RelayConnectionChangeReason::IoError(ErrorKind::ConnectionAborted);
+                        self.record_connection_drop();
+                        self.disconnect().await;
+                    }
+
                     update = update => return update(self).await,
 
                     else => (),
@@ -770,6 +1102,10 @@ impl Runtime for State {
                     return (update.await)(self).await;
                 }
 
+                if self.idle {
+                    return self.wait_while_idle(update).await;
+                }
+
                 let connecting = if let Some(connecting) = &mut self.connecting {
                     connecting
                 } else {
@@ -782,9 +1118,15 @@ impl Runtime for State {
                 tokio::select! {
                     res = connecting => {
                         match res {
-                            Ok((server, conn)) => {
+                            Ok((mut server, conn)) => {
+                                if self.is_flapping() {
+                                    telio_log_warn!("({}) DERP connection keeps dropping", Self::NAME);
+                                    server.conn_state = RelayState::Degraded;
+                                }
                                 self.server = Some(server.clone());
                                 self.conn = Some(conn);
+                                self.last_relayed_at = Instant::now();
+                                self.send_woken_by().await;
                                 if let Err(err) = self.event.send(Box::new(server.clone())) {
                                     telio_log_warn!("({}) sending new server info failed {}", Self::NAME, err)
                                 }
@@ -814,7 +1156,7 @@ mod tests {
     use super::*;
     use std::time::Duration;
     use telio_nurse::aggregator::MockConnectivityDataAggregator;
-    use telio_proto::DataMsg;
+    use telio_proto::{DataMsg, PingerMsg, WGPort};
     use telio_sockets::NativeProtector;
     use telio_task::io::McChan;
     use telio_test::await_timeout;
@@ -887,6 +1229,75 @@ mod tests {
         assert_eq!(None, config.servers.get_next());
     }
 
+    #[test]
+    fn test_overloaded_server_is_tried_last() {
+        let servers: Vec<_> = [11, 22, 33]
+            .into_iter()
+            .map(|weight| Server {
+                relay_port: weight as u16,
+                weight,
+                ..Default::default()
+            })
+            .collect();
+        let mut sorted = SortedServers::new(servers.clone());
+
+        sorted.deprioritize(|server| server.weight == 11);
+        assert_eq!(sorted.get_next(), Some(servers[1].clone()));
+        assert_eq!(sorted.get_next(), Some(servers[2].clone()));
+        assert_eq!(sorted.get_next(), Some(servers[0].clone()));
+        assert_eq!(sorted.get_next(), None);
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_wakes_up_on_outgoing_traffic() {
+        let McChan { tx: devent_tx, .. } = McChan::default();
+        let (derp_outer_ch, derp_inner_ch) = Chan::pipe();
+        let test_derp = DerpRelay::start_with(
+            derp_inner_ch,
+            Arc::new(SocketPool::new(
+                NativeProtector::new(
+                    #[cfg(target_os = "macos")]
+                    false,
+                )
+                .unwrap(),
+            )),
+            devent_tx,
+            None,
+        );
+        test_derp
+            .configure(Some(Config {
+                idle_timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            }))
+            .await;
+        let is_idle = || task_exec!(&test_derp.task, async move |s| Ok(s.idle));
+        task_exec!(&test_derp.task, async move |s| {
+            s.idle = true;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        // Control messages wake the connection up too, and are kept until it is connected
+        let pk = SecretKey::gen().public();
+        derp_outer_ch
+            .tx
+            .send((pk, PacketRelayed::Pinger(PingerMsg::ping(WGPort(1), 1, 0))))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(!is_idle().await.unwrap());
+        let woken_by = task_exec!(&test_derp.task, async move |s| Ok(s
+            .woken_by
+            .as_ref()
+            .map(|(pk, msg)| (*pk, msg.packet_type()))))
+        .await
+        .unwrap();
+        assert_eq!(woken_by, Some((pk, PacketTypeRelayed::Pinger)));
+
+        await_timeout!(test_derp.stop());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[ignore = "derp cannot connect to real host"]
     async fn test_derp_fallback() {
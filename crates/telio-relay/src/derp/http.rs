@@ -4,13 +4,18 @@ use super::proto::{
     exchange_keys, read_server_info, start_read, start_write, Error, PairAddr, TCP_KEEPALIVE_COUNT,
     TCP_KEEPALIVE_IDLE, TCP_KEEPALIVE_INTERVAL, TCP_USER_TIMEOUT,
 };
+use super::{
+    presence::PeerPresence,
+    probes::PathProbes,
+    queue::{DropCounters, QueuedBytes},
+};
 use futures::FutureExt;
 use httparse::Status;
 use std::{
     convert::TryFrom,
     io::{Cursor, Error as IoError, ErrorKind},
     net::SocketAddr,
-    sync::Arc,
+    sync::{Arc, OnceLock},
     time::Duration,
 };
 use telio_sockets::{SocketBufSizes, SocketPool, TcpParams};
@@ -28,7 +33,11 @@ use tokio::{
     time::timeout,
 };
 use tokio_rustls::{
-    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    rustls::{
+        client::{ClientSessionMemoryCache, Resumption},
+        pki_types::ServerName,
+        ClientConfig, RootCertStore,
+    },
     TlsConnector,
 };
 use url::{Host, Url};
@@ -38,6 +47,13 @@ const SOCK_BUF_SZ: usize = 212992;
 /// Max TCP packet size is 65535
 const MAX_TCP_PACKET_SIZE: usize = u16::MAX as usize;
 
+/// Session tickets shared by all the connections, so reconnects resume the session instead of
+/// doing a full handshake. Each verifier has its own, as a resumed session is not verified again.
+static BUILT_IN_ROOTS_TLS_SESSIONS: OnceLock<Arc<ClientSessionMemoryCache>> = OnceLock::new();
+static PLATFORM_TLS_SESSIONS: OnceLock<Arc<ClientSessionMemoryCache>> = OnceLock::new();
+/// Servers of a meshnet fit easily, with a ticket or two each
+const TLS_SESSIONS_CAPACITY: usize = 64;
+
 enum DerpVersion {
     V1,
     V2,
@@ -56,6 +72,15 @@ pub struct DerpConnection {
 
     /// For polling derp about remote peers states
     pub poll_timer: Interval,
+
+    /// Relayed packets dropped by this connection
+    pub dropped: Arc<DropCounters>,
+    /// Bytes of relayed packets queued by this connection
+    pub queued: Arc<QueuedBytes>,
+    /// Presence of the peers, as signaled by the server to this connection
+    pub presence: Arc<PeerPresence>,
+    /// Delay of the relayed path, as measured by the probes of this connection
+    pub probes: Arc<PathProbes>,
 }
 
 impl DerpConnection {
@@ -125,8 +150,17 @@ async fn try_connect(
     let hostport = format!("{}:{}", hostname, port);
 
     let use_tcp_keepalives = matches!(derp_version, DerpVersion::V1);
-    let socket = socket_pool.new_external_tcp_v4(Some(build_tcp_parameters(use_tcp_keepalives)))?;
-    let stream = timeout(derp_config.timeout, socket.connect(ip)).await??;
+    // Connecting on the old network would hang after an Android handover, so it is retried
+    let pool = &socket_pool;
+    let stream = timeout(
+        derp_config.timeout,
+        socket_pool.retry_on_rebind(move || async move {
+            pool.new_external_tcp_v4(Some(build_tcp_parameters(use_tcp_keepalives)))?
+                .connect(ip)
+                .await
+        }),
+    )
+    .await??;
     let addr = PairAddr {
         local: stream.local_addr()?,
         remote: stream.peer_addr()?,
@@ -139,23 +173,14 @@ async fn try_connect(
                 addr,
                 derp_config.secret_key.clone(),
                 derp_config.server_keepalives,
+                derp_config.path_probes.map(|probes| probes.interval),
                 &hostport,
                 derp_version,
             ))
             .await
         }
         _ => {
-            let config = if derp_config.use_built_in_root_certificates {
-                let root_store: RootCertStore = TLS_SERVER_ROOTS.iter().cloned().collect();
-
-                let config = ClientConfig::builder()
-                    .with_root_certificates(root_store)
-                    .with_no_client_auth();
-
-                TlsConnector::from(Arc::new(config))
-            } else {
-                TlsConnector::from(Arc::new(rustls_platform_verifier::tls_config()))
-            };
+            let config = TlsConnector::from(tls_config(derp_config.use_built_in_root_certificates));
 
             let server_name =
                 ServerName::try_from(hostname).map_err(|_| Error::InvalidServerName)?;
@@ -165,6 +190,7 @@ async fn try_connect(
                 addr,
                 derp_config.secret_key.clone(),
                 derp_config.server_keepalives,
+                derp_config.path_probes.map(|probes| probes.interval),
                 &hostport,
                 derp_version,
             )
@@ -173,15 +199,47 @@ async fn try_connect(
     }
 }
 
+fn tls_config(use_built_in_root_certificates: bool) -> Arc<ClientConfig> {
+    let config = if use_built_in_root_certificates {
+        let root_store: RootCertStore = TLS_SERVER_ROOTS.iter().cloned().collect();
+        let config = ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        with_shared_sessions(config, &BUILT_IN_ROOTS_TLS_SESSIONS)
+    } else {
+        with_shared_sessions(
+            rustls_platform_verifier::tls_config(),
+            &PLATFORM_TLS_SESSIONS,
+        )
+    };
+    Arc::new(config)
+}
+
+/// Resume the sessions of the earlier connections. Early data stays disabled, as the config
+/// is the same for every connection and 0-RTT data can be replayed by anyone on the path.
+fn with_shared_sessions(
+    mut config: ClientConfig,
+    sessions: &OnceLock<Arc<ClientSessionMemoryCache>>,
+) -> ClientConfig {
+    let sessions = sessions
+        .get_or_init(|| Arc::new(ClientSessionMemoryCache::new(TLS_SESSIONS_CAPACITY)))
+        .clone();
+    config.resumption = Resumption::store(sessions);
+    config.enable_early_data = false;
+    config
+}
+
 async fn connect_and_start<RW: AsyncRead + AsyncWrite + Send + 'static>(
     stream: RW,
     addr: PairAddr,
     secret_key: SecretKey,
     server_keepalives: DerpKeepaliveConfig,
+    probe_interval: Option<Duration>,
     host: &str,
     derp_version: DerpVersion,
 ) -> Result<DerpConnection, Error> {
     let (mut reader, mut writer) = split(stream);
+    let probes = Arc::new(PathProbes::new(secret_key.public()));
 
     let leftovers = Box::pin(connect_http(
         &mut reader,
@@ -212,16 +270,48 @@ async fn connect_and_start<RW: AsyncRead + AsyncWrite + Send + 'static>(
     let poll_interval = Duration::from_secs(server_keepalives.derp_keepalive as u64);
     telio_log_debug!("Derp poll interval {:?}", poll_interval);
 
+    let dropped = Arc::new(DropCounters::default());
+    let (dropped_read, dropped_write) = (dropped.clone(), dropped.clone());
+    let queued = Arc::new(QueuedBytes::default());
+    let (queued_read, queued_write) = (queued.clone(), queued.clone());
+    let presence = Arc::new(PeerPresence::default());
+    let presence_read = presence.clone();
+    let (probes_read, probes_write) = (probes.clone(), probes.clone());
+
     Ok(DerpConnection {
         comms_relayed: comm_side_relayed,
         comms_direct: comm_side_direct,
         join_sender: tokio::spawn(async move {
-            start_read(reader, sender_relayed, sender_direct, addr).await
+            start_read(
+                reader,
+                sender_relayed,
+                sender_direct,
+                addr,
+                dropped_read,
+                queued_read,
+                presence_read,
+                probes_read,
+            )
+            .await
         }),
         join_receiver: tokio::spawn(async move {
-            start_write(writer, receiver_relayed, receiver_direct, addr).await
+            start_write(
+                writer,
+                receiver_relayed,
+                receiver_direct,
+                addr,
+                dropped_write,
+                queued_write,
+                probes_write,
+                probe_interval,
+            )
+            .await
         }),
         poll_timer: { interval_at(tokio::time::Instant::now() + poll_interval, poll_interval) },
+        dropped,
+        queued,
+        presence,
+        probes,
     })
 }
 
@@ -456,4 +546,65 @@ mod tests {
                 .as_slice()
         );
     }
+
+    #[tokio::test]
+    async fn reconnect_resumes_the_tls_session() {
+        use tokio_rustls::{
+            rustls::{
+                pki_types::{CertificateDer, PrivatePkcs8KeyDer},
+                HandshakeKind, ServerConfig,
+            },
+            TlsAcceptor,
+        };
+
+        let server_config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(
+                    &include_bytes!("testdata/cert.der")[..],
+                )],
+                PrivatePkcs8KeyDer::from(&include_bytes!("testdata/key.der")[..]).into(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // The tickets follow the handshake, the client reads them with the byte
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let _ = stream.write_all(b"x").await;
+                        let _ = stream.flush().await;
+                        let _ = stream.read_u8().await;
+                    }
+                });
+            }
+        });
+
+        let mut root_store = RootCertStore::empty();
+        root_store
+            .add(CertificateDer::from(&include_bytes!("testdata/ca.der")[..]))
+            .unwrap();
+        let sessions = OnceLock::new();
+        let mut handshakes = Vec::new();
+        for _ in 0..2 {
+            let config = ClientConfig::builder()
+                .with_root_certificates(root_store.clone())
+                .with_no_client_auth();
+            let connector = TlsConnector::from(Arc::new(with_shared_sessions(config, &sessions)));
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let server_name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(server_name, stream).await.unwrap();
+            assert_eq!(stream.read_u8().await.unwrap(), b'x');
+            handshakes.push(stream.get_ref().1.handshake_kind());
+            assert!(!stream.get_ref().1.is_early_data_accepted());
+        }
+
+        assert_eq!(
+            handshakes,
+            vec![Some(HandshakeKind::Full), Some(HandshakeKind::Resumed)]
+        );
+    }
 }
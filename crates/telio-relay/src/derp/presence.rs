@@ -0,0 +1,81 @@
+//! Presence of the peers, as signaled by the server
+//!
+//! The server sends `PeerGone` once a peer we exchanged packets with disconnects from it, and
+//! `PeerPresent` once a peer connects. These arrive right away, unlike the peer states polled
+//! every `derp_keepalive` seconds, so the newer of the two wins. A peer marked offline comes
+//! back on its own after [OFFLINE_MARK_TTL], in case the server misses to signal its return.
+
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+use telio_crypto::PublicKey;
+use telio_proto::PeersStatesMap;
+use tokio::time::Instant;
+
+/// Time after which the offline mark of a peer expires, whether polled since or not
+pub const OFFLINE_MARK_TTL: Duration = Duration::from_secs(120);
+
+/// Presence signals received by a single connection, updated by its read loop
+#[derive(Debug, Default)]
+pub struct PeerPresence(Mutex<HashMap<PublicKey, (bool, Instant)>>);
+
+impl PeerPresence {
+    /// Record the peer going online or offline
+    pub fn set(&self, public_key: PublicKey, online: bool) {
+        self.0.lock().insert(public_key, (online, Instant::now()));
+    }
+
+    /// Override the polled peer states with the signals received after them
+    pub fn merge_into(&self, states: &mut PeersStatesMap, polled_at: Option<Instant>) {
+        let mut signals = self.0.lock();
+        signals.retain(|_, (online, at)| *online || at.elapsed() < OFFLINE_MARK_TTL);
+        for (public_key, (online, at)) in signals.iter() {
+            if polled_at.map_or(true, |polled_at| *at > polled_at) {
+                states.insert(*public_key, *online);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use telio_crypto::SecretKey;
+
+    #[tokio::test(start_paused = true)]
+    async fn newer_signals_override_polled_states() {
+        let (gone, back) = (SecretKey::gen().public(), SecretKey::gen().public());
+        let presence = PeerPresence::default();
+
+        presence.set(gone, false);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let polled_at = Instant::now();
+        tokio::time::advance(Duration::from_secs(1)).await;
+        presence.set(back, true);
+
+        let mut states = PeersStatesMap::from([(gone, true), (back, false)]);
+        presence.merge_into(&mut states, Some(polled_at));
+        assert_eq!(states, PeersStatesMap::from([(gone, true), (back, true)]));
+
+        let mut states = PeersStatesMap::new();
+        presence.merge_into(&mut states, None);
+        assert_eq!(states, PeersStatesMap::from([(gone, false), (back, true)]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn offline_mark_expires_without_polling() {
+        let gone = SecretKey::gen().public();
+        let presence = PeerPresence::default();
+        presence.set(gone, false);
+
+        tokio::time::advance(OFFLINE_MARK_TTL - Duration::from_secs(1)).await;
+        let mut states = PeersStatesMap::new();
+        presence.merge_into(&mut states, None);
+        assert_eq!(states, PeersStatesMap::from([(gone, false)]));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let mut states = PeersStatesMap::new();
+        presence.merge_into(&mut states, None);
+        assert!(states.is_empty());
+    }
+}
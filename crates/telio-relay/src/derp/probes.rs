@@ -0,0 +1,183 @@
+//! Delay and delay variation of the relayed path, measured with timestamped probes
+//!
+//! Every probe is a packet carrying the time it was sent, addressed to this client itself, so
+//! the server relays it back through the same forwarding queues as the packets of the peers. The
+//! round trip time tells how far the server is, while its variation between consecutive probes,
+//! the jitter, grows with the queues of an overloaded server. A distant server has a high but
+//! steady delay, an overloaded one a varying delay. Jitter is smoothed like the interarrival
+//! jitter of RFC 3550.
+
+use parking_lot::Mutex;
+use std::time::Duration;
+use telio_crypto::PublicKey;
+use tokio::time::Instant;
+
+/// Size of the probe payload, the time it was sent at
+pub const PROBE_SIZE: usize = 8;
+
+/// Probes answered before the jitter is trusted
+const MIN_SAMPLES: u32 = 4;
+
+/// Probing of the relayed path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathProbesConfig {
+    /// Interval between the probes
+    pub interval: Duration,
+    /// Jitter above which the server is considered overloaded
+    pub overloaded_jitter: Duration,
+}
+
+/// Delay of the relayed path, as measured by the probes of a single connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStats {
+    /// Smoothed round trip time
+    pub rtt: Duration,
+    /// Lowest round trip time, the delay of the path without queueing
+    pub min_rtt: Duration,
+    /// Smoothed variation of the round trip time between consecutive probes
+    pub jitter: Duration,
+    /// Round trip time of the last probe
+    pub last_rtt: Duration,
+    /// Number of probes answered
+    pub samples: u32,
+}
+
+impl PathStats {
+    /// Whether the delay varies enough to blame an overloaded server, rather than a distant one
+    pub fn is_overloaded(&self, overloaded_jitter: Duration) -> bool {
+        self.samples >= MIN_SAMPLES && self.jitter > overloaded_jitter
+    }
+
+    fn update(&mut self, rtt: Duration) {
+        let variation = rtt.max(self.last_rtt) - rtt.min(self.last_rtt);
+        if variation > self.jitter {
+            self.jitter += (variation - self.jitter) / 16;
+        } else {
+            self.jitter -= (self.jitter - variation) / 16;
+        }
+        self.rtt = (self.rtt * 7 + rtt) / 8;
+        self.min_rtt = self.min_rtt.min(rtt);
+        self.last_rtt = rtt;
+        self.samples = self.samples.saturating_add(1);
+    }
+}
+
+/// Probes of a single connection, stamped by its write loop and answered in its read loop
+#[derive(Debug)]
+pub struct PathProbes {
+    public_key: PublicKey,
+    epoch: Instant,
+    stats: Mutex<Option<PathStats>>,
+}
+
+impl PathProbes {
+    /// Probes of the connection of the client with the public key
+    pub fn new(public_key: PublicKey) -> Self {
+        Self {
+            public_key,
+            epoch: Instant::now(),
+            stats: Mutex::new(None),
+        }
+    }
+
+    /// Key the probes are addressed to and relayed back from, only this client can send them
+    pub fn public_key(&self) -> PublicKey {
+        self.public_key
+    }
+
+    /// Payload of a new probe, the time it is sent at
+    pub fn stamp(&self) -> [u8; PROBE_SIZE] {
+        (self.epoch.elapsed().as_micros() as u64).to_be_bytes()
+    }
+
+    /// Record the probe relayed back by the server, malformed payloads are ignored
+    pub fn answered(&self, payload: &[u8]) {
+        let Ok(sent_at) = <[u8; PROBE_SIZE]>::try_from(payload).map(u64::from_be_bytes) else {
+            return;
+        };
+        let Some(rtt) = self
+            .epoch
+            .elapsed()
+            .checked_sub(Duration::from_micros(sent_at))
+        else {
+            return;
+        };
+
+        let mut stats = self.stats.lock();
+        match stats.as_mut() {
+            Some(stats) => stats.update(rtt),
+            None => {
+                *stats = Some(PathStats {
+                    rtt,
+                    min_rtt: rtt,
+                    jitter: Duration::ZERO,
+                    last_rtt: rtt,
+                    samples: 1,
+                })
+            }
+        }
+    }
+
+    /// Delay measured so far, if any probe was answered
+    pub fn stats(&self) -> Option<PathStats> {
+        *self.stats.lock()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use tokio::time;
+
+    const OVERLOADED_JITTER: Duration = Duration::from_millis(20);
+
+    async fn probe(probes: &PathProbes, rtt: Duration) {
+        let payload = probes.stamp();
+        time::advance(rtt).await;
+        probes.answered(&payload);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn distant_server_has_steady_delay() {
+        let probes = PathProbes::new(SecretKey::gen().public());
+        assert_eq!(probes.stats(), None);
+
+        for _ in 0..10 {
+            probe(&probes, Duration::from_millis(200)).await;
+        }
+        let stats = probes.stats().unwrap();
+        assert_eq!(stats.rtt, Duration::from_millis(200));
+        assert_eq!(stats.min_rtt, Duration::from_millis(200));
+        assert_eq!(stats.jitter, Duration::ZERO);
+        assert_eq!(stats.samples, 10);
+        assert!(!stats.is_overloaded(OVERLOADED_JITTER));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overloaded_server_has_varying_delay() {
+        let probes = PathProbes::new(SecretKey::gen().public());
+
+        for rtt in [20, 400, 30, 500, 20, 450, 40, 600, 30, 500] {
+            probe(&probes, Duration::from_millis(rtt)).await;
+        }
+        let stats = probes.stats().unwrap();
+        assert_eq!(stats.min_rtt, Duration::from_millis(20));
+        assert!(stats.jitter > OVERLOADED_JITTER);
+        assert!(stats.is_overloaded(OVERLOADED_JITTER));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn few_or_malformed_answers_are_not_trusted() {
+        let probes = PathProbes::new(SecretKey::gen().public());
+
+        probe(&probes, Duration::from_millis(10)).await;
+        probe(&probes, Duration::from_millis(900)).await;
+        assert!(!probes.stats().unwrap().is_overloaded(OVERLOADED_JITTER));
+
+        probes.answered(&[1, 2, 3]);
+        // Sent in the future
+        probes.answered(&u64::MAX.to_be_bytes());
+        assert_eq!(probes.stats().unwrap().samples, 2);
+    }
+}
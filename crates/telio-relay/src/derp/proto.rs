@@ -17,18 +17,26 @@ use std::{
     io::{Error as IoError, ErrorKind},
     net::SocketAddr,
     num::TryFromIntError,
+    sync::Arc,
     time::Duration,
 };
 use telio_crypto::{PublicKey, SecretKey, KEY_SIZE};
 use telio_model::config::RelayConnectionChangeReason;
-use telio_utils::{telio_log_debug, telio_log_trace};
+use telio_utils::{interval, telio_log_debug, telio_log_hot, telio_log_trace};
 use thiserror::Error as TError;
-use tracing::{enabled, Level};
 
+use futures::future::pending;
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     select,
     sync::mpsc::{error::SendError, Receiver, Sender},
+    time::{Instant, Interval},
+};
+
+use super::{
+    presence::PeerPresence,
+    probes::PathProbes,
+    queue::{DropCounters, PacketQueue, QueuedBytes},
 };
 
 #[cfg(test)]
@@ -181,12 +189,37 @@ pub struct PairAddr {
 
 /// This function starts a loop which reads all the frames from a reader, handles the known types
 /// and bypasses the content of DERP frames to the reader_sender
-#[allow(mpsc_blocking_send)]
+///
+/// Relayed packets are queued per peer, so reading from the server never waits for a peer
+/// which can't keep up. When its queue is full, its oldest packets are dropped and counted.
+/// Presence signals of the peers are recorded in `presence`, answered probes in `probes`.
+#[allow(clippy::too_many_arguments)]
 pub async fn start_read<R: AsyncRead + Unpin>(
-    mut reader: R,
+    reader: R,
     sender_relayed: Sender<(PublicKey, Vec<u8>)>,
     sender_direct: Sender<Vec<u8>>,
     addr: PairAddr,
+    dropped: Arc<DropCounters>,
+    queued: Arc<QueuedBytes>,
+    presence: Arc<PeerPresence>,
+    probes: Arc<PathProbes>,
+) -> Result<(), Error> {
+    let queue = PacketQueue::new(queued);
+    select! {
+        res = read_frames(reader, &queue, sender_direct, addr, &dropped, &presence, &probes) => res,
+        res = forward_relayed(&queue, sender_relayed) => res,
+    }
+}
+
+#[allow(mpsc_blocking_send)]
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: R,
+    queue: &PacketQueue,
+    sender_direct: Sender<Vec<u8>>,
+    addr: PairAddr,
+    dropped: &DropCounters,
+    presence: &PeerPresence,
+    probes: &PathProbes,
 ) -> Result<(), Error> {
     loop {
         let (frame_type, mut data) = read_frame(&mut reader).await?;
@@ -195,18 +228,24 @@ pub async fn start_read<R: AsyncRead + Unpin>(
             FrameType::RecvPacket => {
                 let public_key =
                     <PublicKey as TryFrom<&[u8]>>::try_from(data.drain(0..KEY_SIZE).as_slice())?;
+                // Only this client can send packets from its own key, those are its probes
+                if public_key == probes.public_key() {
+                    probes.answered(&data);
+                    continue;
+                }
 
-                if enabled!(Level::TRACE) {
-                    telio_log_trace!(
-                        "DERP Rx: {} -> {}, frame type: {:?}, data len: {}, pubkey: {:?}",
-                        addr.remote,
-                        addr.local,
-                        frame_type,
-                        data.len(),
-                        public_key,
-                    );
+                telio_log_hot!(
+                    "DERP Rx: {} -> {}, frame type: {:?}, data len: {}, pubkey: {:?}",
+                    addr.remote,
+                    addr.local,
+                    frame_type,
+                    data.len(),
+                    public_key,
+                );
+                if queue.push(public_key, data) {
+                    telio_log_hot!("DERP Rx: queue of {:?} is full, dropped", public_key);
+                    dropped.drop_incoming();
                 }
-                sender_relayed.send((public_key, data)).await?
             }
             // Derp -> LocalNode
             FrameType::ControlMessage => {
@@ -219,42 +258,100 @@ pub async fn start_read<R: AsyncRead + Unpin>(
                 );
                 sender_direct.send(data).await?
             }
+            // Derp -> LocalNode, a peer connected to or disconnected from the server
+            FrameType::PeerGone | FrameType::PeerPersistent => {
+                match data
+                    .get(..KEY_SIZE)
+                    .map(<PublicKey as TryFrom<&[u8]>>::try_from)
+                {
+                    Some(Ok(public_key)) => {
+                        let online = frame_type == FrameType::PeerPersistent;
+                        telio_log_trace!("DERP Rx: peer {:?} online: {}", public_key, online);
+                        presence.set(public_key, online);
+                    }
+                    _ => telio_log_debug!("Malformed presence frame: {:?}", frame_type),
+                }
+            }
             _ => telio_log_debug!("Unhandled packet: {:?}: {:?}", frame_type, data),
         }
     }
 }
 
+#[allow(mpsc_blocking_send)]
+async fn forward_relayed(
+    queue: &PacketQueue,
+    sender_relayed: Sender<(PublicKey, Vec<u8>)>,
+) -> Result<(), Error> {
+    loop {
+        sender_relayed.send(queue.pop().await).await?;
+    }
+}
+
 /// This function starts a loop which receives all the messages to the writer_receiver,
 /// encapsulates them to DERP frames and bypasses them to the writer
+///
+/// Relayed packets are queued per destination, so a burst to one peer can't delay the packets
+/// for all the others. When its queue is full, its oldest packets are dropped and counted.
+/// Control messages, DNS and keepalives are written before the bulk data of any peer.
+/// With a `probe_interval`, timestamped probes stamped by `probes` are relayed back to this
+/// client.
+#[allow(clippy::too_many_arguments)]
 pub async fn start_write<W: AsyncWrite + Unpin>(
-    mut writer: W,
+    writer: W,
+    receiver_relayed: Receiver<(PublicKey, Vec<u8>)>,
+    receiver_direct: Receiver<Vec<u8>>,
+    addr: PairAddr,
+    dropped: Arc<DropCounters>,
+    queued: Arc<QueuedBytes>,
+    probes: Arc<PathProbes>,
+    probe_interval: Option<Duration>,
+) -> Result<(), Error> {
+    let queue = PacketQueue::new(queued);
+    select! {
+        () = queue_relayed(receiver_relayed, &queue, &dropped) => Ok(()),
+        res = write_frames(writer, &queue, receiver_direct, addr, &probes, probe_interval) => res,
+    }
+}
+
+async fn queue_relayed(
     mut receiver_relayed: Receiver<(PublicKey, Vec<u8>)>,
+    queue: &PacketQueue,
+    dropped: &DropCounters,
+) {
+    while let Some((public_key, data)) = receiver_relayed.recv().await {
+        if queue.push(public_key, data) {
+            telio_log_hot!("DERP Tx: queue of {:?} is full, dropped", public_key);
+            dropped.drop_outgoing();
+        }
+    }
+}
+
+async fn write_frames<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    queue: &PacketQueue,
     mut receiver_direct: Receiver<Vec<u8>>,
     addr: PairAddr,
+    probes: &PathProbes,
+    probe_interval: Option<Duration>,
 ) -> Result<(), Error> {
+    let mut probe_timer = probe_interval.map(interval);
     loop {
         select! {
             // LocalNode -> Derp -> RemoteNode
-            received = receiver_relayed.recv() => {
-                if let Some((public_key, data)) = received {
-                    let mut buf = Vec::<u8>::new();
-                    buf.write_all(public_key.as_ref()).await?;
-                    buf.write_all(&data).await?;
+            (public_key, data) = queue.pop() => {
+                let mut buf = Vec::<u8>::new();
+                buf.write_all(public_key.as_ref()).await?;
+                buf.write_all(&data).await?;
 
-                    if enabled!(Level::TRACE) {
-                        telio_log_trace!(
-                            "DERP Tx: {} -> {}, data len: {}, pubkey: {:?}",
-                            addr.local,
-                            addr.remote,
-                            data.len(),
-                            public_key,
-                        );
-                    }
+                telio_log_hot!(
+                    "DERP Tx: {} -> {}, data len: {}, pubkey: {:?}",
+                    addr.local,
+                    addr.remote,
+                    data.len(),
+                    public_key,
+                );
 
-                    write_frame(&mut writer, FrameType::SendPacket, buf).await?;
-                } else {
-                    break;
-                }
+                write_frame(&mut writer, FrameType::SendPacket, buf).await?;
             },
             // LocalNode -> Derp
             received = receiver_direct.recv() => {
@@ -266,11 +363,26 @@ pub async fn start_write<W: AsyncWrite + Unpin>(
                     break;
                 }
             }
+            // LocalNode -> Derp -> LocalNode, timestamped probe relayed back to this client
+            Some(_) = tick(probe_timer.as_mut()) => {
+                let mut buf = Vec::<u8>::new();
+                buf.write_all(probes.public_key().as_ref()).await?;
+                buf.write_all(&probes.stamp()).await?;
+                write_frame(&mut writer, FrameType::SendPacket, buf).await?;
+            }
         }
     }
     Ok(())
 }
 
+/// Tick of the optional timer, never ready without one
+async fn tick(timer: Option<&mut Interval>) -> Option<Instant> {
+    match timer {
+        Some(timer) => Some(timer.tick().await),
+        None => pending().await,
+    }
+}
+
 /// Reads the server key and sends the initiation message via a writer to the DERP server
 /// Initiation message consists of:
 /// * `public key`
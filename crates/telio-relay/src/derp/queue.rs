@@ -0,0 +1,517 @@
+//! Per-peer queues of relayed packets
+//!
+//! Packets are queued separately for each peer and served round-robin, so a burst to or from
+//! a single peer can't hold up the packets of all the others. When a queue is full, its oldest
+//! packets are dropped, as they are the most likely to be stale already.
+//!
+//! Meshnet control messages and WireGuard handshakes are also served ahead of the tunnel data,
+//! so that hole punch coordination and session setup are not held up behind bulk data on
+//! congested relay links. Those are sent only a few times per peer and second at most, so they
+//! can't starve the data. The tunnel data itself is served in the order it was queued, as the
+//! tunnel hides which flow each packet belongs to, and any reordering could hit a TCP flow.
+
+use parking_lot::Mutex;
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::AddAssign,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use telio_crypto::PublicKey;
+use telio_proto::PacketTypeRelayed;
+use tokio::sync::Notify;
+
+/// Packets queued for a single peer before the oldest ones are dropped
+pub const PEER_QUEUE_SIZE: usize = 128;
+
+/// Packets queued for all peers before the oldest ones of the busiest peer are dropped
+pub const QUEUE_SIZE: usize = 4096;
+
+/// Packets of the urgent class queued for a single peer before the oldest ones are dropped
+pub const URGENT_PEER_QUEUE_SIZE: usize = 32;
+
+/// Packets of the urgent class queued for all peers before the oldest ones are dropped
+pub const URGENT_QUEUE_SIZE: usize = 512;
+
+/// Number of relayed packets dropped because the other side could not keep up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DroppedPackets {
+    /// Packets received from the server, dropped before being handed over to the peers
+    pub incoming: u64,
+    /// Packets sent to the peers, dropped before being written to the server
+    pub outgoing: u64,
+}
+
+impl AddAssign for DroppedPackets {
+    fn add_assign(&mut self, other: Self) {
+        self.incoming += other.incoming;
+        self.outgoing += other.outgoing;
+    }
+}
+
+/// Drop counters of a single connection, updated by its read and write loops
+#[derive(Debug, Default)]
+pub struct DropCounters {
+    incoming: AtomicU64,
+    outgoing: AtomicU64,
+}
+
+impl DropCounters {
+    /// Count a dropped incoming packet
+    pub fn drop_incoming(&self) {
+        self.incoming.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a dropped outgoing packet
+    pub fn drop_outgoing(&self) {
+        self.outgoing.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the current values of the counters
+    pub fn get(&self) -> DroppedPackets {
+        DroppedPackets {
+            incoming: self.incoming.load(Ordering::Relaxed),
+            outgoing: self.outgoing.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bytes held by the queues of a single connection, updated by its read and write loops
+#[derive(Debug, Default)]
+pub struct QueuedBytes(AtomicUsize);
+
+impl QueuedBytes {
+    /// Get the number of bytes currently queued
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn update(&self, before: usize, after: usize) {
+        if after >= before {
+            self.0.fetch_add(after - before, Ordering::Relaxed);
+        } else {
+            self.0.fetch_sub(before - after, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Priority class of a relayed packet, from the most urgent one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Meshnet control messages and WireGuard handshakes
+    Control,
+    /// Tunnel data, keepalives included
+    Bulk,
+}
+
+impl Priority {
+    /// Class of the packet, judging by its relayed packet type and WireGuard header
+    pub fn of(packet: &[u8]) -> Self {
+        let wg_packet = match packet.first().map(|t| PacketTypeRelayed::from(*t)) {
+            Some(PacketTypeRelayed::Data) => packet.get(1..),
+            Some(PacketTypeRelayed::GenData) => packet.get(4..),
+            Some(_) => return Priority::Control,
+            None => None,
+        };
+        let Some(wg_packet) = wg_packet else {
+            return Priority::Bulk;
+        };
+        match wg_packet.first() {
+            // Handshake initiation, response and cookie reply
+            Some(1..=3) => Priority::Control,
+            _ => Priority::Bulk,
+        }
+    }
+}
+
+/// Bounded packet queues of the peers, served round-robin
+#[derive(Debug)]
+pub struct FairQueue {
+    queues: HashMap<PublicKey, VecDeque<Vec<u8>>>,
+    /// Peers with queued packets, in the order they will be served
+    order: VecDeque<PublicKey>,
+    len: usize,
+    bytes: usize,
+    peer_capacity: usize,
+    capacity: usize,
+}
+
+impl FairQueue {
+    /// Create a queue holding up to `peer_capacity` packets for each peer and up to `capacity`
+    /// packets in total
+    pub fn new(peer_capacity: usize, capacity: usize) -> Self {
+        Self {
+            queues: HashMap::new(),
+            order: VecDeque::new(),
+            len: 0,
+            bytes: 0,
+            peer_capacity: peer_capacity.max(1),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queue the packet of the peer, returns true if some older packet had to be dropped
+    pub fn push(&mut self, public_key: PublicKey, packet: Vec<u8>) -> bool {
+        let mut dropped = false;
+
+        let queue = self.queues.entry(public_key).or_default();
+        if queue.is_empty() {
+            self.order.push_back(public_key);
+        }
+        if queue.len() >= self.peer_capacity {
+            self.bytes -= queue.pop_front().map_or(0, |p| p.capacity());
+            dropped = true;
+        } else {
+            self.len += 1;
+        }
+        self.bytes += packet.capacity();
+        queue.push_back(packet);
+
+        if self.len > self.capacity {
+            if let Some((busiest, queue)) = self.queues.iter_mut().max_by_key(|(_, q)| q.len()) {
+                let busiest = *busiest;
+                self.bytes -= queue.pop_front().map_or(0, |p| p.capacity());
+                if queue.is_empty() {
+                    // Otherwise the peer would be served twice per round once it queues again
+                    self.queues.remove(&busiest);
+                    self.order.retain(|public_key| *public_key != busiest);
+                }
+                self.len -= 1;
+                dropped = true;
+            }
+        }
+
+        dropped
+    }
+
+    /// Take the next packet, each peer with queued packets gets its turn
+    pub fn pop(&mut self) -> Option<(PublicKey, Vec<u8>)> {
+        while let Some(public_key) = self.order.pop_front() {
+            let Some(queue) = self.queues.get_mut(&public_key) else {
+                continue;
+            };
+            let Some(packet) = queue.pop_front() else {
+                self.queues.remove(&public_key);
+                continue;
+            };
+            self.len -= 1;
+            self.bytes -= packet.capacity();
+            if queue.is_empty() {
+                self.queues.remove(&public_key);
+            } else {
+                self.order.push_back(public_key);
+            }
+            return Some((public_key, packet));
+        }
+        None
+    }
+
+    /// Number of queued packets
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of bytes allocated for the queued packets
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Check if there are no queued packets
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Fair queues of the priority classes, the more urgent classes are served first
+#[derive(Debug)]
+pub struct PriorityQueue {
+    classes: [FairQueue; 2],
+}
+
+impl PriorityQueue {
+    /// Create an empty queue, the bulk class gets the given capacities
+    pub fn new(peer_capacity: usize, capacity: usize) -> Self {
+        Self {
+            classes: [
+                FairQueue::new(URGENT_PEER_QUEUE_SIZE, URGENT_QUEUE_SIZE),
+                FairQueue::new(peer_capacity, capacity),
+            ],
+        }
+    }
+
+    /// Queue the packet in its class, returns true if some older packet had to be dropped
+    pub fn push(&mut self, public_key: PublicKey, packet: Vec<u8>) -> bool {
+        self.classes[Priority::of(&packet) as usize].push(public_key, packet)
+    }
+
+    /// Take the next packet of the most urgent class with queued packets
+    pub fn pop(&mut self) -> Option<(PublicKey, Vec<u8>)> {
+        self.classes.iter_mut().find_map(FairQueue::pop)
+    }
+
+    /// Number of queued packets
+    pub fn len(&self) -> usize {
+        self.classes.iter().map(FairQueue::len).sum()
+    }
+
+    /// Number of bytes allocated for the queued packets
+    pub fn bytes(&self) -> usize {
+        self.classes.iter().map(FairQueue::bytes).sum()
+    }
+
+    /// Check if there are no queued packets
+    pub fn is_empty(&self) -> bool {
+        self.classes.iter().all(FairQueue::is_empty)
+    }
+}
+
+/// Priority queue shared between the loop filling it and the one draining it
+#[derive(Debug)]
+pub struct PacketQueue {
+    queue: Mutex<PriorityQueue>,
+    queued: Notify,
+    usage: Arc<QueuedBytes>,
+}
+
+impl PacketQueue {
+    /// Create an empty queue with the default capacities, accounting its bytes in `usage`
+    pub fn new(usage: Arc<QueuedBytes>) -> Self {
+        Self {
+            queue: Mutex::new(PriorityQueue::new(PEER_QUEUE_SIZE, QUEUE_SIZE)),
+            queued: Notify::new(),
+            usage,
+        }
+    }
+
+    /// Queue the packet without waiting, returns true if some older packet had to be dropped
+    pub fn push(&self, public_key: PublicKey, packet: Vec<u8>) -> bool {
+        let dropped = {
+            let mut queue = self.queue.lock();
+            let before = queue.bytes();
+            let dropped = queue.push(public_key, packet);
+            self.usage.update(before, queue.bytes());
+            dropped
+        };
+        self.queued.notify_one();
+        dropped
+    }
+
+    /// Wait for the next packet
+    pub async fn pop(&self) -> (PublicKey, Vec<u8>) {
+        loop {
+            let next = {
+                let mut queue = self.queue.lock();
+                let before = queue.bytes();
+                let next = queue.pop();
+                self.usage.update(before, queue.bytes());
+                next
+            };
+            if let Some(next) = next {
+                return next;
+            }
+            self.queued.notified().await;
+        }
+    }
+}
+
+impl Drop for PacketQueue {
+    fn drop(&mut self) {
+        self.usage.update(self.queue.get_mut().bytes(), 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey([i; 32])
+    }
+
+    #[test]
+    fn peers_are_served_round_robin() {
+        let mut queue = FairQueue::new(16, 64);
+        for i in 0..4 {
+            assert!(!queue.push(pk(1), vec![i]));
+        }
+        assert!(!queue.push(pk(2), vec![10]));
+        assert!(!queue.push(pk(3), vec![20]));
+        assert!(!queue.push(pk(2), vec![11]));
+
+        let served: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            served,
+            vec![
+                (pk(1), vec![0]),
+                (pk(2), vec![10]),
+                (pk(3), vec![20]),
+                (pk(1), vec![1]),
+                (pk(2), vec![11]),
+                (pk(1), vec![2]),
+                (pk(1), vec![3]),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn oldest_packets_of_peer_are_dropped() {
+        let mut queue = FairQueue::new(2, 64);
+        assert!(!queue.push(pk(1), vec![0]));
+        assert!(!queue.push(pk(1), vec![1]));
+        assert!(queue.push(pk(1), vec![2]));
+        assert!(!queue.push(pk(2), vec![10]));
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.pop(), Some((pk(1), vec![1])));
+        assert_eq!(queue.pop(), Some((pk(2), vec![10])));
+        assert_eq!(queue.pop(), Some((pk(1), vec![2])));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn busiest_peer_pays_for_full_queue() {
+        let mut queue = FairQueue::new(8, 4);
+        for i in 0..3 {
+            assert!(!queue.push(pk(1), vec![i]));
+        }
+        assert!(!queue.push(pk(2), vec![10]));
+        assert!(queue.push(pk(3), vec![20]));
+        assert_eq!(queue.len(), 4);
+
+        let served: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            served,
+            vec![
+                (pk(1), vec![1]),
+                (pk(2), vec![10]),
+                (pk(3), vec![20]),
+                (pk(1), vec![2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn busy_peer_does_not_delay_others() {
+        let mut queue = FairQueue::new(64, 256);
+        for i in 0..64 {
+            queue.push(pk(1), vec![i]);
+        }
+        assert!(!queue.push(pk(2), vec![100]));
+
+        assert_eq!(queue.pop(), Some((pk(1), vec![0])));
+        assert_eq!(queue.pop(), Some((pk(2), vec![100])));
+        assert_eq!(queue.len(), 63);
+    }
+
+    #[test]
+    fn peer_emptied_by_full_queue_keeps_single_turn() {
+        let mut queue = FairQueue::new(8, 2);
+        for round in 0..3 {
+            for i in 1..=3 {
+                // Every queue holds at most a single packet, so making room empties one of them
+                queue.push(pk(i), vec![round]);
+                let mut scheduled: Vec<_> = queue.order.iter().copied().collect();
+                scheduled.sort();
+                let mut queued: Vec<_> = queue.queues.keys().copied().collect();
+                queued.sort();
+                assert_eq!(scheduled, queued);
+            }
+        }
+        assert_eq!(queue.len(), 2);
+        let (first, _) = queue.pop().unwrap();
+        let (second, _) = queue.pop().unwrap();
+        assert_ne!(first, second);
+    }
+
+    fn wg_data(len: usize) -> Vec<u8> {
+        let mut packet = vec![PacketTypeRelayed::Data as u8, 4];
+        packet.resize(1 + len, 0);
+        packet
+    }
+
+    #[test]
+    fn packets_are_classified() {
+        assert_eq!(
+            Priority::of(&[PacketTypeRelayed::Encrypted as u8, 1, 2]),
+            Priority::Control
+        );
+        assert_eq!(
+            Priority::of(&[PacketTypeRelayed::Data as u8, 1, 0, 0, 0]),
+            Priority::Control
+        );
+        assert_eq!(
+            Priority::of(&[PacketTypeRelayed::GenData as u8, 0, 0, 1, 2]),
+            Priority::Control
+        );
+        assert_eq!(Priority::of(&wg_data(32)), Priority::Bulk);
+        assert_eq!(Priority::of(&wg_data(112)), Priority::Bulk);
+        assert_eq!(Priority::of(&wg_data(1420)), Priority::Bulk);
+        assert_eq!(Priority::of(&[]), Priority::Bulk);
+    }
+
+    #[test]
+    fn control_is_served_first() {
+        let mut queue = PriorityQueue::new(16, 64);
+        let control = vec![PacketTypeRelayed::Encrypted as u8, 0];
+        let handshake = vec![PacketTypeRelayed::Data as u8, 1, 0, 0, 0];
+        assert!(!queue.push(pk(1), wg_data(1420)));
+        assert!(!queue.push(pk(1), handshake.clone()));
+        assert!(!queue.push(pk(2), wg_data(1420)));
+        assert!(!queue.push(pk(2), control.clone()));
+        assert_eq!(queue.len(), 4);
+
+        let served: Vec<_> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(
+            served,
+            vec![
+                (pk(1), handshake),
+                (pk(2), control),
+                (pk(1), wg_data(1420)),
+                (pk(2), wg_data(1420)),
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn data_of_peer_keeps_its_order() {
+        let mut queue = PriorityQueue::new(16, 64);
+        // A bulk transfer and its ACKs and keepalives, small packets must not overtake
+        let data = [wg_data(1420), wg_data(80), wg_data(32), wg_data(1420)];
+        for packet in data.iter() {
+            assert!(!queue.push(pk(1), packet.clone()));
+        }
+
+        let served: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|(_, p)| p).collect();
+        assert_eq!(served, data);
+    }
+
+    #[tokio::test]
+    async fn shared_queue_wakes_up_the_consumer() {
+        let queue = PacketQueue::new(Default::default());
+        let (popped, ()) = tokio::join!(queue.pop(), async {
+            tokio::task::yield_now().await;
+            queue.push(pk(1), vec![1]);
+        });
+        assert_eq!(popped, (pk(1), vec![1]));
+    }
+    #[test]
+    fn queued_bytes_follow_the_queues() {
+        let usage = Arc::new(QueuedBytes::default());
+        let queue = PacketQueue::new(usage.clone());
+        queue.push(pk(1), vec![0; 100]);
+        queue.push(pk(2), vec![0; 20]);
+        assert_eq!(usage.get(), 120);
+
+        for _ in 0..PEER_QUEUE_SIZE {
+            queue.push(pk(2), vec![0; 10]);
+        }
+        assert_eq!(usage.get(), 100 + PEER_QUEUE_SIZE * 10);
+
+        drop(queue);
+        assert_eq!(usage.get(), 0);
+    }
+}
@@ -0,0 +1,245 @@
+//! DSCP marking of the packets sent by a socket
+
+use std::io;
+
+use crate::native::NativeSocket;
+
+/// Highest value of the 6 bit DSCP field
+pub const MAX_DSCP: u8 = 63;
+
+/// Set the DSCP field of the packets sent by the socket
+///
+/// Dual stack sockets carry both IPv4 and IPv6 packets, so both the TOS and the traffic class are
+/// set, and it is enough for one of them to succeed.
+#[cfg(unix)]
+pub fn set_dscp(socket: NativeSocket, dscp: u8) -> io::Result<()> {
+    validate(dscp)?;
+    let tos = libc::c_int::from(dscp) << 2;
+    let set = |level, name| {
+        let res = unsafe {
+            libc::setsockopt(
+                socket,
+                level,
+                name,
+                &tos as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    };
+
+    let v4 = set(libc::IPPROTO_IP, libc::IP_TOS);
+    let v6 = set(libc::IPPROTO_IPV6, libc::IPV6_TCLASS);
+    v4.or(v6)
+}
+
+/// Set the DSCP field of the packets sent by the socket
+///
+/// Windows ignores `IP_TOS`, so the socket is added to a qWAVE flow instead. Setting the outgoing
+/// DSCP value of a flow requires administrator rights. A connected socket gets a single flow,
+/// unconnected ones only get the flows of the destinations added by [`add_destination`], which are
+/// updated here. Calling it again on a socket reuses its flows instead of adding new ones.
+#[cfg(windows)]
+pub fn set_dscp(socket: NativeSocket, dscp: u8) -> io::Result<()> {
+    validate(dscp)?;
+    let mut flows = qwave::FLOWS.lock();
+    let mut res = Ok(());
+    for (_, flow_id) in flows.iter().filter(|((s, _), _)| *s == socket) {
+        res = res.and(qwave::set_flow_dscp(*flow_id, dscp));
+    }
+    if qwave::is_connected(socket) && !flows.contains_key(&(socket, None)) {
+        let flow_id = qwave::add_flow(socket, None, dscp)?;
+        flows.insert((socket, None), flow_id);
+    }
+    res
+}
+
+/// Add the destination of an unconnected socket to a qWAVE flow marked with the DSCP value
+///
+/// qWAVE does not mark the packets of unconnected sockets unless their destination is part of a
+/// flow. Destinations which were already added are left as they are.
+#[cfg(windows)]
+pub fn add_destination(
+    socket: NativeSocket,
+    destination: std::net::SocketAddr,
+    dscp: u8,
+) -> io::Result<()> {
+    validate(dscp)?;
+    let mut flows = qwave::FLOWS.lock();
+    if let std::collections::hash_map::Entry::Vacant(entry) =
+        flows.entry((socket, Some(destination)))
+    {
+        entry.insert(qwave::add_flow(socket, Some(destination), dscp)?);
+    }
+    Ok(())
+}
+
+/// Forget the flows of a closed socket, qWAVE closes them together with the socket
+#[cfg(windows)]
+pub fn forget(socket: NativeSocket) {
+    qwave::FLOWS.lock().retain(|(s, _), _| *s != socket);
+}
+
+/// Mark the packets sent from the local UDP port with the DSCP value, or stop marking them
+///
+/// The WireGuard adapters on Windows send from sockets of their own, which can only be marked by a
+/// QoS policy matching their port. The policy is kept in the active store, so it does not outlive
+/// a reboot.
+#[cfg(windows)]
+pub fn set_port_dscp(port: Option<u16>, dscp: Option<u8>) -> io::Result<()> {
+    const POLICY_NAME: &str = "libtelio-wireguard-dscp";
+
+    let mut script = format!(
+        "Remove-NetQosPolicy -Name {POLICY_NAME} -PolicyStore ActiveStore -Confirm:$false \
+         -ErrorAction SilentlyContinue"
+    );
+    if let (Some(port), Some(dscp)) = (port, dscp) {
+        validate(dscp)?;
+        script.push_str(&format!(
+            "; New-NetQosPolicy -Name {POLICY_NAME} -PolicyStore ActiveStore \
+             -IPProtocolMatchCondition UDP -IPSrcPortMatchCondition {port} -DSCPAction {dscp} \
+             -NetworkProfile All -ErrorAction Stop"
+        ));
+    }
+
+    let output = std::process::Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ))
+    }
+}
+
+#[cfg(windows)]
+mod qwave {
+    use std::{collections::HashMap, io, net::SocketAddr, os::windows::io::BorrowedSocket};
+
+    use once_cell::sync::Lazy;
+    use parking_lot::Mutex;
+    use socket2::{SockAddr, SockRef};
+    use telio_utils::telio_log_warn;
+    use windows::Win32::{
+        Foundation::HANDLE,
+        NetworkManagement::QoS::{
+            QOSAddSocketToFlow, QOSCreateHandle, QOSSetFlow, QOSSetOutgoingDSCPValue,
+            QOSTrafficTypeExcellentEffort, QOS_NON_ADAPTIVE_FLOW, QOS_VERSION,
+        },
+        Networking::WinSock::{SOCKADDR, SOCKET},
+    };
+
+    use crate::native::NativeSocket;
+
+    /// Flows added so far, by socket and destination, the destination of connected sockets is None
+    pub(super) static FLOWS: Lazy<Mutex<HashMap<(NativeSocket, Option<SocketAddr>), u32>>> =
+        Lazy::new(Default::default);
+
+    // A single qWAVE handle is shared by the whole process, flows are closed with their sockets
+    static QOS_HANDLE: Lazy<Option<isize>> = Lazy::new(|| {
+        let version = QOS_VERSION {
+            MajorVersion: 1,
+            MinorVersion: 0,
+        };
+        let mut handle = HANDLE::default();
+        match unsafe { QOSCreateHandle(&version, &mut handle) } {
+            Ok(()) => Some(handle.0),
+            Err(err) => {
+                telio_log_warn!("Failed to create qWAVE handle: {}", err);
+                None
+            }
+        }
+    });
+
+    fn handle() -> io::Result<HANDLE> {
+        Ok(HANDLE((*QOS_HANDLE).ok_or_else(|| {
+            io::Error::from(io::ErrorKind::Unsupported)
+        })?))
+    }
+
+    pub(super) fn is_connected(socket: NativeSocket) -> bool {
+        let socket = unsafe { BorrowedSocket::borrow_raw(socket) };
+        SockRef::from(&socket).peer_addr().is_ok()
+    }
+
+    pub(super) fn add_flow(
+        socket: NativeSocket,
+        destination: Option<SocketAddr>,
+        dscp: u8,
+    ) -> io::Result<u32> {
+        let destination = destination.map(SockAddr::from);
+        let mut flow_id = 0;
+        unsafe {
+            QOSAddSocketToFlow(
+                handle()?,
+                SOCKET(socket as usize),
+                destination
+                    .as_ref()
+                    .map(|addr| addr.as_ptr() as *const SOCKADDR),
+                QOSTrafficTypeExcellentEffort,
+                QOS_NON_ADAPTIVE_FLOW,
+                &mut flow_id,
+            )?;
+        }
+        set_flow_dscp(flow_id, dscp)?;
+        Ok(flow_id)
+    }
+
+    pub(super) fn set_flow_dscp(flow_id: u32, dscp: u8) -> io::Result<()> {
+        let dscp = u32::from(dscp);
+        unsafe {
+            QOSSetFlow(
+                handle()?,
+                flow_id,
+                QOSSetOutgoingDSCPValue,
+                std::mem::size_of::<u32>() as u32,
+                &dscp as *const u32 as *const std::ffi::c_void,
+                0,
+                None,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn validate(dscp: u8) -> io::Result<()> {
+    if dscp > MAX_DSCP {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("DSCP value {dscp} does not fit in 6 bits"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::net::UdpSocket;
+
+    use socket2::SockRef;
+
+    use super::*;
+    use crate::native::AsNativeSocket;
+
+    #[test]
+    fn test_set_dscp_marks_outgoing_packets() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        set_dscp(socket.as_native_socket(), 46).unwrap();
+        assert_eq!(SockRef::from(&socket).tos().unwrap(), 46 << 2);
+    }
+
+    #[test]
+    fn test_set_dscp_rejects_values_above_6_bits() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let err = set_dscp(socket.as_native_socket(), MAX_DSCP + 1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(SockRef::from(&socket).tos().unwrap(), 0);
+    }
+}
@@ -1,9 +1,12 @@
+mod dscp;
+mod network;
 mod socket_pool;
 
 pub mod native;
 pub mod protector;
 pub mod socket_params;
 
+pub use dscp::MAX_DSCP;
 pub use protector::{NativeProtector, Protect, Protector};
 pub use socket_params::{SocketBufSizes, TcpParams, UdpParams};
 pub use socket_pool::{External, SocketPool};
@@ -0,0 +1,30 @@
+//! Binding of sockets to the network given by the app
+
+use std::io;
+
+use crate::native::NativeSocket;
+
+/// Bind the socket to the network, its packets then go out through it whichever network is the
+/// default one
+///
+/// The handle is the one returned by `android.net.Network.getNetworkHandle()`.
+#[cfg(target_os = "android")]
+pub fn bind_to_network(socket: NativeSocket, network: u64) -> io::Result<()> {
+    #[link(name = "android")]
+    extern "C" {
+        // Available since API level 23
+        fn android_setsocknetwork(network: u64, fd: libc::c_int) -> libc::c_int;
+    }
+
+    if unsafe { android_setsocknetwork(network, socket) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Network handles are only given on Android, elsewhere the sockets follow the routes
+#[cfg(not(target_os = "android"))]
+pub fn bind_to_network(_socket: NativeSocket, _network: u64) -> io::Result<()> {
+    Ok(())
+}
@@ -466,16 +466,21 @@ fn get_dynamic_store(sockets: Weak<Mutex<Sockets>>) -> SCDynamicStore {
 }
 
 fn spawn_dynamic_store_loop(sockets: Weak<Mutex<Sockets>>) {
-    std::thread::spawn(|| {
-        let store = get_dynamic_store(sockets);
-        let run_loop_source = store.create_run_loop_source();
-        let run_loop = core_foundation::runloop::CFRunLoop::get_current();
-        run_loop.add_source(&run_loop_source, unsafe {
-            core_foundation::runloop::kCFRunLoopCommonModes
+    let handle = std::thread::Builder::new()
+        .name("libtelio-netstore".to_owned())
+        .spawn(|| {
+            let store = get_dynamic_store(sockets);
+            let run_loop_source = store.create_run_loop_source();
+            let run_loop = core_foundation::runloop::CFRunLoop::get_current();
+            run_loop.add_source(&run_loop_source, unsafe {
+                core_foundation::runloop::kCFRunLoopCommonModes
+            });
+
+            core_foundation::runloop::CFRunLoop::run_current();
         });
-
-        core_foundation::runloop::CFRunLoop::run_current();
-    });
+    if let Err(e) = handle {
+        telio_log_error!("Failed to start the dynamic store thread: {e:?}");
+    }
 }
 
 pub fn setup_network_path_monitor() {
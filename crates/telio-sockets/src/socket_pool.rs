@@ -1,4 +1,6 @@
 use std::{
+    collections::HashSet,
+    future::Future,
     io,
     net::SocketAddr,
     ops::{Deref, DerefMut},
@@ -7,29 +9,38 @@ use std::{
     task::Poll,
 };
 
+use parking_lot::Mutex;
 use socket2::{Domain, Protocol, Socket, Type};
 
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::{TcpSocket, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::watch,
 };
 
 #[cfg(unix)]
 use neptun::device::MakeExternalNeptun;
-use telio_utils::{telio_log_debug, telio_log_warn};
+use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
 
 use crate::{
+    dscp::{self, MAX_DSCP},
     native::{AsNativeSocket, NativeSocket},
-    Protector, TcpParams, UdpParams,
+    network, Protector, TcpParams, UdpParams,
 };
 
 struct SocketGuard {
     socket: NativeSocket,
     protector: ArcProtector,
+    externals: Externals,
+    #[cfg(windows)]
+    dscp: Dscp,
 }
 
 impl Drop for SocketGuard {
     fn drop(&mut self) {
+        self.externals.lock().remove(&self.socket);
+        #[cfg(windows)]
+        dscp::forget(self.socket);
         self.protector.clean(self.socket)
     }
 }
@@ -42,18 +53,88 @@ pub struct External<T: AsNativeSocket> {
 #[derive(Clone)]
 pub struct SocketPool {
     protect: ArcProtector,
+    /// External sockets which are still open
+    externals: Externals,
+    /// Handle of the default network, as last reported by the app
+    default_network: Arc<watch::Sender<Option<u64>>>,
+    /// DSCP value to mark the packets of the external sockets with
+    dscp: Dscp,
+    /// Local port of the WireGuard adapter, whose sockets are not ours to mark
+    #[cfg(windows)]
+    wireguard_port: Arc<Mutex<Option<u16>>>,
 }
 
 type ArcProtector = Arc<dyn Protector>;
+type Externals = Arc<Mutex<HashSet<NativeSocket>>>;
+type Dscp = Arc<Mutex<Option<u8>>>;
 
 impl External<TcpSocket> {
     pub async fn connect(self, addr: SocketAddr) -> io::Result<External<TcpStream>> {
         let Self { guard, socket } = self;
         let socket = socket.connect(addr).await?;
+        // qWAVE flows can only be set up for connected sockets
+        #[cfg(windows)]
+        guard.apply_dscp(None);
         Ok(External { socket, guard })
     }
 }
 
+impl External<UdpSocket> {
+    /// Connect the socket like [UdpSocket::connect]
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.socket.connect(addr).await?;
+        // qWAVE flows can only be set up for connected sockets
+        #[cfg(windows)]
+        self.guard.apply_dscp(None);
+        Ok(())
+    }
+
+    /// Send a datagram like [UdpSocket::send_to]
+    ///
+    /// On Windows qWAVE only marks the datagrams of unconnected sockets going to destinations
+    /// added to a flow, so each new destination is added first.
+    pub async fn send_to<A: ToSocketAddrs>(&self, buf: &[u8], target: A) -> io::Result<usize> {
+        #[cfg(windows)]
+        return self.send_to_marked(buf, target).await;
+
+        #[cfg(not(windows))]
+        self.socket.send_to(buf, target).await
+    }
+
+    #[cfg(windows)]
+    async fn send_to_marked<A: ToSocketAddrs>(&self, buf: &[u8], target: A) -> io::Result<usize> {
+        let mut last_err = None;
+        for addr in tokio::net::lookup_host(target).await? {
+            self.guard.apply_dscp(Some(addr));
+            match self.socket.send_to(buf, addr).await {
+                Ok(len) => return Ok(len),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+}
+
+#[cfg(windows)]
+impl SocketGuard {
+    fn apply_dscp(&self, destination: Option<SocketAddr>) {
+        if let Some(dscp) = *self.dscp.lock() {
+            let res = match destination {
+                Some(destination) => dscp::add_destination(self.socket, destination, dscp),
+                None => dscp::set_dscp(self.socket, dscp),
+            };
+            if let Err(err) = res {
+                telio_log_warn!("Failed to set DSCP of socket {}: {}", self.socket, err);
+            }
+        }
+    }
+}
+
 impl<T: AsNativeSocket> Deref for External<T> {
     type Target = T;
 
@@ -112,12 +193,146 @@ impl SocketPool {
     pub fn new<T: Protector + 'static>(protect: T) -> Self {
         Self {
             protect: Arc::new(protect),
+            externals: Default::default(),
+            default_network: Arc::new(watch::channel(None).0),
+            dscp: Default::default(),
+            #[cfg(windows)]
+            wireguard_port: Default::default(),
+        }
+    }
+
+    /// Mark the packets of the external sockets with the DSCP value, including the already
+    /// opened ones
+    ///
+    /// The sockets of the NepTUN adapter are external too, so the WireGuard packets get marked
+    /// along with the relay connections, letting the network prioritize them.
+    pub fn set_dscp(&self, dscp: u8) {
+        if dscp > MAX_DSCP {
+            telio_log_warn!("Ignoring invalid DSCP value {}", dscp);
+            return;
         }
+        *self.dscp.lock() = Some(dscp);
+        for socket in self.externals.lock().iter() {
+            self.apply_dscp(*socket);
+        }
+        #[cfg(windows)]
+        self.apply_wireguard_port_dscp();
     }
 
+    /// Mark the packets sent from the local port of the WireGuard adapter, None once it stops
+    ///
+    /// The WireGuard adapters on Windows do not send through the external sockets, so their
+    /// packets are marked by a QoS policy on the port instead.
+    #[cfg(windows)]
+    pub fn set_wireguard_port(&self, port: Option<u16>) {
+        let changed = {
+            let mut current = self.wireguard_port.lock();
+            std::mem::replace(&mut *current, port) != port
+        };
+        if changed {
+            self.apply_wireguard_port_dscp();
+        }
+    }
+
+    #[cfg(windows)]
+    fn apply_wireguard_port_dscp(&self) {
+        let (port, dscp) = (*self.wireguard_port.lock(), *self.dscp.lock());
+        if dscp.is_none() {
+            return;
+        }
+        if let Err(err) = dscp::set_port_dscp(port, dscp) {
+            telio_log_warn!("Failed to set DSCP of WireGuard port {:?}: {}", port, err);
+        }
+    }
+
+    fn apply_dscp(&self, socket: NativeSocket) {
+        if let Some(dscp) = *self.dscp.lock() {
+            if let Err(err) = dscp::set_dscp(socket, dscp) {
+                telio_log_warn!("Failed to set DSCP of socket {}: {}", socket, err);
+            }
+        }
+    }
+
+    /// Set the fwmark of the external sockets, including the already opened ones
+    ///
+    /// Long lived connections, like the one to the relay server, would otherwise keep
+    /// being routed into the tunnel once it covers the default route.
     #[cfg(target_os = "linux")]
     pub fn set_fwmark(&self, fwmark: u32) {
         self.protect.set_fwmark(fwmark);
+        for socket in self.externals.lock().iter() {
+            if let Err(err) = self.protect.make_external(*socket) {
+                telio_log_warn!("Failed to set fwmark of socket {}: {}", socket, err);
+            }
+        }
+    }
+
+    /// Rebind the external sockets to the new default network
+    ///
+    /// On Android, protected sockets stay on the network they were opened on, and get stuck after
+    /// a Wi-Fi/mobile handover. The open external sockets are protected again and bound to the
+    /// new network, as are the ones opened from now on, and the operations running in
+    /// [SocketPool::retry_on_rebind] are retried. Returns whether the network has changed.
+    pub fn set_default_network(&self, network: u64) -> bool {
+        let changed = self.default_network.send_if_modified(|current| {
+            let changed = *current != Some(network);
+            *current = Some(network);
+            changed
+        });
+        if changed {
+            telio_log_info!("Default network is now {}, rebinding sockets", network);
+            for socket in self.externals.lock().iter() {
+                if let Err(err) = self
+                    .protect
+                    .make_external(*socket)
+                    .and_then(|_| network::bind_to_network(*socket, network))
+                {
+                    telio_log_warn!("Failed to rebind socket {}: {}", socket, err);
+                }
+            }
+        }
+        changed
+    }
+
+    fn bind_to_default_network(&self, socket: NativeSocket) {
+        if let Some(network) = *self.default_network.borrow() {
+            if let Err(err) = network::bind_to_network(socket, network) {
+                telio_log_warn!(
+                    "Failed to bind socket {} to network {}: {}",
+                    socket,
+                    network,
+                    err
+                );
+            }
+        }
+    }
+
+    /// Run an operation on external sockets, retrying it once if the default network changes
+    /// while it is in flight
+    ///
+    /// The operation is abandoned as soon as the network changes, since its sockets may be stuck
+    /// on the old network, so it should open the sockets it uses.
+    pub async fn retry_on_rebind<T, F, Fut>(&self, mut op: F) -> io::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        let mut rebinds = self.default_network.subscribe();
+        rebinds.borrow_and_update();
+
+        tokio::select! {
+            res = op() => match res {
+                Err(err) if rebinds.has_changed().unwrap_or(false) => {
+                    telio_log_debug!("Retrying operation failed on the old network: {}", err);
+                    op().await
+                }
+                res => res,
+            },
+            Ok(()) = rebinds.changed() => {
+                telio_log_debug!("Retrying operation in flight on the old network");
+                op().await
+            }
+        }
     }
 
     #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos", windows))]
@@ -128,13 +343,28 @@ impl SocketPool {
     pub fn new_external_tcp_v4(
         &self,
         params: Option<TcpParams>,
+    ) -> io::Result<External<TcpSocket>> {
+        self.new_external_tcp(Domain::IPV4, params)
+    }
+
+    pub fn new_external_tcp_v6(
+        &self,
+        params: Option<TcpParams>,
+    ) -> io::Result<External<TcpSocket>> {
+        self.new_external_tcp(Domain::IPV6, params)
+    }
+
+    fn new_external_tcp(
+        &self,
+        domain: Domain,
+        params: Option<TcpParams>,
     ) -> io::Result<External<TcpSocket>> {
         let ty = Type::STREAM;
 
         #[cfg(any(target_os = "android", target_os = "linux"))]
         let ty = ty.nonblocking();
 
-        let socket2_socket = Socket::new(Domain::IPV4, ty, Some(Protocol::TCP))?;
+        let socket2_socket = Socket::new(domain, ty, Some(Protocol::TCP))?;
 
         #[cfg(not(any(target_os = "android", target_os = "linux")))]
         socket2_socket.set_nonblocking(true)?;
@@ -144,7 +374,8 @@ impl SocketPool {
         }
 
         telio_log_debug!(
-            "Creating external tcp_v4 socket: {}",
+            "Creating external tcp socket ({:?}): {}",
+            domain,
             socket2_socket.as_native_socket()
         );
 
@@ -209,11 +440,17 @@ impl SocketPool {
 
     fn new_external<T: AsNativeSocket>(&self, socket: T) -> io::Result<External<T>> {
         self.protect.make_external(socket.as_native_socket())?;
+        self.bind_to_default_network(socket.as_native_socket());
+        self.apply_dscp(socket.as_native_socket());
+        self.externals.lock().insert(socket.as_native_socket());
 
         Ok(External {
             guard: SocketGuard {
                 protector: self.protect.clone(),
                 socket: socket.as_native_socket(),
+                externals: self.externals.clone(),
+                #[cfg(windows)]
+                dscp: self.dscp.clone(),
             },
             socket,
         })
@@ -224,6 +461,8 @@ impl SocketPool {
 impl MakeExternalNeptun for SocketPool {
     fn make_external(&self, socket: NativeSocket) {
         let _ = self.protect.make_external(socket);
+        self.bind_to_default_network(socket);
+        self.apply_dscp(socket);
     }
 }
 
@@ -232,7 +471,10 @@ mod tests {
     use std::{
         io::ErrorKind,
         net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4},
-        sync::Mutex,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
     };
 
     use mockall::mock;
@@ -307,6 +549,116 @@ mod tests {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn set_fwmark_protects_open_sockets() {
+        let mut protect = MockProtector::default();
+
+        let socks = Arc::new(Mutex::new(Vec::new()));
+
+        protect.expect_make_external().returning({
+            let socks = socks.clone();
+            move |s| {
+                socks.lock().unwrap().push(s);
+                Ok(())
+            }
+        });
+        protect.expect_set_fwmark().return_const(()).times(2);
+        protect.expect_clean().return_const(());
+
+        let pool = SocketPool::new(protect);
+        let tcp = pool.new_external_tcp_v4(None).expect("tcp");
+        {
+            let _closed = pool.new_external_tcp_v4(None).expect("tcp");
+        }
+        socks.lock().unwrap().clear();
+
+        pool.set_fwmark(11673110);
+        assert_eq!(socks.lock().unwrap().clone(), vec![tcp.as_native_socket()]);
+
+        drop(tcp);
+        pool.set_fwmark(11673110);
+        assert!(socks.lock().unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn set_dscp_marks_open_and_new_sockets() {
+        let mut protect = MockProtector::default();
+        protect.expect_make_external().returning(|_| Ok(()));
+        protect.expect_clean().return_const(());
+
+        let pool = SocketPool::new(protect);
+        let tos = |socket: &TcpSocket| socket2::SockRef::from(socket).tos().unwrap();
+
+        let open = pool.new_external_tcp_v4(None).expect("tcp");
+        assert_eq!(tos(&open), 0);
+
+        pool.set_dscp(46);
+        let new = pool.new_external_tcp_v4(None).expect("tcp");
+        assert_eq!(tos(&open), 46 << 2);
+        assert_eq!(tos(&new), 46 << 2);
+
+        pool.set_dscp(MAX_DSCP + 1);
+        assert_eq!(tos(&open), 46 << 2);
+    }
+
+    #[tokio::test]
+    async fn set_default_network_rebinds_open_sockets() {
+        let mut protect = MockProtector::default();
+
+        let socks = Arc::new(Mutex::new(Vec::new()));
+
+        protect.expect_make_external().returning({
+            let socks = socks.clone();
+            move |s| {
+                socks.lock().unwrap().push(s);
+                Ok(())
+            }
+        });
+        protect.expect_clean().return_const(());
+
+        let pool = SocketPool::new(protect);
+        let tcp = pool.new_external_tcp_v4(None).expect("tcp");
+        socks.lock().unwrap().clear();
+
+        assert!(pool.set_default_network(100));
+        assert_eq!(socks.lock().unwrap().clone(), vec![tcp.as_native_socket()]);
+
+        socks.lock().unwrap().clear();
+        assert!(!pool.set_default_network(100));
+        assert!(socks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn operation_in_flight_is_retried_on_rebind() {
+        let pool = SocketPool::new(MockProtector::default());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let op = tokio::spawn({
+            let pool = pool.clone();
+            let calls = calls.clone();
+            async move {
+                pool.retry_on_rebind(|| {
+                    let call = calls.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if call == 0 {
+                            // Stuck on the old network
+                            futures::future::pending::<()>().await;
+                        }
+                        Ok(call)
+                    }
+                })
+                .await
+            }
+        });
+        tokio::task::yield_now().await;
+
+        pool.set_default_network(100);
+        assert_eq!(op.await.unwrap().unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
     #[tokio::test]
     async fn create_socket_with_protect_fn() {
         let socks = Arc::new(Mutex::new(Vec::new()));
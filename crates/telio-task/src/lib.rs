@@ -19,3 +19,4 @@ pub mod io;
 pub use macros::*;
 pub use task::BoxAction;
 pub use task::*;
+pub use tokio_util::sync::CancellationToken;
@@ -1,4 +1,4 @@
-use std::{any::Any, sync::Arc};
+use std::{any::Any, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use futures::{
@@ -9,8 +9,9 @@ use tokio::{
     sync::{oneshot, Notify},
     task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
 
-use telio_utils::telio_log_warn;
+use telio_utils::{telio_log_debug, telio_log_error, telio_log_warn};
 
 use crate::io::{
     chan::{Rx, Tx},
@@ -81,6 +82,21 @@ pub trait RuntimeExt: Runtime {
 
 impl<T> RuntimeExt for T where T: Runtime {}
 
+/// How long [Task::stop] waits for a task to drain before aborting it
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+tokio::task_local! {
+    /// Cancellation token of the task within which the current code runs
+    static CURRENT_CANCELLATION: CancellationToken;
+}
+
+/// Run `future` so that every [Task] started within it is cancelled along with `token`
+///
+/// Tasks started from within another task's wait or exec are already children of that task.
+pub async fn cancellation_scope<F: Future>(token: CancellationToken, future: F) -> F::Output {
+    CURRENT_CANCELLATION.scope(token, future).await
+}
+
 /// A general runtime for components
 ///
 /// This task should be used in components requiring long running actions.
@@ -89,8 +105,13 @@ impl<T> RuntimeExt for T where T: Runtime {}
 ///   * Graceful stop. (On drop or with stop)
 ///   * Wait entry to wait for outside triggers. (Like [Rx], sockets, timers etc)
 ///   * Ability to execute mutation on state without requiring mutex'es
+///
+/// Tasks form a hierarchy: a task started while running within another task (or within a
+/// [cancellation_scope]) is stopped once its parent is cancelled. On [Task::stop] the state gets
+/// the chance to stop its children in order, whatever is left is cancelled afterwards.
 pub struct Task<S: Runtime> {
     stop: Arc<Notify>,
+    children: CancellationToken,
     execute: Tx<Update<S, S::Err>>,
     join: Option<JoinHandle<Result<(), S::Err>>>,
 }
@@ -109,6 +130,8 @@ pub enum StopResult<E> {
     Err(E),
     /// Task panic'ed
     Panic(Box<dyn Any + Send + 'static>),
+    /// Task did not stop in time and was aborted
+    Timeout,
 }
 
 /// Response from [Runtime::wait].
@@ -152,29 +175,48 @@ where
             rx: execute_rx,
         } = Chan::<Update<S, S::Err>>::default();
 
+        // Cancelled along with the parent, or once this task is stopped
+        let children = CURRENT_CANCELLATION
+            .try_with(|parent| parent.child_token())
+            .unwrap_or_else(|_| CancellationToken::new());
+
         let stopped = stop.clone();
-        let join = Some(tokio::spawn(async move {
-            tokio::select! {
-                res = Self::run_loop(&mut state, execute_rx) => {
-                    state.stop().await;
-                    res
-                },
-                _ = stopped.notified() => {
-                    state.stop().await;
-                    Ok(())
-                },
-            }
-        }));
+        let cancelled = children.clone();
+        let join = Some(tokio::spawn(CURRENT_CANCELLATION.scope(
+            children.clone(),
+            async move {
+                tokio::select! {
+                    res = Self::run_loop(&mut state, execute_rx) => {
+                        state.stop().await;
+                        res
+                    },
+                    _ = stopped.notified() => {
+                        state.stop().await;
+                        Ok(())
+                    },
+                    _ = cancelled.cancelled() => {
+                        state.stop().await;
+                        Ok(())
+                    },
+                }
+            },
+        )));
 
         println!("task started - {}", S::NAME);
 
         Self {
             stop,
+            children,
             execute,
             join,
         }
     }
 
+    /// Token cancelled when this task is stopped, for components tied to the task's lifetime
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.children.clone()
+    }
+
     /// Execute action with exclusive access on state
     #[allow(mpsc_blocking_send)]
     pub async fn exec<A, V>(&self, action: A) -> Result<V, ExecError>
@@ -198,21 +240,39 @@ where
         }
     }
 
-    /// Stop task
-    pub async fn stop(mut self) -> StopResult<S::Err> {
+    /// Stop task, waiting at most [DEFAULT_STOP_TIMEOUT] for it
+    pub async fn stop(self) -> StopResult<S::Err> {
+        self.stop_with_timeout(DEFAULT_STOP_TIMEOUT).await
+    }
+
+    /// Stop task, aborting it if it does not stop within `timeout`
+    pub async fn stop_with_timeout(mut self, timeout: Duration) -> StopResult<S::Err> {
         println!("task stopped - {}", S::NAME);
         self.stop.notify_one();
-        let join = match self.join.take() {
+        let mut join = match self.join.take() {
             Some(v) => v,
             None => return StopResult::Ok,
         };
 
-        match join.await {
-            Ok(Ok(())) => StopResult::Ok,
-            Ok(Err(e)) => StopResult::Err(e),
-            Err(e) if e.is_panic() => StopResult::Panic(e.into_panic()),
-            _ => StopResult::Ok,
-        }
+        let res = match tokio::time::timeout(timeout, &mut join).await {
+            Ok(Ok(Ok(()))) => StopResult::Ok,
+            Ok(Ok(Err(e))) => StopResult::Err(e),
+            Ok(Err(e)) if e.is_panic() => StopResult::Panic(e.into_panic()),
+            Ok(Err(_)) => StopResult::Ok,
+            Err(_) => {
+                telio_log_error!(
+                    "Task [{}] failed to stop within {:?}, aborting it",
+                    S::NAME,
+                    timeout
+                );
+                join.abort();
+                StopResult::Timeout
+            }
+        };
+
+        // Cancel children the task did not stop by itself
+        self.children.cancel();
+        res
     }
 
     async fn run_loop(state: &mut S, mut execed: Rx<Update<S, S::Err>>) -> Result<(), S::Err> {
@@ -222,6 +282,12 @@ where
                     Box::new(move |s: &mut S| {
                         async move {
                             if let Some((action, resp)) = e {
+                                // Caller gave up on the action before it got its turn, e.g.
+                                // after a timeout, so it is not run behind the caller's back
+                                if resp.is_closed() {
+                                    telio_log_debug!("Task's {} exec dropped by caller.", S::NAME);
+                                    return Ok(());
+                                }
                                 let _ = resp.send(action(s).await?);
                                 Ok(())
                             } else {
@@ -241,6 +307,7 @@ impl<S: Runtime> Drop for Task<S> {
     fn drop(&mut self) {
         if self.join.is_some() {
             self.stop.notify_waiters();
+            self.children.cancel();
             telio_log_warn!("Task [{}] was not stopped.", S::NAME);
         }
     }
@@ -262,13 +329,20 @@ impl<E> StopResult<E> {
         matches!(self, &StopResult::Panic(_))
     }
 
+    /// Aborted after not stopping in time
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, &StopResult::Timeout)
+    }
+
     /// Return Ok for proper stop or Err if internal error occurred
     ///
+    /// Timeouts are already reported when they happen, so they are not treated as errors.
+    ///
     /// # Panics
     /// Propagates panic if task stopped due to panic
     pub fn resume_unwind(self) -> Result<(), E> {
         match self {
-            Self::Ok => Ok(()),
+            Self::Ok | Self::Timeout => Ok(()),
             Self::Err(e) => Err(e),
             Self::Panic(p) => std::panic::resume_unwind(p),
         }
@@ -281,6 +355,7 @@ impl<E: PartialEq> PartialEq for StopResult<E> {
             (&Self::Ok, &Self::Ok) => true,
             (&Self::Err(el), &Self::Err(er)) => el == er,
             (&Self::Panic(_), &Self::Panic(_)) => true,
+            (&Self::Timeout, &Self::Timeout) => true,
             _ => false,
         }
     }
@@ -518,6 +593,72 @@ mod tests {
         let _ = test.stop().await.resume_unwind();
     }
 
+    struct Stuck;
+
+    #[async_trait]
+    impl Runtime for Stuck {
+        const NAME: &'static str = "Stuck";
+
+        type Err = ();
+
+        async fn stop(self) {
+            pending::<()>().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stuck_task_is_aborted() {
+        let task = Task::start(Stuck);
+        assert!(task
+            .stop_with_timeout(Duration::from_millis(100))
+            .await
+            .is_timeout());
+    }
+
+    #[tokio::test]
+    async fn test_parent_cancellation_stops_children() {
+        let (lc, _rc) = Chan::pipe();
+        let (stop, stopped) = oneshot::channel();
+        let parent = CancellationToken::new();
+        let test =
+            cancellation_scope(parent.clone(), async { Test::new(Io { msg: lc, stop }) }).await;
+
+        parent.cancel();
+        assert_eq!(Ok("stopped"), stopped.await);
+        assert!(test.stop().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_exec_dropped_before_its_turn_is_not_run() {
+        let (lc, _rc) = Chan::pipe();
+        let (stop, _stopped) = oneshot::channel();
+        let test = Test::new(Io { msg: lc, stop });
+
+        let (release, released) = oneshot::channel::<()>();
+        let busy = task_exec!(&test.task, async move |_s| {
+            let _ = released.await;
+            Ok(())
+        });
+        let dropped = async {
+            // Queued behind the busy action and given up before it runs
+            assert!(timeout(
+                Duration::from_millis(100),
+                task_exec!(&test.task, async move |s| {
+                    s.buf.push("dropped");
+                    Ok(())
+                })
+            )
+            .await
+            .is_err());
+            let _ = release.send(());
+        };
+        let (busy, ()) = tokio::join!(busy, dropped);
+
+        assert!(busy.is_ok());
+        assert_eq!(Some(Vec::new()), test.test_get().await);
+        assert_eq!(Ok(()), test.stop().await.resume_unwind());
+    }
+
     #[tokio::test]
     async fn test_sleep_cancellation() {
         let (lc, mut rc) = Chan::pipe();
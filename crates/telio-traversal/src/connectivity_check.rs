@@ -7,7 +7,7 @@ use thiserror::Error as TError;
 use telio_model::{features::EndpointProvider, SocketAddr};
 
 use telio_crypto::PublicKey;
-use telio_proto::{CallMeMaybeMsg, Session};
+use telio_proto::{CallMeMaybeMsg, PunchRequestMsg, Session};
 use tokio::time::Instant;
 
 #[derive(Debug, TError)]
@@ -22,6 +22,10 @@ pub enum Error {
     RespondingToCMMInitiatorError(
         #[from] tokio::sync::mpsc::error::SendError<(PublicKey, CallMeMaybeMsg)>,
     ),
+    #[error("Error sending punch request")]
+    SendingPunchRequestError(
+        #[from] tokio::sync::mpsc::error::SendError<(PublicKey, PunchRequestMsg)>,
+    ),
     #[error("Error publishing WG endpoint")]
     PublishingWireGuardEndpointError(
         #[from] Box<tokio::sync::mpsc::error::SendError<WireGuardEndpointCandidateChangeEvent>>,
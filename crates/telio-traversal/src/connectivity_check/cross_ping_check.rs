@@ -16,23 +16,39 @@ use std::fmt::Debug;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Formatter,
 };
 use telio_crypto::PublicKey;
-use telio_model::{config::Config, features::EndpointProvider as ApiEndpointProvider, SocketAddr};
-use telio_proto::{CallMeMaybeMsg, CallMeMaybeType, Session};
+use telio_model::{
+    config::{Config, PeerCapability},
+    features::{EndpointProvider as ApiEndpointProvider, FeatureCoordinatedPunch},
+    mesh::{
+        AddressFamily, CandidateCheckState, FamilyCheckStats, LocalCandidate, PeerCandidates,
+        RemoteCandidate,
+    },
+    SocketAddr,
+};
+use telio_proto::{CallMeMaybeMsg, CallMeMaybeType, ControlHeader, PunchRequestMsg, Session};
 use telio_task::{io::chan, io::Chan, task_exec, BoxAction, Runtime, Task};
 use telio_utils::{
     exponential_backoff::{Backoff, ExponentialBackoff, ExponentialBackoffBounds},
     interval, telio_log_debug, telio_log_info, telio_log_trace, telio_log_warn, LruCache,
 };
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 use tokio::time::{Instant, Interval};
 
 const CPC_TIMEOUT: Duration = Duration::from_secs(10);
 const UPGRADE_TIMEOUT: Duration = Duration::from_secs(60);
 const MAX_SESSION_CANDIDATES: usize = 512;
+/// Number of pings sent to each address during a coordinated punch
+const PUNCH_BURST_LEN: u32 = 5;
+/// Spacing of the punch pings, covers the clock and latency skew between the two sides
+const PUNCH_BURST_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Connectivity check outcomes per address family
+pub type FamilyStats = BTreeMap<AddressFamily, FamilyCheckStats>;
 
 #[cfg_attr(any(test, feature = "mockall"), mockall::automock)]
 #[async_trait]
@@ -55,6 +71,17 @@ pub trait CrossPingCheckTrait: UpgradeController {
         &self,
         public_key: PublicKey,
     ) -> Result<(), Error>;
+    /// Take the per address family outcomes of the checks done since the last call
+    async fn collect_family_stats(&self) -> Result<FamilyStats, Error>;
+    /// Peers known to be offline, no call me maybe requests are sent to them
+    async fn set_offline_peers(&self, offline_peers: HashSet<PublicKey>) -> Result<(), Error>;
+    /// Local and remote candidates of every session with the peer
+    async fn get_peer_candidates(&self, public_key: PublicKey) -> Result<PeerCandidates, Error>;
+    /// Whether the relay carrying the call me maybe requests is connected, the requests lost
+    /// while it was not are resent once it reconnects
+    async fn set_relay_connected(&self, connected: bool) -> Result<(), Error>;
+    /// Whether the device is suspended, no checks are made while it is
+    async fn set_suspended(&self, suspended: bool) -> Result<(), Error>;
 }
 
 #[cfg(any(test, feature = "mockall"))]
@@ -71,6 +98,11 @@ mockall::mock! {
             &self,
             public_key: PublicKey,
         ) -> Result<(), Error>;
+        async fn collect_family_stats(&self) -> Result<FamilyStats, Error>;
+        async fn set_offline_peers(&self, offline_peers: HashSet<PublicKey>) -> Result<(), Error>;
+        async fn get_peer_candidates(&self, public_key: PublicKey) -> Result<PeerCandidates, Error>;
+        async fn set_relay_connected(&self, connected: bool) -> Result<(), Error>;
+        async fn set_suspended(&self, suspended: bool) -> Result<(), Error>;
     }
 
     #[async_trait]
@@ -111,6 +143,12 @@ pub struct Io {
     /// packet Pipes for sending messages to each other. Therefore this stores the instance of
     /// those channels
     pub intercoms: Chan<(PublicKey, CallMeMaybeMsg)>,
+
+    /// Coordinated punch requests (over DERP) communication channel
+    ///
+    /// Requests from other nodes are always served, our own are only sent when coordinated
+    /// punching is enabled
+    pub punch_requests: Chan<(PublicKey, PunchRequestMsg)>,
 }
 
 type ExponentialBackoffProvider<E> = Box<dyn Fn() -> Result<E, Error> + Send>;
@@ -161,6 +199,24 @@ pub struct State<E: Backoff> {
 
     /// Session IDs received from other nodes in CMM requests
     session_id_candidates: LruCache<Session, PublicKey>,
+
+    /// Coordinated punching configuration, copied into every new session
+    coordinated_punch: Option<FeatureCoordinatedPunch>,
+
+    /// Pinging rounds started and succeeded per address family, taken by analytics
+    family_stats: FamilyStats,
+
+    /// Peers reported offline by the relay, copied into every new session
+    offline_peers: HashSet<PublicKey>,
+
+    /// Control headers from the latest control messages of the peers, copied into every new session
+    peer_controls: HashMap<PublicKey, ControlHeader>,
+
+    /// Whether the relay is connected, call me maybe requests sent otherwise are likely lost
+    relay_connected: bool,
+
+    /// Whether the device is suspended, the checks are held back until it resumes
+    suspended: bool,
 }
 
 impl<E: Backoff> CrossPingCheck<E> {
@@ -171,6 +227,7 @@ impl<E: Backoff> CrossPingCheck<E> {
         poll_period: Duration,
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         exponential_backoff_helper_provider: ExponentialBackoffProvider<E>,
+        coordinated_punch: Option<FeatureCoordinatedPunch>,
     ) -> Self {
         let poll_timer = interval(poll_period);
         Self {
@@ -185,6 +242,12 @@ impl<E: Backoff> CrossPingCheck<E> {
                 ping_pong_handler,
                 exponential_backoff_helper_provider,
                 session_id_candidates: LruCache::new(UPGRADE_TIMEOUT, MAX_SESSION_CANDIDATES),
+                coordinated_punch,
+                family_stats: BTreeMap::new(),
+                offline_peers: HashSet::new(),
+                peer_controls: HashMap::new(),
+                relay_connected: true,
+                suspended: false,
             }),
         }
     }
@@ -198,6 +261,7 @@ impl CrossPingCheck {
         poll_period: Duration,
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         exponential_backoff_bounds: ExponentialBackoffBounds,
+        coordinated_punch: Option<FeatureCoordinatedPunch>,
     ) -> Self {
         telio_log_info!("Starting cross ping check");
 
@@ -210,6 +274,7 @@ impl CrossPingCheck {
             Box::new(move || {
                 ExponentialBackoff::new(exponential_backoff_bounds).map_err(Error::from)
             }),
+            coordinated_punch,
         )
     }
 
@@ -225,27 +290,40 @@ impl CrossPingCheckTrait for CrossPingCheck {
     ) -> Result<HashMap<PublicKey, WireGuardEndpointCandidateChangeEvent>, Error> {
         let res: Result<HashMap<PublicKey, WireGuardEndpointCandidateChangeEvent>, Error> =
             task_exec!(&self.task, async move |s| {
-                // TODO: update logic to maintain all endpoints instead of last one
-                Ok(s.endpoint_connectivity_check_state
-                    .iter()
-                    .filter_map(
-                        |(session, v)| match (v.state.get(), v.last_validated_endpoint) {
-                            (EndpointState::Published, Some(ep)) => Some((
-                                v.public_key,
-                                WireGuardEndpointCandidateChangeEvent {
-                                    public_key: v.public_key,
-                                    remote_endpoint: ep,
-                                    local_endpoint: (
-                                        v.local_endpoint_candidate.wg,
-                                        v.provider_type,
-                                    ),
-                                    session: *session,
-                                    changed_at: v.last_state_transition,
-                                },
-                            )),
-                            _ => None,
-                        },
-                    )
+                // With several validated endpoints for a peer, e.g. one per address family,
+                // the one with the fastest pong is used
+                let mut validated: HashMap<
+                    PublicKey,
+                    (Option<Duration>, WireGuardEndpointCandidateChangeEvent),
+                > = HashMap::new();
+                for (session, v) in s.endpoint_connectivity_check_state.iter() {
+                    let (EndpointState::Published, Some(ep)) =
+                        (v.state.get(), v.last_validated_endpoint)
+                    else {
+                        continue;
+                    };
+                    if let Some((best_rtt, _)) = validated.get(&v.public_key) {
+                        if !is_faster(v.last_rtt, *best_rtt) {
+                            continue;
+                        }
+                    }
+                    validated.insert(
+                        v.public_key,
+                        (
+                            v.last_rtt,
+                            WireGuardEndpointCandidateChangeEvent {
+                                public_key: v.public_key,
+                                remote_endpoint: ep,
+                                local_endpoint: (v.local_endpoint_candidate.wg, v.provider_type),
+                                session: *session,
+                                changed_at: v.last_state_transition,
+                            },
+                        ),
+                    );
+                }
+                Ok(validated
+                    .into_iter()
+                    .map(|(public_key, (_, event))| (public_key, event))
                     .collect())
             })
             .await
@@ -272,6 +350,49 @@ impl CrossPingCheckTrait for CrossPingCheck {
         res
     }
 
+    async fn collect_family_stats(&self) -> Result<FamilyStats, Error> {
+        task_exec!(&self.task, async move |s| Ok(std::mem::take(
+            &mut s.family_stats
+        )))
+        .await
+        .map_err(|e| e.into())
+    }
+
+    async fn set_offline_peers(&self, offline_peers: HashSet<PublicKey>) -> Result<(), Error> {
+        task_exec!(&self.task, async move |s| {
+            s.set_offline_peers(offline_peers);
+            Ok(())
+        })
+        .await
+        .map_err(|e| e.into())
+    }
+
+    async fn get_peer_candidates(&self, public_key: PublicKey) -> Result<PeerCandidates, Error> {
+        task_exec!(&self.task, async move |s| Ok(s.peer_candidates(public_key)))
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn set_relay_connected(&self, connected: bool) -> Result<(), Error> {
+        task_exec!(&self.task, async move |s| {
+            s.set_relay_connected(connected).await.unwrap_or_else(|e| {
+                telio_log_warn!("Failed to resend CMM requests {:?}", e);
+            });
+            Ok(())
+        })
+        .await
+        .map_err(|e| e.into())
+    }
+
+    async fn set_suspended(&self, suspended: bool) -> Result<(), Error> {
+        task_exec!(&self.task, async move |s| {
+            s.suspended = suspended;
+            Ok(())
+        })
+        .await
+        .map_err(|e| e.into())
+    }
+
     async fn configure(&self, config: Option<Config>) -> Result<(), Error> {
         let _ = task_exec!(&self.task, async move |s| {
             // FIXME: error handling with task_exec! seems to suck a lot. Need to fix that.
@@ -295,6 +416,57 @@ impl<E: Backoff> State<E> {
             .ok_or(Error::UnkownSessionForRxedPongPacket)
     }
 
+    /// Candidates of every session with the peer, the remote ones merged across the sessions
+    fn peer_candidates(&self, public_key: PublicKey) -> PeerCandidates {
+        let mut local = Vec::new();
+        let mut remote: BTreeMap<SocketAddr, RemoteCandidate> = BTreeMap::new();
+        let sessions = self
+            .endpoint_connectivity_check_state
+            .values()
+            .filter(|session| session.public_key == public_key);
+        for session in sessions {
+            local.push(LocalCandidate {
+                endpoint: session.local_endpoint_candidate.udp,
+                candidate_type: session.provider_type.into(),
+                state: session.check_state(),
+                failed_rounds: session.failed_ping_rounds,
+                rtt_us: session.last_rtt.map(|rtt| rtt.as_micros() as u64),
+            });
+            for endpoint in &session.remote_candidates {
+                remote.entry(*endpoint).or_insert(RemoteCandidate {
+                    endpoint: *endpoint,
+                    candidate_type: None,
+                    validated: false,
+                });
+            }
+        }
+
+        // The validated endpoint carries the WireGuard port of the peer in place of the pinged
+        // one, so the candidate is matched by the address the pongs came from
+        let validated = self
+            .endpoint_connectivity_check_state
+            .values()
+            .filter(|session| {
+                session.public_key == public_key && session.state.get() == EndpointState::Published
+            })
+            .filter_map(|session| {
+                let (_, provider) = session.last_validated_endpoint?;
+                Some((session.validated_candidate?, provider))
+            });
+        for (endpoint, provider) in validated {
+            if let Some(candidate) = remote.get_mut(&endpoint) {
+                candidate.candidate_type = Some(provider.into());
+                candidate.validated = true;
+            }
+        }
+
+        local.sort_by_key(|candidate| candidate.endpoint);
+        PeerCandidates {
+            local,
+            remote: remote.into_values().collect(),
+        }
+    }
+
     fn gather_all_local_endpoints(&self) -> Result<HashSet<EndpointCandidate>, Error> {
         Ok(self
             .local_endpoint_cache
@@ -315,6 +487,15 @@ impl<E: Backoff> State<E> {
         }
     }
 
+    fn punch_requester(&self) -> Option<PunchRequester> {
+        self.coordinated_punch
+            .as_ref()
+            .map(|config| PunchRequester {
+                config: config.clone(),
+                requests: self.io.punch_requests.tx.clone(),
+            })
+    }
+
     fn gather_all_nodes(&self) -> Result<HashSet<PublicKey>, Error> {
         Ok(self.node_cache.iter().cloned().collect())
     }
@@ -340,6 +521,8 @@ impl<E: Backoff> State<E> {
             }
             retain
         });
+        self.peer_controls
+            .retain(|pk, _| !removed_nodes.contains(pk));
 
         // Create sessions for all new nodes
         for added_node in added_nodes {
@@ -352,10 +535,24 @@ impl<E: Backoff> State<E> {
                         state: EndpointStateMachine::default(),
                         last_state_transition: Instant::now(),
                         last_validated_endpoint: None,
+                        validated_candidate: None,
                         last_rx_time_provider: self.last_rx_time_provider.clone(),
                         exponential_backoff: (self.exponential_backoff_helper_provider)()?,
                         local_session: session_id,
                         provider_type: provider_type.into(),
+                        punch_requester: self.punch_requester(),
+                        failed_ping_rounds: 0,
+                        punch_requested_at: None,
+                        last_rtt: None,
+                        remote_candidates: Vec::new(),
+                        peer_offline: self.offline_peers.contains(&added_node),
+                        unpublished: false,
+                        peer_control: self
+                            .peer_controls
+                            .get(&added_node)
+                            .copied()
+                            .unwrap_or_default(),
+                        punch: None,
                     };
 
                     // Store freshly created connectivity check session
@@ -417,9 +614,19 @@ impl<E: Backoff> State<E> {
                     state: EndpointStateMachine::default(),
                     last_state_transition: Instant::now(),
                     last_validated_endpoint: None,
+                    validated_candidate: None,
                     last_rx_time_provider: self.last_rx_time_provider.clone(),
                     exponential_backoff: (self.exponential_backoff_helper_provider)()?,
                     provider_type: event.0.into(),
+                    punch_requester: self.punch_requester(),
+                    failed_ping_rounds: 0,
+                    punch_requested_at: None,
+                    last_rtt: None,
+                    remote_candidates: Vec::new(),
+                    peer_offline: self.offline_peers.contains(&node),
+                    unpublished: false,
+                    peer_control: self.peer_controls.get(&node).copied().unwrap_or_default(),
+                    punch: None,
                 };
 
                 // Store freshly created connectivity check session
@@ -449,21 +656,70 @@ impl<E: Backoff> State<E> {
         Ok(())
     }
 
+    fn set_offline_peers(&mut self, offline_peers: HashSet<PublicKey>) {
+        for session in self.endpoint_connectivity_check_state.values_mut() {
+            let offline = offline_peers.contains(&session.public_key);
+            if session.peer_offline && !offline {
+                // Peer came back, retry right away instead of waiting out the backoff
+                telio_log_debug!("Peer {} is back online", session.public_key);
+                session.exponential_backoff.reset();
+            }
+            session.peer_offline = offline;
+        }
+        self.offline_peers = offline_peers;
+    }
+
+    async fn set_relay_connected(&mut self, connected: bool) -> Result<(), Error> {
+        let reconnected = connected && !self.relay_connected;
+        self.relay_connected = connected;
+
+        for (session, state) in self.endpoint_connectivity_check_state.iter_mut() {
+            if !connected {
+                // The requests sent right before the relay went down were likely lost with it
+                state.unpublished |= state.state.get() == EndpointState::EndpointGathering;
+            } else if reconnected {
+                state
+                    .handle_relay_reconnected(*session, self.io.intercoms.tx.clone())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn handle_pong_rx_event(&mut self, event: PongEvent) -> Result<(), Error> {
         let session_id = event.msg.get_session();
         let session = State::get_connectivty_check_state(
             &mut self.endpoint_connectivity_check_state,
             &session_id,
         )?;
+        let was_pinging = session.state.get() == EndpointState::Ping;
         session
             .handle_pong_rx_event(event, self.io.wg_endpoint_publisher.clone())
-            .await
+            .await?;
+        if was_pinging && session.state.get() == EndpointState::Published {
+            self.family_stats
+                .entry(session.local_endpoint_candidate.family())
+                .or_default()
+                .successes += 1;
+        }
+        Ok(())
+    }
+
+    /// Remember the features the peer advertised, so that the sessions use only what it supports
+    fn set_peer_control(&mut self, public_key: PublicKey, control: ControlHeader) {
+        for session in self.endpoint_connectivity_check_state.values_mut() {
+            if session.public_key == public_key {
+                session.peer_control = control;
+            }
+        }
+        self.peer_controls.insert(public_key, control);
     }
 
     async fn handle_call_me_maybe_rxed_event(
         &mut self,
         (public_key, message): (PublicKey, CallMeMaybeMsg),
     ) -> Result<(), Error> {
+        self.set_peer_control(public_key, message.get_control());
         match message.get_message_type() {
             CallMeMaybeType::INITIATOR => {
                 // First send UDP pings to all of the received endpoints via endpoint providers
@@ -485,21 +741,8 @@ impl<E: Backoff> State<E> {
                     .await?;
                 }
 
-                let remote_session_id = message.get_session();
-                self.session_id_candidates
-                    .insert(remote_session_id, public_key);
-
                 // Next format and exchange CallMeMabe response message
-                let call_me_maybe_response = CallMeMaybeMsg::new(
-                    false,
-                    self.gather_all_local_endpoints()?.iter().map(|e| e.udp),
-                    remote_session_id,
-                );
-                #[allow(mpsc_blocking_send)]
-                self.io
-                    .intercoms
-                    .tx
-                    .send((public_key, call_me_maybe_response))
+                self.send_call_me_maybe_response(public_key, message.get_session())
                     .await?;
             }
 
@@ -510,6 +753,7 @@ impl<E: Backoff> State<E> {
                     &mut self.endpoint_connectivity_check_state,
                     &session_id,
                 )?;
+                let was_gathering = session.state.get() == EndpointState::EndpointGathering;
                 session
                     .handle_call_me_maybe_response_rxed_event(
                         session_id,
@@ -517,17 +761,104 @@ impl<E: Backoff> State<E> {
                         self.endpoint_providers.clone(),
                     )
                     .await?;
+                if was_gathering {
+                    self.family_stats
+                        .entry(session.local_endpoint_candidate.family())
+                        .or_default()
+                        .attempts += 1;
+                }
             }
         }
         Ok(())
     }
 
+    async fn send_call_me_maybe_response(
+        &mut self,
+        public_key: PublicKey,
+        remote_session_id: Session,
+    ) -> Result<(), Error> {
+        self.session_id_candidates
+            .insert(remote_session_id, public_key);
+
+        let call_me_maybe_response = CallMeMaybeMsg::new(
+            false,
+            self.gather_all_local_endpoints()?.iter().map(|e| e.udp),
+            remote_session_id,
+        );
+        #[allow(mpsc_blocking_send)]
+        self.io
+            .intercoms
+            .tx
+            .send((public_key, call_me_maybe_response))
+            .await?;
+        Ok(())
+    }
+
+    async fn handle_punch_request_rxed_event(
+        &mut self,
+        (public_key, message): (PublicKey, PunchRequestMsg),
+    ) -> Result<(), Error> {
+        self.set_peer_control(public_key, message.get_control());
+        let (local_session_id, session) = self
+            .endpoint_connectivity_check_state
+            .iter_mut()
+            .find(|(_, v)| v.public_key == public_key)
+            .ok_or(Error::UnexpectedPeer(public_key))?;
+
+        // The requester learns our endpoints from the response and starts its own pings so that
+        // they meet ours, which leave after the requested delay
+        session.set_punch(Self::schedule_punch(
+            self.endpoint_providers.clone(),
+            message.get_addrs(),
+            *local_session_id,
+            public_key,
+            message.get_delay(),
+        ));
+        self.send_call_me_maybe_response(public_key, message.get_session())
+            .await
+    }
+
+    /// Ping `targets` in a short burst once `start_in` passes, so that pings of both sides open
+    /// the mappings in their NATs at about the same time
+    fn schedule_punch(
+        ep_providers: Vec<Arc<dyn EndpointProvider>>,
+        targets: Vec<SocketAddr>,
+        session_id: Session,
+        public_key: PublicKey,
+        start_in: Duration,
+    ) -> JoinHandle<()> {
+        telio_log_debug!(
+            "Punching towards {:?} at {:?} in {:?}",
+            public_key,
+            targets,
+            start_in
+        );
+        tokio::spawn(async move {
+            tokio::time::sleep(start_in).await;
+            for _ in 0..PUNCH_BURST_LEN {
+                for target in &targets {
+                    let _ = Self::send_ping_via_all_endpoint_providers(
+                        &ep_providers,
+                        *target,
+                        session_id,
+                        public_key,
+                    )
+                    .await;
+                }
+                tokio::time::sleep(PUNCH_BURST_INTERVAL).await;
+            }
+        })
+    }
+
     async fn handle_tick_event(&mut self) -> Result<(), Error> {
         // Tick over all currently ongoing sessions
         for (session, state) in self.endpoint_connectivity_check_state.iter_mut() {
             state
                 .handle_tick_event(*session, self.io.intercoms.tx.clone())
                 .await?;
+            if !self.relay_connected && state.state.get() == EndpointState::EndpointGathering {
+                state.unpublished = true;
+            }
         }
         Ok(())
     }
@@ -659,7 +990,20 @@ impl<E: Backoff> Runtime for State<E> {
                 Ok(())
             }
 
-            _ = self.poll_timer.tick() => {
+            Some(punch_request) = self.io.punch_requests.rx.recv() => {
+                telio_log_debug!("Punch request event occured: {:?}", punch_request);
+                self
+                    .handle_punch_request_rxed_event(punch_request)
+                    .await
+                    .unwrap_or_else(
+                        |e| {
+                            telio_log_warn!("Failed to handle punch request: {:?}, ignoring", e);
+                        });
+
+                Ok(())
+            }
+
+            _ = self.poll_timer.tick(), if !self.suspended => {
                 telio_log_trace!("tick event occured");
                 self
                     .handle_tick_event()
@@ -691,9 +1035,43 @@ pub struct EndpointConnectivityCheckState<E: Backoff> {
     state: EndpointStateMachine,
     last_state_transition: Instant,
     last_validated_endpoint: Option<(SocketAddr, ApiEndpointProvider)>,
+    /// Candidate of the peer which answered the pings of the validated endpoint
+    validated_candidate: Option<SocketAddr>,
     last_rx_time_provider: Option<Arc<dyn TimeSinceLastRxProvider>>,
     exponential_backoff: E,
     provider_type: ApiEndpointProvider,
+    punch_requester: Option<PunchRequester>,
+    /// Pinging rounds which timed out since the last published endpoint
+    failed_ping_rounds: u32,
+    /// Set while waiting for the response to our punch request
+    punch_requested_at: Option<Instant>,
+    /// Round trip of the pong which validated the current endpoint
+    last_rtt: Option<Duration>,
+    /// Candidates of the peer from its latest call me maybe response
+    remote_candidates: Vec<SocketAddr>,
+    /// Set while the relay reports the peer as offline
+    peer_offline: bool,
+    /// Set when our request might have been lost while the relay was down
+    unpublished: bool,
+    /// Control header from the latest control message of the peer
+    peer_control: ControlHeader,
+    /// Pending or ongoing coordinated punch, aborted along with the session
+    punch: Option<JoinHandle<()>>,
+}
+
+impl<E: Backoff> Drop for EndpointConnectivityCheckState<E> {
+    fn drop(&mut self) {
+        if let Some(punch) = self.punch.take() {
+            punch.abort();
+        }
+    }
+}
+
+/// Sends coordinated punch requests for a session
+#[derive(Clone)]
+struct PunchRequester {
+    config: FeatureCoordinatedPunch,
+    requests: chan::Tx<(PublicKey, PunchRequestMsg)>,
 }
 
 impl<E: Backoff> Debug for EndpointConnectivityCheckState<E> {
@@ -705,6 +1083,12 @@ impl<E: Backoff> Debug for EndpointConnectivityCheckState<E> {
             .field("last_state_transition", &self.last_state_transition)
             .field("last_validate_endpoint", &self.last_validated_endpoint)
             .field("exponential_backoff", &self.exponential_backoff)
+            .field("failed_ping_rounds", &self.failed_ping_rounds)
+            .field("last_rtt", &self.last_rtt)
+            .field("remote_candidates", &self.remote_candidates)
+            .field("peer_offline", &self.peer_offline)
+            .field("unpublished", &self.unpublished)
+            .field("peer_control", &self.peer_control)
             .field(
                 "last_rx_time_provider",
                 if self.last_rx_time_provider.is_some() {
@@ -718,11 +1102,55 @@ impl<E: Backoff> Debug for EndpointConnectivityCheckState<E> {
 }
 
 impl<E: Backoff> EndpointConnectivityCheckState<E> {
+    fn check_state(&self) -> CandidateCheckState {
+        match self.state.get() {
+            EndpointState::Disconnected(_) => CandidateCheckState::Idle,
+            EndpointState::EndpointGathering => CandidateCheckState::Gathering,
+            EndpointState::Ping => CandidateCheckState::Pinging,
+            EndpointState::Published => CandidateCheckState::Validated,
+        }
+    }
+
+    /// Track the punch of the session, a newer punch replaces the pending one
+    fn set_punch(&mut self, punch: JoinHandle<()>) {
+        if let Some(old) = self.punch.replace(punch) {
+            old.abort();
+        }
+    }
+
     async fn send_call_me_maybe_request(
         &mut self,
         session: Session,
         intercoms: chan::Tx<(PublicKey, CallMeMaybeMsg)>,
     ) -> Result<(), Error> {
+        self.punch_requested_at = None;
+        if let Some(punch_requester) = &self.punch_requester {
+            // Peers which did not advertise punch requests keep getting CMMs
+            if self.failed_ping_rounds >= punch_requester.config.failed_rounds_threshold
+                && self.peer_control.supports(PeerCapability::PunchRequest)
+            {
+                let punch_request = PunchRequestMsg::new(
+                    [self.local_endpoint_candidate.udp].iter().cloned(),
+                    session,
+                    Duration::from_millis(punch_requester.config.delay_ms as u64),
+                );
+
+                telio_log_debug!(
+                    "Sending a punch request to {:?}: {:?}",
+                    self.public_key,
+                    punch_request
+                );
+
+                #[allow(mpsc_blocking_send)]
+                punch_requester
+                    .requests
+                    .send((self.public_key, punch_request))
+                    .await?;
+                self.punch_requested_at = Some(Instant::now());
+                return Ok(());
+            }
+        }
+
         let call_me_maybe_init = CallMeMaybeMsg::new(
             true,
             [self.local_endpoint_candidate.udp].iter().cloned(),
@@ -742,6 +1170,33 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
         Ok(())
     }
 
+    /// Resend the request lost while the relay was down, once per session and without waiting
+    /// out the backoff, as the peer did not get a chance to answer it
+    async fn handle_relay_reconnected(
+        &mut self,
+        session: Session,
+        intercoms: chan::Tx<(PublicKey, CallMeMaybeMsg)>,
+    ) -> Result<(), Error> {
+        if !std::mem::take(&mut self.unpublished) || self.peer_offline {
+            return Ok(());
+        }
+
+        match self.state.get() {
+            EndpointState::EndpointGathering => {
+                telio_log_debug!("Relay reconnected, resending CMM to {:?}", self.public_key);
+                self.send_call_me_maybe_request(session, intercoms).await?;
+                self.last_state_transition = Instant::now();
+            }
+            EndpointState::Disconnected(Event::Timeout) => {
+                telio_log_debug!("Relay reconnected, resending CMM to {:?}", self.public_key);
+                self.send_call_me_maybe_request(session, intercoms).await?;
+                do_state_transition!(self, Event::SendCallMeMaybeRequest);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     async fn handle_endpoint_gone_notification(&mut self) -> Result<(), Error> {
         if self.state.get() == EndpointState::Published {
             telio_log_info!(
@@ -772,15 +1227,32 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
                     public_key,
                     message
                 );
-
-                for addr in message.get_addrs() {
-                    State::<E>::send_ping_via_all_endpoint_providers(
-                        &ep_providers,
-                        addr,
-                        session_id,
-                        public_key,
-                    )
-                    .await?;
+                self.remote_candidates = message.get_addrs();
+
+                match (&self.punch_requester, self.punch_requested_at) {
+                    (Some(punch_requester), Some(requested_at)) => {
+                        // The peer started its countdown about half a round trip after our request
+                        let delay = Duration::from_millis(punch_requester.config.delay_ms as u64);
+                        let start_in = delay.saturating_sub(requested_at.elapsed() / 2);
+                        self.set_punch(State::<E>::schedule_punch(
+                            ep_providers,
+                            message.get_addrs(),
+                            session_id,
+                            public_key,
+                            start_in,
+                        ));
+                    }
+                    _ => {
+                        for addr in message.get_addrs() {
+                            State::<E>::send_ping_via_all_endpoint_providers(
+                                &ep_providers,
+                                addr,
+                                session_id,
+                                public_key,
+                            )
+                            .await?;
+                        }
+                    }
                 }
 
                 do_state_transition!(self, Event::ReceiveCallMeMaybeResponse);
@@ -823,6 +1295,8 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
 
                         // Reset exponential backoff on succesfull endpoint verification
                         self.exponential_backoff.reset();
+                        self.failed_ping_rounds = 0;
+                        self.last_rtt = Some(event.rtt);
 
                         let wg_publish_event = WireGuardEndpointCandidateChangeEvent {
                             public_key: self.public_key,
@@ -835,6 +1309,7 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
                             wg_publish_event, self.provider_type, event.msg.get_ponging_ep_provider());
                         self.last_validated_endpoint =
                             Some((remote_endpoint, remote_endpoint_type));
+                        self.validated_candidate = Some(event.addr);
                         wg_ep_publisher
                             .send(wg_publish_event)
                             .await
@@ -869,7 +1344,9 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
         &self,
         duration_in_state: Duration,
     ) -> ShouldSendCMMResult {
-        if duration_in_state > self.exponential_backoff.get_backoff() {
+        if self.peer_offline {
+            ShouldSendCMMResult::Offline
+        } else if duration_in_state > self.exponential_backoff.get_backoff() {
             if let Some(last_rx_time_provider) = &self.last_rx_time_provider {
                 if is_peer_alive(&**last_rx_time_provider, &self.public_key).await {
                     ShouldSendCMMResult::Yes
@@ -922,12 +1399,21 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
                         "Timeout waiting for pongs, next retry after {}s",
                         self.exponential_backoff.get_backoff().as_secs_f64()
                     );
+                    self.failed_ping_rounds = self.failed_ping_rounds.saturating_add(1);
                     do_state_transition!(self, Event::Timeout);
                 }
             }
             EndpointState::Disconnected(Event::StartUp) => {
-                self.send_call_me_maybe_request(session, intercoms).await?;
-                do_state_transition!(self, Event::SendCallMeMaybeRequest);
+                if self.peer_offline {
+                    telio_log_debug!(
+                        "Skipping sending CMM to peer {} ({:?})",
+                        self.public_key,
+                        ShouldSendCMMResult::Offline
+                    );
+                } else {
+                    self.send_call_me_maybe_request(session, intercoms).await?;
+                    do_state_transition!(self, Event::SendCallMeMaybeRequest);
+                }
             }
             EndpointState::Disconnected(Event::Timeout) => {
                 match self
@@ -972,18 +1458,31 @@ impl<E: Backoff> EndpointConnectivityCheckState<E> {
     }
 }
 
+/// Endpoints with a measured round trip beat the ones without
+fn is_faster(rtt: Option<Duration>, than: Option<Duration>) -> bool {
+    match (rtt, than) {
+        (Some(rtt), Some(than)) => rtt < than,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum ShouldSendCMMResult {
     Yes,
     Backoff,
     Unresponsive,
+    Offline,
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
         net::{IpAddr, Ipv4Addr},
-        sync::Arc,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
         time::Duration,
     };
 
@@ -997,6 +1496,7 @@ mod tests {
     use telio_crypto::{PublicKey, SecretKey};
     use telio_model::{
         config::{Config, Peer, PeerBase},
+        mesh::CandidateType,
         SocketAddr,
     };
     use telio_proto::{PingerMsg, WGPort};
@@ -1017,6 +1517,7 @@ mod tests {
         pong_rx_events: Sender<PongEvent>,
         wg_endpoint_publish_events: Receiver<WireGuardEndpointCandidateChangeEvent>,
         intercoms: Chan<(PublicKey, CallMeMaybeMsg)>,
+        punch_requests: Chan<(PublicKey, PunchRequestMsg)>,
     }
     const SESSION_ID: u64 = 0;
 
@@ -1035,6 +1536,7 @@ mod tests {
         let pong_rx_events = Chan::default();
         let wg_endpoint_publish_events = Chan::default();
         let (checker_intercoms, intercoms) = Chan::pipe();
+        let (checker_punch_requests, punch_requests) = Chan::pipe();
 
         let checker = CrossPingCheck::start(
             Io {
@@ -1042,12 +1544,14 @@ mod tests {
                 pong_rx_subscriber: pong_rx_events.rx,
                 wg_endpoint_publisher: wg_endpoint_publish_events.tx,
                 intercoms: checker_intercoms,
+                punch_requests: checker_punch_requests,
             },
             vec![Arc::new(endpoint_provider_mock)],
             Some(Arc::new(MockTimeSinceLastRxProvider::new())),
             Duration::from_secs(2),
             Arc::new(Mutex::new(PingPongHandler::new(SecretKey::gen()))),
             ExponentialBackoffBounds::default(),
+            None,
         );
 
         let channels = TestChannels {
@@ -1055,6 +1559,7 @@ mod tests {
             pong_rx_events: pong_rx_events.tx,
             wg_endpoint_publish_events: wg_endpoint_publish_events.rx,
             intercoms,
+            punch_requests,
         };
 
         Ok((checker, channels))
@@ -1064,6 +1569,16 @@ mod tests {
         channels: &mut TestChannels,
         endpoint: SocketAddr,
         original_pub_key: PublicKey,
+    ) {
+        validate_endpoint_among(channels, endpoint, vec![endpoint], original_pub_key).await;
+    }
+
+    /// Validate the endpoint, with the peer reporting the given candidates
+    async fn validate_endpoint_among(
+        channels: &mut TestChannels,
+        endpoint: SocketAddr,
+        remote_candidates: Vec<SocketAddr>,
+        original_pub_key: PublicKey,
     ) {
         channels
             .endpoint_change_subscriber
@@ -1082,7 +1597,7 @@ mod tests {
             .tx
             .send((
                 original_pub_key,
-                CallMeMaybeMsg::new(false, vec![endpoint].into_iter(), cmm_init.get_session()),
+                CallMeMaybeMsg::new(false, remote_candidates.into_iter(), cmm_init.get_session()),
             ))
             .await
             .unwrap();
@@ -1122,9 +1637,19 @@ mod tests {
             state,
             last_state_transition: Instant::now(),
             last_validated_endpoint: None,
+            validated_candidate: None,
             last_rx_time_provider: Some(last_rx_time_provider),
             exponential_backoff: MockBackoff::default(),
             provider_type: telio_model::features::EndpointProvider::Stun,
+            punch_requester: None,
+            failed_ping_rounds: 0,
+            punch_requested_at: None,
+            last_rtt: None,
+            remote_candidates: Vec::new(),
+            peer_offline: false,
+            unpublished: false,
+            peer_control: ControlHeader::default(),
+            punch: None,
         }
     }
 
@@ -1162,6 +1687,142 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn peer_candidates_report_the_validated_pair() {
+        let (checker, mut channels) = prepare_checker_test().unwrap();
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let mut peer = Peer::default();
+        let original_pub_key = PublicKey(*b"ABBBBBBBBBBBBBBBBBBBAAAAAAAAAAAA");
+        peer.base.public_key = original_pub_key;
+
+        checker
+            .configure(Some(Config {
+                this: PeerBase::default(),
+                peers: Some(vec![peer]),
+                derp_servers: None,
+                dns: None,
+            }))
+            .await
+            .unwrap();
+        validate_endpoint(&mut channels, endpoint, original_pub_key).await;
+
+        assert_eq!(
+            checker.get_peer_candidates(original_pub_key).await.unwrap(),
+            PeerCandidates {
+                local: vec![LocalCandidate {
+                    endpoint,
+                    candidate_type: CandidateType::Local,
+                    state: CandidateCheckState::Validated,
+                    failed_rounds: 0,
+                    rtt_us: Some(100_000),
+                }],
+                remote: vec![RemoteCandidate {
+                    endpoint,
+                    candidate_type: Some(CandidateType::Local),
+                    validated: true,
+                }],
+            }
+        );
+        assert_eq!(
+            checker
+                .get_peer_candidates(PublicKey::default())
+                .await
+                .unwrap(),
+            PeerCandidates::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn peer_candidates_validate_only_the_answering_port() {
+        let (checker, mut channels) = prepare_checker_test().unwrap();
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let other_port = SocketAddr::new(endpoint.ip(), 9090);
+        let mut peer = Peer::default();
+        let original_pub_key = PublicKey(*b"ABBBBBBBBBBBBBBBBBBBAAAAAAAAAAAA");
+        peer.base.public_key = original_pub_key;
+
+        checker
+            .configure(Some(Config {
+                this: PeerBase::default(),
+                peers: Some(vec![peer]),
+                derp_servers: None,
+                dns: None,
+            }))
+            .await
+            .unwrap();
+        validate_endpoint_among(
+            &mut channels,
+            endpoint,
+            vec![endpoint, other_port],
+            original_pub_key,
+        )
+        .await;
+
+        assert_eq!(
+            checker
+                .get_peer_candidates(original_pub_key)
+                .await
+                .unwrap()
+                .remote,
+            vec![
+                RemoteCandidate {
+                    endpoint,
+                    candidate_type: Some(CandidateType::Local),
+                    validated: true,
+                },
+                RemoteCandidate {
+                    endpoint: other_port,
+                    candidate_type: None,
+                    validated: false,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn family_stats_are_collected_once() {
+        let (checker, mut channels) = prepare_checker_test().unwrap();
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let mut peer = Peer::default();
+        let original_pub_key = PublicKey(*b"ABBBBBBBBBBBBBBBBBBBAAAAAAAAAAAA");
+        peer.base.public_key = original_pub_key;
+
+        checker
+            .configure(Some(Config {
+                this: PeerBase::default(),
+                peers: Some(vec![peer]),
+                derp_servers: None,
+                dns: None,
+            }))
+            .await
+            .unwrap();
+
+        validate_endpoint(&mut channels, endpoint, original_pub_key).await;
+        assert_eq!(
+            checker.collect_family_stats().await.unwrap(),
+            FamilyStats::from([(
+                AddressFamily::IPv4,
+                FamilyCheckStats {
+                    attempts: 1,
+                    successes: 1,
+                }
+            )])
+        );
+        assert!(checker.collect_family_stats().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn measured_rtt_is_preferred() {
+        let fast = Some(Duration::from_millis(10));
+        let slow = Some(Duration::from_millis(100));
+
+        assert!(is_faster(fast, slow));
+        assert!(!is_faster(slow, fast));
+        assert!(is_faster(slow, None));
+        assert!(!is_faster(None, slow));
+        assert!(!is_faster(None, None));
+    }
+
     #[tokio::test]
     async fn notify_failed_wg_connection() {
         let (checker, mut channels) = prepare_checker_test().unwrap();
@@ -1212,6 +1873,34 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn endpoint_connectivity_check_state_skips_cmm_to_offline_peer() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::Disconnected(Event::StartUp)),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080),
+            last_rx_time_provider_mock,
+        );
+        endpoint_connectivity_check_state.peer_offline = true;
+        let Chan {
+            rx: mut intercoms_rx,
+            tx: intercoms_tx,
+        } = Chan::default();
+
+        endpoint_connectivity_check_state
+            .handle_tick_event(SESSION_ID, intercoms_tx)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            endpoint_connectivity_check_state.state,
+            EndpointState::Disconnected(Event::StartUp),
+        );
+        intercoms_rx
+            .try_recv()
+            .expect_err("CMM message should not be sent");
+    }
+
     #[tokio::test]
     async fn endpoint_connectivity_check_state_receive_cmm_response() {
         let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
@@ -1239,6 +1928,153 @@ mod tests {
         assert_eq!(endpoint_connectivity_check_state.state, EndpointState::Ping);
     }
 
+    #[tokio::test]
+    async fn endpoint_connectivity_check_state_send_punch_request_after_failed_rounds() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::Disconnected(Event::StartUp)),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080),
+            last_rx_time_provider_mock,
+        );
+        let mut punch_requests = Chan::default();
+        endpoint_connectivity_check_state.punch_requester = Some(PunchRequester {
+            config: FeatureCoordinatedPunch::default(),
+            requests: punch_requests.tx.clone(),
+        });
+        endpoint_connectivity_check_state.failed_ping_rounds =
+            FeatureCoordinatedPunch::default().failed_rounds_threshold;
+        endpoint_connectivity_check_state.peer_control = ControlHeader::local();
+        let mut intercoms = Chan::default();
+
+        endpoint_connectivity_check_state
+            .handle_tick_event(SESSION_ID, intercoms.tx.clone())
+            .await
+            .unwrap();
+
+        intercoms
+            .rx
+            .try_recv()
+            .expect_err("CMM request should be replaced by the punch request");
+        let (_, request) = punch_requests.rx.try_recv().unwrap();
+        assert_eq!(request.get_session(), SESSION_ID);
+        assert_eq!(request.get_delay(), Duration::from_millis(1000));
+        assert!(endpoint_connectivity_check_state
+            .punch_requested_at
+            .is_some());
+        assert_eq!(
+            endpoint_connectivity_check_state.state,
+            EndpointState::EndpointGathering,
+        );
+    }
+
+    #[tokio::test]
+    async fn endpoint_connectivity_check_state_sends_cmm_to_legacy_peer_after_failed_rounds() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::Disconnected(Event::StartUp)),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080),
+            last_rx_time_provider_mock,
+        );
+        let mut punch_requests = Chan::default();
+        endpoint_connectivity_check_state.punch_requester = Some(PunchRequester {
+            config: FeatureCoordinatedPunch::default(),
+            requests: punch_requests.tx.clone(),
+        });
+        endpoint_connectivity_check_state.failed_ping_rounds =
+            FeatureCoordinatedPunch::default().failed_rounds_threshold;
+        let mut intercoms = Chan::default();
+
+        endpoint_connectivity_check_state
+            .handle_tick_event(SESSION_ID, intercoms.tx.clone())
+            .await
+            .unwrap();
+
+        punch_requests
+            .rx
+            .try_recv()
+            .expect_err("Peer did not advertise punch requests");
+        let (_, cmm) = intercoms.rx.try_recv().unwrap();
+        assert_eq!(cmm.get_session(), SESSION_ID);
+        assert!(endpoint_connectivity_check_state
+            .punch_requested_at
+            .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn endpoint_connectivity_check_state_punch_waits_for_coordinated_time() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let pings = Arc::new(AtomicUsize::new(0));
+        let mut endpoint_provider_mock = MockEndpointProvider::new();
+        endpoint_provider_mock.expect_send_ping().returning({
+            let pings = pings.clone();
+            move |_, _, _| {
+                pings.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        });
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::EndpointGathering),
+            endpoint,
+            last_rx_time_provider_mock,
+        );
+        endpoint_connectivity_check_state.punch_requester = Some(PunchRequester {
+            config: FeatureCoordinatedPunch::default(),
+            requests: Chan::default().tx,
+        });
+        endpoint_connectivity_check_state.punch_requested_at = Some(Instant::now());
+
+        // Response arrives after a round trip of 200ms, so the peer punches in 900ms
+        time::advance(Duration::from_millis(200)).await;
+        let cmm_msg = CallMeMaybeMsg::new(false, vec![endpoint].into_iter(), 1);
+        endpoint_connectivity_check_state
+            .handle_call_me_maybe_response_rxed_event(
+                1,
+                (PublicKey::default(), cmm_msg),
+                vec![Arc::new(endpoint_provider_mock)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(endpoint_connectivity_check_state.state, EndpointState::Ping);
+
+        time::sleep(Duration::from_millis(850)).await;
+        assert_eq!(pings.load(Ordering::Relaxed), 0);
+
+        time::sleep(PUNCH_BURST_INTERVAL * PUNCH_BURST_LEN).await;
+        assert_eq!(pings.load(Ordering::Relaxed), PUNCH_BURST_LEN as usize);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn endpoint_connectivity_check_state_punch_stops_with_session() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let pings = Arc::new(AtomicUsize::new(0));
+        let mut endpoint_provider_mock = MockEndpointProvider::new();
+        endpoint_provider_mock.expect_send_ping().returning({
+            let pings = pings.clone();
+            move |_, _, _| {
+                pings.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        });
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::EndpointGathering),
+            endpoint,
+            last_rx_time_provider_mock,
+        );
+        endpoint_connectivity_check_state.set_punch(State::<MockBackoff>::schedule_punch(
+            vec![Arc::new(endpoint_provider_mock)],
+            vec![endpoint],
+            1,
+            PublicKey::default(),
+            Duration::from_millis(500),
+        ));
+
+        drop(endpoint_connectivity_check_state);
+        time::sleep(Duration::from_millis(500) + PUNCH_BURST_INTERVAL * PUNCH_BURST_LEN).await;
+        assert_eq!(pings.load(Ordering::Relaxed), 0);
+    }
+
     #[tokio::test]
     async fn endpoint_connectivity_check_state_publish() {
         let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
@@ -1321,6 +2157,52 @@ mod tests {
         );
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn endpoint_connectivity_check_state_resends_cmm_once_relay_reconnects() {
+        let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
+        let mut endpoint_connectivity_check_state = prepare_test_session_in_state(
+            EndpointStateMachine::new(EndpointState::EndpointGathering),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080),
+            last_rx_time_provider_mock,
+        );
+        endpoint_connectivity_check_state.unpublished = true;
+        let Chan {
+            rx: mut intercoms_rx,
+            tx: intercoms_tx,
+        } = Chan::default();
+
+        // The request timed out while the relay was down
+        time::advance(CPC_TIMEOUT + Duration::from_millis(1)).await;
+        endpoint_connectivity_check_state
+            .handle_tick_event(SESSION_ID, intercoms_tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            endpoint_connectivity_check_state.state,
+            EndpointState::Disconnected(Event::Timeout),
+        );
+
+        endpoint_connectivity_check_state
+            .handle_relay_reconnected(SESSION_ID, intercoms_tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(
+            endpoint_connectivity_check_state.state,
+            EndpointState::EndpointGathering,
+        );
+        let (_, cmm) = intercoms_rx.try_recv().expect("CMM message should be sent");
+        assert_eq!(cmm.get_session(), SESSION_ID);
+
+        // Reconnecting again does not duplicate the request
+        endpoint_connectivity_check_state
+            .handle_relay_reconnected(SESSION_ID, intercoms_tx)
+            .await
+            .unwrap();
+        intercoms_rx
+            .try_recv()
+            .expect_err("CMM message should not be sent twice");
+    }
+
     #[tokio::test(start_paused = true)]
     async fn endpoint_connectivity_check_state_timeout_ping() {
         let last_rx_time_provider_mock = Arc::new(Mutex::new(MockTimeSinceLastRxProvider::new()));
@@ -10,7 +10,7 @@ use telio_crypto::{encryption, PublicKey};
 use telio_utils::exponential_backoff;
 use thiserror::Error as TError;
 
-use telio_model::SocketAddr;
+use telio_model::{mesh::AddressFamily, SocketAddr};
 use telio_proto::{PlaintextPongerMsg, Session};
 use telio_task::io::chan;
 
@@ -103,6 +103,13 @@ pub struct EndpointCandidate {
     pub udp: SocketAddr,
 }
 
+impl EndpointCandidate {
+    /// Address family WireGuard uses with this candidate
+    pub fn family(&self) -> AddressFamily {
+        self.wg.ip().into()
+    }
+}
+
 pub type EndpointCandidatesChangeEvent = (EndpointProviderType, Vec<EndpointCandidate>);
 
 #[derive(Debug, Clone)]
@@ -161,4 +168,13 @@ pub trait EndpointProvider: Sync + Send + 'static {
     async fn is_paused(&self) -> bool {
         false
     }
+
+    /// Notify the endpoint provider about the availability of the network.
+    ///
+    /// Without network all of the polling stops right away, regardless of the battery
+    /// optimization, and [EndpointProvider::unpause] has no effect. Once the network is back,
+    /// the discovery starts anew, as the endpoints found before are most likely gone.
+    ///
+    /// Note: This function implementation *MUST* be idempotent.
+    async fn set_network_available(&self, _available: bool) {}
 }
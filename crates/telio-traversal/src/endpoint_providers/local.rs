@@ -38,6 +38,8 @@ pub struct State<T: WireGuard, G: GetIfAddrs> {
     udp_socket: External<UdpSocket>,
     ping_pong_handler: Arc<Mutex<PingPongHandler>>,
     get_if_addr: G,
+    /// Interfaces are not polled without network
+    is_network_available: bool,
 }
 
 #[async_trait]
@@ -95,6 +97,15 @@ impl<T: WireGuard, G: GetIfAddrs> EndpointProvider for LocalInterfacesEndpointPr
         .await
         .unwrap_or(None)
     }
+
+    async fn set_network_available(&self, available: bool) {
+        task_exec!(&self.task, async move |s| {
+            s.is_network_available = available;
+            Ok(())
+        })
+        .await
+        .unwrap_or_default();
+    }
 }
 
 impl<T: WireGuard> LocalInterfacesEndpointProvider<T> {
@@ -134,6 +145,7 @@ impl<T: WireGuard, G: GetIfAddrs> LocalInterfacesEndpointProvider<T, G> {
                 udp_socket,
                 ping_pong_handler,
                 get_if_addr,
+                is_network_available: true,
             }),
         }
     }
@@ -247,7 +259,7 @@ impl<T: WireGuard, G: GetIfAddrs> Runtime for State<T, G> {
                         telio_log_warn!("Failed to handle packet received no local interface endpoint provider {:?}", e);
                     });
             }
-            _ = self.poll_timer.tick() => {
+            _ = self.poll_timer.tick(), if self.is_network_available => {
                 self.poll_local_endpoints().await.unwrap_or_else(
                     |e| {
                         telio_log_warn!("Failed to poll local endpoints {:?}", e);
@@ -320,6 +332,7 @@ mod tests {
                 .unwrap(),
                 ping_pong_handler: ping_pong_handler.clone(),
                 get_if_addr: get_if_addrs_mock,
+                is_network_available: true,
             },
             secret_key,
             ping_pong_handler,
@@ -595,6 +608,77 @@ mod tests {
         local_provider.stop().await;
     }
 
+    #[tokio::test]
+    async fn replayed_pong_is_not_propagated_through_the_channel() {
+        let mut wg_mock = MockWireGuard::new();
+        wg_mock.expect_get_interface().returning(|| {
+            Ok(Interface {
+                listen_port: Some(12345),
+                ..Default::default()
+            })
+        });
+
+        let mut get_if_addrs_mock = MockGetIfAddrs::new();
+        get_if_addrs_mock
+            .expect_get()
+            .returning(|| generate_fake_local_interface(1));
+
+        let (
+            local_provider,
+            _,
+            mut pong_rx,
+            peer_socket,
+            peer_addr,
+            _,
+            local_sk,
+            ping_pong_handler,
+        ) = prepare_local_provider_test(wg_mock, get_if_addrs_mock).await;
+
+        let session_id = 456;
+        let remote_sk = SecretKey::gen();
+        let remote_pk = remote_sk.public();
+
+        ping_pong_handler
+            .lock()
+            .await
+            .configure(hashmap! { session_id => remote_pk });
+
+        local_provider
+            .send_ping(peer_addr, session_id, remote_pk)
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; MAX_PACKET_SIZE];
+        let (len, addr) = peer_socket.recv_from(&mut buf).await.unwrap();
+        let decrypt_transform = |_packet_type, b: &[u8]| {
+            Ok(decrypt_request(b, &remote_sk, |_| true)
+                .map(|(buf, pk)| (buf, Some(pk)))
+                .unwrap())
+        };
+        let (msg, _) = PingerMsg::decode_and_decrypt(&buf[..len], decrypt_transform).unwrap();
+
+        let resp = msg
+            .pong(
+                msg.get_wg_port(),
+                &addr.ip(),
+                telio_model::features::EndpointProvider::Local,
+            )
+            .unwrap();
+        let mut rng = rand::thread_rng();
+        let encrypt_transform =
+            |b: &[u8]| Ok(encrypt_response(b, &mut rng, &remote_sk, &local_sk.public()).unwrap());
+
+        let buf = resp.encode_and_encrypt(encrypt_transform).unwrap();
+        peer_socket.send_to(&buf, addr).await.unwrap();
+        assert!(pong_rx.recv().await.is_some());
+
+        // The same pong again, e.g. replayed by a host colliding with the candidate
+        peer_socket.send_to(&buf, addr).await.unwrap();
+        let pong = timeout(Duration::from_secs(2), pong_rx.recv()).await;
+        assert!(pong.is_err());
+        local_provider.stop().await;
+    }
+
     #[tokio::test]
     async fn handling_of_received_ping_from_allowed_peer() {
         let wg_port = 12345;
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
@@ -7,7 +8,7 @@ use async_trait::async_trait;
 use futures::{future::pending, Future};
 use stun_codec::TransactionId;
 use telio_crypto::PublicKey;
-use telio_model::config::Server;
+use telio_model::{config::Server, mesh::AddressFamily};
 use telio_proto::{Session, WGPort};
 use telio_sockets::SocketPool;
 use telio_sockets::{native::AsNativeSocket, External};
@@ -48,6 +49,24 @@ pub enum IpProto {
     IPv6,
 }
 
+impl IpProto {
+    fn other(self) -> Self {
+        match self {
+            IpProto::IPv4 => IpProto::IPv6,
+            IpProto::IPv6 => IpProto::IPv4,
+        }
+    }
+}
+
+impl From<IpProto> for AddressFamily {
+    fn from(proto: IpProto) -> Self {
+        match proto {
+            IpProto::IPv4 => AddressFamily::IPv4,
+            IpProto::IPv6 => AddressFamily::IPv6,
+        }
+    }
+}
+
 impl<Wg: WireGuard> StunEndpointProvider<Wg> {
     /// Start stun endpoint provider,
     /// # Params
@@ -56,12 +75,15 @@ impl<Wg: WireGuard> StunEndpointProvider<Wg> {
     /// - `wg` - wireguard controll
     /// - `exponential_backoff_bounds` - Config for exponential backoff
     ///   controlling intervals between consequtive queries to the STUN server
+    /// - `dual_stack` - Keep endpoints of both IPv4 and IPv6 published, instead of
+    ///   falling back to IPv4 only when IPv6 fails
     pub fn start(
         wg: Arc<Wg>,
         exponential_backoff_bounds: ExponentialBackoffBounds,
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         stun_peer_publisher: chan::Tx<Option<StunServer>>,
         is_battery_optimization_on: bool,
+        dual_stack: bool,
     ) -> Result<Self, Error> {
         Ok(Self::start_with_exp_backoff(
             wg,
@@ -69,6 +91,7 @@ impl<Wg: WireGuard> StunEndpointProvider<Wg> {
             ping_pong_handler,
             stun_peer_publisher,
             is_battery_optimization_on,
+            dual_stack,
         ))
     }
 
@@ -89,6 +112,7 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         stun_peer_publisher: chan::Tx<Option<StunServer>>,
         is_battery_optimization_on: bool,
+        dual_stack: bool,
     ) -> Self {
         telio_log_info!("Starting stun endpoint provider");
         Self {
@@ -104,8 +128,11 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
                 exponential_backoff,
                 current_timeout: PinnedSleep::new(STUN_TIMEOUT, ()),
                 last_candidates: Vec::new(),
+                family_candidates: BTreeMap::new(),
                 stun_state: StunState::WaitingForWg,
                 is_battery_optimization_on,
+                dual_stack,
+                is_network_available: true,
                 stun_peer_publisher,
                 sockets: None,
             }),
@@ -136,6 +163,7 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
 
             // Create internal and external socket for STUN operation
             if s.sockets.is_none() || ip_version_changed {
+                s.family_candidates.clear();
                 s.sockets = {
                     let tun_socket_v4 = match socket_pool
                         .new_internal_udp((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0), None)
@@ -369,7 +397,7 @@ impl<Wg: WireGuard, E: Backoff + 'static> EndpointProvider for StunEndpointProvi
 
     async fn unpause(&self) {
         let _ = task_exec!(&self.task, async move |s| {
-            if s.stun_state == StunState::Paused {
+            if s.stun_state == StunState::Paused && s.is_network_available {
                 s.exponential_backoff.reset();
                 s.transition_to_wait_for_wg();
                 s.try_transition_to_searching_for_server().await;
@@ -386,6 +414,14 @@ impl<Wg: WireGuard, E: Backoff + 'static> EndpointProvider for StunEndpointProvi
         .await
         .unwrap_or(false)
     }
+
+    async fn set_network_available(&self, available: bool) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.set_network_available(available).await;
+            Ok(())
+        })
+        .await;
+    }
 }
 
 //                                -------------
@@ -443,8 +479,12 @@ struct State<Wg: WireGuard, E: Backoff> {
     exponential_backoff: E,
     current_timeout: PinnedSleep<()>,
     last_candidates: Vec<EndpointCandidate>,
+    /// Candidates discovered over each address family, only kept in dual stack mode
+    family_candidates: BTreeMap<AddressFamily, EndpointCandidate>,
     stun_state: StunState,
     is_battery_optimization_on: bool,
+    is_network_available: bool,
+    dual_stack: bool,
 
     stun_peer_publisher: chan::Tx<Option<StunServer>>,
 }
@@ -490,6 +530,9 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
     }
 
     async fn reconnect(&mut self) {
+        if !self.is_network_available {
+            return;
+        }
         match self.stun_state {
             StunState::BackingOff | StunState::Paused => {
                 self.transition_to_wait_for_wg();
@@ -634,6 +677,40 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
         }
     }
 
+    async fn set_network_available(&mut self, available: bool) {
+        if self.is_network_available == available {
+            return;
+        }
+        self.is_network_available = available;
+
+        if available {
+            telio_log_info!("Network is back, restarting STUN discovery");
+            self.current_proto = if self.ipv6_is_enabled() {
+                IpProto::IPv6
+            } else {
+                IpProto::IPv4
+            };
+            self.exponential_backoff.reset();
+            self.transition_to_wait_for_wg();
+            self.try_transition_to_searching_for_server().await;
+        } else {
+            telio_log_info!("Network is gone, pausing STUN discovery");
+            self.stun_session = None;
+            self.stun_state = StunState::Paused;
+            self.current_timeout = PinnedSleep::new(STUN_TIMEOUT_PAUSED, ());
+            // Endpoints of the lost network are not reachable anymore
+            self.family_candidates.clear();
+            if !self.last_candidates.is_empty() {
+                self.last_candidates.clear();
+                if let Some(change_event) = &self.change_event {
+                    let _ = change_event
+                        .send((EndpointProviderType::Stun, vec![]))
+                        .await;
+                }
+            }
+        }
+    }
+
     fn transition_to_wait_for_wg(&mut self) {
         self.stun_state = StunState::WaitingForWg;
         self.current_timeout = PinnedSleep::new(STUN_TIMEOUT, ());
@@ -654,7 +731,17 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
 
     async fn transition_to_has_endpoints_state(&mut self, candidate: EndpointCandidate) {
         // Announce the new candidates
-        let candidates = vec![candidate];
+        let candidates = if self.is_dual_stack() {
+            // Candidate of the other family stays published until its own session fails
+            self.family_candidates
+                .insert(self.current_proto.into(), candidate);
+            let mut candidates: Vec<_> = self.family_candidates.values().cloned().collect();
+            // Both families may map to the same endpoint
+            candidates.dedup();
+            candidates
+        } else {
+            vec![candidate]
+        };
         if self.last_candidates != candidates {
             self.last_candidates = candidates.clone();
             if let Some(change_event) = &self.change_event {
@@ -671,9 +758,59 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
         self.exponential_backoff.reset();
         // Current backoff should be one derived from features
         self.current_timeout = PinnedSleep::new(self.exponential_backoff.get_backoff(), ());
+
+        if self.is_dual_stack() {
+            // Next round refreshes the other family, right away if it has no candidate yet
+            self.current_proto = self.current_proto.other();
+            if !self
+                .family_candidates
+                .contains_key(&self.current_proto.into())
+            {
+                // Session of the resolved family is already consumed, so only one runs
+                if let Err(err) = self.start_stun_session().await {
+                    telio_log_debug!("Starting session of the other family failed: {:?}", err);
+                }
+            }
+        }
+    }
+
+    /// Drop the candidate of the failed family while the other one is still published
+    ///
+    /// Returns `false` when there is no other candidate left to keep.
+    async fn try_keep_other_family(&mut self) -> bool {
+        if !self.is_dual_stack() {
+            return false;
+        }
+
+        let failed = AddressFamily::from(self.current_proto);
+        let _ = self.family_candidates.remove(&failed);
+        if self.family_candidates.is_empty() {
+            return false;
+        }
+
+        telio_log_debug!("STUN over {:?} failed, keeping the other family", failed);
+        let candidates: Vec<_> = self.family_candidates.values().cloned().collect();
+        if self.last_candidates != candidates {
+            self.last_candidates = candidates.clone();
+            if let Some(change_event) = &self.change_event {
+                let _ = change_event
+                    .send((EndpointProviderType::Stun, candidates))
+                    .await;
+            }
+        }
+
+        self.stun_session = None;
+        self.current_proto = self.current_proto.other();
+        self.stun_state = StunState::HasEndpoints;
+        self.current_timeout = PinnedSleep::new(self.exponential_backoff.get_backoff(), ());
+        true
     }
 
     async fn transition_to_backing_off_state_or_change_proto(&mut self) {
+        if self.try_keep_other_family().await {
+            return;
+        }
+
         // We failed so we clear the candidates if there are any
         if !self.last_candidates.is_empty() {
             self.last_candidates.clear();
@@ -734,6 +871,10 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
         false
     }
 
+    fn is_dual_stack(&self) -> bool {
+        self.dual_stack && self.ipv6_is_enabled()
+    }
+
     fn is_in_ipv6_mode(&self) -> bool {
         self.ipv6_is_enabled() && self.current_proto == IpProto::IPv6
     }
@@ -1146,6 +1287,63 @@ mod tests {
         env.stun_provider.stop().await;
     }
 
+    #[tokio::test]
+    async fn discovery_paused_without_network() {
+        let mut env = prepare_test_env(None, false).await;
+
+        env.configure_env().await;
+
+        let udp_endpoint = SocketAddr::new([1, 1, 1, 1].into(), 11111);
+        let wg_endpoint = SocketAddr::new([2, 2, 2, 2].into(), 22222);
+        let candidates = vec![EndpointCandidate {
+            udp: udp_endpoint,
+            wg: wg_endpoint,
+        }];
+
+        await_timeout!(stun_reply(
+            &env.peers[0].stun_sock,
+            XorMappedAddress::new(udp_endpoint)
+        ));
+        await_timeout!(stun_reply(
+            &env.peers[0].peer_sock_v4,
+            MappedAddress::new(wg_endpoint)
+        ));
+        let event = await_timeout!(env.change_event.recv());
+        assert_eq!(
+            event.expect("got event"),
+            (EndpointProviderType::Stun, candidates.clone())
+        );
+
+        // Endpoints of the lost network are withdrawn and nothing can resume the discovery
+        env.stun_provider.set_network_available(false).await;
+        let event = await_timeout!(env.change_event.recv());
+        assert_eq!(
+            event.expect("got event"),
+            (EndpointProviderType::Stun, vec![])
+        );
+        env.stun_provider.unpause().await;
+        assert!(env.stun_provider.is_paused().await);
+
+        // Fresh discovery once the network is back
+        env.stun_provider.set_network_available(true).await;
+        assert!(!env.stun_provider.is_paused().await);
+        await_timeout!(stun_reply(
+            &env.peers[0].stun_sock,
+            XorMappedAddress::new(udp_endpoint)
+        ));
+        await_timeout!(stun_reply(
+            &env.peers[0].peer_sock_v4,
+            MappedAddress::new(wg_endpoint)
+        ));
+        let event = await_timeout!(env.change_event.recv());
+        assert_eq!(
+            event.expect("got event"),
+            (EndpointProviderType::Stun, candidates)
+        );
+
+        env.stun_provider.stop().await;
+    }
+
     #[tokio::test]
     async fn collect_stun_endpoints_on_configure_ipv6() {
         // Just to make sure that it works well when given IPv6 socket
@@ -1182,7 +1380,7 @@ mod tests {
     #[tokio::test(start_paused = true)]
     async fn stun_ipv6v4_fallback() {
         let poll_interval = Duration::from_millis(10000);
-        let mut env = prepare_test_env_with_server_weights(None, vec![100, 110], true).await;
+        let mut env = prepare_test_env_with_server_weights(None, vec![100, 110], true, false).await;
         env.configure_env().await;
 
         tokio::task::yield_now().await;
@@ -1220,6 +1418,43 @@ mod tests {
         env.stun_provider.stop().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn dual_stack_keeps_both_families() {
+        let poll_interval = Duration::from_millis(10000);
+        let mut env = prepare_test_env_with_server_weights(None, vec![100], true, true).await;
+        env.configure_env().await;
+
+        let candidate_v4 = EndpointCandidate {
+            udp: SocketAddr::new([1, 1, 1, 1].into(), 11111),
+            wg: SocketAddr::new([2, 2, 2, 2].into(), 22222),
+        };
+        let candidate_v6 = EndpointCandidate {
+            udp: SocketAddr::new("2001:db8::1".parse().unwrap(), 11111),
+            wg: SocketAddr::new("2001:db8::2".parse().unwrap(), 22222),
+        };
+
+        // IPv6 is discovered first, IPv4 follows right away in a single new session
+        env.reply_with_candidate(0, IpProto::IPv6, &candidate_v6)
+            .await;
+        let (_, candidates) = await_timeout!(env.change_event.recv()).expect("got event");
+        assert_eq!(candidates, vec![candidate_v6.clone()]);
+
+        env.reply_with_candidate(0, IpProto::IPv4, &candidate_v4)
+            .await;
+        let (_, candidates) = await_timeout!(env.change_event.recv()).expect("got event");
+        assert_eq!(candidates, vec![candidate_v4.clone(), candidate_v6]);
+        assert!(env.change_event.try_recv().is_err());
+
+        // Failing IPv6 round keeps the IPv4 candidate published
+        jump_to_next_session_start(poll_interval).await;
+        env.timeout_both_sockets(0, IpProto::IPv6).await;
+        jump_to_next_session_start(STUN_TIMEOUT).await;
+        let (_, candidates) = await_timeout!(env.change_event.recv()).expect("got event");
+        assert_eq!(candidates, vec![candidate_v4]);
+
+        env.stun_provider.stop().await;
+    }
+
     #[tokio::test]
     async fn collect_stun_endpoints_on_change() {
         let mut env = prepare_test_env(None, false).await;
@@ -1657,7 +1892,8 @@ mod tests {
 
     #[tokio::test(start_paused = true)]
     async fn server_changed_when_current_connection_is_broken() {
-        let mut env = prepare_test_env_with_server_weights(None, vec![100, 200, 10], false).await;
+        let mut env =
+            prepare_test_env_with_server_weights(None, vec![100, 200, 10], false, false).await;
         let poll_interval = Duration::from_secs(10000);
 
         env.configure_env().await;
@@ -1686,7 +1922,8 @@ mod tests {
     #[tokio::test(start_paused = true)]
     #[cfg(not(target_os = "macos"))]
     async fn server_maintained_when_current_connection_is_active() {
-        let mut env = prepare_test_env_with_server_weights(None, vec![100, 200, 10], false).await;
+        let mut env =
+            prepare_test_env_with_server_weights(None, vec![100, 200, 10], false, false).await;
         let poll_interval = Duration::from_millis(10000);
 
         env.configure_env().await;
@@ -1787,6 +2024,7 @@ mod tests {
             Vec::new(),
             wg,
             false,
+            false,
         )
         .await;
 
@@ -1892,6 +2130,7 @@ mod tests {
             stun_peers,
             wg,
             false,
+            false,
         )
         .await;
 
@@ -1946,6 +2185,7 @@ mod tests {
         impl WireGuard for Wg {
             async fn get_interface(&self) -> Result<Interface, Error>;
             async fn get_adapter_luid(&self) -> Result<u64, Error>;
+            async fn get_adapter_type(&self) -> Result<telio_wg::AdapterType, Error>;
             async fn wait_for_listen_port(&self, d: Duration) -> Result<u16, Error>;
             async fn wait_for_proxy_listen_port(&self, d: Duration) -> Result<u16, Error>;
             async fn get_wg_socket(&self, ipv6: bool) -> Result<Option<i32>, Error>;
@@ -1956,9 +2196,13 @@ mod tests {
             async fn del_peer(&self, key: PublicKey) -> Result<(), Error>;
             async fn drop_connected_sockets(&self) -> Result<(), Error>;
             async fn time_since_last_rx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
+            async fn time_since_last_tx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
+            async fn get_handshake_counts(&self) -> Result<std::collections::HashMap<PublicKey, u64>, Error>;
             async fn stop(self);
             async fn reset_existing_connections(&self, exit_pubkey: PublicKey, exit_ipv4: Ipv4Addr) -> Result<(), Error>;
             async fn set_ip_stack(&self, ip_stack: Option<IpStack>) -> Result<(), Error>;
+            async fn set_mtu(&self, mtu: u16) -> Result<(), Error>;
+            async fn switch_adapter(&self, adapter: telio_wg::AdapterType) -> Result<(), Error>;
         }
     }
 
@@ -1995,13 +2239,14 @@ mod tests {
     }
 
     async fn prepare_test_env(backoff_array: Option<[u64; 6]>, ipv6: bool) -> Env {
-        prepare_test_env_with_server_weights(backoff_array, vec![100], ipv6).await
+        prepare_test_env_with_server_weights(backoff_array, vec![100], ipv6, false).await
     }
 
     async fn prepare_test_env_with_server_weights(
         backoff_array: Option<[u64; 6]>,
         server_weights: Vec<u32>,
         ipv6: bool,
+        dual_stack: bool,
     ) -> Env {
         let mut wg = MockWg::default();
         let wg_port = 12345;
@@ -2097,6 +2342,7 @@ mod tests {
             stun_peers,
             wg,
             ipv6,
+            dual_stack,
         )
         .await
     }
@@ -2107,6 +2353,7 @@ mod tests {
         stun_peers: Vec<StunPeerSockets>,
         wg: MockWg,
         ipv6: bool,
+        dual_stack: bool,
     ) -> Env {
         let socket_pool = SocketPool::new(
             NativeProtector::new(
@@ -2147,6 +2394,7 @@ mod tests {
             ping_pong_handler.clone(),
             stun_peer_publisher,
             false,
+            dual_stack,
         );
 
         let candidates_channel = Chan::<EndpointCandidatesChangeEvent>::default();
@@ -2254,8 +2502,22 @@ mod tests {
         }
 
         async fn reply_on_both_sockets(&self, server_num: usize, peer_sock_proto: IpProto) {
-            let udp_endpoint = SocketAddr::new([1, 1, 1, 1].into(), 11111);
-            let wg_endpoint = SocketAddr::new([2, 2, 2, 2].into(), 22222);
+            let candidate = EndpointCandidate {
+                udp: SocketAddr::new([1, 1, 1, 1].into(), 11111),
+                wg: SocketAddr::new([2, 2, 2, 2].into(), 22222),
+            };
+            self.reply_with_candidate(server_num, peer_sock_proto, &candidate)
+                .await;
+        }
+
+        async fn reply_with_candidate(
+            &self,
+            server_num: usize,
+            peer_sock_proto: IpProto,
+            candidate: &EndpointCandidate,
+        ) {
+            let udp_endpoint = candidate.udp;
+            let wg_endpoint = candidate.wg;
 
             await_timeout!(stun_reply(
                 &self.peers[server_num].stun_sock,
@@ -325,6 +325,7 @@ impl<Wg: WireGuard, I: UpnpEpCommands, E: Backoff> UpnpEndpointProvider<Wg, I, E
                 upnp_interval: PinnedSleep::new(initial_upnp_interval, ()),
                 is_battery_optimization_on,
                 is_endpoint_provider_paused: false,
+                is_network_available: true,
                 rx_buff,
                 igd_gw,
                 ping_pong_handler,
@@ -460,9 +461,19 @@ impl<Wg: WireGuard> EndpointProvider for UpnpEndpointProvider<Wg> {
     }
 
     async fn is_paused(&self) -> bool {
-        task_exec!(&self.task, async move |s| Ok(s.is_endpoint_provider_paused))
-            .await
-            .unwrap_or(false)
+        task_exec!(&self.task, async move |s| Ok(
+            s.is_endpoint_provider_paused || !s.is_network_available
+        ))
+        .await
+        .unwrap_or(false)
+    }
+
+    async fn set_network_available(&self, available: bool) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.set_network_available(available).await;
+            Ok(())
+        })
+        .await;
     }
 }
 
@@ -479,6 +490,7 @@ struct State<Wg: WireGuard, I: UpnpEpCommands, E: Backoff> {
     upnp_interval: PinnedSleep<()>,
     is_battery_optimization_on: bool,
     is_endpoint_provider_paused: bool,
+    is_network_available: bool,
     rx_buff: Vec<u8>,
     igd_gw: I,
     ping_pong_handler: Arc<Mutex<PingPongHandler>>,
@@ -616,6 +628,28 @@ impl<Wg: WireGuard, I: UpnpEpCommands, E: Backoff> State<Wg, I, E> {
         Ok(())
     }
 
+    async fn set_network_available(&mut self, available: bool) {
+        if self.is_network_available == available {
+            return;
+        }
+        self.is_network_available = available;
+
+        // Gateway of the lost network is gone, and so are its port mappings
+        self.igd_gw.drop_igd_gateway();
+        if available {
+            telio_log_info!("Network is back, restarting UPnP discovery");
+            self.exponential_backoff.reset();
+            self.upnp_interval = PinnedSleep::new(self.exponential_backoff.get_backoff(), ());
+        } else {
+            telio_log_info!("Network is gone, pausing UPnP discovery");
+            if self.endpoint_candidate.take().is_some() {
+                if let Some(epc_tx) = &self.epc_event_tx {
+                    let _ = epc_tx.send((EndpointProviderType::Upnp, vec![])).await;
+                }
+            }
+        }
+    }
+
     async fn send_endpoint_candidate(&self) {
         if let Some(epc) = self.endpoint_candidate.clone() {
             if let Some(epc_tx) = &self.epc_event_tx {
@@ -659,6 +693,12 @@ impl<Wg: WireGuard, I: UpnpEpCommands, E: Backoff> Runtime for State<Wg, I, E> {
     {
         pin!(updated);
 
+        if !self.is_network_available {
+            telio_log_debug!("Skipping getting endpoint via UPNP endpoint provider(NoNetwork)");
+            let update = updated.await;
+            return update(self).await;
+        }
+
         if !self.igd_gw.lease_needs_renew() && self.is_endpoint_provider_paused {
             let d = self.igd_gw.should_renew_lease_after();
             telio_log_debug!("Skipping getting endpoint via UPNP endpoint provider(ModulePaused), lease renw after {d:?}");
@@ -714,7 +754,7 @@ mod tests {
         async_trait, EndpointCandidate, MockUpnpEpCommands, UpnpEndpointProvider,
         EPHEMERAL_PORT_RANGE,
     };
-    use crate::endpoint_providers::Error;
+    use crate::endpoint_providers::{EndpointProvider, Error};
     use crate::ping_pong_handler::PingPongHandler;
     use lazy_static::lazy_static;
     use mockall::mock;
@@ -744,6 +784,7 @@ mod tests {
         impl WireGuard for Wg {
             async fn get_interface(&self) -> Result1<Interface,>;
             async fn get_adapter_luid(&self) -> Result1<u64>;
+            async fn get_adapter_type(&self) -> Result1<telio_wg::AdapterType>;
             async fn wait_for_listen_port(&self, d: Duration) -> Result1<u16>;
             async fn wait_for_proxy_listen_port(&self, d: Duration) -> Result1<u16>;
             async fn get_wg_socket(&self, ipv6: bool) -> Result1<Option<i32>>;
@@ -754,9 +795,13 @@ mod tests {
             async fn del_peer(&self, key: PublicKey) -> Result1<()>;
             async fn drop_connected_sockets(&self) -> Result1<()>;
             async fn time_since_last_rx(&self, public_key: PublicKey) -> Result1<Option<Duration>>;
+            async fn time_since_last_tx(&self, public_key: PublicKey) -> Result1<Option<Duration>>;
+            async fn get_handshake_counts(&self) -> Result1<std::collections::HashMap<PublicKey, u64>>;
             async fn stop(self);
             async fn reset_existing_connections(&self, exit_pubkey: PublicKey, exit_ipv4: Ipv4Addr) -> Result1<()>;
             async fn set_ip_stack(&self, ip_stack: Option<IpStack>) -> Result1<()>;
+            async fn set_mtu(&self, mtu: u16) -> Result1<()>;
+            async fn switch_adapter(&self, adapter: telio_wg::AdapterType) -> Result1<()>;
         }
     }
 
@@ -956,4 +1001,27 @@ mod tests {
         tokio::time::sleep(Duration::from_millis(200 + 20)).await;
         assert!(ENDPOINT.lock().wg.port() == old_port);
     }
+
+    #[tokio::test]
+    async fn test_no_discovery_without_network() {
+        let _quard = SEQUENTIAL_LOCK.lock().await;
+        let upnp = prepare_test_setup().await;
+        tokio::time::sleep(Duration::from_millis(100 + 20)).await;
+        assert!(upnp.get_endpoint_candidate().await.is_some());
+
+        upnp.set_network_available(false).await;
+        assert!(upnp.get_endpoint_candidate().await.is_none());
+        // Peers eligible for upgrade can't resume the discovery without network
+        upnp.unpause().await;
+        assert!(upnp.is_paused().await);
+        tokio::time::sleep(Duration::from_millis(200 + 20)).await;
+        assert!(!*IGD_IS_AVAILABLE.lock());
+        assert!(upnp.get_endpoint_candidate().await.is_none());
+
+        upnp.set_network_available(true).await;
+        assert!(!upnp.is_paused().await);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(*IGD_IS_AVAILABLE.lock());
+        assert!(upnp.get_endpoint_candidate().await.is_some());
+    }
 }
@@ -14,20 +14,32 @@ use telio_proto::{
     CodecError, PacketRelayed, PacketTypeRelayed, PingerMsg, Session, Timestamp, WGPort,
 };
 use telio_task::io::chan;
-use telio_utils::{telio_log_debug, telio_log_warn};
+use telio_utils::{telio_log_debug, telio_log_warn, LruCache};
 use tokio::{net::UdpSocket, sync::Mutex};
 
 use crate::endpoint_providers::PongEvent;
 
+/// For how long a pong answering our ping is accepted
+const PING_NONCE_TTL: Duration = Duration::from_secs(30);
+/// Most pings awaiting a pong at once
+const MAX_PINGS_IN_FLIGHT: usize = 4096;
+
 /// PingPongHandler will send and receive encrypted Pinger and Ponger messages.
 ///
 /// When Pinger is received it will be validated against configured set of allowed
 /// keys. When Ponger message is received it will validate against the set of live
-/// sessions.
+/// sessions and the pings sent.
+///
+/// Pongs are encrypted with the key of the session's peer, so only the peer itself can
+/// answer a ping. The session and the start timestamp of a ping act as its nonce, which
+/// is accepted once, so a pong replayed by a host colliding with a local candidate of the
+/// peer is dropped. The source address of the pong is not checked, it may differ from the
+/// pinged one behind endpoint dependent NAT.
 pub struct PingPongHandler {
     secret_key: SecretKey,
     known_keys: HashSet<PublicKey>,
     known_sessions: HashMap<Session, PublicKey>,
+    pings_in_flight: LruCache<(Session, Timestamp), ()>,
     rng: Mutex<StdRng>,
 }
 
@@ -38,6 +50,7 @@ impl PingPongHandler {
             secret_key,
             known_keys: Default::default(),
             known_sessions: Default::default(),
+            pings_in_flight: LruCache::new(PING_NONCE_TTL, MAX_PINGS_IN_FLIGHT),
             rng: Mutex::new(StdRng::from_entropy()),
         }
     }
@@ -45,6 +58,8 @@ impl PingPongHandler {
     /// Configure list of allowed sessions together with maching public keys of peer.
     pub fn configure(&mut self, known_sessions: HashMap<Session, PublicKey>) {
         self.known_keys = known_sessions.values().copied().collect();
+        self.pings_in_flight
+            .retain(|(session, _), _| known_sessions.contains_key(session));
         self.known_sessions = known_sessions;
     }
 
@@ -73,6 +88,7 @@ impl PingPongHandler {
 
         let buf = ping.encode_and_encrypt(encrypt_transform)?;
         udp_socket.send_to(&buf, addr).await?;
+        self.pings_in_flight.insert((session_id, ts), ());
 
         Ok(())
     }
@@ -81,7 +97,7 @@ impl PingPongHandler {
     ///
     /// Other message types are discarded.
     pub async fn handle_rx_packet(
-        &self,
+        &mut self,
         encrypted_buf: &[u8],
         addr: &SocketAddr,
         wg_port: WGPort,
@@ -129,6 +145,14 @@ impl PingPongHandler {
                                 .map_err(|e| CodecError::DecryptionFailed(e.to_string()))
                         };
                         let msg = packet.decrypt(decrypt_transform)?;
+                        let nonce = (msg.get_session(), msg.get_start_timestamp());
+                        if self.pings_in_flight.remove(&nonce).is_none() {
+                            telio_log_warn!(
+                                "Received pong from {:?} not answering any ping in flight, e.g. replayed",
+                                addr
+                            );
+                            return Ok(());
+                        }
                         telio_log_debug!("Received pong from {:?}, notifying", addr);
                         let ts = SystemTime::now()
                             .duration_since(UNIX_EPOCH)
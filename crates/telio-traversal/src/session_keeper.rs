@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 use socket2::Type;
+use std::collections::HashMap;
 use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
@@ -16,6 +17,10 @@ use telio_utils::{
 };
 const PING_PAYLOAD_SIZE: usize = 56;
 
+/// Keepalives of different peers are sent up to this early, so that they
+/// are batched into a single wakeup instead of waking up for every peer
+const KEEPALIVE_SLACK: Duration = Duration::from_secs(2);
+
 /// Possible [SessionKeeper] errors.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -52,6 +57,7 @@ pub trait SessionKeeperTrait {
     ) -> Result<()>;
     async fn remove_node(&self, key: &PublicKey) -> Result<()>;
     async fn get_interval(&self, key: &PublicKey) -> Option<u32>;
+    async fn get_keepalives_sent(&self) -> HashMap<PublicKey, u64>;
 }
 
 pub struct SessionKeeper {
@@ -80,7 +86,8 @@ impl SessionKeeper {
                     pinger_client_v6: client_v6,
                 },
 
-                actions: RepeatedActions::default(),
+                actions: RepeatedActions::with_slack(KEEPALIVE_SLACK),
+                keepalives_sent: HashMap::new(),
             }),
         })
     }
@@ -195,12 +202,13 @@ impl SessionKeeperTrait for SessionKeeper {
                 interval,
                 Arc::new(move |c| {
                     Box::pin(async move {
-                        if let Err(e) = ping(&c.pingers, (&public_key, &dual_target)).await {
-                            telio_log_warn!(
+                        match ping(&c.pingers, (&public_key, &dual_target)).await {
+                            Ok(()) => *c.keepalives_sent.entry(public_key).or_default() += 1,
+                            Err(e) => telio_log_warn!(
                                 "Failed to ping, peer with key: {:?}, error: {:?}",
                                 public_key,
                                 e
-                            );
+                            ),
                         }
                         Ok(())
                     })
@@ -219,6 +227,7 @@ impl SessionKeeperTrait for SessionKeeper {
         let pk = *key;
         task_exec!(&self.task, async move |s| {
             let _ = s.actions.remove_action(&pk);
+            s.keepalives_sent.remove(&pk);
             Ok(())
         })
         .await?;
@@ -234,6 +243,12 @@ impl SessionKeeperTrait for SessionKeeper {
         .await
         .unwrap_or(None)
     }
+
+    async fn get_keepalives_sent(&self) -> HashMap<PublicKey, u64> {
+        task_exec!(&self.task, async move |s| Ok(s.keepalives_sent.clone()))
+            .await
+            .unwrap_or_default()
+    }
 }
 
 struct Pingers {
@@ -244,6 +259,8 @@ struct Pingers {
 struct State {
     pingers: Pingers,
     actions: RepeatedActions<PublicKey, Self, Result<()>>,
+    // Keepalives sent to each node since it was added
+    keepalives_sent: HashMap<PublicKey, u64>,
 }
 
 #[async_trait]
@@ -284,7 +301,46 @@ mod tests {
     use telio_crypto::PublicKey;
     use telio_sockets::NativeProtector;
     use telio_test::assert_elapsed;
-    use tokio::time;
+    use tokio::time::{self, Instant};
+
+    /// Upper bound of wakeups per minute for an idle mesh
+    const IDLE_MESH_WAKEUPS_PER_MINUTE: u64 = 30;
+
+    async fn idle_mesh_wakeups_per_minute(slack: Duration) -> u64 {
+        const PEERS: u64 = 10;
+        const KEEPALIVE: Duration = Duration::from_secs(5);
+
+        let mut actions = RepeatedActions::<u64, (), ()>::with_slack(slack);
+        for peer in 0..PEERS {
+            actions
+                .add_action(peer, KEEPALIVE, Arc::new(|_: _| Box::pin(async {})))
+                .unwrap();
+            // First keepalive is sent right away
+            let (_, action) = actions.select_action().await.unwrap();
+            action(&mut ()).await;
+            // Peers come online at different times, so their keepalives are not aligned
+            time::advance(KEEPALIVE / PEERS as u32).await;
+        }
+
+        let wakeups = actions.wakeups();
+        let minute = time::sleep_until(Instant::now() + Duration::from_secs(60));
+        tokio::pin!(minute);
+        loop {
+            tokio::select! {
+                Ok((_, action)) = actions.select_action() => action(&mut ()).await,
+                _ = &mut minute => break,
+            }
+        }
+        actions.wakeups() - wakeups
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_mesh_wakeups_stay_under_target() {
+        assert!(idle_mesh_wakeups_per_minute(Duration::ZERO).await > IDLE_MESH_WAKEUPS_PER_MINUTE);
+        assert!(
+            idle_mesh_wakeups_per_minute(KEEPALIVE_SLACK).await <= IDLE_MESH_WAKEUPS_PER_MINUTE
+        );
+    }
 
     #[tokio::test(start_paused = false)]
     #[ignore = "cannot recv on internal ICMP socket"]
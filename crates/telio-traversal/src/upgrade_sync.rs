@@ -6,10 +6,10 @@ use std::net::SocketAddr;
 use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 use telio_crypto::{smaller_key_in_meshnet_canonical_order, PublicKey};
-use telio_model::features::EndpointProvider;
-use telio_proto::{Decision, Session, UpgradeDecisionMsg, UpgradeMsg};
+use telio_model::{config::PeerCapability, features::EndpointProvider};
+use telio_proto::{ControlHeader, Decision, Session, UpgradeDecisionMsg, UpgradeMsg};
 use telio_task::{io::chan, io::Chan, task_exec, BoxAction, Runtime, Task};
-use telio_utils::{interval, telio_log_debug, telio_log_info, telio_log_warn, LruCache};
+use telio_utils::{coalesced_interval, telio_log_debug, telio_log_info, telio_log_warn, LruCache};
 use tokio::{
     sync::mpsc::error::SendError,
     time::{Instant, Interval},
@@ -48,6 +48,15 @@ pub struct UpgradeRequest {
     pub session: Session,
 }
 
+/// Upgrades of the connection with a single peer, requested by either side
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct UpgradeCounts {
+    /// Upgrade requests sent to or received from the peer
+    pub attempted: u64,
+    /// Upgrades agreed on by both sides
+    pub succeeded: u64,
+}
+
 /// Used to indicate if the inner value was received from the other peer, or
 /// sent to it.
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -80,6 +89,7 @@ pub trait UpgradeSyncTrait {
     ) -> Result<bool>;
     async fn get_accepted_session(&self, public_key: PublicKey) -> Option<SessionData>;
     async fn clear_accepted_session(&self, public_key: PublicKey);
+    async fn get_upgrade_counts(&self) -> HashMap<PublicKey, UpgradeCounts>;
 }
 
 pub struct UpgradeSync {
@@ -90,6 +100,9 @@ pub struct UpgradeSync {
 pub struct SessionData {
     pub local_ep: EndpointProvider,
     pub remote_ep: EndpointProvider,
+    /// Our endpoint, known only if we have requested the upgrade
+    pub local_addr: Option<SocketAddr>,
+    pub remote_addr: SocketAddr,
 }
 
 pub struct State {
@@ -104,6 +117,9 @@ pub struct State {
     pending_direct_sessions: LruCache<Session, SessionData>,
     accepted_direct_sessions: HashMap<PublicKey, SessionData>,
     our_public_key: PublicKey,
+    upgrade_counts: HashMap<PublicKey, UpgradeCounts>,
+    /// Expired requests are not looked for while the device is suspended
+    suspended: bool,
 }
 
 impl UpgradeSync {
@@ -117,7 +133,7 @@ impl UpgradeSync {
         our_public_key: PublicKey,
     ) -> Result<Self> {
         telio_log_info!("Starting Upgrade sync module");
-        let poll_timer = interval(expiration_period / 2);
+        let poll_timer = coalesced_interval(expiration_period / 2);
         Ok(Self {
             task: Task::start(State {
                 upgrade_request_publisher,
@@ -133,6 +149,8 @@ impl UpgradeSync {
                 ),
                 accepted_direct_sessions: Default::default(),
                 our_public_key,
+                upgrade_counts: Default::default(),
+                suspended: false,
             }),
         })
     }
@@ -149,6 +167,26 @@ impl UpgradeSync {
         .await
         .map_err(|e| e.into())
     }
+
+    /// Drop the session and the counters of a peer which was removed
+    pub async fn forget_peer(&self, public_key: PublicKey) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.accepted_direct_sessions.remove(&public_key);
+            s.upgrade_counts.remove(&public_key);
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Stop the periodic expiration of the requests while the device is suspended
+    pub async fn set_suspended(&self, suspended: bool) -> Result<()> {
+        task_exec!(&self.task, async move |s| {
+            s.suspended = suspended;
+            Ok(())
+        })
+        .await
+        .map_err(|e| e.into())
+    }
 }
 
 #[async_trait]
@@ -201,6 +239,12 @@ impl UpgradeSyncTrait for UpgradeSync {
         })
         .await;
     }
+
+    async fn get_upgrade_counts(&self) -> HashMap<PublicKey, UpgradeCounts> {
+        task_exec!(&self.task, async move |s| Ok(s.upgrade_counts.clone()))
+            .await
+            .unwrap_or_default()
+    }
 }
 
 impl State {
@@ -242,10 +286,12 @@ impl State {
                     endpoint_type: local_direct_endpoint.1,
                     receiver_endpoint_type: remote_endpoint.1,
                     session,
+                    control: ControlHeader::local(),
                 },
             ))
             .await
             .map_err(Error::SendUpgradeMsgErr)?;
+        self.upgrade_counts_of(*public_key).attempted += 1;
 
         // Insert endpoint to local end to force our side to keep the endpoint too
 
@@ -261,6 +307,8 @@ impl State {
             SessionData {
                 local_ep: local_direct_endpoint.1,
                 remote_ep: remote_endpoint.1,
+                local_addr: Some(local_direct_endpoint.0),
+                remote_addr: remote_endpoint.0,
             },
         );
 
@@ -280,6 +328,7 @@ impl State {
             upgrade_msg.receiver_endpoint_type,
             upgrade_msg.session,
         );
+        self.upgrade_counts_of(*public_key).attempted += 1;
 
         match self
             .upgrade_controller
@@ -287,6 +336,7 @@ impl State {
             .await
         {
             Ok(true) => {
+                let mut winning_session = None;
                 let decision = match self.upgrade_requests.get(&Direction::Sent(*public_key)) {
                     Some(v) => {
                         if Self::is_expired(self.expiration_period, v) {
@@ -300,8 +350,22 @@ impl State {
                             // We reject this request, because we sent our own which is not
                             // yet expired and our key is winning - the other side should accept
                             // our request instead.
+                            winning_session = upgrade_msg
+                                .control
+                                .supports(PeerCapability::UpgradeRaceResolution)
+                                .then_some(v.session);
                             telio_proto::Decision::RejectedDueToConcurrentUpgrade
                         } else {
+                            // Our key is losing, so both sides settle on this request. Keeping
+                            // our own one around would leave each side using a different endpoint.
+                            telio_log_debug!(
+                                "Dropping our upgrade request for session {} in favor of {} from {public_key:?}",
+                                v.session,
+                                upgrade_msg.session
+                            );
+                            let session = v.session;
+                            self.upgrade_requests.remove(&Direction::Sent(*public_key));
+                            self.pending_direct_sessions.remove(&session);
                             telio_proto::Decision::Accepted
                         }
                     }
@@ -322,6 +386,8 @@ impl State {
                         UpgradeDecisionMsg {
                             decision,
                             session: upgrade_msg.session,
+                            winning_session,
+                            control: ControlHeader::local(),
                         },
                     ))
                     .await
@@ -336,8 +402,11 @@ impl State {
                     SessionData {
                         local_ep: upgrade_msg.receiver_endpoint_type,
                         remote_ep: upgrade_msg.endpoint_type,
+                        local_addr: None,
+                        remote_addr: upgrade_msg.endpoint,
                     },
                 );
+                self.upgrade_counts_of(*public_key).succeeded += 1;
             }
             Ok(false) => {
                 // We might have restarted in the middle of upgrade procedure and have
@@ -357,6 +426,8 @@ impl State {
                         UpgradeDecisionMsg {
                             decision,
                             session: upgrade_msg.session,
+                            winning_session: None,
+                            control: ControlHeader::local(),
                         },
                     ))
                     .await
@@ -378,6 +449,8 @@ impl State {
                         UpgradeDecisionMsg {
                             decision,
                             session: upgrade_msg.session,
+                            winning_session: None,
+                            control: ControlHeader::local(),
                         },
                     ))
                     .await
@@ -440,6 +513,10 @@ impl State {
         self.our_public_key = public_key;
     }
 
+    fn upgrade_counts_of(&mut self, public_key: PublicKey) -> &mut UpgradeCounts {
+        self.upgrade_counts.entry(public_key).or_default()
+    }
+
     fn is_expired(expiration_period: Duration, upgrade_request: &UpgradeRequest) -> bool {
         Instant::now() - upgrade_request.requested_at > expiration_period
     }
@@ -468,7 +545,8 @@ impl Runtime for State {
                 match msg.decision {
                     Decision::Accepted => {
                         if let Some(data) = self.pending_direct_sessions.remove(&msg.session) {
-                            self.accepted_direct_sessions.insert(public_key, SessionData {local_ep: data.local_ep, remote_ep: data.remote_ep});
+                            self.accepted_direct_sessions.insert(public_key, data);
+                            self.upgrade_counts_of(public_key).succeeded += 1;
                         } else {
                             telio_log_warn!("Received upgrade decision from {public_key:?} for unknow session: {msg:?}");
                         }
@@ -479,11 +557,13 @@ impl Runtime for State {
                         }
                     }
                     Decision::RejectedDueToConcurrentUpgrade => {
+                        telio_log_debug!("Upgrade request {} to {public_key:?} lost to their session {:?}", msg.session, msg.winning_session);
                         self.upgrade_requests.retain(|pk, req| !(pk == &Direction::Sent(public_key) && req.session == msg.session));
+                        self.pending_direct_sessions.remove(&msg.session);
                     }
                 }
             }
-            _ = self.poll_timer.tick() => {
+            _ = self.poll_timer.tick(), if !self.suspended => {
                 self.handle_tick()
                     .await
                     .unwrap_or_else(
@@ -615,6 +695,7 @@ mod tests {
             session: 42,
             endpoint_type: telio_model::features::EndpointProvider::Local,
             receiver_endpoint_type: telio_model::features::EndpointProvider::Local,
+            control: ControlHeader::local(),
         };
 
         let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
@@ -670,6 +751,7 @@ mod tests {
             session: 42,
             endpoint_type: telio_model::features::EndpointProvider::Stun,
             receiver_endpoint_type: telio_model::features::EndpointProvider::Stun,
+            control: ControlHeader::local(),
         };
 
         let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
@@ -706,6 +788,22 @@ mod tests {
                 }
             }
 
+            assert_eq!(
+                upg_sync.get_upgrade_counts().await,
+                HashMap::from([(
+                    pk,
+                    UpgradeCounts {
+                        attempted: 1,
+                        succeeded: 1
+                    }
+                )])
+            );
+
+            // Nothing is kept for a removed peer
+            upg_sync.forget_peer(pk).await;
+            assert!(upg_sync.get_upgrade_counts().await.is_empty());
+            assert!(upg_sync.get_accepted_session(pk).await.is_none());
+
             let _ = upg_sync.stop().await;
         })
         .await
@@ -740,6 +838,8 @@ mod tests {
                 UpgradeDecisionMsg {
                     decision: Decision::RejectedDueToUnknownSession,
                     session,
+                    winning_session: None,
+                    control: ControlHeader::local(),
                 },
             ))
             .await
@@ -785,6 +885,8 @@ mod tests {
                 UpgradeDecisionMsg {
                     decision: Decision::RejectedDueToConcurrentUpgrade,
                     session,
+                    winning_session: Some(43),
+                    control: ControlHeader::local(),
                 },
             ))
             .await
@@ -796,6 +898,157 @@ mod tests {
         .await;
     }
 
+    /// Generate a pair of keys ordered as (winning, losing) in a concurrent upgrade
+    fn concurrent_upgrade_keys() -> (PublicKey, PublicKey) {
+        let a = SecretKey::gen().public();
+        let b = SecretKey::gen().public();
+        if &a == smaller_key_in_meshnet_canonical_order(&a, &b) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_upgrade_with_winning_key_is_rejected() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, _upg_rq_rx, mut intercoms_them, mut upgrade_decision_them) =
+            setup(EXPIRY, Arc::new(KnowsAllSessions::new()));
+        let (winning_key, losing_key) = concurrent_upgrade_keys();
+        upg_sync.set_public_key(winning_key).await.unwrap();
+
+        let endpoint: (SocketAddr, EndpointProvider) = (
+            "127.0.0.1:6666".parse().unwrap(),
+            telio_model::features::EndpointProvider::Local,
+        );
+        assert!(upg_sync
+            .request_upgrade(&losing_key, endpoint, endpoint, 42)
+            .await
+            .unwrap());
+        assert_eq!(intercoms_them.rx.recv().await.unwrap().1.session, 42);
+
+        let upg_msg = UpgradeMsg {
+            endpoint: "127.0.0.1:7777".parse().unwrap(),
+            session: 43,
+            endpoint_type: telio_model::features::EndpointProvider::Local,
+            receiver_endpoint_type: telio_model::features::EndpointProvider::Local,
+            control: ControlHeader::local(),
+        };
+        intercoms_them.tx.send((losing_key, upg_msg)).await.unwrap();
+
+        let (_, decision) = upgrade_decision_them.rx.recv().await.unwrap();
+        assert_eq!(decision.decision, Decision::RejectedDueToConcurrentUpgrade);
+        assert_eq!(decision.session, 43);
+        assert_eq!(decision.winning_session, Some(42));
+
+        let requests = upg_sync.get_upgrade_requests().await.unwrap();
+        assert_eq!(requests.get(&losing_key).unwrap().session, 42);
+    }
+
+    #[tokio::test]
+    async fn concurrent_upgrade_from_legacy_peer_is_rejected_without_winning_session() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, _upg_rq_rx, mut intercoms_them, mut upgrade_decision_them) =
+            setup(EXPIRY, Arc::new(KnowsAllSessions::new()));
+        let (winning_key, losing_key) = concurrent_upgrade_keys();
+        upg_sync.set_public_key(winning_key).await.unwrap();
+
+        let endpoint: (SocketAddr, EndpointProvider) = (
+            "127.0.0.1:6666".parse().unwrap(),
+            telio_model::features::EndpointProvider::Local,
+        );
+        assert!(upg_sync
+            .request_upgrade(&losing_key, endpoint, endpoint, 42)
+            .await
+            .unwrap());
+        assert_eq!(intercoms_them.rx.recv().await.unwrap().1.session, 42);
+
+        let upg_msg = UpgradeMsg {
+            endpoint: "127.0.0.1:7777".parse().unwrap(),
+            session: 43,
+            endpoint_type: telio_model::features::EndpointProvider::Local,
+            receiver_endpoint_type: telio_model::features::EndpointProvider::Local,
+            control: ControlHeader::default(),
+        };
+        intercoms_them.tx.send((losing_key, upg_msg)).await.unwrap();
+
+        let (_, decision) = upgrade_decision_them.rx.recv().await.unwrap();
+        assert_eq!(decision.decision, Decision::RejectedDueToConcurrentUpgrade);
+        assert_eq!(decision.winning_session, None);
+        assert_eq!(decision.control, ControlHeader::local());
+    }
+
+    #[tokio::test]
+    async fn concurrent_upgrade_with_losing_key_drops_own_request() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, _upg_rq_rx, mut intercoms_them, mut upgrade_decision_them) =
+            setup(EXPIRY, Arc::new(KnowsAllSessions::new()));
+        let (winning_key, losing_key) = concurrent_upgrade_keys();
+        upg_sync.set_public_key(losing_key).await.unwrap();
+
+        let endpoint: (SocketAddr, EndpointProvider) = (
+            "127.0.0.1:6666".parse().unwrap(),
+            telio_model::features::EndpointProvider::Local,
+        );
+        assert!(upg_sync
+            .request_upgrade(&winning_key, endpoint, endpoint, 42)
+            .await
+            .unwrap());
+        assert_eq!(intercoms_them.rx.recv().await.unwrap().1.session, 42);
+
+        let upg_msg = UpgradeMsg {
+            endpoint: "127.0.0.1:7777".parse().unwrap(),
+            session: 43,
+            endpoint_type: telio_model::features::EndpointProvider::Local,
+            receiver_endpoint_type: telio_model::features::EndpointProvider::Local,
+            control: ControlHeader::local(),
+        };
+        intercoms_them
+            .tx
+            .send((winning_key, upg_msg))
+            .await
+            .unwrap();
+
+        let (_, decision) = upgrade_decision_them.rx.recv().await.unwrap();
+        assert_eq!(decision.decision, Decision::Accepted);
+        assert_eq!(decision.session, 43);
+
+        // Only the winning request is left, so both sides use the same endpoint
+        wait_for(Duration::from_secs(15), || async {
+            let requests = upg_sync.get_upgrade_requests().await.unwrap();
+            requests.len() == 1 && requests.get(&winning_key).unwrap().session == 43
+        })
+        .await;
+
+        // A late acceptance of our dropped session must not override the winning one
+        upgrade_decision_them
+            .tx
+            .send((
+                winning_key,
+                UpgradeDecisionMsg {
+                    decision: Decision::Accepted,
+                    session: 42,
+                    winning_session: None,
+                    control: ControlHeader::local(),
+                },
+            ))
+            .await
+            .unwrap();
+        // Decisions are handled as soon as they are taken off the queue, before any later request
+        let decisions = &upgrade_decision_them.tx;
+        wait_for(Duration::from_secs(15), || async {
+            tokio::task::yield_now().await;
+            decisions.capacity() == decisions.max_capacity()
+        })
+        .await;
+        let accepted = upg_sync.get_accepted_session(winning_key).await.unwrap();
+        assert_eq!(accepted.remote_addr, "127.0.0.1:7777".parse().unwrap());
+        assert_eq!(
+            upg_sync.get_upgrade_counts().await[&winning_key].succeeded,
+            1
+        );
+    }
+
     async fn wait_for<Fut>(timeout: Duration, pred: impl Fn() -> Fut)
     where
         Fut: Future<Output = bool>,
@@ -824,6 +1077,7 @@ mod tests {
             session: 42,
             endpoint_type: telio_model::features::EndpointProvider::Stun,
             receiver_endpoint_type: telio_model::features::EndpointProvider::Stun,
+            control: ControlHeader::local(),
         };
 
         intercoms_them
@@ -849,6 +1103,7 @@ mod tests {
             session: 42,
             endpoint_type: telio_model::features::EndpointProvider::Stun,
             receiver_endpoint_type: telio_model::features::EndpointProvider::Stun,
+            control: ControlHeader::local(),
         };
 
         let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
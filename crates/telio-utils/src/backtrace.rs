@@ -3,6 +3,17 @@ use backtrace;
 
 /// Print to debug log current backtrace
 pub fn log_current_backtrace() {
+    trace_frames(|frame| telio_log_debug!("backtrace: {frame}"));
+}
+
+/// Capture current backtrace, one frame per line
+pub fn current_backtrace() -> String {
+    let mut frames = Vec::new();
+    trace_frames(|frame| frames.push(frame));
+    frames.join("\n")
+}
+
+fn trace_frames(mut on_frame: impl FnMut(String)) {
     let mut counter = 0;
     backtrace::trace(|frame| {
         let ip = frame.ip();
@@ -22,7 +33,7 @@ pub fn log_current_backtrace() {
 
             let lineno = symbol.lineno().unwrap_or(0);
 
-            telio_log_debug!("backtrace: {counter:3} {ip:?} {filename}:{lineno} {name}");
+            on_frame(format!("{counter:3} {ip:?} {filename}:{lineno} {name}"));
             counter += 1;
         });
 
@@ -0,0 +1,183 @@
+//! Fault injection for resilience tests
+//!
+//! Faults are set on command, e.g. from tcli, and consulted by the hooks in the tunnel packet
+//! callbacks, the relayed packet path, the relay connection and the DNS forwarder. Packets are
+//! dropped in the tunnel, so on every path the peer is reached through, while the delays can
+//! only be added where the packets are forwarded by us, that is on the relayed path. They only take effect with the `chaos`
+//! feature, which is meant for test builds. Without it the setters are ignored and every hook is
+//! a no-op.
+
+#![cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+
+use std::{collections::HashSet, net::IpAddr, time::Duration};
+
+use rand::Rng;
+
+/// Faults injected into the packets exchanged with a peer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PacketFault {
+    /// Probability of dropping a packet, from 0 to 1
+    pub drop: f64,
+    /// Delay added to every packet
+    pub delay: Duration,
+    /// Upper bound of the random delay added on top, reordering the packets
+    pub jitter: Duration,
+}
+
+impl PacketFault {
+    /// Whether the next packet is dropped
+    pub fn drops(&self, rng: &mut impl Rng) -> bool {
+        rng.gen::<f64>() < self.drop
+    }
+
+    /// Delay of the next packet
+    pub fn delay(&self, rng: &mut impl Rng) -> Duration {
+        if self.jitter.is_zero() {
+            self.delay
+        } else {
+            self.delay + rng.gen_range(Duration::ZERO..=self.jitter)
+        }
+    }
+}
+
+#[cfg(feature = "chaos")]
+mod faults {
+    use super::PacketFault;
+    use parking_lot::Mutex;
+    use std::{
+        collections::{HashMap, HashSet},
+        net::IpAddr,
+        sync::OnceLock,
+    };
+    use tokio::sync::Notify;
+
+    #[derive(Default)]
+    pub(super) struct Faults {
+        pub(super) packets: HashMap<[u8; 32], PacketFault>,
+        pub(super) failing_dns_upstreams: HashSet<IpAddr>,
+    }
+
+    pub(super) fn get() -> &'static Mutex<Faults> {
+        static FAULTS: OnceLock<Mutex<Faults>> = OnceLock::new();
+        FAULTS.get_or_init(Default::default)
+    }
+
+    pub(super) fn relay_kill() -> &'static Notify {
+        static RELAY_KILL: OnceLock<Notify> = OnceLock::new();
+        RELAY_KILL.get_or_init(Notify::new)
+    }
+}
+
+/// Inject faults into the packets of the peer, `None` to stop
+pub fn set_packet_fault(peer: [u8; 32], fault: Option<PacketFault>) {
+    #[cfg(feature = "chaos")]
+    {
+        let packets = &mut faults::get().lock().packets;
+        match fault {
+            Some(fault) => packets.insert(peer, fault),
+            None => packets.remove(&peer),
+        };
+    }
+}
+
+/// Whether the next packet exchanged with the peer is dropped, consulted by the tunnel for
+/// packets in both directions
+pub fn packet_dropped(peer: &[u8; 32]) -> bool {
+    #[cfg(feature = "chaos")]
+    if let Some(fault) = faults::get().lock().packets.get(peer) {
+        return fault.drops(&mut rand::thread_rng());
+    }
+    false
+}
+
+/// Delay of the next packet relayed to or from the peer
+pub fn packet_delay(peer: &[u8; 32]) -> Duration {
+    #[cfg(feature = "chaos")]
+    if let Some(fault) = faults::get().lock().packets.get(peer) {
+        return fault.delay(&mut rand::thread_rng());
+    }
+    Duration::ZERO
+}
+
+/// Kill the relay connections which are currently up, they reconnect as after a network failure
+pub fn kill_relay() {
+    #[cfg(feature = "chaos")]
+    faults::relay_kill().notify_waiters();
+}
+
+/// Completes once the relay connection is killed, never without the `chaos` feature
+pub async fn relay_killed() {
+    #[cfg(feature = "chaos")]
+    faults::relay_kill().notified().await;
+    #[cfg(not(feature = "chaos"))]
+    futures::future::pending::<()>().await;
+}
+
+/// Make the queries to the upstream DNS servers fail, an empty set to stop
+pub fn set_failing_dns_upstreams(upstreams: HashSet<IpAddr>) {
+    #[cfg(feature = "chaos")]
+    {
+        faults::get().lock().failing_dns_upstreams = upstreams;
+    }
+}
+
+/// Whether the queries to the upstream DNS server should fail
+pub fn is_dns_upstream_failing(upstream: &IpAddr) -> bool {
+    #[cfg(feature = "chaos")]
+    if faults::get()
+        .lock()
+        .failing_dns_upstreams
+        .contains(upstream)
+    {
+        return true;
+    }
+    false
+}
+
+/// Stop injecting any faults
+pub fn clear() {
+    #[cfg(feature = "chaos")]
+    {
+        *faults::get().lock() = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn packets_follow_the_fault() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert!(!PacketFault::default().drops(&mut rng));
+        assert_eq!(PacketFault::default().delay(&mut rng), Duration::ZERO);
+        let drop = PacketFault {
+            drop: 1.0,
+            ..Default::default()
+        };
+        assert!(drop.drops(&mut rng));
+        let delay = PacketFault {
+            delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+        assert!(!delay.drops(&mut rng));
+        assert_eq!(delay.delay(&mut rng), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let fault = PacketFault {
+            delay: Duration::from_millis(100),
+            jitter: Duration::from_millis(50),
+            ..Default::default()
+        };
+
+        for _ in 0..100 {
+            assert!((Duration::from_millis(100)..=Duration::from_millis(150))
+                .contains(&fault.delay(&mut rng)));
+        }
+    }
+}
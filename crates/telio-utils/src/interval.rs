@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 use tokio::time::{self, Duration, Instant, Interval, MissedTickBehavior};
 
 /// Just like `tokio::time::interval` but the missed tick behaviour
@@ -14,3 +16,55 @@ pub fn interval_at(start: Instant, period: Duration) -> Interval {
     interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
     interval
 }
+
+/// Like [interval], but ticks are aligned to a process wide grid, so that
+/// independent tasks polling with the same (or a multiple of the same)
+/// period wake up together instead of one after another.
+///
+/// The first tick completes immediately. Missed ticks are skipped to stay
+/// on the grid.
+pub fn coalesced_interval(period: Duration) -> Interval {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+    let now = Instant::now();
+    let epoch = *EPOCH.get_or_init(|| now);
+    let since_epoch = now.saturating_duration_since(epoch);
+    let periods = since_epoch
+        .as_nanos()
+        .checked_div(period.as_nanos())
+        .and_then(|p| u32::try_from(p).ok())
+        .unwrap_or(0);
+    // Not further than `now`, so the first tick is immediate
+    let start = period
+        .checked_mul(periods)
+        .and_then(|offset| epoch.checked_add(offset))
+        .map_or(now, |s| s.min(now));
+
+    let mut interval = time::interval_at(start, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    interval
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesced_intervals_tick_together() {
+        let period = Duration::from_secs(5);
+        let mut first = coalesced_interval(period);
+        first.tick().await;
+
+        time::advance(Duration::from_millis(1500)).await;
+        let mut second = coalesced_interval(period);
+        let start = Instant::now();
+        second.tick().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // Both land on the same grid point despite being created at different times
+        first.tick().await;
+        let first_tick = Instant::now();
+        second.tick().await;
+        assert_eq!(Instant::now(), first_tick);
+    }
+}
@@ -12,6 +12,10 @@ pub use map::*;
 pub mod sleep;
 pub use sleep::*;
 
+/// Keyed periodic timers with coalesced wakeups
+pub mod timer_wheel;
+pub use timer_wheel::*;
+
 /// Timed, repeated actions
 pub mod repeated_actions;
 pub use repeated_actions::*;
@@ -62,3 +66,6 @@ pub mod log_censor;
 
 /// Utilities for working with backtraces/stacktraces/callstacks
 pub mod backtrace;
+
+/// Fault injection for resilience tests
+pub mod chaos;
@@ -168,6 +168,16 @@ impl<Key: Clone + Eq + Hash, Value> LruCache<Key, Value> {
         self.map.is_empty()
     }
 
+    /// Returns the number of bytes allocated for the entries and the hash table, expired entries
+    /// included. Heap data owned by the keys and values is not counted.
+    pub fn allocated_size(&self) -> usize {
+        // Each entry is a separately allocated node linked to its neighbours,
+        // the table itself only holds pointers to the nodes
+        let node =
+            std::mem::size_of::<(Key, TimedValue<Value>)>() + 2 * std::mem::size_of::<usize>();
+        self.map.len() * node + self.map.capacity() * std::mem::size_of::<usize>()
+    }
+
     #[cfg(test)]
     pub fn len_slow(&mut self) -> usize {
         let now = Instant::now();
@@ -278,6 +288,15 @@ impl<Key: Clone + Eq + Hash, Value> LruCache<Key, Value> {
         Some(&timed_value.data)
     }
 
+    /// Retains only the elements for which the predicate returns true
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Key, &mut Value) -> bool,
+    {
+        self.map
+            .retain(|key, timed_value| f(key, &mut timed_value.data));
+    }
+
     /// Returns an iterator over all (key, value) pairs
     pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
         self.map.iter().map(|(key, val)| (key, &val.data))
@@ -501,6 +520,23 @@ mod tests {
         assert_eq!(None, lru_cache.peek(&0));
     }
 
+    #[test]
+    fn retain_keeps_matching_entries() {
+        let mut lru_cache = LruCache::<usize, usize>::new(Duration::from_secs(1), usize::MAX);
+        for i in 0..6 {
+            lru_cache.insert(i, i * 10);
+        }
+
+        lru_cache.retain(|key, value| {
+            *value += 1;
+            key % 2 == 0
+        });
+
+        assert_eq!(lru_cache.len(), 3);
+        assert_eq!(lru_cache.peek(&2), Some(&21));
+        assert_eq!(lru_cache.peek(&3), None);
+    }
+
     mod remove_expired {
         use super::*;
 
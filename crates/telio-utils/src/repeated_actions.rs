@@ -1,17 +1,9 @@
-use std::{
-    collections::{hash_map, HashMap},
-    hash::Hash,
-    sync::Arc,
-};
+use std::{hash::Hash, sync::Arc};
 use thiserror::Error as ThisError;
 
-use crate::{interval, interval_at};
-use futures::{
-    future::BoxFuture,
-    stream::{FuturesUnordered, StreamExt},
-    FutureExt,
-};
-use tokio::time::{Duration, Instant, Interval};
+use crate::TimerWheel;
+use futures::future::BoxFuture;
+use tokio::time::{Duration, Instant};
 
 /// Possible [RepeatedAction] errors.
 #[derive(ThisError, Debug)]
@@ -33,7 +25,7 @@ type Result<T> = std::result::Result<T, RepeatedActionError>;
 
 /// Main struct container, that hold all actions
 pub struct RepeatedActions<K, C, R> {
-    actions: HashMap<K, (Interval, RepeatedAction<C, R>)>,
+    actions: TimerWheel<K, RepeatedAction<C, R>>,
 }
 
 impl<K, C, R> Default for RepeatedActions<K, C, R>
@@ -51,16 +43,20 @@ where
 {
     /// Container's constructor
     pub fn new() -> Self {
+        Self::with_slack(Duration::ZERO)
+    }
+
+    /// Container which may execute actions up to `slack` early, to batch
+    /// them together with other actions instead of waking up separately
+    pub fn with_slack(slack: Duration) -> Self {
         Self {
-            actions: HashMap::new(),
+            actions: TimerWheel::new(slack),
         }
     }
 
     /// Set all actions to be executed when polled next time
     pub fn set_all_immediate(&mut self) {
-        self.actions
-            .values_mut()
-            .for_each(|v| v.0.reset_immediately());
+        self.actions.set_all_immediate();
     }
 
     /// Add single action (first tick is immediate)
@@ -70,67 +66,55 @@ where
         dur: Duration,
         action: RepeatedAction<C, R>,
     ) -> Result<()> {
-        if let hash_map::Entry::Vacant(e) = self.actions.entry(key) {
-            e.insert((interval(dur), action));
-            return Ok(());
+        if self.actions.contains(&key) {
+            return Err(RepeatedActionError::DuplicateRepeatedAction);
         }
 
-        Err(RepeatedActionError::DuplicateRepeatedAction)
+        self.actions.insert(key, dur, Instant::now(), action);
+        Ok(())
     }
 
     /// Remove single action
     pub fn remove_action(&mut self, key: &K) -> Result<()> {
-        self.actions.remove(key).map_or_else(
-            || Err(RepeatedActionError::RepeatedActionNotFound),
-            |_| Ok(()),
-        )
+        self.actions
+            .remove(key)
+            .map(|_| ())
+            .ok_or(RepeatedActionError::RepeatedActionNotFound)
     }
 
     /// Update interval (first tick is now() + dur)
     pub fn update_interval(&mut self, key: &K, dur: Duration) -> Result<()> {
-        self.actions.get_mut(key).map_or_else(
-            || Err(RepeatedActionError::RepeatedActionNotFound),
-            |a| {
-                let interval = interval_at(Instant::now() + dur, dur);
-                a.0 = interval;
-                Ok(())
-            },
-        )
+        if self.actions.reschedule(key, dur, Instant::now() + dur) {
+            Ok(())
+        } else {
+            Err(RepeatedActionError::RepeatedActionNotFound)
+        }
     }
 
     /// Check if it contains action
     pub fn contains_action(&mut self, key: &K) -> bool {
-        self.actions.contains_key(key)
+        self.actions.contains(key)
     }
 
     /// Returns future (action), that will soon 'tick()'
     pub async fn select_action(&mut self) -> Result<(&K, RepeatedAction<C, R>)> {
-        if self.actions.is_empty() {
-            return Err(RepeatedActionError::ListEmpty);
-        }
-
-        // Collect futures
-        let a = self
-            .actions
-            .iter_mut()
-            .map(|(key, (interval, action))| (key, interval.tick(), action));
-
-        // Transform futures to `Output = (key, action)`
-        let mut b: FuturesUnordered<_> = a
-            .map(|(key, interval, action)| interval.map(move |_| (key, action)).boxed())
-            .collect();
-
-        b.next()
+        self.actions
+            .next()
             .await
-            .ok_or(RepeatedActionError::RepeatedActionNotFound)
-            .map(|(s, f)| (s, f.clone()))
+            .map(|(key, action)| (key, action.clone()))
+            .ok_or(RepeatedActionError::ListEmpty)
     }
 
     /// Returns the interval period in seconds
     pub fn get_interval(&self, key: &K) -> Option<u32> {
         self.actions
-            .get(key)
-            .and_then(|(i, _)| i.period().as_secs().try_into().ok())
+            .period(key)
+            .and_then(|period| period.as_secs().try_into().ok())
+    }
+
+    /// Number of times the container had to wake up to execute an action
+    pub fn wakeups(&self) -> u64 {
+        self.actions.wakeups()
     }
 }
 
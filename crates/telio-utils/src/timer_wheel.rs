@@ -0,0 +1,235 @@
+use std::{collections::HashMap, hash::Hash};
+
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// Ticks arriving later than this are considered missed and the timer is
+/// rescheduled from the current time, just like `MissedTickBehavior::Delay`.
+const LATE_TICK_TOLERANCE: Duration = Duration::from_millis(5);
+
+/// Set of keyed periodic timers sharing a single wakeup.
+///
+/// Only the earliest deadline is waited for. Once awake, every timer due
+/// within `slack` fires as well, so timers with close deadlines collapse
+/// into a single wakeup and stay aligned afterwards.
+pub struct TimerWheel<K, V> {
+    timers: HashMap<K, Timer<V>>,
+    slack: Duration,
+    wakeups: u64,
+}
+
+struct Timer<V> {
+    deadline: Instant,
+    period: Duration,
+    value: V,
+}
+
+impl<K, V> Default for TimerWheel<K, V>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+impl<K, V> TimerWheel<K, V>
+where
+    K: Eq + Hash,
+{
+    /// Create a wheel firing timers up to `slack` early to coalesce wakeups
+    pub fn new(slack: Duration) -> Self {
+        Self {
+            timers: HashMap::new(),
+            slack,
+            wakeups: 0,
+        }
+    }
+
+    /// Add a timer with its first tick at `start`, replacing any timer with the same key
+    pub fn insert(&mut self, key: K, period: Duration, start: Instant, value: V) -> Option<V> {
+        self.timers
+            .insert(
+                key,
+                Timer {
+                    deadline: start,
+                    period,
+                    value,
+                },
+            )
+            .map(|t| t.value)
+    }
+
+    /// Remove a timer
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.timers.remove(key).map(|t| t.value)
+    }
+
+    /// Check if the timer exists
+    pub fn contains(&self, key: &K) -> bool {
+        self.timers.contains_key(key)
+    }
+
+    /// Number of timers
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Check if there are no timers
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Period of the timer
+    pub fn period(&self, key: &K) -> Option<Duration> {
+        self.timers.get(key).map(|t| t.period)
+    }
+
+    /// Change the period of the timer, with the next tick at `start`
+    pub fn reschedule(&mut self, key: &K, period: Duration, start: Instant) -> bool {
+        self.timers
+            .get_mut(key)
+            .map(|t| {
+                t.period = period;
+                t.deadline = start;
+            })
+            .is_some()
+    }
+
+    /// Make all timers fire when polled next time
+    pub fn set_all_immediate(&mut self) {
+        let now = Instant::now();
+        self.timers.values_mut().for_each(|t| t.deadline = now);
+    }
+
+    /// Number of times the wheel had to sleep to fire a timer
+    pub fn wakeups(&self) -> u64 {
+        self.wakeups
+    }
+
+    /// Wait for the next timer to fire, `None` if there are no timers.
+    ///
+    /// Cancel safe, the timer is only rescheduled once it fires.
+    pub async fn next(&mut self) -> Option<(&K, &V)> {
+        let earliest = self.timers.values().map(|t| t.deadline).min()?;
+        if earliest > Instant::now() + self.slack {
+            sleep_until(earliest).await;
+            self.wakeups += 1;
+        }
+
+        let now = Instant::now();
+        let due = now + self.slack;
+        let (key, timer) = self
+            .timers
+            .iter_mut()
+            .filter(|(_, t)| t.deadline <= due)
+            .min_by_key(|(_, t)| t.deadline)?;
+
+        timer.deadline = if timer.deadline > now || now > timer.deadline + LATE_TICK_TOLERANCE {
+            // Fired early to coalesce or missed, either way align to the current wakeup
+            now + timer.period
+        } else {
+            timer.deadline + timer.period
+        };
+
+        Some((key, &timer.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn empty_wheel_returns_none() {
+        let mut wheel = TimerWheel::<u8, ()>::default();
+        assert!(wheel.next().await.is_none());
+        assert_eq!(wheel.wakeups(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timers_fire_in_deadline_order() {
+        let mut wheel = TimerWheel::default();
+        let start = Instant::now();
+        wheel.insert(
+            "slow",
+            Duration::from_secs(3),
+            start + Duration::from_secs(3),
+            (),
+        );
+        wheel.insert(
+            "fast",
+            Duration::from_secs(2),
+            start + Duration::from_secs(2),
+            (),
+        );
+
+        let mut fired = Vec::new();
+        for _ in 0..3 {
+            let (key, _) = wheel.next().await.unwrap();
+            fired.push((*key, start.elapsed().as_secs()));
+        }
+
+        assert_eq!(fired, vec![("fast", 2), ("slow", 3), ("fast", 4)]);
+        assert_eq!(wheel.wakeups(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_deadlines_are_coalesced() {
+        let mut wheel = TimerWheel::new(Duration::from_secs(1));
+        let start = Instant::now();
+        wheel.insert(
+            1,
+            Duration::from_secs(10),
+            start + Duration::from_secs(10),
+            (),
+        );
+        wheel.insert(
+            2,
+            Duration::from_secs(10),
+            start + Duration::from_millis(10_500),
+            (),
+        );
+
+        for _ in 0..2 {
+            wheel.next().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(10));
+        }
+        assert_eq!(wheel.wakeups(), 1);
+
+        // Both timers are aligned from now on
+        for _ in 0..2 {
+            wheel.next().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(20));
+        }
+        assert_eq!(wheel.wakeups(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reschedule_and_remove() {
+        let mut wheel = TimerWheel::default();
+        let start = Instant::now();
+        wheel.insert(1, Duration::from_secs(1), start, "one");
+        assert_eq!(wheel.period(&1), Some(Duration::from_secs(1)));
+
+        assert!(wheel.reschedule(&1, Duration::from_secs(5), start + Duration::from_secs(5)));
+        assert!(!wheel.reschedule(&2, Duration::from_secs(5), start));
+        assert_eq!(wheel.next().await, Some((&1, &"one")));
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        assert_eq!(wheel.remove(&1), Some("one"));
+        assert!(wheel.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missed_ticks_are_delayed() {
+        let mut wheel = TimerWheel::default();
+        let start = Instant::now();
+        wheel.insert((), Duration::from_secs(1), start, ());
+
+        time::advance(Duration::from_secs(3)).await;
+        wheel.next().await.unwrap();
+        wheel.next().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(4));
+    }
+}
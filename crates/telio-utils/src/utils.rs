@@ -36,6 +36,58 @@ macro_rules! telio_log_error {
        ( $format: expr, $($arg:tt)+) => { tracing::error!( $format,  $($arg)+) };
 }
 
+/// Hot path logs are kept, but only one of this many is emitted with the `hot_path_logs_sampled`
+/// feature
+pub const HOT_PATH_LOG_SAMPLE: u64 = 1024;
+
+/// Trace log on a per-packet path, e.g. the firewall verdicts.
+///
+/// Formatting the log costs more than handling the packet, so release builds may drop these at
+/// compile time with the `hot_path_logs_off` feature, or keep only a per-callsite counter with
+/// the `hot_path_logs_sampled` one. In both cases the arguments are never evaluated, so the
+/// handling of the packets takes the same time whatever the log level.
+#[cfg(not(any(feature = "hot_path_logs_off", feature = "hot_path_logs_sampled")))]
+#[macro_export]
+macro_rules! telio_log_hot {
+       ( $msg: expr) => {tracing::trace!($msg)};
+       ( $format: expr, $($arg:tt)+) => {tracing::trace!($format,  $($arg)+)};
+}
+
+/// Trace log on a per-packet path, removed at compile time
+#[cfg(feature = "hot_path_logs_off")]
+#[macro_export]
+macro_rules! telio_log_hot {
+    ( $format: expr $(, $($arg:tt)+)?) => {
+        // Type checked, but never evaluated
+        if false {
+            let _ = format_args!($format $(, $($arg)+)?);
+        }
+    };
+}
+
+/// Trace log on a per-packet path, counted and emitted without its arguments once per
+/// [`HOT_PATH_LOG_SAMPLE`](crate::utils::HOT_PATH_LOG_SAMPLE) hits
+#[cfg(all(feature = "hot_path_logs_sampled", not(feature = "hot_path_logs_off")))]
+#[macro_export]
+macro_rules! telio_log_hot {
+    ( $format: expr $(, $($arg:tt)+)?) => {{
+        static HITS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        if false {
+            let _ = format_args!($format $(, $($arg)+)?);
+        }
+        let hits = HITS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        if hits % $crate::utils::HOT_PATH_LOG_SAMPLE == 0 {
+            tracing::trace!(
+                "{} (hit {} times at {}:{})",
+                $format,
+                hits + 1,
+                file!(),
+                line!()
+            );
+        }
+    }};
+}
+
 /// Error with log is used to log something
 /// e.g. an error and give it back afterwards
 /// wrapped on a Err(..)
@@ -72,4 +124,14 @@ mod tests {
             telio_err_with_log!(counter.fetch_add(1, Ordering::Relaxed));
         assert_eq!(1, counter.load(Ordering::Relaxed));
     }
+
+    #[cfg(any(feature = "hot_path_logs_off", feature = "hot_path_logs_sampled"))]
+    #[test]
+    fn telio_log_hot_never_evaluates_arguments() {
+        let counter = AtomicUsize::new(0);
+        for _ in 0..3 {
+            telio_log_hot!("Packet {}", counter.fetch_add(1, Ordering::Relaxed));
+        }
+        assert_eq!(0, counter.load(Ordering::Relaxed));
+    }
 }
@@ -25,10 +25,14 @@ use std::{
     sync::Arc,
 };
 use telio_crypto::PublicKey;
+use telio_model::features::{FeatureHandshakeLoad, FeatureObfuscation};
 use telio_sockets::{Protect, SocketPool};
 use thiserror::Error as TError;
 
-use crate::uapi::{self, Cmd, Response};
+use crate::{
+    mtu, obfuscation,
+    uapi::{self, Cmd, Response},
+};
 
 /// Function pointer to Firewall Callback
 pub type FirewallCb = Option<Arc<dyn Fn(&[u8; 32], &[u8]) -> bool + Send + Sync>>;
@@ -61,6 +65,11 @@ pub trait Adapter: Send + Sync {
     /// details.
     async fn send_uapi_cmd(&self, cmd: &Cmd) -> Result<Response, Error>;
 
+    /// Send raw uapi command text, and receive the raw response text. Overridable
+    async fn send_raw_uapi_cmd(&self, _cmd: &str) -> Result<String, Error> {
+        Err(Error::UnsupportedAdapter)
+    }
+
     /// Get WireGuard adapter file descriptor. Overridable
     fn get_wg_socket(&self, _ipv6: bool) -> Result<Option<i32>, Error> {
         Ok(None)
@@ -71,6 +80,11 @@ pub trait Adapter: Send + Sync {
 
     /// Reset all the connections by injecting packets into the tunnel
     async fn inject_reset_packets(&self, _exit_pubkey: &PublicKey, _exit_ipv4_addr: Ipv4Addr) {}
+
+    /// Set MTU of the tunnel interface. Overridable
+    async fn set_mtu(&self, _mtu: u16) -> Result<(), Error> {
+        Err(mtu::Error::UnsupportedAdapter.into())
+    }
 }
 
 /// Enumeration of `Error` types for `Adapter` struct
@@ -103,6 +117,14 @@ pub enum Error {
     #[error("Unsupported adapter")]
     UnsupportedAdapter,
 
+    /// Handshake load threshold of zero would put the adapter under load permanently
+    #[error("Handshake load threshold must be positive")]
+    InvalidHandshakeLoadThreshold,
+
+    /// Adapter refused to apply the handshake load threshold
+    #[error("Adapter rejected handshake load threshold with errno {0}")]
+    HandshakeLoadRejected(i32),
+
     /// Unsupported on Windows adapter
     #[error("Mismatched windows adapter")]
     MismatchedWindowsAdapter,
@@ -130,6 +152,10 @@ pub enum Error {
     #[error("Unsupported operation error")]
     UnsupportedOperationError,
 
+    /// Tunnel supplied by the app is configured by the app and can't move to another adapter
+    #[error("Adapter can't be switched on a tunnel supplied by the app")]
+    AppOwnedTunnel,
+
     /// Duplicate Allowed IPs error
     #[error("Duplicate AllowedIPs Error")]
     DuplicateAllowedIPsError,
@@ -153,10 +179,18 @@ pub enum Error {
     /// Internal Error
     #[error("Internal error: {0}")]
     InternalError(&'static str),
+
+    /// Obfuscation could not be enabled
+    #[error(transparent)]
+    Obfuscation(#[from] obfuscation::Error),
+
+    /// MTU could not be changed
+    #[error(transparent)]
+    Mtu(#[from] mtu::Error),
 }
 
 /// Enumeration of types for `Adapter` struct
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AdapterType {
     /// NepTUN
     NepTUN,
@@ -184,6 +218,14 @@ impl Default for AdapterType {
     }
 }
 
+impl AdapterType {
+    /// Check if the adapter understands the UAPI keys of the obfuscation and of the handshake
+    /// rate limit, which only NepTUN builds with the `neptun_extensions` feature do
+    pub fn supports_uapi_extensions(&self) -> bool {
+        matches!(self, AdapterType::NepTUN) && cfg!(feature = "neptun_extensions")
+    }
+}
+
 impl FromStr for AdapterType {
     type Err = Error;
 
@@ -208,9 +250,32 @@ pub(crate) fn start(
     firewall_process_inbound_callback: FirewallCb,
     firewall_process_outbound_callback: FirewallCb,
     firewall_reset_conns_callback: FirewallResetConnsCb,
+    obfuscation: Option<FeatureObfuscation>,
+    handshake_load: Option<FeatureHandshakeLoad>,
 ) -> Result<Box<dyn Adapter>, Error> {
     #![allow(unused_variables)]
 
+    if let Some(params) = &obfuscation {
+        // Unobfuscated traffic would be mistaken for obfuscated, so it must not start at all
+        if !adapter.supports_uapi_extensions() {
+            return Err(obfuscation::Error::UnsupportedAdapter.into());
+        }
+        obfuscation::validate(params)?;
+    }
+
+    if let Some(params) = &handshake_load {
+        if params.under_load_threshold == 0 {
+            return Err(Error::InvalidHandshakeLoadThreshold);
+        }
+        // Other adapters have their own fixed thresholds, which are good enough to keep going
+        if !adapter.supports_uapi_extensions() {
+            telio_utils::telio_log_warn!(
+                "Handshake load protection is only configurable for NepTUN with UAPI extensions, ignoring"
+            );
+        }
+    }
+    let handshake_load = handshake_load.filter(|_| adapter.supports_uapi_extensions());
+
     match adapter {
         AdapterType::NepTUN => {
             #[cfg(windows)]
@@ -224,6 +289,8 @@ pub(crate) fn start(
                 firewall_process_inbound_callback,
                 firewall_process_outbound_callback,
                 firewall_reset_conns_callback,
+                obfuscation,
+                handshake_load,
             )?))
         }
         AdapterType::LinuxNativeWg => {
@@ -83,6 +83,11 @@ impl Adapter for LinuxNativeWg {
     async fn stop(&self) {
         let _ = self.rtsocket.lock().await.del_device(&self.ifname);
     }
+
+    async fn set_mtu(&self, mtu: u16) -> Result<(), AdapterError> {
+        telio_log_info!("Setting MTU of {} to {}", self.ifname, mtu);
+        Ok(crate::mtu::set_interface_mtu(&self.ifname, mtu)?)
+    }
 }
 
 impl Drop for LinuxNativeWg {
@@ -7,10 +7,12 @@ use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
 use std::{io, ops::Deref};
 use telio_crypto::PublicKey;
-use telio_utils::telio_log_debug;
+use telio_model::features::{FeatureHandshakeLoad, FeatureObfuscation};
+use telio_utils::{telio_log_debug, telio_log_info};
 use tokio::sync::RwLock;
 
 use super::{Adapter, Error as AdapterError, Tun as NativeTun};
+use crate::obfuscation;
 use crate::uapi::{self, Cmd, Response};
 
 use libc::socket;
@@ -34,6 +36,8 @@ impl NepTUN {
         firewall_process_inbound_callback: FirewallCb,
         firewall_process_outbound_callback: FirewallCb,
         firewall_reset_connections_callback: super::FirewallResetConnsCb,
+        obfuscation: Option<FeatureObfuscation>,
+        handshake_load: Option<FeatureHandshakeLoad>,
     ) -> Result<Self, AdapterError> {
         let config = DeviceConfig {
             // Apple's NepTUN device runs most efficiently on a single perf-core
@@ -70,6 +74,33 @@ impl NepTUN {
             None => DeviceHandle::new(name, config)?,
         };
 
+        if let Some(params) = obfuscation {
+            telio_log_info!("Enabling obfuscation: {:?}", params);
+            let res = device.send_uapi_cmd(&obfuscation::uapi_cmd(&params));
+            let errno = uapi::response_from_str(&res)?.errno;
+            if errno != 0 {
+                let mut device = device;
+                device.trigger_exit();
+                device.wait();
+                return Err(obfuscation::Error::Rejected(errno).into());
+            }
+        }
+
+        if let Some(params) = handshake_load {
+            telio_log_info!("Configuring handshake load protection: {:?}", params);
+            let res = device.send_uapi_cmd(&format!(
+                "set=1\nhandshake_rate_limit={}\n\n",
+                params.under_load_threshold
+            ));
+            let errno = uapi::response_from_str(&res)?.errno;
+            if errno != 0 {
+                let mut device = device;
+                device.trigger_exit();
+                device.wait();
+                return Err(AdapterError::HandshakeLoadRejected(errno));
+            }
+        }
+
         Ok(NepTUN {
             device: RwLock::new(device),
             reset_conns_cb: firewall_reset_connections_callback,
@@ -88,6 +119,10 @@ impl Adapter for NepTUN {
         Ok(uapi::response_from_str(&res)?)
     }
 
+    async fn send_raw_uapi_cmd(&self, cmd: &str) -> Result<String, AdapterError> {
+        Ok(self.send_uapi_cmd_str(cmd).await)
+    }
+
     fn get_adapter_luid(&self) -> u64 {
         0
     }
@@ -145,4 +180,26 @@ impl Adapter for NepTUN {
 
         cb(exit_pubkey, exit_ipv4, &mut sink4, &mut sink6);
     }
+
+    #[cfg(target_os = "linux")]
+    async fn set_mtu(&self, mtu: u16) -> Result<(), AdapterError> {
+        let name = {
+            let dev = self.device.read().await;
+            let dev = dev.device.read();
+            dev.iface().name()?
+        };
+        telio_log_info!("Setting MTU of {} to {}", name, mtu);
+        crate::mtu::set_interface_mtu(&name, mtu)?;
+
+        if cfg!(feature = "neptun_extensions") {
+            let res = self.send_uapi_cmd_str(&crate::mtu::uapi_cmd(mtu)).await;
+            let errno = uapi::response_from_str(&res)?.errno;
+            if errno != 0 {
+                return Err(crate::mtu::Error::Rejected(errno).into());
+            }
+        } else {
+            telio_log_debug!("Forwarded TCP connections are not clamped to the new MTU");
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,215 @@
+//! Tracking of the handshakes with the peers, turning the periodic polls of the adapter into
+//! the completed and failed handshake notifications
+
+use std::collections::{BTreeMap, HashMap};
+
+use telio_crypto::PublicKey;
+use telio_model::{
+    event::{HandshakeResult, PeerHandshake},
+    features::FeatureHandshakeEvents,
+};
+use tokio::time::{Duration, Instant};
+
+use crate::uapi::Peer;
+
+/// Sessions older than this are rejected by WireGuard, a new handshake is needed
+const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+
+#[derive(Default)]
+struct PeerHandshakes {
+    waiting_since: Option<Instant>,
+    failure_reported_at: Option<Instant>,
+}
+
+/// Tracks which peers are waiting for a session and for how long
+pub(crate) struct HandshakeTracker {
+    failure_timeout: Duration,
+    peers: HashMap<PublicKey, PeerHandshakes>,
+}
+
+impl HandshakeTracker {
+    pub(crate) fn new(feature: FeatureHandshakeEvents) -> Self {
+        Self {
+            failure_timeout: Duration::from_secs(feature.failure_timeout_s.into()),
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Update with the polled peers, returning the handshakes to report.
+    ///
+    /// Completion is only reported for the handshakes establishing a session with a peer which
+    /// had none, the periodic rekeying of a live session changes nothing for the app. A new peer
+    /// with a session already, e.g. handshaking faster than the polls, is reported completed as
+    /// well. Peers without an endpoint are not waiting for anything, as no handshake can be sent
+    /// to them.
+    pub(crate) fn observe(&mut self, peers: &BTreeMap<PublicKey, Peer>) -> Vec<PeerHandshake> {
+        let now = Instant::now();
+        self.peers.retain(|pk, _| peers.contains_key(pk));
+
+        let mut handshakes = Vec::new();
+        for (public_key, peer) in peers {
+            let is_new = !self.peers.contains_key(public_key);
+            let state = self.peers.entry(*public_key).or_default();
+            let has_session = peer
+                .time_since_last_handshake
+                .is_some_and(|since| since < REJECT_AFTER_TIME);
+
+            if has_session {
+                let since = state.waiting_since.take();
+                if since.is_some() || is_new {
+                    state.failure_reported_at = None;
+                    handshakes.push(PeerHandshake {
+                        public_key: *public_key,
+                        result: HandshakeResult::Completed,
+                        elapsed_ms: since.map_or(0, |since| elapsed_ms(since, now)),
+                    });
+                }
+            } else if peer.endpoint.is_some() {
+                let since = *state.waiting_since.get_or_insert(now);
+                let due = now.saturating_duration_since(state.failure_reported_at.unwrap_or(since))
+                    >= self.failure_timeout;
+                if due {
+                    state.failure_reported_at = Some(now);
+                    handshakes.push(PeerHandshake {
+                        public_key: *public_key,
+                        result: HandshakeResult::Failed,
+                        elapsed_ms: elapsed_ms(since, now),
+                    });
+                }
+            } else {
+                *state = PeerHandshakes::default();
+            }
+        }
+        handshakes
+    }
+}
+
+fn elapsed_ms(since: Instant, now: Instant) -> u64 {
+    now.saturating_duration_since(since).as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn tracker() -> HandshakeTracker {
+        HandshakeTracker::new(FeatureHandshakeEvents {
+            failure_timeout_s: TIMEOUT.as_secs() as u32,
+        })
+    }
+
+    fn peer(public_key: PublicKey, time_since_last_handshake: Option<Duration>) -> Peer {
+        Peer {
+            public_key,
+            endpoint: Some(([10, 0, 0, 1], 51820).into()),
+            time_since_last_handshake,
+            ..Default::default()
+        }
+    }
+
+    fn peers(peers: &[Peer]) -> BTreeMap<PublicKey, Peer> {
+        peers.iter().map(|p| (p.public_key, p.clone())).collect()
+    }
+
+    fn event(public_key: PublicKey, result: HandshakeResult, elapsed: Duration) -> PeerHandshake {
+        PeerHandshake {
+            public_key,
+            result,
+            elapsed_ms: elapsed.as_millis() as u64,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn completed_handshake_is_reported_once() {
+        let pk = SecretKey::gen().public();
+        let mut tracker = tracker();
+
+        assert_eq!(tracker.observe(&peers(&[peer(pk, None)])), vec![]);
+        time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(
+            tracker.observe(&peers(&[peer(pk, Some(Duration::ZERO))])),
+            vec![event(
+                pk,
+                HandshakeResult::Completed,
+                Duration::from_millis(1500)
+            )]
+        );
+        // Rekeying of the live session is not reported
+        time::advance(Duration::from_secs(120)).await;
+        assert_eq!(
+            tracker.observe(&peers(&[peer(pk, Some(Duration::ZERO))])),
+            vec![]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn session_established_before_first_poll_is_reported() {
+        let pk = SecretKey::gen().public();
+        let mut tracker = tracker();
+        let established = peers(&[peer(pk, Some(Duration::from_millis(200)))]);
+
+        assert_eq!(
+            tracker.observe(&established),
+            vec![event(pk, HandshakeResult::Completed, Duration::ZERO)]
+        );
+        time::advance(Duration::from_secs(1)).await;
+        assert_eq!(tracker.observe(&established), vec![]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failures_are_reported_once_per_timeout_until_completion() {
+        let pk = SecretKey::gen().public();
+        let mut tracker = tracker();
+        // Session expired long ago
+        let expired = peers(&[peer(pk, Some(REJECT_AFTER_TIME * 2))]);
+
+        assert_eq!(tracker.observe(&expired), vec![]);
+        time::advance(TIMEOUT).await;
+        assert_eq!(
+            tracker.observe(&expired),
+            vec![event(pk, HandshakeResult::Failed, TIMEOUT)]
+        );
+        time::advance(TIMEOUT / 2).await;
+        assert_eq!(tracker.observe(&expired), vec![]);
+        time::advance(TIMEOUT / 2).await;
+        assert_eq!(
+            tracker.observe(&expired),
+            vec![event(pk, HandshakeResult::Failed, TIMEOUT * 2)]
+        );
+
+        time::advance(TIMEOUT / 2).await;
+        assert_eq!(
+            tracker.observe(&peers(&[peer(pk, Some(Duration::ZERO))])),
+            vec![event(
+                pk,
+                HandshakeResult::Completed,
+                TIMEOUT * 2 + TIMEOUT / 2
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn peers_without_endpoint_or_removed_start_over() {
+        let pk = SecretKey::gen().public();
+        let mut tracker = tracker();
+        let waiting = peers(&[peer(pk, None)]);
+
+        assert_eq!(tracker.observe(&waiting), vec![]);
+        time::advance(TIMEOUT / 2).await;
+        assert_eq!(tracker.observe(&BTreeMap::new()), vec![]);
+        assert_eq!(tracker.observe(&waiting), vec![]);
+        time::advance(TIMEOUT / 2).await;
+        assert_eq!(tracker.observe(&waiting), vec![]);
+
+        let mut no_endpoint = peer(pk, None);
+        no_endpoint.endpoint = None;
+        time::advance(TIMEOUT).await;
+        assert_eq!(tracker.observe(&peers(&[no_endpoint])), vec![]);
+        time::advance(TIMEOUT).await;
+        assert_eq!(tracker.observe(&waiting), vec![]);
+    }
+}
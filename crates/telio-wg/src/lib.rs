@@ -5,12 +5,20 @@
 //! Interface between [WireGuard](https://wireguard.com/) and the telio library
 
 pub(crate) mod adapter;
+pub(crate) mod handshake;
 pub(crate) mod wg;
 pub(crate) mod windows;
 
+pub mod mtu;
+pub mod obfuscation;
 pub mod uapi;
 
+mod link_config;
 mod link_detection;
+#[cfg(target_os = "linux")]
+mod netlink;
+#[cfg(unix)]
+mod uapi_socket;
 
 pub use crate::{
     adapter::{Adapter, AdapterType, Error, FirewallCb, Tun},
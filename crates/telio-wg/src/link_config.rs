@@ -0,0 +1,215 @@
+//! Interface config kept by the OS, which is lost together with the interface when the adapter
+//! is recreated
+//!
+//! On Linux the addresses, routes, MTU and state of the interface are saved before the old
+//! adapter is stopped and applied again on the interface of the new one. Elsewhere the app has to
+//! configure the interface again once the adapter is recovered.
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+use crate::netlink::{
+    find_attr, read_u16, read_u32, Socket, IFADDRMSG_LEN, NLMSG_HDR_LEN, NLM_F_ACK, NLM_F_CREATE,
+    NLM_F_REPLACE, NLM_F_REQUEST, RTA_OIF, RTMSG_LEN, RTM_GETADDR, RTM_GETROUTE, RTM_NEWADDR,
+    RTM_NEWROUTE,
+};
+
+/// Saved config of the interface
+#[cfg(target_os = "linux")]
+pub(crate) struct LinkConfig {
+    name: String,
+    index: u32,
+    up: bool,
+    mtu: libc::c_int,
+    /// Requests adding the addresses and the routes back, addresses first
+    requests: Vec<Vec<u8>>,
+}
+
+/// Saved config of the interface
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct LinkConfig;
+
+#[cfg(target_os = "linux")]
+impl LinkConfig {
+    pub(crate) fn save(name: &str) -> io::Result<Self> {
+        let mut ifr = crate::mtu::ifreq(name)?;
+        let index = unsafe { libc::if_nametoindex(ifr.ifr_name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        ioctl(libc::SIOCGIFFLAGS, &mut ifr)?;
+        let up = unsafe { ifr.ifr_ifru.ifru_flags } & libc::IFF_UP as libc::c_short != 0;
+        ioctl(libc::SIOCGIFMTU, &mut ifr)?;
+        let mtu = unsafe { ifr.ifr_ifru.ifru_mtu };
+
+        let sock = Socket::open()?;
+        let mut requests = Vec::new();
+        for (kind, hdr_len) in [(RTM_GETADDR, IFADDRMSG_LEN), (RTM_GETROUTE, RTMSG_LEN)] {
+            for family in [libc::AF_INET, libc::AF_INET6] {
+                for msg in sock.dump(kind, family as u8, hdr_len)? {
+                    requests.extend(replay(&msg, index, index));
+                }
+            }
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            index,
+            up,
+            mtu,
+            requests,
+        })
+    }
+
+    /// Apply the saved config on the interface of the same name, which may have a new index
+    pub(crate) fn restore(&self) -> io::Result<()> {
+        let mut ifr = crate::mtu::ifreq(&self.name)?;
+        let index = unsafe { libc::if_nametoindex(ifr.ifr_name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        ifr.ifr_ifru.ifru_mtu = self.mtu;
+        ioctl(libc::SIOCSIFMTU, &mut ifr)?;
+        if self.up {
+            // Routes can't be added through an interface which is down
+            ioctl(libc::SIOCGIFFLAGS, &mut ifr)?;
+            unsafe { ifr.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+            ioctl(libc::SIOCSIFFLAGS, &mut ifr)?;
+        }
+
+        let sock = Socket::open()?;
+        for req in self.requests.iter() {
+            if let Some(req) = replay(req, self.index, index) {
+                sock.request(&req)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl LinkConfig {
+    pub(crate) fn save(_name: &str) -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    pub(crate) fn restore(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Link local IPv6 addresses are added by the kernel with the interface
+#[cfg(target_os = "linux")]
+const RT_SCOPE_LINK: u8 = 253;
+/// Routes of the addresses are added by the kernel with the addresses
+#[cfg(target_os = "linux")]
+const RTPROT_KERNEL: u8 = 2;
+
+#[cfg(target_os = "linux")]
+fn ioctl(request: libc::c_ulong, ifr: &mut libc::ifreq) -> io::Result<()> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let res = unsafe { libc::ioctl(sock, request as _, ifr as *mut libc::ifreq) };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(sock) };
+
+    if res < 0 {
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Request adding the address or the route of the interface `from` to the interface `to`,
+/// unless it is one the kernel adds by itself
+#[cfg(target_os = "linux")]
+fn replay(msg: &[u8], from: u32, to: u32) -> Option<Vec<u8>> {
+    let mut req = msg.to_vec();
+    let index = match read_u16(msg, 4)? {
+        RTM_NEWADDR => {
+            let family = *msg.get(NLMSG_HDR_LEN)?;
+            let scope = *msg.get(NLMSG_HDR_LEN + 3)?;
+            if family == libc::AF_INET6 as u8 && scope == RT_SCOPE_LINK {
+                return None;
+            }
+            NLMSG_HDR_LEN + 4..NLMSG_HDR_LEN + 8
+        }
+        RTM_NEWROUTE => {
+            if *msg.get(NLMSG_HDR_LEN + 5)? == RTPROT_KERNEL {
+                return None;
+            }
+            let oif = find_attr(msg, NLMSG_HDR_LEN + RTMSG_LEN..msg.len(), RTA_OIF)?;
+            oif.start..oif.start + 4
+        }
+        _ => return None,
+    };
+    if read_u32(msg, index.start)? != from {
+        return None;
+    }
+
+    req.get_mut(index)?.copy_from_slice(&to.to_ne_bytes());
+    req.get_mut(6..8)?
+        .copy_from_slice(&(NLM_F_REQUEST | NLM_F_CREATE | NLM_F_REPLACE | NLM_F_ACK).to_ne_bytes());
+    Some(req)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::netlink::{align, RTA_HDR_LEN};
+
+    fn msg(kind: u16, hdr: &[u8], attrs: &[u8]) -> Vec<u8> {
+        let len = NLMSG_HDR_LEN + hdr.len() + attrs.len();
+        let mut msg = (len as u32).to_ne_bytes().to_vec();
+        msg.extend_from_slice(&kind.to_ne_bytes());
+        msg.extend_from_slice(&0x2u16.to_ne_bytes());
+        msg.extend_from_slice(&[0; 8]);
+        msg.extend_from_slice(hdr);
+        msg.extend_from_slice(attrs);
+        msg
+    }
+
+    fn address(family: libc::c_int, scope: u8, index: u32) -> Vec<u8> {
+        let mut hdr = vec![family as u8, 24, 0, scope];
+        hdr.extend_from_slice(&index.to_ne_bytes());
+        msg(RTM_NEWADDR, &hdr, &[])
+    }
+
+    fn route(protocol: u8, oif: u32) -> Vec<u8> {
+        let mut hdr = [0; RTMSG_LEN];
+        hdr[0] = libc::AF_INET as u8;
+        hdr[5] = protocol;
+        let mut attr = ((RTA_HDR_LEN + 4) as u16).to_ne_bytes().to_vec();
+        attr.extend_from_slice(&RTA_OIF.to_ne_bytes());
+        attr.extend_from_slice(&oif.to_ne_bytes());
+        attr.resize(align(attr.len()), 0);
+        msg(RTM_NEWROUTE, &hdr, &attr)
+    }
+
+    #[test]
+    fn config_of_interface_is_moved_to_new_index() {
+        let flags = Some(NLM_F_REQUEST | NLM_F_CREATE | NLM_F_REPLACE | NLM_F_ACK);
+
+        let req = replay(&address(libc::AF_INET, 0, 7), 7, 9).unwrap();
+        assert_eq!(read_u16(&req, 6), flags);
+        assert_eq!(req[8..], address(libc::AF_INET, 0, 9)[8..]);
+
+        // Static routes are added by the app, unlike the kernel ones
+        let req = replay(&route(4, 7), 7, 9).unwrap();
+        assert_eq!(read_u16(&req, 6), flags);
+        assert_eq!(req[8..], route(4, 9)[8..]);
+    }
+
+    #[test]
+    fn config_added_by_kernel_or_of_other_interfaces_is_skipped() {
+        assert_eq!(
+            replay(&address(libc::AF_INET6, RT_SCOPE_LINK, 7), 7, 9),
+            None
+        );
+        assert_eq!(replay(&address(libc::AF_INET, 0, 3), 7, 9), None);
+        assert_eq!(replay(&route(RTPROT_KERNEL, 7), 7, 9), None);
+        assert_eq!(replay(&route(4, 3), 7, 9), None);
+        assert_eq!(replay(&[0; 8], 7, 9), None);
+    }
+}
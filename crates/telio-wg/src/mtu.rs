@@ -0,0 +1,258 @@
+//! MTU of the tunnel interface
+//!
+//! TCP connections over the tunnel derive their MSS from the MTU of the interface, so changing
+//! it at runtime affects the new connections right away. Forwarded traffic is clamped to
+//! [mss_ipv4] and [mss_ipv6] by NepTUN built with the `neptun_extensions` feature, with the other
+//! adapters it has to be done by whoever forwards it.
+//!
+//! Routes through the interface follow its MTU, unless they pin one of their own. Those are
+//! rewritten to the new MTU as well, otherwise they would keep the old one.
+
+use std::fmt::Write;
+use std::io;
+
+/// Overhead of WireGuard over IPv6: IPv6 and UDP headers, data message header and the tag
+const WG_OVERHEAD: u16 = 40 + 8 + 16 + 16;
+/// Largest MTU of the physical interfaces, jumbo frames
+const MAX_LINK_MTU: u16 = 9000;
+
+/// Smallest MTU the tunnel may have, the minimal IPv6 MTU
+pub const MIN_MTU: u16 = 1280;
+/// Largest MTU the tunnel may have, the WireGuard packets must fit into jumbo frames
+pub const MAX_MTU: u16 = MAX_LINK_MTU - WG_OVERHEAD;
+/// MTU of the tunnel when nothing else is known about the network
+pub const DEFAULT_MTU: u16 = 1420;
+
+/// MTU errors
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// MTU is too small or too large for the tunnel
+    #[error("MTU {0} is outside of the supported range {MIN_MTU}..={MAX_MTU}")]
+    OutOfRange(u16),
+    /// Adapter can't change the MTU of its interface, it has to be done by the app
+    #[error("Changing MTU is not supported by the adapter")]
+    UnsupportedAdapter,
+    /// Interface refused the MTU
+    #[error("Failed to set MTU: {0}")]
+    Io(#[from] io::Error),
+    /// Adapter refused to clamp the MSS
+    #[error("Adapter rejected MSS clamping with errno {0}")]
+    Rejected(i32),
+}
+
+/// Check that the MTU is within the limits of the tunnel
+pub fn validate(mtu: u16) -> Result<(), Error> {
+    if (MIN_MTU..=MAX_MTU).contains(&mtu) {
+        Ok(())
+    } else {
+        Err(Error::OutOfRange(mtu))
+    }
+}
+
+/// Largest TCP segment of IPv4 connections fitting into the tunnel
+pub fn mss_ipv4(mtu: u16) -> u16 {
+    // IPv4 and TCP headers
+    mtu.saturating_sub(20 + 20)
+}
+
+/// Largest TCP segment of IPv6 connections fitting into the tunnel
+pub fn mss_ipv6(mtu: u16) -> u16 {
+    // IPv6 and TCP headers
+    mtu.saturating_sub(40 + 20)
+}
+
+/// Build the UAPI set command clamping the MSS of the forwarded TCP connections
+pub fn uapi_cmd(mtu: u16) -> String {
+    let mut cmd = String::from("set=1\n");
+    let _ = writeln!(cmd, "mss_clamp_ipv4={}", mss_ipv4(mtu));
+    let _ = writeln!(cmd, "mss_clamp_ipv6={}", mss_ipv6(mtu));
+    cmd.push('\n');
+    cmd
+}
+
+/// Set the MTU of the interface and of the routes through it
+#[cfg(target_os = "linux")]
+pub(crate) fn set_interface_mtu(name: &str, mtu: u16) -> Result<(), Error> {
+    validate(mtu)?;
+
+    let mut ifr = ifreq(name)?;
+    ifr.ifr_ifru.ifru_mtu = mtu.into();
+
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let res = unsafe { libc::ioctl(sock, libc::SIOCSIFMTU as _, &ifr) };
+    let err = io::Error::last_os_error();
+    unsafe { libc::close(sock) };
+
+    if res < 0 {
+        return Err(err.into());
+    }
+
+    let ifindex = unsafe { libc::if_nametoindex(ifr.ifr_name.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let updated = route::set_mtu(ifindex, mtu)?;
+    if updated > 0 {
+        telio_utils::telio_log_debug!("Updated MTU of {} routes through {}", updated, name);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn ifreq(name: &str) -> io::Result<libc::ifreq> {
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    if name.len() >= ifr.ifr_name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Interface name is too long",
+        ));
+    }
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    Ok(ifr)
+}
+
+/// Routes with the MTU metric, see rtnetlink(7)
+#[cfg(target_os = "linux")]
+mod route {
+    use std::io;
+
+    use crate::netlink::{
+        find_attr, read_u16, read_u32, Socket, NLMSG_HDR_LEN, NLM_F_ACK, NLM_F_REPLACE,
+        NLM_F_REQUEST, RTA_OIF, RTMSG_LEN, RTM_GETROUTE, RTM_NEWROUTE,
+    };
+
+    const RTA_METRICS: u16 = 8;
+    const RTAX_MTU: u16 = 2;
+
+    /// Rewrite the routes through the interface which pin their MTU, returns how many were
+    pub(super) fn set_mtu(ifindex: u32, mtu: u16) -> io::Result<usize> {
+        let sock = Socket::open()?;
+        let mut updated = 0;
+        for family in [libc::AF_INET, libc::AF_INET6] {
+            for route in sock.dump(RTM_GETROUTE, family as u8, RTMSG_LEN)? {
+                if let Some(req) = with_mtu(&route, ifindex, mtu) {
+                    sock.request(&req)?;
+                    updated += 1;
+                }
+            }
+        }
+        Ok(updated)
+    }
+
+    /// Replace request of the route with the MTU changed, if the route goes through the
+    /// interface and pins a different MTU
+    pub(super) fn with_mtu(msg: &[u8], ifindex: u32, mtu: u16) -> Option<Vec<u8>> {
+        if read_u16(msg, 4)? != RTM_NEWROUTE {
+            return None;
+        }
+        let attrs = NLMSG_HDR_LEN + RTMSG_LEN..msg.len();
+        let oif = find_attr(msg, attrs.clone(), RTA_OIF)?;
+        if read_u32(msg, oif.start)? != ifindex {
+            return None;
+        }
+        let metrics = find_attr(msg, attrs, RTA_METRICS)?;
+        let pinned = find_attr(msg, metrics, RTAX_MTU)?;
+        if read_u32(msg, pinned.start)? == u32::from(mtu) {
+            return None;
+        }
+
+        let mut req = msg.to_vec();
+        req.get_mut(pinned.start..pinned.start + 4)?
+            .copy_from_slice(&u32::from(mtu).to_ne_bytes());
+        req.get_mut(6..8)?
+            .copy_from_slice(&(NLM_F_REQUEST | NLM_F_REPLACE | NLM_F_ACK).to_ne_bytes());
+        Some(req)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::netlink::{align, RTA_HDR_LEN};
+
+        fn attr(kind: u16, payload: &[u8]) -> Vec<u8> {
+            let mut attr = ((RTA_HDR_LEN + payload.len()) as u16)
+                .to_ne_bytes()
+                .to_vec();
+            attr.extend_from_slice(&kind.to_ne_bytes());
+            attr.extend_from_slice(payload);
+            attr.resize(align(attr.len()), 0);
+            attr
+        }
+
+        fn route(oif: u32, mtu: Option<u32>) -> Vec<u8> {
+            let mut attrs = attr(RTA_OIF, &oif.to_ne_bytes());
+            if let Some(mtu) = mtu {
+                // Metrics are nested, the kernel sets the flag in the dumps
+                attrs.extend(attr(
+                    RTA_METRICS | 0x8000,
+                    &attr(RTAX_MTU, &mtu.to_ne_bytes()),
+                ));
+            }
+            let len = NLMSG_HDR_LEN + RTMSG_LEN + attrs.len();
+            let mut msg = (len as u32).to_ne_bytes().to_vec();
+            msg.extend_from_slice(&RTM_NEWROUTE.to_ne_bytes());
+            msg.extend_from_slice(&0x2u16.to_ne_bytes());
+            msg.extend_from_slice(&[0; 8]);
+            msg.extend_from_slice(&[libc::AF_INET as u8; 1]);
+            msg.extend_from_slice(&[0; RTMSG_LEN - 1]);
+            msg.extend(attrs);
+            msg
+        }
+
+        #[test]
+        fn pinned_route_through_interface_is_replaced() {
+            let req = with_mtu(&route(7, Some(1420)), 7, 1280).unwrap();
+
+            assert_eq!(
+                read_u16(&req, 6),
+                Some(NLM_F_REQUEST | NLM_F_REPLACE | NLM_F_ACK)
+            );
+            assert_eq!(req.len(), route(7, Some(1280)).len());
+            assert_eq!(req[8..], route(7, Some(1280))[8..]);
+        }
+
+        #[test]
+        fn other_routes_are_left_alone() {
+            assert_eq!(with_mtu(&route(7, None), 7, 1280), None);
+            assert_eq!(with_mtu(&route(3, Some(1420)), 7, 1280), None);
+            assert_eq!(with_mtu(&route(7, Some(1280)), 7, 1280), None);
+            assert_eq!(with_mtu(&[0; 8], 7, 1280), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mtu_is_validated() {
+        assert!(validate(DEFAULT_MTU).is_ok());
+        assert!(validate(MIN_MTU).is_ok());
+        assert!(validate(MAX_MTU).is_ok());
+        assert!(matches!(
+            validate(MIN_MTU - 1),
+            Err(Error::OutOfRange(1279))
+        ));
+        assert!(matches!(validate(MAX_MTU + 1), Err(Error::OutOfRange(_))));
+    }
+
+    #[test]
+    fn mss_leaves_room_for_headers() {
+        assert_eq!(mss_ipv4(DEFAULT_MTU), 1380);
+        assert_eq!(mss_ipv6(DEFAULT_MTU), 1360);
+    }
+
+    #[test]
+    fn uapi_cmd_clamps_both_families() {
+        assert_eq!(
+            uapi_cmd(DEFAULT_MTU),
+            "set=1\nmss_clamp_ipv4=1380\nmss_clamp_ipv6=1360\n\n"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Minimal rtnetlink client for the addresses and routes of the tunnel interface, see
+//! rtnetlink(7)
+
+use std::{io, mem};
+
+pub(crate) const NLMSG_HDR_LEN: usize = 16;
+pub(crate) const RTMSG_LEN: usize = 12;
+pub(crate) const IFADDRMSG_LEN: usize = 8;
+pub(crate) const RTA_HDR_LEN: usize = 4;
+/// Nested and byte order flags of the attribute type
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+pub(crate) const RTM_NEWADDR: u16 = 20;
+pub(crate) const RTM_GETADDR: u16 = 22;
+pub(crate) const RTM_NEWROUTE: u16 = 24;
+pub(crate) const RTM_GETROUTE: u16 = 26;
+
+pub(crate) const NLM_F_REQUEST: u16 = 0x1;
+pub(crate) const NLM_F_ACK: u16 = 0x4;
+pub(crate) const NLM_F_REPLACE: u16 = 0x100;
+pub(crate) const NLM_F_CREATE: u16 = 0x400;
+const NLM_F_DUMP: u16 = 0x300;
+
+pub(crate) const RTA_OIF: u16 = 4;
+
+/// Payload of the first attribute of the kind within the range
+pub(crate) fn find_attr(
+    buf: &[u8],
+    range: std::ops::Range<usize>,
+    kind: u16,
+) -> Option<std::ops::Range<usize>> {
+    let mut off = range.start;
+    while off + RTA_HDR_LEN <= range.end {
+        let len = usize::from(read_u16(buf, off)?);
+        if len < RTA_HDR_LEN || off + len > range.end {
+            return None;
+        }
+        if read_u16(buf, off + 2)? & NLA_TYPE_MASK == kind {
+            return Some(off + RTA_HDR_LEN..off + len);
+        }
+        off += align(len);
+    }
+    None
+}
+
+/// Netlink messages packed into the buffer
+fn messages(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut off = 0;
+    std::iter::from_fn(move || {
+        let len = read_u32(buf, off)? as usize;
+        if len < NLMSG_HDR_LEN {
+            return None;
+        }
+        let msg = buf.get(off..off + len)?;
+        off += align(len);
+        Some(msg)
+    })
+}
+
+pub(crate) fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+pub(crate) fn read_u16(buf: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_ne_bytes(buf.get(off..off + 2)?.try_into().ok()?))
+}
+
+pub(crate) fn read_u32(buf: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_ne_bytes(buf.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn read_i32(buf: &[u8], off: usize) -> Option<i32> {
+    Some(i32::from_ne_bytes(buf.get(off..off + 4)?.try_into().ok()?))
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed netlink message")
+}
+
+pub(crate) struct Socket(libc::c_int);
+
+impl Socket {
+    pub(crate) fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Socket(fd))
+    }
+
+    /// Every object of the family, the request header of the kind is `hdr_len` long
+    pub(crate) fn dump(&self, kind: u16, family: u8, hdr_len: usize) -> io::Result<Vec<Vec<u8>>> {
+        let mut req = vec![0u8; NLMSG_HDR_LEN + hdr_len];
+        let len = req.len() as u32;
+        req[0..4].copy_from_slice(&len.to_ne_bytes());
+        req[4..6].copy_from_slice(&kind.to_ne_bytes());
+        req[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+        req[NLMSG_HDR_LEN] = family;
+        self.send(&req)?;
+
+        let mut objects = Vec::new();
+        let mut buf = vec![0u8; 32 * 1024];
+        loop {
+            let len = self.recv(&mut buf)?;
+            for msg in messages(buf.get(..len).ok_or_else(invalid)?) {
+                match read_u16(msg, 4).ok_or_else(invalid)? {
+                    NLMSG_DONE => return Ok(objects),
+                    NLMSG_ERROR => check_ack(msg)?,
+                    _ => objects.push(msg.to_vec()),
+                }
+            }
+        }
+    }
+
+    /// Send the request and wait for its acknowledgement
+    pub(crate) fn request(&self, req: &[u8]) -> io::Result<()> {
+        self.send(req)?;
+        let mut buf = vec![0u8; 4096];
+        let len = self.recv(&mut buf)?;
+        let ack = messages(buf.get(..len).ok_or_else(invalid)?)
+            .next()
+            .ok_or_else(invalid)?;
+        check_ack(ack)
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<()> {
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let res = unsafe {
+            libc::sendto(
+                self.0,
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let res =
+            unsafe { libc::recv(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(res as usize)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Error message carries the negated errno, zero is an acknowledgement
+fn check_ack(msg: &[u8]) -> io::Result<()> {
+    if read_u16(msg, 4).ok_or_else(invalid)? != NLMSG_ERROR {
+        return Err(invalid());
+    }
+    match read_i32(msg, NLMSG_HDR_LEN).ok_or_else(invalid)? {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(-errno)),
+    }
+}
@@ -0,0 +1,194 @@
+//! AmneziaWG-compatible obfuscation of WireGuard packets
+//!
+//! Parameters are handed to the adapter through the
+//! [UAPI](https://www.wireguard.com/xplatform/) using the same keys as amneziawg-go.
+
+use std::fmt::Write;
+
+use telio_model::features::FeatureObfuscation;
+
+/// Largest packet the obfuscation may produce, the minimal IPv6 MTU
+const MAX_PACKET_SIZE: u16 = 1280;
+/// Size of a WireGuard handshake initiation
+const HANDSHAKE_INIT_SIZE: u16 = 148;
+/// Size of a WireGuard handshake response
+const HANDSHAKE_RESPONSE_SIZE: u16 = 92;
+/// Most junk packets AmneziaWG peers are expected to send
+const MAX_JUNK_PACKET_COUNT: u8 = 128;
+
+/// Obfuscation errors
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    /// Too many junk packets
+    #[error("Junk packet count {0} exceeds 128")]
+    TooManyJunkPackets(u8),
+    /// Junk packet size range is empty or too large
+    #[error("Invalid junk packet size range {0}..={1}")]
+    InvalidJunkPacketSize(u16, u16),
+    /// Padded handshake would not fit into a packet
+    #[error("Handshake padding of {0} bytes is too large")]
+    HandshakePaddingTooLarge(u16),
+    /// Padded handshake initiation and response are indistinguishable by size
+    #[error("Padded handshake initiation and response have the same size")]
+    HandshakePaddingCollision,
+    /// Every message type needs its own magic header
+    #[error("Magic headers must be distinct")]
+    DuplicateMagicHeader,
+    /// Only NepTUN built with the `neptun_extensions` feature understands the parameters
+    #[error("Obfuscation is only supported by the NepTUN adapter with UAPI extensions")]
+    UnsupportedAdapter,
+    /// Adapter refused to apply the parameters
+    #[error("Adapter rejected obfuscation parameters with errno {0}")]
+    Rejected(i32),
+}
+
+/// Check that the parameters are consistent and interoperable with AmneziaWG
+pub fn validate(cfg: &FeatureObfuscation) -> Result<(), Error> {
+    if cfg.junk_packet_count > MAX_JUNK_PACKET_COUNT {
+        return Err(Error::TooManyJunkPackets(cfg.junk_packet_count));
+    }
+
+    if cfg.junk_packet_count > 0
+        && (cfg.junk_packet_min_size > cfg.junk_packet_max_size
+            || cfg.junk_packet_max_size > MAX_PACKET_SIZE)
+    {
+        return Err(Error::InvalidJunkPacketSize(
+            cfg.junk_packet_min_size,
+            cfg.junk_packet_max_size,
+        ));
+    }
+
+    for (padding, size) in [
+        (cfg.init_packet_junk_size, HANDSHAKE_INIT_SIZE),
+        (cfg.response_packet_junk_size, HANDSHAKE_RESPONSE_SIZE),
+    ] {
+        if padding > MAX_PACKET_SIZE - size {
+            return Err(Error::HandshakePaddingTooLarge(padding));
+        }
+    }
+
+    // Receivers tell the handshake messages apart by their size
+    if u32::from(cfg.init_packet_junk_size) + u32::from(HANDSHAKE_INIT_SIZE)
+        == u32::from(cfg.response_packet_junk_size) + u32::from(HANDSHAKE_RESPONSE_SIZE)
+    {
+        return Err(Error::HandshakePaddingCollision);
+    }
+
+    let headers = [
+        cfg.init_packet_magic_header,
+        cfg.response_packet_magic_header,
+        cfg.under_load_packet_magic_header,
+        cfg.transport_packet_magic_header,
+    ];
+    for (i, header) in headers.iter().enumerate() {
+        if headers.iter().skip(i + 1).any(|other| other == header) {
+            return Err(Error::DuplicateMagicHeader);
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the UAPI set command applying the parameters
+pub(crate) fn uapi_cmd(cfg: &FeatureObfuscation) -> String {
+    let mut cmd = String::from("set=1\n");
+    for (key, value) in [
+        ("jc", u32::from(cfg.junk_packet_count)),
+        ("jmin", u32::from(cfg.junk_packet_min_size)),
+        ("jmax", u32::from(cfg.junk_packet_max_size)),
+        ("s1", u32::from(cfg.init_packet_junk_size)),
+        ("s2", u32::from(cfg.response_packet_junk_size)),
+        ("h1", cfg.init_packet_magic_header),
+        ("h2", cfg.response_packet_magic_header),
+        ("h3", cfg.under_load_packet_magic_header),
+        ("h4", cfg.transport_packet_magic_header),
+    ] {
+        let _ = writeln!(cmd, "{key}={value}");
+    }
+    cmd.push('\n');
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(validate(&FeatureObfuscation::default()), Ok(()));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let default = FeatureObfuscation::default();
+        let cases = [
+            (
+                FeatureObfuscation {
+                    junk_packet_count: 129,
+                    ..default
+                },
+                Error::TooManyJunkPackets(129),
+            ),
+            (
+                FeatureObfuscation {
+                    junk_packet_min_size: 100,
+                    junk_packet_max_size: 50,
+                    ..default
+                },
+                Error::InvalidJunkPacketSize(100, 50),
+            ),
+            (
+                FeatureObfuscation {
+                    junk_packet_max_size: 1281,
+                    ..default
+                },
+                Error::InvalidJunkPacketSize(40, 1281),
+            ),
+            (
+                FeatureObfuscation {
+                    init_packet_junk_size: 1133,
+                    ..default
+                },
+                Error::HandshakePaddingTooLarge(1133),
+            ),
+            (
+                FeatureObfuscation {
+                    init_packet_junk_size: 10,
+                    response_packet_junk_size: 66,
+                    ..default
+                },
+                Error::HandshakePaddingCollision,
+            ),
+            (
+                FeatureObfuscation {
+                    transport_packet_magic_header: 1,
+                    ..default
+                },
+                Error::DuplicateMagicHeader,
+            ),
+        ];
+
+        for (cfg, err) in cases {
+            assert_eq!(validate(&cfg), Err(err));
+        }
+    }
+
+    #[test]
+    fn junk_sizes_are_ignored_without_junk_packets() {
+        let cfg = FeatureObfuscation {
+            junk_packet_count: 0,
+            junk_packet_min_size: 100,
+            junk_packet_max_size: 0,
+            ..Default::default()
+        };
+        assert_eq!(validate(&cfg), Ok(()));
+    }
+
+    #[test]
+    fn uapi_cmd_uses_amneziawg_keys() {
+        assert_eq!(
+            uapi_cmd(&FeatureObfuscation::default()),
+            "set=1\njc=4\njmin=40\njmax=70\ns1=0\ns2=0\nh1=1\nh2=2\nh3=3\nh4=4\n\n"
+        );
+    }
+}
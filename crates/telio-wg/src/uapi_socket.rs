@@ -0,0 +1,274 @@
+//! Local [UAPI](https://www.wireguard.com/xplatform/) socket, for inspecting the adapter with
+//! standard WireGuard tooling such as `wg show`
+
+use std::{
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use telio_model::features::FeatureUapiSocket;
+use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{timeout, Duration},
+};
+
+/// Directory where WireGuard tools look for the sockets of userspace implementations
+const DEFAULT_SOCKET_DIR: &str = "/var/run/wireguard";
+/// Longest accepted command, a full configuration of a few hundred peers fits well
+const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+/// Time a client gets to send the whole command
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Keys of the `get` response never shown on the read-only socket
+const SECRET_KEYS: [&str; 2] = ["private_key=", "preshared_key="];
+
+/// UAPI command received on the socket, waiting for the adapter's response
+pub(crate) struct UapiRequest {
+    pub(crate) cmd: String,
+    reply: oneshot::Sender<String>,
+}
+
+impl UapiRequest {
+    pub(crate) fn respond(self, response: String) {
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Listening UAPI socket. The socket file is removed when dropped.
+pub(crate) struct UapiSocket {
+    path: PathBuf,
+    listener: JoinHandle<()>,
+    requests: mpsc::Receiver<UapiRequest>,
+}
+
+impl UapiSocket {
+    /// Bind the socket for the interface `name`, replacing a socket left behind by a crashed process
+    pub(crate) fn bind(name: &str, config: &FeatureUapiSocket) -> io::Result<Self> {
+        let path = config
+            .path
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(DEFAULT_SOCKET_DIR).join(format!("{name}.sock")));
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, Permissions::from_mode(config.mode))?;
+        telio_log_info!("UAPI socket listening on {path:?}");
+
+        let (tx, requests) = mpsc::channel(16);
+        let listener = tokio::spawn(accept_connections(listener, tx, config.read_only));
+
+        Ok(Self {
+            path,
+            listener,
+            requests,
+        })
+    }
+
+    /// Next command to be passed to the adapter
+    pub(crate) async fn recv(&mut self) -> Option<UapiRequest> {
+        self.requests.recv().await
+    }
+}
+
+impl Drop for UapiSocket {
+    fn drop(&mut self) {
+        self.listener.abort();
+        if let Err(e) = fs::remove_file(&self.path) {
+            telio_log_warn!("Failed to remove UAPI socket {:?}: {}", self.path, e);
+        }
+    }
+}
+
+async fn accept_connections(
+    listener: UnixListener,
+    tx: mpsc::Sender<UapiRequest>,
+    read_only: bool,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(stream, tx, read_only).await {
+                        telio_log_debug!("UAPI connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                telio_log_warn!("Failed to accept UAPI connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Serve the commands of a single client until it disconnects
+async fn serve_connection(
+    stream: UnixStream,
+    tx: mpsc::Sender<UapiRequest>,
+    read_only: bool,
+) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+
+    while let Some(cmd) = timeout(REQUEST_TIMEOUT, read_request(&mut read))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+    {
+        let response = if read_only && !cmd.starts_with("get=1\n") {
+            format!("errno={}\n\n", libc::EPERM)
+        } else {
+            let (reply, response) = oneshot::channel();
+            tx.send(UapiRequest { cmd, reply })
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            let response = response
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            if read_only {
+                strip_secrets(&response)
+            } else {
+                response
+            }
+        };
+        write.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Remove the keys from the response, inspecting the adapter must not leak them
+fn strip_secrets(response: &str) -> String {
+    response
+        .split_inclusive('\n')
+        .filter(|line| !SECRET_KEYS.iter().any(|key| line.starts_with(key)))
+        .collect()
+}
+
+/// Read a command terminated by an empty line, `None` once the client is done
+async fn read_request<R: AsyncRead + Unpin>(read: &mut BufReader<R>) -> io::Result<Option<String>> {
+    let mut cmd = String::new();
+    loop {
+        let len = read.read_line(&mut cmd).await?;
+        if len == 0 {
+            if cmd.is_empty() {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if cmd.len() > MAX_REQUEST_SIZE {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        if cmd.ends_with("\n\n") || cmd == "\n" {
+            return Ok(Some(cmd));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    fn socket_config(read_only: bool) -> FeatureUapiSocket {
+        let name = format!("telio-uapi-test-{}.sock", rand::random::<u64>());
+        FeatureUapiSocket {
+            path: Some(
+                std::env::temp_dir()
+                    .join(name)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            read_only,
+            ..Default::default()
+        }
+    }
+
+    async fn exchange(path: &Path, cmd: &str) -> String {
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        stream.write_all(cmd.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn commands_are_forwarded_and_socket_removed_on_drop() {
+        let config = socket_config(false);
+        let path = PathBuf::from(config.path.clone().unwrap());
+        let mut socket = UapiSocket::bind("wg-test", &config).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let client = tokio::spawn({
+            let path = path.clone();
+            async move { exchange(&path, "get=1\n\n").await }
+        });
+        let request = socket.recv().await.unwrap();
+        assert_eq!(request.cmd, "get=1\n\n");
+        request.respond("listen_port=51820\nerrno=0\n\n".to_owned());
+        assert_eq!(client.await.unwrap(), "listen_port=51820\nerrno=0\n\n");
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn read_only_socket_rejects_set() {
+        let config = socket_config(true);
+        let path = PathBuf::from(config.path.clone().unwrap());
+        let _socket = UapiSocket::bind("wg-test", &config).unwrap();
+
+        assert_eq!(
+            exchange(&path, "set=1\nlisten_port=1\n\n").await,
+            format!("errno={}\n\n", libc::EPERM)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_only_socket_hides_keys() {
+        let config = socket_config(true);
+        let path = PathBuf::from(config.path.clone().unwrap());
+        let mut socket = UapiSocket::bind("wg-test", &config).unwrap();
+
+        let client = tokio::spawn({
+            let path = path.clone();
+            async move { exchange(&path, "get=1\n\n").await }
+        });
+        socket.recv().await.unwrap().respond(
+            concat!(
+                "private_key=e84b5a6d2717c1003a13b431570353dbaca9146cf150c5f8575680feba52027a\n",
+                "listen_port=51820\n",
+                "public_key=b85996fecc9c7f1fc6d2572a76eda11d59bcd20be8e543b15ce4bd85a8e75a33\n",
+                "preshared_key=188515093e952f5f22e865cef3012e72f8b5f0b598ac0309d5dacce3b70fcf52\n",
+                "endpoint=[abcd:23::33%2]:51820\n",
+                "errno=0\n\n",
+            )
+            .to_owned(),
+        );
+
+        assert_eq!(
+            client.await.unwrap(),
+            concat!(
+                "listen_port=51820\n",
+                "public_key=b85996fecc9c7f1fc6d2572a76eda11d59bcd20be8e543b15ce4bd85a8e75a33\n",
+                "endpoint=[abcd:23::33%2]:51820\n",
+                "errno=0\n\n",
+            )
+        );
+    }
+}
@@ -3,19 +3,25 @@ use futures::{future::pending, FutureExt};
 use ipnet::{AddrParseError, IpNet};
 use slog::{o, Drain, Logger, Never};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     net::{IpAddr, Ipv4Addr, SocketAddr},
 };
 use telio_model::{
-    event::{Error as LibtelioError, ErrorCode, ErrorLevel, Event as LibtelioEvent, EventMsg, Set},
-    features::{FeatureBatching, FeatureLinkDetection},
+    event::{
+        AdapterRecovery, AdapterRecoveryState, Error as LibtelioError, ErrorCode, ErrorLevel,
+        Event as LibtelioEvent, EventMsg, PeerHandshake, Set,
+    },
+    features::{
+        FeatureAdapterRecovery, FeatureBatching, FeatureHandshakeEvents, FeatureHandshakeLoad,
+        FeatureLinkDetection, FeatureObfuscation, FeatureUapiSocket,
+    },
     mesh::{ExitNode, NodeState},
 };
 use telio_sockets::{NativeProtector, SocketPool};
 use telio_utils::{
     dual_target::{DualTarget, DualTargetError},
     get_ip_stack, interval, interval_at, telio_err_with_log, telio_log_debug, telio_log_error,
-    telio_log_trace, telio_log_warn, IpStack,
+    telio_log_info, telio_log_trace, telio_log_warn, IpStack,
 };
 use thiserror::Error as TError;
 use tokio::sync::watch;
@@ -32,11 +38,16 @@ use telio_task::{
 
 use crate::{
     adapter::{self, Adapter, AdapterType, Error, FirewallResetConnsCb, Tun},
+    handshake::HandshakeTracker,
+    link_config::LinkConfig,
     link_detection::{self, LinkDetection, LinkDetectionUpdateResult},
     uapi::{self, AnalyticsEvent, Cmd, Event, Interface, Peer, PeerState, Response, UpdateReason},
     FirewallCb,
 };
 
+#[cfg(unix)]
+use crate::uapi_socket::{UapiRequest, UapiSocket};
+
 use std::{
     collections::HashSet,
     future::Future,
@@ -53,6 +64,8 @@ pub trait WireGuard: Send + Sync + 'static {
     async fn get_interface(&self) -> Result<Interface, Error>;
     /// Get adapter luid (local identifier) if supported by platform, `zero` otherwise
     async fn get_adapter_luid(&self) -> Result<u64, Error>;
+    /// Get the type of the adapter in use
+    async fn get_adapter_type(&self) -> Result<AdapterType, Error>;
     /// wait for listen port to be assigned by the WireGuard implementation, and return it afterwards
     async fn wait_for_listen_port(&self, d: Duration) -> Result<u16, Error>;
     /// wait for listen port that should be used by telio-proxy,
@@ -75,6 +88,10 @@ pub trait WireGuard: Send + Sync + 'static {
     async fn drop_connected_sockets(&self) -> Result<(), Error>;
     /// Retrieve time since last RXed (and accepted) packet
     async fn time_since_last_rx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
+    /// Retrieve time since last TXed packet, keepalives excluded
+    async fn time_since_last_tx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
+    /// Retrieve the number of handshakes completed with each peer since it was added
+    async fn get_handshake_counts(&self) -> Result<HashMap<PublicKey, u64>, Error>;
     /// Stop adapter
     async fn stop(self);
     /// Inject apropiate packets into the tunel to reset exising connections.
@@ -86,6 +103,12 @@ pub trait WireGuard: Send + Sync + 'static {
     ) -> Result<(), Error>;
     /// Set the ip stack for the adapter
     async fn set_ip_stack(&self, ip_stack: Option<IpStack>) -> Result<(), Error>;
+    /// Set MTU of the tunnel interface
+    async fn set_mtu(&self, mtu: u16) -> Result<(), Error>;
+    /// Replace the adapter with one of another type, keeping the keys, peers and endpoints.
+    /// The previous adapter is restored if the new one cannot take over. Not supported on a
+    /// tunnel supplied by the app.
+    async fn switch_adapter(&self, adapter: AdapterType) -> Result<(), Error>;
 }
 
 /// WireGuard implementation allowing dynamic selection of implementation.
@@ -110,6 +133,17 @@ pub struct Config {
     /// Callback of firewall to create connection reset packets
     /// for all active connections
     pub firewall_reset_connections: FirewallResetConnsCb,
+    /// AmneziaWG-compatible obfuscation, only supported by NepTUN
+    pub obfuscation: Option<FeatureObfuscation>,
+    /// Handshake load protection, only supported by NepTUN
+    pub handshake_load: Option<FeatureHandshakeLoad>,
+    /// Local UAPI socket, only supported by NepTUN on unix
+    pub uapi_socket: Option<FeatureUapiSocket>,
+    /// Recreate the adapter after fatal failures. A tunnel supplied by the app can only be
+    /// reused while its file descriptor is still valid.
+    pub recovery: Option<FeatureAdapterRecovery>,
+    /// Report the completed and failed handshakes of the peers as libtelio events
+    pub handshake_events: Option<FeatureHandshakeEvents>,
 }
 
 /// Events and analytics transmission channels
@@ -220,9 +254,10 @@ impl BytesAndTimestamps {
 }
 
 struct State {
-    #[cfg(unix)]
     cfg: Config,
     adapter: Box<dyn Adapter>,
+    #[cfg(unix)]
+    uapi_socket: Option<UapiSocket>,
     interval: Interval,
     interface: Interface,
     event: Tx<Box<Event>>,
@@ -237,17 +272,35 @@ struct State {
 
     libtelio_event: Option<mc_chan::Tx<Box<LibtelioEvent>>>,
 
+    handshakes: Option<HandshakeTracker>,
+
     stats: HashMap<PublicKey, Arc<Mutex<BytesAndTimestamps>>>,
 
+    // Handshakes completed with each peer, including the rekeying of live sessions
+    handshake_counts: HashMap<PublicKey, u64>,
+
     ip_stack: Option<IpStack>,
 
     polling_period: Duration,
     polling_period_after_update: Duration,
     last_update: Instant,
+
+    // Recreating the failed adapter, attempted from `wait` so the task keeps serving requests
+    recovery: Option<Recovery>,
+}
+
+struct Recovery {
+    deadline: Instant,
+    backoff: Duration,
+    next_attempt: Instant,
+    link_config: Option<LinkConfig>,
 }
 
 const MAX_UAPI_FAIL_COUNT: i32 = 10;
 
+const ADAPTER_RECOVERY_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const ADAPTER_RECOVERY_MAX_BACKOFF: Duration = Duration::from_secs(8);
+
 #[cfg(all(not(any(test, feature = "test-adapter")), windows))]
 const DEFAULT_NAME: &str = "NordLynx";
 
@@ -327,6 +380,11 @@ impl DynamicWg {
     ///             firewall_process_outbound_callback:
     ///                 Some(Arc::new(firewall_filter_outbound_packets)),
     ///             firewall_reset_connections: None,
+    ///             obfuscation: None,
+    ///             handshake_load: None,
+    ///             uapi_socket: None,
+    ///             recovery: None,
+    ///             handshake_events: None,
     ///         },
     ///         None,
     ///         true,
@@ -346,11 +404,14 @@ impl DynamicWg {
     where
         Self: Sized,
     {
+        #[cfg(unix)]
+        let uapi_socket = Self::bind_uapi_socket(&cfg)?;
         let adapter = Self::start_adapter(cfg.try_clone()?)?;
         #[cfg(unix)]
         return Ok(Self::start_with(
             io,
             adapter,
+            uapi_socket,
             link_detection,
             cfg,
             ipv6_enabled,
@@ -362,6 +423,7 @@ impl DynamicWg {
             io,
             adapter,
             link_detection,
+            cfg,
             ipv6_enabled,
             polling_period,
             polling_period_after_update,
@@ -371,8 +433,9 @@ impl DynamicWg {
     fn start_with(
         io: Io,
         adapter: Box<dyn Adapter>,
+        #[cfg(unix)] uapi_socket: Option<UapiSocket>,
         link_detection: Option<FeatureLinkDetection>,
-        #[cfg(unix)] cfg: Config,
+        cfg: Config,
         ipv6_enabled: bool,
         polling_period: Duration,
         polling_period_after_update: Duration,
@@ -380,9 +443,10 @@ impl DynamicWg {
         let interval = interval(polling_period);
         Self {
             task: Task::start(State {
-                #[cfg(unix)]
                 cfg,
                 adapter,
+                #[cfg(unix)]
+                uapi_socket,
                 interval,
                 interface: Default::default(),
                 event: io.events,
@@ -390,11 +454,14 @@ impl DynamicWg {
                 uapi_fail_counter: 0,
                 link_detection: link_detection.map(|ld| LinkDetection::new(ld, ipv6_enabled)),
                 libtelio_event: io.libtelio_wide_event_publisher,
+                handshakes: cfg.handshake_events.map(HandshakeTracker::new),
                 stats: HashMap::new(),
+                handshake_counts: HashMap::new(),
                 ip_stack: None,
                 polling_period,
                 polling_period_after_update,
                 last_update: Instant::now(),
+                recovery: None,
             }),
         }
     }
@@ -409,9 +476,29 @@ impl DynamicWg {
             cfg.firewall_process_inbound_callback,
             cfg.firewall_process_outbound_callback,
             cfg.firewall_reset_connections,
+            cfg.obfuscation,
+            cfg.handshake_load,
         )
     }
 
+    #[cfg(all(unix, not(any(test, feature = "test-adapter"))))]
+    fn bind_uapi_socket(cfg: &Config) -> Result<Option<UapiSocket>, Error> {
+        let Some(uapi_socket) = &cfg.uapi_socket else {
+            return Ok(None);
+        };
+        if !matches!(cfg.adapter, AdapterType::NepTUN) {
+            telio_log_warn!("UAPI socket is only supported by NepTUN, ignoring");
+            return Ok(None);
+        }
+        let name = cfg.name.as_deref().unwrap_or(DEFAULT_NAME);
+        Ok(Some(UapiSocket::bind(name, uapi_socket)?))
+    }
+
+    #[cfg(all(unix, any(test, feature = "test-adapter")))]
+    fn bind_uapi_socket(_cfg: &Config) -> Result<Option<UapiSocket>, Error> {
+        Ok(None)
+    }
+
     #[cfg(any(test, feature = "test-adapter"))]
     fn start_adapter(_cfg: Config) -> Result<Box<dyn Adapter>, Error> {
         use std::sync::Mutex;
@@ -434,6 +521,10 @@ impl WireGuard for DynamicWg {
         Ok(task_exec!(&self.task, async move |s| Ok(s.adapter.get_adapter_luid())).await?)
     }
 
+    async fn get_adapter_type(&self) -> Result<AdapterType, Error> {
+        Ok(task_exec!(&self.task, async move |s| Ok(s.cfg.adapter)).await?)
+    }
+
     async fn wait_for_listen_port(&self, d: Duration) -> Result<u16, Error> {
         let start = std::time::SystemTime::now();
         loop {
@@ -572,6 +663,17 @@ impl WireGuard for DynamicWg {
         .await?)
     }
 
+    async fn time_since_last_tx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error> {
+        Ok(task_exec!(&self.task, async move |s| Ok(
+            s.time_since_last_tx(public_key)
+        ))
+        .await?)
+    }
+
+    async fn get_handshake_counts(&self) -> Result<HashMap<PublicKey, u64>, Error> {
+        Ok(task_exec!(&self.task, async move |s| Ok(s.handshake_counts.clone())).await?)
+    }
+
     async fn stop(mut self) {
         let _ = self.task.stop().await.resume_unwind();
     }
@@ -597,6 +699,18 @@ impl WireGuard for DynamicWg {
         })
         .await?)
     }
+
+    async fn set_mtu(&self, mtu: u16) -> Result<(), Error> {
+        crate::mtu::validate(mtu)?;
+        task_exec!(&self.task, async move |s| Ok(s.adapter.set_mtu(mtu).await)).await?
+    }
+
+    async fn switch_adapter(&self, adapter: AdapterType) -> Result<(), Error> {
+        task_exec!(&self.task, async move |s| Ok(s
+            .switch_adapter(adapter)
+            .await))
+        .await?
+    }
 }
 
 impl Config {
@@ -623,6 +737,11 @@ impl Config {
             firewall_process_inbound_callback: self.firewall_process_inbound_callback.clone(),
             firewall_process_outbound_callback: self.firewall_process_outbound_callback.clone(),
             firewall_reset_connections: self.firewall_reset_connections.clone(),
+            obfuscation: self.obfuscation,
+            handshake_load: self.handshake_load,
+            uapi_socket: self.uapi_socket.clone(),
+            recovery: self.recovery,
+            handshake_events: self.handshake_events,
         })
     }
 }
@@ -635,23 +754,252 @@ struct DiffKeys {
 }
 
 impl State {
+    #[cfg(unix)]
+    async fn serve_uapi_request(&self, request: UapiRequest) {
+        telio_log_debug!(
+            "Serving UAPI socket command: {:?}",
+            request.cmd.lines().next()
+        );
+        let response = match self.adapter.send_raw_uapi_cmd(&request.cmd).await {
+            Ok(response) => response,
+            Err(e) => {
+                telio_log_warn!("UAPI socket command failed: {}", e);
+                format!("errno={}\n\n", libc::EOPNOTSUPP)
+            }
+        };
+        request.respond(response);
+    }
+
     async fn sync(&mut self) -> Result<(), Error> {
-        if let Some(to) = self.uapi_request(&uapi::Cmd::Get).await?.interface {
+        let ret = match self.uapi_request(&uapi::Cmd::Get).await {
+            Ok(ret) => ret,
+            Err(err) => match self.cfg.recovery {
+                Some(recovery) if self.uapi_fail_counter >= MAX_UAPI_FAIL_COUNT => {
+                    self.start_recovery(recovery);
+                    return Ok(());
+                }
+                _ => return Err(err),
+            },
+        };
+
+        if let Some(to) = ret.interface {
             let _ = self.update(to, UpdateReason::Pull).await;
         }
 
         Ok(())
     }
 
+    fn start_recovery(&mut self, recovery: FeatureAdapterRecovery) {
+        telio_log_warn!(
+            "Adapter failed {} times in a row, recreating it",
+            self.uapi_fail_counter
+        );
+        self.publish_adapter_recovery(AdapterRecoveryState::Recovering);
+
+        let now = Instant::now();
+        self.recovery = Some(Recovery {
+            deadline: now + Duration::from_secs(recovery.timeout_s.into()),
+            backoff: ADAPTER_RECOVERY_INITIAL_BACKOFF,
+            next_attempt: now,
+            link_config: self.save_link_config(),
+        });
+    }
+
+    /// Recreate the failed adapter and apply the last known interface config on it again, the
+    /// next attempt is scheduled with backoff until the recovery timeout runs out.
+    async fn recover_adapter(&mut self) -> Result<(), Error> {
+        let Some(mut recovery) = self.recovery.take() else {
+            return Ok(());
+        };
+
+        let result = match self.check_tun() {
+            Ok(()) => self.recreate_adapter(recovery.link_config.as_ref()).await,
+            Err(err) => {
+                // Retrying can't bring a closed file descriptor back
+                recovery.deadline = Instant::now();
+                Err(err.into())
+            }
+        };
+        let err = match result {
+            Ok(()) => {
+                telio_log_info!("Adapter recreated");
+                self.uapi_fail_counter = 0;
+                self.publish_adapter_recovery(AdapterRecoveryState::Recovered);
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+        telio_log_warn!("Failed to recreate adapter: {}", err);
+
+        let now = Instant::now();
+        if now + recovery.backoff >= recovery.deadline {
+            telio_log_error!("Adapter could not be recreated before the recovery timeout");
+            self.publish_adapter_recovery(AdapterRecoveryState::Failed);
+            self.publish_interface_gone();
+            return Err(Error::InternalError("Interface gone"));
+        }
+        recovery.next_attempt = now + recovery.backoff;
+        recovery.backoff = (recovery.backoff * 2).min(ADAPTER_RECOVERY_MAX_BACKOFF);
+        self.recovery = Some(recovery);
+        Ok(())
+    }
+
+    /// The tunnel supplied by the app can only be reused while its file descriptor is valid
+    #[cfg(unix)]
+    fn check_tun(&self) -> io::Result<()> {
+        let Some(tun) = self.cfg.tun else {
+            return Ok(());
+        };
+        if unsafe { libc::fcntl(tun as libc::c_int, libc::F_GETFD) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EBADF) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn check_tun(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Save the config of the interface, which goes away together with the adapter. The app
+    /// configures the tunnel it supplied by itself.
+    #[cfg(not(any(test, feature = "test-adapter")))]
+    fn save_link_config(&self) -> Option<LinkConfig> {
+        if self.cfg.tun.is_some() {
+            return None;
+        }
+        let name = self.cfg.name.as_deref().unwrap_or(DEFAULT_NAME);
+        match LinkConfig::save(name) {
+            Ok(link_config) => Some(link_config),
+            Err(err) => {
+                telio_log_warn!("Failed to save the config of {}: {}", name, err);
+                None
+            }
+        }
+    }
+
+    #[cfg(any(test, feature = "test-adapter"))]
+    fn save_link_config(&self) -> Option<LinkConfig> {
+        None
+    }
+
+    async fn recreate_adapter(&mut self, link_config: Option<&LinkConfig>) -> Result<(), Error> {
+        self.adapter.stop().await;
+        self.adapter = DynamicWg::start_adapter(self.cfg.try_clone()?)?;
+
+        let ret = self
+            .adapter
+            .send_uapi_cmd(&Cmd::Set(self.interface.clone().into()))
+            .await?;
+        if ret.errno != 0 {
+            return Err(Error::InternalError(
+                "Recreated adapter rejected the last config",
+            ));
+        }
+
+        if let Some(link_config) = link_config {
+            link_config.restore()?;
+        }
+
+        Ok(())
+    }
+
+    /// Traffic is interrupted from stopping the old adapter until the new one applies the last
+    /// interface config, sessions are then established again with new handshakes.
+    async fn switch_adapter(&mut self, adapter: AdapterType) -> Result<(), Error> {
+        let previous = self.cfg.adapter;
+        if previous == adapter {
+            return Ok(());
+        }
+        if self.cfg.tun.is_some() {
+            return Err(Error::AppOwnedTunnel);
+        }
+
+        let started_at = Instant::now();
+        let link_config = self.save_link_config();
+        self.cfg.adapter = adapter;
+        let Err(err) = self.recreate_adapter(link_config.as_ref()).await else {
+            telio_log_info!(
+                "Switched adapter from {previous:?} to {adapter:?}, traffic interrupted for {:?}",
+                started_at.elapsed()
+            );
+            return Ok(());
+        };
+
+        telio_log_warn!("Failed to switch adapter to {adapter:?}: {err}, restoring {previous:?}");
+        self.cfg.adapter = previous;
+        if let Err(restore_err) = self.recreate_adapter(link_config.as_ref()).await {
+            telio_log_error!("Failed to restore adapter {previous:?}: {restore_err}");
+            self.publish_interface_gone();
+            return Err(restore_err);
+        }
+        Err(err)
+    }
+
+    fn publish_adapter_recovery(&self, state: AdapterRecoveryState) {
+        if let Some(libtelio_event) = &self.libtelio_event {
+            let event = LibtelioEvent::builder::<AdapterRecovery>()
+                .set(AdapterRecovery { state })
+                .build();
+            if let Some(event) = event {
+                let _ = libtelio_event.send(Box::new(event));
+            }
+        }
+    }
+
+    fn publish_handshakes(&mut self, peers: &BTreeMap<PublicKey, Peer>) {
+        let Some(handshakes) = &mut self.handshakes else {
+            return;
+        };
+        for handshake in handshakes.observe(peers) {
+            telio_log_debug!(
+                "Handshake with {:?}: {:?}",
+                handshake.public_key,
+                handshake.result
+            );
+            if let Some(libtelio_event) = &self.libtelio_event {
+                let event = LibtelioEvent::builder::<PeerHandshake>()
+                    .set(handshake)
+                    .build();
+                if let Some(event) = event {
+                    let _ = libtelio_event.send(Box::new(event));
+                }
+            }
+        }
+    }
+
+    fn publish_interface_gone(&self) {
+        if let Some(libtelio_event) = &self.libtelio_event {
+            let err_event = LibtelioEvent::builder::<LibtelioError>()
+                .set(EventMsg::from("Interface gone"))
+                .set(ErrorCode::Unknown)
+                .set(ErrorLevel::Critical)
+                .build();
+            if let Some(err_event) = err_event {
+                let _ = libtelio_event.send(Box::new(err_event));
+            }
+        }
+    }
+
     async fn uapi_request(&mut self, cmd: &Cmd) -> Result<Response, Error> {
-        let ret = self.adapter.send_uapi_cmd(cmd).await?;
+        let ret = match self.adapter.send_uapi_cmd(cmd).await {
+            Ok(ret) => ret,
+            Err(err) => {
+                self.uapi_fail_counter += 1;
+                return Err(err);
+            }
+        };
         telio_log_debug!("UAPI request: {}, response: {:?}", &cmd.to_string(), &ret);
 
         // Count continuous adapter failures.
         // As observed on Windows, a vNIC driver might fail a call right after wake-up,
         // but will properly resume work after that. In order to determine a non-recoverable failure
         // such as a malicious removal, we need to count the successive failed calls.
-        // If a certain threshold is reached, cleanup the network config and notify the app about connection loss.
+        // If a certain threshold is reached, either recreate the adapter or, if recovery is
+        // disabled, cleanup the network config and notify the app about connection loss.
         if 0 == ret.errno {
             self.uapi_fail_counter = 0;
         } else {
@@ -659,15 +1007,9 @@ impl State {
         }
 
         if self.uapi_fail_counter >= MAX_UAPI_FAIL_COUNT && ret.interface.is_none() {
-            if let Some(libtelio_event) = &self.libtelio_event {
-                let err_event = LibtelioEvent::builder::<LibtelioError>()
-                    .set(EventMsg::from("Interface gone"))
-                    .set(ErrorCode::Unknown)
-                    .set(ErrorLevel::Critical)
-                    .build();
-                if let Some(err_event) = err_event {
-                    let _ = libtelio_event.send(Box::new(err_event));
-                }
+            // With recovery enabled the app is only told once recreating the adapter fails
+            if self.cfg.recovery.is_none() {
+                self.publish_interface_gone();
             }
             return Err(Error::InternalError("Interface gone"));
         }
@@ -715,6 +1057,16 @@ impl State {
         })
     }
 
+    fn time_since_last_tx(&self, public_key: PublicKey) -> Option<Duration> {
+        self.stats.get(&public_key).and_then(|s| match s.lock() {
+            Ok(s) => Some(s.tx_ts?.elapsed()),
+            Err(e) => {
+                telio_log_error!("poisoned lock - {}", e);
+                None
+            }
+        })
+    }
+
     async fn send_event(
         &self,
         state: PeerState,
@@ -876,14 +1228,20 @@ impl State {
             .filter(|(pubkey, _)| diff_keys.delete_keys.contains(pubkey))
             .map(|(pubkey, _)| set::Peer::from_public_key(pubkey.0).remove(true));
         let peers = to.peers.iter().map(|(pubkey, peer)| {
-            set::Peer::from(peer)
+            let old_peer = from.peers.get(pubkey);
+            let mut set_peer = set::Peer::from(peer)
                 .update_only(diff_keys.update_keys.contains(pubkey))
                 .replace_allowed_ips(
-                    from.peers
-                        .get(pubkey)
+                    old_peer
                         .map(|p| p.allowed_ips != peer.allowed_ips)
                         .unwrap_or(false),
-                )
+                );
+            // A left out preshared key is kept by the adapters, so clear it explicitly
+            if peer.preshared_key.is_none() && old_peer.map_or(false, |p| p.preshared_key.is_some())
+            {
+                set_peer.preshared_key = Some([0; 32]);
+            }
+            set_peer
         });
 
         set::Device {
@@ -893,7 +1251,7 @@ impl State {
                 to.listen_port,
             ),
             fwmark: match to.fwmark {
-                0 => None,
+                0 if from.fwmark == 0 => None,
                 x => Some(x),
             },
             replace_peers: None,
@@ -967,8 +1325,22 @@ impl State {
                 }
             }
             peer.time_since_last_rx = self.time_since_last_rx(*pk);
+
+            // The time since the last handshake only goes back when a new one completes
+            let last_handshake = self
+                .interface
+                .peers
+                .get(pk)
+                .and_then(|old| old.time_since_last_handshake);
+            if let Some(since) = peer.time_since_last_handshake {
+                if last_handshake.map_or(true, |last| since < last) {
+                    *self.handshake_counts.entry(*pk).or_default() += 1;
+                }
+            }
         }
         self.stats.retain(|pk, _| to.peers.contains_key(pk));
+        self.handshake_counts
+            .retain(|pk, _| to.peers.contains_key(pk));
 
         // Diff and report events
 
@@ -1007,6 +1379,8 @@ impl State {
         self.update_send_notification_events(&to, &diff_keys, reason)
             .await?;
 
+        self.publish_handshakes(&to.peers);
+
         let mut success = true;
         if reason == UpdateReason::Push {
             let dev = self.update_construct_set_device(&to, &diff_keys);
@@ -1048,6 +1422,12 @@ impl State {
             }
         }
 
+        // Only the pulled config has the port the adapter actually listens on
+        #[cfg(windows)]
+        if reason == UpdateReason::Pull {
+            self.cfg.socket_pool.set_wireguard_port(to.listen_port);
+        }
+
         self.interface = to;
 
         Ok(success)
@@ -1104,12 +1484,30 @@ impl Runtime for State {
         {
             self.interval = interval_at(Instant::now() + self.polling_period, self.polling_period);
         }
+        if let Some(next_attempt) = self.recovery.as_ref().map(|r| r.next_attempt) {
+            time::sleep_until(next_attempt).await;
+            return Self::guard(self.recover_adapter());
+        }
+        #[cfg(unix)]
+        if let Some(uapi_socket) = self.uapi_socket.as_mut() {
+            tokio::select! {
+                Some(request) = uapi_socket.recv() => {
+                    return Self::guard(async move {
+                        self.serve_uapi_request(request).await;
+                        Ok(())
+                    });
+                }
+                _ = self.interval.tick() => return Self::guard(self.sync()),
+            }
+        }
         let _ = self.interval.tick().await;
         Self::guard(self.sync())
     }
 
     async fn stop(self) {
         self.adapter.stop().await;
+        #[cfg(windows)]
+        self.cfg.socket_pool.set_wireguard_port(None);
         if let Some(link_detection) = self.link_detection {
             link_detection.stop().await;
         }
@@ -1120,6 +1518,9 @@ impl Runtime for State {
     }
 }
 
+#[cfg(test)]
+mod uapi_semantics;
+
 #[cfg(any(test, feature = "test-adapter"))]
 #[allow(missing_docs)]
 pub mod tests {
@@ -1151,6 +1552,8 @@ pub mod tests {
     lazy_static! {
         pub(super) static ref RUNTIME_ADAPTER: StdMutex<Option<Box<dyn Adapter>>> =
             StdMutex::new(None);
+        // Held by the tests restarting the adapter, so they don't take each other's adapters
+        static ref RUNTIME_ADAPTER_USERS: Mutex<()> = Mutex::new(());
     }
 
     const DEFAULT_POLLING_PERIOD_MS: u64 = 1000;
@@ -1204,9 +1607,9 @@ pub mod tests {
         }
     }
 
-    #[cfg(all(unix, test))]
+    #[cfg(test)]
     impl Config {
-        fn new() -> std::io::Result<Self> {
+        pub(super) fn new() -> std::io::Result<Self> {
             Ok(Self {
                 adapter: Default::default(),
                 name: Default::default(),
@@ -1218,6 +1621,11 @@ pub mod tests {
                 firewall_process_inbound_callback: Default::default(),
                 firewall_process_outbound_callback: Default::default(),
                 firewall_reset_connections: None,
+                obfuscation: None,
+                handshake_load: None,
+                uapi_socket: None,
+                recovery: None,
+                handshake_events: None,
             })
         }
     }
@@ -1301,10 +1709,12 @@ pub mod tests {
                 libtelio_wide_event_publisher: None,
             },
             Box::new(adapter.clone()),
+            #[cfg(unix)]
             None,
-            #[cfg(all(unix, test))]
+            None,
+            #[cfg(test)]
             Config::new().unwrap(),
-            #[cfg(all(unix, not(test)))]
+            #[cfg(not(test))]
             cfg,
             true,
             Duration::from_millis(DEFAULT_POLLING_PERIOD_MS),
@@ -1398,6 +1808,157 @@ pub mod tests {
         wg.stop().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn wg_recreates_adapter_after_fatal_failure() {
+        let _runtime_adapter = RUNTIME_ADAPTER_USERS.lock().await;
+        let Env { adapter, wg, .. } = setup().await;
+        let McChan {
+            tx: libtelio_tx,
+            rx: mut libtelio_rx,
+        } = McChan::<Box<LibtelioEvent>>::default();
+        task_exec!(&wg.task, async move |s| {
+            s.cfg.recovery = Some(FeatureAdapterRecovery { timeout_s: 5 });
+            s.libtelio_event = Some(libtelio_tx);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .with(predicate::eq(Cmd::Get))
+            .times(MAX_UAPI_FAIL_COUNT as usize)
+            .returning(|_| {
+                Ok(Response {
+                    errno: 1,
+                    interface: None,
+                })
+            });
+        adapter.lock().await.expect_stop().return_once(|| ());
+
+        let new_adapter = Arc::new(Mutex::new(MockAdapter::new()));
+        new_adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .returning(|_| {
+                Ok(Response {
+                    errno: 0,
+                    interface: Some(Interface::default()),
+                })
+            });
+        *RUNTIME_ADAPTER.lock().unwrap() = Some(Box::new(new_adapter.clone()));
+
+        sleep(Duration::from_millis(
+            DEFAULT_POLLING_PERIOD_MS * MAX_UAPI_FAIL_COUNT as u64 + 1,
+        ))
+        .await;
+
+        assert!(matches!(
+            *libtelio_rx.recv().await.unwrap(),
+            LibtelioEvent::AdapterRecovery {
+                body: AdapterRecovery {
+                    state: AdapterRecoveryState::Recovering
+                }
+            }
+        ));
+        assert!(matches!(
+            *libtelio_rx.recv().await.unwrap(),
+            LibtelioEvent::AdapterRecovery {
+                body: AdapterRecovery {
+                    state: AdapterRecoveryState::Recovered
+                }
+            }
+        ));
+        adapter.lock().await.checkpoint();
+
+        new_adapter.lock().await.expect_stop().return_once(|| ());
+        wg.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wg_switches_adapter_keeping_interface() {
+        let _runtime_adapter = RUNTIME_ADAPTER_USERS.lock().await;
+        let Env { adapter, wg, .. } = setup().await;
+        let interface = random_interface();
+        let to = interface.clone();
+        task_exec!(&wg.task, async move |s| {
+            s.interface = to;
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        // Switching to the adapter already running is a no-op
+        let current = task_exec!(&wg.task, async move |s| Ok(s.cfg.adapter))
+            .await
+            .unwrap();
+        wg.switch_adapter(current).await.unwrap();
+
+        adapter.lock().await.expect_stop().return_once(|| ());
+        let new_adapter = Arc::new(Mutex::new(MockAdapter::new()));
+        new_adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .returning(|_| {
+                Ok(Response {
+                    errno: 0,
+                    interface: None,
+                })
+            });
+        *RUNTIME_ADAPTER.lock().unwrap() = Some(Box::new(new_adapter.clone()));
+
+        let other = match current {
+            AdapterType::NepTUN => AdapterType::LinuxNativeWg,
+            _ => AdapterType::NepTUN,
+        };
+        wg.switch_adapter(other).await.unwrap();
+        adapter.lock().await.checkpoint();
+        assert_eq!(wg.get_interface().await.unwrap(), interface);
+        assert_eq!(
+            task_exec!(&wg.task, async move |s| Ok(s.cfg.adapter))
+                .await
+                .unwrap(),
+            other
+        );
+
+        new_adapter.lock().await.expect_stop().return_once(|| ());
+        wg.stop().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(start_paused = true)]
+    async fn wg_does_not_switch_adapter_of_app_tunnel() {
+        use std::os::unix::io::IntoRawFd;
+
+        let Env { adapter, wg, .. } = setup().await;
+        let tun = std::fs::File::open("/dev/null").unwrap().into_raw_fd();
+        task_exec!(&wg.task, async move |s| {
+            s.cfg.tun = Some(tun);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let current = task_exec!(&wg.task, async move |s| Ok(s.cfg.adapter))
+            .await
+            .unwrap();
+        let other = match current {
+            AdapterType::NepTUN => AdapterType::LinuxNativeWg,
+            _ => AdapterType::NepTUN,
+        };
+        assert!(matches!(
+            wg.switch_adapter(other).await,
+            Err(Error::AppOwnedTunnel)
+        ));
+
+        adapter.lock().await.expect_stop().return_once(|| ());
+        wg.stop().await;
+    }
+
     #[tokio::test(start_paused = true)]
     async fn wg_sets_secret_key() {
         let Env { adapter, wg, .. } = setup().await;
@@ -1535,6 +2096,10 @@ pub mod tests {
             })),
             event.recv().await
         );
+        assert_eq!(
+            wg.get_handshake_counts().await.unwrap(),
+            HashMap::from([(pkc, 1)])
+        );
 
         adapter.lock().await.expect_stop().return_once(|| ());
         wg.stop().await;
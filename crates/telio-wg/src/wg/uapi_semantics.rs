@@ -0,0 +1,141 @@
+//! Checks the configuration pushed by [DynamicWg] against an in-memory adapter following the
+//! cross-platform UAPI semantics. The same checks are run against the real adapters by the
+//! `adapter_semantics` integration test.
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use ipnet::IpNet;
+use proptest::prelude::*;
+use telio_crypto::{PresharedKey, PublicKey, SecretKey};
+use telio_task::{io::Chan, task_exec};
+use tokio::time::Duration;
+use wireguard_uapi::xplatform::set;
+
+use super::{DynamicWg, Io, WireGuard};
+use crate::{
+    adapter::{Adapter, Error},
+    uapi::{Cmd, Interface, Peer, Response},
+};
+
+#[path = "../../tests/model/mod.rs"]
+mod model;
+
+/// In-memory adapter following the cross-platform UAPI semantics
+#[derive(Default)]
+struct MemoryAdapter {
+    interface: Mutex<Interface>,
+}
+
+impl MemoryAdapter {
+    fn apply(interface: &mut Interface, device: &set::Device) {
+        if let Some(private_key) = device.private_key {
+            interface.private_key = Some(SecretKey::new(private_key));
+        }
+        if let Some(listen_port) = device.listen_port {
+            interface.listen_port = Some(listen_port);
+        }
+        if let Some(fwmark) = device.fwmark {
+            interface.fwmark = fwmark;
+        }
+
+        for set_peer in &device.peers {
+            let public_key = PublicKey(set_peer.public_key);
+            if set_peer.remove == Some(true) {
+                interface.peers.remove(&public_key);
+                continue;
+            }
+            if set_peer.update_only == Some(true) && !interface.peers.contains_key(&public_key) {
+                continue;
+            }
+
+            let allowed_ips: Vec<IpNet> = set_peer
+                .allowed_ips
+                .iter()
+                .filter_map(|ip| IpNet::new(ip.ipaddr, ip.cidr_mask).ok())
+                .collect();
+            for other in interface.peers.values_mut() {
+                other.allowed_ips.retain(|ip| !allowed_ips.contains(ip));
+            }
+
+            let peer = interface.peers.entry(public_key).or_insert_with(|| Peer {
+                public_key,
+                persistent_keepalive_interval: Some(0),
+                ..Default::default()
+            });
+            if set_peer.replace_allowed_ips == Some(true) {
+                peer.allowed_ips.clear();
+            }
+            for ip in allowed_ips {
+                if !peer.allowed_ips.contains(&ip) {
+                    peer.allowed_ips.push(ip);
+                }
+            }
+            if let Some(endpoint) = set_peer.endpoint {
+                peer.endpoint = Some(endpoint);
+            }
+            if let Some(keepalive) = set_peer.persistent_keepalive_interval {
+                peer.persistent_keepalive_interval = Some(keepalive.into());
+            }
+            if let Some(preshared_key) = set_peer.preshared_key {
+                peer.preshared_key =
+                    (preshared_key != [0; 32]).then(|| PresharedKey::new(preshared_key));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Adapter for MemoryAdapter {
+    async fn stop(&self) {}
+
+    fn get_adapter_luid(&self) -> u64 {
+        0
+    }
+
+    async fn send_uapi_cmd(&self, cmd: &Cmd) -> Result<Response, Error> {
+        let mut interface = self
+            .interface
+            .lock()
+            .map_err(|_| Error::InternalError("poisoned lock"))?;
+        if let Cmd::Set(device) = cmd {
+            Self::apply(&mut interface, device);
+        }
+        Ok(Response {
+            errno: 0,
+            interface: Some(interface.clone()),
+        })
+    }
+}
+
+proptest! {
+    #[test]
+    fn memory_adapter_matches_model(ops in model::ops()) {
+        model::run(async {
+            let events = Chan::default();
+            let wg = DynamicWg::start_with(
+                Io {
+                    events: events.tx,
+                    analytics_tx: None,
+                    libtelio_wide_event_publisher: None,
+                },
+                Box::new(MemoryAdapter::default()),
+                #[cfg(unix)]
+                None,
+                None,
+                super::Config::new().map_err(|e| TestCaseError::fail(e.to_string()))?,
+                false,
+                Duration::from_secs(3600),
+                Duration::from_secs(3600),
+            );
+            // Read the adapter state back right away instead of waiting for the polling
+            let pull = || async {
+                task_exec!(&wg.task, async move |s| Ok(s.sync().await)).await??;
+                wg.get_interface().await
+            };
+            let res = model::check_against_model(&wg, pull, &ops).await;
+            wg.stop().await;
+            res
+        })?;
+    }
+}
@@ -0,0 +1,87 @@
+//! Runs the peer configuration model against the real adapters.
+//!
+//! Creating the tunnel interfaces needs CAP_NET_ADMIN, so these only build with the
+//! `privileged_tests` feature:
+//!
+//! ```sh
+//! sudo -E cargo test -p telio-wg --features privileged_tests --test adapter_semantics
+//! ```
+
+#![cfg(all(
+    target_os = "linux",
+    feature = "privileged_tests",
+    not(feature = "test-adapter")
+))]
+
+use std::sync::Arc;
+
+use proptest::prelude::*;
+use telio_sockets::{NativeProtector, SocketPool};
+use telio_task::io::Chan;
+use telio_wg::{
+    uapi::{Interface, Peer},
+    AdapterType, Config, DynamicWg, Error, Io, WireGuard,
+};
+use tokio::time::{sleep, Duration};
+
+mod model;
+
+const POLLING_PERIOD: Duration = Duration::from_millis(10);
+
+async fn check_adapter(
+    adapter: AdapterType,
+    name: &str,
+    ops: &[model::Op],
+) -> Result<(), TestCaseError> {
+    let events = Chan::default();
+    let protector = NativeProtector::new().map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let wg = DynamicWg::start(
+        Io {
+            events: events.tx,
+            analytics_tx: None,
+            libtelio_wide_event_publisher: None,
+        },
+        Config {
+            adapter,
+            name: Some(name.to_owned()),
+            tun: None,
+            socket_pool: Arc::new(SocketPool::new(protector)),
+            firewall_process_inbound_callback: None,
+            firewall_process_outbound_callback: None,
+            firewall_reset_connections: None,
+            obfuscation: None,
+            handshake_load: None,
+            uapi_socket: None,
+            recovery: None,
+            handshake_events: None,
+        },
+        None,
+        false,
+        POLLING_PERIOD,
+        POLLING_PERIOD,
+    )
+    .map_err(|e| TestCaseError::fail(e.to_string()))?;
+
+    // The state read back from the adapter is picked up by the next polling
+    let pull = || async {
+        sleep(POLLING_PERIOD * 5).await;
+        wg.get_interface().await
+    };
+    let res = model::check_against_model(&wg, pull, ops).await;
+    wg.stop().await;
+    res
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn neptun_matches_model(ops in model::ops()) {
+        model::run(check_adapter(AdapterType::NepTUN, "tlpropneptun", &ops))?;
+    }
+
+    #[test]
+    fn linux_native_wg_matches_model(ops in model::ops()) {
+        model::run(check_adapter(AdapterType::LinuxNativeWg, "tlpropnative", &ops))?;
+    }
+}
@@ -0,0 +1,182 @@
+//! Model of the peer configuration done through [WireGuard], shared by the tests running it
+//! against the in-memory and the real adapters.
+//!
+//! The including module provides `Error`, `Interface`, `Peer` and `WireGuard`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use ipnet::IpNet;
+use proptest::prelude::*;
+use telio_crypto::{PresharedKey, PublicKey, SecretKey};
+
+use super::{Error, Interface, Peer, WireGuard};
+
+const PEER_COUNT: u8 = 4;
+
+type PeerConfig = (
+    Option<SocketAddr>,
+    Option<u32>,
+    BTreeSet<IpNet>,
+    Option<PresharedKey>,
+);
+
+/// Adapter independent view of the configuration
+#[derive(Debug, Default, PartialEq)]
+pub struct Model {
+    private_key: Option<SecretKey>,
+    fwmark: u32,
+    peers: BTreeMap<PublicKey, PeerConfig>,
+}
+
+impl Model {
+    /// Apply `op`, returns whether it is expected to be accepted
+    fn apply(&mut self, op: &Op) -> bool {
+        match op {
+            Op::AddPeer(peer) => {
+                let allowed_ips: BTreeSet<IpNet> = peer.allowed_ips.iter().cloned().collect();
+                if self
+                    .peers
+                    .iter()
+                    .filter(|(public_key, _)| **public_key != peer.public_key)
+                    .any(|(_, (_, _, ips, _))| !ips.is_disjoint(&allowed_ips))
+                {
+                    return false;
+                }
+                // Endpoints cannot be cleared through UAPI, a missing one keeps the old endpoint
+                let endpoint = peer.endpoint.or_else(|| {
+                    self.peers
+                        .get(&peer.public_key)
+                        .and_then(|(endpoint, ..)| *endpoint)
+                });
+                self.peers.insert(
+                    peer.public_key,
+                    (
+                        endpoint,
+                        peer.persistent_keepalive_interval.filter(|k| *k != 0),
+                        allowed_ips,
+                        peer.preshared_key.clone(),
+                    ),
+                );
+            }
+            Op::DelPeer(public_key) => {
+                self.peers.remove(public_key);
+            }
+            Op::SetSecretKey(secret_key) => self.private_key = Some(secret_key.clone()),
+            Op::SetFwmark(fwmark) => self.fwmark = *fwmark,
+        }
+        true
+    }
+}
+
+impl From<&Interface> for Model {
+    fn from(interface: &Interface) -> Self {
+        Self {
+            private_key: interface.private_key.clone(),
+            fwmark: interface.fwmark,
+            peers: interface
+                .peers
+                .iter()
+                .map(|(public_key, peer)| {
+                    (
+                        *public_key,
+                        (
+                            peer.endpoint,
+                            peer.persistent_keepalive_interval.filter(|k| *k != 0),
+                            peer.allowed_ips.iter().cloned().collect(),
+                            peer.preshared_key.clone(),
+                        ),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    AddPeer(Peer),
+    DelPeer(PublicKey),
+    SetSecretKey(SecretKey),
+    SetFwmark(u32),
+}
+
+fn public_key(index: u8) -> PublicKey {
+    SecretKey::new([index + 1; 32]).public()
+}
+
+fn peer() -> impl Strategy<Value = Peer> {
+    (
+        0..PEER_COUNT,
+        proptest::option::of((1u8..8, 1024u16..)),
+        proptest::option::of(0u32..120),
+        proptest::collection::vec(0u8..8, 0..4),
+        proptest::option::of(any::<[u8; 32]>().prop_filter("zero key", |k| k != &[0; 32])),
+    )
+        .prop_map(
+            |(index, endpoint, keepalive, allowed_ips, preshared_key)| Peer {
+                public_key: public_key(index),
+                endpoint: endpoint.map(|(host, port)| SocketAddr::from(([127, 0, 0, host], port))),
+                persistent_keepalive_interval: keepalive,
+                allowed_ips: allowed_ips
+                    .into_iter()
+                    .map(|host| IpNet::from(IpAddr::V4(Ipv4Addr::new(100, 64, 0, host))))
+                    .collect(),
+                preshared_key: preshared_key.map(PresharedKey::new),
+                ..Default::default()
+            },
+        )
+}
+
+pub fn ops() -> impl Strategy<Value = Vec<Op>> {
+    let op = prop_oneof![
+        3 => peer().prop_map(Op::AddPeer),
+        1 => (0..PEER_COUNT).prop_map(|index| Op::DelPeer(public_key(index))),
+        1 => any::<[u8; 32]>().prop_map(|key| Op::SetSecretKey(SecretKey::new(key))),
+        1 => any::<u32>().prop_map(Op::SetFwmark),
+    ];
+    proptest::collection::vec(op, 1..32)
+}
+
+fn fail(e: Error) -> TestCaseError {
+    TestCaseError::fail(e.to_string())
+}
+
+/// Apply `ops` through `wg`, checking the configuration read back from the adapter by `pull`
+/// against the model after each one
+pub async fn check_against_model<W, F, Fut>(
+    wg: &W,
+    pull: F,
+    ops: &[Op],
+) -> Result<(), TestCaseError>
+where
+    W: WireGuard,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Interface, Error>>,
+{
+    let mut model = Model::from(&pull().await.map_err(fail)?);
+    for op in ops {
+        let res = match op {
+            Op::AddPeer(peer) => wg.add_peer(peer.clone()).await,
+            Op::DelPeer(public_key) => wg.del_peer(*public_key).await,
+            Op::SetSecretKey(secret_key) => wg.set_secret_key(secret_key.clone()).await,
+            Op::SetFwmark(fwmark) => wg.set_fwmark(*fwmark).await,
+        };
+        prop_assert_eq!(res.is_ok(), model.apply(op), "{:?} for {:?}", res, op);
+
+        let interface = pull().await.map_err(fail)?;
+        prop_assert_eq!(&Model::from(&interface), &model, "after {:?}", op);
+    }
+    Ok(())
+}
+
+pub fn run(check: impl Future<Output = Result<(), TestCaseError>>) -> Result<(), TestCaseError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| TestCaseError::fail(e.to_string()))?
+        .block_on(check)
+}
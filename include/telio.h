@@ -0,0 +1,231 @@
+#ifndef TELIO_H
+#define TELIO_H
+
+/* Warning, this file is autogenerated by cbindgen. Don't modify this manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * WireGuard implementation used by a started device
+ */
+typedef enum TelioAdapter {
+  /**
+   * Userland rust implementation
+   */
+  TelioAdapter_NepTUN = 0,
+  /**
+   * Linux in-kernel WireGuard implementation
+   */
+  TelioAdapter_LinuxNativeTun = 1,
+  /**
+   * WireguardGo implementation
+   */
+  TelioAdapter_WireguardGoTun = 2,
+  /**
+   * WindowsNativeWireguardNt implementation
+   */
+  TelioAdapter_WindowsNativeTun = 3,
+} TelioAdapter;
+
+/**
+ * Result of the calls of the C ABI
+ */
+typedef enum TelioResult {
+  /**
+   * The call succeeded
+   */
+  TelioResult_Ok = 0,
+  /**
+   * Unexpected failure, the details are logged
+   */
+  TelioResult_UnknownError = 1,
+  /**
+   * The key is not a valid base64 WireGuard key
+   */
+  TelioResult_InvalidKey = 2,
+  /**
+   * The config or the argument could not be parsed
+   */
+  TelioResult_BadConfig = 3,
+  /**
+   * The device lock is poisoned
+   */
+  TelioResult_LockError = 4,
+  /**
+   * The string argument is NULL or not UTF-8
+   */
+  TelioResult_InvalidString = 5,
+  /**
+   * The device is already started
+   */
+  TelioResult_AlreadyStarted = 6,
+  /**
+   * The device is not started
+   */
+  TelioResult_NotStarted = 7,
+  /**
+   * The meshnet config has more peers than allowed
+   */
+  TelioResult_TooManyPeers = 8,
+  /**
+   * The call did not complete in time
+   */
+  TelioResult_Timeout = 9,
+  /**
+   * A required pointer argument is NULL
+   */
+  TelioResult_NullArgument = 10,
+} TelioResult;
+
+/**
+ * Opaque handle of a device, created by `telio_new` and freed by `telio_destroy`
+ */
+typedef struct TelioDevice TelioDevice;
+
+/**
+ * Version of the C ABI, compatible with every caller built against the same major version and
+ * an older or equal minor version
+ */
+typedef struct TelioAbiVersion {
+  /**
+   * Bumped on breaking changes of the exported signatures or types
+   */
+  uint32_t major;
+  /**
+   * Bumped when functions or types are added
+   */
+  uint32_t minor;
+  /**
+   * Bumped on fixes which do not change the ABI
+   */
+  uint32_t patch;
+} TelioAbiVersion;
+
+/**
+ * Receiver of the events of a device, called from the library threads with the `ctx` given to
+ * `telio_new` and the event as NUL terminated JSON, which is only valid during the call
+ */
+typedef void (*TelioEventFn)(void *ctx, const char *event);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Get the version of the C ABI implemented by the library.
+ */
+struct TelioAbiVersion telio_abi_version(void);
+
+/**
+ * Check whether the library can be used by a caller built against the given ABI version.
+ */
+bool telio_abi_is_compatible(uint32_t major, uint32_t minor);
+
+/**
+ * Get the current version tag, as a static NUL terminated string owned by the library.
+ */
+const char *telio_version_tag(void);
+
+/**
+ * Get the current commit sha, as a static NUL terminated string owned by the library.
+ */
+const char *telio_commit_sha(void);
+
+/**
+ * Create a device, without starting it.
+ *
+ * `features` is the JSON of the features config, NULL or empty for the defaults. `events` is
+ * called with `ctx` for every event, from the library threads. On success the handle is stored
+ * to `device`, and must be freed with `telio_destroy`.
+ *
+ * # Safety
+ * `features` must be NULL or a NUL terminated string, and `device` must be a valid pointer.
+ */
+enum TelioResult telio_new(const char *features,
+                           TelioEventFn events,
+                           void *ctx,
+                           struct TelioDevice **device);
+
+/**
+ * Stop a device and free it, NULL is ignored.
+ *
+ * # Safety
+ * `device` must be NULL or a handle from `telio_new` which is not used anymore.
+ */
+void telio_destroy(struct TelioDevice *device);
+
+/**
+ * Start a device with the base64 encoded `private_key` and the given adapter.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`, `private_key` a NUL terminated string.
+ */
+enum TelioResult telio_start(const struct TelioDevice *device,
+                             const char *private_key,
+                             enum TelioAdapter adapter);
+
+/**
+ * Stop a device, it can be started again.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`.
+ */
+enum TelioResult telio_stop(const struct TelioDevice *device);
+
+/**
+ * Enable meshnet or update its config, given as the JSON of the meshnet config.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`, `config` a NUL terminated string.
+ */
+enum TelioResult telio_set_meshnet(const struct TelioDevice *device, const char *config);
+
+/**
+ * Disable meshnet.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`.
+ */
+enum TelioResult telio_set_meshnet_off(const struct TelioDevice *device);
+
+/**
+ * Connect to an exit node with the base64 encoded `public_key`.
+ *
+ * `allowed_ips` is a comma separated list of subnets routed to the exit node, NULL for all of
+ * them. `endpoint` is the address of a VPN server with its port, NULL for a meshnet peer.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`, `public_key` a NUL terminated string, and
+ * `allowed_ips` and `endpoint` NULL or NUL terminated strings.
+ */
+enum TelioResult telio_connect_to_exit_node(const struct TelioDevice *device,
+                                            const char *public_key,
+                                            const char *allowed_ips,
+                                            const char *endpoint);
+
+/**
+ * Disconnect from all the exit nodes.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`.
+ */
+enum TelioResult telio_disconnect_from_exit_nodes(const struct TelioDevice *device);
+
+/**
+ * Notify the device about a change of the network, `network_info` is NULL when nothing is
+ * known about the new network.
+ *
+ * # Safety
+ * `device` must be a handle from `telio_new`, `network_info` NULL or a NUL terminated string.
+ */
+enum TelioResult telio_notify_network_change(const struct TelioDevice *device,
+                                             const char *network_info);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* TELIO_H */
@@ -23,6 +23,22 @@ from utils.bindings import (
     Server,
     ErrorEvent,
     Event,
+    PeerRekeyed,
+    PeerUnreachable,
+    MtuChanged,
+    InboundConnection,
+    MaintenanceStateChanged,
+    ConfigRollback,
+    AdapterRecovery,
+    MeshRemediation,
+    HaRoleChanged,
+    AnalyticsConsentChanged,
+    RouteConflict,
+    PeerHandshake,
+    DnsRecordsInvalidated,
+    AuditRecord,
+    PingProbe,
+    PeerStatsReport,
     PathType,
     NodeState,
     RelayState,
@@ -51,6 +67,23 @@ class Runtime:
     _peer_state_events: List[TelioNode]
     _derp_state_events: List[Server]
     _error_events: List[ErrorEvent]
+    _peer_rekeyed_events: List[PeerRekeyed]
+    _peer_unreachable_events: List[PeerUnreachable]
+    _mtu_changed_events: List[MtuChanged]
+    _inbound_connection_events: List[InboundConnection]
+    _maintenance_state_events: List[MaintenanceStateChanged]
+    _config_rollback_events: List[ConfigRollback]
+    _adapter_recovery_events: List[AdapterRecovery]
+    _mesh_remediation_events: List[MeshRemediation]
+    _ha_role_changed_events: List[HaRoleChanged]
+    _analytics_consent_events: List[AnalyticsConsentChanged]
+    _route_conflict_events: List[RouteConflict]
+    _peer_handshake_events: List[PeerHandshake]
+    _dns_records_invalidated_events: List[DnsRecordsInvalidated]
+    _audit_record_events: List[AuditRecord]
+    _ping_probe_events: List[PingProbe]
+    _peer_stats_events: List[PeerStatsReport]
+    _node_transition_events: List[TelioNode]
     _started_tasks: List[str]
     _stopped_tasks: List[str]
     allowed_pub_keys: Set[str]
@@ -60,6 +93,23 @@ class Runtime:
         self._peer_state_events = []
         self._derp_state_events = []
         self._error_events = []
+        self._peer_rekeyed_events = []
+        self._peer_unreachable_events = []
+        self._mtu_changed_events = []
+        self._inbound_connection_events = []
+        self._maintenance_state_events = []
+        self._config_rollback_events = []
+        self._adapter_recovery_events = []
+        self._mesh_remediation_events = []
+        self._ha_role_changed_events = []
+        self._analytics_consent_events = []
+        self._route_conflict_events = []
+        self._peer_handshake_events = []
+        self._dns_records_invalidated_events = []
+        self._audit_record_events = []
+        self._ping_probe_events = []
+        self._peer_stats_events = []
+        self._node_transition_events = []
         self._started_tasks = []
         self._stopped_tasks = []
         self.allowed_pub_keys = set()
@@ -89,6 +139,40 @@ class Runtime:
             self._handle_derp_event(event.body)
         elif isinstance(event, Event.ERROR):
             self._handle_error_event(event.body)
+        elif isinstance(event, Event.PEER_REKEYED):
+            self._peer_rekeyed_events.append(event.body)
+        elif isinstance(event, Event.PEER_UNREACHABLE):
+            self._peer_unreachable_events.append(event.body)
+        elif isinstance(event, Event.MTU_CHANGED):
+            self._mtu_changed_events.append(event.body)
+        elif isinstance(event, Event.INBOUND_CONNECTION):
+            self._inbound_connection_events.append(event.body)
+        elif isinstance(event, Event.MAINTENANCE_STATE_CHANGED):
+            self._maintenance_state_events.append(event.body)
+        elif isinstance(event, Event.CONFIG_ROLLBACK):
+            self._config_rollback_events.append(event.body)
+        elif isinstance(event, Event.ADAPTER_RECOVERY):
+            self._adapter_recovery_events.append(event.body)
+        elif isinstance(event, Event.MESH_REMEDIATION):
+            self._mesh_remediation_events.append(event.body)
+        elif isinstance(event, Event.HA_ROLE_CHANGED):
+            self._ha_role_changed_events.append(event.body)
+        elif isinstance(event, Event.ANALYTICS_CONSENT_CHANGED):
+            self._analytics_consent_events.append(event.body)
+        elif isinstance(event, Event.ROUTE_CONFLICT):
+            self._route_conflict_events.append(event.body)
+        elif isinstance(event, Event.PEER_HANDSHAKE):
+            self._peer_handshake_events.append(event.body)
+        elif isinstance(event, Event.DNS_RECORDS_INVALIDATED):
+            self._dns_records_invalidated_events.append(event.body)
+        elif isinstance(event, Event.AUDIT_RECORD):
+            self._audit_record_events.append(event.body)
+        elif isinstance(event, Event.PING_PROBE):
+            self._ping_probe_events.append(event.body)
+        elif isinstance(event, Event.PEER_STATS_REPORT):
+            self._peer_stats_events.append(event.body)
+        elif isinstance(event, Event.NODE_TRANSITION):
+            self._node_transition_events.append(event.body)
         else:
             raise TypeError(f"Got invalid event type: {event}")
 
@@ -26,7 +26,9 @@ def _build_parameters(
             derp_keepalive=15,
             poll_keepalive=True,
             enable_polling=False,
+            enable_peer_presence=False,
             use_built_in_root_certificates=False,
+            enable_compression=False,
         )
     return [
         SetupParameters(
@@ -0,0 +1,101 @@
+import asyncio
+import pytest
+from contextlib import AsyncExitStack
+from datetime import datetime
+from helpers import setup_environment, SetupParameters
+from typing import List, Tuple
+from utils.bindings import (
+    default_features,
+    EndpointProvider,
+    NodeState,
+    PathType,
+    RelayState,
+    TelioAdapterType,
+)
+from utils.connection_util import ConnectionTag
+
+# Upper bound of the time it takes to go direct, the upgrade logic checks the endpoints every
+# few seconds, so it is a regression if it takes much longer than a few rounds
+TIME_TO_DIRECT_LIMIT = 60.0
+
+# fmt: off
+NAT_PAIRS: List[Tuple[ConnectionTag, ConnectionTag, List[EndpointProvider]]] = [
+    (ConnectionTag.DOCKER_OPEN_INTERNET_CLIENT_1, ConnectionTag.DOCKER_OPEN_INTERNET_CLIENT_2, [EndpointProvider.STUN]),
+    (ConnectionTag.DOCKER_FULLCONE_CLIENT_1, ConnectionTag.DOCKER_FULLCONE_CLIENT_2, [EndpointProvider.STUN]),
+    (ConnectionTag.DOCKER_CONE_CLIENT_1, ConnectionTag.DOCKER_CONE_CLIENT_2, [EndpointProvider.STUN]),
+    (ConnectionTag.DOCKER_CONE_CLIENT_1, ConnectionTag.DOCKER_FULLCONE_CLIENT_1, [EndpointProvider.STUN]),
+    (ConnectionTag.DOCKER_SYMMETRIC_CLIENT_1, ConnectionTag.DOCKER_FULLCONE_CLIENT_1, [EndpointProvider.STUN]),
+    (ConnectionTag.DOCKER_UPNP_CLIENT_1, ConnectionTag.DOCKER_UPNP_CLIENT_2, [EndpointProvider.UPNP]),
+]
+# fmt: on
+
+
+def _setup_parameters(
+    tag: ConnectionTag, providers: List[EndpointProvider]
+) -> SetupParameters:
+    features = default_features(enable_direct=True)
+    assert features.direct
+    features.direct.providers = providers
+    return SetupParameters(
+        connection_tag=tag,
+        adapter_type_override=TelioAdapterType.NEP_TUN,
+        features=features,
+        fingerprint=f"{tag}",
+    )
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "tag_1, tag_2, providers",
+    [
+        pytest.param(tag_1, tag_2, providers, id=f"{tag_1.name}-{tag_2.name}")
+        for tag_1, tag_2, providers in NAT_PAIRS
+    ],
+)
+async def test_time_to_direct(
+    tag_1: ConnectionTag, tag_2: ConnectionTag, providers: List[EndpointProvider]
+) -> None:
+    async with AsyncExitStack() as exit_stack:
+        env = await exit_stack.enter_async_context(
+            setup_environment(
+                exit_stack,
+                [
+                    _setup_parameters(tag_1, providers),
+                    _setup_parameters(tag_2, providers),
+                ],
+            )
+        )
+        alpha, beta = env.clients
+        alpha_node, beta_node = env.nodes
+
+        await asyncio.gather(*[
+            client.wait_for_state_on_any_derp([RelayState.CONNECTED])
+            for client in env.clients
+        ])
+        await asyncio.gather(
+            alpha.wait_for_state_peer(
+                beta_node.public_key, [NodeState.CONNECTED], [PathType.RELAY]
+            ),
+            beta.wait_for_state_peer(
+                alpha_node.public_key, [NodeState.CONNECTED], [PathType.RELAY]
+            ),
+        )
+
+        started = asyncio.get_running_loop().time()
+        await asyncio.wait_for(
+            asyncio.gather(
+                alpha.wait_for_state_peer(
+                    beta_node.public_key, [NodeState.CONNECTED], [PathType.DIRECT]
+                ),
+                beta.wait_for_state_peer(
+                    alpha_node.public_key, [NodeState.CONNECTED], [PathType.DIRECT]
+                ),
+            ),
+            TIME_TO_DIRECT_LIMIT,
+        )
+        elapsed = asyncio.get_running_loop().time() - started
+
+        print(
+            datetime.now(),
+            f"Time to direct {tag_1.name} - {tag_2.name}: {elapsed:.2f}s",
+        )
@@ -1,29 +1,64 @@
+mod audit_log;
+mod config_queue;
+mod debounce;
+mod ha;
+mod namespaces;
+mod on_demand;
+mod peer_limit;
+mod peer_stats;
+mod preview;
+mod reachability;
+mod route_conflicts;
+mod threads;
+mod wake_on_lan;
+mod watchdog;
 mod wg_controller;
 
+use audit_log::AuditLog;
+use config_queue::{ConfigQueue, ConfigWaiter, PendingConfig};
+use debounce::NodeDebouncer;
+use ha::{HaAction, HaEvent, HaState, HighAvailability};
+use namespaces::MeshnetNamespaces;
+use on_demand::TrafficWatcher;
+use peer_limit::PeerLimit;
+use peer_stats::{Counters, PeerStatsStream};
+use preview::ConnectivityHints;
+use reachability::{CauseHints, ReachabilityTracker};
+use watchdog::MeshWatchdog;
+
 use async_trait::async_trait;
-use telio_crypto::{PublicKey, SecretKey};
-use telio_firewall::firewall::{Firewall, StatefullFirewall};
+use telio_crypto::{
+    acceleration::{crypto_acceleration_report, CpuFeatures, CryptoAccelerationReport},
+    PublicKey, SecretKey,
+};
+use telio_firewall::firewall::{Firewall, MonitorStats, StatefullFirewall};
 use telio_lana::init_lana;
 use telio_nat_detect::nat_detection::{retrieve_single_nat, NatData};
-use telio_network_monitors::{local_interfaces::SystemGetIfAddrs, monitor::NetworkMonitor};
+use telio_network_monitors::{
+    local_interfaces::SystemGetIfAddrs,
+    monitor::{
+        is_network_available, is_on_cellular, NetworkMonitor, LOCAL_ADDRS_CACHE,
+        NETWORK_AVAILABILITY,
+    },
+};
 use telio_pq::PostQuantum;
-use telio_proto::HeartbeatMessage;
+use telio_proto::{HeartbeatMessage, WakeOnLanMsg};
 use telio_proxy::{Config as ProxyConfig, Io as ProxyIo, Proxy, UdpProxy};
 use telio_relay::{
-    derp::Config as DerpConfig, multiplexer::Multiplexer, DerpKeepaliveConfig, DerpRelay,
-    SortedServers,
+    derp::Config as DerpConfig, multiplexer::Multiplexer, queue::DroppedPackets,
+    DerpKeepaliveConfig, DerpRelay, PathProbesConfig, SortedServers,
 };
-use telio_sockets::{NativeProtector, Protector, SocketPool};
+use telio_sockets::{NativeProtector, Protector, SocketPool, MAX_DSCP};
 use telio_starcast::{
     starcast_peer::{Config as StarcastPeerConfig, StarcastPeer},
     transport::{Config as StarcastTransportConfig, Transport},
 };
 use telio_task::{
+    cancellation_scope,
     io::{chan, mc_chan, mc_chan::Tx, Chan, McChan},
-    task_exec, BoxAction, Runtime as TaskRuntime, Task,
+    task_exec, BoxAction, CancellationToken, Runtime as TaskRuntime, Task,
 };
 
-use telio_traversal::UpgradeSyncTrait;
 use telio_traversal::{
     connectivity_check,
     cross_ping_check::{CrossPingCheck, CrossPingCheckTrait, Io as CpcIo, UpgradeController},
@@ -38,34 +73,49 @@ use telio_traversal::{
     ping_pong_handler::PingPongHandler,
     SessionKeeper, UpgradeRequestChangeEvent, UpgradeSync, WireGuardEndpointCandidateChangeEvent,
 };
+use telio_traversal::{SessionKeeperTrait, UpgradeSyncTrait};
 
 #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
 use telio_sockets::native;
 
 use telio_nurse::{
-    aggregator::ConnectivityDataAggregator, config::AggregatorConfig,
-    config::Config as NurseConfig, data::MeshConfigUpdateEvent,
+    aggregator::{ConnectivityDataAggregator, DnsFailureKind as NurseDnsFailureKind},
+    config::AggregatorConfig,
+    config::Config as NurseConfig,
+    data::MeshConfigUpdateEvent,
     MeshnetEntities as NurseMeshnetEntities, Nurse, NurseIo,
 };
 use telio_wg as wg;
 use thiserror::Error as TError;
 use tokio::{
     runtime::{Builder, Runtime as AsyncRuntime},
-    sync::{broadcast::error::RecvError, Mutex},
+    sync::{broadcast::error::RecvError, oneshot, watch, Mutex},
+    task::JoinHandle,
     time::Interval,
 };
 
-use telio_dns::{DnsResolver, LocalDnsResolver, Records};
+use telio_dns::{
+    bootstrap::ServerBootstrap, Blocklist, DnsBlocklistStats, DnsFailure, DnsFailureKind,
+    DnsResolver, LocalDnsResolver, Records, ZoneVersions,
+};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::IntoRawFd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use telio_netstack::{Config as NetstackConfig, Netstack, Socks5Gateway, VirtualTun};
 
 use telio_dns::bind_tun;
-use wg::uapi::{self, PeerState};
+use wg::uapi::{self, AnalyticsEvent, PeerState};
 
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
     io::{self, Error as IoError},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -73,19 +123,29 @@ use cfg_if::cfg_if;
 use futures::FutureExt;
 
 use telio_utils::{
-    commit_sha,
+    chaos, coalesced_interval, commit_sha,
     exponential_backoff::ExponentialBackoffBounds,
-    get_ip_stack, interval, telio_log_debug, telio_log_error, telio_log_info, telio_log_warn,
+    get_ip_stack, telio_log_debug, telio_log_error, telio_log_info, telio_log_warn,
     tokio::{Monitor, ThreadTracker},
     version_tag,
 };
 
 use telio_model::{
-    config::{Config, Peer, PeerBase, Server as DerpServer},
+    config::{Config, MeshnetNamespacePolicy, Peer, PeerBase, RelayState, Server as DerpServer},
     constants::{VPN_EXTERNAL_IPV4, VPN_INTERNAL_IPV4},
-    event::{Event, Set},
+    event::{
+        AnalyticsConsentChanged, AuditAction, AuditRecord, ConfigRollback, ConnectionProtocol,
+        DnsRecordsInvalidated, Event, HaRoleChanged, InboundConnection, MaintenanceState,
+        MaintenanceStateChanged, MeshRemediation, MtuChanged, PeerRekeyed, PeerUnreachable,
+        PingProbe, RemediationStep, RouteConflict, Set,
+    },
     features::{FeaturePersistentKeepalive, Features, PathType},
-    mesh::{ExitNode, LinkState, Node, NodeState},
+    memory::{ComponentMemoryUsage, MemoryAccounting, MemoryComponent},
+    mesh::{
+        CandidateType, ConnectivityPreview, ExitNode, LinkState, Node, NodeState, PathPreference,
+        PeerCandidates, PeerCounters,
+    },
+    network::NetworkInfo,
     validation::validate_nickname,
     EndpointMap,
 };
@@ -96,6 +156,11 @@ use telio_network_monitors::monitor::PATH_CHANGE_BROADCAST;
 #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
 static NETWORK_PATH_MONITOR_START: std::sync::Once = std::sync::Once::new();
 
+/// Time the runtime has to answer the health probe of a call made after another one timed out
+const HEALTH_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub use wg_controller::WgPeerPlan;
+
 pub use wg::{
     uapi::Event as WGEvent, uapi::Interface, AdapterType, DynamicWg, Error as AdapterError,
     FirewallCb, Tun, WireGuard,
@@ -175,20 +240,73 @@ pub enum Error {
     PmtuProbe(std::io::Error),
     #[error("Connection upgrade failed - there is no session for key {0:?}")]
     NoSessionForKey(PublicKey),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[error("Userspace network stack error: {0}")]
+    Netstack(#[from] telio_netstack::Error),
     #[error("Pinger receive timeout")]
     PingerReceiveTimeout,
     #[error("Pinger received unexpected packet")]
     PingerReceiveUnexpected,
+    #[error("Ping is only supported in userspace mode")]
+    PingUnsupported,
+    #[error("Invalid ping count {0}")]
+    InvalidPingCount(u32),
     #[error(transparent)]
     StarcastError(#[from] telio_starcast::starcast_peer::Error),
     #[error(transparent)]
     TransportError(#[from] telio_starcast::transport::Error),
     #[error("Events processing thread failed to start: {0}")]
     EventsProcessingThreadStartError(std::io::Error),
+    #[error(transparent)]
+    Namespace(#[from] namespaces::Error),
+    #[error("Meshnet config has {count} peers, more than the limit of {limit}")]
+    TooManyPeers { count: usize, limit: usize },
+    #[error("Meshnet config was not applied: {0}")]
+    ConfigNotApplied(String),
+    #[error("Device call timed out after {0:?}")]
+    Timeout(Duration),
+    #[error("Device runtime is not responding")]
+    RuntimeUnhealthy,
+    #[error(transparent)]
+    WakeOnLan(#[from] wake_on_lan::Error),
 }
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
+/// Completion of a config submitted with `Device::submit_config`
+pub struct ConfigHandle {
+    rx: oneshot::Receiver<std::result::Result<(), String>>,
+    /// Time budget of the `api_timeout` feature, with the runtime keeping the time
+    budget: Option<(tokio::runtime::Handle, Duration)>,
+}
+
+impl ConfigHandle {
+    /// Block until the config, or a later one which replaced it, is applied, within the time
+    /// budget of the `api_timeout` feature
+    ///
+    /// A config which is not applied within the budget is not withdrawn, it is still applied
+    /// once the device gets to it.
+    pub fn wait(self) -> Result {
+        let received = match self.budget {
+            Some((runtime, timeout)) => runtime
+                .block_on(tokio::time::timeout(timeout, self.rx))
+                .map_err(|_| Error::Timeout(timeout))?,
+            None => self.rx.blocking_recv(),
+        };
+        match received {
+            Ok(result) => result.map_err(Error::ConfigNotApplied),
+            Err(_) => Err(Error::ConfigNotApplied("device stopped".to_owned())),
+        }
+    }
+
+    /// Block until the config, or a later one which replaced it, is applied, however long it
+    /// takes
+    fn wait_unbounded(mut self) -> Result {
+        self.budget = None;
+        self.wait()
+    }
+}
+
 pub trait EventCb: Fn(Box<Event>) + Send + 'static {}
 impl<T> EventCb for T where T: Fn(Box<Event>) + Send + 'static {}
 
@@ -205,8 +323,14 @@ pub struct Device {
     async_runtime: Option<Box<AsyncRuntime>>,
     event: Tx<Box<Event>>,
     rt: Option<Task<Runtime>>,
+    // Parent of every task started by the runtime, cancelled once the device is stopped
+    cancel: Option<CancellationToken>,
     protect: Option<Arc<dyn Protector>>,
     features: Features,
+    // Set once a call ran out of its time budget, until a call completes again
+    unhealthy: AtomicBool,
+    // A start or stop which ran out of its time budget, completing in the background
+    pending: Option<JoinHandle<()>>,
 }
 
 #[derive(Default)]
@@ -214,7 +338,10 @@ pub struct RequestedState {
     // WireGuard interface configuration
     pub device_config: DeviceConfig,
 
-    // A configuration as requested by libtelio.set_config(...) call, no modifications
+    // Configurations of all meshnet namespaces, merged into `meshnet_config`
+    pub(crate) meshnet_namespaces: MeshnetNamespaces,
+
+    // A configuration as requested by libtelio.set_config(...) call, merged with other namespaces
     pub meshnet_config: Option<Config>,
 
     // An old meshnet configuration
@@ -237,6 +364,33 @@ pub struct RequestedState {
 
     // Requested keepalive periods
     pub(crate) keepalive_periods: FeaturePersistentKeepalive,
+
+    // Network state, as last passed by libtelio.notify_network_change(...)
+    pub network_info: NetworkInfo,
+
+    // Handshake timeout of the VPN fallbacks, overriding the configured one on cellular networks
+    pub(crate) vpn_handshake_timeout_s: Option<u64>,
+
+    // Nodes never connected directly, as set by libtelio.set_peer_path_preference(...)
+    pub(crate) relay_only_peers: HashSet<PublicKey>,
+
+    // Background activity is stopped by libtelio.suspend(...) until libtelio.resume(...)
+    pub(crate) suspended: bool,
+
+    // Meshnet peers quiesced until traffic is sent to them, with the on_demand feature
+    pub(crate) idle_peers: HashSet<PublicKey>,
+
+    // Candidate exit node staged by libtelio.prewarm_exit_node(...) for a quick switch
+    pub(crate) prewarmed_exit_node: Option<PrewarmedExitNode>,
+}
+
+/// VPN server staged while connected to another one, so that switching to it is quick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PrewarmedExitNode {
+    pub(crate) public_key: PublicKey,
+    pub(crate) endpoint: SocketAddr,
+    /// Handshake with the server ahead of the switch, keeping the session up with keepalives
+    pub(crate) handshake: bool,
 }
 
 pub struct MeshnetEntities {
@@ -257,6 +411,9 @@ pub struct MeshnetEntities {
 
     // Keepalive sender
     session_keeper: Option<Arc<SessionKeeper>>,
+
+    // Wake-on-LAN requests sent to the other nodes
+    wake_on_lan: chan::Tx<(PublicKey, WakeOnLanMsg)>,
 }
 
 #[derive(Default, Debug)]
@@ -318,6 +475,17 @@ pub struct Entities {
     pmtu_detection: Option<telio_pmtu::Entity>,
 
     network_monitor: NetworkMonitor,
+
+    // Userspace network stack, used instead of the tunnel interface when running without one
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    netstack: Option<Arc<Netstack>>,
+
+    // Local SOCKS5 proxy into the userspace network stack
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    socks5: Option<Socks5Gateway>,
+
+    // Trail of the peer keys with access to the device
+    audit_log: Option<AuditLog>,
 }
 
 impl Entities {
@@ -383,6 +551,9 @@ pub struct EventListeners {
     endpoint_upgrade_event_subscriber: chan::Rx<UpgradeRequestChangeEvent>,
     stun_server_subscriber: chan::Rx<Option<StunServer>>,
     post_quantum_subscriber: chan::Rx<telio_pq::Event>,
+    network_availability_subscriber: watch::Receiver<bool>,
+    inbound_connection_subscriber: chan::Rx<(PublicKey, ConnectionProtocol, u16)>,
+    dns_failure_subscriber: chan::Rx<DnsFailure>,
 }
 
 pub struct EventPublishers {
@@ -397,11 +568,17 @@ pub struct EventPublishers {
     /// Used to manually trigger a qos collection
     qos_collection_trigger_publisher: Option<Tx<()>>,
 
+    /// Used to restart nurse once the analytics consent is given again
+    wg_analytics_publisher: Option<Tx<Box<AnalyticsEvent>>>,
+
     // Saved for meshnet entities
     wg_endpoint_publish_event_publisher: chan::Tx<WireGuardEndpointCandidateChangeEvent>,
     endpoint_upgrade_event_subscriber: chan::Tx<UpgradeRequestChangeEvent>,
     stun_server_publisher: chan::Tx<Option<StunServer>>,
     derp_events_publisher: mc_chan::Tx<Box<DerpServer>>,
+
+    /// Used by the DNS resolver to report failed upstream queries to analytics
+    dns_failure_publisher: chan::Tx<DnsFailure>,
 }
 
 // All of the instances and state required to run local DNS resolver for NordNames
@@ -413,6 +590,9 @@ pub struct DNS<D: DnsResolver = LocalDnsResolver> {
     // A file descriptor for virtual host hosting the DNS server within libtelio used for
     // configuring routing on some platforms
     virtual_host_tun_fd: Option<i32>,
+
+    // Domains blocked by the resolver, kept while magic DNS is off so it resumes blocking them
+    blocklist: Option<Arc<Blocklist>>,
 }
 
 struct Runtime {
@@ -457,6 +637,54 @@ struct Runtime {
     /// TODO: This is planned to be refactored into a bit better solution in https://github.com/NordSecurity/libtelio/pull/1021
     last_transmitted_event: HashMap<PublicKey, Node>,
 
+    /// Tracks peers which take too long to connect, if enabled
+    reachability: Option<ReachabilityTracker>,
+
+    /// Remediates meshnet stuck without connected peers, if enabled
+    mesh_watchdog: Option<MeshWatchdog>,
+
+    /// Holds back brief connection drops from node events, if enabled
+    node_debouncer: Option<NodeDebouncer>,
+
+    /// Caps the number of meshnet peers configured on the adapter, if enabled
+    peer_limit: Option<PeerLimit>,
+
+    /// Holds back configs set while the device is unsettled, if enabled
+    config_queue: Option<ConfigQueue>,
+
+    /// Relay server as last reported by the derp client, along with its connection state
+    relay_state: Option<DerpServer>,
+
+    /// Discovers relay and STUN servers through DNS for configs which do not list any, if enabled
+    server_bootstrap: Option<ServerBootstrap>,
+
+    /// Link to the other instance of a high availability pair, if enabled
+    ha: Option<HighAvailability>,
+
+    /// Wake-on-LAN requests of the other nodes, while meshnet is running
+    wake_on_lan_requests: Option<chan::Rx<(PublicKey, WakeOnLanMsg)>>,
+
+    /// Pushes the traffic counters of the peers, if enabled
+    peer_stats: Option<PeerStatsStream>,
+
+    /// Watches the traffic sent to the peers, to activate them on demand if enabled
+    traffic_watcher: Option<Arc<TrafficWatcher>>,
+
+    /// Current and peak memory usage of the subsystems, sampled on every poll
+    memory: MemoryAccounting,
+
+    /// Direct connections of the peers which fell back to the relay
+    downgrades: HashMap<PublicKey, u64>,
+
+    /// Records of the `.nord` zone as last upserted, to tell which names were invalidated
+    dns_records: Records,
+
+    /// Serials of the `.nord` zone and its reverse zones exported without magic DNS
+    dns_zone_versions: ZoneVersions,
+
+    /// Echo requests still being sent to the peers by `ping`
+    pings: HashMap<PublicKey, JoinHandle<()>>,
+
     #[cfg(test)]
     /// MockedAdapter (tests)
     test_env: telio_wg::tests::Env,
@@ -466,7 +694,7 @@ impl Device {
     // Incoming changes should fix
     #[allow(unwrap_check)]
     pub fn new<F: EventCb>(
-        features: Features,
+        mut features: Features,
         event_cb: F,
         protect: Option<Arc<dyn Protector>>,
     ) -> Result<Self> {
@@ -475,9 +703,15 @@ impl Device {
         let version_tag = version_tag();
         let commit_sha = commit_sha();
         telio_log_info!("Created libtelio instance {}, {}", version_tag, commit_sha);
+        telio_log_info!("CPU crypto features: {:?}", CpuFeatures::detect());
 
         telio_log_info!("libtelio is starting up with features : {:?}", features);
 
+        features.map_deprecated();
+        for warning in features.warnings() {
+            telio_log_warn!("{}", warning);
+        }
+
         if let Some(lana) = &features.lana {
             if init_lana(lana.event_path.clone(), version_tag.to_string(), lana.prod).is_err() {
                 telio_log_error!("Failed to initialize lana")
@@ -489,14 +723,22 @@ impl Device {
             .call_once(telio_sockets::protector::platform::setup_network_path_monitor);
 
         let thread_tracker = Arc::new(parking_lot::Mutex::new(ThreadTracker::default()));
+        let worker_priority = features
+            .threads
+            .map(|threads| threads.worker_priority)
+            .unwrap_or_default();
 
         let art = Builder::new_multi_thread()
             .worker_threads(num_cpus::get())
+            .thread_name_fn(threads::worker_thread_name)
             .enable_io()
             .enable_time()
             .on_thread_start({
                 let thread_tracker = thread_tracker.clone();
-                move || thread_tracker.lock().on_thread_start()
+                move || {
+                    threads::set_current_thread_priority(worker_priority);
+                    thread_tracker.lock().on_thread_start()
+                }
             })
             .on_thread_stop({
                 let thread_tracker = thread_tracker.clone();
@@ -540,7 +782,10 @@ impl Device {
             async_runtime: Some(Box::new(art)),
             event: event_tx,
             rt: None,
+            cancel: None,
             protect,
+            unhealthy: AtomicBool::new(false),
+            pending: None,
         })
     }
 
@@ -549,40 +794,300 @@ impl Device {
     }
 
     pub fn external_nodes(&self) -> Result<Vec<Node>> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |s| Ok(s.external_nodes().await)).await?
         })
     }
 
+    /// Relay server the device is connecting or connected to, `None` if meshnet is off
+    pub fn relay_state(&self) -> Result<Option<DerpServer>> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s.relay_state.clone())).await?)
+        })
+    }
+
+    /// Relayed packets dropped because either the relay server or the peers could not keep up,
+    /// counted since meshnet was turned on
+    pub fn relay_dropped_packets(&self) -> Result<DroppedPackets> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| {
+                Ok(match s.entities.meshnet.left() {
+                    Some(meshnet) => meshnet.derp.get_dropped_packets().await,
+                    None => DroppedPackets::default(),
+                })
+            })
+            .await?)
+        })
+    }
+
+    /// Event counters of every peer, collected in a single call so that monitoring agents need
+    /// only one call per scrape regardless of the size of the meshnet
+    pub fn get_peer_counters(&self) -> Result<Vec<PeerCounters>> {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |s| Ok(s.peer_counters().await)).await?
+        })
+    }
+
+    /// Records of the audit log, the oldest first, empty unless the audit log is enabled
+    pub fn get_audit_log(&self) -> Result<Vec<AuditRecord>> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s.audit_records())).await?)
+        })
+    }
+
+    /// Packets the firewall rules would have dropped, counted while in monitor-only mode
+    pub fn get_firewall_monitor_stats(&self) -> Result<MonitorStats> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s
+                .entities
+                .firewall
+                .get_monitor_stats()))
+            .await?)
+        })
+    }
+
+    /// Changes the next consolidation would make to the peers of the adapter, without making
+    /// them, for debugging a config that does not converge
+    pub fn get_wg_peer_plan(&self) -> Result<WgPeerPlan> {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |s| Ok(s.wg_peer_plan().await)).await?
+        })
+    }
+
+    /// Current and peak memory usage of the subsystems
+    pub fn memory_usage(&self) -> Result<Vec<ComponentMemoryUsage>> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s.memory_usage().await)).await?)
+        })
+    }
+
+    /// Features the device runs with, see [Features::effective], with the values ignored at
+    /// runtime, e.g. an out of range DSCP value, turned off
+    ///
+    /// Once started, these also reflect the adjustments made at runtime, e.g. the peer limit
+    /// capped by the adapter or batching turned off when unsupported.
+    pub fn effective_features(&self) -> Result<Features> {
+        if !self.is_running() {
+            return Ok(effective_features(&self.features));
+        }
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s.effective_features())).await?)
+        })
+    }
+
+    /// Current records of the `.nord` zone, or of one of its reverse zones, as zone file text
+    pub fn export_dns_zone(&self, zone: &str) -> Result<Option<String>> {
+        let zone = zone.to_owned();
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s
+                .export_dns_zone(&zone)
+                .await))
+            .await?)
+        })
+    }
+
+    /// Replace the domains blocked by magic DNS with the zlib compressed `list`
+    pub fn set_dns_blocklist(&self, list: Vec<u8>) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_dns_blocklist(&list)
+                .await))
+            .await?
+        })
+    }
+
+    /// Size of the blocklist and the queries it blocked, `None` if no list was loaded
+    pub fn dns_blocklist_stats(&self) -> Result<Option<DnsBlocklistStats>> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |rt| Ok(rt
+                .dns_blocklist_stats()
+                .await))
+            .await?)
+        })
+    }
+
+    /// Endpoint candidates currently known for a meshnet peer, with the outcomes of their
+    /// connectivity checks, for diagnosing why the peer never goes direct
+    ///
+    /// Both lists are empty while direct connections are disabled.
+    pub fn get_peer_candidates(&self, public_key: &PublicKey) -> Result<PeerCandidates> {
+        self.block_on(async {
+            let public_key = *public_key;
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .peer_candidates(public_key)
+                .await))
+            .await?
+        })
+    }
+
+    /// Assess the expected connectivity to a node before it is added to the meshnet
+    pub fn preview_peer_connectivity(
+        &self,
+        public_key: PublicKey,
+        endpoint_hints: Vec<SocketAddr>,
+    ) -> Result<ConnectivityPreview> {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .preview_peer_connectivity(public_key, endpoint_hints)
+                .await))
+            .await?
+        })
+    }
+
+    /// Force the path of a node
+    ///
+    /// Nodes set to `RelayOnly` are never connected directly, regardless of the paths enabled by
+    /// the features. The preference is kept when the node leaves the meshnet.
+    pub fn set_peer_path_preference(
+        &self,
+        public_key: PublicKey,
+        preference: PathPreference,
+    ) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_peer_path_preference(public_key, preference)
+                .boxed()
+                .await))
+            .await?
+        })
+    }
+
+    /// Path forced for the node, `DirectPreferred` unless set otherwise
+    pub fn peer_path_preference(&self, public_key: PublicKey) -> Result<PathPreference> {
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |s| Ok(s
+                .requested_state
+                .peer_path_preference(&public_key)))
+            .await?)
+        })
+    }
+
     pub fn start(&mut self, config: &DeviceConfig) -> Result {
         if self.is_running() {
             return Err(Error::AlreadyStarted);
         }
 
-        self.rt = Some(self.async_runtime()?.block_on(async {
+        // A start or stop which ran out of its time budget before has to complete first
+        if let Some(mut pending) = self.pending.take() {
+            let completed = self.block_on_within_budget(async {
+                let _ = (&mut pending).await;
+                Ok(())
+            });
+            if completed.is_err() {
+                self.pending = Some(pending);
+                return completed;
+            }
+        }
+
+        let cancel = CancellationToken::new();
+        let (event, features, protect) = (
+            self.event.clone(),
+            self.features.clone(),
+            self.protect.clone(),
+        );
+        let runtime_config = config.clone();
+        // Spawned, so that a start which runs out of its time budget is not dropped midway
+        let art = self.async_runtime()?;
+        let mut starting = art.spawn(cancellation_scope(cancel.clone(), async move {
             let t = Task::start(
-                Runtime::start(
-                    self.event.clone(),
-                    config,
-                    self.features.clone(),
-                    self.protect.clone(),
-                )
-                .boxed()
-                .await?,
+                Runtime::start(event, &runtime_config, features, protect)
+                    .boxed()
+                    .await?,
             );
             Ok::<Task<Runtime>, Error>(t)
-        })?);
+        }));
+        let rt = match self.block_on_within_budget(join(&mut starting)) {
+            Ok(rt) => rt,
+            Err(err @ Error::Timeout(_)) => {
+                // The runtime brought up by the start completing in the background is stopped
+                self.pending = Some(self.async_runtime()?.spawn(async move {
+                    if let Ok(Ok(rt)) = starting.await {
+                        let _ = rt.stop().await;
+                    }
+                    cancel.cancel();
+                }));
+                return Err(err);
+            }
+            Err(err) => {
+                // Tasks spawned by a start which failed are not left running
+                cancel.cancel();
+                return Err(err);
+            }
+        };
+        self.rt = Some(rt);
+        self.cancel = Some(cancel);
+        telio_log_info!(
+            "Crypto acceleration: {:?}",
+            crypto_acceleration_report(config.adapter == AdapterType::NepTUN)
+        );
 
         Ok(())
     }
 
+    /// [Linux and Android only] Start the device without creating a tunnel interface
+    ///
+    /// Decrypted traffic is handled by a network stack running inside the process instead of
+    /// the operating system one, so no privileges are needed. Connections to the meshnet nodes
+    /// are made through the returned [Netstack], which follows the meshnet addresses of this
+    /// node. Only the NepTUN adapter supports this mode.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn start_userspace(&mut self, config: &DeviceConfig) -> Result<Arc<Netstack>> {
+        if !matches!(config.adapter, AdapterType::NepTUN) {
+            return Err(Error::AdapterConfig(
+                "userspace mode is supported only by NepTUN".to_owned(),
+            ));
+        }
+        if config.tun.is_some() {
+            return Err(Error::AdapterConfig(
+                "tunnel file descriptor cannot be used in userspace mode".to_owned(),
+            ));
+        }
+
+        let (tun, virtual_tun) = VirtualTun::new().map_err(telio_netstack::Error::from)?;
+        // The descriptor is handed over to the adapter, which closes it, before starting, so
+        // that a failed start can't close it a second time
+        self.start(&DeviceConfig {
+            tun: Some(tun.into_raw_fd()),
+            ..config.clone()
+        })?;
+
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .start_netstack(virtual_tun)
+                .await))
+            .await?
+        })
+    }
+
     pub fn stop(&mut self) {
-        if let Some(rt) = self.rt.take() {
-            if let Some(art) = &self.async_runtime {
-                let _ = art.block_on(rt.stop());
+        // Tasks left running after the runtime stopped are cancelled, so none outlives the device
+        let cancel = self.cancel.take();
+        match (self.rt.take(), self.async_runtime.as_ref()) {
+            (Some(rt), Some(art)) => {
+                // Spawned, so that a stop which runs out of its time budget still completes in
+                // the background, and is waited for by the next start
+                let mut stopping = art.spawn(async move {
+                    let _ = rt.stop().await;
+                    if let Some(cancel) = cancel {
+                        cancel.cancel();
+                    }
+                });
+                let stopped = self.block_on_within_budget(async {
+                    let _ = (&mut stopping).await;
+                    Ok(())
+                });
+                if stopped.is_err() {
+                    self.pending = Some(stopping);
+                }
                 self.flush_events();
             }
+            _ => {
+                if let Some(cancel) = cancel {
+                    cancel.cancel();
+                }
+            }
         }
+        *self.unhealthy.get_mut() = false;
     }
 
     fn flush_events(&self) {
@@ -601,14 +1106,18 @@ impl Device {
     ///
     /// This methods retrieves the UUID of the virtual interface created by device::start() call
     pub fn get_adapter_luid(&mut self) -> u64 {
-        if let Some(art) = &self.async_runtime {
-            let res: Result<u64> = art.block_on(async {
-                task_exec!(self.rt()?, async move |rt| Ok(rt.get_adapter_luid().await)).await?
-            });
-            res.unwrap_or(0)
-        } else {
-            0
-        }
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_adapter_luid().await)).await?
+        })
+        .unwrap_or(0)
+    }
+
+    /// Report of the crypto acceleration, with the tunnel cipher only while NepTUN runs the tunnel
+    pub fn get_crypto_acceleration_report(&self) -> CryptoAccelerationReport {
+        let adapter = self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_adapter_type().await)).await?
+        });
+        crypto_acceleration_report(matches!(adapter, Ok(AdapterType::NepTUN)))
     }
 
     pub fn shutdown_art(&mut self) {
@@ -633,7 +1142,7 @@ impl Device {
     /// not running. E.g. either before start()'ing or after stop()'ing it.
     pub fn set_private_key(&self, private_key: &SecretKey) -> Result {
         let private_key = private_key.clone(); //Going into async context, therefore just copy for lifetimes
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| {
                 Ok(rt.set_private_key(&private_key).boxed().await)
             })
@@ -643,16 +1152,16 @@ impl Device {
 
     /// Retrieves currently configured private key for the interface
     pub fn get_private_key(&self) -> Result<SecretKey> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt.get_private_key().await)).await?
         })
     }
 
-    /// [Linux only] Configure the fwmark used for encapsulated packets
+    /// [Linux only] Configure the fwmark used for encapsulated packets and the relay connection
     #[cfg(any(target_os = "linux", doc))]
     #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
     pub fn set_fwmark(&self, fwmark: u32) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt
                 .set_fwmark(fwmark)
                 .boxed()
@@ -661,6 +1170,23 @@ impl Device {
         })
     }
 
+    /// Change the MTU of the tunnel interface at runtime
+    ///
+    /// Meant for networks dropping large packets, e.g. some hotel Wi-Fi. The MTU is checked
+    /// against the limits of the tunnel, and `MtuChanged` event is emitted once the interface
+    /// and the routes pinning their MTU use it. Adapters not owning their interface return an
+    /// error, the app has to change it. Forwarded TCP connections are clamped to the new MSS
+    /// only by NepTUN with UAPI extensions, otherwise the host has to clamp them.
+    pub fn set_mtu(&self, mtu: u16) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_mtu(mtu)
+                .boxed()
+                .await))
+            .await?
+        })
+    }
+
     #[cfg(not(windows))]
     async fn protect_from_vpn(&self, adapter: &impl WireGuard) -> Result {
         if let Some(protect) = self.protect.as_ref() {
@@ -676,10 +1202,15 @@ impl Device {
 
     /// Configure meshnet
     ///
-    /// This method sets the desired meshnet configuration
+    /// This method sets the desired meshnet configuration. With the `config_queue` feature the
+    /// config is applied once the device settles, see `submit_config`. `None` disables meshnet,
+    /// removing the named namespaces as well.
     pub fn set_config(&self, config: &Option<Config>) -> Result {
+        if self.features.config_queue.is_some() {
+            return self.submit_config(config)?.wait();
+        }
         let config = config.clone();
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt
                 .set_config(&config)
                 .boxed()
@@ -688,39 +1219,133 @@ impl Device {
         })
     }
 
+    /// Submit the meshnet configuration without waiting for it to be applied
+    ///
+    /// With the `config_queue` feature configs submitted during a network change or a relay
+    /// reconnect are held back, and only the latest one is applied once the device settles.
+    /// The returned handle completes with the outcome of the config which was applied.
+    pub fn submit_config(&self, config: &Option<Config>) -> Result<ConfigHandle> {
+        let config = config.clone();
+        let (tx, rx) = oneshot::channel();
+        self.block_on(async {
+            Ok(task_exec!(self.rt()?, async move |rt| {
+                rt.submit_config(config, tx).boxed().await;
+                Ok(())
+            })
+            .await?)
+        })?;
+        let budget = match self.features.api_timeout {
+            Some(api_timeout) => Some((
+                self.async_runtime()?.handle().clone(),
+                Duration::from_millis(api_timeout.timeout_ms),
+            )),
+            None => None,
+        };
+        Ok(ConfigHandle { rx, budget })
+    }
+
+    /// Submit the meshnet configuration, calling `on_applied` with the outcome of the config
+    /// which was applied, see `submit_config`
+    pub fn submit_config_with_callback(
+        &self,
+        config: &Option<Config>,
+        on_applied: impl FnOnce(Result) + Send + 'static,
+    ) -> Result {
+        let handle = self.submit_config(config)?;
+        // The handle completes once the device is stopped at the latest
+        self.async_runtime()?
+            .spawn_blocking(move || on_applied(handle.wait_unbounded()));
+        Ok(())
+    }
+
+    /// Configure a named meshnet namespace
+    ///
+    /// Namespaces are applied alongside the meshnet set by `set_config` over the same adapter,
+    /// with peers capped by the `policy` and served in the `<namespace>.nord` DNS zone.
+    /// `None` removes the namespace.
+    pub fn set_namespace_config(
+        &self,
+        namespace: &str,
+        config: &Option<Config>,
+        policy: MeshnetNamespacePolicy,
+    ) -> Result {
+        let namespace = namespace.to_owned();
+        let config = config.clone();
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_namespace_config(&namespace, &config, policy)
+                .boxed()
+                .await))
+            .await?
+        })
+    }
+
     /// Notify device about network change event
     ///
     /// In some cases integrators may have better knowledge of the network state or state changes,
     /// therefore this method assumes, that a device has migrated from one network to the other,
-    /// and adapts whatever is needed
-    pub fn notify_network_change(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+    /// and adapts whatever is needed. The `network_info` describes the new network.
+    pub fn notify_network_change(&self, network_info: NetworkInfo) -> Result {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.notify_network_change().await)
+                Ok(rt.notify_network_change(network_info).await)
             })
             .await?
         })
     }
 
     pub fn notify_sleep(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt.notify_sleep().await)).await?
         })
     }
 
     pub fn notify_wakeup(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt.notify_wakeup().await)).await?
         })
     }
 
+    /// Connect the relay again if it was closed for being idle, e.g. on a push notification
+    pub fn wake_relay(&self) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                if let Some(meshnet_entities) = rt.entities.meshnet.left() {
+                    meshnet_entities.derp.wake().await;
+                }
+                Ok(())
+            })
+            .await?;
+            Ok(())
+        })
+    }
+
+    /// Suspend all background activity, e.g. for OS sleep states or doze mode on Android
+    ///
+    /// Probing, analytics and keepalives are stopped, while the adapter stays configured and the
+    /// relay connected, so peers can still reach the device. Pending analytics are saved first.
+    pub fn suspend(&self) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.suspend().boxed().await)).await?
+        })
+    }
+
+    /// Resume the background activity stopped by [Device::suspend]
+    ///
+    /// The connections are resynchronized at once, as after a network change.
+    pub fn resume(&self) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.resume().boxed().await)).await?
+        })
+    }
+
     /// Connect to exit node
     ///
     /// Exit node in this case may be the VPN server or another meshnet node. In the former case,
     /// new node is created and WireGuard tunnel is established to that node. In the latter case
     /// the specified (matched by public key) meshnet node is "promoted" to be the exit node
     pub fn connect_exit_node(&self, node: &ExitNode) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             let node = node.clone();
             let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
                 rt.connect_exit_node(&node).boxed().await?;
@@ -736,6 +1361,60 @@ impl Device {
         })
     }
 
+    /// Switch the started device to another adapter, e.g. to roll back a backend misbehaving on
+    /// some OS versions
+    ///
+    /// Keys, peers and their endpoints are carried over, traffic is only interrupted until the
+    /// new adapter takes over and handshakes again. The previous adapter is restored if the new
+    /// one fails to start. Not supported on a tunnel supplied by the app.
+    pub fn switch_adapter(&self, adapter: AdapterType) -> Result {
+        self.block_on(async {
+            let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
+                rt.switch_adapter(adapter).boxed().await?;
+                Ok(rt.entities.wireguard_interface.clone())
+            })
+            .await?;
+
+            // Sockets of the new adapter are not protected yet
+            #[cfg(not(windows))]
+            self.protect_from_vpn(&*_wireguard_interface).await?;
+
+            Ok(())
+        })
+    }
+
+    /// Ping a meshnet peer through the tunnel, e.g. to troubleshoot connectivity from the app
+    ///
+    /// Returns once the probes are scheduled, `count` echo requests are then sent to the meshnet
+    /// IP of the peer every `interval`, each reported with a `PingProbe` event. The requests are
+    /// sent by the userspace network stack, so the device has to run in userspace mode. Pinging
+    /// the peer again replaces the probes still being sent, stopping the device stops them all.
+    pub fn ping(&self, public_key: &PublicKey, count: u32, interval: Duration) -> Result {
+        self.block_on(async {
+            let public_key = *public_key;
+            task_exec!(self.rt()?, async move |rt| Ok(
+                rt.ping(public_key, count, interval)
+            ))
+            .await?
+        })
+    }
+
+    /// Wake up a sleeping meshnet node
+    ///
+    /// The request is sent to the nodes advertising the Wake-on-LAN capability, which broadcast
+    /// the magic packet to their LAN on our behalf. The target must advertise its hardware
+    /// address in the meshnet config, and we must be allowed to access the local network of the
+    /// relaying nodes.
+    pub fn wake_peer(&self, public_key: &PublicKey) -> Result {
+        self.block_on(async {
+            let public_key = *public_key;
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .wake_peer(public_key)
+                .await))
+            .await?
+        })
+    }
+
     /// Connect to exit node with post-quantum secure tunnel
     ///
     /// Exit node in this case may only be the VPN server.
@@ -743,7 +1422,7 @@ impl Device {
     /// Meshnet is disallowed when forming a post-quantum tunnel and if it's enabled
     /// this call will error out.
     pub fn connect_vpn_post_quantum(&self, node: &ExitNode) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             let node = node.clone();
             let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
                 rt.connect_exit_node_pq(&node).boxed().await?;
@@ -763,7 +1442,7 @@ impl Device {
     ///
     /// Undoes the effects of calling device::connect_exit_node(), matching the node by public key
     pub fn disconnect_exit_node(&self, node_key: &PublicKey) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             let node_key = *node_key;
             task_exec!(self.rt()?, async move |rt| {
                 Ok(rt.disconnect_exit_node(&node_key).boxed().await)
@@ -776,7 +1455,7 @@ impl Device {
     /// Disconnects from any VPN and/or demotes any meshnet node to be a regular meshnet node
     /// instead of exit node
     pub fn disconnect_exit_nodes(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| {
                 Ok(rt.disconnect_exit_nodes().boxed().await)
             })
@@ -785,15 +1464,105 @@ impl Device {
         })
     }
 
-    fn rt(&self) -> Result<&Task<Runtime>> {
-        self.rt.as_ref().ok_or(Error::NotStarted)
-    }
-
-    fn async_runtime(&self) -> Result<&AsyncRuntime> {
-        match self.async_runtime.as_ref() {
-            Some(art) => Ok(art),
-            None => Err(Error::NotStarted),
-        }
+    /// Stage a VPN server while connected to another one, so that switching to it is quick
+    ///
+    /// The server is configured on the adapter without any allowed IPs, so no traffic is routed
+    /// through it until device::connect_exit_node() is called with its key. With `handshake`, the
+    /// session with the server is also established and kept up ahead of the switch. Staging
+    /// another server replaces the previous one.
+    pub fn prewarm_exit_node(
+        &self,
+        public_key: PublicKey,
+        endpoint: SocketAddr,
+        handshake: bool,
+    ) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt
+                    .prewarm_exit_node(Some(PrewarmedExitNode {
+                        public_key,
+                        endpoint,
+                        handshake,
+                    }))
+                    .boxed()
+                    .await)
+            })
+            .await?
+        })
+    }
+
+    /// Remove the VPN server staged by device::prewarm_exit_node(), if not connected to already
+    pub fn cancel_exit_node_prewarm(&self) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.prewarm_exit_node(None).boxed().await)
+            })
+            .await?
+        })
+    }
+
+    fn rt(&self) -> Result<&Task<Runtime>> {
+        self.rt.as_ref().ok_or(Error::NotStarted)
+    }
+
+    fn async_runtime(&self) -> Result<&AsyncRuntime> {
+        match self.async_runtime.as_ref() {
+            Some(art) => Ok(art),
+            None => Err(Error::NotStarted),
+        }
+    }
+
+    /// Block on a call into the runtime, within the time budget of the `api_timeout` feature
+    ///
+    /// With `fail_fast` the call is refused right away while the runtime is not responding.
+    fn block_on<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let art = self.async_runtime()?;
+        let fail_fast = self
+            .features
+            .api_timeout
+            .is_some_and(|api_timeout| api_timeout.fail_fast);
+
+        if fail_fast && self.unhealthy.load(Ordering::Relaxed) {
+            let probe = art.block_on(async {
+                tokio::time::timeout(HEALTH_PROBE_TIMEOUT, async {
+                    task_exec!(self.rt()?, async move |_rt| Ok(())).await?;
+                    Ok::<(), Error>(())
+                })
+                .await
+            });
+            if !matches!(probe, Ok(Ok(()))) {
+                return Err(Error::RuntimeUnhealthy);
+            }
+        }
+
+        self.block_on_within_budget(call)
+    }
+
+    /// Block on the call within the time budget of the `api_timeout` feature
+    ///
+    /// A call which timed out while still queued for the runtime is withdrawn and never runs.
+    /// One which the runtime already started is left to complete in the background, so its
+    /// effects may still show up after the caller got `Error::Timeout`. The future given here is
+    /// dropped on timeout, so work which is not run by the runtime task, like starting and
+    /// stopping it, is spawned and only its handle is awaited here.
+    fn block_on_within_budget<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let art = self.async_runtime()?;
+        let Some(api_timeout) = self.features.api_timeout else {
+            return art.block_on(call);
+        };
+
+        let timeout = Duration::from_millis(api_timeout.timeout_ms);
+        match art.block_on(tokio::time::timeout(timeout, call)) {
+            Ok(result) => {
+                self.unhealthy.store(false, Ordering::Relaxed);
+                result
+            }
+            Err(_) => {
+                telio_log_warn!("Device call timed out after {timeout:?}");
+                self.unhealthy.store(true, Ordering::Relaxed);
+                Err(Error::Timeout(timeout))
+            }
+        }
     }
 
     /// Enables DNS server
@@ -802,7 +1571,7 @@ impl Device {
     /// of the meshnet nodes. If the DNS query sent to this server does not fall under .nord
     /// top-level-domain, the query is forwarded to one of the `upstream_servers`.
     pub fn enable_magic_dns(&self, upstream_servers: &[IpAddr]) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             let upstream_servers = upstream_servers.to_vec();
             task_exec!(self.rt()?, async move |rt| {
                 Ok(rt.start_dns(&upstream_servers).boxed().await)
@@ -815,7 +1584,7 @@ impl Device {
     ///
     /// Undoes the effects of `device::enable_magic_dns()` call
     pub fn disable_magic_dns(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt.stop_dns().boxed().await)).await?
         })
     }
@@ -824,27 +1593,28 @@ impl Device {
     ///
     /// Used only for testing purposes
     pub fn _panic(&self) -> Result {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt._panic().await)).await?
         })
     }
 
     /// Retrieves a reference to SocketPool. Use this instead of SocketPool::default() when possible
     pub fn get_socket_pool(&self) -> Result<Arc<SocketPool>> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt.get_socket_pool().await)).await?
         })
     }
 
     pub fn get_nat(&self, skt: SocketAddr) -> Result<NatData> {
-        match self.async_runtime()?.block_on(retrieve_single_nat(skt)) {
-            Ok(data) => Ok(data),
-            Err(no_data) => Err(Error::FailedNatInfoRecover(no_data)),
-        }
+        self.block_on(async {
+            retrieve_single_nat(skt)
+                .await
+                .map_err(Error::FailedNatInfoRecover)
+        })
     }
 
     pub fn trigger_analytics_event(&self) -> Result<()> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt
                 .trigger_analytics_event()
                 .await))
@@ -853,7 +1623,7 @@ impl Device {
     }
 
     pub fn trigger_qos_collection(&self) -> Result<()> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt
                 .trigger_qos_collection()
                 .await))
@@ -861,8 +1631,18 @@ impl Device {
         })
     }
 
+    /// Grant or revoke the consent to collect analytics
+    pub fn set_analytics_consent(&self, consent: bool) -> Result {
+        self.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_analytics_consent(consent)
+                .await))
+            .await?
+        })
+    }
+
     pub fn receive_ping(&self) -> Result<String> {
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(
                 Box::pin(rt.receive_ping()).await
             ))
@@ -875,7 +1655,7 @@ impl Device {
     pub fn probe_pmtu(&self, host: IpAddr) -> Result<u32> {
         use std::os::fd::AsRawFd;
 
-        self.async_runtime()?.block_on(async {
+        self.block_on(async {
             let sock = telio_pmtu::PMTUSocket::new(
                 host,
                 Duration::from_secs(
@@ -909,6 +1689,52 @@ impl Device {
     }
 }
 
+/// Outcome of a call spawned on the async runtime, a panic of the call is resumed here
+async fn join<T>(handle: &mut JoinHandle<Result<T>>) -> Result<T> {
+    match handle.await {
+        Ok(result) => result,
+        Err(err) => match err.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            // Spawned calls are cancelled only by the async runtime shutting down
+            Err(_) => Err(Error::NotStarted),
+        },
+    }
+}
+
+/// Start nurse if it is configured and lana is initialized
+async fn start_nurse(
+    features: &Features,
+    public_key: PublicKey,
+    nurse_io: NurseIo<'_>,
+    aggregator: Arc<ConnectivityDataAggregator>,
+) -> Option<Arc<Nurse>> {
+    if !telio_lana::is_lana_initialized() {
+        telio_log_debug!("lana not initialized");
+        return None;
+    }
+    let Some(nurse_features) = &features.nurse else {
+        telio_log_debug!("nurse not configured");
+        return None;
+    };
+
+    let nurse_config = NurseConfig::new(nurse_features);
+    telio_log_debug!(
+        "Nurse config heartbeat fp: {}",
+        nurse_config.heartbeat_config.fingerprint
+    );
+
+    Some(Arc::new(
+        Nurse::start_with(
+            public_key,
+            nurse_config,
+            nurse_io,
+            aggregator,
+            features.ipv6,
+        )
+        .await,
+    ))
+}
+
 impl Drop for Device {
     fn drop(&mut self) {
         if self.features.lana.is_some() {
@@ -917,7 +1743,140 @@ impl Drop for Device {
     }
 }
 
+/// Wait for the next event of the other instance, forever if high availability is off
+async fn high_availability_event(ha: &mut Option<HighAvailability>) -> HaEvent {
+    match ha {
+        Some(ha) => ha.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn traffic_woken(watcher: &Option<Arc<TrafficWatcher>>) {
+    match watcher {
+        Some(watcher) => watcher.woken().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn peer_stats_tick(stream: &mut Option<PeerStatsStream>) {
+    match stream {
+        Some(stream) => stream.tick().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn wake_on_lan_request(
+    requests: &mut Option<chan::Rx<(PublicKey, WakeOnLanMsg)>>,
+) -> Option<(PublicKey, WakeOnLanMsg)> {
+    match requests {
+        Some(requests) => requests.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait for the bootstrapped server list to change, forever if there is no bootstrap
+async fn bootstrapped_servers_changed(
+    bootstrap: &mut Option<ServerBootstrap>,
+) -> Option<Vec<DerpServer>> {
+    match bootstrap {
+        Some(bootstrap) => bootstrap.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Features as they take effect, an out of range DSCP value is rejected by the sockets
+fn effective_features(features: &Features) -> Features {
+    let mut features = features.effective();
+    if features.dscp.is_some_and(|dscp| dscp.value > MAX_DSCP) {
+        features.dscp = None;
+    }
+    features
+}
+
+/// Configs differing only in the order of their peers or relay servers are the same
+fn is_same_meshnet_config(a: Option<&Config>, b: Option<&Config>) -> bool {
+    let normalize = |config: &Config| {
+        let mut config = config.clone();
+        if let Some(peers) = config.peers.as_mut() {
+            peers.sort_by_key(|peer| peer.public_key);
+        }
+        if let Some(servers) = config.derp_servers.as_mut() {
+            servers.sort_by_key(|server| server.public_key);
+        }
+        config
+    };
+    match (a, b) {
+        (Some(a), Some(b)) => normalize(a) == normalize(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Find nodes which got a new public key while keeping their meshnet addresses
+fn find_rekeyed_peers(old: Option<&Config>, new: Option<&Config>) -> Vec<PeerRekeyed> {
+    let (Some(old_peers), Some(new_peers)) = (
+        old.and_then(|c| c.peers.as_ref()),
+        new.and_then(|c| c.peers.as_ref()),
+    ) else {
+        return Vec::new();
+    };
+    let old_keys: HashSet<PublicKey> = old_peers.iter().map(|p| p.public_key).collect();
+    let new_keys: HashSet<PublicKey> = new_peers.iter().map(|p| p.public_key).collect();
+
+    new_peers
+        .iter()
+        .filter(|new_peer| !old_keys.contains(&new_peer.public_key))
+        .filter_map(|new_peer| {
+            let ips = new_peer.ip_addresses.as_ref()?;
+            let old_peer = old_peers.iter().find(|old_peer| {
+                !new_keys.contains(&old_peer.public_key)
+                    && old_peer
+                        .ip_addresses
+                        .as_ref()
+                        .map_or(false, |old_ips| old_ips.iter().any(|ip| ips.contains(ip)))
+            })?;
+            Some(PeerRekeyed {
+                identifier: new_peer.identifier.clone(),
+                old_public_key: old_peer.public_key,
+                new_public_key: new_peer.public_key,
+                ip_addresses: ips.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Names which no longer resolve or resolve to other addresses, wildcards are left out as the
+/// caches only hold the names actually queried
+fn invalidated_dns_names(old: &Records, new: &Records) -> Vec<String> {
+    let mut names: Vec<String> = old
+        .iter()
+        .filter(|(name, ips)| !name.starts_with("*.") && new.get(*name) != Some(*ips))
+        .map(|(name, _)| name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
 impl RequestedState {
+    // Path forced for the node, direct connections are attempted unless it is forced to relay
+    pub fn peer_path_preference(&self, public_key: &PublicKey) -> PathPreference {
+        if self.relay_only_peers.contains(public_key) {
+            PathPreference::RelayOnly
+        } else {
+            PathPreference::DirectPreferred
+        }
+    }
+
+    // The requested meshnet config without the nodes forced to relay, for the components
+    // negotiating direct connections
+    pub fn direct_meshnet_config(&self) -> Option<Config> {
+        let mut config = self.meshnet_config.clone()?;
+        if let Some(peers) = config.peers.as_mut() {
+            peers.retain(|peer| !self.relay_only_peers.contains(&peer.public_key));
+        }
+        Some(config)
+    }
+
     // A Convenience function to build a DNS records list from the requested meshnet config
     // This function does not take into account whether DNS is enabled or not. It simply builds a
     // list of hostname<->IP pairs out of currently requested meshnet nodes. If meshnet is disabled,
@@ -944,6 +1903,18 @@ impl RequestedState {
             .collect()
     }
 
+    // Hostname and, if enabled, nickname records of all meshnet nodes. Hostnames have
+    // priority over nicknames (override if there's any conflict).
+    pub fn collect_meshnet_records(&self, nicknames: bool) -> Records {
+        let mut records = if nicknames {
+            self.collect_dns_nickname_records()
+        } else {
+            Records::new()
+        };
+        records.extend(self.collect_dns_records());
+        records
+    }
+
     // Same as collect_dns_records() fn but for peers with defined nicknames.
     pub fn collect_dns_nickname_records(&self) -> Records {
         let to_record = |p: &PeerBase| {
@@ -1047,15 +2018,44 @@ impl Runtime {
         features: Features,
         protect: Option<Arc<dyn Protector>>,
     ) -> Result<Self> {
-        let firewall = Arc::new(StatefullFirewall::new(features.ipv6, features.firewall));
+        let inbound_connections = Chan::default();
+        let mut firewall = StatefullFirewall::new(features.ipv6, features.firewall);
+        if features.firewall.connection_notifications {
+            let tx = inbound_connections.tx;
+            firewall =
+                firewall.with_inbound_connection_callback(Box::new(move |peer, protocol, port| {
+                    // Called for every new connection on the packet path, must not block
+                    let _ = tx.try_send((peer, protocol, port));
+                }));
+        }
+        let firewall = Arc::new(firewall);
 
+        let datapath_priority = features
+            .threads
+            .map(|threads| threads.datapath_priority)
+            .unwrap_or_default();
         let firewall_filter_inbound_packets = {
             let fw = firewall.clone();
-            move |peer: &[u8; 32], packet: &[u8]| fw.process_inbound_packet(peer, packet)
+            move |peer: &[u8; 32], packet: &[u8]| {
+                threads::set_datapath_thread_priority(datapath_priority);
+                !chaos::packet_dropped(peer) && fw.process_inbound_packet(peer, packet)
+            }
         };
+        let traffic_watcher = features.on_demand.map(|f| {
+            Arc::new(TrafficWatcher::new(Duration::from_secs(
+                f.idle_timeout_s.into(),
+            )))
+        });
         let firewall_filter_outbound_packets = {
             let fw = firewall.clone();
-            move |peer: &[u8; 32], packet: &[u8]| fw.process_outbound_packet(peer, packet)
+            let watcher = traffic_watcher.clone();
+            move |peer: &[u8; 32], packet: &[u8]| {
+                threads::set_datapath_thread_priority(datapath_priority);
+                if let Some(watcher) = &watcher {
+                    watcher.observe(peer);
+                }
+                !chaos::packet_dropped(peer) && fw.process_outbound_packet(peer, packet)
+            }
         };
         let firewall_reset_connections = if features.firewall.neptun_reset_conns {
             let fw = firewall.clone();
@@ -1083,6 +2083,14 @@ impl Runtime {
                 )?)
             }
         });
+        // Relay and other external connections share the fwmark of the WireGuard socket
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = config.fwmark {
+            socket_pool.set_fwmark(fwmark);
+        }
+        if let Some(dscp) = &features.dscp {
+            socket_pool.set_dscp(dscp.value);
+        }
 
         let derp_events = McChan::default();
 
@@ -1121,6 +2129,11 @@ impl Runtime {
                             firewall_filter_outbound_packets,
                         )),
                         firewall_reset_connections,
+                        obfuscation: features.obfuscation,
+                        handshake_load: features.wireguard.handshake_load,
+                        uapi_socket: features.wireguard.uapi_socket.clone(),
+                        recovery: features.wireguard.recovery,
+                        handshake_events: features.wireguard.handshake_events,
                     },
                     features.link_detection,
                     features.ipv6,
@@ -1145,6 +2158,11 @@ impl Runtime {
                                 firewall_filter_outbound_packets,
                             )),
                             firewall_reset_connections,
+                            obfuscation: features.obfuscation,
+                            handshake_load: features.wireguard.handshake_load,
+                            uapi_socket: features.wireguard.uapi_socket.clone(),
+                            recovery: features.wireguard.recovery,
+                            handshake_events: features.wireguard.handshake_events,
                         }
                     ).await;
 
@@ -1171,39 +2189,19 @@ impl Runtime {
             config.private_key.public(),
         ));
 
-        let nurse = if telio_lana::is_lana_initialized() {
-            if let Some(nurse_features) = &features.nurse {
-                let nurse_io = NurseIo {
-                    wg_event_channel: &libtelio_wide_event_publisher,
-                    wg_analytics_channel: analytics_ch.clone(),
-                    config_update_channel: config_update_ch.clone(),
-                    collection_trigger_channel: collection_trigger_ch.clone(),
-                    qos_trigger_channel: qos_trigger_ch.clone(),
-                };
-                let nurse_config = NurseConfig::new(nurse_features);
-                telio_log_debug!(
-                    "Nurse config heartbeat fp: {}",
-                    nurse_config.heartbeat_config.fingerprint
-                );
-
-                Some(Arc::new(
-                    Nurse::start_with(
-                        config.private_key.public(),
-                        nurse_config,
-                        nurse_io,
-                        aggregator.clone(),
-                        features.ipv6,
-                    )
-                    .await,
-                ))
-            } else {
-                telio_log_debug!("nurse not configured");
-                None
-            }
-        } else {
-            telio_log_debug!("lana not initialized");
-            None
-        };
+        let nurse = start_nurse(
+            &features,
+            config.private_key.public(),
+            NurseIo {
+                wg_event_channel: &libtelio_wide_event_publisher,
+                wg_analytics_channel: analytics_ch.clone(),
+                config_update_channel: config_update_ch.clone(),
+                collection_trigger_channel: collection_trigger_ch.clone(),
+                qos_trigger_channel: qos_trigger_ch.clone(),
+            },
+            aggregator.clone(),
+        )
+        .await;
 
         #[cfg(windows)]
         {
@@ -1225,11 +2223,13 @@ impl Runtime {
             virtual_host_tun_fd: config.tun,
             #[cfg(windows)]
             virtual_host_tun_fd: None,
+            blocklist: None,
         }));
 
         let wg_endpoint_publish_events = Chan::default();
         let wg_upgrade_sync = Chan::default();
         let stun_server_events = Chan::default();
+        let dns_failures = Chan::default();
 
         let post_quantum = Chan::default();
 
@@ -1239,7 +2239,46 @@ impl Runtime {
             post_quantum.tx,
         );
 
-        let polling_interval = interval(Duration::from_secs(5));
+        let polling_interval = coalesced_interval(Duration::from_secs(5));
+        let reachability = features
+            .peer_unreachable
+            .map(|f| ReachabilityTracker::new(Duration::from_secs(f.connection_timeout_s.into())));
+        let mesh_watchdog = features.mesh_watchdog.map(|f| {
+            MeshWatchdog::new(
+                Duration::from_secs(f.stuck_timeout_s.into()),
+                Duration::from_secs(f.step_interval_s.into()),
+            )
+        });
+        let node_debouncer = features
+            .node_event_debounce
+            .map(|f| NodeDebouncer::new(Duration::from_millis(f.hold_ms)));
+        let peer_limit = features.peer_limit.map(PeerLimit::new);
+        let config_queue = features.config_queue.map(|f| {
+            ConfigQueue::new(
+                Duration::from_millis(f.settle_ms),
+                Duration::from_millis(f.max_delay_ms),
+            )
+        });
+        let peer_stats = features.peer_stats.map(|f| {
+            PeerStatsStream::new(
+                Duration::from_secs(f.interval_s.into()),
+                f.full_report_every,
+            )
+        });
+
+        let server_bootstrap = features
+            .derp
+            .as_ref()
+            .and_then(|derp| derp.server_bootstrap.as_ref())
+            .and_then(
+                |cfg| match ServerBootstrap::start(cfg, socket_pool.clone()) {
+                    Ok(bootstrap) => Some(bootstrap),
+                    Err(e) => {
+                        telio_log_warn!("Server bootstrap disabled: {}", e);
+                        None
+                    }
+                },
+            );
 
         let pmtu_detection = features.pmtu_discovery.map(|cfg| {
             telio_pmtu::Entity::new(
@@ -1248,6 +2287,21 @@ impl Runtime {
             )
         });
 
+        let ha = match features.high_availability.as_ref() {
+            Some(cfg) => match HighAvailability::start(cfg, &socket_pool).await {
+                Ok(ha) => Some(ha),
+                Err(e) => {
+                    telio_log_warn!("High availability disabled: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let audit_log = features
+            .audit_log
+            .map(|feature| AuditLog::new(feature, libtelio_wide_event_publisher.clone()));
+
         Ok(Runtime {
             features,
             requested_state,
@@ -1262,6 +2316,11 @@ impl Runtime {
                 postquantum_wg,
                 pmtu_detection,
                 network_monitor,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                netstack: None,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                socks5: None,
+                audit_log,
             },
             event_listeners: EventListeners {
                 wg_endpoint_publish_event_subscriber: wg_endpoint_publish_events.rx,
@@ -1270,19 +2329,40 @@ impl Runtime {
                 endpoint_upgrade_event_subscriber: wg_upgrade_sync.rx,
                 stun_server_subscriber: stun_server_events.rx,
                 post_quantum_subscriber: post_quantum.rx,
+                network_availability_subscriber: NETWORK_AVAILABILITY.subscribe(),
+                inbound_connection_subscriber: inbound_connections.rx,
+                dns_failure_subscriber: dns_failures.rx,
             },
             event_publishers: EventPublishers {
                 libtelio_event_publisher: libtelio_wide_event_publisher,
                 nurse_config_update_publisher: config_update_ch,
                 nurse_collection_trigger_publisher: collection_trigger_ch,
                 qos_collection_trigger_publisher: qos_trigger_ch,
+                wg_analytics_publisher: analytics_ch.clone(),
                 wg_endpoint_publish_event_publisher: wg_endpoint_publish_events.tx,
                 endpoint_upgrade_event_subscriber: wg_upgrade_sync.tx,
                 stun_server_publisher: stun_server_events.tx,
                 derp_events_publisher: derp_events.tx,
+                dns_failure_publisher: dns_failures.tx,
             },
             polling_interval,
             last_transmitted_event: Default::default(),
+            reachability,
+            mesh_watchdog,
+            node_debouncer,
+            peer_limit,
+            config_queue,
+            relay_state: None,
+            server_bootstrap,
+            ha,
+            wake_on_lan_requests: None,
+            peer_stats,
+            traffic_watcher,
+            memory: MemoryAccounting::default(),
+            downgrades: HashMap::new(),
+            dns_records: Records::new(),
+            dns_zone_versions: ZoneVersions::default(),
+            pings: HashMap::new(),
             #[cfg(test)]
             test_env: wg::tests::Env {
                 analytics: analytics_ch,
@@ -1306,6 +2386,9 @@ impl Runtime {
             relay: multiplexer.get_channel().await?,
         }));
 
+        let wake_on_lan: Chan<(PublicKey, WakeOnLanMsg)> = multiplexer.get_channel().await?;
+        self.wake_on_lan_requests = Some(wake_on_lan.rx);
+
         // Start Derp client
         let derp = Arc::new(DerpRelay::start_with(
             derp_multiplexer_chan,
@@ -1405,6 +2488,7 @@ impl Runtime {
                         .endpoint_providers_optimization
                         .unwrap_or_default()
                         .optimize_direct_upgrade_stun,
+                    direct.dual_stack,
                 )?);
                 endpoint_providers.push(ep.clone());
                 Some(ep)
@@ -1446,6 +2530,9 @@ impl Runtime {
 
             // Subscribe to endpoint providers' events
             for endpoint_provider in &endpoint_providers {
+                endpoint_provider
+                    .set_network_available(is_network_available())
+                    .await;
                 endpoint_provider
                     .subscribe_for_endpoint_candidates_change_events(
                         endpoint_publish_events.tx.clone(),
@@ -1477,12 +2564,14 @@ impl Runtime {
                         .wg_endpoint_publish_event_publisher
                         .clone(),
                     intercoms: multiplexer.get_channel().await?,
+                    punch_requests: multiplexer.get_channel().await?,
                 },
                 endpoint_providers.clone(),
                 last_rx_time_provider.clone(),
                 Duration::from_secs(2),
                 ping_pong_tracker,
                 Default::default(),
+                direct.coordinated_punch.clone(),
             ));
 
             // Create WireGuard connection upgrade synchronizer
@@ -1521,6 +2610,7 @@ impl Runtime {
             direct,
             starcast,
             session_keeper,
+            wake_on_lan: wake_on_lan.tx,
         })
     }
 
@@ -1569,30 +2659,95 @@ impl Runtime {
         }))
     }
 
-    async fn upsert_dns_peers(&self) -> Result {
-        if let Some(dns) = &self.entities.dns.lock().await.resolver {
-            let mut peers: Records = HashMap::new();
-            // Hostnames have priority over nicknames (override if there's any conflict)
-            if self.features.nicknames {
-                peers = self.requested_state.collect_dns_nickname_records();
-            }
-            peers.extend(self.requested_state.collect_dns_records());
+    fn dns_zone_records(&self) -> Records {
+        let mut peers = self
+            .requested_state
+            .collect_meshnet_records(self.features.nicknames);
 
-            // Insert wildcard for subdomains
-            let wildcarded_peers: Records = peers
-                .iter()
-                .map(|(name, ip)| (format!("*.{}", name), ip.clone()))
-                .collect();
-            peers.extend(wildcarded_peers);
+        // Insert wildcard for subdomains
+        let wildcarded_peers: Records = peers
+            .iter()
+            .map(|(name, ip)| (format!("*.{}", name), ip.clone()))
+            .collect();
+        peers.extend(wildcarded_peers);
+        peers
+    }
 
-            dns.upsert("nord", &peers, self.features.dns.ttl_value)
+    async fn upsert_dns_peers(&mut self) -> Result {
+        let records = self.dns_zone_records();
+        if let Some(dns) = &self.entities.dns.lock().await.resolver {
+            dns.upsert("nord", &records, self.features.dns.ttl_value)
                 .await
                 .map_err(Error::DnsResolverError)?;
+
+            // The local zone is replaced right away, but the OS may still cache the old records
+            let names = invalidated_dns_names(&self.dns_records, &records);
+            if !names.is_empty() {
+                telio_log_debug!("Invalidated DNS records of {names:?}");
+                let body = DnsRecordsInvalidated { names };
+                let _ = self
+                    .event_publishers
+                    .libtelio_event_publisher
+                    .send(Box::new(Event::DnsRecordsInvalidated { body }));
+            }
+        }
+        self.dns_records = records;
+
+        Ok(())
+    }
+
+    fn effective_features(&self) -> Features {
+        effective_features(&self.features)
+    }
+
+    /// Zone served by the resolver if magic DNS is on, otherwise the one it would serve
+    async fn export_dns_zone(&mut self, zone: &str) -> Option<String> {
+        if let Some(dns) = &self.entities.dns.lock().await.resolver {
+            if let Some(text) = dns.export_zone(zone).await {
+                return Some(text);
+            }
+        }
+
+        let records = self.dns_zone_records();
+        if let Err(err) =
+            self.dns_zone_versions
+                .update("nord", &records, self.features.dns.ttl_value)
+        {
+            telio_log_warn!("Failed to update the exported .nord zone: {err}");
+            return None;
         }
+        self.dns_zone_versions
+            .export(zone, self.features.dns.soa.as_ref())
+    }
+
+    async fn set_dns_blocklist(&mut self, list: &[u8]) -> Result {
+        let Some(feature) = &self.features.dns.blocklist else {
+            return Err(Error::DnsResolverError(
+                "DNS blocklist is not enabled".to_owned(),
+            ));
+        };
+        let blocklist = Arc::new(
+            Blocklist::from_compressed(list, feature.sinkhole_ips.clone())
+                .map_err(Error::DnsResolverError)?,
+        );
+        telio_log_info!("Loaded DNS blocklist: {:?}", blocklist.stats());
 
+        let mut dns_entity = self.entities.dns.lock().await;
+        if let Some(dns) = &dns_entity.resolver {
+            dns.set_blocklist(Some(blocklist.clone())).await;
+        }
+        dns_entity.blocklist = Some(blocklist);
         Ok(())
     }
 
+    async fn dns_blocklist_stats(&self) -> Option<DnsBlocklistStats> {
+        let dns_entity = self.entities.dns.lock().await;
+        dns_entity
+            .blocklist
+            .as_ref()
+            .map(|blocklist| blocklist.stats())
+    }
+
     async fn set_private_key(&mut self, private_key: &SecretKey) -> Result {
         // TODO: create a global controll state to consolidate all entities
 
@@ -1649,6 +2804,10 @@ impl Runtime {
         Ok(self.entities.wireguard_interface.get_adapter_luid().await?)
     }
 
+    async fn get_adapter_type(&self) -> Result<AdapterType> {
+        Ok(self.entities.wireguard_interface.get_adapter_type().await?)
+    }
+
     #[cfg(target_os = "linux")]
     async fn set_fwmark(&mut self, fwmark: u32) -> Result {
         self.requested_state.device_config.fwmark = Some(fwmark);
@@ -1660,60 +2819,390 @@ impl Runtime {
         Ok(())
     }
 
-    async fn notify_network_change(&mut self) -> Result {
+    async fn set_mtu(&mut self, mtu: u16) -> Result {
+        self.entities.wireguard_interface.set_mtu(mtu).await?;
+        telio_log_info!("Tunnel MTU set to {}", mtu);
+
+        let body = MtuChanged {
+            mtu,
+            mss_ipv4: wg::mtu::mss_ipv4(mtu),
+            mss_ipv6: wg::mtu::mss_ipv6(mtu),
+        };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::MtuChanged { body }));
+        Ok(())
+    }
+
+    async fn switch_adapter(&mut self, adapter: AdapterType) -> Result {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.entities.netstack.is_some() && adapter != AdapterType::NepTUN {
+            return Err(Error::AdapterConfig(
+                "userspace mode is supported only by NepTUN".to_owned(),
+            ));
+        }
+
         self.entities
             .wireguard_interface
-            .drop_connected_sockets()
+            .switch_adapter(adapter)
             .await?;
+        Ok(())
+    }
 
-        if let Some(meshnet_entities) = self.entities.meshnet.left() {
-            meshnet_entities.proxy.on_network_change().await;
-            if let Some(direct) = &meshnet_entities.direct {
-                if let Some(stun) = &direct.stun_endpoint_provider {
-                    // this unpauses provider if paused
-                    stun.reconnect().await;
-                }
-
-                if let Some(upnp) = &direct.upnp_endpoint_provider {
-                    upnp.unpause().await;
+    async fn wake_peer(&self, public_key: PublicKey) -> Result {
+        let config = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+        let meshnet = self
+            .entities
+            .meshnet
+            .left()
+            .ok_or(Error::MeshnetNotConfigured)?;
+
+        let mut relayed = false;
+        for relay in wake_on_lan::relays_for(config, &public_key)? {
+            match meshnet
+                .wake_on_lan
+                .try_send((relay, WakeOnLanMsg::new(public_key)))
+            {
+                Ok(()) => {
+                    telio_log_debug!("Asked {relay:?} to wake up {public_key:?}");
+                    relayed = true;
                 }
+                Err(e) => telio_log_warn!("Failed to ask {relay:?} to wake up {public_key:?}: {e}"),
             }
-
-            meshnet_entities.derp.reconnect().await;
         }
-
-        #[cfg(target_os = "android")]
-        PATH_CHANGE_BROADCAST.send(());
-
+        if !relayed {
+            return Err(wake_on_lan::Error::NotRelayed(public_key).into());
+        }
         Ok(())
     }
 
-    async fn notify_sleep(&mut self) -> Result {
-        self.entities
-            .aggregator
-            .force_save_unacknowledged_segments()
-            .await;
-        Ok(())
-    }
+    /// Send the magic packet requested by another node to the LAN, if it is allowed to
+    async fn relay_wake_on_lan(&self, requester: PublicKey, request: WakeOnLanMsg) {
+        let Some(feature) = self.features.wake_on_lan else {
+            telio_log_debug!(
+                "Ignoring the Wake-on-LAN request of {requester:?}, relaying is disabled"
+            );
+            return;
+        };
+        let Some(config) = self.requested_state.meshnet_config.as_ref() else {
+            return;
+        };
 
-    async fn notify_wakeup(&mut self) -> Result {
-        self.entities.aggregator.clear_ongoinging_segments().await;
-        Ok(())
+        let result = match wake_on_lan::target_mac(config, &requester, &request.target) {
+            Ok(mac) => {
+                wake_on_lan::send_magic_packet(&self.entities.socket_pool, mac, feature.port).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => telio_log_info!(
+                "Sent the Wake-on-LAN magic packet of {:?} on behalf of {requester:?}",
+                request.target
+            ),
+            Err(e) => {
+                telio_log_warn!("Failed to relay the Wake-on-LAN request of {requester:?}: {e}")
+            }
+        }
     }
 
-    async fn start_dns(&mut self, upstream_dns_servers: &[IpAddr]) -> Result {
-        self.requested_state.upstream_servers = Some(Vec::from(upstream_dns_servers));
-        {
-            let mut dns_entity = self.entities.dns.lock().await;
+    /// Quiesce the meshnet peers which were not sent anything for a while, if enabled
+    fn update_idle_peers(&mut self) {
+        let Some(watcher) = self.traffic_watcher.as_ref() else {
+            return;
+        };
+        // The exit node carries all of the traffic, so it is never idle for long anyway
+        let exit_node = self
+            .requested_state
+            .exit_node
+            .as_ref()
+            .map(|e| e.public_key);
+        let peers = self
+            .requested_state
+            .meshnet_config
+            .iter()
+            .flat_map(|config| config.peers.iter().flatten())
+            .map(|peer| peer.public_key)
+            .filter(|public_key| Some(*public_key) != exit_node);
+
+        let idle_peers = watcher.idle_peers(peers);
+        if idle_peers != self.requested_state.idle_peers {
+            telio_log_debug!("{} meshnet peers are idle", idle_peers.len());
+            self.requested_state.idle_peers = idle_peers;
+        }
+    }
 
-            //get the public key of the main WG interface
-            let itf = self.entities.wireguard_interface.get_interface().await?;
-            let public_key = itf
-                .private_key
-                .ok_or_else(|| {
-                    Error::AdapterConfig("Adapter does not have a private key".to_owned())
-                })?
-                .public();
+    /// Push the traffic counters of the peers as changed since the previous report
+    async fn report_peer_stats(&mut self) {
+        let Some(stream) = self.peer_stats.as_mut() else {
+            return;
+        };
+        let peers = match self.entities.wireguard_interface.get_interface().await {
+            Ok(interface) => interface.peers,
+            Err(e) => {
+                telio_log_warn!("Failed to read the peer stats: {e:?}");
+                return;
+            }
+        };
+
+        let counters = peers.into_iter().map(|(public_key, peer)| {
+            let counters = Counters {
+                rx_bytes: peer.rx_bytes.unwrap_or_default(),
+                tx_bytes: peer.tx_bytes.unwrap_or_default(),
+            };
+            (public_key, counters)
+        });
+        if let Some(body) = stream.report(counters) {
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::PeerStatsReport { body }));
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn ping(&mut self, public_key: PublicKey, count: u32, interval: Duration) -> Result {
+        // Echo requests sent too often would be a flood rather than a probe
+        const MIN_PING_INTERVAL: Duration = Duration::from_millis(100);
+        // Sequence numbers of the echo requests are 16 bit
+        const MAX_PING_COUNT: u16 = 1000;
+
+        let count = u16::try_from(count)
+            .ok()
+            .filter(|count| (1..=MAX_PING_COUNT).contains(count))
+            .ok_or(Error::InvalidPingCount(count))?;
+        let netstack = self
+            .entities
+            .netstack
+            .clone()
+            .ok_or(Error::PingUnsupported)?;
+        let peer = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?
+            .peers
+            .iter()
+            .flatten()
+            .find(|peer| peer.base.public_key == public_key)
+            .ok_or(Error::InvalidNode)?;
+        let ipv6 = self.features.ipv6;
+        let address = peer
+            .base
+            .ip_addresses
+            .iter()
+            .flatten()
+            .find(|ip| ip.is_ipv4() || ipv6)
+            .copied()
+            .ok_or(Error::NoMeshnetIP)?;
+        let events = self.event_publishers.libtelio_event_publisher.clone();
+
+        let probes = tokio::spawn(async move {
+            let ident = rand::random();
+            let mut interval = tokio::time::interval(interval.max(MIN_PING_INTERVAL));
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            for sequence in 0..count {
+                interval.tick().await;
+                let rtt = netstack
+                    .ping(address, ident, sequence)
+                    .await
+                    .unwrap_or_else(|e| {
+                        telio_log_warn!("Failed to ping {address}: {e}");
+                        None
+                    });
+                let body = PingProbe {
+                    public_key,
+                    address,
+                    sequence: sequence.into(),
+                    rtt_us: rtt.map(|rtt| rtt.as_micros() as u64),
+                };
+                // Nobody listens for the results anymore once the device is gone
+                if events.send(Box::new(Event::PingProbe { body })).is_err() {
+                    break;
+                }
+            }
+        });
+        if let Some(previous) = self.pings.insert(public_key, probes) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn ping(&mut self, _public_key: PublicKey, _count: u32, _interval: Duration) -> Result {
+        Err(Error::PingUnsupported)
+    }
+
+    async fn set_network_available(&self, available: bool) {
+        if let Some(direct) = self
+            .entities
+            .meshnet
+            .left()
+            .and_then(|meshnet_entities| meshnet_entities.direct.as_ref())
+        {
+            if let Some(local) = &direct.local_interfaces_endpoint_provider {
+                local.set_network_available(available).await;
+            }
+            if let Some(stun) = &direct.stun_endpoint_provider {
+                stun.set_network_available(available).await;
+            }
+            if let Some(upnp) = &direct.upnp_endpoint_provider {
+                upnp.set_network_available(available).await;
+            }
+        }
+    }
+
+    async fn notify_network_change(&mut self, network_info: NetworkInfo) -> Result {
+        if let Some(queue) = self.config_queue.as_mut() {
+            queue.unsettle();
+        }
+        if let Some(network) = network_info.network_handle {
+            self.entities.socket_pool.set_default_network(network);
+        }
+
+        self.entities
+            .wireguard_interface
+            .drop_connected_sockets()
+            .await?;
+
+        self.requested_state.network_info = network_info;
+        if self.apply_cellular_profile() {
+            wg_controller::consolidate_wg_state(
+                &self.requested_state,
+                &self.entities,
+                &self.features,
+            )
+            .boxed()
+            .await?;
+        }
+
+        if let Some(meshnet_entities) = self.entities.meshnet.left() {
+            meshnet_entities.proxy.on_network_change().await;
+            if let Some(direct) = &meshnet_entities.direct {
+                if let Some(stun) = &direct.stun_endpoint_provider {
+                    // this unpauses provider if paused
+                    stun.reconnect().await;
+                }
+
+                if let Some(upnp) = &direct.upnp_endpoint_provider {
+                    upnp.unpause().await;
+                }
+            }
+
+            meshnet_entities.derp.reconnect().await;
+        }
+
+        #[cfg(target_os = "android")]
+        PATH_CHANGE_BROADCAST.send(());
+
+        Ok(())
+    }
+
+    async fn notify_sleep(&mut self) -> Result {
+        self.entities
+            .aggregator
+            .force_save_unacknowledged_segments()
+            .await;
+        Ok(())
+    }
+
+    async fn notify_wakeup(&mut self) -> Result {
+        self.entities.aggregator.clear_ongoinging_segments().await;
+        Ok(())
+    }
+
+    async fn suspend(&mut self) -> Result {
+        if self.requested_state.suspended {
+            return Ok(());
+        }
+        telio_log_info!("Suspending background activity");
+        self.requested_state.suspended = true;
+
+        self.entities
+            .aggregator
+            .force_save_unacknowledged_segments()
+            .await;
+        self.set_entities_suspended(true).await;
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .boxed()
+            .await?;
+
+        self.publish_maintenance_state(MaintenanceState::Suspended);
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result {
+        if !self.requested_state.suspended {
+            return Ok(());
+        }
+        telio_log_info!("Resuming background activity");
+        self.requested_state.suspended = false;
+
+        self.set_entities_suspended(false).await;
+
+        // The relay connection may have gone stale while the device slept
+        if let Some(meshnet_entities) = self.entities.meshnet.left() {
+            meshnet_entities.derp.reconnect().await;
+        }
+        // Consolidates and checks the peers right away
+        self.polling_interval.reset_immediately();
+
+        self.publish_maintenance_state(MaintenanceState::Resumed);
+        Ok(())
+    }
+
+    /// Hold back the periodic activity of the entities while the device is suspended
+    async fn set_entities_suspended(&self, suspended: bool) {
+        if let Some(nurse) = &self.entities.nurse {
+            nurse.set_suspended(suspended).await;
+        }
+        if let Some(meshnet_entities) = self.entities.meshnet.left() {
+            meshnet_entities.derp.set_suspended(suspended).await;
+        }
+        if let Some(cpc) = self.entities.cross_ping_check() {
+            if let Err(err) = cpc.set_suspended(suspended).await {
+                telio_log_warn!("Failed to pass the suspension to cross ping check: {err:?}");
+            }
+        }
+        if let Some(upgrade_sync) = self.entities.upgrade_sync() {
+            if let Err(err) = upgrade_sync.set_suspended(suspended).await {
+                telio_log_warn!("Failed to pass the suspension to upgrade sync: {err:?}");
+            }
+        }
+
+        let available = !suspended
+            && *self
+                .event_listeners
+                .network_availability_subscriber
+                .borrow();
+        self.set_network_available(available).await;
+    }
+
+    fn publish_maintenance_state(&self, state: MaintenanceState) {
+        let body = MaintenanceStateChanged { state };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::MaintenanceStateChanged { body }));
+    }
+
+    async fn start_dns(&mut self, upstream_dns_servers: &[IpAddr]) -> Result {
+        self.requested_state.upstream_servers = Some(Vec::from(upstream_dns_servers));
+        {
+            let mut dns_entity = self.entities.dns.lock().await;
+
+            //get the public key of the main WG interface
+            let itf = self.entities.wireguard_interface.get_interface().await?;
+            let public_key = itf
+                .private_key
+                .ok_or_else(|| {
+                    Error::AdapterConfig("Adapter does not have a private key".to_owned())
+                })?
+                .public();
 
             if let Some(dns) = &dns_entity.resolver {
                 dns.forward(upstream_dns_servers)
@@ -1733,10 +3222,13 @@ impl Runtime {
                         .await?,
                     upstream_dns_servers,
                     dns_entity.virtual_host_tun_fd,
-                    self.features.dns.exit_dns.clone(),
+                    &self.features.dns,
+                    Some(self.event_publishers.dns_failure_publisher.clone()),
+                    Some(self.entities.socket_pool.clone()),
                 )
                 .await
                 .map_err(Error::DnsResolverError)?;
+                dns.set_blocklist(dns_entity.blocklist.clone()).await;
                 dns.start().await;
                 dns_entity.resolver = Some(dns);
             }
@@ -1774,7 +3266,221 @@ impl Runtime {
         Ok(())
     }
 
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    async fn start_netstack(&mut self, virtual_tun: VirtualTun) -> Result<Arc<Netstack>> {
+        let netstack = Arc::new(Netstack::start(virtual_tun, NetstackConfig::default())?);
+        if let Some(addresses) = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|c| c.this.ip_addresses.as_deref())
+        {
+            netstack.set_addresses(addresses);
+        }
+
+        if let Some(socks5) = self.features.socks5 {
+            let gateway = Socks5Gateway::start(
+                netstack.clone(),
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), socks5.port),
+            )
+            .await?;
+            gateway.set_hosts(
+                self.requested_state
+                    .collect_meshnet_records(self.features.nicknames),
+            );
+            self.entities.socks5 = Some(gateway);
+        }
+
+        self.entities.netstack = Some(netstack.clone());
+        Ok(netstack)
+    }
+
     async fn set_config(&mut self, config: &Option<Config>) -> Result {
+        let mut namespaces = self.requested_state.meshnet_namespaces.clone();
+        match config {
+            Some(config) => namespaces.set_default(Some(config.clone())),
+            // Turning the meshnet off must not leave the named namespaces running
+            None => namespaces.clear(),
+        }
+        self.apply_namespaces(namespaces).await
+    }
+
+    async fn submit_config(&mut self, config: Option<Config>, waiter: ConfigWaiter) {
+        let due = match self.config_queue.as_mut() {
+            Some(queue) => queue.submit(config, waiter),
+            None => Some(PendingConfig::new(config, waiter)),
+        };
+        match due {
+            Some(pending) => self.apply_pending_config(pending).await,
+            None => telio_log_debug!("Holding back meshnet config until the device settles"),
+        }
+    }
+
+    async fn apply_pending_config(&mut self, pending: PendingConfig) {
+        let result = self.set_config(&pending.config).await;
+        if let Err(e) = &result {
+            telio_log_warn!("Failed to apply meshnet config: {e}");
+        }
+        pending.complete(result.map_err(|e| e.to_string()));
+    }
+
+    async fn set_namespace_config(
+        &mut self,
+        namespace: &str,
+        config: &Option<Config>,
+        policy: MeshnetNamespacePolicy,
+    ) -> Result {
+        let mut namespaces = self.requested_state.meshnet_namespaces.clone();
+        namespaces.set(namespace, config.clone().map(|c| (c, policy)))?;
+        self.apply_namespaces(namespaces).await
+    }
+
+    async fn apply_namespaces(&mut self, namespaces: MeshnetNamespaces) -> Result {
+        let config = namespaces.merge()?;
+
+        // Reapplying would restart the endpoint discovery and reconfigure every meshnet
+        // component, so a config equal to the last applied one is only validated
+        let applied = self
+            .requested_state
+            .meshnet_namespaces
+            .merge()
+            .ok()
+            .flatten();
+        if is_same_meshnet_config(config.as_ref(), applied.as_ref()) {
+            self.validate_meshnet_config(&config).await?;
+            telio_log_debug!("Meshnet config has not changed, nothing to apply");
+        } else {
+            let config = self.limit_peers(config).await?;
+            self.validate_meshnet_config(&config).await?;
+            if let Some(config) = &config {
+                let peers = route_conflicts::meshnet_allowed_ips(config, self.features.ipv6);
+                self.report_route_conflicts(Some(config), &peers);
+            }
+
+            let previous_config = self.requested_state.meshnet_config.clone();
+            let previous_old_config = self.requested_state.old_meshnet_config.clone();
+            if let Err(err) = self.apply_meshnet_config(&config).await {
+                self.rollback_meshnet_config(previous_config, previous_old_config, &err)
+                    .boxed()
+                    .await;
+                return Err(err);
+            }
+        }
+        self.requested_state.meshnet_namespaces = namespaces;
+        Ok(())
+    }
+
+    /// Warn about the allowed IPs colliding with the local networks of the host
+    fn report_route_conflicts(
+        &self,
+        config: Option<&Config>,
+        peers: &[(PublicKey, Vec<ipnet::IpNet>)],
+    ) {
+        let Some(feature) = self.features.route_conflicts else {
+            return;
+        };
+        let local = route_conflicts::host_networks(
+            &SystemGetIfAddrs,
+            self.requested_state.device_config.name.as_deref(),
+            config,
+        );
+        let peers = peers
+            .iter()
+            .map(|(public_key, allowed_ips)| (*public_key, allowed_ips.as_slice()));
+        for conflict in route_conflicts::find_conflicts(peers, &local) {
+            telio_log_warn!(
+                "Allowed IPs {} of {:?} overlap local network {} on {}",
+                conflict.allowed_ips,
+                conflict.public_key,
+                conflict.local.network,
+                conflict.local.interface
+            );
+            let body = RouteConflict {
+                public_key: conflict.public_key,
+                allowed_ips: conflict.allowed_ips,
+                local_network: conflict.local.network,
+                interface_name: conflict.local.interface,
+                excluded: feature.exclude,
+            };
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::RouteConflict { body }));
+        }
+    }
+
+    fn audit(&self, action: AuditAction, public_key: Option<PublicKey>) {
+        if let Some(audit_log) = &self.entities.audit_log {
+            audit_log.record(action, public_key);
+        }
+    }
+
+    /// Restore the last known-good meshnet config after a new one failed to apply midway, so
+    /// the device is not left half-configured
+    async fn rollback_meshnet_config(
+        &mut self,
+        config: Option<Config>,
+        old_config: Option<Config>,
+        cause: &Error,
+    ) {
+        telio_log_warn!("Failed to apply meshnet config: {cause}, rolling back");
+        if let Err(err) = self.apply_meshnet_config(&config).await {
+            telio_log_error!("Failed to roll back meshnet config: {err}");
+        }
+        self.requested_state.old_meshnet_config = old_config;
+
+        let body = ConfigRollback {
+            cause: cause.to_string(),
+        };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::ConfigRollback { body }));
+    }
+
+    /// Trim the config down to the peer limit, if enabled, keeping the exit node and the peers
+    /// online
+    async fn limit_peers(&mut self, config: Option<Config>) -> Result<Option<Config>> {
+        if self.peer_limit.is_none() {
+            return Ok(config);
+        }
+        let wg_itf = self.entities.wireguard_interface.get_interface().await?;
+        let exit_node = self
+            .requested_state
+            .exit_node
+            .as_ref()
+            .map(|n| n.public_key);
+
+        let Some(peer_limit) = self.peer_limit.as_mut() else {
+            return Ok(config);
+        };
+        peer_limit.apply(config, |public_key| {
+            if exit_node.as_ref() == Some(public_key) {
+                return Some(Duration::ZERO);
+            }
+            wg_itf
+                .peers
+                .get(public_key)
+                .and_then(|peer| peer.time_since_last_handshake)
+        })
+    }
+
+    /// Reapply the meshnet config trimmed down to the peer limit, for the slots of the offline
+    /// peers to go to the next ones
+    async fn rotate_trimmed_peers(&mut self) -> Result {
+        if !self
+            .peer_limit
+            .as_ref()
+            .is_some_and(|peer_limit| peer_limit.rotation_due())
+        {
+            return Ok(());
+        }
+        let config = self.requested_state.meshnet_namespaces.merge()?;
+        let config = self.limit_peers(config).await?;
+        self.apply_meshnet_config(&config).await
+    }
+
+    async fn validate_meshnet_config(&self, config: &Option<Config>) -> Result {
         if self.entities.postquantum_wg.is_rotating_keys() && config.is_some() {
             // Post quantum VPN is enabled and we're trying to set up the meshnet
             return Err(Error::MeshnetUnavailableWithPQ);
@@ -1789,8 +3495,49 @@ impl Runtime {
             }
         }
 
+        Ok(())
+    }
+
+    async fn apply_meshnet_config(&mut self, config: &Option<Config>) -> Result {
+        self.validate_meshnet_config(config).await?;
+
         self.requested_state.old_meshnet_config = self.requested_state.meshnet_config.clone();
         self.requested_state.meshnet_config = config.clone();
+        match (&self.requested_state.old_meshnet_config, config) {
+            (None, Some(_)) => self.audit(AuditAction::MeshnetEnabled, None),
+            (Some(_), None) => self.audit(AuditAction::MeshnetDisabled, None),
+            (Some(old), Some(new)) if old != new => {
+                self.audit(AuditAction::MeshnetConfigChanged, None)
+            }
+            _ => (),
+        }
+
+        // Drop everything tied to the old keys before the new peers are configured, so the
+        // traffic of a rekeyed node is never matched against stale state
+        let rekeyed_peers = find_rekeyed_peers(
+            self.requested_state.old_meshnet_config.as_ref(),
+            config.as_ref(),
+        );
+        for rekeyed in &rekeyed_peers {
+            self.forget_peer(&rekeyed.old_public_key).await;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(netstack) = &self.entities.netstack {
+            netstack.set_addresses(
+                config
+                    .as_ref()
+                    .and_then(|c| c.this.ip_addresses.as_deref())
+                    .unwrap_or_default(),
+            );
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(socks5) = &self.entities.socks5 {
+            socks5.set_hosts(
+                self.requested_state
+                    .collect_meshnet_records(self.features.nicknames),
+            );
+        }
 
         let wg_itf = self.entities.wireguard_interface.get_interface().await?;
         let secret_key = if let Some(secret_key) = wg_itf.private_key {
@@ -1798,13 +3545,7 @@ impl Runtime {
         } else {
             return Err(Error::BadPrivateKey);
         };
-        let peers: HashSet<PublicKey> = config
-            .clone()
-            .and_then(|c| c.peers)
-            .unwrap_or_default()
-            .iter()
-            .map(|p| p.base.public_key)
-            .collect();
+        let peers = self.relayed_peers(config.as_ref());
 
         // Update for proxy and derp config
         if let Some(config) = config {
@@ -1833,12 +3574,10 @@ impl Runtime {
                 self.start_meshnet_entities().await?
             };
 
-            let proxy_config = ProxyConfig {
-                wg_port: Some(wg_port),
-                peers: peers.clone(),
-            };
-
-            meshnet_entities.proxy.configure(proxy_config).await?;
+            meshnet_entities
+                .proxy
+                .configure(self.proxy_config(wg_port, peers.clone()))
+                .await?;
 
             if let Some(ref starcast) = meshnet_entities.starcast {
                 let starcast_vpeer_config = StarcastPeerConfig {
@@ -1886,7 +3625,7 @@ impl Runtime {
 
             let derp_config = DerpConfig {
                 secret_key,
-                servers: SortedServers::new(config.derp_servers.clone().unwrap_or_default()),
+                servers: SortedServers::new(self.relay_servers(config)),
                 meshnet_peers: peers,
                 timeout: Duration::from_secs(10), //TODO: make configurable
                 server_keepalives: DerpKeepaliveConfig::from(&self.features.derp),
@@ -1897,12 +3636,39 @@ impl Runtime {
                     .unwrap_or_default()
                     .enable_polling
                     .unwrap_or_default(),
+                enable_peer_presence: self
+                    .features
+                    .derp
+                    .as_ref()
+                    .is_some_and(|derp| derp.enable_peer_presence),
                 use_built_in_root_certificates: self
                     .features
                     .derp
                     .clone()
                     .unwrap_or_default()
                     .use_built_in_root_certificates,
+                idle_timeout: self
+                    .features
+                    .derp
+                    .as_ref()
+                    .and_then(|derp| derp.idle_disconnect)
+                    .map(|idle| Duration::from_secs(idle.idle_timeout_s.into())),
+                enable_compression: self
+                    .features
+                    .derp
+                    .as_ref()
+                    .is_some_and(|derp| derp.enable_compression),
+                path_probes: self
+                    .features
+                    .derp
+                    .as_ref()
+                    .and_then(|derp| derp.path_probes)
+                    .map(|probes| PathProbesConfig {
+                        interval: Duration::from_secs(probes.interval_s.into()),
+                        overloaded_jitter: Duration::from_millis(
+                            probes.overloaded_jitter_ms.into(),
+                        ),
+                    }),
             };
 
             // Update configuration for DERP client
@@ -1922,7 +3688,7 @@ impl Runtime {
 
                     stun_ep
                         .configure(
-                            config.derp_servers.clone().unwrap_or_default(),
+                            self.relay_servers(config),
                             use_ipv6,
                             self.get_socket_pool().await?,
                         )
@@ -1931,6 +3697,10 @@ impl Runtime {
             }
 
             self.entities.meshnet = MeshnetState::Entities(meshnet_entities);
+            // Entities started while suspended stay quiet until resumed
+            if self.requested_state.suspended {
+                self.set_entities_suspended(true).await;
+            }
 
             self.upsert_dns_peers().await?;
         } else {
@@ -1948,6 +3718,7 @@ impl Runtime {
                 let meshnet_entities_last_state = meshnet_entities.stop().await;
                 self.entities.meshnet = MeshnetState::LastState(meshnet_entities_last_state);
             }
+            self.wake_on_lan_requests = None;
 
             self.requested_state.wg_stun_server = None;
 
@@ -1955,7 +3726,8 @@ impl Runtime {
         }
 
         if let Some(cpc) = self.entities.cross_ping_check() {
-            cpc.configure(config.clone()).await?;
+            cpc.configure(self.requested_state.direct_meshnet_config())
+                .await?;
         }
 
         // If Disabling meshnet (by calling `set_config()` with `None` as the argument) need to clear exit node
@@ -1993,9 +3765,71 @@ impl Runtime {
             }
         }
 
+        for rekeyed in rekeyed_peers {
+            telio_log_info!(
+                "Peer {} was rekeyed to {}",
+                rekeyed.old_public_key,
+                rekeyed.new_public_key
+            );
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::PeerRekeyed { body: rekeyed }));
+        }
+
         Ok(())
     }
 
+    /// Relay servers of the config, or the bootstrapped ones when the config lists none
+    fn relay_servers(&self, config: &Config) -> Vec<DerpServer> {
+        match (&config.derp_servers, &self.server_bootstrap) {
+            (Some(servers), _) if !servers.is_empty() => servers.clone(),
+            (_, Some(bootstrap)) => bootstrap.servers(),
+            (servers, None) => servers.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Reapply the meshnet config relying on the bootstrapped relay servers
+    async fn apply_bootstrapped_servers(&mut self, servers: Vec<DerpServer>) -> Result {
+        let config = self.requested_state.meshnet_config.clone();
+        let uses_bootstrap = config.as_ref().map_or(false, |c| {
+            c.derp_servers.as_ref().map_or(true, Vec::is_empty)
+        });
+        if !uses_bootstrap {
+            return Ok(());
+        }
+        telio_log_info!(
+            "Reconfiguring meshnet with {} bootstrapped servers",
+            servers.len()
+        );
+        self.apply_meshnet_config(&config).await
+    }
+
+    /// Drop the state kept for a public key which is no longer used by any node
+    async fn forget_peer(&mut self, public_key: &PublicKey) {
+        self.entities.firewall.remove_peer_connections(public_key);
+        self.last_transmitted_event.remove(public_key);
+        if let Some(tracker) = self.reachability.as_mut() {
+            tracker.on_node_state(*public_key, NodeState::Disconnected);
+        }
+        if let Some(debouncer) = self.node_debouncer.as_mut() {
+            debouncer.forget(public_key);
+        }
+
+        if let Some(upgrade_sync) = self.entities.upgrade_sync() {
+            upgrade_sync.forget_peer(*public_key).await;
+        }
+        if let Some(meshnet_entities) = self.entities.meshnet.left() {
+            meshnet_entities.proxy.remove_peer(*public_key).await;
+        }
+        // Pings and keepalive counts of the old key
+        if let Some(session_keeper) = self.entities.session_keeper() {
+            if let Err(e) = session_keeper.remove_node(public_key).await {
+                telio_log_warn!("Failed to remove {public_key:?} from the session keeper: {e}");
+            }
+        }
+    }
+
     /// Connect ot exit node with post-quantum tunnel
     async fn connect_exit_node_pq(&mut self, exit_node: &ExitNode) -> Result {
         if self.requested_state.meshnet_config.is_some() {
@@ -2017,6 +3851,71 @@ impl Runtime {
         res
     }
 
+    /// Peers whose traffic may go over the relay: all meshnet peers, and the VPN server
+    /// if it is allowed to fall back to the relay
+    fn relayed_peers(&self, config: Option<&Config>) -> HashSet<PublicKey> {
+        let mut peers: HashSet<PublicKey> = config
+            .and_then(|c| c.peers.as_deref())
+            .unwrap_or_default()
+            .iter()
+            .map(|p| p.base.public_key)
+            .collect();
+
+        if self.features.vpn_relay_fallback.is_some() {
+            if let Some(exit_node) = &self.requested_state.exit_node {
+                peers.insert(exit_node.public_key);
+            }
+        }
+
+        peers
+    }
+
+    fn proxy_config(&self, wg_port: u16, peers: HashSet<PublicKey>) -> ProxyConfig {
+        ProxyConfig {
+            wg_port: Some(wg_port),
+            peers,
+            idle_timeout: self
+                .features
+                .lazy_proxy
+                .as_ref()
+                .map(|lazy_proxy| Duration::from_secs(lazy_proxy.idle_timeout_s)),
+        }
+    }
+
+    /// Update proxy and derp with the relayed peers after the exit node has changed
+    async fn reconfigure_relayed_peers(&self) -> Result {
+        if self.features.vpn_relay_fallback.is_none() {
+            return Ok(());
+        }
+        let meshnet_entities = match self.entities.meshnet.left() {
+            Some(meshnet_entities) => meshnet_entities,
+            None => return Ok(()),
+        };
+
+        let peers = self.relayed_peers(self.requested_state.meshnet_config.as_ref());
+        let wg_port = self
+            .entities
+            .wireguard_interface
+            .wait_for_proxy_listen_port(Duration::from_secs(1))
+            .await?;
+        meshnet_entities
+            .proxy
+            .configure(self.proxy_config(wg_port, peers.clone()))
+            .await?;
+
+        if let Some(derp_config) = meshnet_entities.derp.get_config().await {
+            meshnet_entities
+                .derp
+                .configure(Some(DerpConfig {
+                    meshnet_peers: peers,
+                    ..derp_config
+                }))
+                .await;
+        }
+
+        Ok(())
+    }
+
     async fn connect_exit_node(&mut self, exit_node: &ExitNode) -> Result {
         // Silence the nagger warning
         Box::pin(self.connect_exit_node_internal(exit_node, false)).await
@@ -2068,7 +3967,26 @@ impl Runtime {
             return Err(Error::EndpointNotProvided);
         }
 
+        // The staged server becomes the exit node, its session is reused
+        if self
+            .requested_state
+            .prewarmed_exit_node
+            .is_some_and(|prewarmed| prewarmed.public_key == exit_node.public_key)
+        {
+            self.requested_state.prewarmed_exit_node = None;
+        }
+        if let Some(allowed_ips) = &exit_node.allowed_ips {
+            self.report_route_conflicts(
+                self.requested_state.meshnet_config.as_ref(),
+                &[(exit_node.public_key, allowed_ips.clone())],
+            );
+        }
+        let exit_node_key = exit_node.public_key;
         let old_exit_node = self.requested_state.exit_node.replace(exit_node);
+        if old_exit_node.as_ref().map(|node| node.public_key) != Some(exit_node_key) {
+            self.audit(AuditAction::ExitNodeConnected, Some(exit_node_key));
+        }
+        self.reconfigure_relayed_peers().await?;
         wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
             .boxed()
             .await?;
@@ -2126,7 +4044,12 @@ impl Runtime {
 
     async fn disconnect_exit_nodes(&mut self) -> Result {
         if let Some(exit_node) = self.requested_state.exit_node.take() {
+            self.audit(
+                AuditAction::ExitNodeDisconnected,
+                Some(exit_node.public_key),
+            );
             self.requested_state.last_exit_node = Some(exit_node);
+            self.reconfigure_relayed_peers().await?;
 
             // for macos dns
             bind_tun::set_should_bind(false);
@@ -2153,7 +4076,35 @@ impl Runtime {
             pmtud.stop().await;
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    async fn prewarm_exit_node(&mut self, prewarmed: Option<PrewarmedExitNode>) -> Result {
+        if let Some(prewarmed) = &prewarmed {
+            let is_meshnet_peer = self
+                .requested_state
+                .meshnet_config
+                .as_ref()
+                .and_then(|config| config.peers.as_deref())
+                .is_some_and(|peers| peers.iter().any(|p| p.public_key == prewarmed.public_key));
+            if is_meshnet_peer {
+                return Err(Error::InvalidNode);
+            }
+            telio_log_info!(
+                "Prewarming exit node {:?} at {:?}, handshake: {}",
+                prewarmed.public_key,
+                prewarmed.endpoint,
+                prewarmed.handshake
+            );
+        }
+
+        if self.requested_state.prewarmed_exit_node == prewarmed {
+            return Ok(());
+        }
+        self.requested_state.prewarmed_exit_node = prewarmed;
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .boxed()
+            .await
     }
 
     #[allow(clippy::panic)]
@@ -2170,6 +4121,85 @@ impl Runtime {
         Ok(self.entities.socket_pool.clone())
     }
 
+    async fn set_peer_path_preference(
+        &mut self,
+        public_key: PublicKey,
+        preference: PathPreference,
+    ) -> Result {
+        let relay_only = &mut self.requested_state.relay_only_peers;
+        let changed = match preference {
+            PathPreference::RelayOnly => relay_only.insert(public_key),
+            PathPreference::DirectPreferred => relay_only.remove(&public_key),
+        };
+        if !changed {
+            return Ok(());
+        }
+        telio_log_info!("Path of node {:?} set to {:?}", public_key, preference);
+
+        if let Some(cpc) = self.entities.cross_ping_check() {
+            cpc.configure(self.requested_state.direct_meshnet_config())
+                .await?;
+        }
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .boxed()
+            .await?;
+        Ok(())
+    }
+
+    async fn peer_candidates(&self, public_key: PublicKey) -> Result<PeerCandidates> {
+        if self.entities.meshnet.left().is_none() {
+            return Err(Error::MeshnetNotConfigured);
+        }
+        match self.entities.cross_ping_check() {
+            Some(cpc) => Ok(cpc.get_peer_candidates(public_key).await?),
+            None => Ok(PeerCandidates::default()),
+        }
+    }
+
+    async fn preview_peer_connectivity(
+        &self,
+        public_key: PublicKey,
+        endpoint_hints: Vec<SocketAddr>,
+    ) -> Result<ConnectivityPreview> {
+        if self.entities.meshnet.left().is_none() {
+            return Err(Error::MeshnetNotConfigured);
+        }
+        if public_key == self.requested_state.device_config.private_key.public() {
+            return Err(Error::InvalidNode);
+        }
+
+        let mut hints = ConnectivityHints {
+            relay_connected: self
+                .relay_state
+                .as_ref()
+                .map_or(false, |server| server.conn_state == RelayState::Connected),
+            direct_enabled: self.entities.cross_ping_check().is_some(),
+            remote_endpoints: endpoint_hints,
+            ..Default::default()
+        };
+        hints.interface_addresses.extend(
+            LOCAL_ADDRS_CACHE
+                .lock()
+                .iter()
+                .map(|interface| interface.addr.ip()),
+        );
+        for provider in self.entities.endpoint_providers() {
+            let Some(candidates) = provider.get_current_endpoints().await else {
+                continue;
+            };
+            let addresses = match provider.name() {
+                "stun" => &mut hints.stun_addresses,
+                "UPnP" => &mut hints.forwarded_addresses,
+                _ => &mut hints.interface_addresses,
+            };
+            addresses.extend(candidates.iter().map(|c| c.udp.ip()));
+        }
+
+        let preview = hints.preview();
+        telio_log_debug!("Connectivity preview for {:?}: {:?}", public_key, preview);
+        Ok(preview)
+    }
+
     async fn peer_to_node<'a>(
         &'a self,
         peer: &uapi::Peer,
@@ -2217,6 +4247,39 @@ impl Runtime {
             .and_then(|proxy| endpoint.filter(|actual| proxy.contains(actual)))
             .map_or(PathType::Direct, |_| PathType::Relay);
 
+        // Resolve which candidates formed the direct connection. The remote endpoint differs
+        // from the negotiated one when the peer was reached through a peer-reflexive address.
+        let session = match (&self.entities.meshnet, path_type) {
+            (MeshnetState::Entities(meshnet_entities), PathType::Direct) => {
+                match &meshnet_entities.direct {
+                    Some(direct) => {
+                        direct
+                            .upgrade_sync
+                            .get_accepted_session(peer.public_key)
+                            .await
+                    }
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+        let local_candidate = session.as_ref().map(|session| session.local_ep.into());
+        let remote_candidate = session.as_ref().map(|session| {
+            if endpoint == Some(session.remote_addr) {
+                session.remote_ep.into()
+            } else {
+                CandidateType::PeerReflexive
+            }
+        });
+        let local_endpoint = session.and_then(|session| session.local_addr);
+        let redact = |addr: Option<SocketAddr>| {
+            if self.features.redact_node_endpoints {
+                addr.map(redact_endpoint)
+            } else {
+                addr
+            }
+        };
+
         // Build a node to report event about, we need to report about either meshnet peers
         // or VPN peers. Others (like DNS, or anycast) are considered to be "internal" ones
         // and will not be reported via libtelio events.
@@ -2240,7 +4303,7 @@ impl Runtime {
                     is_vpn: false,
                     ip_addresses: meshnet_peer.base.ip_addresses.clone().unwrap_or_default(),
                     allowed_ips: peer.allowed_ips.clone(),
-                    endpoint,
+                    endpoint: redact(endpoint),
                     hostname: Some(meshnet_peer.base.hostname.0.clone().to_string()),
                     allow_incoming_connections: meshnet_peer.allow_incoming_connections,
                     allow_peer_traffic_routing: meshnet_peer.allow_peer_traffic_routing,
@@ -2249,6 +4312,9 @@ impl Runtime {
                     path: path_type,
                     allow_multicast: meshnet_peer.allow_multicast,
                     peer_allows_multicast: meshnet_peer.peer_allows_multicast,
+                    local_candidate,
+                    remote_candidate,
+                    local_endpoint: redact(local_endpoint),
                 })
             }
             (None, Some(exit_node)) => {
@@ -2274,6 +4340,95 @@ impl Runtime {
         }
     }
 
+    async fn set_analytics_consent(&mut self, consent: bool) -> Result {
+        let purged = if consent {
+            if let Some(lana) = &self.features.lana {
+                let (event_path, prod) = (lana.event_path.clone(), lana.prod);
+                if init_lana(event_path, version_tag().to_string(), prod).is_err() {
+                    telio_log_error!("Failed to initialize lana")
+                }
+            }
+            self.entities.aggregator.set_enabled(true).await;
+            if self.entities.nurse.is_none() {
+                self.restart_nurse().await;
+            }
+            false
+        } else {
+            let stopped = match self.entities.nurse.take() {
+                Some(nurse) => {
+                    // Nurse is keeping Arc to Derp, so it has to let go of it to be stopped
+                    nurse.configure_meshnet(None).await;
+                    match Arc::try_unwrap(nurse) {
+                        Ok(nurse) => {
+                            nurse.discard().await;
+                            true
+                        }
+                        Err(nurse) => {
+                            // It may still collect until it notices, so nothing is purged for sure
+                            telio_log_warn!(
+                                "Something is holding a strong reference to the Nurse, cancelling it"
+                            );
+                            nurse.cancel();
+                            false
+                        }
+                    }
+                }
+                None => true,
+            };
+            self.entities.aggregator.set_enabled(false).await;
+            let purged = match &self.features.lana {
+                Some(lana) => telio_lana::purge_lana(&lana.event_path).is_ok(),
+                None => true,
+            };
+            stopped && purged
+        };
+
+        telio_log_info!("Analytics consent changed to {consent}, purged: {purged}");
+        let body = AnalyticsConsentChanged { consent, purged };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::AnalyticsConsentChanged { body }));
+        Ok(())
+    }
+
+    async fn restart_nurse(&mut self) {
+        self.entities.nurse = start_nurse(
+            &self.features,
+            self.requested_state.device_config.private_key.public(),
+            NurseIo {
+                wg_event_channel: &self.event_publishers.libtelio_event_publisher,
+                wg_analytics_channel: self.event_publishers.wg_analytics_publisher.clone(),
+                config_update_channel: self.event_publishers.nurse_config_update_publisher.clone(),
+                collection_trigger_channel: self
+                    .event_publishers
+                    .nurse_collection_trigger_publisher
+                    .clone(),
+                qos_trigger_channel: self
+                    .event_publishers
+                    .qos_collection_trigger_publisher
+                    .clone(),
+            },
+            self.entities.aggregator.clone(),
+        )
+        .await;
+
+        if let (Some(nurse), Some(meshnet)) =
+            (self.entities.nurse.as_ref(), self.entities.meshnet.left())
+        {
+            nurse
+                .configure_meshnet(Some(NurseMeshnetEntities {
+                    multiplexer_channel: meshnet
+                        .multiplexer
+                        .get_channel::<HeartbeatMessage>()
+                        .await
+                        .unwrap_or_default(),
+                    derp_event_channel: self.event_publishers.derp_events_publisher.clone(),
+                }))
+                .await;
+        }
+    }
+
     async fn trigger_qos_collection(&self) -> Result<()> {
         if let Some(ch) = &self.event_publishers.qos_collection_trigger_publisher {
             let _ = ch.send(());
@@ -2340,7 +4495,420 @@ impl Runtime {
             .unwrap_or(false)
     }
 
+    /// Publish events for peers which failed to connect in time
+    async fn report_unreachable_peers(&mut self) {
+        let timed_out = match self.reachability.as_mut() {
+            Some(tracker) => tracker.take_timed_out(),
+            None => return,
+        };
+        if timed_out.is_empty() {
+            return;
+        }
+
+        let network_available = !LOCAL_ADDRS_CACHE.lock().is_empty();
+        let (relay_connected, peer_states) = match self.entities.meshnet.left() {
+            Some(meshnet) => (
+                Some(meshnet.derp.get_conn_state().await),
+                meshnet.derp.get_remote_peer_states().await,
+            ),
+            None => (None, Default::default()),
+        };
+
+        for (public_key, connecting_for) in timed_out {
+            let Some(node) = self.last_transmitted_event.get(&public_key) else {
+                continue;
+            };
+            let hints = CauseHints {
+                network_available,
+                is_vpn: node.is_vpn,
+                relay_connected,
+                peer_online: peer_states.get(&public_key).copied(),
+            };
+            let body = PeerUnreachable {
+                identifier: node.identifier.clone(),
+                public_key,
+                is_vpn: node.is_vpn,
+                cause: hints.cause(),
+                connecting_for_s: connecting_for.as_secs(),
+            };
+            telio_log_info!("Peer {} is unreachable: {:?}", public_key, body.cause);
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::PeerUnreachable { body }));
+        }
+    }
+
+    /// Take the next remediation step if meshnet is stuck without any connected peers
+    async fn run_mesh_watchdog(&mut self) {
+        let Some(watchdog) = self.mesh_watchdog.as_mut() else {
+            return;
+        };
+        let has_peers = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|config| config.peers.as_ref())
+            .is_some_and(|peers| !peers.is_empty());
+        // Availability is kept up to date by the network monitor on every path change
+        let network_up = !self.requested_state.suspended
+            && *self
+                .event_listeners
+                .network_availability_subscriber
+                .borrow();
+        let watched = has_peers && self.entities.meshnet.left().is_some() && network_up;
+        let connected_peers = self
+            .last_transmitted_event
+            .values()
+            .filter(|node| !node.is_vpn && node.state == PeerState::Connected)
+            .count();
+
+        let Some((step, stuck_for)) = watchdog.observe(watched, connected_peers) else {
+            return;
+        };
+        telio_log_info!(
+            "Meshnet without connected peers for {:?}, remediation step: {:?}",
+            stuck_for,
+            step
+        );
+        if let Err(e) = self.remediate_mesh(step).await {
+            telio_log_warn!("Mesh remediation step {:?} failed: {:?}", step, e);
+        }
+
+        let body = MeshRemediation {
+            step,
+            stuck_for_s: stuck_for.as_secs(),
+        };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::MeshRemediation { body }));
+    }
+
+    async fn remediate_mesh(&self, step: RemediationStep) -> Result {
+        let Some(meshnet_entities) = self.entities.meshnet.left() else {
+            return Ok(());
+        };
+        match step {
+            RemediationStep::RelayReconnect => meshnet_entities.derp.reconnect().await,
+            RemediationStep::EndpointRediscovery => {
+                for ep in self.entities.endpoint_providers().iter() {
+                    if let Err(err) = ep.trigger_endpoint_candidates_discovery(true).await {
+                        telio_log_debug!("Failed to trigger: {}", err);
+                    }
+                }
+            }
+            RemediationStep::AdapterRehandshake => {
+                // Peers added back by the consolidation start with fresh handshakes
+                let meshnet_peers: HashSet<PublicKey> = self
+                    .requested_state
+                    .meshnet_config
+                    .as_ref()
+                    .and_then(|config| config.peers.as_ref())
+                    .map(|peers| peers.iter().map(|peer| peer.public_key).collect())
+                    .unwrap_or_default();
+                let interface = self.entities.wireguard_interface.get_interface().await?;
+                for public_key in interface.peers.keys() {
+                    if !meshnet_peers.contains(public_key) {
+                        continue;
+                    }
+                    // The other peers still get their rehandshake, the peers keep their access
+                    if let Err(e) = wg_controller::remove_peer(
+                        &*self.entities.wireguard_interface,
+                        self.entities.audit_log.as_ref(),
+                        *public_key,
+                        false,
+                    )
+                    .await
+                    {
+                        telio_log_warn!("Failed to remove peer {:?}: {:?}", public_key, e);
+                    }
+                }
+                wg_controller::consolidate_wg_state(
+                    &self.requested_state,
+                    &self.entities,
+                    &self.features,
+                )
+                .boxed()
+                .await?;
+            }
+            RemediationStep::Recovered | RemediationStep::Exhausted => (),
+        }
+        Ok(())
+    }
+
+    /// Exchange heartbeats with the other instance of the pair, taking over or stepping down
+    async fn handle_ha_event(&mut self, event: HaEvent) {
+        let state = HaState {
+            meshnet_config: self.requested_state.meshnet_config.clone(),
+            exit_node: self.requested_state.exit_node.clone(),
+        };
+        let Some(ha) = self.ha.as_mut() else {
+            return;
+        };
+        let action = ha
+            .handle(
+                event,
+                &self.requested_state.device_config.private_key,
+                &state,
+            )
+            .await;
+        let (role, term) = (ha.role(), ha.term());
+
+        match action {
+            Some(HaAction::TakeOver(state)) => {
+                telio_log_info!("Taking over as the primary of term {}", term);
+                if let Err(e) = self.set_config(&state.meshnet_config).await {
+                    telio_log_warn!("Failed to apply the mirrored meshnet config: {:?}", e);
+                }
+                if let Some(exit_node) = state.exit_node {
+                    if let Err(e) = self.connect_exit_node(&exit_node).await {
+                        telio_log_warn!("Failed to connect to the mirrored exit node: {:?}", e);
+                    }
+                }
+            }
+            Some(HaAction::StepDown) => {
+                telio_log_info!("Stepping down for the primary of term {}", term);
+                if let Err(e) = self.disconnect_exit_nodes().await {
+                    telio_log_warn!("Failed to disconnect the exit node: {:?}", e);
+                }
+                if let Err(e) = self.set_config(&None).await {
+                    telio_log_warn!("Failed to clear the meshnet config: {:?}", e);
+                }
+            }
+            None => return,
+        }
+
+        let body = HaRoleChanged { role, term };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::HaRoleChanged { body }));
+    }
+
+    /// Publish an event for a new inbound connection accepted by the firewall
+    fn report_inbound_connection(
+        &self,
+        public_key: PublicKey,
+        protocol: ConnectionProtocol,
+        port: u16,
+    ) {
+        // Only meshnet peers are reported, not the VPN server
+        let Some(peer) = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|config| config.peers.as_ref())
+            .and_then(|peers| peers.iter().find(|peer| peer.public_key == public_key))
+        else {
+            return;
+        };
+        let body = InboundConnection {
+            identifier: peer.identifier.clone(),
+            public_key,
+            hostname: peer.hostname.0.clone(),
+            protocol,
+            port,
+        };
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::InboundConnection { body }));
+    }
+
+    /// Count a failed query of an upstream DNS resolver in analytics
+    async fn report_dns_failure(&self, failure: DnsFailure) {
+        let kind = match failure.kind {
+            DnsFailureKind::ServFail => NurseDnsFailureKind::ServFail,
+            DnsFailureKind::Refused => NurseDnsFailureKind::Refused,
+            DnsFailureKind::Timeout => NurseDnsFailureKind::Timeout,
+            DnsFailureKind::Unreachable => NurseDnsFailureKind::Unreachable,
+            DnsFailureKind::Malformed => NurseDnsFailureKind::Malformed,
+            DnsFailureKind::Other => NurseDnsFailureKind::Other,
+        };
+        self.entities
+            .aggregator
+            .report_dns_failure(failure.upstream, kind)
+            .await;
+    }
+
+    /// Forward the connectivity check outcomes per address family to analytics
+    async fn report_family_checks(&self) {
+        let Some(cpc) = self.entities.cross_ping_check() else {
+            return;
+        };
+        match cpc.collect_family_stats().await {
+            Ok(stats) => self.entities.aggregator.report_family_checks(stats).await,
+            Err(e) => telio_log_debug!("Failed to collect family stats: {:?}", e),
+        }
+    }
+
+    /// Apply the tuning of the cellular carrier the device is on, or revert to the configured
+    /// keepalives off cellular. Returns whether anything has changed.
+    fn apply_cellular_profile(&mut self) -> bool {
+        let network_info = &self.requested_state.network_info;
+        let tunnel = self.requested_state.device_config.name.as_deref();
+        let profile = self.features.cellular.as_ref().and_then(|cellular| {
+            let on_cellular = network_info
+                .cellular
+                .unwrap_or_else(|| cellular.detect && is_on_cellular(tunnel));
+            if on_cellular {
+                cellular.profile(network_info.carrier.as_deref())
+            } else {
+                None
+            }
+        });
+        let vpn_keepalive = profile.and_then(|profile| profile.vpn_keepalive_s).or(self
+            .features
+            .wireguard
+            .persistent_keepalive
+            .vpn);
+        let handshake_timeout_s = profile.and_then(|profile| profile.handshake_timeout_s);
+
+        let state = &mut self.requested_state;
+        if state.keepalive_periods.vpn == vpn_keepalive
+            && state.vpn_handshake_timeout_s == handshake_timeout_s
+        {
+            return false;
+        }
+        telio_log_info!(
+            "Tuning VPN for the network, keepalive: {:?}s, handshake timeout: {:?}s",
+            vpn_keepalive,
+            handshake_timeout_s
+        );
+        state.keepalive_periods.vpn = vpn_keepalive;
+        state.vpn_handshake_timeout_s = handshake_timeout_s;
+        true
+    }
+
+    /// Sample the memory held by the subsystems
+    async fn sample_memory_usage(&mut self) {
+        let conntrack = self.entities.firewall.get_conntrack_memory_usage();
+        let dns = match &self.entities.dns.lock().await.resolver {
+            Some(resolver) => resolver.memory_usage().await,
+            None => 0,
+        };
+        let (proxy, relay) = match self.entities.meshnet.left() {
+            Some(meshnet) => (
+                meshnet.proxy.get_memory_usage().await.unwrap_or_else(|e| {
+                    telio_log_debug!("Failed to get proxy memory usage: {:?}", e);
+                    0
+                }),
+                meshnet.derp.get_queued_bytes().await,
+            ),
+            None => (0, 0),
+        };
+
+        for (component, bytes) in [
+            (MemoryComponent::ProxyBuffers, proxy),
+            (MemoryComponent::FirewallConntrack, conntrack),
+            (MemoryComponent::DnsCache, dns),
+            (MemoryComponent::RelayQueues, relay),
+        ] {
+            self.memory.record(component, bytes as u64);
+        }
+    }
+
+    async fn memory_usage(&mut self) -> Vec<ComponentMemoryUsage> {
+        self.sample_memory_usage().await;
+        self.memory.usage()
+    }
+
+    async fn wg_peer_plan(&self) -> Result<WgPeerPlan> {
+        wg_controller::plan_wg_peers(&self.requested_state, &self.entities, &self.features).await
+    }
+
+    /// Records of the audit log, the oldest first
+    fn audit_records(&self) -> Vec<AuditRecord> {
+        self.entities
+            .audit_log
+            .as_ref()
+            .map(AuditLog::records)
+            .unwrap_or_default()
+    }
+
+    async fn peer_counters(&mut self) -> Result<Vec<PeerCounters>> {
+        let wireguard = &self.entities.wireguard_interface;
+        let peers = wireguard.get_interface().await?.peers;
+        let handshakes = wireguard.get_handshake_counts().await?;
+        let upgrades = match self.entities.upgrade_sync() {
+            Some(upgrade_sync) => upgrade_sync.get_upgrade_counts().await,
+            None => HashMap::new(),
+        };
+        let keepalives = match self.entities.session_keeper() {
+            Some(session_keeper) => session_keeper.get_keepalives_sent().await,
+            None => HashMap::new(),
+        };
+        let firewall_drops = self.entities.firewall.get_dropped_packets();
+        let rate_limits = self.entities.firewall.get_rate_limit_stats();
+        self.downgrades
+            .retain(|public_key, _| peers.contains_key(public_key));
+
+        Ok(peers
+            .keys()
+            .map(|public_key| {
+                let count = |counts: &HashMap<PublicKey, u64>| {
+                    counts.get(public_key).copied().unwrap_or_default()
+                };
+                let upgrades = upgrades.get(public_key).copied().unwrap_or_default();
+                let rate_limits = rate_limits.get(public_key).copied().unwrap_or_default();
+                PeerCounters {
+                    public_key: *public_key,
+                    handshakes_completed: count(&handshakes),
+                    upgrades_attempted: upgrades.attempted,
+                    upgrades_succeeded: upgrades.succeeded,
+                    downgrades: count(&self.downgrades),
+                    keepalives_sent: count(&keepalives),
+                    firewall_drops: firewall_drops.get(public_key).copied().unwrap_or_default(),
+                    rate_limited_connections: rate_limits.dropped_connections,
+                    rate_limited_echo_requests: rate_limits.dropped_echo_requests,
+                }
+            })
+            .collect())
+    }
+
+    /// Publish the raw node transition if requested, and hold back brief connection drops
+    fn debounce_node_event(&mut self, node: Node) -> Option<Node> {
+        let Some(debouncer) = self.node_debouncer.as_mut() else {
+            return Some(node);
+        };
+        if self
+            .features
+            .node_event_debounce
+            .map_or(false, |f| f.node_transition_events)
+        {
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::NodeTransition { body: node.clone() }));
+        }
+
+        let last_published = self.last_transmitted_event.get(&node.public_key);
+        let node = debouncer.on_node(node, last_published);
+        if node.is_none() {
+            telio_log_debug!("Holding back node event while the connection may recover");
+        }
+        node
+    }
+
+    /// Publish the node event to the app, unless it repeats the last one or is suppressed
+    fn publish_node_event(&mut self, node: Node) {
+        if self.is_dublicated_event(&node) || self.should_supress_disconnected(&node) {
+            telio_log_debug!("Event is dublicated, skip publishing {node:?}");
+            return;
+        }
+        telio_log_debug!("Event is being published to libtelio integrators {node:?}");
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::Node { body: node.clone() }));
+        self.remember_last_transmitted_node_event(node);
+    }
+
     fn remember_last_transmitted_node_event(&mut self, node: Node) {
+        if let Some(tracker) = self.reachability.as_mut() {
+            tracker.on_node_state(node.public_key, node.state);
+        }
         if node.state == PeerState::Disconnected {
             self.last_transmitted_event.remove(&node.public_key);
         } else {
@@ -2359,6 +4927,9 @@ impl TaskRuntime for Runtime {
     where
         F: Future<Output = BoxAction<Self, std::result::Result<(), Self::Err>>> + Send,
     {
+        let debounce_deadline = self.node_debouncer.as_ref().and_then(|d| d.next_deadline());
+        let config_deadline = self.config_queue.as_ref().and_then(|q| q.next_deadline());
+
         tokio::select! {
             Some(_) = self.event_listeners.wg_endpoint_publish_event_subscriber.recv() => {
                 telio_log_debug!("WG consolidation triggered by endpoint publish event");
@@ -2395,31 +4966,60 @@ impl TaskRuntime for Runtime {
                             mesh_entities.proxy.mute_peer(public_key, None).await?;
 
                             telio_log_info!("Peer {} was downgraded", public_key);
+                            *self.downgrades.entry(public_key).or_default() += 1;
                         }
                     }
                 }
 
+                if mesh_event.state == PeerState::Connected
+                    && self.requested_state.exit_node.as_ref().is_some_and(|exit_node| exit_node.public_key == public_key)
+                {
+                    self.entities.firewall.complete_exit_switch(public_key);
+                }
+
                 let node = self.peer_to_node(&mesh_event.peer, Some(mesh_event.state), mesh_event.link_state).await;
                 telio_log_debug!("Converted peer to node {node:?}");
 
-                if let Some(node) = node {
+                if let Some(node) = node.and_then(|node| self.debounce_node_event(node)) {
                     // Publish WG event to app
-                    if !self.is_dublicated_event(&node) && !self.should_supress_disconnected(&node) {
-                        telio_log_debug!("Event is being published to libtelio integrators {node:?}");
-                        let _ = self.event_publishers.libtelio_event_publisher.send(
-                            Box::new(Event::Node {body: node.clone()})
-                        );
-                        self.remember_last_transmitted_node_event(node);
-                    } else {
-                        telio_log_debug!("Event is dublicated, skip publishing {node:?}");
-                    }
+                    self.publish_node_event(node);
+                }
+
+                Ok(())
+            },
+
+            _ = tokio::time::sleep_until(debounce_deadline.unwrap_or_else(tokio::time::Instant::now)), if debounce_deadline.is_some() => {
+                let due = self.node_debouncer.as_mut().map(|d| d.take_due()).unwrap_or_default();
+                for node in due {
+                    telio_log_debug!("Releasing held back node event {node:?}");
+                    self.publish_node_event(node);
                 }
+                Ok(())
+            },
 
+            _ = tokio::time::sleep_until(config_deadline.unwrap_or_else(tokio::time::Instant::now)), if config_deadline.is_some() => {
+                if let Some(pending) = self.config_queue.as_mut().and_then(|q| q.take_due()) {
+                    telio_log_debug!("Applying the held back meshnet config");
+                    self.apply_pending_config(pending).await;
+                }
                 Ok(())
             },
 
             Ok(derp_event) = self.event_listeners.derp_event_subscriber.recv() => {
                 telio_log_debug!("Recieved wg_event {derp_event:?}");
+                self.relay_state = match derp_event.conn_state {
+                    RelayState::Disconnected => None,
+                    _ => Some(*derp_event.clone()),
+                };
+                if let Some(queue) = self.config_queue.as_mut() {
+                    queue.set_relay_settled(!matches!(derp_event.conn_state, RelayState::Connecting));
+                }
+                if let Some(cpc) = self.entities.cross_ping_check() {
+                    let connected = matches!(derp_event.conn_state, RelayState::Connected | RelayState::Degraded);
+                    if let Err(err) = cpc.set_relay_connected(connected).await {
+                        telio_log_warn!("Failed to pass the relay state to cross ping check: {err:?}");
+                    }
+                }
                 let event = Event::builder::<DerpServer>().set(*derp_event).build();
                 if let Some(event) = event {
                 let _ = self.event_publishers.libtelio_event_publisher.send(
@@ -2429,6 +5029,43 @@ impl TaskRuntime for Runtime {
                 Ok(())
             },
 
+            event = high_availability_event(&mut self.ha), if self.ha.is_some() => {
+                self.handle_ha_event(event).await;
+                Ok(())
+            },
+
+            Some(servers) = bootstrapped_servers_changed(&mut self.server_bootstrap), if self.server_bootstrap.is_some() => {
+                self.apply_bootstrapped_servers(servers)
+                    .await
+                    .unwrap_or_else(|e| {
+                        telio_log_warn!("Failed to apply bootstrapped servers: {:?}", e);
+                    });
+                Ok(())
+            },
+
+            Some((public_key, request)) = wake_on_lan_request(&mut self.wake_on_lan_requests), if self.wake_on_lan_requests.is_some() => {
+                self.relay_wake_on_lan(public_key, request).await;
+                Ok(())
+            },
+
+            _ = traffic_woken(&self.traffic_watcher), if self.traffic_watcher.is_some() => {
+                telio_log_debug!("WG consolidation triggered by traffic to an idle peer");
+                self.update_idle_peers();
+                wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+                    .boxed()
+                    .await
+                    .unwrap_or_else(
+                        |e| {
+                            telio_log_warn!("WireGuard controller failure: {:?}. Ignoring", e);
+                        });
+                Ok(())
+            },
+
+            _ = peer_stats_tick(&mut self.peer_stats), if self.peer_stats.is_some() && !self.requested_state.suspended => {
+                self.report_peer_stats().await;
+                Ok(())
+            },
+
             Some(_) = self.event_listeners.endpoint_upgrade_event_subscriber.recv() => {
                 telio_log_debug!("WG consolidation triggered by upgrade sync request");
                 wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
@@ -2457,6 +5094,26 @@ impl TaskRuntime for Runtime {
                 Ok(())
             },
 
+            Ok(()) = self.event_listeners.network_availability_subscriber.changed() => {
+                let available = *self.event_listeners.network_availability_subscriber.borrow_and_update();
+                telio_log_debug!("Network availability changed: {available}");
+                // Suspended device keeps the providers paused until resumed
+                if !self.requested_state.suspended {
+                    self.set_network_available(available).await;
+                }
+                Ok(())
+            },
+
+            Some((public_key, protocol, port)) = self.event_listeners.inbound_connection_subscriber.recv() => {
+                self.report_inbound_connection(public_key, protocol, port);
+                Ok(())
+            },
+
+            Some(failure) = self.event_listeners.dns_failure_subscriber.recv() => {
+                self.report_dns_failure(failure).await;
+                Ok(())
+            },
+
             Some(pq_event) = self.event_listeners.post_quantum_subscriber.recv() => {
                 telio_log_debug!("WG consolidation triggered by PQ event");
 
@@ -2509,12 +5166,17 @@ impl TaskRuntime for Runtime {
                 Ok(())
             },
 
-            _ = self.polling_interval.tick() => {
+            _ = self.polling_interval.tick(), if !self.requested_state.suspended => {
                 telio_log_debug!("WG consolidation triggered by tick event, total logs dropped: {}", logs_dropped_until_now());
                 let dropped = logs_dropped_since_last_checked();
                 if dropped > 0 {
                     telio_log_warn!("New logs dropped: {dropped}");
                 }
+                self.apply_cellular_profile();
+                self.update_idle_peers();
+                if let Err(e) = self.rotate_trimmed_peers().await {
+                    telio_log_warn!("Failed to rotate trimmed meshnet peers: {e}");
+                }
                 wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
                     .boxed()
                     .await
@@ -2522,6 +5184,10 @@ impl TaskRuntime for Runtime {
                         |e| {
                             telio_log_warn!("WireGuard controller failure: {:?}. Ignoring", e);
                         });
+                self.report_unreachable_peers().await;
+                self.run_mesh_watchdog().await;
+                self.report_family_checks().await;
+                self.sample_memory_usage().await;
                 Ok(())
             },
 
@@ -2574,6 +5240,17 @@ impl TaskRuntime for Runtime {
 
         drop(self.entities.aggregator);
         drop(self.entities.network_monitor);
+        // Probes of `ping` hold the stack, they don't outlive the device
+        for probes in self.pings.into_values() {
+            probes.abort();
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(socks5) = self.entities.socks5 {
+            socks5.stop().await;
+        }
+        // Integrators may still hold the stack, it stops once the last reference is gone
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        drop(self.entities.netstack);
 
         stop_arc_entity!(self.entities.wireguard_interface, "WireguardInterface");
 
@@ -2614,6 +5291,15 @@ fn set_tunnel_interface(socket_pool: &Arc<SocketPool>, config: &DeviceConfig) {
     }
 }
 
+/// Hide the IP address of an endpoint, keeping its family and port
+fn redact_endpoint(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, addr.port())
+}
+
 fn node_from_exit_node(exit_node: &ExitNode) -> Node {
     Node {
         identifier: exit_node.identifier.clone(),
@@ -2643,7 +5329,7 @@ mod tests {
     use rstest::*;
     use std::net::Ipv6Addr;
     use telio_model::config::{Peer, PeerBase};
-    use telio_model::features::FeatureDirect;
+    use telio_model::features::{FeatureApiTimeout, FeatureDirect, FeatureDscp, FeaturePeerLimit};
     use telio_sockets::native::NativeSocket;
     use telio_sockets::Protector;
 
@@ -2680,6 +5366,89 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_direct_meshnet_config_skips_relay_only_peers() {
+        let mut relayed = build_peer(String::from("alpha.nord"), None, None);
+        relayed.base.public_key = SecretKey::gen().public();
+        let mut direct = build_peer(String::from("beta.nord"), None, None);
+        direct.base.public_key = SecretKey::gen().public();
+
+        let requested_state = RequestedState {
+            meshnet_config: Some(build_mesh_config(Some(vec![
+                relayed.clone(),
+                direct.clone(),
+            ]))),
+            relay_only_peers: HashSet::from([relayed.public_key]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            requested_state.direct_meshnet_config(),
+            Some(build_mesh_config(Some(vec![direct.clone()])))
+        );
+        assert_eq!(
+            requested_state.peer_path_preference(&relayed.public_key),
+            PathPreference::RelayOnly
+        );
+        assert_eq!(
+            requested_state.peer_path_preference(&direct.public_key),
+            PathPreference::DirectPreferred
+        );
+    }
+
+    #[test]
+    fn test_stuck_call_times_out_and_fails_fast() {
+        let features = Features {
+            api_timeout: Some(FeatureApiTimeout {
+                timeout_ms: 100,
+                fail_fast: true,
+            }),
+            ..Default::default()
+        };
+        let device = Device::new(features, |_| {}, None).unwrap();
+
+        assert!(matches!(
+            device.block_on(std::future::pending::<Result>()),
+            Err(Error::Timeout(_))
+        ));
+        // Not started, so the runtime cannot answer the health probe
+        assert!(matches!(
+            device.block_on(async { Ok(()) }),
+            Err(Error::RuntimeUnhealthy)
+        ));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_start_after_stop_timed_out() {
+        let mut device = Device::new(Features::default(), |_| {}, None).unwrap();
+        let config = DeviceConfig {
+            private_key: SecretKey::gen(),
+            ..Default::default()
+        };
+        device.start(&config).unwrap();
+
+        // Without any budget the stop can only complete in the background
+        device.features.api_timeout = Some(FeatureApiTimeout {
+            timeout_ms: 0,
+            fail_fast: false,
+        });
+        device.stop();
+        assert!(!device.is_running());
+        assert!(device.pending.is_some());
+        assert!(matches!(device.start(&config), Err(Error::Timeout(_))));
+
+        device.features.api_timeout = Some(FeatureApiTimeout {
+            timeout_ms: 10_000,
+            fail_fast: false,
+        });
+        device.start(&config).unwrap();
+        assert!(device.is_running());
+        assert!(device.pending.is_none());
+        device.stop();
+        assert!(device.pending.is_none());
+    }
+
     #[test]
     fn test_collect_dns_records() {
         let alpha_ipv4 = Ipv4Addr::new(1, 2, 3, 4);
@@ -2887,6 +5656,142 @@ mod tests {
         assert_eq!(records.len(), 4);
     }
 
+    #[test]
+    fn test_find_rekeyed_peers() {
+        let ip = |last| IpAddr::V4(Ipv4Addr::new(100, 64, 0, last));
+        let peer = |key: &PublicKey, ips: Vec<IpAddr>| Peer {
+            base: PeerBase {
+                identifier: format!("{key}"),
+                public_key: *key,
+                ip_addresses: Some(ips),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (alpha, beta, gamma, delta) = (
+            SecretKey::gen().public(),
+            SecretKey::gen().public(),
+            SecretKey::gen().public(),
+            SecretKey::gen().public(),
+        );
+
+        let old = build_mesh_config(Some(vec![
+            peer(&alpha, vec![ip(1)]),
+            peer(&beta, vec![ip(2)]),
+            peer(&gamma, vec![ip(3)]),
+        ]));
+        let new = build_mesh_config(Some(vec![
+            // Same key, nothing changed
+            peer(&alpha, vec![ip(1)]),
+            // New key, same IP
+            peer(&delta, vec![ip(2)]),
+            // Known key took over the IP of a removed peer
+            peer(&gamma, vec![ip(3), ip(4)]),
+        ]));
+
+        assert_eq!(
+            find_rekeyed_peers(Some(&old), Some(&new)),
+            vec![PeerRekeyed {
+                identifier: format!("{delta}"),
+                old_public_key: beta,
+                new_public_key: delta,
+                ip_addresses: vec![ip(2)],
+            }]
+        );
+        assert!(find_rekeyed_peers(None, Some(&new)).is_empty());
+        assert!(find_rekeyed_peers(Some(&old), None).is_empty());
+        assert!(find_rekeyed_peers(Some(&old), Some(&old)).is_empty());
+    }
+
+    #[test]
+    fn test_invalidated_dns_names() {
+        let ip = |last| IpAddr::V4(Ipv4Addr::new(100, 64, 0, last));
+        let old = Records::from([
+            ("alpha.nord".to_owned(), vec![ip(1)]),
+            ("*.alpha.nord".to_owned(), vec![ip(1)]),
+            ("beta.nord".to_owned(), vec![ip(2)]),
+            ("gamma.nord".to_owned(), vec![ip(3)]),
+        ]);
+        let new = Records::from([
+            ("alpha.nord".to_owned(), vec![ip(1)]),
+            ("beta.nord".to_owned(), vec![ip(4)]),
+            ("delta.nord".to_owned(), vec![ip(5)]),
+        ]);
+
+        assert_eq!(
+            invalidated_dns_names(&old, &new),
+            vec!["beta.nord".to_owned(), "gamma.nord".to_owned()]
+        );
+        assert!(invalidated_dns_names(&Records::new(), &new).is_empty());
+        assert!(invalidated_dns_names(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_is_same_meshnet_config() {
+        let peer = |key: &PublicKey| Peer {
+            base: PeerBase {
+                public_key: *key,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (alpha, beta) = (SecretKey::gen().public(), SecretKey::gen().public());
+
+        let config = build_mesh_config(Some(vec![peer(&alpha), peer(&beta)]));
+        let reordered = build_mesh_config(Some(vec![peer(&beta), peer(&alpha)]));
+        let changed = build_mesh_config(Some(vec![peer(&alpha)]));
+
+        assert!(is_same_meshnet_config(Some(&config), Some(&reordered)));
+        assert!(!is_same_meshnet_config(Some(&config), Some(&changed)));
+        assert!(!is_same_meshnet_config(Some(&config), None));
+        assert!(is_same_meshnet_config(None, None));
+    }
+
+    #[test]
+    fn test_redact_endpoint() {
+        assert_eq!(
+            redact_endpoint("10.0.0.1:1234".parse().unwrap()),
+            "0.0.0.0:1234".parse().unwrap()
+        );
+        assert_eq!(
+            redact_endpoint("[fd00::1]:4321".parse().unwrap()),
+            "[::]:4321".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_collect_meshnet_records() {
+        let alpha_ipv4 = Ipv4Addr::new(1, 2, 3, 4);
+        let beta_ipv4 = Ipv4Addr::new(2, 3, 4, 1);
+
+        let peers = Some(vec![
+            build_peer(
+                String::from("alpha.nord"),
+                Some(vec![IpAddr::V4(alpha_ipv4)]),
+                Some("beta".to_owned()),
+            ),
+            build_peer(
+                String::from("beta.nord"),
+                Some(vec![IpAddr::V4(beta_ipv4)]),
+                Some("gamma".to_owned()),
+            ),
+        ]);
+
+        let requested_state = RequestedState {
+            meshnet_config: Some(build_mesh_config(peers)),
+            ..Default::default()
+        };
+
+        let records = requested_state.collect_meshnet_records(false);
+        assert!(!records.contains_key("gamma.nord"));
+        assert_eq!(records["beta.nord"], vec![IpAddr::V4(beta_ipv4)]);
+
+        // Hostname wins over the conflicting nickname
+        let records = requested_state.collect_meshnet_records(true);
+        assert_eq!(records["gamma.nord"], vec![IpAddr::V4(beta_ipv4)]);
+        assert_eq!(records["beta.nord"], vec![IpAddr::V4(beta_ipv4)]);
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_mocked_adapter() {
@@ -2931,6 +5836,26 @@ mod tests {
         );
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_effective_features() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let features = Features {
+            peer_limit: Some(FeaturePeerLimit::default()),
+            dscp: Some(FeatureDscp { value: 64 }),
+            ..Default::default()
+        };
+        let config = DeviceConfig::default();
+
+        let rt = Runtime::start(sender, &config, features, None)
+            .await
+            .unwrap();
+
+        let effective = rt.effective_features();
+        assert_eq!(effective.peer_limit, Some(FeaturePeerLimit::default()));
+        assert_eq!(effective.dscp, None);
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_disconnect_exit_nodes() {
@@ -2962,6 +5887,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let get_config = Config {
             this: peer_base.clone(),
@@ -3038,6 +5964,7 @@ mod tests {
                 hostname: telio_utils::Hidden("hostname".to_owned()),
                 ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
                 nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+                metadata: None,
             },
             peers: Some(vec![
                 Peer {
@@ -3127,6 +6054,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -3188,6 +6116,212 @@ mod tests {
         rt.test_env.adapter.lock().await.checkpoint();
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_set_config_is_idempotent() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let private_key = SecretKey::gen();
+
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: private_key.clone(),
+                ..Default::default()
+            },
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let peer = |last| Peer {
+            base: PeerBase {
+                identifier: format!("peer-{last}"),
+                public_key: SecretKey::gen().public(),
+                hostname: telio_utils::Hidden(format!("peer-{last}.nord")),
+                ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, last))]),
+                nickname: None,
+                metadata: None,
+            },
+            ..Default::default()
+        };
+        let peers = vec![peer(2), peer(3)];
+        let config = Config {
+            this: PeerBase {
+                identifier: "this".to_owned(),
+                public_key: private_key.public(),
+                hostname: telio_utils::Hidden("this.nord".to_owned()),
+                ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))]),
+                nickname: None,
+                metadata: None,
+            },
+            peers: Some(peers.clone()),
+            derp_servers: None,
+            dns: None,
+        };
+        let reordered = Config {
+            peers: Some(peers.into_iter().rev().collect()),
+            ..config.clone()
+        };
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.entities
+            .wireguard_interface
+            .set_listen_port(1234)
+            .await
+            .unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .returning(|_| {
+                Ok(uapi::Response {
+                    errno: 0,
+                    interface: Some(Interface::default()),
+                })
+            });
+        rt.set_config(&Some(config.clone())).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        // Neither the same config nor the one with reordered peers reach the adapter
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(0)
+            .await;
+        rt.set_config(&Some(config)).await.unwrap();
+        rt.set_config(&Some(reordered)).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_set_config_rolls_back_on_apply_failure() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+        let private_key = SecretKey::gen();
+
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: private_key.clone(),
+                ..Default::default()
+            },
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let peer = |ip| Peer {
+            base: PeerBase {
+                public_key: SecretKey::gen().public(),
+                ip_addresses: Some(vec![IpAddr::V4(ip)]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let good_config = Config {
+            this: PeerBase {
+                identifier: "this".to_owned(),
+                public_key: private_key.public(),
+                hostname: telio_utils::Hidden("this.nord".to_owned()),
+                ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))]),
+                nickname: None,
+                metadata: None,
+            },
+            peers: Some(vec![peer(Ipv4Addr::new(100, 64, 0, 2))]),
+            derp_servers: None,
+            dns: None,
+        };
+        // Overlapping allowed IPs are only rejected once the peers reach the WireGuard controller
+        let bad_config = Config {
+            peers: Some(vec![
+                peer(Ipv4Addr::new(100, 64, 0, 3)),
+                peer(Ipv4Addr::new(100, 64, 0, 3)),
+            ]),
+            ..good_config.clone()
+        };
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.entities
+            .wireguard_interface
+            .set_listen_port(1234)
+            .await
+            .unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .returning(|_| {
+                Ok(uapi::Response {
+                    errno: 0,
+                    interface: Some(Interface::default()),
+                })
+            });
+        rt.set_config(&Some(good_config.clone())).await.unwrap();
+        assert!(rt.set_config(&Some(bad_config)).await.is_err());
+
+        assert_eq!(rt.requested_state.meshnet_config, Some(good_config.clone()));
+        assert_eq!(
+            rt.requested_state.meshnet_namespaces.merge().unwrap(),
+            Some(good_config)
+        );
+
+        let mut rollbacks = 0;
+        while let Ok(event) = receiver.try_recv() {
+            if let Event::ConfigRollback { body } = *event {
+                assert!(!body.cause.is_empty());
+                rollbacks += 1;
+            }
+        }
+        assert_eq!(rollbacks, 1);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_revoking_analytics_consent_is_acknowledged() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                ..Default::default()
+            },
+            Default::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        rt.set_analytics_consent(false).await.unwrap();
+        assert!(rt.entities.nurse.is_none());
+
+        let mut acknowledgements = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let Event::AnalyticsConsentChanged { body } = *event {
+                acknowledgements.push(body);
+            }
+        }
+        assert_eq!(
+            acknowledgements,
+            vec![AnalyticsConsentChanged {
+                consent: false,
+                purged: true,
+            }]
+        );
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_default_features_when_direct_is_empty() {
@@ -3226,6 +6360,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -3306,6 +6441,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -3398,6 +6534,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -3466,6 +6603,7 @@ mod tests {
                 hostname: telio_utils::Hidden("hostname".to_owned()),
                 ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
                 nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+                metadata: None,
             };
             Config {
                 this: peer_base.clone(),
@@ -3562,6 +6700,7 @@ mod tests {
                 hostname: telio_utils::Hidden("hostname".to_owned()),
                 ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
                 nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+                metadata: None,
             };
             Config {
                 this: peer_base.clone(),
@@ -3621,6 +6760,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Config {
             this: peer_base.clone(),
@@ -3734,6 +6874,7 @@ mod tests {
                 IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0xc00a, 0x2ff)),
             ]),
             nickname: Some(telio_utils::Hidden("nickname".to_owned())),
+            metadata: None,
         };
         let config = Config {
             this: peer_base.clone(),
@@ -0,0 +1,190 @@
+//! Audit trail of the peer keys with access to the meshnet of the device, so it can be told which
+//! identities could reach the device and when
+
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use telio_crypto::PublicKey;
+use telio_model::{
+    event::{AuditAction, AuditRecord, Event, Set},
+    features::FeatureAuditLog,
+};
+use telio_task::io::mc_chan::Tx;
+use telio_utils::telio_log_debug;
+
+/// Bounded log of the audit records, dropping the oldest ones once full
+pub(crate) struct AuditLog {
+    records: Mutex<VecDeque<AuditRecord>>,
+    capacity: usize,
+    /// Keys recorded as installed, so the peers removed and added back while keeping their
+    /// access, e.g. on rehandshakes, are not recorded again
+    installed: Mutex<HashSet<PublicKey>>,
+    /// Publishes every record as an event, for the app to persist it
+    events: Option<Tx<Box<Event>>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(feature: FeatureAuditLog, events: Tx<Box<Event>>) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            capacity: feature.capacity as usize,
+            installed: Mutex::new(HashSet::new()),
+            events: feature.events.then_some(events),
+        }
+    }
+
+    /// Record the action, stamped with the current time
+    pub(crate) fn record(&self, action: AuditAction, public_key: Option<PublicKey>) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        let record = AuditRecord {
+            timestamp_ms,
+            action,
+            public_key,
+        };
+        telio_log_debug!("Audit record {record:?}");
+
+        if let Some(events) = &self.events {
+            if let Some(event) = Event::builder::<AuditRecord>().set(record.clone()).build() {
+                let _ = events.send(Box::new(event));
+            }
+        }
+
+        let mut records = self.records.lock();
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        if self.capacity > 0 {
+            records.push_back(record);
+        }
+    }
+
+    /// Record the installation of the peer, unless it is installed already
+    pub(crate) fn peer_installed(&self, public_key: PublicKey) {
+        let is_new = self.installed.lock().insert(public_key);
+        if is_new {
+            self.record(AuditAction::PeerInstalled, Some(public_key));
+        }
+    }
+
+    /// Record the removal of the peer, if its installation was recorded
+    pub(crate) fn peer_removed(&self, public_key: PublicKey) {
+        let was_installed = self.installed.lock().remove(&public_key);
+        if was_installed {
+            self.record(AuditAction::PeerRemoved, Some(public_key));
+        }
+    }
+
+    /// Record the removal of the installed peers which lost their access, while being off the
+    /// interface
+    pub(crate) fn retain_peers(&self, has_access: impl Fn(&PublicKey) -> bool) {
+        let removed: Vec<_> = self
+            .installed
+            .lock()
+            .iter()
+            .filter(|public_key| !has_access(public_key))
+            .copied()
+            .collect();
+        for public_key in removed {
+            self.peer_removed(public_key);
+        }
+    }
+
+    /// Records kept so far, the oldest first
+    pub(crate) fn records(&self) -> Vec<AuditRecord> {
+        self.records.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use telio_task::io::McChan;
+
+    #[test]
+    fn oldest_records_are_dropped_once_full() {
+        let events = McChan::<Box<Event>>::default();
+        let mut events_rx = events.rx;
+        let audit_log = AuditLog::new(
+            FeatureAuditLog {
+                capacity: 2,
+                events: true,
+            },
+            events.tx,
+        );
+        let public_key = SecretKey::gen().public();
+
+        audit_log.record(AuditAction::MeshnetEnabled, None);
+        audit_log.record(AuditAction::PeerInstalled, Some(public_key));
+        audit_log.record(AuditAction::PeerRemoved, Some(public_key));
+
+        let records = audit_log.records();
+        let actions: Vec<_> = records.iter().map(|record| record.action).collect();
+        assert_eq!(
+            actions,
+            [AuditAction::PeerInstalled, AuditAction::PeerRemoved]
+        );
+        assert!(records
+            .iter()
+            .all(|record| record.public_key == Some(public_key)));
+        assert!(records[0].timestamp_ms <= records[1].timestamp_ms);
+
+        // Every record is published, including the dropped ones
+        let event = events_rx.try_recv().unwrap();
+        assert!(matches!(
+            *event,
+            Event::AuditRecord {
+                body: AuditRecord {
+                    action: AuditAction::MeshnetEnabled,
+                    ..
+                }
+            }
+        ));
+    }
+
+    #[test]
+    fn peers_keeping_their_access_are_recorded_once() {
+        let events = McChan::<Box<Event>>::default();
+        let audit_log = AuditLog::new(FeatureAuditLog::default(), events.tx);
+        let (kept, revoked) = (SecretKey::gen().public(), SecretKey::gen().public());
+
+        audit_log.peer_installed(kept);
+        audit_log.peer_installed(revoked);
+        // Added back after a rehandshake
+        audit_log.peer_installed(kept);
+        audit_log.retain_peers(|public_key| *public_key == kept);
+        audit_log.retain_peers(|public_key| *public_key == kept);
+        audit_log.peer_removed(revoked);
+
+        let records: Vec<_> = audit_log
+            .records()
+            .into_iter()
+            .map(|record| (record.action, record.public_key))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                (AuditAction::PeerInstalled, Some(kept)),
+                (AuditAction::PeerInstalled, Some(revoked)),
+                (AuditAction::PeerRemoved, Some(revoked)),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_are_only_published_if_enabled() {
+        let events = McChan::<Box<Event>>::default();
+        let mut events_rx = events.rx;
+        let audit_log = AuditLog::new(FeatureAuditLog::default(), events.tx);
+
+        audit_log.record(AuditAction::ExitNodeDisconnected, None);
+        assert_eq!(audit_log.records().len(), 1);
+        assert!(events_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Queue of the meshnet configs set while the device is unsettled, so a config arriving in the
+//! middle of a network change or a relay reconnect does not race it
+
+use telio_model::config::Config;
+use tokio::{
+    sync::oneshot,
+    time::{Duration, Instant},
+};
+
+/// Told the outcome of applying a config, or of the config coalesced into it
+pub(crate) type ConfigWaiter = oneshot::Sender<Result<(), String>>;
+
+/// Config to be applied, along with everyone waiting for it
+pub(crate) struct PendingConfig {
+    pub(crate) config: Option<Config>,
+    queued_at: Instant,
+    waiters: Vec<ConfigWaiter>,
+}
+
+impl PendingConfig {
+    pub(crate) fn new(config: Option<Config>, waiter: ConfigWaiter) -> Self {
+        Self {
+            config,
+            queued_at: Instant::now(),
+            waiters: vec![waiter],
+        }
+    }
+
+    /// Tell every waiter the outcome of applying the config
+    pub(crate) fn complete(self, result: Result<(), String>) {
+        for waiter in self.waiters {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+/// Holds back configs while the device is unsettled, keeping only the latest one
+pub(crate) struct ConfigQueue {
+    settle: Duration,
+    max_delay: Duration,
+    /// End of the unsettled period after the last network change
+    settled_at: Option<Instant>,
+    /// The relay is not in the middle of reconnecting
+    relay_settled: bool,
+    pending: Option<PendingConfig>,
+}
+
+impl ConfigQueue {
+    pub(crate) fn new(settle: Duration, max_delay: Duration) -> Self {
+        Self {
+            settle,
+            max_delay,
+            settled_at: None,
+            relay_settled: true,
+            pending: None,
+        }
+    }
+
+    /// The network changed, hold back configs for the settle time
+    pub(crate) fn unsettle(&mut self) {
+        self.settled_at = Some(Instant::now() + self.settle);
+    }
+
+    pub(crate) fn set_relay_settled(&mut self, settled: bool) {
+        self.relay_settled = settled;
+    }
+
+    fn is_settled(&self) -> bool {
+        self.relay_settled && self.settled_at.map_or(true, |at| at <= Instant::now())
+    }
+
+    /// Queue the config, `Some` if it should be applied right away
+    ///
+    /// A config queued behind another one replaces it, and its waiter is told the outcome of
+    /// applying the latest config.
+    pub(crate) fn submit(
+        &mut self,
+        config: Option<Config>,
+        waiter: ConfigWaiter,
+    ) -> Option<PendingConfig> {
+        match self.pending.as_mut() {
+            Some(pending) => {
+                pending.config = config;
+                pending.waiters.push(waiter);
+                None
+            }
+            None if self.is_settled() => Some(PendingConfig::new(config, waiter)),
+            None => {
+                self.pending = Some(PendingConfig::new(config, waiter));
+                None
+            }
+        }
+    }
+
+    /// Time at which the queued config is due, held back no longer than the max delay
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let pending = self.pending.as_ref()?;
+        let latest = pending.queued_at + self.max_delay;
+        if !self.relay_settled {
+            return Some(latest);
+        }
+        Some(self.settled_at.unwrap_or(pending.queued_at).min(latest))
+    }
+
+    /// Queued config, if it is due
+    pub(crate) fn take_due(&mut self) -> Option<PendingConfig> {
+        if self.next_deadline()? <= Instant::now() {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    fn config(tag: &str) -> Option<Config> {
+        Some(Config {
+            this: telio_model::config::PeerBase {
+                identifier: tag.to_owned(),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    fn submit(
+        queue: &mut ConfigQueue,
+        tag: &str,
+    ) -> (Option<PendingConfig>, oneshot::Receiver<Result<(), String>>) {
+        let (tx, rx) = oneshot::channel();
+        (queue.submit(config(tag), tx), rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn settled_device_applies_right_away() {
+        let mut queue = ConfigQueue::new(Duration::from_secs(2), Duration::from_secs(10));
+        let (due, _rx) = submit(&mut queue, "a");
+        assert_eq!(due.unwrap().config, config("a"));
+        assert_eq!(queue.next_deadline(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latest_config_is_applied_once_settled() {
+        let mut queue = ConfigQueue::new(Duration::from_secs(2), Duration::from_secs(10));
+        queue.unsettle();
+        let (due, mut first) = submit(&mut queue, "a");
+        assert!(due.is_none());
+        time::advance(Duration::from_secs(1)).await;
+        let (due, mut second) = submit(&mut queue, "b");
+        assert!(due.is_none());
+        assert!(queue.take_due().is_none());
+
+        time::advance(Duration::from_secs(1)).await;
+        let pending = queue.take_due().unwrap();
+        assert_eq!(pending.config, config("b"));
+        pending.complete(Err("failed".to_owned()));
+        // Both callers are told the outcome of the config which was applied
+        assert_eq!(first.try_recv().unwrap(), Err("failed".to_owned()));
+        assert_eq!(second.try_recv().unwrap(), Err("failed".to_owned()));
+        assert_eq!(queue.next_deadline(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnecting_relay_holds_back_up_to_max_delay() {
+        let mut queue = ConfigQueue::new(Duration::from_secs(2), Duration::from_secs(10));
+        queue.set_relay_settled(false);
+        let (due, _rx) = submit(&mut queue, "a");
+        assert!(due.is_none());
+        time::advance(Duration::from_secs(9)).await;
+        assert!(queue.take_due().is_none());
+        time::advance(Duration::from_secs(1)).await;
+        assert!(queue.take_due().is_some());
+
+        // Once the relay settles queued configs are applied right away
+        let (due, _rx) = submit(&mut queue, "b");
+        assert!(due.is_none());
+        queue.set_relay_settled(true);
+        assert!(queue.take_due().is_some());
+    }
+}
@@ -0,0 +1,145 @@
+//! Hysteresis for node state changes reported to the integrators
+
+use std::collections::HashMap;
+
+use telio_crypto::PublicKey;
+use telio_model::mesh::{Node, NodeState};
+use tokio::time::{Duration, Instant};
+
+/// Holds back nodes which drop from connected to connecting, and publishes them only if they do
+/// not come back before the hold time passes
+pub(crate) struct NodeDebouncer {
+    hold: Duration,
+    pending: HashMap<PublicKey, (Node, Instant)>,
+}
+
+impl NodeDebouncer {
+    pub(crate) fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Filter a node event against the last published one, `Some` if it should be published now
+    pub(crate) fn on_node(&mut self, node: Node, last_published: Option<&Node>) -> Option<Node> {
+        let was_connected = last_published.map_or(false, |n| n.state == NodeState::Connected);
+        if !was_connected || node.state != NodeState::Connecting {
+            self.pending.remove(&node.public_key);
+            return Some(node);
+        }
+
+        // Keep the deadline of the first drop, so repeated events do not extend the hold
+        let deadline = self
+            .pending
+            .get(&node.public_key)
+            .map_or_else(|| Instant::now() + self.hold, |(_, deadline)| *deadline);
+        self.pending.insert(node.public_key, (node, deadline));
+        None
+    }
+
+    /// Earliest time at which a held node is due
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|(_, deadline)| *deadline).min()
+    }
+
+    /// Held nodes which did not recover in time
+    pub(crate) fn take_due(&mut self) -> Vec<Node> {
+        let now = Instant::now();
+        let due: Vec<PublicKey> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(public_key, _)| *public_key)
+            .collect();
+        due.iter()
+            .filter_map(|public_key| self.pending.remove(public_key))
+            .map(|(node, _)| node)
+            .collect()
+    }
+
+    /// Drop a held node, if any
+    pub(crate) fn forget(&mut self, public_key: &PublicKey) {
+        self.pending.remove(public_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use tokio::time;
+
+    fn node(public_key: PublicKey, state: NodeState) -> Node {
+        Node {
+            public_key,
+            state,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn brief_drop_is_suppressed() {
+        let mut debouncer = NodeDebouncer::new(Duration::from_secs(3));
+        let pk = SecretKey::gen().public();
+        let connected = node(pk, NodeState::Connected);
+
+        assert!(debouncer
+            .on_node(node(pk, NodeState::Connecting), Some(&connected))
+            .is_none());
+        time::advance(Duration::from_secs(1)).await;
+        assert!(debouncer.take_due().is_empty());
+
+        assert_eq!(
+            debouncer.on_node(connected.clone(), Some(&connected)),
+            Some(connected)
+        );
+        assert_eq!(debouncer.next_deadline(), None);
+        time::advance(Duration::from_secs(3)).await;
+        assert!(debouncer.take_due().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lasting_drop_is_published_after_hold() {
+        let mut debouncer = NodeDebouncer::new(Duration::from_secs(3));
+        let pk = SecretKey::gen().public();
+        let connected = node(pk, NodeState::Connected);
+        let connecting = node(pk, NodeState::Connecting);
+
+        assert!(debouncer
+            .on_node(connecting.clone(), Some(&connected))
+            .is_none());
+        time::advance(Duration::from_secs(2)).await;
+        // Repeated events do not extend the hold
+        assert!(debouncer
+            .on_node(connecting.clone(), Some(&connected))
+            .is_none());
+        time::advance(Duration::from_secs(1)).await;
+        assert_eq!(debouncer.take_due(), vec![connecting]);
+        assert_eq!(debouncer.next_deadline(), None);
+    }
+
+    #[test]
+    fn other_changes_pass_through() {
+        let mut debouncer = NodeDebouncer::new(Duration::from_secs(3));
+        let pk = SecretKey::gen().public();
+        let connected = node(pk, NodeState::Connected);
+        let connecting = node(pk, NodeState::Connecting);
+        let disconnected = node(pk, NodeState::Disconnected);
+
+        // First connection attempt is not a drop
+        assert_eq!(
+            debouncer.on_node(connecting.clone(), None),
+            Some(connecting.clone())
+        );
+        // Disconnects are always reported right away, replacing any held node
+        assert!(debouncer
+            .on_node(connecting.clone(), Some(&connected))
+            .is_none());
+        assert_eq!(
+            debouncer.on_node(disconnected.clone(), Some(&connected)),
+            Some(disconnected)
+        );
+        assert_eq!(debouncer.next_deadline(), None);
+    }
+}
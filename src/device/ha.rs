@@ -0,0 +1,621 @@
+//! High availability pair of gateways, a standby instance taking over a failed primary
+//!
+//! The primary sends heartbeats to the standby, along with its meshnet config and exit node
+//! whenever the standby has not acknowledged them yet. The messages are encrypted to the public
+//! key shared by both instances, so only the other half of the pair can read or forge them.
+//!
+//! Every takeover starts a new term. An instance hearing the primary of a newer term steps
+//! down, so a primary which was only stalled does not keep serving along with the standby. Two
+//! primaries of the same term, e.g. both configured as primaries, are resolved by their random
+//! ids, the one with the lower id steps down.
+//!
+//! Each acknowledgement of the standby carries a random challenge, which the primary echoes in
+//! its next messages. Only a message echoing the current challenge proves the primary is alive,
+//! so replayed heartbeats do not hold off the takeover. The state is sent in parts small enough
+//! for a datagram each and put together by the standby.
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use telio_crypto::{
+    encryption::{decrypt_request, encrypt_request},
+    SecretKey,
+};
+use telio_model::{
+    config::Config,
+    features::{FeatureHighAvailability, HaRole},
+    mesh::ExitNode,
+    SocketAddr,
+};
+use telio_sockets::{External, SocketPool};
+use telio_utils::{telio_log_debug, telio_log_info, telio_log_warn};
+use tokio::{
+    net::UdpSocket,
+    time::{interval, Duration, Instant, Interval, MissedTickBehavior},
+};
+
+/// Largest payload of a UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// Largest part of the encoded state sent in one message, leaving room for escaping it within
+/// the message and for the encryption
+const MAX_STATE_PART_LEN: usize = 16 * 1024;
+/// Most parts of the state the standby puts together
+const MAX_STATE_PARTS: usize = 256;
+
+/// State mirrored from the primary to the standby
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct HaState {
+    pub(crate) meshnet_config: Option<Config>,
+    pub(crate) exit_node: Option<ExitNode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HaMessage {
+    /// Sent by the primary while the standby is up to date
+    Heartbeat {
+        term: u64,
+        version: u64,
+        id: u64,
+        echo: Option<u64>,
+    },
+    /// Sent by the primary until the standby acknowledges its state, one message per part of
+    /// the encoded state
+    State {
+        term: u64,
+        version: u64,
+        id: u64,
+        echo: Option<u64>,
+        part: u32,
+        parts: u32,
+        state: String,
+    },
+    /// Sent by the standby in reply, with the version of the state it holds and the challenge
+    /// to be echoed
+    Ack { version: Option<u64>, nonce: u64 },
+}
+
+/// Change of the role to be applied by the device
+#[derive(Debug)]
+pub(crate) enum HaAction {
+    /// The primary is gone, its mirrored state has to be applied
+    TakeOver(HaState),
+    /// A newer primary took over, the state of this instance has to be cleared
+    StepDown,
+}
+
+/// Roles and terms of the pair, along with the versions of the mirrored state
+struct HaCoordinator {
+    role: HaRole,
+    term: u64,
+    /// Random id breaking the tie between two primaries of the same term
+    id: u64,
+    takeover_timeout: Duration,
+    /// Last time the primary was heard of, while in the standby role
+    last_heard: Instant,
+    /// State of the primary with its version, while in the standby role
+    mirrored: Option<(u64, HaState)>,
+    /// Parts of the state of the primary received so far, with its version
+    pending: Option<(u64, Vec<Option<String>>)>,
+    /// Challenge the primary has to echo to be heard, while in the standby role
+    challenge: u64,
+    /// Challenge echoed last, parts of the state sent along with it are still accepted
+    previous_challenge: Option<u64>,
+    /// Version of the state of this instance, starting at random so that a restarted primary is
+    /// not mistaken for being in sync
+    version: u64,
+    last_state: Option<String>,
+    acked_version: Option<u64>,
+    /// Challenge of the standby to echo, while in the primary role
+    echo: Option<u64>,
+}
+
+impl HaCoordinator {
+    fn new(role: HaRole, takeover_timeout: Duration) -> Self {
+        Self {
+            role,
+            term: 0,
+            id: rand::random(),
+            takeover_timeout,
+            last_heard: Instant::now(),
+            mirrored: None,
+            pending: None,
+            challenge: rand::random(),
+            previous_challenge: None,
+            version: rand::random(),
+            last_state: None,
+            acked_version: None,
+            echo: None,
+        }
+    }
+
+    /// Messages to send to the other instance on a heartbeat, or the takeover once the primary
+    /// has been silent for too long
+    fn on_tick(&mut self, state: &HaState) -> (Vec<HaMessage>, Option<HaAction>) {
+        match self.role {
+            HaRole::Primary => {
+                let encoded = serde_json::to_string(state)
+                    .map_err(|e| telio_log_warn!("Failed to encode the state: {}", e))
+                    .ok();
+                if encoded != self.last_state {
+                    self.version = self.version.wrapping_add(1);
+                    self.last_state = encoded;
+                }
+                let messages = match &self.last_state {
+                    Some(encoded) if self.acked_version != Some(self.version) => {
+                        let parts = split_state(encoded);
+                        let count = parts.len() as u32;
+                        parts
+                            .into_iter()
+                            .enumerate()
+                            .map(|(part, state)| HaMessage::State {
+                                term: self.term,
+                                version: self.version,
+                                id: self.id,
+                                echo: self.echo,
+                                part: part as u32,
+                                parts: count,
+                                state: state.to_owned(),
+                            })
+                            .collect()
+                    }
+                    _ => vec![HaMessage::Heartbeat {
+                        term: self.term,
+                        version: self.version,
+                        id: self.id,
+                        echo: self.echo,
+                    }],
+                };
+                (messages, None)
+            }
+            HaRole::Standby if self.last_heard.elapsed() >= self.takeover_timeout => {
+                self.role = HaRole::Primary;
+                self.term += 1;
+                self.acked_version = None;
+                self.echo = None;
+                self.pending = None;
+                let state = self.mirrored.take().map(|(_, state)| state);
+                (
+                    Vec::new(),
+                    Some(HaAction::TakeOver(state.unwrap_or_default())),
+                )
+            }
+            HaRole::Standby => (Vec::new(), None),
+        }
+    }
+
+    /// Handle a message of the other instance, returning the reply and the role change if any
+    fn on_message(&mut self, message: HaMessage) -> (Option<HaMessage>, Option<HaAction>) {
+        let (term, version, id, echo, part) = match message {
+            HaMessage::Ack { version, nonce } => {
+                if self.role == HaRole::Primary {
+                    self.acked_version = version;
+                    self.echo = Some(nonce);
+                }
+                return (None, None);
+            }
+            HaMessage::Heartbeat {
+                term,
+                version,
+                id,
+                echo,
+            } => (term, version, id, echo, None),
+            HaMessage::State {
+                term,
+                version,
+                id,
+                echo,
+                part,
+                parts,
+                state,
+            } => (term, version, id, echo, Some((part, parts, state))),
+        };
+
+        if term < self.term {
+            // A stale primary, it steps down once it hears ours
+            telio_log_debug!("Ignoring the primary of an old term {}", term);
+            return (None, None);
+        }
+
+        let mut action = None;
+        if self.role == HaRole::Primary {
+            if term == self.term && id <= self.id {
+                // The other primary steps down once it hears ours
+                telio_log_warn!("Both instances are primaries of term {}, staying", term);
+                return (None, None);
+            }
+            telio_log_warn!("Stepping down for the primary of term {}", term);
+            self.role = HaRole::Standby;
+            self.echo = None;
+            // The new primary has a full timeout to answer our challenge
+            self.last_heard = Instant::now();
+            action = Some(HaAction::StepDown);
+        }
+        self.term = term;
+
+        if echo == Some(self.challenge) {
+            self.last_heard = Instant::now();
+            self.previous_challenge = Some(self.challenge);
+            self.challenge = rand::random();
+        }
+        // Parts of a replayed state echo a challenge of the past
+        if echo.is_some() && echo == self.previous_challenge {
+            if let Some((part, parts, state)) = part {
+                self.add_state_part(version, part as usize, parts as usize, state);
+            }
+        }
+
+        let version = self.mirrored.as_ref().map(|(version, _)| *version);
+        let nonce = self.challenge;
+        (Some(HaMessage::Ack { version, nonce }), action)
+    }
+
+    /// Keep the part of the state, mirroring the state once all of its parts are received
+    fn add_state_part(&mut self, version: u64, part: usize, parts: usize, state: String) {
+        if part >= parts || parts > MAX_STATE_PARTS {
+            telio_log_warn!("Dropping part {} of {} of the state", part, parts);
+            return;
+        }
+        if self.mirrored.as_ref().map(|(v, _)| *v) == Some(version) {
+            return;
+        }
+        if !matches!(&self.pending, Some((v, pending)) if *v == version && pending.len() == parts) {
+            self.pending = Some((version, vec![None; parts]));
+        }
+        let Some((_, pending)) = self.pending.as_mut() else {
+            return;
+        };
+        pending[part] = Some(state);
+        if pending.iter().any(Option::is_none) {
+            return;
+        }
+
+        let encoded: String = pending.iter().flatten().map(String::as_str).collect();
+        self.pending = None;
+        match serde_json::from_str(&encoded) {
+            Ok(state) => self.mirrored = Some((version, state)),
+            Err(e) => telio_log_warn!("Dropping a malformed state: {}", e),
+        }
+    }
+}
+
+/// Split the encoded state into parts of at most [MAX_STATE_PART_LEN] bytes
+fn split_state(mut encoded: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    while !encoded.is_empty() || parts.is_empty() {
+        let mut end = encoded.len().min(MAX_STATE_PART_LEN);
+        while !encoded.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, rest) = encoded.split_at(end);
+        parts.push(part);
+        encoded = rest;
+    }
+    parts
+}
+
+/// Event of the link to the other instance
+pub(crate) enum HaEvent {
+    /// Time for the next heartbeat
+    Tick,
+    /// Datagram received from the other instance
+    Received(Vec<u8>),
+}
+
+/// Link to the other instance of the pair
+pub(crate) struct HighAvailability {
+    coordinator: HaCoordinator,
+    socket: External<UdpSocket>,
+    peer_address: SocketAddr,
+    heartbeat: Interval,
+}
+
+impl HighAvailability {
+    pub(crate) async fn start(
+        config: &FeatureHighAvailability,
+        socket_pool: &SocketPool,
+    ) -> io::Result<Self> {
+        let socket = socket_pool
+            .new_external_udp(config.listen_address, None)
+            .await?;
+        let mut heartbeat = interval(Duration::from_millis(config.heartbeat_interval_ms.max(1)));
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        telio_log_info!(
+            "Starting high availability as {:?}, peer {:?}",
+            config.role,
+            config.peer_address
+        );
+
+        Ok(Self {
+            coordinator: HaCoordinator::new(
+                config.role,
+                Duration::from_millis(config.takeover_timeout_ms),
+            ),
+            socket,
+            peer_address: config.peer_address,
+            heartbeat,
+        })
+    }
+
+    pub(crate) fn role(&self) -> HaRole {
+        self.coordinator.role
+    }
+
+    pub(crate) fn term(&self) -> u64 {
+        self.coordinator.term
+    }
+
+    pub(crate) async fn next_event(&mut self) -> HaEvent {
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        loop {
+            tokio::select! {
+                _ = self.heartbeat.tick() => return HaEvent::Tick,
+                res = self.socket.recv_from(&mut buf) => match res {
+                    Ok((len, _)) => return HaEvent::Received(buf[..len].to_vec()),
+                    Err(e) => telio_log_warn!("Failed to receive from the other instance: {}", e),
+                },
+            }
+        }
+    }
+
+    /// Handle the event with the current state of this instance, returning the role change
+    /// to be applied
+    pub(crate) async fn handle(
+        &mut self,
+        event: HaEvent,
+        secret_key: &SecretKey,
+        state: &HaState,
+    ) -> Option<HaAction> {
+        let (messages, action) = match event {
+            HaEvent::Tick => self.coordinator.on_tick(state),
+            HaEvent::Received(bytes) => match Self::decode(&bytes, secret_key) {
+                Some(message) => {
+                    let (reply, action) = self.coordinator.on_message(message);
+                    (reply.into_iter().collect(), action)
+                }
+                None => (Vec::new(), None),
+            },
+        };
+
+        for message in messages.iter() {
+            self.send(message, secret_key).await;
+        }
+        action
+    }
+
+    fn decode(bytes: &[u8], secret_key: &SecretKey) -> Option<HaMessage> {
+        let public_key = secret_key.public();
+        let (plain, _) = decrypt_request(bytes, secret_key, |pk| *pk == public_key)
+            .map_err(|e| {
+                telio_log_warn!("Dropping a message not sent by the other instance: {}", e)
+            })
+            .ok()?;
+        serde_json::from_slice(&plain)
+            .map_err(|e| telio_log_warn!("Dropping a malformed message: {}", e))
+            .ok()
+    }
+
+    async fn send(&self, message: &HaMessage, secret_key: &SecretKey) {
+        let encrypted = serde_json::to_vec(message)
+            .map_err(|e| e.to_string())
+            .and_then(|plain| {
+                encrypt_request(
+                    &plain,
+                    &mut rand::thread_rng(),
+                    secret_key,
+                    &secret_key.public(),
+                )
+                .map_err(|e| e.to_string())
+            });
+        match encrypted {
+            Ok(buf) if buf.len() > MAX_DATAGRAM_SIZE => {
+                telio_log_warn!("Message of {} bytes is too large to be sent", buf.len());
+            }
+            Ok(buf) => {
+                if let Err(e) = self.socket.send_to(&buf, self.peer_address).await {
+                    telio_log_warn!("Failed to send to the other instance: {}", e);
+                }
+            }
+            Err(e) => telio_log_warn!("Failed to encode a message: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::PublicKey;
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn state(exit_node: PublicKey) -> HaState {
+        HaState {
+            meshnet_config: None,
+            exit_node: Some(ExitNode {
+                public_key: exit_node,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Challenge of the standby, handed to the primary by replying to a heartbeat
+    fn challenge(standby: &mut HaCoordinator, term: u64) -> u64 {
+        match standby.on_message(HaMessage::Heartbeat {
+            term,
+            version: 0,
+            id: 0,
+            echo: None,
+        }) {
+            (Some(HaMessage::Ack { nonce, .. }), _) => nonce,
+            other => panic!("Unexpected {other:?}"),
+        }
+    }
+
+    fn heartbeat(term: u64, echo: u64) -> HaMessage {
+        HaMessage::Heartbeat {
+            term,
+            version: 7,
+            id: 0,
+            echo: Some(echo),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn standby_takes_over_once_the_primary_is_silent() {
+        let exit_node = SecretKey::gen().public();
+        let mut standby = HaCoordinator::new(HaRole::Standby, TIMEOUT);
+        let mut primary = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        primary.echo = Some(challenge(&mut standby, 0));
+
+        let (messages, _) = primary.on_tick(&state(exit_node));
+        let version = primary.version;
+        for message in messages {
+            assert!(matches!(
+                standby.on_message(message),
+                (Some(HaMessage::Ack { .. }), None)
+            ));
+        }
+        assert_eq!(standby.mirrored.as_ref().map(|(v, _)| *v), Some(version));
+
+        time::advance(TIMEOUT / 2).await;
+        assert!(matches!(standby.on_tick(&HaState::default()), (m, None) if m.is_empty()));
+        let nonce = challenge(&mut standby, 0);
+        standby.on_message(heartbeat(0, nonce));
+        time::advance(TIMEOUT / 2).await;
+        assert!(matches!(standby.on_tick(&HaState::default()), (m, None) if m.is_empty()));
+
+        time::advance(TIMEOUT / 2).await;
+        match standby.on_tick(&HaState::default()) {
+            (m, Some(HaAction::TakeOver(state))) if m.is_empty() => {
+                assert_eq!(state.exit_node.map(|n| n.public_key), Some(exit_node));
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
+        assert_eq!(standby.role, HaRole::Primary);
+        assert_eq!(standby.term, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replayed_heartbeat_does_not_hold_off_the_takeover() {
+        let mut standby = HaCoordinator::new(HaRole::Standby, TIMEOUT);
+        let nonce = challenge(&mut standby, 0);
+        standby.on_message(heartbeat(0, nonce));
+
+        for _ in 0..4 {
+            time::advance(TIMEOUT / 2).await;
+            standby.on_message(heartbeat(0, nonce));
+        }
+        assert!(matches!(
+            standby.on_tick(&HaState::default()),
+            (_, Some(HaAction::TakeOver(_)))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn primary_sends_its_state_until_acknowledged() {
+        let mut primary = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        let current = state(SecretKey::gen().public());
+
+        let version = match primary.on_tick(&current) {
+            (m, None) => match m.as_slice() {
+                [HaMessage::State { version, .. }] => *version,
+                other => panic!("Unexpected {other:?}"),
+            },
+            other => panic!("Unexpected {other:?}"),
+        };
+        assert!(matches!(
+            primary.on_tick(&current).0.as_slice(),
+            [HaMessage::State { .. }]
+        ));
+
+        primary.on_message(HaMessage::Ack {
+            version: Some(version),
+            nonce: 3,
+        });
+        assert!(matches!(
+            primary.on_tick(&current).0.as_slice(),
+            [HaMessage::Heartbeat { term: 0, version: v, echo: Some(3), .. }] if *v == version
+        ));
+
+        // A changed state is sent again under a new version
+        let changed = state(SecretKey::gen().public());
+        assert!(matches!(
+            primary.on_tick(&changed).0.as_slice(),
+            [HaMessage::State { version: v, .. }] if *v == version.wrapping_add(1)
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn large_state_is_mirrored_in_parts() {
+        let mut standby = HaCoordinator::new(HaRole::Standby, TIMEOUT);
+        let mut primary = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        primary.echo = Some(challenge(&mut standby, 0));
+
+        let large = HaState {
+            meshnet_config: Some(Config {
+                peers: Some(
+                    (0..1000)
+                        .map(|_| telio_model::config::Peer {
+                            base: telio_model::config::PeerBase {
+                                public_key: SecretKey::gen().public(),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            exit_node: None,
+        };
+        let (messages, _) = primary.on_tick(&large);
+        assert!(messages.len() > 1);
+
+        // Parts may arrive in any order
+        for message in messages.into_iter().rev() {
+            standby.on_message(message);
+        }
+        let peers = |state: &HaState| state.meshnet_config.as_ref().and_then(|c| c.peers.clone());
+        let mirrored = standby.mirrored.as_ref().map(|(_, state)| peers(state));
+        assert_eq!(mirrored, Some(peers(&large)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn primary_steps_down_for_a_newer_term() {
+        let mut primary = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        primary.term = 1;
+
+        // The stale primary is ignored
+        assert!(matches!(primary.on_message(heartbeat(0, 1)), (None, None)));
+        assert_eq!(primary.role, HaRole::Primary);
+
+        let (reply, action) = primary.on_message(heartbeat(2, 1));
+        assert!(matches!(reply, Some(HaMessage::Ack { version: None, .. })));
+        assert!(matches!(action, Some(HaAction::StepDown)));
+        assert_eq!(primary.role, HaRole::Standby);
+        assert_eq!(primary.term, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn primary_with_lower_id_steps_down_for_the_same_term() {
+        let mut lower = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        let mut higher = HaCoordinator::new(HaRole::Primary, TIMEOUT);
+        lower.id = 1;
+        higher.id = 2;
+
+        for message in lower.on_tick(&HaState::default()).0 {
+            assert!(matches!(higher.on_message(message), (None, None)));
+        }
+        assert_eq!(higher.role, HaRole::Primary);
+
+        for message in higher.on_tick(&HaState::default()).0 {
+            assert!(matches!(
+                lower.on_message(message),
+                (Some(HaMessage::Ack { .. }), Some(HaAction::StepDown))
+            ));
+        }
+        assert_eq!(lower.role, HaRole::Standby);
+        assert_eq!(lower.term, 0);
+    }
+}
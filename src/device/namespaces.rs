@@ -0,0 +1,325 @@
+//! Several meshnets applied over a single adapter
+//!
+//! Every namespace is a separate meshnet config, which is merged with the others into the single
+//! config driving the device. Peers of named namespaces are served in their own DNS zone, i.e.
+//! `host.nord` of the `work` namespace becomes `host.work.nord`.
+
+use std::collections::{BTreeMap, HashSet};
+
+use telio_model::config::{Config, MeshnetNamespacePolicy};
+use telio_utils::{telio_log_warn, Hidden};
+
+const MESHNET_ZONE_SUFFIX: &str = ".nord";
+const MAX_NAMESPACE_LENGTH: usize = 63;
+
+/// Namespace errors
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    /// Namespace is not a valid DNS label
+    #[error("Invalid meshnet namespace {0:?}")]
+    InvalidName(String),
+    /// All namespaces are applied over a single adapter, so they must share the key
+    #[error("Meshnet namespace {0:?} is configured for a different public key")]
+    KeyMismatch(String),
+}
+
+/// Meshnet configs requested by the integrators
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MeshnetNamespaces {
+    default: Option<Config>,
+    named: BTreeMap<String, (Config, MeshnetNamespacePolicy)>,
+}
+
+impl MeshnetNamespaces {
+    /// Replace the config of the default namespace, as set by `set_config`
+    pub(crate) fn set_default(&mut self, config: Option<Config>) {
+        self.default = config;
+    }
+
+    /// Remove all of the namespaces, the default one included
+    pub(crate) fn clear(&mut self) {
+        self.default = None;
+        self.named.clear();
+    }
+
+    /// Replace the config of a named namespace, `None` removes the namespace
+    pub(crate) fn set(
+        &mut self,
+        namespace: &str,
+        config: Option<(Config, MeshnetNamespacePolicy)>,
+    ) -> Result<(), Error> {
+        if !is_valid_namespace(namespace) {
+            return Err(Error::InvalidName(namespace.to_owned()));
+        }
+
+        match config {
+            Some(config) => {
+                self.named.insert(namespace.to_owned(), config);
+            }
+            None => {
+                self.named.remove(namespace);
+            }
+        }
+        Ok(())
+    }
+
+    /// Merge all of the namespaces into a single config, `None` if none is set.
+    ///
+    /// The default namespace takes priority, followed by named namespaces in alphabetical order.
+    /// Peer which is present in several namespaces is only taken from the first one.
+    pub(crate) fn merge(&self) -> Result<Option<Config>, Error> {
+        let named = self.named.iter().map(|(namespace, (config, policy))| {
+            let mut config = config.clone();
+            qualify(&mut config, namespace, policy);
+            (namespace.as_str(), config)
+        });
+        let mut configs = self.default.iter().cloned().map(|c| ("", c)).chain(named);
+
+        let Some((_, mut merged)) = configs.next() else {
+            return Ok(None);
+        };
+        let mut known_peers: HashSet<_> = merged
+            .peers
+            .iter()
+            .flatten()
+            .map(|p| p.public_key)
+            .collect();
+
+        for (namespace, config) in configs {
+            if config.this.public_key != merged.this.public_key {
+                return Err(Error::KeyMismatch(namespace.to_owned()));
+            }
+
+            if let Some(ips) = config.this.ip_addresses {
+                let merged_ips = merged.this.ip_addresses.get_or_insert_with(Vec::new);
+                for ip in ips {
+                    if !merged_ips.contains(&ip) {
+                        merged_ips.push(ip);
+                    }
+                }
+            }
+
+            for peer in config.peers.into_iter().flatten() {
+                if !known_peers.insert(peer.public_key) {
+                    telio_log_warn!(
+                        "Peer {:?} of namespace {namespace:?} is already configured, ignoring",
+                        peer.public_key
+                    );
+                    continue;
+                }
+                merged.peers.get_or_insert_with(Vec::new).push(peer);
+            }
+
+            if merged.derp_servers.is_none() {
+                merged.derp_servers = config.derp_servers;
+            }
+            if merged.dns.is_none() {
+                merged.dns = config.dns;
+            }
+        }
+
+        Ok(Some(merged))
+    }
+}
+
+/// Namespace becomes a part of the DNS names, so it has to be a valid label
+fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace.len() <= MAX_NAMESPACE_LENGTH
+        && !namespace.starts_with('-')
+        && !namespace.ends_with('-')
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Move the nodes of the config into the DNS zone of the namespace and apply its policy
+fn qualify(config: &mut Config, namespace: &str, policy: &MeshnetNamespacePolicy) {
+    let zone = |hostname: &Hidden<String>| {
+        let host = hostname
+            .0
+            .strip_suffix(MESHNET_ZONE_SUFFIX)
+            .unwrap_or(&hostname.0);
+        Hidden(format!("{host}.{namespace}{MESHNET_ZONE_SUFFIX}"))
+    };
+
+    config.this.hostname = zone(&config.this.hostname);
+    // Nicknames are not qualified by any zone, so they are only served for the default namespace
+    config.this.nickname = None;
+    for peer in config.peers.iter_mut().flatten() {
+        peer.base.hostname = zone(&peer.base.hostname);
+        peer.base.nickname = None;
+        policy.restrict(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use telio_crypto::{PublicKey, SecretKey};
+    use telio_model::config::{Peer, PeerBase};
+
+    fn config(this: PublicKey, ip: &str, peers: &[(PublicKey, &str)]) -> Config {
+        Config {
+            this: PeerBase {
+                public_key: this,
+                hostname: Hidden("this.nord".to_owned()),
+                ip_addresses: Some(vec![ip.parse().unwrap()]),
+                ..Default::default()
+            },
+            peers: Some(
+                peers
+                    .iter()
+                    .map(|(public_key, hostname)| Peer {
+                        base: PeerBase {
+                            public_key: *public_key,
+                            hostname: Hidden(hostname.to_string()),
+                            nickname: Some(Hidden("nick".to_owned())),
+                            ..Default::default()
+                        },
+                        allow_incoming_connections: true,
+                        allow_peer_send_files: true,
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_namespaces_merge_to_none() {
+        assert_eq!(MeshnetNamespaces::default().merge(), Ok(None));
+    }
+
+    #[test]
+    fn invalid_namespaces_are_rejected() {
+        let mut namespaces = MeshnetNamespaces::default();
+        for name in ["", "-work", "work.nord", "a".repeat(64).as_str()] {
+            assert_eq!(
+                namespaces.set(name, None),
+                Err(Error::InvalidName(name.to_owned()))
+            );
+        }
+        assert_eq!(namespaces.set("work-1", None), Ok(()));
+    }
+
+    #[test]
+    fn namespaces_are_merged() {
+        let this = SecretKey::gen().public();
+        let (alice, bob, carol) = (
+            SecretKey::gen().public(),
+            SecretKey::gen().public(),
+            SecretKey::gen().public(),
+        );
+
+        let mut namespaces = MeshnetNamespaces::default();
+        namespaces.set_default(Some(config(
+            this,
+            "100.64.0.1",
+            &[(alice, "alice.nord"), (bob, "bob.nord")],
+        )));
+        namespaces
+            .set(
+                "work",
+                Some((
+                    config(
+                        this,
+                        "100.64.1.1",
+                        &[(bob, "bob.nord"), (carol, "carol.nord")],
+                    ),
+                    MeshnetNamespacePolicy {
+                        allow_peer_send_files: false,
+                        ..Default::default()
+                    },
+                )),
+            )
+            .unwrap();
+
+        let merged = namespaces.merge().unwrap().unwrap();
+        assert_eq!(merged.this.hostname.0, "this.nord");
+        assert_eq!(
+            merged.this.ip_addresses,
+            Some(vec![
+                "100.64.0.1".parse::<IpAddr>().unwrap(),
+                "100.64.1.1".parse().unwrap()
+            ])
+        );
+
+        let peers = merged.peers.unwrap();
+        let hostnames: Vec<_> = peers
+            .iter()
+            .map(|p| (p.public_key, p.hostname.0.as_str()))
+            .collect();
+        assert_eq!(
+            hostnames,
+            vec![
+                (alice, "alice.nord"),
+                (bob, "bob.nord"),
+                (carol, "carol.work.nord")
+            ]
+        );
+
+        // Default namespace is untouched, the named one is restricted by its policy
+        assert!(peers[1].allow_peer_send_files);
+        assert!(peers[1].nickname.is_some());
+        assert!(!peers[2].allow_peer_send_files);
+        assert!(peers[2].allow_incoming_connections);
+        assert!(peers[2].nickname.is_none());
+    }
+
+    #[test]
+    fn namespaces_must_share_the_key() {
+        let mut namespaces = MeshnetNamespaces::default();
+        namespaces.set_default(Some(config(SecretKey::gen().public(), "100.64.0.1", &[])));
+        namespaces
+            .set(
+                "work",
+                Some((
+                    config(SecretKey::gen().public(), "100.64.1.1", &[]),
+                    Default::default(),
+                )),
+            )
+            .unwrap();
+
+        assert_eq!(
+            namespaces.merge(),
+            Err(Error::KeyMismatch("work".to_owned()))
+        );
+    }
+
+    #[test]
+    fn named_namespace_works_without_default() {
+        let this = SecretKey::gen().public();
+        let mut namespaces = MeshnetNamespaces::default();
+        namespaces
+            .set(
+                "work",
+                Some((config(this, "100.64.1.1", &[]), Default::default())),
+            )
+            .unwrap();
+
+        let merged = namespaces.merge().unwrap().unwrap();
+        assert_eq!(merged.this.hostname.0, "this.work.nord");
+
+        namespaces.set("work", None).unwrap();
+        assert_eq!(namespaces.merge(), Ok(None));
+    }
+
+    #[test]
+    fn clearing_removes_named_namespaces() {
+        let this = SecretKey::gen().public();
+        let mut namespaces = MeshnetNamespaces::default();
+        namespaces.set_default(Some(config(this, "100.64.0.1", &[])));
+        namespaces
+            .set(
+                "work",
+                Some((config(this, "100.64.1.1", &[]), Default::default())),
+            )
+            .unwrap();
+
+        namespaces.clear();
+        assert_eq!(namespaces.merge(), Ok(None));
+    }
+}
@@ -0,0 +1,133 @@
+//! On-demand activation of the meshnet peers
+//!
+//! Rarely used peers stay on the adapter, so that the traffic to their meshnet IPs is still
+//! routed to them, but without keepalives and without keeping the direct connection machinery
+//! busy. The traffic sent to a peer, as seen by the outbound packet filter of the tunnel, wakes
+//! it up right away, and the peer is quiesced again once idle for long enough.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::RwLock;
+use telio_crypto::PublicKey;
+use tokio::{
+    sync::Notify,
+    time::{Duration, Instant},
+};
+
+/// No packet was sent to the peer since it is watched
+const NEVER: u64 = u64::MAX;
+
+pub(crate) struct TrafficWatcher {
+    idle_timeout: Duration,
+    /// Packet times are kept in milliseconds since the start of the watcher
+    started: Instant,
+    /// Last time a packet was sent to each of the watched peers. The peers are only added and
+    /// removed when the idle ones are checked, the packets just update their times.
+    last_sent: RwLock<HashMap<PublicKey, AtomicU64>>,
+    woken: Notify,
+}
+
+impl TrafficWatcher {
+    pub(crate) fn new(idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            started: Instant::now(),
+            last_sent: RwLock::new(HashMap::new()),
+            woken: Notify::new(),
+        }
+    }
+
+    /// A packet is being sent to the peer, called for every packet so it must stay cheap
+    pub(crate) fn observe(&self, public_key: &[u8; 32]) {
+        let last_sent = self.last_sent.read();
+        // Peers which are not watched are never idle, so there is nothing to wake up
+        let Some(at) = last_sent.get(&PublicKey(*public_key)) else {
+            return;
+        };
+        let now = millis(self.started.elapsed());
+        let previous = at.load(Ordering::Relaxed);
+        if previous == now {
+            return;
+        }
+        at.store(now, Ordering::Relaxed);
+        if self.is_idle(previous, now) {
+            self.woken.notify_one();
+        }
+    }
+
+    /// Wait for traffic to one of the idle peers
+    pub(crate) async fn woken(&self) {
+        self.woken.notified().await
+    }
+
+    /// Peers among the given ones which have not been sent anything for the idle timeout, the
+    /// given peers are watched from now on and the others are forgotten
+    pub(crate) fn idle_peers(
+        &self,
+        peers: impl IntoIterator<Item = PublicKey>,
+    ) -> HashSet<PublicKey> {
+        let now = millis(self.started.elapsed());
+        let peers: HashSet<PublicKey> = peers.into_iter().collect();
+        let mut last_sent = self.last_sent.write();
+        last_sent.retain(|public_key, _| peers.contains(public_key));
+        peers
+            .into_iter()
+            .filter(|public_key| {
+                let at = last_sent
+                    .entry(*public_key)
+                    .or_insert_with(|| AtomicU64::new(NEVER));
+                self.is_idle(at.load(Ordering::Relaxed), now)
+            })
+            .collect()
+    }
+
+    fn is_idle(&self, last_sent: u64, now: u64) -> bool {
+        last_sent == NEVER || now.saturating_sub(last_sent) >= millis(self.idle_timeout)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(NEVER - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn traffic_wakes_up_idle_peers() {
+        let watcher = TrafficWatcher::new(Duration::from_secs(300));
+        let a = SecretKey::gen().public();
+        let b = SecretKey::gen().public();
+        assert_eq!(watcher.idle_peers([a, b]), HashSet::from([a, b]));
+
+        watcher.observe(&a.0);
+        // Waking up an idle peer is signalled even if nobody waits yet
+        time::timeout(Duration::from_secs(1), watcher.woken())
+            .await
+            .unwrap();
+        assert_eq!(watcher.idle_peers([a, b]), HashSet::from([b]));
+
+        time::advance(Duration::from_secs(299)).await;
+        watcher.observe(&a.0);
+        // Traffic to an active peer does not wake anything up
+        assert!(time::timeout(Duration::from_secs(1), watcher.woken())
+            .await
+            .is_err());
+
+        time::advance(Duration::from_secs(300)).await;
+        assert_eq!(watcher.idle_peers([a, b]), HashSet::from([a, b]));
+
+        // Peers removed from the meshnet are not watched anymore
+        assert_eq!(watcher.idle_peers([a]), HashSet::from([a]));
+        watcher.observe(&b.0);
+        assert!(time::timeout(Duration::from_secs(1), watcher.woken())
+            .await
+            .is_err());
+    }
+}
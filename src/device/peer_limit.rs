@@ -0,0 +1,220 @@
+//! Limit on the number of meshnet peers configured on the adapter
+//!
+//! Every peer costs memory in the adapter and in the meshnet components, so on devices with
+//! little memory the app can cap the number of meshnet peers. Configs exceeding the limit are
+//! either rejected or trimmed down to the peers online, the remaining slots being rotated among
+//! the offline peers so that each of them gets a chance to come online.
+
+use std::{collections::HashSet, time::Duration};
+
+use telio_crypto::PublicKey;
+use telio_model::{
+    config::Config,
+    features::{FeaturePeerLimit, PeerLimitStrategy},
+};
+use telio_utils::telio_log_warn;
+use tokio::time::Instant;
+
+use super::Error;
+
+/// Time after the last handshake during which the peer is considered online
+const ONLINE_WINDOW: Duration = Duration::from_secs(180);
+
+/// Time after which the slots of the offline peers go to the next ones in the config
+const ROTATION_PERIOD: Duration = Duration::from_secs(120);
+
+/// Maximum number of meshnet peers and the handling of the configs exceeding it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PeerLimit {
+    max_peers: usize,
+    strategy: PeerLimitStrategy,
+    /// Position among the offline peers the rotation continues from
+    cursor: usize,
+    /// When the config was last trimmed, `None` while it fits the limit
+    trimmed_at: Option<Instant>,
+}
+
+impl PeerLimit {
+    pub(crate) fn new(feature: FeaturePeerLimit) -> Self {
+        PeerLimit {
+            max_peers: feature.max_peers as usize,
+            strategy: feature.strategy,
+            cursor: 0,
+            trimmed_at: None,
+        }
+    }
+
+    /// Whether the last applied config was trimmed long enough ago for the offline peers to
+    /// be rotated
+    pub(crate) fn rotation_due(&self) -> bool {
+        self.trimmed_at
+            .is_some_and(|trimmed_at| trimmed_at.elapsed() >= ROTATION_PERIOD)
+    }
+
+    /// Enforce the limit on the config
+    ///
+    /// `last_seen` gives the time since the peer was last heard from, `None` for peers never
+    /// seen. Trimming keeps the remaining peers in their original order.
+    pub(crate) fn apply(
+        &mut self,
+        config: Option<Config>,
+        last_seen: impl Fn(&PublicKey) -> Option<Duration>,
+    ) -> Result<Option<Config>, Error> {
+        let mut config = match config {
+            Some(config) => config,
+            None => {
+                self.trimmed_at = None;
+                return Ok(None);
+            }
+        };
+        let peers = match config.peers.as_mut() {
+            Some(peers) if peers.len() > self.max_peers => peers,
+            _ => {
+                self.trimmed_at = None;
+                return Ok(Some(config));
+            }
+        };
+
+        let count = peers.len();
+        if self.strategy == PeerLimitStrategy::Reject {
+            return Err(Error::TooManyPeers {
+                count,
+                limit: self.max_peers,
+            });
+        }
+
+        let (mut online, offline): (Vec<_>, Vec<_>) = peers
+            .iter()
+            .map(|peer| (last_seen(&peer.public_key), peer.public_key))
+            .partition(|(seen, _)| seen.is_some_and(|seen| seen < ONLINE_WINDOW));
+
+        // Some of the slots are always left to the offline peers, otherwise the ones trimmed
+        // could never come online again
+        let reserved = (self.max_peers / 8)
+            .max(1)
+            .min(offline.len())
+            .min(self.max_peers);
+        online.sort_by_key(|(seen, _)| *seen);
+        online.truncate(self.max_peers - reserved);
+
+        let free = self.max_peers - online.len();
+        let start = self.cursor % offline.len().max(1);
+        self.cursor = start + free;
+        let kept: HashSet<PublicKey> = online
+            .into_iter()
+            .chain(offline.iter().cycle().skip(start).take(free).copied())
+            .map(|(_, public_key)| public_key)
+            .collect();
+        peers.retain(|peer| kept.contains(&peer.public_key));
+        self.trimmed_at = Some(Instant::now());
+
+        telio_log_warn!(
+            "Meshnet config has {} peers, keeping {} of them",
+            count,
+            peers.len()
+        );
+        Ok(Some(config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use telio_model::config::{Peer, PeerBase};
+
+    fn config(keys: &[PublicKey]) -> Option<Config> {
+        Some(Config {
+            peers: Some(
+                keys.iter()
+                    .map(|public_key| Peer {
+                        base: PeerBase {
+                            public_key: *public_key,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        })
+    }
+
+    fn limit(max_peers: u32, strategy: PeerLimitStrategy) -> PeerLimit {
+        PeerLimit::new(FeaturePeerLimit {
+            max_peers,
+            strategy,
+        })
+    }
+
+    #[test]
+    fn config_within_limit_is_kept() {
+        let keys: Vec<_> = (0..2).map(|_| SecretKey::gen().public()).collect();
+        let mut limit = limit(2, PeerLimitStrategy::Reject);
+
+        assert_eq!(limit.apply(config(&keys), |_| None).unwrap(), config(&keys));
+        assert_eq!(limit.apply(None, |_| None).unwrap(), None);
+        assert_eq!(limit.trimmed_at, None);
+    }
+
+    #[test]
+    fn config_over_limit_is_rejected() {
+        let keys: Vec<_> = (0..3).map(|_| SecretKey::gen().public()).collect();
+
+        assert!(matches!(
+            limit(2, PeerLimitStrategy::Reject).apply(config(&keys), |_| None),
+            Err(Error::TooManyPeers { count: 3, limit: 2 })
+        ));
+    }
+
+    #[test]
+    fn online_peers_are_kept() {
+        let keys: Vec<_> = (0..4).map(|_| SecretKey::gen().public()).collect();
+        let last_seen = |public_key: &PublicKey| match keys.iter().position(|k| k == public_key) {
+            Some(1) => Some(Duration::from_secs(60)),
+            Some(2) => Some(Duration::from_secs(600)),
+            Some(3) => Some(Duration::from_secs(5)),
+            _ => None,
+        };
+
+        // One slot is left to the offline peers
+        assert_eq!(
+            limit(3, PeerLimitStrategy::Prioritize)
+                .apply(config(&keys), last_seen)
+                .unwrap(),
+            config(&[keys[0], keys[1], keys[3]])
+        );
+        assert_eq!(
+            limit(2, PeerLimitStrategy::Prioritize)
+                .apply(config(&keys), last_seen)
+                .unwrap(),
+            config(&[keys[0], keys[3]])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn offline_peers_are_rotated() {
+        let keys: Vec<_> = (0..5).map(|_| SecretKey::gen().public()).collect();
+        let last_seen = |public_key: &PublicKey| (*public_key == keys[0]).then_some(Duration::ZERO);
+        let mut limit = limit(2, PeerLimitStrategy::Prioritize);
+
+        let mut rotated = Vec::new();
+        for _ in 0..4 {
+            let config = limit.apply(config(&keys), last_seen).unwrap().unwrap();
+            let peers: Vec<_> = config
+                .peers
+                .unwrap()
+                .into_iter()
+                .map(|peer| peer.public_key)
+                .collect();
+            assert_eq!(peers.len(), 2);
+            assert_eq!(peers[0], keys[0]);
+            rotated.push(peers[1]);
+
+            assert!(!limit.rotation_due());
+            tokio::time::advance(ROTATION_PERIOD).await;
+            assert!(limit.rotation_due());
+        }
+        assert_eq!(rotated, keys[1..]);
+    }
+}
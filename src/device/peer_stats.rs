@@ -0,0 +1,157 @@
+//! Stream of the traffic counters of the peers, pushed to the apps instead of being polled
+//!
+//! To keep the reports small for large meshes, only the peers whose counters changed are
+//! reported, with the bytes since the previous report. Every few reports all of the peers are
+//! reported with their totals, so that apps can resynchronize.
+
+use std::collections::HashMap;
+
+use telio_crypto::PublicKey;
+use telio_model::event::{PeerStats, PeerStatsReport};
+use telio_utils::coalesced_interval;
+use tokio::time::{Duration, Interval};
+
+/// Traffic counters of a peer, as read from the adapter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Counters {
+    pub(crate) rx_bytes: u64,
+    pub(crate) tx_bytes: u64,
+}
+
+pub(crate) struct PeerStatsStream {
+    interval: Interval,
+    full_report_every: u64,
+    sequence: u64,
+    /// Counters of every peer as of the previous report
+    last: HashMap<PublicKey, Counters>,
+}
+
+impl PeerStatsStream {
+    pub(crate) fn new(period: Duration, full_report_every: u32) -> Self {
+        Self {
+            interval: coalesced_interval(period),
+            full_report_every: u64::from(full_report_every.max(1)),
+            sequence: 0,
+            last: HashMap::new(),
+        }
+    }
+
+    pub(crate) async fn tick(&mut self) {
+        self.interval.tick().await;
+    }
+
+    /// Report of the counters, `None` if nothing changed since the previous report
+    pub(crate) fn report(
+        &mut self,
+        counters: impl IntoIterator<Item = (PublicKey, Counters)>,
+    ) -> Option<PeerStatsReport> {
+        let counters: HashMap<_, _> = counters.into_iter().collect();
+        let full = self.sequence % self.full_report_every == 0;
+
+        let mut peers: Vec<_> = counters
+            .iter()
+            .filter_map(|(public_key, current)| {
+                if full {
+                    return Some(stats(*public_key, *current));
+                }
+                match self.last.get(public_key).copied() {
+                    Some(previous) if previous == *current => None,
+                    Some(previous) => Some(stats(
+                        *public_key,
+                        Counters {
+                            rx_bytes: delta(previous.rx_bytes, current.rx_bytes),
+                            tx_bytes: delta(previous.tx_bytes, current.tx_bytes),
+                        },
+                    )),
+                    None => Some(stats(*public_key, *current)),
+                }
+            })
+            .collect();
+        let mut removed: Vec<_> = if full {
+            Vec::new()
+        } else {
+            self.last
+                .keys()
+                .filter(|public_key| !counters.contains_key(public_key))
+                .copied()
+                .collect()
+        };
+        self.last = counters;
+
+        if !full && peers.is_empty() && removed.is_empty() {
+            return None;
+        }
+        peers.sort_by_key(|peer| peer.public_key);
+        removed.sort();
+
+        let report = PeerStatsReport {
+            sequence: self.sequence,
+            full,
+            peers,
+            removed,
+        };
+        self.sequence += 1;
+        Some(report)
+    }
+}
+
+fn stats(public_key: PublicKey, counters: Counters) -> PeerStats {
+    PeerStats {
+        public_key,
+        rx_bytes: counters.rx_bytes,
+        tx_bytes: counters.tx_bytes,
+    }
+}
+
+/// Bytes since the previous report, the counters start over when the peer is added again
+fn delta(previous: u64, current: u64) -> u64 {
+    current.checked_sub(previous).unwrap_or(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+
+    fn counters(rx_bytes: u64, tx_bytes: u64) -> Counters {
+        Counters { rx_bytes, tx_bytes }
+    }
+
+    #[tokio::test]
+    async fn only_changes_are_reported_between_full_reports() {
+        let mut stream = PeerStatsStream::new(Duration::from_secs(10), 3);
+        let a = SecretKey::gen().public();
+        let b = SecretKey::gen().public();
+
+        let report = stream
+            .report([(a, counters(100, 50)), (b, counters(10, 10))])
+            .unwrap();
+        assert!(report.full);
+        assert_eq!(report.sequence, 0);
+        assert_eq!(report.peers.len(), 2);
+
+        // Only the bytes since the previous report
+        let report = stream
+            .report([(a, counters(150, 50)), (b, counters(10, 10))])
+            .unwrap();
+        assert!(!report.full);
+        assert_eq!(report.peers, vec![stats(a, counters(50, 0))]);
+
+        // Nothing changed, nothing to report
+        assert!(stream
+            .report([(a, counters(150, 50)), (b, counters(10, 10))])
+            .is_none());
+
+        // Removed peers are listed, a peer added again starts over
+        let report = stream.report([(a, counters(20, 5))]).unwrap();
+        assert!(!report.full);
+        assert_eq!(report.peers, vec![stats(a, counters(20, 5))]);
+        assert_eq!(report.removed, vec![b]);
+
+        let report = stream.report([(a, counters(20, 5))]).unwrap();
+        assert!(report.full);
+        assert_eq!(report.sequence, 3);
+        assert_eq!(report.peers, vec![stats(a, counters(20, 5))]);
+        assert!(report.removed.is_empty());
+    }
+}
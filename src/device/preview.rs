@@ -0,0 +1,153 @@
+//! Connectivity pre-check for nodes which are not yet part of the meshnet
+
+use std::net::{IpAddr, SocketAddr};
+
+use telio_model::mesh::{ConnectivityOutlook, ConnectivityPreview};
+
+/// What is known locally about the paths towards a prospective peer
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ConnectivityHints {
+    /// Relay connection is up
+    pub relay_connected: bool,
+    /// Direct connections are running
+    pub direct_enabled: bool,
+    /// Addresses of our network interfaces
+    pub interface_addresses: Vec<IpAddr>,
+    /// Addresses our packets come from as seen by the STUN servers
+    pub stun_addresses: Vec<IpAddr>,
+    /// External addresses of the ports forwarded to us, e.g. by UPnP
+    pub forwarded_addresses: Vec<IpAddr>,
+    /// Endpoints the peer is expected to be reachable at
+    pub remote_endpoints: Vec<SocketAddr>,
+}
+
+impl ConnectivityHints {
+    pub(crate) fn preview(&self) -> ConnectivityPreview {
+        let same_network = self.remote_endpoints.iter().any(|remote| {
+            self.interface_addresses
+                .iter()
+                .any(|local| is_same_network(local, &remote.ip()))
+        });
+        // A public STUN address alone is the address of the NAT in front of us, we are reachable
+        // at it only if it belongs to one of our interfaces
+        let local_public_endpoint = self.forwarded_addresses.iter().any(is_public)
+            || self
+                .stun_addresses
+                .iter()
+                .any(|ip| is_public(ip) && self.interface_addresses.contains(ip));
+        let remote_public_endpoint = self.remote_endpoints.iter().any(|ep| is_public(&ep.ip()));
+
+        // Hole punching needs at most one side to be behind a hard NAT, a public endpoint on
+        // either side hints that the other one can be reached
+        let direct_likely = self.direct_enabled
+            && (same_network || local_public_endpoint || remote_public_endpoint);
+        let outlook = match (direct_likely, self.relay_connected) {
+            (true, _) => ConnectivityOutlook::Direct,
+            (false, true) => ConnectivityOutlook::RelayOnly,
+            (false, false) => ConnectivityOutlook::Unreachable,
+        };
+
+        ConnectivityPreview {
+            relay_reachable: self.relay_connected,
+            direct_enabled: self.direct_enabled,
+            same_network,
+            local_public_endpoint,
+            remote_public_endpoint,
+            outlook,
+        }
+    }
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            // 100.64.0.0/10 is used by carrier grade NATs
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || shared)
+        }
+        IpAddr::V6(ip) => {
+            // Only global unicast, 2000::/3
+            (ip.segments()[0] & 0xe000) == 0x2000
+        }
+    }
+}
+
+/// Interface netmasks are not known here, so a /24 (IPv4) or /64 (IPv6) is assumed
+fn is_same_network(local: &IpAddr, remote: &IpAddr) -> bool {
+    if is_public(local) || is_public(remote) {
+        return false;
+    }
+    match (local, remote) {
+        (IpAddr::V4(local), IpAddr::V4(remote)) => local.octets()[..3] == remote.octets()[..3],
+        (IpAddr::V6(local), IpAddr::V6(remote)) => local.segments()[..4] == remote.segments()[..4],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hints(local: &[&str], remote: &[&str]) -> ConnectivityHints {
+        ConnectivityHints {
+            relay_connected: true,
+            direct_enabled: true,
+            interface_addresses: local.iter().map(|ip| ip.parse().unwrap()).collect(),
+            remote_endpoints: remote.iter().map(|ep| ep.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn peer_on_the_same_lan_is_direct() {
+        let preview = hints(&["192.168.1.10", "100.72.0.3"], &["192.168.1.20:51820"]).preview();
+        assert!(preview.same_network);
+        assert!(!preview.local_public_endpoint);
+        assert_eq!(preview.outlook, ConnectivityOutlook::Direct);
+    }
+
+    #[test]
+    fn both_sides_behind_nat_is_relay_only() {
+        let mut hints = hints(&["10.0.0.2", "100.72.0.3"], &["192.168.1.20:51820"]);
+        assert_eq!(hints.preview().outlook, ConnectivityOutlook::RelayOnly);
+
+        // STUN seeing a public address of the NAT does not change it
+        let public: IpAddr = "203.0.113.7".parse().unwrap();
+        hints.stun_addresses.push(public);
+        let preview = hints.preview();
+        assert!(!preview.local_public_endpoint);
+        assert_eq!(preview.outlook, ConnectivityOutlook::RelayOnly);
+
+        // Unless there is no NAT and the address is our own
+        hints.interface_addresses.push(public);
+        let preview = hints.preview();
+        assert!(preview.local_public_endpoint);
+        assert_eq!(preview.outlook, ConnectivityOutlook::Direct);
+    }
+
+    #[test]
+    fn forwarded_port_is_public_endpoint() {
+        let mut hints = hints(&["10.0.0.2"], &["192.168.1.20:51820"]);
+        hints
+            .forwarded_addresses
+            .push("203.0.113.7".parse().unwrap());
+        assert!(hints.preview().local_public_endpoint);
+    }
+
+    #[test]
+    fn relay_down_and_direct_disabled_is_unreachable() {
+        let mut hints = hints(&["192.168.1.10"], &["198.51.100.1:51820"]);
+        hints.direct_enabled = false;
+        assert_eq!(hints.preview().outlook, ConnectivityOutlook::RelayOnly);
+
+        hints.relay_connected = false;
+        let preview = hints.preview();
+        assert!(preview.remote_public_endpoint);
+        assert!(!preview.relay_reachable);
+        assert_eq!(preview.outlook, ConnectivityOutlook::Unreachable);
+    }
+}
@@ -0,0 +1,180 @@
+//! Detection of peers which do not manage to connect in time
+
+use std::collections::{HashMap, HashSet};
+
+use telio_crypto::PublicKey;
+use telio_model::{event::UnreachableCause, mesh::NodeState};
+use tokio::time::{Duration, Instant};
+
+/// State of the subsystems at the time a peer is reported, used to guess the cause
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct CauseHints {
+    /// Device has at least one usable network interface
+    pub network_available: bool,
+    /// Peer is a VPN server, which is never reached through the relay
+    pub is_vpn: bool,
+    /// Relay connection state, `None` if meshnet is not running
+    pub relay_connected: Option<bool>,
+    /// Online state of the peer as seen by the relay, `None` if not known
+    pub peer_online: Option<bool>,
+}
+
+impl CauseHints {
+    pub(crate) fn cause(&self) -> UnreachableCause {
+        if !self.network_available {
+            return UnreachableCause::NoNetwork;
+        }
+        if self.is_vpn {
+            return UnreachableCause::EndpointUnreachable;
+        }
+        match (self.relay_connected, self.peer_online) {
+            (Some(false), _) => UnreachableCause::RelayDown,
+            (Some(true), Some(false)) => UnreachableCause::PeerOffline,
+            // Relay forwards the handshakes, so only the keys are left to blame
+            (Some(true), Some(true)) => UnreachableCause::PossibleKeyMismatch,
+            _ => UnreachableCause::Unknown,
+        }
+    }
+}
+
+/// Tracks for how long peers are connecting and reports each attempt once it times out
+pub(crate) struct ReachabilityTracker {
+    timeout: Duration,
+    connecting_since: HashMap<PublicKey, Instant>,
+    reported: HashSet<PublicKey>,
+}
+
+impl ReachabilityTracker {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            connecting_since: HashMap::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    /// Update with the state of a node, as reported to the integrators
+    pub(crate) fn on_node_state(&mut self, public_key: PublicKey, state: NodeState) {
+        match state {
+            NodeState::Connecting => {
+                self.connecting_since
+                    .entry(public_key)
+                    .or_insert_with(Instant::now);
+            }
+            NodeState::Connected | NodeState::Disconnected => {
+                self.connecting_since.remove(&public_key);
+                self.reported.remove(&public_key);
+            }
+        }
+    }
+
+    /// Peers which have been connecting for longer than the timeout and were not reported yet
+    pub(crate) fn take_timed_out(&mut self) -> Vec<(PublicKey, Duration)> {
+        let now = Instant::now();
+        let timed_out: Vec<_> = self
+            .connecting_since
+            .iter()
+            .map(|(pk, since)| (*pk, now.saturating_duration_since(*since)))
+            .filter(|(pk, elapsed)| *elapsed >= self.timeout && !self.reported.contains(pk))
+            .collect();
+        self.reported
+            .extend(timed_out.iter().map(|(public_key, _)| *public_key));
+        timed_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn connecting_peer_is_reported_once() {
+        let mut tracker = ReachabilityTracker::new(Duration::from_secs(30));
+        let pk = SecretKey::gen().public();
+
+        tracker.on_node_state(pk, NodeState::Connecting);
+        time::advance(Duration::from_secs(20)).await;
+        // Repeated events do not restart the attempt
+        tracker.on_node_state(pk, NodeState::Connecting);
+        assert!(tracker.take_timed_out().is_empty());
+
+        time::advance(Duration::from_secs(10)).await;
+        assert_eq!(
+            tracker.take_timed_out(),
+            vec![(pk, Duration::from_secs(30))]
+        );
+        time::advance(Duration::from_secs(30)).await;
+        assert!(tracker.take_timed_out().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connected_peer_starts_over() {
+        let mut tracker = ReachabilityTracker::new(Duration::from_secs(30));
+        let pk = SecretKey::gen().public();
+
+        tracker.on_node_state(pk, NodeState::Connecting);
+        time::advance(Duration::from_secs(30)).await;
+        assert_eq!(tracker.take_timed_out().len(), 1);
+
+        tracker.on_node_state(pk, NodeState::Connected);
+        tracker.on_node_state(pk, NodeState::Connecting);
+        assert!(tracker.take_timed_out().is_empty());
+        time::advance(Duration::from_secs(30)).await;
+        assert_eq!(tracker.take_timed_out().len(), 1);
+    }
+
+    #[test]
+    fn cause_analysis() {
+        let online = CauseHints {
+            network_available: true,
+            relay_connected: Some(true),
+            peer_online: Some(true),
+            ..Default::default()
+        };
+
+        let cases = [
+            (
+                CauseHints {
+                    network_available: false,
+                    ..online
+                },
+                UnreachableCause::NoNetwork,
+            ),
+            (
+                CauseHints {
+                    is_vpn: true,
+                    ..online
+                },
+                UnreachableCause::EndpointUnreachable,
+            ),
+            (
+                CauseHints {
+                    relay_connected: Some(false),
+                    ..online
+                },
+                UnreachableCause::RelayDown,
+            ),
+            (
+                CauseHints {
+                    peer_online: Some(false),
+                    ..online
+                },
+                UnreachableCause::PeerOffline,
+            ),
+            (online, UnreachableCause::PossibleKeyMismatch),
+            (
+                CauseHints {
+                    peer_online: None,
+                    ..online
+                },
+                UnreachableCause::Unknown,
+            ),
+        ];
+
+        for (hints, cause) in cases {
+            assert_eq!(hints.cause(), cause, "{hints:?}");
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//! Detection of the allowed IPs colliding with the local networks of the host, e.g. the meshnet
+//! subnet overlapping the home LAN
+//!
+//! The local networks are the ones of the interfaces of the host and, on Linux, the ones routed
+//! through them.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use telio_crypto::PublicKey;
+use telio_model::config::Config;
+use telio_network_monitors::local_interfaces::GetIfAddrs;
+use telio_utils::telio_log_warn;
+
+/// Network the host is directly attached to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LocalNetwork {
+    pub interface: String,
+    pub network: IpNet,
+}
+
+/// Allowed IPs of a peer overlapping a local network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Conflict {
+    pub public_key: PublicKey,
+    pub allowed_ips: IpNet,
+    pub local: LocalNetwork,
+}
+
+/// Networks of the host, without the ones of our own adapter
+pub(crate) fn host_networks(
+    get_if_addrs: &impl GetIfAddrs,
+    adapter_name: Option<&str>,
+    config: Option<&Config>,
+) -> Vec<LocalNetwork> {
+    let own_ips = config
+        .and_then(|config| config.this.ip_addresses.clone())
+        .unwrap_or_default();
+    let interfaces = get_if_addrs.get().unwrap_or_else(|e| {
+        telio_log_warn!("Failed to list the interfaces: {}", e);
+        Vec::new()
+    });
+    let mut networks = local_networks(&interfaces, adapter_name, &own_ips);
+
+    // Interfaces with our meshnet IPs are the adapter, even when its name is not known
+    let own_interfaces: HashSet<&str> = interfaces
+        .iter()
+        .filter(|itf| own_ips.contains(&itf.ip()))
+        .map(|itf| itf.name.as_str())
+        .chain(adapter_name)
+        .collect();
+    for route in routes::routed_networks() {
+        if !own_interfaces.contains(route.interface.as_str())
+            && is_routable(&route.network)
+            && !networks.contains(&route)
+        {
+            networks.push(route);
+        }
+    }
+    networks
+}
+
+/// Allowed IPs of the meshnet peers, one host network per meshnet IP
+pub(crate) fn meshnet_allowed_ips(config: &Config, ipv6: bool) -> Vec<(PublicKey, Vec<IpNet>)> {
+    config
+        .peers
+        .iter()
+        .flatten()
+        .map(|peer| {
+            let allowed_ips = peer
+                .ip_addresses
+                .iter()
+                .flatten()
+                .filter(|ip| ipv6 || ip.is_ipv4())
+                .map(|ip| IpNet::from(*ip))
+                .collect();
+            (peer.public_key, allowed_ips)
+        })
+        .collect()
+}
+
+fn local_networks(
+    interfaces: &[if_addrs::Interface],
+    adapter_name: Option<&str>,
+    own_ips: &[IpAddr],
+) -> Vec<LocalNetwork> {
+    interfaces
+        .iter()
+        .filter(|itf| Some(itf.name.as_str()) != adapter_name && !own_ips.contains(&itf.ip()))
+        .filter_map(|itf| {
+            let network = match &itf.addr {
+                if_addrs::IfAddr::V4(addr) => {
+                    IpNet::with_netmask(addr.ip.into(), addr.netmask.into())
+                }
+                if_addrs::IfAddr::V6(addr) => {
+                    IpNet::with_netmask(addr.ip.into(), addr.netmask.into())
+                }
+            }
+            .ok()?
+            .trunc();
+            is_routable(&network).then(|| LocalNetwork {
+                interface: itf.name.clone(),
+                network,
+            })
+        })
+        .collect()
+}
+
+/// Nothing is routed to the peers over loopback, link local or multicast networks, and default
+/// routes are meant to cover every network
+fn is_routable(network: &IpNet) -> bool {
+    let unroutable = match network.addr() {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_link_local() || ip.is_multicast(),
+        IpAddr::V6(ip) => {
+            ip.is_loopback() || (ip.segments()[0] & 0xffc0) == 0xfe80 || ip.is_multicast()
+        }
+    };
+    network.prefix_len() > 0 && !unroutable
+}
+
+/// Routing tables of the host, see proc(5)
+#[cfg(target_os = "linux")]
+mod routes {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use ipnet::IpNet;
+    use telio_utils::telio_log_debug;
+
+    use super::LocalNetwork;
+
+    const RTF_UP: u32 = 0x1;
+    /// Addresses of the host itself are routed through the loopback interface
+    const LOOPBACK: &str = "lo";
+
+    pub(super) fn routed_networks() -> Vec<LocalNetwork> {
+        let mut networks = Vec::new();
+        for (path, parse) in [
+            (
+                "/proc/net/route",
+                ipv4_route as fn(&[&str]) -> Option<LocalNetwork>,
+            ),
+            ("/proc/net/ipv6_route", ipv6_route),
+        ] {
+            match std::fs::read_to_string(path) {
+                Ok(table) => networks.extend(parse_table(&table, parse)),
+                Err(e) => telio_log_debug!("Failed to read {}: {}", path, e),
+            }
+        }
+        networks
+    }
+
+    pub(super) fn parse_table(
+        table: &str,
+        parse: fn(&[&str]) -> Option<LocalNetwork>,
+    ) -> Vec<LocalNetwork> {
+        table
+            .lines()
+            .filter_map(|line| parse(&line.split_whitespace().collect::<Vec<_>>()))
+            .filter(|route| route.interface != LOOPBACK)
+            .collect()
+    }
+
+    /// Route of /proc/net/route, the addresses are in the byte order of the host
+    pub(super) fn ipv4_route(fields: &[&str]) -> Option<LocalNetwork> {
+        let hex = |i: usize| u32::from_str_radix(fields.get(i)?, 16).ok();
+        if hex(3)? & RTF_UP == 0 {
+            return None;
+        }
+        let destination = Ipv4Addr::from(hex(1)?.to_ne_bytes());
+        let netmask = Ipv4Addr::from(hex(7)?.to_ne_bytes());
+        Some(LocalNetwork {
+            interface: fields.first()?.to_string(),
+            network: IpNet::with_netmask(destination.into(), netmask.into())
+                .ok()?
+                .trunc(),
+        })
+    }
+
+    /// Route of /proc/net/ipv6_route, the addresses are in the network byte order
+    pub(super) fn ipv6_route(fields: &[&str]) -> Option<LocalNetwork> {
+        if u32::from_str_radix(fields.get(8)?, 16).ok()? & RTF_UP == 0 {
+            return None;
+        }
+        let destination = Ipv6Addr::from(u128::from_str_radix(fields.first()?, 16).ok()?);
+        let prefix_len = u8::from_str_radix(fields.get(1)?, 16).ok()?;
+        Some(LocalNetwork {
+            interface: fields.get(9)?.to_string(),
+            network: IpNet::new(destination.into(), prefix_len).ok()?.trunc(),
+        })
+    }
+}
+
+/// Routing tables are not read elsewhere, only the networks of the interfaces are known
+#[cfg(not(target_os = "linux"))]
+mod routes {
+    use super::LocalNetwork;
+
+    pub(super) fn routed_networks() -> Vec<LocalNetwork> {
+        Vec::new()
+    }
+}
+
+/// Allowed IPs of the peers overlapping the local networks.
+///
+/// Default routes are not reported, they are meant to cover every network and the local ones
+/// stay reachable through their more specific routes.
+pub(crate) fn find_conflicts<'a>(
+    peers: impl IntoIterator<Item = (PublicKey, &'a [IpNet])>,
+    local: &[LocalNetwork],
+) -> Vec<Conflict> {
+    peers
+        .into_iter()
+        .flat_map(|(public_key, allowed_ips)| {
+            allowed_ips
+                .iter()
+                .filter(|allowed_ips| allowed_ips.prefix_len() > 0)
+                .flat_map(move |allowed_ips| {
+                    local
+                        .iter()
+                        .filter(|local| overlaps(allowed_ips, &local.network))
+                        .map(move |local| Conflict {
+                            public_key,
+                            allowed_ips: *allowed_ips,
+                            local: local.clone(),
+                        })
+                })
+        })
+        .collect()
+}
+
+/// Allowed IPs with the ranges of the local networks cut out
+pub(crate) fn exclude_conflicts(allowed_ips: &[IpNet], local: &[LocalNetwork]) -> Vec<IpNet> {
+    local
+        .iter()
+        .fold(allowed_ips.to_vec(), |allowed_ips, local| {
+            allowed_ips
+                .into_iter()
+                .flat_map(|net| match net.prefix_len() {
+                    0 => vec![net],
+                    _ => exclude(net, local.network),
+                })
+                .collect()
+        })
+}
+
+fn overlaps(a: &IpNet, b: &IpNet) -> bool {
+    a.contains(b) || b.contains(a)
+}
+
+/// Smallest set of networks covering `net` without `excluded`
+fn exclude(net: IpNet, excluded: IpNet) -> Vec<IpNet> {
+    if excluded.contains(&net) {
+        return Vec::new();
+    }
+    if !net.contains(&excluded) {
+        return vec![net];
+    }
+    net.subnets(net.prefix_len() + 1)
+        .map(|halves| halves.flat_map(|half| exclude(half, excluded)).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use telio_crypto::SecretKey;
+    use telio_network_monitors::local_interfaces::MockGetIfAddrs;
+
+    fn interface(name: &str, ip: [u8; 4], netmask: [u8; 4]) -> if_addrs::Interface {
+        if_addrs::Interface {
+            name: name.to_owned(),
+            addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                ip: Ipv4Addr::from(ip),
+                netmask: Ipv4Addr::from(netmask),
+                broadcast: None,
+            }),
+            index: None,
+            #[cfg(windows)]
+            adapter_name: "{78f73923-a518-4936-ba87-2a30427b1f63}".to_string(),
+        }
+    }
+
+    fn nets(nets: &[&str]) -> Vec<IpNet> {
+        nets.iter().map(|net| net.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn own_and_unroutable_interfaces_are_not_local_networks() {
+        let interfaces = [
+            interface("lo", [127, 0, 0, 1], [255, 0, 0, 0]),
+            interface("eth0", [192, 168, 1, 10], [255, 255, 255, 0]),
+            interface("eth1", [169, 254, 3, 1], [255, 255, 0, 0]),
+            interface("nlx", [100, 64, 0, 1], [255, 192, 0, 0]),
+            interface("utun3", [100, 64, 0, 1], [255, 192, 0, 0]),
+        ];
+        let own_ips = [IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))];
+
+        assert_eq!(
+            local_networks(&interfaces, Some("nlx"), &own_ips),
+            vec![LocalNetwork {
+                interface: "eth0".to_owned(),
+                network: "192.168.1.0/24".parse().unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn meshnet_colliding_with_home_lan_is_a_host_network() {
+        let mut get_if_addrs = MockGetIfAddrs::new();
+        get_if_addrs.expect_get().returning(|| {
+            Ok(vec![
+                interface("nlx", [100, 64, 0, 1], [255, 192, 0, 0]),
+                interface("wlan0", [100, 64, 7, 10], [255, 255, 0, 0]),
+                if_addrs::Interface {
+                    name: "wlan0".to_owned(),
+                    addr: if_addrs::IfAddr::V6(if_addrs::Ifv6Addr {
+                        ip: Ipv6Addr::new(0xfd74, 0x656c, 0x696f, 0, 0, 0, 0, 7),
+                        netmask: Ipv6Addr::new(0xffff, 0xffff, 0xffff, 0xffff, 0, 0, 0, 0),
+                        broadcast: None,
+                    }),
+                    index: None,
+                    #[cfg(windows)]
+                    adapter_name: "{78f73923-a518-4936-ba87-2a30427b1f63}".to_string(),
+                },
+            ])
+        });
+        let config = Config {
+            this: telio_model::config::PeerBase {
+                ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let local = host_networks(&get_if_addrs, None, Some(&config));
+        for network in ["100.64.0.0/16", "fd74:656c:696f::/64"] {
+            assert!(local.contains(&LocalNetwork {
+                interface: "wlan0".to_owned(),
+                network: network.parse().unwrap(),
+            }));
+        }
+        assert!(local.iter().all(|local| local.interface != "nlx"));
+
+        let peer = SecretKey::gen().public();
+        let peer_ips = nets(&["100.64.0.2/32", "fd74:656c:696f::2/128"]);
+        let conflicts = find_conflicts([(peer, &peer_ips[..])], &local);
+        assert!(peer_ips
+            .iter()
+            .all(|ip| conflicts.iter().any(|c| c.allowed_ips == *ip)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn routes_through_the_interfaces_are_parsed() {
+        let hex = |ip: [u8; 4]| format!("{:08X}", u32::from_ne_bytes(ip));
+        let ipv4 = format!(
+            "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\tMTU\tWindow\tIRTT\n\
+             eth0\t{}\t{}\t0003\t0\t0\t0\t{}\t0\t0\t0\n\
+             eth0\t{}\t{}\t0003\t0\t0\t0\t{}\t0\t0\t0\n\
+             eth1\t{}\t{}\t0000\t0\t0\t0\t{}\t0\t0\t0\n",
+            hex([0, 0, 0, 0]),
+            hex([192, 168, 1, 1]),
+            hex([0, 0, 0, 0]),
+            hex([10, 8, 0, 0]),
+            hex([192, 168, 1, 1]),
+            hex([255, 255, 0, 0]),
+            hex([10, 9, 0, 0]),
+            hex([0, 0, 0, 0]),
+            hex([255, 255, 0, 0]),
+        );
+        let ipv6 = "fd000000000000000000000000000000 08 00000000000000000000000000000000 00 \
+                    00000000000000000000000000000000 00000400 00000001 00000000 00000001 eth0\n\
+                    fd740000000000000000000000000001 80 00000000000000000000000000000000 00 \
+                    00000000000000000000000000000000 00000000 00000002 00000000 80200001 lo\n";
+
+        let routes = routes::parse_table(&ipv4, routes::ipv4_route)
+            .into_iter()
+            .chain(routes::parse_table(ipv6, routes::ipv6_route))
+            .filter(|route| is_routable(&route.network))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            routes,
+            vec![
+                LocalNetwork {
+                    interface: "eth0".to_owned(),
+                    network: "10.8.0.0/16".parse().unwrap(),
+                },
+                LocalNetwork {
+                    interface: "eth0".to_owned(),
+                    network: "fd00::/8".parse().unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn meshnet_peer_inside_home_lan_is_reported() {
+        let local = [LocalNetwork {
+            interface: "wlan0".to_owned(),
+            network: "100.64.0.0/16".parse().unwrap(),
+        }];
+        let peer = SecretKey::gen().public();
+        let exit = SecretKey::gen().public();
+        let peer_ips = nets(&["100.64.0.2/32", "fd74:656c:696f::2/128"]);
+        let exit_ips = nets(&["0.0.0.0/0", "10.0.0.0/8"]);
+
+        assert_eq!(
+            find_conflicts([(peer, &peer_ips[..]), (exit, &exit_ips[..])], &local),
+            vec![Conflict {
+                public_key: peer,
+                allowed_ips: peer_ips[0],
+                local: local[0].clone(),
+            }]
+        );
+    }
+
+    #[test]
+    fn conflicting_ranges_are_cut_out() {
+        let local = [LocalNetwork {
+            interface: "eth0".to_owned(),
+            network: "10.0.1.0/24".parse().unwrap(),
+        }];
+
+        assert_eq!(
+            exclude_conflicts(&nets(&["10.0.0.0/22", "10.0.1.7/32"]), &local),
+            nets(&["10.0.0.0/24", "10.0.2.0/23"])
+        );
+        // Full tunnel is kept as is, the local routes are more specific
+        assert_eq!(
+            exclude_conflicts(&nets(&["0.0.0.0/0", "::/0"]), &local),
+            nets(&["0.0.0.0/0", "::/0"])
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! Names and scheduling priority of the threads started by the device, so they can be told apart
+//! in debuggers and profilers and the ones carrying the traffic can be elevated
+
+use std::{
+    cell::Cell,
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use telio_model::features::ThreadPriority;
+use telio_utils::telio_log_warn;
+
+/// Name of the next async runtime worker, numbered and kept within the 15 bytes Linux allows
+pub(crate) fn worker_thread_name() -> String {
+    static NEXT_WORKER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "libtelio-rt-{}",
+        NEXT_WORKER.fetch_add(1, Ordering::Relaxed) % 1000
+    )
+}
+
+/// Set the priority of the calling thread, the thread keeps running as it was if that fails
+pub(crate) fn set_current_thread_priority(priority: ThreadPriority) {
+    if priority == ThreadPriority::Normal {
+        return;
+    }
+    if let Err(e) = set_priority(priority) {
+        telio_log_warn!(
+            "Failed to set the priority of {:?} to {priority:?}: {e}",
+            std::thread::current().name()
+        );
+    }
+}
+
+/// Set the priority of the calling datapath thread, once per thread. The adapter starts its
+/// threads on its own, so they get the priority from the packet callbacks they run.
+pub(crate) fn set_datapath_thread_priority(priority: ThreadPriority) {
+    thread_local! {
+        static PRIORITY_SET: Cell<bool> = const { Cell::new(false) };
+    }
+    if !PRIORITY_SET.with(|set| set.replace(true)) {
+        set_current_thread_priority(priority);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    let nice = match priority {
+        ThreadPriority::Normal => 0,
+        ThreadPriority::AboveNormal => -5,
+        ThreadPriority::High => -10,
+    };
+    // The nice value is per thread on Linux, given the thread id in place of the process id
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    use libc::qos_class_t;
+
+    let qos_class = match priority {
+        ThreadPriority::Normal => qos_class_t::QOS_CLASS_DEFAULT,
+        ThreadPriority::AboveNormal => qos_class_t::QOS_CLASS_USER_INITIATED,
+        ThreadPriority::High => qos_class_t::QOS_CLASS_USER_INTERACTIVE,
+    };
+    match unsafe { libc::pthread_set_qos_class_self_np(qos_class, 0) } {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+#[cfg(windows)]
+fn set_priority(priority: ThreadPriority) -> io::Result<()> {
+    use winapi::um::{
+        processthreadsapi::{GetCurrentThread, SetThreadPriority},
+        winbase::{THREAD_PRIORITY_ABOVE_NORMAL, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_NORMAL},
+    };
+
+    let thread_priority = match priority {
+        ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+        ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+        ThreadPriority::High => THREAD_PRIORITY_HIGHEST,
+    };
+    if unsafe { SetThreadPriority(GetCurrentThread(), thread_priority as i32) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "tvos",
+    windows
+)))]
+fn set_priority(_priority: ThreadPriority) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_names_fit_linux_limit() {
+        for _ in 0..1100 {
+            assert!(worker_thread_name().len() <= 15);
+        }
+    }
+}
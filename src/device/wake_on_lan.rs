@@ -0,0 +1,211 @@
+//! Waking up the sleeping nodes through the meshnet
+//!
+//! A sleeping node cannot be reached over the tunnel, so the request is sent to the nodes
+//! advertising the Wake-on-LAN capability, which broadcast the magic packet to their LAN.
+
+use std::{io, net::Ipv4Addr};
+
+use telio_crypto::PublicKey;
+use telio_model::config::{Config, Peer, PeerCapability};
+use telio_sockets::SocketPool;
+
+/// Wake-on-LAN errors
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Only the nodes of the meshnet can be woken up
+    #[error("{0:?} is not a meshnet node")]
+    UnknownNode(PublicKey),
+    /// The target did not advertise the hardware address it is woken up by
+    #[error("{0:?} has no valid hardware address")]
+    NoMacAddress(PublicKey),
+    /// No node advertises the Wake-on-LAN capability
+    #[error("No meshnet node relays Wake-on-LAN requests")]
+    NoRelay,
+    /// The request could not be passed to any of the relaying nodes
+    #[error("None of the relaying nodes could be asked to wake up {0:?}")]
+    NotRelayed(PublicKey),
+    /// The requester is not allowed to access the local network
+    #[error("{0:?} is not allowed to wake up the local network nodes")]
+    NotAllowed(PublicKey),
+    /// Failed to broadcast the magic packet
+    #[error("Failed to send the magic packet: {0}")]
+    Send(#[from] io::Error),
+}
+
+/// Nodes asked to wake up the target, the ones which may share its LAN
+pub(crate) fn relays_for(config: &Config, target: &PublicKey) -> Result<Vec<PublicKey>, Error> {
+    let peer = find_peer(config, target)?;
+    mac_address(peer)?;
+
+    let relays: Vec<_> = config
+        .peers
+        .iter()
+        .flatten()
+        .filter(|peer| peer.public_key != *target && peer.supports(PeerCapability::WakeOnLan))
+        .map(|peer| peer.public_key)
+        .collect();
+    if relays.is_empty() {
+        return Err(Error::NoRelay);
+    }
+    Ok(relays)
+}
+
+/// Hardware address of the target, if the requester may wake it up through this node
+pub(crate) fn target_mac(
+    config: &Config,
+    requester: &PublicKey,
+    target: &PublicKey,
+) -> Result<[u8; 6], Error> {
+    // Sending packets to the LAN on behalf of a node is a form of local network access
+    let requester_allowed = config
+        .peers
+        .iter()
+        .flatten()
+        .any(|peer| peer.public_key == *requester && peer.allow_peer_local_network_access);
+    if !requester_allowed {
+        return Err(Error::NotAllowed(*requester));
+    }
+    mac_address(find_peer(config, target)?)
+}
+
+/// Broadcast the magic packet for the hardware address to the LAN
+pub(crate) async fn send_magic_packet(
+    socket_pool: &SocketPool,
+    mac: [u8; 6],
+    port: u16,
+) -> Result<(), Error> {
+    let socket = socket_pool
+        .new_external_udp((Ipv4Addr::UNSPECIFIED, 0), None)
+        .await?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&magic_packet(mac), (Ipv4Addr::BROADCAST, port))
+        .await?;
+    Ok(())
+}
+
+fn find_peer<'a>(config: &'a Config, public_key: &PublicKey) -> Result<&'a Peer, Error> {
+    config
+        .peers
+        .iter()
+        .flatten()
+        .find(|peer| peer.public_key == *public_key)
+        .ok_or(Error::UnknownNode(*public_key))
+}
+
+fn mac_address(peer: &Peer) -> Result<[u8; 6], Error> {
+    peer.metadata
+        .as_ref()
+        .and_then(|metadata| metadata.mac_address.as_deref())
+        .and_then(parse_mac)
+        .ok_or(Error::NoMacAddress(peer.public_key))
+}
+
+/// Hardware address written as six hex bytes separated by ':' or '-'
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split([':', '-']);
+    for byte in bytes.iter_mut() {
+        let part = parts.next().filter(|part| part.len() == 2)?;
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(bytes)
+}
+
+/// Six 0xff bytes followed by sixteen repetitions of the hardware address
+fn magic_packet(mac: [u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xff; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac);
+    }
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::SecretKey;
+    use telio_model::config::{PeerBase, PeerMetadata};
+
+    fn peer(capabilities: Vec<PeerCapability>, mac_address: Option<&str>) -> Peer {
+        Peer {
+            base: PeerBase {
+                public_key: SecretKey::gen().public(),
+                metadata: Some(PeerMetadata {
+                    capabilities,
+                    mac_address: mac_address.map(str::to_owned),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mac_addresses_are_parsed() {
+        assert_eq!(
+            parse_mac("aa:BB:cc:01:02:03"),
+            Some([0xaa, 0xbb, 0xcc, 0x01, 0x02, 0x03])
+        );
+        assert_eq!(
+            parse_mac("aa-bb-cc-01-02-03"),
+            Some([0xaa, 0xbb, 0xcc, 0x01, 0x02, 0x03])
+        );
+        assert_eq!(parse_mac("aa:bb:cc:01:02"), None);
+        assert_eq!(parse_mac("aa:bb:cc:01:02:03:04"), None);
+        assert_eq!(parse_mac("aabb:cc:01:02:03"), None);
+        assert_eq!(parse_mac("zz:bb:cc:01:02:03"), None);
+
+        let packet = magic_packet([1, 2, 3, 4, 5, 6]);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(packet[..6], [0xff; 6]);
+        assert_eq!(packet[96..], [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn requests_go_to_the_capable_nodes() {
+        let target = peer(vec![PeerCapability::WakeOnLan], Some("aa:bb:cc:dd:ee:ff"));
+        let relay = peer(vec![PeerCapability::WakeOnLan], None);
+        let other = peer(vec![], None);
+        let config = Config {
+            peers: Some(vec![target.clone(), relay.clone(), other.clone()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            relays_for(&config, &target.public_key).unwrap(),
+            vec![relay.public_key]
+        );
+        assert!(matches!(
+            relays_for(&config, &relay.public_key),
+            Err(Error::NoMacAddress(_))
+        ));
+        let stranger = SecretKey::gen().public();
+        assert!(matches!(
+            relays_for(&config, &stranger),
+            Err(Error::UnknownNode(_))
+        ));
+    }
+
+    #[test]
+    fn only_nodes_with_local_network_access_are_relayed() {
+        let target = peer(vec![], Some("aa:bb:cc:dd:ee:ff"));
+        let mut trusted = peer(vec![], None);
+        trusted.allow_peer_local_network_access = true;
+        let untrusted = peer(vec![], None);
+        let config = Config {
+            peers: Some(vec![target.clone(), trusted.clone(), untrusted.clone()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            target_mac(&config, &trusted.public_key, &target.public_key).unwrap(),
+            [0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]
+        );
+        assert!(matches!(
+            target_mac(&config, &untrusted.public_key, &target.public_key),
+            Err(Error::NotAllowed(_))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+//! Detection of meshnet stuck without any connected peers
+
+use telio_model::event::RemediationStep;
+use tokio::time::{Duration, Instant};
+
+/// Remediation steps, from the least to the most disruptive one
+const LADDER: [RemediationStep; 3] = [
+    RemediationStep::RelayReconnect,
+    RemediationStep::EndpointRediscovery,
+    RemediationStep::AdapterRehandshake,
+];
+
+/// Tracks for how long meshnet is without connected peers and picks the remediation steps
+pub(crate) struct MeshWatchdog {
+    stuck_timeout: Duration,
+    step_interval: Duration,
+    stuck_since: Option<Instant>,
+    last_step_at: Option<Instant>,
+    next_step: usize,
+}
+
+impl MeshWatchdog {
+    pub(crate) fn new(stuck_timeout: Duration, step_interval: Duration) -> Self {
+        Self {
+            stuck_timeout,
+            step_interval,
+            stuck_since: None,
+            last_step_at: None,
+            next_step: 0,
+        }
+    }
+
+    /// Update with the current state of meshnet, returning the step to take along with the time
+    /// meshnet has been stuck for.
+    ///
+    /// Meshnet is only watched while it has peers configured and the network is up, otherwise
+    /// having no connected peers is expected and the watchdog starts over.
+    pub(crate) fn observe(
+        &mut self,
+        watched: bool,
+        connected_peers: usize,
+    ) -> Option<(RemediationStep, Duration)> {
+        let now = Instant::now();
+        if !watched {
+            self.reset();
+            return None;
+        }
+
+        if connected_peers > 0 {
+            let remediating = self.last_step_at.is_some();
+            let stuck_since = self.stuck_since;
+            self.reset();
+            return match stuck_since {
+                Some(since) if remediating => Some((
+                    RemediationStep::Recovered,
+                    now.saturating_duration_since(since),
+                )),
+                _ => None,
+            };
+        }
+
+        let stuck_for = now.saturating_duration_since(*self.stuck_since.get_or_insert(now));
+        let due = match self.last_step_at {
+            None => stuck_for >= self.stuck_timeout,
+            Some(at) => now.saturating_duration_since(at) >= self.step_interval,
+        };
+        if !due || self.next_step > LADDER.len() {
+            return None;
+        }
+
+        let step = LADDER
+            .get(self.next_step)
+            .copied()
+            .unwrap_or(RemediationStep::Exhausted);
+        self.next_step += 1;
+        self.last_step_at = Some(now);
+        Some((step, stuck_for))
+    }
+
+    fn reset(&mut self) {
+        self.stuck_since = None;
+        self.last_step_at = None;
+        self.next_step = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(300);
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    #[tokio::test(start_paused = true)]
+    async fn stuck_meshnet_climbs_the_ladder_once() {
+        let mut watchdog = MeshWatchdog::new(TIMEOUT, INTERVAL);
+
+        assert_eq!(watchdog.observe(true, 0), None);
+        time::advance(TIMEOUT).await;
+        assert_eq!(
+            watchdog.observe(true, 0),
+            Some((RemediationStep::RelayReconnect, TIMEOUT))
+        );
+        // Each step gets some time to help
+        time::advance(INTERVAL / 2).await;
+        assert_eq!(watchdog.observe(true, 0), None);
+
+        for (step, stuck_for) in [
+            (RemediationStep::EndpointRediscovery, TIMEOUT + INTERVAL),
+            (RemediationStep::AdapterRehandshake, TIMEOUT + INTERVAL * 2),
+            (RemediationStep::Exhausted, TIMEOUT + INTERVAL * 3),
+        ] {
+            time::advance(INTERVAL - INTERVAL / 2).await;
+            assert_eq!(watchdog.observe(true, 0), Some((step, stuck_for)));
+            time::advance(INTERVAL / 2).await;
+        }
+
+        time::advance(INTERVAL * 10).await;
+        assert_eq!(watchdog.observe(true, 0), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn connected_peer_ends_the_remediation() {
+        let mut watchdog = MeshWatchdog::new(TIMEOUT, INTERVAL);
+
+        assert_eq!(watchdog.observe(true, 0), None);
+        time::advance(TIMEOUT).await;
+        assert!(watchdog.observe(true, 0).is_some());
+        time::advance(INTERVAL / 2).await;
+        assert_eq!(
+            watchdog.observe(true, 1),
+            Some((RemediationStep::Recovered, TIMEOUT + INTERVAL / 2))
+        );
+        assert_eq!(watchdog.observe(true, 1), None);
+
+        // Being stuck again starts from the first step
+        assert_eq!(watchdog.observe(true, 0), None);
+        time::advance(TIMEOUT).await;
+        assert_eq!(
+            watchdog.observe(true, 0),
+            Some((RemediationStep::RelayReconnect, TIMEOUT))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unwatched_meshnet_starts_over() {
+        let mut watchdog = MeshWatchdog::new(TIMEOUT, INTERVAL);
+
+        assert_eq!(watchdog.observe(true, 0), None);
+        time::advance(TIMEOUT - INTERVAL).await;
+        // e.g. the network went down, recovery is not reported as nothing was done
+        assert_eq!(watchdog.observe(false, 0), None);
+        time::advance(INTERVAL).await;
+        assert_eq!(watchdog.observe(true, 0), None);
+        assert_eq!(watchdog.observe(true, 1), None);
+    }
+}
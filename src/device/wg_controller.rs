@@ -1,4 +1,4 @@
-use super::{Entities, RequestedState, Result};
+use super::{audit_log::AuditLog, route_conflicts, Entities, RequestedState, Result};
 use futures::FutureExt;
 use ipnet::IpNet;
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -10,10 +10,11 @@ use telio_crypto::PublicKey;
 use telio_dns::DnsResolver;
 use telio_firewall::firewall::{Firewall, Permissions, FILE_SEND_PORT};
 use telio_model::constants::{VPN_EXTERNAL_IPV4, VPN_INTERNAL_IPV4, VPN_INTERNAL_IPV6};
-use telio_model::features::Features;
+use telio_model::features::{FeatureVpnRelayFallback, Features, PathType};
 use telio_model::mesh::{LinkState, NodeState};
 use telio_model::EndpointMap;
 use telio_model::SocketAddr;
+use telio_network_monitors::local_interfaces::SystemGetIfAddrs;
 use telio_nurse::aggregator::ConnectivityDataAggregator;
 use telio_proto::{PeersStatesMap, Session};
 use telio_proxy::Proxy;
@@ -31,6 +32,9 @@ use tokio::time::Instant;
 
 pub const DEFAULT_PEER_UPGRADE_WINDOW: u64 = 15;
 
+/// Keepalive of the prewarmed exit node, when no VPN keepalive is configured
+const PREWARM_KEEPALIVE_INTERVAL: u32 = 25;
+
 #[derive(Debug, TError)]
 pub enum Error {
     #[error("Duplicate allowed ips.")]
@@ -64,6 +68,51 @@ struct RequestedPeer {
     endpoint: Option<EndpointState>,
 }
 
+/// Changes bringing the peers of the adapter to the requested state, planned from a single
+/// snapshot of both so that every peer ends up in exactly one of the lists
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WgPeerPlan {
+    /// Peers on the adapter which are not requested anymore
+    pub remove: Vec<PublicKey>,
+    /// Requested peers missing from the adapter
+    pub insert: Vec<PublicKey>,
+    /// Peers on the adapter with a config different from the requested one
+    pub update: Vec<PublicKey>,
+    /// Peers already on the adapter as requested
+    pub unchanged: Vec<PublicKey>,
+}
+
+impl WgPeerPlan {
+    fn new(
+        requested: &BTreeMap<PublicKey, RequestedPeer>,
+        actual: &BTreeMap<PublicKey, Peer>,
+    ) -> Self {
+        let mut plan = Self {
+            remove: actual
+                .keys()
+                .filter(|key| !requested.contains_key(key))
+                .copied()
+                .collect(),
+            ..Default::default()
+        };
+        for (key, requested_peer) in requested {
+            match actual.get(key) {
+                None => plan.insert.push(*key),
+                Some(actual_peer) if compare_peers(&requested_peer.peer, actual_peer) => {
+                    plan.unchanged.push(*key)
+                }
+                Some(_) => plan.update.push(*key),
+            }
+        }
+        plan
+    }
+
+    /// Whether the adapter already is in the requested state
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.insert.is_empty() && self.update.is_empty()
+    }
+}
+
 pub async fn consolidate_wg_state(
     requested_state: &RequestedState,
     entities: &Entities,
@@ -71,11 +120,23 @@ pub async fn consolidate_wg_state(
 ) -> Result {
     maybe_restart_pq(entities).await;
 
-    let remote_peer_states = if let Some(meshnet_entities) = entities.meshnet.left() {
-        meshnet_entities.derp.get_remote_peer_states().await
-    } else {
-        Default::default()
-    };
+    let remote_peer_states = remote_peer_states(entities).await;
+
+    // Don't try to reach peers the relay reports as offline
+    if features
+        .derp
+        .as_ref()
+        .is_some_and(|derp| derp.enable_peer_presence)
+    {
+        if let Some(cpc) = entities.cross_ping_check() {
+            let offline_peers = remote_peer_states
+                .iter()
+                .filter(|(_, online)| !**online)
+                .map(|(public_key, _)| *public_key)
+                .collect();
+            cpc.set_offline_peers(offline_peers).await?;
+        }
+    }
 
     consolidate_wg_private_key(
         requested_state,
@@ -113,6 +174,7 @@ pub async fn consolidate_wg_state(
         }),
         &entities.postquantum_wg,
         entities.starcast_vpeer(),
+        entities.audit_log.as_ref(),
         features,
     )
     .boxed()
@@ -146,9 +208,60 @@ pub async fn consolidate_wg_state(
         dns_pubkey,
     )
     .await?;
+    consolidate_exit_switch(
+        requested_state,
+        &*entities.wireguard_interface,
+        &*entities.firewall,
+    )
+    .await?;
     Ok(())
 }
 
+/// Changes the next consolidation would make to the peers of the adapter, without making them
+pub async fn plan_wg_peers(
+    requested_state: &RequestedState,
+    entities: &Entities,
+    features: &Features,
+) -> Result<WgPeerPlan> {
+    let proxy = entities.meshnet.left().map(|m| &*m.proxy);
+    let proxy_endpoints = if let Some(p) = proxy {
+        p.get_endpoint_map().await?
+    } else {
+        Default::default()
+    };
+    let actual_peers = entities.wireguard_interface.get_interface().await?.peers;
+
+    let requested_peers = build_desired_peers(
+        requested_state,
+        &*entities.wireguard_interface,
+        proxy,
+        &proxy_endpoints,
+        entities.cross_ping_check(),
+        entities.upgrade_sync(),
+        &*entities.dns,
+        &remote_peer_states(entities).await,
+        entities.meshnet.left().and_then(|m| {
+            m.direct
+                .as_ref()
+                .and_then(|direct| direct.stun_endpoint_provider.as_ref())
+        }),
+        &entities.postquantum_wg,
+        entities.starcast_vpeer(),
+        features,
+    )
+    .await?;
+
+    Ok(WgPeerPlan::new(&requested_peers, &actual_peers))
+}
+
+async fn remote_peer_states(entities: &Entities) -> PeersStatesMap {
+    if let Some(meshnet_entities) = entities.meshnet.left() {
+        meshnet_entities.derp.get_remote_peer_states().await
+    } else {
+        Default::default()
+    }
+}
+
 // Postquantum has a quirk that can cause VPN connections to fail due to the client having a preshared key when the server doesn't
 // This can happen if there is a handshake and a preshared key, then the client nonets for more than 180s (wg reject threshold),
 // and then tries to handshake again
@@ -214,6 +327,71 @@ async fn consolidate_wg_private_key<W: WireGuard>(
     Ok(())
 }
 
+/// Add the peer to the interface, every peer is added through here to keep the audit trail
+pub(super) async fn install_peer<W: WireGuard>(
+    wireguard_interface: &W,
+    audit_log: Option<&AuditLog>,
+    peer: Peer,
+) -> std::result::Result<(), telio_wg::Error> {
+    let public_key = peer.public_key;
+    wireguard_interface.add_peer(peer).await?;
+    if let Some(audit_log) = audit_log {
+        audit_log.peer_installed(public_key);
+    }
+    Ok(())
+}
+
+/// Remove the peer from the interface, every peer is removed through here to keep the audit
+/// trail. Removals of the peers which keep their access, e.g. to rehandshake, are not recorded.
+pub(super) async fn remove_peer<W: WireGuard>(
+    wireguard_interface: &W,
+    audit_log: Option<&AuditLog>,
+    public_key: PublicKey,
+    revoked: bool,
+) -> std::result::Result<(), telio_wg::Error> {
+    wireguard_interface.del_peer(public_key).await?;
+    if let (Some(audit_log), true) = (audit_log, revoked) {
+        audit_log.peer_removed(public_key);
+    }
+    Ok(())
+}
+
+/// Whether the peer is still allowed by the meshnet config or as the exit node
+fn has_access(requested_state: &RequestedState, public_key: &PublicKey) -> bool {
+    let is_meshnet_peer = requested_state
+        .meshnet_config
+        .as_ref()
+        .and_then(|config| config.peers.as_ref())
+        .is_some_and(|peers| peers.iter().any(|peer| peer.public_key == *public_key));
+    let is_exit_node = requested_state
+        .exit_node
+        .as_ref()
+        .is_some_and(|exit_node| exit_node.public_key == *public_key);
+    is_meshnet_peer || is_exit_node
+}
+
+/// Finish the switch to an exit node which is already connected, as it won't report the
+/// transition to connected which finishes the switch otherwise
+async fn consolidate_exit_switch<W: WireGuard, F: Firewall>(
+    requested_state: &RequestedState,
+    wireguard_interface: &W,
+    firewall: &F,
+) -> Result {
+    let Some(exit_node) = &requested_state.exit_node else {
+        return Ok(());
+    };
+    let is_connected = wireguard_interface
+        .get_interface()
+        .await?
+        .peers
+        .get(&exit_node.public_key)
+        .is_some_and(Peer::is_connected);
+    if is_connected {
+        firewall.complete_exit_switch(exit_node.public_key);
+    }
+    Ok(())
+}
+
 async fn consolidate_wg_fwmark<W: WireGuard>(
     requested_state: &RequestedState,
     wireguard_interface: &W,
@@ -348,6 +526,7 @@ async fn consolidate_wg_peers<
     upnp_ep_provider: Option<&Arc<E2>>,
     post_quantum_vpn: &impl telio_pq::PostQuantum,
     starcast_vpeer: Option<&Arc<StarcastPeer>>,
+    audit_log: Option<&AuditLog>,
     features: &Features,
 ) -> Result {
     let proxy_endpoints = if let Some(p) = proxy {
@@ -359,14 +538,17 @@ async fn consolidate_wg_peers<
     let actual_peers = wireguard_interface.get_interface().await?.peers;
     let mut is_any_peer_eligible_for_upgrade = false;
 
-    for (_, peer) in actual_peers.iter() {
-        if is_peer_proxying(peer, &proxy_endpoints) && peer.state() == NodeState::Connected {
+    for (key, peer) in actual_peers.iter() {
+        if is_peer_proxying(peer, &proxy_endpoints)
+            && peer.state() == NodeState::Connected
+            && !requested_state.idle_peers.contains(key)
+        {
             is_any_peer_eligible_for_upgrade = true;
         }
     }
 
     let ep_control = |ep: Arc<dyn EndpointProvider>| async move {
-        if is_any_peer_eligible_for_upgrade {
+        if is_any_peer_eligible_for_upgrade && !requested_state.suspended {
             telio_log_debug!("Unpausing {} provider", ep.name());
             ep.unpause().await;
         } else {
@@ -383,31 +565,37 @@ async fn consolidate_wg_peers<
         ep_control(upnp.clone()).await;
     }
 
-    let requested_peers = build_requested_peers_list(
+    let requested_peers = build_desired_peers(
         requested_state,
         wireguard_interface,
+        proxy,
+        &proxy_endpoints,
         cross_ping_check,
         upgrade_sync,
         dns,
-        &proxy_endpoints,
-        starcast_vpeer,
         &remote_peer_states,
+        stun_ep_provider,
         post_quantum_vpn,
+        starcast_vpeer,
         features,
-        stun_ep_provider,
     )
     .await?;
 
-    check_allowed_ips_correctness(&requested_peers)?;
+    // Pings would wake up the dormant peers of the lazy proxy just as keepalives would
+    let dormant_peers = match (proxy, &features.lazy_proxy) {
+        (Some(p), Some(_)) => p.get_dormant_peers().await?,
+        _ => HashSet::new(),
+    };
 
-    // Calculate diff between requested and actual list of peers
-    let requested_keys: HashSet<&PublicKey> = requested_peers.keys().collect();
-    let actual_keys: HashSet<&PublicKey> = actual_peers.keys().collect();
-    let delete_keys = &actual_keys - &requested_keys;
-    let insert_keys = &requested_keys - &actual_keys;
-    let update_keys = &requested_keys & &actual_keys;
+    let plan = WgPeerPlan::new(&requested_peers, &actual_peers);
+    if !plan.is_empty() {
+        telio_log_debug!("Reconciling WG peers: {plan:?}");
+    }
 
-    for key in delete_keys {
+    // A failed removal must not stop the others, nor the inserts and updates, otherwise the
+    // peers after it are left behind until the next consolidation
+    let mut removal_error: Option<crate::device::Error> = None;
+    for key in &plan.remove {
         let actual_peer = actual_peers.get(key).ok_or(Error::PeerNotFound)?;
         telio_log_info!("Removing peer: {:?}", actual_peer);
 
@@ -420,21 +608,41 @@ async fn consolidate_wg_peers<
             timestamp: Instant::now(),
         };
         aggregator.report_peer_state_relayed(&event).await;
+        aggregator.report_peer_path(*key, None).await;
 
-        wireguard_interface.del_peer(*key).await?;
+        let revoked = !has_access(requested_state, key);
+        if let Err(e) = remove_peer(wireguard_interface, audit_log, *key, revoked).await {
+            telio_log_warn!("Failed to remove peer {key:?}: {e}");
+            removal_error.get_or_insert(e.into());
+            continue;
+        }
 
         // Remove from session_keeper
         if let Some(sk) = session_keeper {
-            sk.remove_node(key).await?;
+            if let Err(e) = sk.remove_node(key).await {
+                telio_log_warn!("Failed to remove peer {key:?} from the session keeper: {e}");
+                removal_error.get_or_insert(e.into());
+            }
         }
     }
 
-    for key in insert_keys {
+    // Peers removed while off the interface, e.g. idle ones, lose their access here
+    if let Some(audit_log) = audit_log {
+        audit_log.retain_peers(|key| {
+            requested_peers.contains_key(key) || has_access(requested_state, key)
+        });
+    }
+
+    for key in &plan.insert {
         telio_log_info!("Inserting peer: {:?}", requested_peers.get(key));
         let peer = requested_peers.get(key).ok_or(Error::PeerNotFound)?;
         let ip_addresses = peer.peer.ip_addresses.clone();
 
-        wireguard_interface.add_peer(peer.peer.clone()).await?;
+        install_peer(wireguard_interface, audit_log, peer.peer.clone()).await?;
+        let is_proxying = is_peer_proxying(&peer.peer, &proxy_endpoints);
+        aggregator
+            .report_peer_path(*key, Some(path_type(is_proxying)))
+            .await;
 
         // Add peer to session keeper if needed
         match (session_keeper, peer.batching_keepalive_interval) {
@@ -461,29 +669,39 @@ async fn consolidate_wg_peers<
         }
     }
 
-    for key in update_keys {
+    let kept = plan.update.iter().map(|key| (key, true));
+    let kept = kept.chain(plan.unchanged.iter().map(|key| (key, false)));
+    for (key, changed) in kept {
         let requested_peer = requested_peers.get(key).ok_or(Error::PeerNotFound)?;
         let actual_peer = actual_peers.get(key).ok_or(Error::PeerNotFound)?;
 
-        // Check if anything of importance has changed and update if needed
-        if !compare_peers(&requested_peer.peer, actual_peer) {
-            // Update peer
+        if changed {
             telio_log_info!(
                 "Peer updated: {:?} -> {:?}",
                 actual_peers.get(key),
                 requested_peers.get(key)
             );
 
-            wireguard_interface
-                .add_peer(requested_peer.peer.clone())
-                .await?;
+            install_peer(wireguard_interface, audit_log, requested_peer.peer.clone()).await?;
         }
 
         let is_actual_peer_proxying = is_peer_proxying(actual_peer, &proxy_endpoints);
         let is_requested_peer_proxying = is_peer_proxying(&requested_peer.peer, &proxy_endpoints);
+        aggregator
+            .report_peer_path(*key, Some(path_type(is_requested_peer_proxying)))
+            .await;
 
         if let Some(sk) = session_keeper {
-            if !is_actual_peer_proxying && is_requested_peer_proxying && features.batching.is_none()
+            let quiet = requested_state.suspended
+                || requested_state.idle_peers.contains(key)
+                || (dormant_peers.contains(key) && is_requested_peer_proxying);
+            if quiet {
+                if sk.get_interval(key).await.is_some() {
+                    sk.remove_node(key).await?;
+                }
+            } else if !is_actual_peer_proxying
+                && is_requested_peer_proxying
+                && features.batching.is_none()
             {
                 sk.remove_node(key).await?;
             } else if let Some(interval) = requested_peer.batching_keepalive_interval {
@@ -591,7 +809,107 @@ async fn consolidate_wg_peers<
         );
     }
 
-    Ok(())
+    removal_error.map_or(Ok(()), Err)
+}
+
+/// Peers the adapter should have, i.e. the desired state of the reconciliation
+#[allow(clippy::too_many_arguments)]
+async fn build_desired_peers<
+    W: WireGuard,
+    P: Proxy,
+    C: CrossPingCheckTrait,
+    U: UpgradeSyncTrait,
+    D: DnsResolver,
+    E: EndpointProvider,
+>(
+    requested_state: &RequestedState,
+    wireguard_interface: &W,
+    proxy: Option<&P>,
+    proxy_endpoints: &EndpointMap,
+    cross_ping_check: Option<&Arc<C>>,
+    upgrade_sync: Option<&Arc<U>>,
+    dns: &Mutex<crate::device::DNS<D>>,
+    remote_peer_states: &PeersStatesMap,
+    stun_ep_provider: Option<&Arc<E>>,
+    post_quantum_vpn: &impl telio_pq::PostQuantum,
+    starcast_vpeer: Option<&Arc<StarcastPeer>>,
+    features: &Features,
+) -> Result<BTreeMap<PublicKey, RequestedPeer>> {
+    let mut requested_peers = build_requested_peers_list(
+        requested_state,
+        wireguard_interface,
+        cross_ping_check,
+        upgrade_sync,
+        dns,
+        proxy_endpoints,
+        starcast_vpeer,
+        remote_peer_states,
+        post_quantum_vpn,
+        features,
+        stun_ep_provider,
+    )
+    .await?;
+
+    // Keepalives would wake up every dormant peer, so they are left for the actual traffic
+    if let (Some(p), Some(_)) = (proxy, &features.lazy_proxy) {
+        let dormant_peers = p.get_dormant_peers().await?;
+        for (_, requested_peer) in requested_peers
+            .iter_mut()
+            .filter(|(key, _)| dormant_peers.contains(key))
+        {
+            if is_peer_proxying(&requested_peer.peer, proxy_endpoints) {
+                requested_peer.peer.persistent_keepalive_interval = None;
+                requested_peer.batching_keepalive_interval = None;
+            }
+        }
+    }
+
+    // Data flowing both ways keeps the NAT mappings open, so keepalives are held back until
+    // the link is idle for so long that the mapping could time out within one keepalive period
+    if let Some(passive_keepalive) = &features.passive_keepalive {
+        let nat_timeout = Duration::from_secs(passive_keepalive.nat_timeout_s.into());
+        for (public_key, requested_peer) in requested_peers.iter_mut() {
+            let Some(keepalive) = requested_peer
+                .peer
+                .persistent_keepalive_interval
+                .or(requested_peer.batching_keepalive_interval)
+            else {
+                continue;
+            };
+            let Some(idle_limit) = nat_timeout.checked_sub(Duration::from_secs(keepalive.into()))
+            else {
+                continue;
+            };
+            let is_recent = |elapsed: Option<Duration>| elapsed.is_some_and(|e| e < idle_limit);
+            if is_recent(wireguard_interface.time_since_last_rx(*public_key).await?)
+                && is_recent(wireguard_interface.time_since_last_tx(*public_key).await?)
+            {
+                telio_log_debug!("Skipping keepalives of {public_key:?}, data is flowing");
+                requested_peer.peer.persistent_keepalive_interval = None;
+                requested_peer.batching_keepalive_interval = None;
+            }
+        }
+    }
+
+    // Suspended device stays quiet, the peers are kept only for the traffic of the apps
+    if requested_state.suspended {
+        for requested_peer in requested_peers.values_mut() {
+            requested_peer.peer.persistent_keepalive_interval = None;
+            requested_peer.batching_keepalive_interval = None;
+        }
+    }
+
+    // Idle peers of the on-demand meshnet stay quiet until traffic is sent to them
+    for (_, requested_peer) in requested_peers
+        .iter_mut()
+        .filter(|(key, _)| requested_state.idle_peers.contains(key))
+    {
+        requested_peer.peer.persistent_keepalive_interval = None;
+        requested_peer.batching_keepalive_interval = None;
+    }
+
+    check_allowed_ips_correctness(&requested_peers)?;
+    Ok(requested_peers)
 }
 
 fn check_allowed_ips_correctness(peers: &BTreeMap<PublicKey, RequestedPeer>) -> Result {
@@ -767,7 +1085,44 @@ async fn build_requested_peers_list<
         } else {
             // Exit node is a fresh node, therefore - insert create new peer
             let public_key = exit_node.public_key;
-            let endpoint = exit_node.endpoint;
+            let endpoint = match (
+                &features.vpn_relay_fallback,
+                proxy_endpoints.get(&public_key).and_then(|eps| eps.first()),
+            ) {
+                (Some(fallback), Some(proxy_endpoint)) => {
+                    // Tuned for the cellular network, if the device is on one
+                    let fallback = &FeatureVpnRelayFallback {
+                        handshake_timeout_s: requested_state
+                            .vpn_handshake_timeout_s
+                            .unwrap_or(fallback.handshake_timeout_s),
+                        ..*fallback
+                    };
+                    let actual_peer = wireguard_interface
+                        .get_interface()
+                        .await?
+                        .peers
+                        .remove(&public_key);
+                    // Handshake packets are not counted by the interface
+                    let time_since_last_rx = wireguard_interface
+                        .time_since_last_rx(public_key)
+                        .await?
+                        .into_iter()
+                        .chain(
+                            actual_peer
+                                .as_ref()
+                                .and_then(|p| p.time_since_last_handshake),
+                        )
+                        .min();
+                    select_vpn_endpoint(
+                        exit_node.endpoint,
+                        *proxy_endpoint,
+                        actual_peer.as_ref(),
+                        time_since_last_rx,
+                        fallback,
+                    )
+                }
+                _ => exit_node.endpoint,
+            };
 
             let mut ip_addresses = vec![VPN_INTERNAL_IPV4.into()];
             if features.ipv6 {
@@ -811,6 +1166,45 @@ async fn build_requested_peers_list<
         }
     }
 
+    // Local networks stay reachable, at the cost of the peers inside them
+    if features.route_conflicts.is_some_and(|f| f.exclude) {
+        let local = route_conflicts::host_networks(
+            &SystemGetIfAddrs,
+            requested_state.device_config.name.as_deref(),
+            requested_state.meshnet_config.as_ref(),
+        );
+        for requested_peer in requested_peers.values_mut() {
+            requested_peer.peer.allowed_ips =
+                route_conflicts::exclude_conflicts(&requested_peer.peer.allowed_ips, &local);
+        }
+    }
+
+    // Stage the candidate exit node without routing anything through it. Switching to it then
+    // only changes its allowed IPs, reusing the session if it handshaked already.
+    if let Some(prewarmed) = &requested_state.prewarmed_exit_node {
+        if !requested_peers.contains_key(&prewarmed.public_key) {
+            let persistent_keepalive_interval = prewarmed.handshake.then(|| {
+                requested_state
+                    .keepalive_periods
+                    .vpn
+                    .unwrap_or(PREWARM_KEEPALIVE_INTERVAL)
+            });
+            requested_peers.insert(
+                prewarmed.public_key,
+                RequestedPeer {
+                    peer: telio_wg::uapi::Peer {
+                        public_key: prewarmed.public_key,
+                        endpoint: Some(prewarmed.endpoint),
+                        persistent_keepalive_interval,
+                        ..Default::default()
+                    },
+                    batching_keepalive_interval: None,
+                    endpoint: None,
+                },
+            );
+        }
+    }
+
     // Add DNS peer if enabled
     let dns = dns.lock().await;
     if let (Some(_), Some(resolver)) = (&requested_state.upstream_servers, &dns.resolver) {
@@ -1044,20 +1438,25 @@ async fn build_requested_meshnet_peers_list<
             }
         }
 
-        // Select actual endpoint
-        let (selected_remote_endpoint, selected_local_endpoint) = select_endpoint_for_peer(
-            public_key,
-            &actual_peer.cloned(),
-            &time_since_last_rx_or_handshake,
-            peer_state,
-            &checked_endpoint.cloned(),
-            match proxy_endpoint {
-                Some(eps) => eps,
-                None => &[],
-            },
-            &upgrade_request_endpoint,
-        )
-        .await?;
+        // Select actual endpoint, nodes forced to relay are never upgraded
+        let (selected_remote_endpoint, selected_local_endpoint) =
+            if requested_state.relay_only_peers.contains(public_key) {
+                (proxy_endpoint.and_then(|eps| eps.first().copied()), None)
+            } else {
+                select_endpoint_for_peer(
+                    public_key,
+                    &actual_peer.cloned(),
+                    &time_since_last_rx_or_handshake,
+                    peer_state,
+                    &checked_endpoint.cloned(),
+                    match proxy_endpoint {
+                        Some(eps) => eps,
+                        None => &[],
+                    },
+                    &upgrade_request_endpoint,
+                )
+                .await?
+            };
 
         // Apply the selected endpoints, and save local endpoint because we may need to share it
         // with the other end
@@ -1091,6 +1490,43 @@ async fn build_requested_meshnet_peers_list<
     Ok(requested_peers)
 }
 
+/// Choose between the direct and the relayed endpoint of a VPN server. The direct endpoint is
+/// used until it stays silent for too long after being set, and is retried periodically while
+/// the traffic is relayed.
+fn select_vpn_endpoint(
+    direct_endpoint: Option<SocketAddr>,
+    proxy_endpoint: SocketAddr,
+    actual_peer: Option<&Peer>,
+    time_since_last_rx: Option<Duration>,
+    fallback: &FeatureVpnRelayFallback,
+) -> Option<SocketAddr> {
+    let direct_endpoint = direct_endpoint?;
+    let (actual_endpoint, time_since_endpoint_change) =
+        match actual_peer.and_then(|p| Some((p.endpoint?, p.endpoint_changed_at?.0.elapsed()))) {
+            Some(actual) => actual,
+            None => return Some(direct_endpoint),
+        };
+
+    if actual_endpoint == proxy_endpoint {
+        if time_since_endpoint_change >= Duration::from_secs(fallback.direct_retry_interval_s) {
+            telio_log_info!("Retrying direct endpoint {direct_endpoint:?} of the VPN server");
+            return Some(direct_endpoint);
+        }
+        return Some(proxy_endpoint);
+    }
+
+    let responded = time_since_last_rx.map_or(false, |rx| rx < time_since_endpoint_change);
+    if !responded && time_since_endpoint_change >= Duration::from_secs(fallback.handshake_timeout_s)
+    {
+        telio_log_warn!(
+            "VPN server did not respond on {direct_endpoint:?}, falling back to the relay"
+        );
+        return Some(proxy_endpoint);
+    }
+
+    Some(direct_endpoint)
+}
+
 /// Internal peers will never have IP collisions, but external peers can collide with both internal and external peers
 /// In case of collision, exclude the colliding IPs from external peers
 /// If a peer ends up not having any IPs after deduplicating, the peer will be unreachable
@@ -1244,13 +1680,23 @@ async fn select_endpoint_for_peer<'a>(
 // status fields, like handshake timestamps, tx'ed or rx'ed data or similar is *not* compared. What
 // is more, "None" peer will always compare _false_ to anything
 fn compare_peers(a: &telio_wg::uapi::Peer, b: &telio_wg::uapi::Peer) -> bool {
+    // Adapters report disabled keepalives as zero
     a.public_key == b.public_key
         && a.endpoint == b.endpoint
-        && a.persistent_keepalive_interval == b.persistent_keepalive_interval
+        && a.persistent_keepalive_interval.unwrap_or(0)
+            == b.persistent_keepalive_interval.unwrap_or(0)
         && a.allowed_ips == b.allowed_ips
         && a.preshared_key == b.preshared_key
 }
 
+fn path_type(is_proxying: bool) -> PathType {
+    if is_proxying {
+        PathType::Relay
+    } else {
+        PathType::Direct
+    }
+}
+
 fn is_peer_proxying(peer: &telio_wg::uapi::Peer, proxy_endpoints: &EndpointMap) -> bool {
     // If proxy has no knowledge of the public key -> the node definitely does not proxy
     let proxy_endpoints = if let Some(proxy_endpoints) = proxy_endpoints.get(&peer.public_key) {
@@ -1329,7 +1775,7 @@ fn peer_state(
 mod tests {
     use super::*;
 
-    use crate::device::{DeviceConfig, DNS};
+    use crate::device::{DeviceConfig, PrewarmedExitNode, DNS};
     use mockall::predicate::{self, eq};
     use rstest::*;
     use telio_nurse::config::AggregatorConfig;
@@ -1341,7 +1787,8 @@ mod tests {
     use telio_firewall::firewall::{MockFirewall, FILE_SEND_PORT};
     use telio_model::config::{Config, PeerBase, Server};
     use telio_model::features::{
-        EndpointProvider as ApiEndpointProvider, FeatureBatching, FeatureDns, TtlValue,
+        EndpointProvider as ApiEndpointProvider, FeatureBatching, FeatureDns,
+        FeaturePassiveKeepalive, TtlValue,
     };
     use telio_model::mesh::ExitNode;
     use telio_pq::MockPostQuantum;
@@ -1759,6 +2206,54 @@ mod tests {
         .unwrap();
     }
 
+    #[tokio::test]
+    async fn switch_to_connected_exit_node_is_completed() {
+        let exit_key = SecretKey::gen().public();
+        let requested_state = RequestedState {
+            exit_node: Some(ExitNode {
+                public_key: exit_key,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut wg_mock = MockWireGuard::new();
+        wg_mock.expect_get_interface().returning(move || {
+            Ok(Interface {
+                peers: BTreeMap::from([(
+                    exit_key,
+                    Peer {
+                        public_key: exit_key,
+                        time_since_last_rx: Some(Duration::from_secs(1)),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            })
+        });
+        let mut firewall = MockFirewall::new();
+        firewall
+            .expect_complete_exit_switch()
+            .with(eq(exit_key))
+            .once()
+            .return_const(());
+
+        consolidate_exit_switch(&requested_state, &wg_mock, &firewall)
+            .await
+            .unwrap();
+
+        // A switch to an exit node which is still connecting waits for it to connect
+        let mut wg_mock = MockWireGuard::new();
+        wg_mock
+            .expect_get_interface()
+            .returning(|| Ok(Interface::default()));
+        let firewall = MockFirewall::new();
+
+        consolidate_exit_switch(&requested_state, &wg_mock, &firewall)
+            .await
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn remove_meshnet_exit_node_from_firewall_if_it_does_not_allow_incoming_connections_anymore(
     ) {
@@ -1824,6 +2319,7 @@ mod tests {
                 dns: Mutex::new(DNS {
                     resolver: Some(MockDnsResolver::new()),
                     virtual_host_tun_fd: None,
+                    blocklist: None,
                 }),
                 // currently we care only about ipv6, so they might be empty
                 features: Features {
@@ -1850,10 +2346,34 @@ mod tests {
                     dns: FeatureDns {
                         exit_dns: None,
                         ttl_value: TtlValue(60),
+                        forward_rules: Vec::new(),
+                        soa: None,
+                        blocklist: None,
                     },
                     pmtu_discovery: Default::default(),
                     multicast: false,
                     batching: None,
+                    socks5: None,
+                    obfuscation: None,
+                    peer_unreachable: None,
+                    lazy_proxy: None,
+                    redact_node_endpoints: false,
+                    vpn_relay_fallback: None,
+                    node_event_debounce: None,
+                    passive_keepalive: None,
+                    cellular: None,
+                    peer_limit: None,
+                    dscp: None,
+                    mesh_watchdog: None,
+                    high_availability: None,
+                    route_conflicts: None,
+                    audit_log: None,
+                    threads: None,
+                    config_queue: None,
+                    api_timeout: None,
+                    wake_on_lan: None,
+                    peer_stats: None,
+                    on_demand: None,
                 },
                 post_quantum: MockPostQuantum::new(),
                 stun_ep_provider,
@@ -1986,6 +2506,17 @@ mod tests {
                 });
         }
 
+        fn when_time_since_last_tx(&mut self, input: Vec<(PublicKey, u64)>) {
+            self.wireguard_interface
+                .expect_time_since_last_tx()
+                .returning(move |pk| {
+                    input
+                        .iter()
+                        .find(|i| i.0 == pk)
+                        .map_or(Ok(None), |i| Ok(Some(Duration::from_secs(i.1))))
+                });
+        }
+
         fn when_cross_check_validated_endpoints(
             &mut self,
             input: Vec<(PublicKey, SocketAddr, (SocketAddr, Session), Instant)>,
@@ -2199,6 +2730,10 @@ mod tests {
         }
 
         async fn consolidate_peers(self) {
+            self.try_consolidate_peers().await.unwrap();
+        }
+
+        async fn try_consolidate_peers(self) -> Result {
             let cross_ping_check = Arc::new(self.cross_ping_check);
             let upgrade_sync = Arc::new(self.upgrade_sync);
             let session_keeper = Arc::new(self.session_keeper);
@@ -2225,10 +2760,10 @@ mod tests {
                 upnp_ep_provider.as_ref(),
                 &self.post_quantum,
                 None,
+                None,
                 &self.features,
             )
             .await
-            .unwrap();
         }
 
         async fn consolidate_endpoint_invalidation(self) {
@@ -2361,7 +2896,77 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn when_ep_is_validated_after_wg_update_produce_no_failed_notification() {
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    async fn when_suspended_then_peer_is_added_without_keepalives(#[case] batching: bool) {
+        let mut f = Fixture::new();
+        if batching {
+            f.features.batching = Some(FeatureBatching::default());
+        }
+
+        let pub_key = SecretKey::gen().public();
+        let ip1 = IpAddr::from([1, 2, 3, 4]);
+        let ip1v6 = IpAddr::from([1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]);
+        let allowed_ips = vec![ip1, ip1v6];
+        let mapped_port = 18;
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], mapped_port));
+
+        f.requested_state.keepalive_periods.proxying = Some(1234);
+        f.requested_state.suspended = true;
+
+        f.when_requested_meshnet_config(vec![(pub_key, allowed_ips.clone())]);
+        f.when_proxy_mapping(vec![(pub_key, mapped_port)]);
+        f.when_current_peers(vec![]);
+        f.when_time_since_last_rx(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+
+        f.then_add_peer(vec![(
+            pub_key,
+            proxy_endpoint,
+            None,
+            allowed_ips.iter().copied().map(|ip| ip.into()).collect(),
+            allowed_ips,
+        )]);
+
+        f.consolidate_peers().await;
+    }
+
+    #[tokio::test]
+    async fn when_idle_on_demand_then_peer_is_added_without_keepalives() {
+        let mut f = Fixture::new();
+
+        let pub_key = SecretKey::gen().public();
+        let ip1 = IpAddr::from([1, 2, 3, 4]);
+        let ip1v6 = IpAddr::from([1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]);
+        let allowed_ips = vec![ip1, ip1v6];
+        let mapped_port = 18;
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], mapped_port));
+
+        f.requested_state.keepalive_periods.proxying = Some(1234);
+        f.requested_state.idle_peers = HashSet::from([pub_key]);
+
+        f.when_requested_meshnet_config(vec![(pub_key, allowed_ips.clone())]);
+        f.when_proxy_mapping(vec![(pub_key, mapped_port)]);
+        f.when_current_peers(vec![]);
+        f.when_time_since_last_rx(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+
+        f.then_add_peer(vec![(
+            pub_key,
+            proxy_endpoint,
+            None,
+            allowed_ips.iter().copied().map(|ip| ip.into()).collect(),
+            allowed_ips,
+        )]);
+
+        f.consolidate_peers().await;
+    }
+
+    #[tokio::test]
+    async fn when_ep_is_validated_after_wg_update_produce_no_failed_notification() {
         // No failed notification should be provided regardless
         // whether we are proxying or in direct connection.
         //
@@ -2770,6 +3375,86 @@ mod tests {
         f.consolidate_peers().await;
     }
 
+    #[tokio::test]
+    async fn when_removing_peer_fails_then_other_peers_are_still_removed() {
+        let mut f = Fixture::new();
+
+        let failing_key = SecretKey::gen().public();
+        let pub_key = SecretKey::gen().public();
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], 12));
+
+        f.when_requested_meshnet_config(vec![]);
+        f.when_proxy_mapping(vec![]);
+        f.when_current_peers(
+            [(failing_key, [1, 2, 3, 4]), (pub_key, [5, 6, 7, 8])]
+                .into_iter()
+                .map(|(key, ip)| {
+                    (
+                        key,
+                        proxy_endpoint,
+                        TEST_PERSISTENT_KEEPALIVE_PERIOD,
+                        vec![IpAddr::from(ip)],
+                        (Instant::now(), UpdateReason::Pull),
+                    )
+                })
+                .collect(),
+        );
+        f.when_time_since_last_rx(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+        f.session_keeper.expect_remove_node().returning(|_| Ok(()));
+
+        f.wireguard_interface
+            .expect_del_peer()
+            .once()
+            .with(eq(failing_key))
+            .return_once(|_| Err(crate::device::AdapterError::RestartFailed));
+        f.then_del_peer(vec![pub_key]);
+
+        assert!(f.try_consolidate_peers().await.is_err());
+    }
+
+    #[test]
+    fn peer_plan_puts_every_peer_in_one_list() {
+        let [removed, inserted, updated, unchanged] = [(); 4].map(|_| SecretKey::gen().public());
+        let make_peer = |public_key, persistent_keepalive_interval| Peer {
+            public_key,
+            persistent_keepalive_interval,
+            ..Default::default()
+        };
+        let requested: BTreeMap<_, _> = [(inserted, 25), (updated, 10), (unchanged, 25)]
+            .into_iter()
+            .map(|(key, keepalive)| {
+                let requested_peer = RequestedPeer {
+                    peer: make_peer(key, Some(keepalive)),
+                    batching_keepalive_interval: None,
+                    endpoint: None,
+                };
+                (key, requested_peer)
+            })
+            .collect();
+        let actual: BTreeMap<_, _> = [removed, updated, unchanged]
+            .into_iter()
+            .map(|key| (key, make_peer(key, Some(25))))
+            .collect();
+
+        let plan = WgPeerPlan::new(&requested, &actual);
+        assert_eq!(
+            plan,
+            WgPeerPlan {
+                remove: vec![removed],
+                insert: vec![inserted],
+                update: vec![updated],
+                unchanged: vec![unchanged],
+            }
+        );
+        assert!(!plan.is_empty());
+        assert!(WgPeerPlan::new(&requested, &BTreeMap::new())
+            .remove
+            .is_empty());
+        assert!(WgPeerPlan::new(&BTreeMap::new(), &BTreeMap::new()).is_empty());
+    }
+
     #[tokio::test]
     async fn when_no_change_in_peers_then_no_update() {
         let mut f = Fixture::new();
@@ -2801,6 +3486,43 @@ mod tests {
         f.consolidate_peers().await;
     }
 
+    #[tokio::test]
+    async fn when_peer_is_relay_only_then_do_not_upgrade() {
+        let mut f = Fixture::new();
+
+        let pub_key = SecretKey::gen().public();
+        let ip1 = IpAddr::from([1, 2, 3, 4]);
+        let ip1v6 = IpAddr::from([1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]);
+        let allowed_ips = vec![ip1, ip1v6];
+        let remote_wg_endpoint = SocketAddr::from(([192, 168, 0, 1], 13));
+        let local_wg_endpoint = (SocketAddr::from(([192, 168, 0, 2], 15)), rand::random());
+        let mapped_port = 12;
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], mapped_port));
+
+        f.requested_state.relay_only_peers.insert(pub_key);
+        f.when_requested_meshnet_config(vec![(pub_key, allowed_ips.clone())]);
+        f.when_proxy_mapping(vec![(pub_key, mapped_port)]);
+        f.when_current_peers(vec![(
+            pub_key,
+            proxy_endpoint,
+            TEST_PERSISTENT_KEEPALIVE_PERIOD,
+            allowed_ips,
+            (Instant::now() - Duration::from_secs(4), UpdateReason::Pull),
+        )]);
+        f.when_time_since_last_rx(vec![(pub_key, 0)]);
+        f.when_cross_check_validated_endpoints(vec![(
+            pub_key,
+            remote_wg_endpoint,
+            local_wg_endpoint,
+            Instant::now(),
+        )]);
+        f.when_upgrade_requests(vec![]);
+        f.session_keeper.expect_get_interval().return_const(None);
+        // then the peer stays relayed, nothing happens
+
+        f.consolidate_peers().await;
+    }
+
     #[tokio::test]
     async fn when_direct_connection_becomes_stable() {
         let mut f = Fixture::new();
@@ -2983,6 +3705,167 @@ mod tests {
         f.consolidate_peers().await;
     }
 
+    #[tokio::test]
+    #[rstest]
+    #[case(true, Some(4321))]
+    #[case(false, None)]
+    async fn when_exit_node_is_prewarmed_then_it_is_added_without_allowed_ips(
+        #[case] handshake: bool,
+        #[case] keepalive: Option<u32>,
+    ) {
+        let mut f = Fixture::new();
+
+        let public_key = SecretKey::gen().public();
+        let endpoint = SocketAddr::from(([192, 168, 0, 2], 51820));
+
+        f.requested_state.keepalive_periods.vpn = Some(4321);
+        f.requested_state.prewarmed_exit_node = Some(PrewarmedExitNode {
+            public_key,
+            endpoint,
+            handshake,
+        });
+
+        f.when_requested_meshnet_config(vec![]);
+        f.when_proxy_mapping(vec![]);
+        f.when_current_peers(vec![]);
+        f.when_time_since_last_rx(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+
+        f.then_add_peer(vec![(public_key, endpoint, keepalive, vec![], vec![])]);
+
+        f.consolidate_peers().await;
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case(10, 1, None)]
+    #[case(10, 25, Some(10))]
+    #[case(4321, 1, Some(4321))]
+    async fn when_vpn_peer_has_passive_keepalive(
+        #[case] keepalive_period: u32,
+        #[case] time_since_last_tx: u64,
+        #[case] keepalive: Option<u32>,
+    ) {
+        let mut f = Fixture::new();
+        f.features.passive_keepalive = Some(FeaturePassiveKeepalive { nat_timeout_s: 30 });
+
+        let public_key = SecretKey::gen().public();
+        let allowed_ips = vec![
+            IpNet::new(IpAddr::from([7, 6, 5, 4]), 23).unwrap(),
+            IpNet::new(
+                IpAddr::from([5, 6, 7, 8, 5, 6, 7, 8, 5, 6, 7, 8, 5, 6, 7, 8]),
+                128,
+            )
+            .unwrap(),
+        ];
+        let ip_addresses = vec![
+            VPN_INTERNAL_IPV4.into(),
+            VPN_INTERNAL_IPV6.into(),
+            VPN_EXTERNAL_IPV4.into(),
+        ];
+        let endpoint_raw = SocketAddr::from(([192, 168, 0, 1], 13));
+
+        f.requested_state.keepalive_periods.vpn = Some(keepalive_period);
+        f.requested_state.exit_node = Some(ExitNode {
+            identifier: "".to_owned(),
+            public_key,
+            allowed_ips: Some(allowed_ips.clone()),
+            endpoint: Some(endpoint_raw),
+        });
+        f.features.ipv6 = true;
+
+        f.when_requested_meshnet_config(vec![]);
+        f.when_proxy_mapping(vec![]);
+        f.when_current_peers(vec![]);
+        f.when_time_since_last_rx(vec![(public_key, 1)]);
+        f.when_time_since_last_tx(vec![(public_key, time_since_last_tx)]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+
+        f.then_add_peer(vec![(
+            public_key,
+            endpoint_raw,
+            keepalive,
+            allowed_ips,
+            ip_addresses,
+        )]);
+
+        f.then_post_quantum_is_checked();
+
+        f.consolidate_peers().await;
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case(None, true)]
+    #[case(Some(30), false)]
+    async fn when_vpn_peer_is_silent_then_relay_fallback_is_used(
+        #[case] tuned_handshake_timeout_s: Option<u64>,
+        #[case] falls_back: bool,
+    ) {
+        let mut f = Fixture::new();
+        f.features.vpn_relay_fallback = Some(FeatureVpnRelayFallback::default());
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], 4242));
+
+        let public_key = SecretKey::gen().public();
+        let allowed_ips = vec![
+            IpNet::new(IpAddr::from([7, 6, 5, 4]), 23).unwrap(),
+            IpNet::new(
+                IpAddr::from([5, 6, 7, 8, 5, 6, 7, 8, 5, 6, 7, 8, 5, 6, 7, 8]),
+                128,
+            )
+            .unwrap(),
+        ];
+        let ip_addresses = vec![
+            VPN_INTERNAL_IPV4.into(),
+            VPN_INTERNAL_IPV6.into(),
+            VPN_EXTERNAL_IPV4.into(),
+        ];
+        let endpoint_raw = SocketAddr::from(([192, 168, 0, 1], 13));
+
+        f.requested_state.keepalive_periods.vpn = Some(4321);
+        f.requested_state.vpn_handshake_timeout_s = tuned_handshake_timeout_s;
+        f.requested_state.exit_node = Some(ExitNode {
+            identifier: "".to_owned(),
+            public_key,
+            allowed_ips: Some(allowed_ips.clone()),
+            endpoint: Some(endpoint_raw),
+        });
+
+        f.when_requested_meshnet_config(vec![]);
+        f.when_proxy_mapping(vec![(public_key, proxy_endpoint.port())]);
+        f.when_current_peers(vec![(
+            public_key,
+            endpoint_raw,
+            4321,
+            ip_addresses.clone(),
+            (Instant::now() - Duration::from_secs(20), UpdateReason::Push),
+        )]);
+        f.when_time_since_last_rx(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+        f.upgrade_sync
+            .expect_get_accepted_session()
+            .returning(|_| None);
+
+        f.then_add_peer(vec![(
+            public_key,
+            if falls_back {
+                proxy_endpoint
+            } else {
+                endpoint_raw
+            },
+            Some(4321),
+            allowed_ips,
+            ip_addresses,
+        )]);
+
+        f.then_post_quantum_is_checked();
+
+        f.consolidate_peers().await;
+    }
+
     #[tokio::test]
     async fn when_stun_peer_should_be_added() {
         #[derive(PartialEq)]
@@ -3197,6 +4080,30 @@ mod tests {
         f.consolidate_peers().await;
     }
 
+    #[test]
+    fn disabled_keepalive_matches_zero_interval() {
+        let peer = Peer {
+            public_key: SecretKey::gen().public(),
+            persistent_keepalive_interval: Some(0),
+            ..Default::default()
+        };
+
+        assert!(compare_peers(
+            &peer,
+            &Peer {
+                persistent_keepalive_interval: None,
+                ..peer.clone()
+            }
+        ));
+        assert!(!compare_peers(
+            &peer,
+            &Peer {
+                persistent_keepalive_interval: Some(25),
+                ..peer.clone()
+            }
+        ));
+    }
+
     #[test]
     fn test_ip_deduplication() {
         fn make_peer(
@@ -3254,4 +4161,84 @@ mod tests {
         assert_eq!(deduplicated_ips[&peer3_key], peer3_expected_ips);
         assert!(deduplicated_ips[&peer4_key].is_empty());
     }
+
+    #[test]
+    fn test_select_vpn_endpoint() {
+        let direct: SocketAddr = "1.2.3.4:51820".parse().unwrap();
+        let proxy: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let fallback = FeatureVpnRelayFallback {
+            handshake_timeout_s: 15,
+            direct_retry_interval_s: 300,
+        };
+        let peer = |endpoint, changed_s| Peer {
+            endpoint: Some(endpoint),
+            endpoint_changed_at: Some((
+                Instant::now() - Duration::from_secs(changed_s),
+                UpdateReason::Push,
+            )),
+            ..Default::default()
+        };
+
+        // Fresh peer starts on the direct endpoint
+        assert_eq!(
+            select_vpn_endpoint(Some(direct), proxy, None, None, &fallback),
+            Some(direct)
+        );
+
+        // Direct endpoint gets some time to respond
+        assert_eq!(
+            select_vpn_endpoint(Some(direct), proxy, Some(&peer(direct, 5)), None, &fallback),
+            Some(direct)
+        );
+
+        // Silent direct endpoint falls back to the relay
+        assert_eq!(
+            select_vpn_endpoint(
+                Some(direct),
+                proxy,
+                Some(&peer(direct, 20)),
+                None,
+                &fallback
+            ),
+            Some(proxy)
+        );
+        assert_eq!(
+            select_vpn_endpoint(
+                Some(direct),
+                proxy,
+                Some(&peer(direct, 20)),
+                Some(Duration::from_secs(30)),
+                &fallback
+            ),
+            Some(proxy)
+        );
+
+        // Responding direct endpoint is kept
+        assert_eq!(
+            select_vpn_endpoint(
+                Some(direct),
+                proxy,
+                Some(&peer(direct, 20)),
+                Some(Duration::from_secs(1)),
+                &fallback
+            ),
+            Some(direct)
+        );
+
+        // Relayed peer stays on the relay until the direct endpoint is retried
+        assert_eq!(
+            select_vpn_endpoint(Some(direct), proxy, Some(&peer(proxy, 20)), None, &fallback),
+            Some(proxy)
+        );
+        assert_eq!(
+            select_vpn_endpoint(
+                Some(direct),
+                proxy,
+                Some(&peer(proxy, 300)),
+                None,
+                &fallback
+            ),
+            Some(direct)
+        );
+    }
 }
@@ -1,3 +1,5 @@
+pub mod c_api;
+pub mod crash_report;
 pub mod defaults_builder;
 pub mod logging;
 pub mod types;
@@ -6,7 +8,13 @@ use anyhow::anyhow;
 use ffi_helpers::{error_handling, panic as panic_handling};
 use ipnet::IpNet;
 use rand::Rng;
-use telio_crypto::{PublicKey, SecretKey};
+use telio_crypto::{
+    acceleration::{crypto_acceleration_report, CryptoAccelerationReport},
+    PublicKey, SecretKey,
+};
+use telio_dns::DnsBlocklistStats;
+use telio_firewall::firewall::MonitorStats;
+use telio_relay::queue::DroppedPackets;
 use telio_wg::AdapterType;
 use tracing::{error, trace};
 
@@ -14,6 +22,7 @@ use telio_sockets::protector::make_external_protector;
 use uuid::Uuid;
 
 use std::{
+    cell::RefCell,
     net::{IpAddr, SocketAddr},
     panic::{self, AssertUnwindSafe},
     sync::{Arc, Mutex, Once},
@@ -23,25 +32,34 @@ use std::{
 use self::{logging::LOGGER_STOPPER, logging::TIMESTAMPS_IN_LOGS, types::*};
 use crate::device::{Device, DeviceConfig, Result as DevResult};
 use telio_model::{
-    config::{Config, ConfigParseError},
+    config::{Config, ConfigParseError, MeshnetNamespacePolicy, Server},
     event::*,
     features::Features,
-    mesh::{ExitNode, Node},
+    memory::ComponentMemoryUsage,
+    mesh::{ConnectivityPreview, ExitNode, Node, PathPreference, PeerCandidates, PeerCounters},
+    network::NetworkInfo,
 };
 
 use nat_detect::NatType;
 
 // debug tools
 use telio_utils::{
-    backtrace::log_current_backtrace, commit_sha, telio_log_debug, telio_log_error, telio_log_info,
-    telio_log_warn, version_tag,
+    backtrace::{current_backtrace, log_current_backtrace},
+    commit_sha, telio_log_debug, telio_log_error, telio_log_info, telio_log_warn, version_tag,
 };
 
 const DEFAULT_PANIC_MSG: &str = "libtelio panicked";
 
+thread_local! {
+    /// Backtrace of the last panic on this thread, captured by the panic hook
+    static PANIC_BACKTRACE: RefCell<Option<String>> = RefCell::new(None);
+}
+
 /// Execute a given closure and catch any panics
 ///
-/// If the closure returns an error or panics, the inner errors or panic message is added to LAST_ERROR storage
+/// If the closure returns an error or panics, the inner errors or panic message is added to LAST_ERROR storage.
+/// Panics are returned as [`TelioError::UnknownError`] with the backtrace of the panicking thread
+/// appended to the panic message.
 fn catch_ffi_panic<T, F>(expr: F) -> FfiResult<T>
 where
     F: FnMut() -> FfiResult<T>,
@@ -60,11 +78,27 @@ where
         Err(err) => {
             let err_string = err.to_string();
             error_handling::update_last_error(anyhow!(err_string.clone()));
-            Err(TelioError::UnknownError { inner: err_string })
+            let backtrace = take_panic_backtrace();
+            Err(TelioError::UnknownError {
+                inner: if backtrace.is_empty() {
+                    err_string
+                } else {
+                    format!("{err_string}\n{backtrace}")
+                },
+            })
         }
     }
 }
 
+/// Backtrace of the last panic, if the panic hook managed to capture it
+fn take_panic_backtrace() -> String {
+    PANIC_BACKTRACE
+        .try_with(|backtrace| backtrace.try_borrow_mut().ok()?.take())
+        .ok()
+        .flatten()
+        .unwrap_or_default()
+}
+
 /// Set the global logger.
 /// # Parameters
 /// - `log_level`: Max log level to log.
@@ -81,6 +115,19 @@ pub fn unset_global_logger() {
     LOGGER_STOPPER.stop();
 }
 
+/// Set the callback receiving the reports of the panics of any libtelio thread.
+/// # Parameters
+/// - `reporter`: Callback to handle the crash reports.
+pub fn set_crash_reporter(reporter: Box<dyn TelioCrashReportCb>) {
+    crash_report::set_reporter(Some(reporter));
+}
+
+/// Unset the crash reporter.
+/// After this call finishes, previously registered crash reporter will not be called.
+pub fn unset_crash_reporter() {
+    crash_report::set_reporter(None);
+}
+
 /// Enable timestamps in logs generated by libtelio.
 /// When enabled host application doesn't need to add timestamps in the logging callback.
 pub fn add_timestamps_to_logs() {
@@ -123,7 +170,10 @@ pub fn deserialize_feature_config(fstr: String) -> FfiResult<Features> {
     if fstr.is_empty() {
         Ok(Features::default())
     } else {
-        serde_json::from_str(&fstr).map_err(|_| TelioError::InvalidString)
+        serde_json::from_str(&fstr).map_err(|e| {
+            telio_log_warn!("Invalid feature config: {e}");
+            TelioError::InvalidString
+        })
     }
 }
 
@@ -138,6 +188,39 @@ pub fn deserialize_meshnet_config(cfg_str: String) -> FfiResult<Config> {
     }
 }
 
+#[cfg(any(target_os = "android", feature = "jni_tests"))]
+/// Java exception thrown when a JNI entry point panics
+const JNI_PANIC_EXCEPTION: &str = "java/lang/RuntimeException";
+
+#[cfg(any(target_os = "android", feature = "jni_tests"))]
+/// Run the body of a JNI entry point, catching any panic
+///
+/// A panic must never unwind into the JVM, as that aborts the whole app. Instead it is turned into
+/// a pending Java exception carrying the panic message and the Rust backtrace, and `default` is
+/// returned to the caller. Every hand written `Java_*` entry point has to go through it, the
+/// calls through the generated bindings are caught by the uniffi scaffolding instead.
+fn catch_jni_panic<T>(env: &jni::JNIEnv, default: T, f: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(e) => {
+            let message = panic_handling::recover_panic_message(e)
+                .unwrap_or_else(|| DEFAULT_PANIC_MSG.to_string());
+            let mut backtrace = take_panic_backtrace();
+            if backtrace.is_empty() {
+                // The panic hook is only installed with the first telio instance
+                backtrace = current_backtrace();
+            }
+            telio_log_error!("JNI call panicked: {message}");
+            error_handling::update_last_error(anyhow!(message.clone()));
+            if let Err(err) = env.throw_new(JNI_PANIC_EXCEPTION, format!("{message}\n{backtrace}"))
+            {
+                telio_log_error!("Failed to throw Java exception: {err:?}");
+            }
+            default
+        }
+    }
+}
+
 #[cfg(target_os = "android")]
 #[no_mangle]
 /// Initialize OS certificate store, should be called only once. Without call to telio_init_cert_store
@@ -153,14 +236,16 @@ pub extern "C" fn Java_com_nordsec_telio_TelioCert_initCertStore(
     use once_cell::sync::OnceCell;
 
     static RESULT: OnceCell<u8> = OnceCell::new();
-    *RESULT.get_or_init(|| {
-        if let Err(err) = rustls_platform_verifier::android::init_hosted(&env, ctx) {
-            telio_log_error!("Failed to initialize certificate store {err:?}");
-            1
-        } else {
-            telio_log_debug!("Successfully initialized certificate store");
-            0
-        }
+    catch_jni_panic(&env, 1, || {
+        *RESULT.get_or_init(|| {
+            if let Err(err) = rustls_platform_verifier::android::init_hosted(&env, ctx) {
+                telio_log_error!("Failed to initialize certificate store {err:?}");
+                1
+            } else {
+                telio_log_debug!("Successfully initialized certificate store");
+                0
+            }
+        })
     })
 }
 
@@ -218,19 +303,17 @@ impl Telio {
                 telio_log_error!("{}", info);
 
                 log_current_backtrace();
+                let backtrace = current_backtrace();
+                let _ = PANIC_BACKTRACE.try_with(|panic_backtrace| {
+                    if let Ok(mut panic_backtrace) = panic_backtrace.try_borrow_mut() {
+                        *panic_backtrace = Some(backtrace.clone());
+                    }
+                });
+                crash_report::report_panic(info, backtrace);
 
-                let err = {
-                    let message = {
-                        if let Some(msg) = info.payload().downcast_ref::<String>() {
-                            msg.clone()
-                        } else if let Some(msg) = info.payload().downcast_ref::<&str>() {
-                            msg.to_string()
-                        } else {
-                            DEFAULT_PANIC_MSG.to_string()
-                        }
-                    };
-                    anyhow::Error::from(panic_handling::Panic { message })
-                };
+                let err = anyhow::Error::from(panic_handling::Panic {
+                    message: crash_report::panic_message(info),
+                });
 
                 // Updating LAST_ERROR.
                 // NOTE: this "could" duplicate updating error, if the error happens on ffi call stack as well ...
@@ -456,6 +539,16 @@ impl Telio {
         })
     }
 
+    /// Report the CPU features accelerating the ciphers, and the implementation sealing the
+    /// tunnel packets while NepTUN runs the tunnel.
+    pub fn get_crypto_acceleration_report(&self) -> CryptoAccelerationReport {
+        self.device_op(true, |dev| Ok(dev.get_crypto_acceleration_report()))
+            .unwrap_or_else(|e| {
+                telio_log_error!("Telio::get_crypto_acceleration_report() failed {:?}", e);
+                crypto_acceleration_report(false)
+            })
+    }
+
     /// get device luid.
     pub fn get_adapter_luid(&self) -> u64 {
         self.device_op(true, |dev| Ok(dev.get_adapter_luid()))
@@ -496,6 +589,7 @@ impl Telio {
     /// Sets fmark for started device.
     ///
     /// This function only does something on linux.
+    /// The mark is applied to the WireGuard socket and to the relay connection.
     ///
     /// # Parameters
     /// - `fwmark`: unsigned 32-bit integer
@@ -516,21 +610,72 @@ impl Telio {
         })
     }
 
+    /// Switch the started device to another adapter, keeping its keys, peers and endpoints.
+    ///
+    /// The previous adapter is restored if the new one fails to start.
+    pub fn switch_adapter(&self, adapter: TelioAdapterType) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::switch_adapter entry with instance id: {}. Adapter: {:?}",
+            self.id,
+            &adapter
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.switch_adapter(adapter.into())
+                    .log_result("Telio::switch_adapter")
+            })
+        })
+    }
+
+    /// Wake up a sleeping meshnet node through the nodes on its LAN.
+    pub fn wake_peer(&self, peer: PublicKey) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::wake_peer entry with instance id: {}. Peer: {:?}",
+            self.id,
+            peer
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.wake_peer(&peer).log_result("Telio::wake_peer")
+            })
+        })
+    }
+
+    /// Sets MTU of the tunnel interface for started device.
+    ///
+    /// Emits `MtuChanged` event with the new MTU and MSS values once applied.
+    ///
+    /// # Parameters
+    /// - `mtu`: MTU of the tunnel, within 1280..=8920
+    ///
+    pub fn set_mtu(&self, mtu: u16) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::set_mtu entry with instance id: {}. mtu: {}",
+            self.id,
+            mtu
+        );
+        catch_ffi_panic(|| self.device_op(true, |dev| dev.set_mtu(mtu).map_err(TelioError::from)))
+    }
+
     /// Notify telio with network state changes.
     ///
     /// # Parameters
-    /// - `network_info`: Json-encoded network state info.
-    ///                   Format to be decided, pass empty string for now.
+    /// - `network_info`: Json-encoded network state info, e.g.
+    ///                   `{"cellular": true, "carrier": "Telco"}`. All of the fields are
+    ///                   optional, an empty string gives no info. On Android,
+    ///                   `network_handle` of the new default network rebinds the sockets.
     pub fn notify_network_change(&self, network_info: String) -> FfiResult<()> {
-        #![allow(unused_variables)]
-
         telio_log_info!(
             "Telio::notify_network_change entry with instance id: {}.",
             self.id
         );
+        let network_info = NetworkInfo::new_from_str(&network_info).unwrap_or_else(|e| {
+            telio_log_warn!("Ignoring invalid network info: {}", e);
+            NetworkInfo::default()
+        });
         catch_ffi_panic(|| {
             self.device_op(true, |dev| {
-                dev.notify_network_change()
+                dev.notify_network_change(network_info.clone())
                     .log_result("Telio::notify_network_change")
             })
         })
@@ -556,6 +701,31 @@ impl Telio {
         })
     }
 
+    /// Connect the relay again if it was closed for being idle.
+    pub fn wake_relay(&self) -> FfiResult<()> {
+        telio_log_info!("telio_wake_relay entry with instance id: {}.", self.id);
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| dev.wake_relay().log_result("Telio::wake_relay"))
+        })
+    }
+
+    /// Suspend all background activity of started device, e.g. for OS sleep or Android doze.
+    ///
+    /// Probing, analytics and keepalives are stopped, while the adapter stays configured.
+    /// Emits `MaintenanceStateChanged` event once suspended.
+    pub fn suspend(&self) -> FfiResult<()> {
+        telio_log_info!("Telio::suspend entry with instance id: {}.", self.id);
+        catch_ffi_panic(|| self.device_op(true, |dev| dev.suspend().log_result("Telio::suspend")))
+    }
+
+    /// Resume the background activity after `suspend`, resynchronizing the connections at once.
+    ///
+    /// Emits `MaintenanceStateChanged` event once resumed.
+    pub fn resume(&self) -> FfiResult<()> {
+        telio_log_info!("Telio::resume entry with instance id: {}.", self.id);
+        catch_ffi_panic(|| self.device_op(true, |dev| dev.resume().log_result("Telio::resume")))
+    }
+
     /// Wrapper for `Telio::connect_to_exit_node_with_id` that doesn't take an identifier
     pub fn connect_to_exit_node(
         &self,
@@ -613,6 +783,49 @@ impl Telio {
         })
     }
 
+    /// Stage a VPN server while connected to another one, so that switching to it is quick.
+    ///
+    /// The server gets no traffic until `connect_to_exit_node` is called with its key.
+    ///
+    /// # Parameters
+    /// - `public_key`: WireGuard public key of the VPN server.
+    /// - `endpoint`: An endpoint of the VPN server, must contain a port.
+    /// - `handshake`: Handshake with the server ahead of the switch.
+    pub fn prewarm_exit_node(
+        &self,
+        public_key: PublicKey,
+        endpoint: SocketAddr,
+        handshake: bool,
+    ) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::prewarm_exit_node entry with instance id :{}. Public Key: {:?}. Endpoint: {:?}. Handshake: {}",
+            self.id,
+            public_key,
+            endpoint,
+            handshake,
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.prewarm_exit_node(public_key, endpoint, handshake)
+                    .log_result("Telio::prewarm_exit_node")
+            })
+        })
+    }
+
+    /// Remove the VPN server staged by `prewarm_exit_node`, unless connected to it already.
+    pub fn cancel_exit_node_prewarm(&self) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::cancel_exit_node_prewarm entry with instance id: {}.",
+            self.id
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.cancel_exit_node_prewarm()
+                    .log_result("Telio::cancel_exit_node_prewarm")
+            })
+        })
+    }
+
     /// Connects to the VPN exit node with post quantum tunnel
     ///
     /// Routing should be set by the user accordingly.
@@ -741,7 +954,36 @@ impl Telio {
         })
     }
 
+    /// Submits the meshnet config without waiting for it to be applied.
+    ///
+    /// `callback` is called once the config, or a later one which replaced it, is applied.
+    pub fn submit_meshnet(
+        &self,
+        cfg: Config,
+        callback: Box<dyn TelioConfigAppliedCb>,
+    ) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::submit_meshnet entry with instance id: {}. Meshmap: {:?}",
+            self.id,
+            &cfg
+        );
+        let callback: Arc<dyn TelioConfigAppliedCb> = Arc::from(callback);
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                let callback = callback.clone();
+                dev.submit_config_with_callback(&Some(cfg.clone()), move |result| {
+                    let error = result.err().map(|err| err.to_string());
+                    if let Err(err) = callback.config_applied(error) {
+                        telio_log_warn!("Failed to report the applied meshnet config: {err:?}");
+                    }
+                })
+                .log_result("Telio::submit_meshnet")
+            })
+        })
+    }
+
     /// Disables the meshnet functionality by closing all the connections.
+    /// Named meshnets set by `set_meshnet_namespace` are removed as well.
     pub fn set_meshnet_off(&self) -> FfiResult<()> {
         telio_log_info!(
             "Telio::set_meshnet_off entry with instance id: {}.",
@@ -754,6 +996,186 @@ impl Telio {
         })
     }
 
+    /// Applies a named meshnet alongside the one set by `set_meshnet`.
+    ///
+    /// # Parameters
+    /// - `namespace`: Name of the meshnet, used as its DNS zone `<namespace>.nord`
+    /// - `cfg`: Output of GET /v1/meshnet/machines/{machineIdentifier}/map of the meshnet
+    /// - `policy`: Permissions granted at most to the peers of the meshnet
+    ///
+    pub fn set_meshnet_namespace(
+        &self,
+        namespace: String,
+        cfg: Config,
+        policy: MeshnetNamespacePolicy,
+    ) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::set_meshnet_namespace entry with instance id: {}. Namespace: {:?}. Policy: {:?}. Meshmap: {:?}",
+            self.id,
+            namespace,
+            policy,
+            &cfg
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                let cfg = Some(cfg.clone());
+                dev.set_namespace_config(&namespace, &cfg, policy)
+                    .log_result("Telio::set_meshnet_namespace")
+            })
+        })
+    }
+
+    /// Removes a named meshnet, leaving the other ones intact.
+    pub fn set_meshnet_namespace_off(&self, namespace: String) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::set_meshnet_namespace_off entry with instance id: {}. Namespace: {:?}",
+            self.id,
+            namespace
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.set_namespace_config(&namespace, &None, Default::default())
+                    .log_result("Telio::set_meshnet_namespace_off")
+            })
+        })
+    }
+
+    /// Get the relay server telio is connecting or connected to, along with the connection state.
+    /// Returns `None` when meshnet is off or the relay is disconnected.
+    pub fn get_relay_state(&self) -> FfiResult<Option<Server>> {
+        self.device_op(true, |dev| dev.relay_state().map_err(|e| e.into()))
+    }
+
+    /// Get the relayed packets dropped because the relay server or the peers could not keep up.
+    /// Counted since meshnet was turned on, zeroes while it is off.
+    pub fn get_relay_dropped_packets(&self) -> FfiResult<DroppedPackets> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.relay_dropped_packets().map_err(|e| e.into())
+            })
+        })
+    }
+
+    /// Assess the expected connectivity to a node before it is added to the meshnet.
+    ///
+    /// # Parameters
+    /// - `public_key`: Public key of the prospective node
+    /// - `endpoint_hints`: Endpoints the node is expected to be reachable at, may be empty
+    pub fn preview_peer_connectivity(
+        &self,
+        public_key: PublicKey,
+        endpoint_hints: Vec<SocketAddr>,
+    ) -> FfiResult<ConnectivityPreview> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.preview_peer_connectivity(public_key, endpoint_hints)
+                    .map_err(|e| e.into())
+            })
+        })
+    }
+
+    /// Get the endpoint candidates currently known for a meshnet peer.
+    ///
+    /// # Parameters
+    /// - `public_key`: Public key of the peer
+    pub fn get_peer_candidates(&self, public_key: PublicKey) -> FfiResult<PeerCandidates> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.get_peer_candidates(&public_key).map_err(|e| e.into())
+            })
+        })
+    }
+
+    /// Force the path of a node, overriding the paths enabled by the features.
+    ///
+    /// # Parameters
+    /// - `public_key`: Public key of the node
+    /// - `preference`: `RelayOnly` to never connect to the node directly
+    pub fn set_peer_path_preference(
+        &self,
+        public_key: PublicKey,
+        preference: PathPreference,
+    ) -> FfiResult<()> {
+        telio_log_info!(
+            "Telio::set_peer_path_preference entry with instance id: {}. Node: {:?}, preference: {:?}",
+            self.id,
+            public_key,
+            preference
+        );
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.set_peer_path_preference(public_key, preference)
+                    .log_result("Telio::set_peer_path_preference")
+            })
+        })
+    }
+
+    /// Get the path forced for the node, `DirectPreferred` unless set otherwise.
+    pub fn get_peer_path_preference(&self, public_key: PublicKey) -> FfiResult<PathPreference> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.peer_path_preference(public_key).map_err(|e| e.into())
+            })
+        })
+    }
+
+    /// Export the records of the `.nord` zone, or of one of its reverse zones, as zone file text.
+    pub fn export_dns_zone(&self, zone: String) -> FfiResult<Option<String>> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.export_dns_zone(&zone)
+                    .log_result("Telio::export_dns_zone")
+            })
+        })
+    }
+
+    /// Replace the domains blocked by magic DNS with the zlib compressed `list`.
+    pub fn set_dns_blocklist(&self, list: Vec<u8>) -> FfiResult<()> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.set_dns_blocklist(list)
+                    .log_result("Telio::set_dns_blocklist")
+            })
+        })
+    }
+
+    /// Get the size of the blocklist and the queries it blocked, `None` if no list was loaded.
+    pub fn get_dns_blocklist_stats(&self) -> FfiResult<Option<DnsBlocklistStats>> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| dev.dns_blocklist_stats().map_err(|e| e.into()))
+        })
+    }
+
+    /// Get the features telio runs with, after the defaults are filled in, the deprecated
+    /// fields are mapped and the values ignored at runtime are turned off.
+    pub fn get_effective_features(&self) -> FfiResult<Features> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| dev.effective_features().map_err(|e| e.into()))
+        })
+    }
+
+    pub fn get_memory_usage(&self) -> FfiResult<Vec<ComponentMemoryUsage>> {
+        catch_ffi_panic(|| self.device_op(true, |dev| dev.memory_usage().map_err(|e| e.into())))
+    }
+
+    pub fn get_peer_counters(&self) -> FfiResult<Vec<PeerCounters>> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| dev.get_peer_counters().map_err(|e| e.into()))
+        })
+    }
+
+    pub fn get_audit_log(&self) -> FfiResult<Vec<AuditRecord>> {
+        catch_ffi_panic(|| self.device_op(true, |dev| dev.get_audit_log().map_err(|e| e.into())))
+    }
+
+    pub fn get_firewall_monitor_stats(&self) -> FfiResult<MonitorStats> {
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.get_firewall_monitor_stats().map_err(|e| e.into())
+            })
+        })
+    }
+
     pub fn get_status_map(&self) -> Vec<Node> {
         trace!("acquiring dev lock");
         match self.device_op(true, |dev| dev.external_nodes().map_err(|e| e.into())) {
@@ -792,6 +1214,20 @@ impl Telio {
         })
     }
 
+    /// Grant or revoke the consent to collect analytics of started device.
+    ///
+    /// Revoking removes the analytics collected but not sent yet.
+    /// Emits `AnalyticsConsentChanged` event once done.
+    pub fn set_analytics_consent(&self, consent: bool) -> FfiResult<()> {
+        telio_log_info!("Telio::set_analytics_consent entry with consent: {consent}.");
+        catch_ffi_panic(|| {
+            self.device_op(true, |dev| {
+                dev.set_analytics_consent(consent)
+                    .log_result("Telio::set_analytics_consent")
+            })
+        })
+    }
+
     pub fn receive_ping(&self) -> FfiResult<String> {
         catch_ffi_panic(|| {
             self.device_op(true, |dev| match dev.receive_ping() {
@@ -899,6 +1335,21 @@ mod tests {
     use super::*;
     use telio_model::features::Features;
 
+    #[test]
+    #[allow(clippy::panic)]
+    fn test_panic_is_returned_as_unknown_error() {
+        let res: FfiResult<()> = catch_ffi_panic(|| panic!("ffi_panic_test"));
+        assert!(matches!(
+            res,
+            Err(TelioError::UnknownError { inner }) if inner.contains("ffi_panic_test")
+        ));
+        // Regular errors are passed through untouched
+        assert!(matches!(
+            catch_ffi_panic(|| -> FfiResult<()> { Err(TelioError::NotStarted) }),
+            Err(TelioError::NotStarted)
+        ));
+    }
+
     // Same as in telio-model/src/config.rs
     const MAX_CONFIG_LENGTH: usize = 16 * 1024 * 1024;
 
@@ -1021,3 +1472,55 @@ mod tests {
         assert!(actual.is_ok());
     }
 }
+
+#[cfg(all(test, feature = "jni_tests"))]
+mod jni_tests {
+    use super::*;
+    use jni::{objects::JString, InitArgsBuilder, JNIEnv, JNIVersion, JavaVM};
+    use once_cell::sync::Lazy;
+
+    // A process can host only one JVM, all of the tests attach to it
+    static JVM: Lazy<JavaVM> = Lazy::new(|| {
+        let args = InitArgsBuilder::new()
+            .version(JNIVersion::V8)
+            .build()
+            .expect("Invalid JVM arguments");
+        JavaVM::new(args).expect("Failed to start JVM")
+    });
+
+    fn with_env(f: impl FnOnce(&JNIEnv)) {
+        let env = JVM
+            .attach_current_thread()
+            .expect("Failed to attach to JVM");
+        f(&env);
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn jni_panic_is_thrown_as_java_exception() {
+        with_env(|env| {
+            assert_eq!(catch_jni_panic(env, 7u8, || panic!("jni_panic_test")), 7);
+            assert!(env.exception_check().unwrap());
+
+            let exception = env.exception_occurred().unwrap();
+            env.exception_clear().unwrap();
+            assert!(env.is_instance_of(exception, JNI_PANIC_EXCEPTION).unwrap());
+
+            let message = env
+                .call_method(exception, "getMessage", "()Ljava/lang/String;", &[])
+                .unwrap()
+                .l()
+                .unwrap();
+            let message: String = env.get_string(JString::from(message)).unwrap().into();
+            assert!(message.starts_with("jni_panic_test"));
+        });
+    }
+
+    #[test]
+    fn jni_result_is_passed_through() {
+        with_env(|env| {
+            assert_eq!(catch_jni_panic(env, 1u8, || 0), 0);
+            assert!(!env.exception_check().unwrap());
+        });
+    }
+}
@@ -0,0 +1,487 @@
+//! Stable C ABI for the integrators which do not go through the uniffi bindings
+//!
+//! The header `include/telio.h` is generated from this module by cbindgen (`just c_header`), and
+//! the tests fail when the checked in header no longer matches the code. Every exported signature
+//! is pinned below as well, so changing one does not compile until its pin is updated together
+//! with the ABI version: the major version for breaking changes, the minor one for additions.
+//!
+//! A device is created with `telio_new`, driven with the lifecycle and config calls and freed with
+//! `telio_destroy`. The configs and events are exchanged as the same JSON the other bindings use.
+
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    net::SocketAddr,
+    str::FromStr,
+};
+
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use telio_crypto::{PublicKey, SecretKey};
+use telio_model::event::Event;
+use telio_utils::{commit_sha, telio_log_warn, version_tag};
+
+use super::{
+    deserialize_feature_config, deserialize_meshnet_config,
+    types::{FfiResult, TelioAdapterType, TelioError, TelioEventCb},
+    Telio,
+};
+
+/// Version of the C ABI, compatible with every caller built against the same major version and
+/// an older or equal minor version
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TelioAbiVersion {
+    /// Bumped on breaking changes of the exported signatures or types
+    pub major: u32,
+    /// Bumped when functions or types are added
+    pub minor: u32,
+    /// Bumped on fixes which do not change the ABI
+    pub patch: u32,
+}
+
+const ABI_VERSION: TelioAbiVersion = TelioAbiVersion {
+    major: 1,
+    minor: 0,
+    patch: 0,
+};
+
+/// Result of the calls of the C ABI
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelioResult {
+    /// The call succeeded
+    Ok = 0,
+    /// Unexpected failure, the details are logged
+    UnknownError = 1,
+    /// The key is not a valid base64 WireGuard key
+    InvalidKey = 2,
+    /// The config or the argument could not be parsed
+    BadConfig = 3,
+    /// The device lock is poisoned
+    LockError = 4,
+    /// The string argument is NULL or not UTF-8
+    InvalidString = 5,
+    /// The device is already started
+    AlreadyStarted = 6,
+    /// The device is not started
+    NotStarted = 7,
+    /// The meshnet config has more peers than allowed
+    TooManyPeers = 8,
+    /// The call did not complete in time
+    Timeout = 9,
+    /// A required pointer argument is NULL
+    NullArgument = 10,
+}
+
+impl From<TelioError> for TelioResult {
+    fn from(err: TelioError) -> Self {
+        match err {
+            TelioError::UnknownError { .. } => Self::UnknownError,
+            TelioError::InvalidKey => Self::InvalidKey,
+            TelioError::BadConfig => Self::BadConfig,
+            TelioError::LockError => Self::LockError,
+            TelioError::InvalidString => Self::InvalidString,
+            TelioError::AlreadyStarted => Self::AlreadyStarted,
+            TelioError::NotStarted => Self::NotStarted,
+            TelioError::TooManyPeers { .. } => Self::TooManyPeers,
+            TelioError::Timeout => Self::Timeout,
+        }
+    }
+}
+
+/// WireGuard implementation used by a started device
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)] // Same names as TelioAdapterType
+pub enum TelioAdapter {
+    /// Userland rust implementation
+    NepTUN = 0,
+    /// Linux in-kernel WireGuard implementation
+    LinuxNativeTun = 1,
+    /// WireguardGo implementation
+    WireguardGoTun = 2,
+    /// WindowsNativeWireguardNt implementation
+    WindowsNativeTun = 3,
+}
+
+impl From<TelioAdapter> for TelioAdapterType {
+    fn from(adapter: TelioAdapter) -> Self {
+        match adapter {
+            TelioAdapter::NepTUN => Self::NepTUN,
+            TelioAdapter::LinuxNativeTun => Self::LinuxNativeTun,
+            TelioAdapter::WireguardGoTun => Self::WireguardGoTun,
+            TelioAdapter::WindowsNativeTun => Self::WindowsNativeTun,
+        }
+    }
+}
+
+/// Opaque handle of a device, created by `telio_new` and freed by `telio_destroy`
+pub struct TelioDevice(Telio);
+
+/// Receiver of the events of a device, called from the library threads with the `ctx` given to
+/// `telio_new` and the event as NUL terminated JSON, which is only valid during the call
+pub type TelioEventFn = extern "C" fn(ctx: *mut c_void, event: *const c_char);
+
+#[derive(Debug)]
+struct EventCallback {
+    callback: TelioEventFn,
+    // Kept as an address, the caller guarantees that the context can be used from any thread
+    ctx: usize,
+}
+
+impl TelioEventCb for EventCallback {
+    fn event(&self, payload: Event) -> FfiResult<()> {
+        let event = serde_json::to_string(&payload).map_err(|err| TelioError::UnknownError {
+            inner: err.to_string(),
+        })?;
+        let event = to_c_string(&event);
+        (self.callback)(self.ctx as *mut c_void, event.as_ptr());
+        Ok(())
+    }
+}
+
+static VERSION_TAG: Lazy<CString> = Lazy::new(|| to_c_string(version_tag()));
+static COMMIT_SHA: Lazy<CString> = Lazy::new(|| to_c_string(commit_sha()));
+
+// Pins of the exported signatures, see the module docs before touching them
+const _: extern "C" fn() -> TelioAbiVersion = telio_abi_version;
+const _: extern "C" fn(u32, u32) -> bool = telio_abi_is_compatible;
+const _: extern "C" fn() -> *const c_char = telio_version_tag;
+const _: extern "C" fn() -> *const c_char = telio_commit_sha;
+const _: unsafe extern "C" fn(
+    *const c_char,
+    TelioEventFn,
+    *mut c_void,
+    *mut *mut TelioDevice,
+) -> TelioResult = telio_new;
+const _: unsafe extern "C" fn(*mut TelioDevice) = telio_destroy;
+const _: unsafe extern "C" fn(*const TelioDevice, *const c_char, TelioAdapter) -> TelioResult =
+    telio_start;
+const _: unsafe extern "C" fn(*const TelioDevice) -> TelioResult = telio_stop;
+const _: unsafe extern "C" fn(*const TelioDevice, *const c_char) -> TelioResult = telio_set_meshnet;
+const _: unsafe extern "C" fn(*const TelioDevice) -> TelioResult = telio_set_meshnet_off;
+const _: unsafe extern "C" fn(
+    *const TelioDevice,
+    *const c_char,
+    *const c_char,
+    *const c_char,
+) -> TelioResult = telio_connect_to_exit_node;
+const _: unsafe extern "C" fn(*const TelioDevice) -> TelioResult = telio_disconnect_from_exit_nodes;
+const _: unsafe extern "C" fn(*const TelioDevice, *const c_char) -> TelioResult =
+    telio_notify_network_change;
+
+/// Get the version of the C ABI implemented by the library.
+#[no_mangle]
+pub extern "C" fn telio_abi_version() -> TelioAbiVersion {
+    ABI_VERSION
+}
+
+/// Check whether the library can be used by a caller built against the given ABI version.
+#[no_mangle]
+pub extern "C" fn telio_abi_is_compatible(major: u32, minor: u32) -> bool {
+    major == ABI_VERSION.major && minor <= ABI_VERSION.minor
+}
+
+/// Get the current version tag, as a static NUL terminated string owned by the library.
+#[no_mangle]
+pub extern "C" fn telio_version_tag() -> *const c_char {
+    VERSION_TAG.as_ptr()
+}
+
+/// Get the current commit sha, as a static NUL terminated string owned by the library.
+#[no_mangle]
+pub extern "C" fn telio_commit_sha() -> *const c_char {
+    COMMIT_SHA.as_ptr()
+}
+
+/// Create a device, without starting it.
+///
+/// `features` is the JSON of the features config, NULL or empty for the defaults. `events` is
+/// called with `ctx` for every event, from the library threads. On success the handle is stored
+/// to `device`, and must be freed with `telio_destroy`.
+///
+/// # Safety
+/// `features` must be NULL or a NUL terminated string, and `device` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn telio_new(
+    features: *const c_char,
+    events: TelioEventFn,
+    ctx: *mut c_void,
+    device: *mut *mut TelioDevice,
+) -> TelioResult {
+    call(|| {
+        let device = device.as_mut().ok_or(TelioResult::NullArgument)?;
+        let features = deserialize_feature_config(opt_str(features)?.unwrap_or_default().into())?;
+        let events = Box::new(EventCallback {
+            callback: events,
+            ctx: ctx as usize,
+        });
+        *device = Box::into_raw(Box::new(TelioDevice(Telio::new(features, events)?)));
+        Ok(())
+    })
+}
+
+/// Stop a device and free it, NULL is ignored.
+///
+/// # Safety
+/// `device` must be NULL or a handle from `telio_new` which is not used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn telio_destroy(device: *mut TelioDevice) {
+    if !device.is_null() {
+        let TelioDevice(telio) = *Box::from_raw(device);
+        if let Err(err) = telio.shutdown_hard() {
+            telio_log_warn!("Failed to shut down the destroyed device: {err:?}");
+        }
+    }
+}
+
+/// Start a device with the base64 encoded `private_key` and the given adapter.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`, `private_key` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn telio_start(
+    device: *const TelioDevice,
+    private_key: *const c_char,
+    adapter: TelioAdapter,
+) -> TelioResult {
+    call(|| {
+        let telio = handle(device)?;
+        let private_key = SecretKey::from_str(str_arg(private_key)?).map_err(TelioError::from)?;
+        Ok(telio.start(private_key, adapter.into())?)
+    })
+}
+
+/// Stop a device, it can be started again.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`.
+#[no_mangle]
+pub unsafe extern "C" fn telio_stop(device: *const TelioDevice) -> TelioResult {
+    call(|| Ok(handle(device)?.stop()?))
+}
+
+/// Enable meshnet or update its config, given as the JSON of the meshnet config.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`, `config` a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn telio_set_meshnet(
+    device: *const TelioDevice,
+    config: *const c_char,
+) -> TelioResult {
+    call(|| {
+        let telio = handle(device)?;
+        let config = deserialize_meshnet_config(str_arg(config)?.into())?;
+        Ok(telio.set_meshnet(config)?)
+    })
+}
+
+/// Disable meshnet.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`.
+#[no_mangle]
+pub unsafe extern "C" fn telio_set_meshnet_off(device: *const TelioDevice) -> TelioResult {
+    call(|| Ok(handle(device)?.set_meshnet_off()?))
+}
+
+/// Connect to an exit node with the base64 encoded `public_key`.
+///
+/// `allowed_ips` is a comma separated list of subnets routed to the exit node, NULL for all of
+/// them. `endpoint` is the address of a VPN server with its port, NULL for a meshnet peer.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`, `public_key` a NUL terminated string, and
+/// `allowed_ips` and `endpoint` NULL or NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn telio_connect_to_exit_node(
+    device: *const TelioDevice,
+    public_key: *const c_char,
+    allowed_ips: *const c_char,
+    endpoint: *const c_char,
+) -> TelioResult {
+    call(|| {
+        let telio = handle(device)?;
+        let public_key = PublicKey::from_str(str_arg(public_key)?).map_err(TelioError::from)?;
+        let allowed_ips = opt_str(allowed_ips)?
+            .map(|ips| {
+                ips.split(',')
+                    .map(|ip| ip.trim().parse::<IpNet>())
+                    .collect()
+            })
+            .transpose()
+            .map_err(|_| TelioResult::BadConfig)?;
+        let endpoint = opt_str(endpoint)?
+            .map(str::parse::<SocketAddr>)
+            .transpose()
+            .map_err(|_| TelioResult::BadConfig)?;
+        Ok(telio.connect_to_exit_node(public_key, allowed_ips, endpoint)?)
+    })
+}
+
+/// Disconnect from all the exit nodes.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`.
+#[no_mangle]
+pub unsafe extern "C" fn telio_disconnect_from_exit_nodes(
+    device: *const TelioDevice,
+) -> TelioResult {
+    call(|| Ok(handle(device)?.disconnect_from_exit_nodes()?))
+}
+
+/// Notify the device about a change of the network, `network_info` is NULL when nothing is
+/// known about the new network.
+///
+/// # Safety
+/// `device` must be a handle from `telio_new`, `network_info` NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn telio_notify_network_change(
+    device: *const TelioDevice,
+    network_info: *const c_char,
+) -> TelioResult {
+    call(|| {
+        let telio = handle(device)?;
+        Ok(telio.notify_network_change(opt_str(network_info)?.unwrap_or_default().into())?)
+    })
+}
+
+fn call(f: impl FnOnce() -> Result<(), TelioResult>) -> TelioResult {
+    f().err().unwrap_or(TelioResult::Ok)
+}
+
+unsafe fn handle<'a>(device: *const TelioDevice) -> Result<&'a Telio, TelioResult> {
+    device
+        .as_ref()
+        .map(|TelioDevice(telio)| telio)
+        .ok_or(TelioResult::NullArgument)
+}
+
+unsafe fn opt_str<'a>(s: *const c_char) -> Result<Option<&'a str>, TelioResult> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(Some)
+        .map_err(|_| TelioResult::InvalidString)
+}
+
+unsafe fn str_arg<'a>(s: *const c_char) -> Result<&'a str, TelioResult> {
+    opt_str(s)?.ok_or(TelioResult::InvalidString)
+}
+
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{panic, ptr};
+
+    use super::*;
+
+    #[test]
+    fn test_abi_compatibility() {
+        let TelioAbiVersion { major, minor, .. } = telio_abi_version();
+        assert!(telio_abi_is_compatible(major, 0));
+        assert!(telio_abi_is_compatible(major, minor));
+        assert!(!telio_abi_is_compatible(major, minor + 1));
+        assert!(!telio_abi_is_compatible(major + 1, 0));
+    }
+
+    #[test]
+    fn test_version_strings() {
+        let tag = unsafe { CStr::from_ptr(telio_version_tag()) };
+        assert_eq!(tag.to_str().unwrap(), version_tag());
+        let sha = unsafe { CStr::from_ptr(telio_commit_sha()) };
+        assert_eq!(sha.to_str().unwrap(), commit_sha());
+    }
+
+    #[test]
+    fn test_c_header_is_up_to_date() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).unwrap();
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{crate_dir}/src/ffi/c_api.rs"))
+            .generate()
+            .expect("Failed to generate the C header")
+            .write(&mut generated);
+        let checked_in = std::fs::read_to_string(format!("{crate_dir}/include/telio.h")).unwrap();
+        assert_eq!(
+            String::from_utf8(generated).unwrap(),
+            checked_in,
+            "include/telio.h is out of date, regenerate it with `just c_header`"
+        );
+    }
+
+    extern "C" fn ignore_events(_ctx: *mut c_void, _event: *const c_char) {}
+
+    #[test]
+    fn test_device_lifecycle() {
+        let mut device = ptr::null_mut();
+        let key = CString::new(SecretKey::gen().public().to_string()).unwrap();
+        let bad = CString::new("not valid").unwrap();
+        unsafe {
+            assert_eq!(
+                telio_new(ptr::null(), ignore_events, ptr::null_mut(), &mut device),
+                TelioResult::Ok
+            );
+            assert!(!device.is_null());
+            assert_eq!(
+                telio_start(device, bad.as_ptr(), TelioAdapter::NepTUN),
+                TelioResult::InvalidKey
+            );
+            assert_eq!(
+                telio_set_meshnet(device, bad.as_ptr()),
+                TelioResult::BadConfig
+            );
+            assert_eq!(
+                telio_connect_to_exit_node(device, key.as_ptr(), ptr::null(), bad.as_ptr()),
+                TelioResult::BadConfig
+            );
+            assert_eq!(telio_stop(device), TelioResult::Ok);
+            telio_destroy(device);
+        }
+
+        // Restore panic hook
+        let _ = panic::take_hook();
+    }
+
+    #[test]
+    fn test_null_arguments() {
+        let bad = CString::new("not valid").unwrap();
+        unsafe {
+            assert_eq!(
+                telio_new(ptr::null(), ignore_events, ptr::null_mut(), ptr::null_mut()),
+                TelioResult::NullArgument
+            );
+            assert_eq!(
+                telio_start(ptr::null(), bad.as_ptr(), TelioAdapter::NepTUN),
+                TelioResult::NullArgument
+            );
+            assert_eq!(telio_stop(ptr::null()), TelioResult::NullArgument);
+            assert_eq!(
+                telio_set_meshnet(ptr::null(), ptr::null()),
+                TelioResult::NullArgument
+            );
+            telio_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_result_codes() {
+        assert_eq!(
+            TelioResult::from(TelioError::NotStarted),
+            TelioResult::NotStarted
+        );
+        assert_eq!(
+            TelioResult::from(TelioError::TooManyPeers { count: 2, limit: 1 }),
+            TelioResult::TooManyPeers
+        );
+        assert_eq!(TelioResult::NullArgument as i32, 10);
+    }
+}
@@ -0,0 +1,111 @@
+//! Crash reports of the panics of libtelio, delivered to the apps so they can attach them to the
+//! reports of their own crash reporters.
+//!
+//! Panics of every thread of libtelio end up in the panic hook installed along with the first
+//! telio instance, which hands the report to the registered callback. The message and the logs
+//! go through the log censor, so the report is as redacted as the logs are.
+
+use std::{collections::VecDeque, panic::PanicInfo, thread};
+
+use parking_lot::Mutex;
+
+use super::logging::LOG_CENSOR;
+use super::types::{CrashReport, TelioCrashReportCb};
+use super::DEFAULT_PANIC_MSG;
+
+/// Number of the most recent log lines attached to the reports
+const RECENT_LOGS: usize = 100;
+
+static REPORTER: Mutex<Option<Box<dyn TelioCrashReportCb>>> = Mutex::new(None);
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+pub fn set_reporter(reporter: Option<Box<dyn TelioCrashReportCb>>) {
+    *REPORTER.lock() = reporter;
+}
+
+/// Keep the log line for the crash reports, the lines are expected to be censored already
+pub fn record_log(line: &str) {
+    let mut recent = RECENT.lock();
+    if recent.len() >= RECENT_LOGS {
+        recent.pop_front();
+    }
+    recent.push_back(line.to_owned());
+}
+
+/// Message the panic was raised with
+pub fn panic_message(info: &PanicInfo) -> String {
+    if let Some(msg) = info.payload().downcast_ref::<String>() {
+        msg.clone()
+    } else if let Some(msg) = info.payload().downcast_ref::<&str>() {
+        msg.to_string()
+    } else {
+        DEFAULT_PANIC_MSG.to_string()
+    }
+}
+
+/// Hand the report of the panic to the registered callback, if any
+pub fn report_panic(info: &PanicInfo, backtrace: String) {
+    // The panic may have happened while holding the locks, they must not deadlock the hook
+    let Some(reporter) = REPORTER.try_lock() else {
+        return;
+    };
+    if let Some(reporter) = reporter.as_ref() {
+        let _ = reporter.report(build_report(info, backtrace));
+    }
+}
+
+fn build_report(info: &PanicInfo, backtrace: String) -> CrashReport {
+    CrashReport {
+        thread: thread::current().name().unwrap_or("<unnamed>").to_owned(),
+        location: info
+            .location()
+            .map(|location| format!("{}:{}", location.file(), location.line()))
+            .unwrap_or_default(),
+        message: LOG_CENSOR.censor_logs(panic_message(info)),
+        backtrace,
+        recent_logs: RECENT
+            .try_lock()
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{panic, sync::Arc};
+
+    #[test]
+    fn only_the_most_recent_logs_are_kept() {
+        for i in 0..=RECENT_LOGS {
+            record_log(&format!("line {i}"));
+        }
+        let recent = RECENT.lock();
+        assert_eq!(recent.len(), RECENT_LOGS);
+        assert!(recent.contains(&format!("line {RECENT_LOGS}")));
+        assert!(!recent.contains(&"line 0".to_owned()));
+    }
+
+    #[test]
+    fn report_carries_the_panic() {
+        let report = Arc::new(Mutex::new(None));
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new({
+            let report = report.clone();
+            move |info| {
+                // Other tests may panic meanwhile
+                if panic_message(info).starts_with("crash report") {
+                    *report.lock() = Some(build_report(info, "frames".to_owned()));
+                }
+            }
+        }));
+        let _ = panic::catch_unwind(|| panic!("crash report {}", 42));
+        panic::set_hook(previous);
+
+        let report = report.lock().take().unwrap();
+        assert_eq!(report.message, "crash report 42");
+        assert!(report.location.contains("crash_report.rs"));
+        assert_eq!(report.backtrace, "frames");
+        assert!(report.recent_logs.len() <= RECENT_LOGS);
+    }
+}
@@ -3,7 +3,7 @@ use std::sync::Arc;
 use parking_lot::Mutex;
 use telio_model::features::{
     FeatureDerp, FeatureLana, FeaturePersistentKeepalive, FeaturePolling, FeatureValidateKeys,
-    FeatureWireguard, Features,
+    FeatureWireguard, Features, FirewallPolicy,
 };
 
 pub struct FeaturesDefaultsBuilder {
@@ -35,6 +35,27 @@ impl FeaturesDefaultsBuilder {
             ipv6: false,
             nicknames: false,
             batching: None,
+            socks5: None,
+            obfuscation: None,
+            peer_unreachable: None,
+            lazy_proxy: None,
+            redact_node_endpoints: false,
+            vpn_relay_fallback: None,
+            node_event_debounce: None,
+            passive_keepalive: None,
+            cellular: None,
+            peer_limit: None,
+            dscp: None,
+            mesh_watchdog: None,
+            high_availability: None,
+            route_conflicts: None,
+            audit_log: None,
+            threads: None,
+            config_queue: None,
+            api_timeout: None,
+            wake_on_lan: None,
+            peer_stats: None,
+            on_demand: None,
         };
 
         Self {
@@ -71,6 +92,90 @@ impl FeaturesDefaultsBuilder {
         self
     }
 
+    /// Enable per peer rate limiting of new inbound connections and ICMP echo requests
+    pub fn enable_firewall_rate_limit(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().firewall.rate_limit = Some(Default::default());
+        self
+    }
+
+    /// Set the firewall actions for meshnet traffic of peers which are not whitelisted
+    pub fn set_firewall_policy(
+        self: Arc<Self>,
+        inbound: FirewallPolicy,
+        outbound: FirewallPolicy,
+    ) -> Arc<Self> {
+        {
+            let mut cfg = self.config.lock();
+            cfg.firewall.inbound_policy = inbound;
+            cfg.firewall.outbound_policy = outbound;
+        }
+        self
+    }
+
+    /// Enable handshake load protection with the default threshold, only supported by NepTUN
+    /// builds with UAPI extensions
+    pub fn enable_handshake_load_protection(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().wireguard.handshake_load = Some(Default::default());
+        self
+    }
+
+    /// Enable the read-only local UAPI socket with defaults, only supported by NepTUN
+    pub fn enable_uapi_socket(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().wireguard.uapi_socket = Some(Default::default());
+        self
+    }
+
+    /// Enable automatic recreation of the adapter after fatal failures with defaults
+    pub fn enable_adapter_recovery(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().wireguard.recovery = Some(Default::default());
+        self
+    }
+
+    /// Enable the handshake events of the peers with defaults
+    pub fn enable_handshake_events(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().wireguard.handshake_events = Some(Default::default());
+        self
+    }
+
+    /// Enable closing of the idle relay connection with defaults
+    pub fn enable_relay_idle_disconnect(self: Arc<Self>) -> Arc<Self> {
+        {
+            let mut cfg = self.config.lock();
+            let prev = cfg.derp.as_ref().cloned().unwrap_or_default();
+            cfg.derp = Some(FeatureDerp {
+                idle_disconnect: Some(Default::default()),
+                ..prev
+            });
+        }
+        self
+    }
+
+    /// Enable compression of the large control messages relayed through derp
+    pub fn enable_relay_compression(self: Arc<Self>) -> Arc<Self> {
+        {
+            let mut cfg = self.config.lock();
+            let prev = cfg.derp.as_ref().cloned().unwrap_or_default();
+            cfg.derp = Some(FeatureDerp {
+                enable_compression: true,
+                ..prev
+            });
+        }
+        self
+    }
+
+    /// Enable probing of the delay of the relayed path with defaults
+    pub fn enable_relay_path_probes(self: Arc<Self>) -> Arc<Self> {
+        {
+            let mut cfg = self.config.lock();
+            let prev = cfg.derp.as_ref().cloned().unwrap_or_default();
+            cfg.derp = Some(FeatureDerp {
+                path_probes: Some(Default::default()),
+                ..prev
+            });
+        }
+        self
+    }
+
     /// Enable default wireguard timings, derp timings and other features for best battery performance
     pub fn enable_battery_saving_defaults(self: Arc<Self>) -> Arc<Self> {
         {
@@ -86,6 +191,10 @@ impl FeaturesDefaultsBuilder {
                     wireguard_polling_period: 1000,
                     wireguard_polling_period_after_state_change: 50,
                 },
+                handshake_load: cfg.wireguard.handshake_load,
+                uapi_socket: cfg.wireguard.uapi_socket.clone(),
+                recovery: cfg.wireguard.recovery,
+                handshake_events: cfg.wireguard.handshake_events,
             };
             let prev = cfg.derp.as_ref().cloned().unwrap_or_default();
             cfg.derp = Some(FeatureDerp {
@@ -144,6 +253,114 @@ impl FeaturesDefaultsBuilder {
         self.config.lock().batching = Some(default());
         self
     }
+
+    /// Enable SOCKS5 gateway with defaults
+    pub fn enable_socks5(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().socks5 = Some(default());
+        self
+    }
+
+    /// Enable AmneziaWG-compatible obfuscation with defaults
+    pub fn enable_obfuscation(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().obfuscation = Some(default());
+        self
+    }
+
+    /// Enable reporting of unreachable peers with defaults
+    pub fn enable_peer_unreachable(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().peer_unreachable = Some(default());
+        self
+    }
+
+    /// Enable lazy opening of proxy sockets with defaults
+    pub fn enable_lazy_proxy(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().lazy_proxy = Some(default());
+        self
+    }
+
+    /// Enable relaying of VPN server traffic over DERP with defaults
+    pub fn enable_vpn_relay_fallback(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().vpn_relay_fallback = Some(default());
+        self
+    }
+
+    /// Enable debouncing of node events with defaults
+    pub fn enable_node_event_debounce(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().node_event_debounce = Some(default());
+        self
+    }
+
+    /// Enable passive keepalives with defaults
+    pub fn enable_passive_keepalive(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().passive_keepalive = Some(default());
+        self
+    }
+
+    /// Enable tuning of VPN keepalives on cellular networks with defaults
+    pub fn enable_cellular(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().cellular = Some(default());
+        self
+    }
+
+    /// Enable limiting the number of meshnet peers with defaults
+    pub fn enable_peer_limit(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().peer_limit = Some(default());
+        self
+    }
+
+    /// Enable DSCP marking of the outer packets with expedited forwarding
+    pub fn enable_dscp(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().dscp = Some(default());
+        self
+    }
+
+    /// Enable remediation of meshnet stuck without connected peers with defaults
+    pub fn enable_mesh_watchdog(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().mesh_watchdog = Some(default());
+        self
+    }
+
+    /// Enable blocking of the domains on a list, blocked names get NXDOMAIN
+    pub fn enable_dns_blocklist(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().dns.blocklist = Some(default());
+        self
+    }
+
+    /// Enable the audit log of the peer keys with access to the device
+    pub fn enable_audit_log(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().audit_log = Some(default());
+        self
+    }
+
+    /// Enable the queueing of the meshnet configs set while the device is unsettled
+    pub fn enable_config_queue(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().config_queue = Some(default());
+        self
+    }
+
+    /// Enable the time budget of the blocking device calls
+    pub fn enable_api_timeout(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().api_timeout = Some(default());
+        self
+    }
+
+    /// Enable the relaying of the Wake-on-LAN requests of the meshnet nodes
+    pub fn enable_wake_on_lan(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().wake_on_lan = Some(default());
+        self
+    }
+
+    /// Enable the stream of the traffic counters of the peers
+    pub fn enable_peer_stats(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().peer_stats = Some(default());
+        self
+    }
+
+    /// Enable the on-demand activation of the meshnet peers
+    pub fn enable_on_demand(self: Arc<Self>) -> Arc<Self> {
+        self.config.lock().on_demand = Some(default());
+        self
+    }
 }
 
 impl Default for FeaturesDefaultsBuilder {
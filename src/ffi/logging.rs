@@ -19,6 +19,7 @@ use tracing_subscriber::{
     registry::LookupSpan,
 };
 
+use super::crash_report;
 use crate::{TelioLogLevel, TelioLoggerCb};
 
 const LOG_BUFFER: usize = 1024;
@@ -183,6 +184,7 @@ impl io::Write for FfiCallbackWriter {
 
         if let Some(filtered_msg) = filter_log_message(msg) {
             let filtered_msg = LOG_CENSOR.censor_logs(filtered_msg);
+            crash_report::record_log(&filtered_msg);
 
             // There are only 2 reasons why try_send should fail:
             // - no more space in a buffer because we are not processing logs fast enough
@@ -19,8 +19,31 @@ pub trait TelioProtectCb: Send + Sync + RefUnwindSafe + std::fmt::Debug {
     fn protect(&self, socket_id: i32) -> FfiResult<()>;
 }
 
+pub trait TelioCrashReportCb: Send + Sync + std::fmt::Debug {
+    fn report(&self, report: CrashReport) -> FfiResult<()>;
+}
+
+pub trait TelioConfigAppliedCb: Send + Sync + std::fmt::Debug {
+    fn config_applied(&self, error: Option<String>) -> FfiResult<()>;
+}
+
 pub type FfiResult<T> = Result<T, TelioError>;
 
+/// Report of a panic of libtelio, redacted the same way as the logs
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    /// Name of the thread which panicked
+    pub thread: String,
+    /// Source file and line the panic was raised at
+    pub location: String,
+    /// Message the panic was raised with
+    pub message: String,
+    /// Backtrace of the panicking thread, one frame per line
+    pub backtrace: String,
+    /// Most recent log lines, oldest first
+    pub recent_logs: Vec<String>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TelioError {
     #[error("UnknownError: {inner}")]
@@ -37,6 +60,10 @@ pub enum TelioError {
     AlreadyStarted,
     #[error("NotStarted")]
     NotStarted,
+    #[error("TooManyPeers: {count} > {limit}")]
+    TooManyPeers { count: u64, limit: u64 },
+    #[error("Timeout")]
+    Timeout,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -95,6 +122,13 @@ impl From<DevError> for TelioError {
         match err {
             DevError::AlreadyStarted => Self::AlreadyStarted,
             DevError::BadPublicKey => Self::InvalidKey,
+            DevError::TooManyPeers { count, limit } => Self::TooManyPeers {
+                count: count as u64,
+                limit: limit as u64,
+            },
+            DevError::Timeout(_) | DevError::RuntimeUnhealthy => Self::Timeout,
+            DevError::Adapter(telio_wg::Error::Mtu(telio_wg::mtu::Error::OutOfRange(_)))
+            | DevError::InvalidPingCount(_) => Self::BadConfig,
             _ => Self::UnknownError {
                 inner: format!("{err:?}"),
             },
@@ -108,6 +142,13 @@ impl From<&DevError> for TelioError {
         match err {
             DevError::AlreadyStarted => Self::AlreadyStarted,
             DevError::BadPublicKey => Self::InvalidKey,
+            DevError::TooManyPeers { count, limit } => Self::TooManyPeers {
+                count: *count as u64,
+                limit: *limit as u64,
+            },
+            DevError::Timeout(_) | DevError::RuntimeUnhealthy => Self::Timeout,
+            DevError::Adapter(telio_wg::Error::Mtu(telio_wg::mtu::Error::OutOfRange(_)))
+            | DevError::InvalidPingCount(_) => Self::BadConfig,
             _ => Self::UnknownError {
                 inner: format!("{err:?}"),
             },
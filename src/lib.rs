@@ -66,14 +66,28 @@ mod uniffi_libtelio {
 
     use ipnet::Ipv4Net;
 
-    use super::crypto::{PublicKey, SecretKey};
+    use super::crypto::{
+        acceleration::{CpuFeatures, CryptoAccelerationReport, CryptoImplementation},
+        PublicKey, SecretKey,
+    };
     use super::*;
 
     use nat_detect::NatType;
+    use telio_dns::DnsBlocklistStats;
+    use telio_firewall::firewall::MonitorStats;
     use telio_model::config::*;
-    use telio_model::event::{ErrorCode, ErrorLevel, Event};
+    use telio_model::event::{
+        AdapterRecovery, AdapterRecoveryState, AnalyticsConsentChanged, AuditAction, AuditRecord,
+        ConfigRollback, ConnectionProtocol, DnsRecordsInvalidated, ErrorCode, ErrorLevel, Event,
+        HaRoleChanged, HandshakeResult, InboundConnection, MaintenanceState,
+        MaintenanceStateChanged, MeshRemediation, MtuChanged, PeerHandshake, PeerRekeyed,
+        PeerStats, PeerStatsReport, PeerUnreachable, PingProbe, RemediationStep, RouteConflict,
+        UnreachableCause,
+    };
     use telio_model::features::*;
+    use telio_model::memory::*;
     use telio_model::mesh::*;
+    use telio_relay::queue::DroppedPackets;
     use telio_utils::{Hidden, HiddenString};
 
     type ErrorEvent = telio_model::event::Error;
@@ -198,6 +212,19 @@ mod uniffi_libtelio {
         }
     }
 
+    impl UniffiCustomTypeConverter for DomainName {
+        type Builtin = String;
+
+        fn into_custom(val: Self::Builtin) -> uniffi::Result<Self> {
+            val.parse()
+                .map_err(|e: InvalidDomainName| anyhow::anyhow!(e))
+        }
+
+        fn from_custom(obj: Self) -> Self::Builtin {
+            obj.into()
+        }
+    }
+
     impl UniffiCustomTypeConverter for HiddenString {
         type Builtin = String;
 